@@ -0,0 +1,150 @@
+//! Benchmarks of the bus access paths: memory (with and without mirroring),
+//! hardware registers (plain, with callbacks, and 64-bit accesses combining
+//! two 32-bit registers), and unmapped areas.
+//!
+//! Run with `cargo bench -p emu --bench bus`.
+#[macro_use]
+extern crate criterion;
+extern crate emu;
+extern crate slog;
+
+use criterion::{black_box, Criterion};
+use emu::bus::be::{Bus, BusFill, Mem, MemFlags, Reg32, RegFlags};
+use std::rc::Rc;
+
+// Number of accesses performed by each iteration.
+const ACCESSES: u32 = 1024;
+
+const RAM_BASE: u32 = 0x0000_0000;
+const MIRROR_BASE: u32 = 0x0400_0000;
+const REG_BASE: u32 = 0x0800_0000;
+const UNMAPPED_BASE: u32 = 0x1000_0000;
+
+// Create a bus with 1 MiB of RAM (also mirrored), a few registers, and an
+// unmapped area. The memory and the registers are leaked, as the bus refers
+// to them for its whole life.
+fn new_bus() -> Box<Bus> {
+    let logger = slog::Logger::root(slog::Discard, slog::o!());
+    let ram: &'static Mem = Box::leak(Box::new(Mem::new(
+        "ram",
+        1024 * 1024,
+        MemFlags::new(true, true),
+        None,
+    )));
+    let regs: &'static [Reg32] = Box::leak(Box::new([
+        Reg32::new_basic("reg0"),
+        Reg32::new_basic("reg1"),
+        Reg32::new(
+            "reg2",
+            0,
+            0xFFFF_FFFF,
+            RegFlags::default(),
+            Some(Rc::new(
+                Box::new(|_old: u32, _new: u32| {}) as Box<dyn Fn(u32, u32)>
+            )),
+            Some(Rc::new(
+                Box::new(|val: u32| val | 0x80) as Box<dyn Fn(u32) -> u32>
+            )),
+        ),
+    ]));
+
+    let mut bus = Bus::new(logger);
+    bus.map_mem(RAM_BASE, RAM_BASE + 0x000F_FFFF, ram, BusFill::None)
+        .unwrap();
+    bus.map_mem(MIRROR_BASE, MIRROR_BASE + 0x00FF_FFFF, ram, BusFill::Mirror)
+        .unwrap();
+    for (i, reg) in regs.iter().enumerate() {
+        bus.map_reg(REG_BASE + i as u32 * 4, reg).unwrap();
+    }
+    bus
+}
+
+// Benchmark reads of 32-bit words at the specified addresses.
+fn bench_read32(c: &mut Criterion, name: &str, addr: fn(u32) -> u32) {
+    let bus = new_bus();
+    c.bench_function(name, move |b| {
+        b.iter(|| {
+            let mut sum = 0u32;
+            for i in 0..ACCESSES {
+                sum = sum.wrapping_add(bus.read::<u32>(addr(i)));
+            }
+            black_box(sum)
+        })
+    });
+}
+
+// Benchmark writes of 32-bit words at the specified addresses.
+fn bench_write32(c: &mut Criterion, name: &str, addr: fn(u32) -> u32) {
+    let mut bus = new_bus();
+    c.bench_function(name, move |b| {
+        b.iter(|| {
+            for i in 0..ACCESSES {
+                bus.write::<u32>(addr(i), black_box(i));
+            }
+        })
+    });
+}
+
+fn bench_mem(c: &mut Criterion) {
+    bench_read32(c, "bus/mem/read32", |i| RAM_BASE + i * 4);
+    bench_read32(c, "bus/mem/read32_mirror", |i| MIRROR_BASE + i * 0x1004);
+    bench_write32(c, "bus/mem/write32", |i| RAM_BASE + i * 4);
+
+    let bus = new_bus();
+    c.bench_function("bus/mem/read8", move |b| {
+        b.iter(|| {
+            let mut sum = 0u8;
+            for i in 0..ACCESSES {
+                sum = sum.wrapping_add(bus.read::<u8>(RAM_BASE + i));
+            }
+            black_box(sum)
+        })
+    });
+
+    let bus = new_bus();
+    c.bench_function("bus/mem/read64", move |b| {
+        b.iter(|| {
+            let mut sum = 0u64;
+            for i in 0..ACCESSES {
+                sum = sum.wrapping_add(bus.read::<u64>(RAM_BASE + i * 8));
+            }
+            black_box(sum)
+        })
+    });
+
+    // Reads without logging, as done by the interpreter to fetch opcodes.
+    let bus = new_bus();
+    c.bench_function("bus/mem/fetch_nolog", move |b| {
+        b.iter(|| {
+            let mut sum = 0u32;
+            for i in 0..ACCESSES {
+                sum = sum.wrapping_add(bus.fetch_read_nolog::<u32>(RAM_BASE + i * 4).read());
+            }
+            black_box(sum)
+        })
+    });
+}
+
+fn bench_regs(c: &mut Criterion) {
+    bench_read32(c, "bus/reg/read32", |i| REG_BASE + (i & 1) * 4);
+    bench_read32(c, "bus/reg/read32_callback", |_| REG_BASE + 8);
+    bench_write32(c, "bus/reg/write32", |i| REG_BASE + (i & 1) * 4);
+    bench_write32(c, "bus/reg/write32_callback", |_| REG_BASE + 8);
+
+    // 64-bit accesses are split among the two underlying 32-bit registers.
+    let bus = new_bus();
+    c.bench_function("bus/reg/read64_combined", move |b| {
+        b.iter(|| {
+            let mut sum = 0u64;
+            for _ in 0..ACCESSES {
+                sum = sum.wrapping_add(bus.read::<u64>(REG_BASE));
+            }
+            black_box(sum)
+        })
+    });
+
+    bench_read32(c, "bus/unmapped/read32", |i| UNMAPPED_BASE + i * 4);
+}
+
+criterion_group!(benches, bench_mem, bench_regs);
+criterion_main!(benches);
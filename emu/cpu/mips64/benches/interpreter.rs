@@ -0,0 +1,182 @@
+//! Benchmarks of the interpreter, running instruction mixes in a loop, and of
+//! single FPU operations.
+//!
+//! Instruction mixes are in benches/mixes, one opcode per line, optionally
+//! prefixed by its address, with the same format of `r64emu disasm`. The
+//! current mixes are hand-written after common patterns of game code; mixes
+//! of real games are generated with `benches/mkmix.py` from instruction
+//! traces recorded by the debugger. Control flow must stay within the mix;
+//! the last instruction falls through into a jump back to the first one.
+//!
+//! Run with `cargo bench -p mips64`.
+#[macro_use]
+extern crate criterion;
+extern crate emu;
+extern crate mips64;
+extern crate slog;
+
+use criterion::{black_box, Criterion};
+use emu::bus::be::{Bus, BusFill, Mem, MemFlags};
+use emu::dbg::Tracer;
+use mips64::{ArchIII, Config, Cop, CopNull, Cp0, Cpu, CpuContext, Fpu};
+
+static MIXES: [(&str, &str); 4] = [
+    ("alu", include_str!("mixes/alu.txt")),
+    ("memcpy", include_str!("mixes/memcpy.txt")),
+    ("branchy", include_str!("mixes/branchy.txt")),
+    ("fpu", include_str!("mixes/fpu.txt")),
+];
+
+// Cycles run by each iteration.
+const CYCLES: i64 = 10_000;
+
+const RAM_SIZE: usize = 8 * 1024 * 1024;
+
+// Data area accessed by the mixes: the registers a0-a3 and sp point to it
+// (at 4 KiB of distance), and it's filled with 1.0f, so that FPU operations
+// do not hit exceptions (eg: invalid operations).
+const DATA_BASE: u32 = 0x0070_0000;
+const DATA_SIZE: u32 = 0x8000;
+const DATA_REGS: [usize; 5] = [4, 5, 6, 7, 29];
+
+// COP0 status: COP0/COP1 usable, 64-bit FPU mode, interrupts disabled.
+const STATUS: u64 = 0x3400_0000;
+
+struct BenchConfig;
+
+impl Config for BenchConfig {
+    type Arch = ArchIII;
+    type Cop0 = Cp0;
+    type Cop1 = Fpu;
+    type Cop2 = CopNull;
+    type Cop3 = CopNull;
+}
+
+// Sign-extend a KSEG0 address.
+fn kseg0(addr: u32) -> u64 {
+    (addr | 0x8000_0000) as i32 as i64 as u64
+}
+
+// Parse a mix, returning the address of its first instruction (if not
+// specified, it's loaded at 0x80001000) and the opcodes.
+fn parse_mix(text: &str) -> (u32, Vec<u32>) {
+    let mut base = None;
+    let mut ops = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap().trim();
+        let mut toks = line.split_whitespace();
+        let mut tok = match toks.next() {
+            Some(tok) => tok,
+            None => continue,
+        };
+        if tok.ends_with(':') {
+            let pc = u32::from_str_radix(&tok[..tok.len() - 1], 16).unwrap();
+            base = base.or(Some(pc));
+            tok = toks.next().unwrap();
+        }
+        ops.push(u32::from_str_radix(tok, 16).unwrap());
+    }
+    (base.unwrap_or(0x8000_1000), ops)
+}
+
+fn new_cpu(mix: &str) -> Cpu<BenchConfig> {
+    let logger = slog::Logger::root(slog::Discard, slog::o!());
+
+    // The memory is leaked, as the bus refers to it for its whole life.
+    let ram: &'static Mem = Box::leak(Box::new(Mem::new(
+        "ram",
+        RAM_SIZE,
+        MemFlags::new(true, true),
+        None,
+    )));
+    let mut bus = Bus::new(logger.new(slog::o!()));
+    bus.map_mem(0, RAM_SIZE as u32 - 1, ram, BusFill::None)
+        .unwrap();
+
+    let (base, mut ops) = parse_mix(mix);
+    ops.push(0x0800_0000 | ((base >> 2) & 0x03FF_FFFF)); // j base
+    ops.push(0); // nop
+    for (i, op) in ops.iter().enumerate() {
+        bus.write::<u32>((base & 0x1FFF_FFFF) + i as u32 * 4, *op);
+    }
+    for addr in (DATA_BASE..DATA_BASE + DATA_SIZE).step_by(4) {
+        bus.write::<u32>(addr, 0x3F80_0000);
+    }
+
+    let mut cpu = Cpu::new(
+        "bench",
+        logger.new(slog::o!()),
+        bus,
+        (
+            Cp0::new("bench", logger.new(slog::o!())),
+            Fpu::new("bench", logger.new(slog::o!())),
+            CopNull {},
+            CopNull {},
+        ),
+    );
+    cpu.set_cop0_reg(12, STATUS);
+    let ctx = cpu.ctx_mut();
+    for (i, &reg) in DATA_REGS.iter().enumerate() {
+        ctx.regs[reg] = kseg0(DATA_BASE + i as u32 * 0x1000);
+    }
+    ctx.set_pc(kseg0(base));
+    cpu
+}
+
+fn bench_mixes(c: &mut Criterion) {
+    for (name, mix) in MIXES.iter() {
+        let mut cpu = new_cpu(mix);
+        c.bench_function(&format!("interpreter/{}", name), move |b| {
+            let t = Tracer::null();
+            b.iter(|| {
+                let until = cpu.ctx().clock + CYCLES;
+                cpu.run(until, &t).unwrap();
+            })
+        });
+    }
+}
+
+// Build a COP1 arithmetic opcode (fd=f0, fs=f2, ft=f4).
+fn cop1(fmt: u32, func: u32) -> u32 {
+    0x11 << 26 | fmt << 21 | 4 << 16 | 2 << 11 | func
+}
+
+const FMT_S: u32 = 16;
+const FMT_D: u32 = 17;
+const FMT_W: u32 = 20;
+
+fn bench_fpu(c: &mut Criterion) {
+    let ops = [
+        ("add.s", cop1(FMT_S, 0x00), 3.0f32.to_bits() as u64),
+        ("mul.s", cop1(FMT_S, 0x02), 3.0f32.to_bits() as u64),
+        ("div.s", cop1(FMT_S, 0x03), 3.0f32.to_bits() as u64),
+        ("sqrt.s", cop1(FMT_S, 0x04), 3.0f32.to_bits() as u64),
+        ("add.d", cop1(FMT_D, 0x00), 3.0f64.to_bits()),
+        ("mul.d", cop1(FMT_D, 0x02), 3.0f64.to_bits()),
+        ("div.d", cop1(FMT_D, 0x03), 3.0f64.to_bits()),
+        ("sqrt.d", cop1(FMT_D, 0x04), 3.0f64.to_bits()),
+        ("cvt.s.d", cop1(FMT_D, 0x20), 3.0f64.to_bits()),
+        ("cvt.w.s", cop1(FMT_S, 0x24), 3.0f32.to_bits() as u64),
+        ("cvt.s.w", cop1(FMT_W, 0x20), 3),
+        ("c.lt.s", cop1(FMT_S, 0x3c), 3.0f32.to_bits() as u64),
+    ];
+
+    for &(name, opcode, val) in ops.iter() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let mut fpu = Fpu::new("bench", logger);
+        let mut cpu = CpuContext::default();
+        cpu.fpu64 = true;
+        c.bench_function(&format!("fpu/{}", name), move |b| {
+            let t = Tracer::null();
+            b.iter(|| {
+                // Reload the operands, as the destination may overlap them.
+                fpu.set_reg(&mut cpu, 2, val as u128);
+                fpu.set_reg(&mut cpu, 4, val as u128);
+                fpu.op(&mut cpu, black_box(opcode), &t).unwrap();
+            })
+        });
+    }
+}
+
+criterion_group!(benches, bench_mixes, bench_fpu);
+criterion_main!(benches);
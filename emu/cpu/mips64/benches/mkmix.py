@@ -0,0 +1,106 @@
+#!/usr/bin/env python3
+"""Extract a benchmark instruction mix from an instruction trace.
+
+The trace is the file written by the debugger ("Stream to file..." in the
+Instruction trace window, or the "trace" command), either plain text or
+LZ4-compressed. The script finds the loop where the CPU spent most of the
+time, among those whose control flow stays within the loop itself (as
+required by the benchmark), and prints it in the format of benches/mixes:
+
+    mkmix.py trace.txt.lz4 --name "Super Mario 64" > mixes/sm64.txt
+
+Only the instructions of the selected CPU (R4300 by default) are considered.
+"""
+
+import argparse
+import collections
+import io
+import re
+import subprocess
+import sys
+
+# "[CPU] PC OPCODE DISASM CHANGED-REGS", with DISASM padded to 40 columns.
+LINE = re.compile(r"^\[(\S+)\] ([0-9a-f]+) ([0-9a-f]{8}) (.{0,40})")
+
+
+def open_trace(path):
+    if not path.endswith(".lz4"):
+        return open(path, encoding="utf-8", errors="replace")
+    try:
+        import lz4.frame
+
+        return io.TextIOWrapper(lz4.frame.open(path), encoding="utf-8", errors="replace")
+    except ImportError:
+        out = subprocess.run(["lz4", "-dc", path], check=True, stdout=subprocess.PIPE).stdout
+        return io.StringIO(out.decode("utf-8", errors="replace"))
+
+
+def branch_target(pc, op):
+    """Return the target of a branch or jump, None if it is not one, or -1
+    if the target is only known at runtime (JR/JALR)."""
+    major = op >> 26
+    if major == 0 and (op & 0x3F) in (0x08, 0x09):
+        return -1
+    if major in (0x02, 0x03):
+        return ((pc + 4) & 0xF000_0000) | ((op & 0x03FF_FFFF) << 2)
+    regimm = major == 0x01 and ((op >> 16) & 0x1F) in (0, 1, 2, 3, 16, 17, 18, 19)
+    if regimm or major in (0x04, 0x05, 0x06, 0x07, 0x14, 0x15, 0x16, 0x17):
+        off = op & 0xFFFF
+        if off & 0x8000:
+            off -= 0x10000
+        return (pc + 4 + off * 4) & 0xFFFF_FFFF
+    if major in (0x11, 0x12) and ((op >> 21) & 0x1F) == 0x08:  # BCzF/BCzT
+        off = op & 0xFFFF
+        if off & 0x8000:
+            off -= 0x10000
+        return (pc + 4 + off * 4) & 0xFFFF_FFFF
+    return None
+
+
+def main():
+    ap = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    ap.add_argument("trace", help="instruction trace (.txt or .txt.lz4)")
+    ap.add_argument("--cpu", default="R4300", help="CPU whose instructions are used")
+    ap.add_argument("--name", default=None, help="game name, for the header comment")
+    ap.add_argument("--max-len", type=int, default=256, help="maximum loop length, in opcodes")
+    args = ap.parse_args()
+
+    opcodes = {}
+    disasm = {}
+    loops = collections.Counter()
+    prev = None
+    with open_trace(args.trace) as f:
+        for line in f:
+            m = LINE.match(line)
+            if not m or m.group(1) != args.cpu:
+                continue
+            pc, op = int(m.group(2), 16) & 0xFFFF_FFFF, int(m.group(3), 16)
+            opcodes[pc] = op
+            disasm[pc] = m.group(4).strip()
+            # A backward jump closes an iteration of the loop starting at
+            # the target; count the executed instructions.
+            if prev is not None and pc <= prev and (prev - pc) // 4 < args.max_len:
+                loops[(pc, prev)] += (prev - pc) // 4 + 1
+            prev = pc
+
+    for (start, end), count in loops.most_common():
+        pcs = range(start, end + 4, 4)
+        if any(pc not in opcodes for pc in pcs):
+            continue  # not all the instructions of the range were executed
+        targets = [branch_target(pc, opcodes[pc]) for pc in pcs]
+        if any(t is not None and not start <= t <= end for t in targets):
+            continue  # control flow escapes the loop
+        break
+    else:
+        sys.exit("no self-contained loop found in the trace")
+
+    source = args.name or args.trace
+    print("# Hottest self-contained loop of %s (%d instructions executed)," % (source, count))
+    print("# extracted from an instruction trace with mkmix.py.")
+    print()
+    for pc in pcs:
+        print("%08x: %08x  %s" % (pc, opcodes[pc], disasm[pc]))
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,59 @@
+//! Decode random opcodes (the first 4 bytes of the input, big-endian) with
+//! the CPU decoder, including the COP0 and FPU ones, and render them.
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate emu;
+extern crate mips64;
+extern crate slog;
+
+use emu::bus::be::Bus;
+use emu::dbg::InsnTraceView;
+use mips64::{ArchIII, Config, CopNull, Cp0, Cpu, Fpu};
+
+struct FuzzConfig;
+
+impl Config for FuzzConfig {
+    type Arch = ArchIII;
+    type Cop0 = Cp0;
+    type Cop1 = Fpu;
+    type Cop2 = CopNull;
+    type Cop3 = CopNull;
+}
+
+thread_local! {
+    static CPU: Cpu<FuzzConfig> = {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        Cpu::new(
+            "fuzz",
+            logger.new(slog::o!()),
+            Bus::new(logger.new(slog::o!())),
+            (
+                Cp0::new("fuzz", logger.new(slog::o!())),
+                Fpu::new("fuzz", logger.new(slog::o!())),
+                CopNull {},
+                CopNull {},
+            ),
+        )
+    };
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 4 {
+        return;
+    }
+    let opcode = u32::from(data[0]) << 24
+        | u32::from(data[1]) << 16
+        | u32::from(data[2]) << 8
+        | u32::from(data[3]);
+
+    CPU.with(|cpu| {
+        // Use a KSEG0 PC, so that branch targets are computed as in games.
+        let insn = cpu.disasm_insn(0xFFFF_FFFF_8000_1000, opcode);
+        let _ = insn.disasm();
+        let _ = insn.branch_target();
+        for arg in insn.args() {
+            let _ = (arg.input(), arg.output(), arg.is_hidden());
+        }
+    });
+});
@@ -0,0 +1,40 @@
+//! Execute random COP1 opcodes on the FPU. The input is made of the opcode
+//! (4 bytes, big-endian; the major opcode is forced to COP1), a byte
+//! selecting the 32-bit or 64-bit mode and the rounding mode, followed by
+//! the initial values of the FPU registers (8 bytes each, big-endian).
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate emu;
+extern crate mips64;
+extern crate slog;
+
+use emu::dbg::Tracer;
+use mips64::{Cop, CpuContext, Fpu};
+
+fn be64(buf: &[u8]) -> u64 {
+    buf.iter().fold(0, |acc, &b| acc << 8 | u64::from(b))
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 5 {
+        return;
+    }
+    let opcode = 0x11 << 26 | (be64(&data[..4]) as u32 & 0x03FF_FFFF);
+    let mode = data[4];
+
+    let logger = slog::Logger::root(slog::Discard, slog::o!());
+    let mut fpu = Fpu::new("fuzz", logger);
+    let mut cpu = CpuContext::default();
+    cpu.fpu64 = mode & 0x80 != 0;
+    for (idx, val) in data[5..].chunks(8).take(32).enumerate() {
+        fpu.set_reg(&mut cpu, idx, be64(val) as u128);
+    }
+    // Select the rounding mode (CTC1 FCSR), without enabling exceptions.
+    cpu.regs[1] = u64::from(mode & 3);
+    let ctc1 = 0x11 << 26 | 0x6 << 21 | 1 << 16 | 31 << 11;
+    fpu.op(&mut cpu, ctc1, &Tracer::null()).unwrap();
+
+    // Errors are fine (eg: unimplemented opcodes); panics are not.
+    let _ = fpu.op(&mut cpu, opcode, &Tracer::null());
+});
@@ -0,0 +1,407 @@
+//! A minimal single-instruction assembler, used by the debugger to patch
+//! instructions in memory.
+//!
+//! It supports the integer instructions of the main CPU (with the same
+//! syntax produced by the disassembler) and a few common pseudo-instructions
+//! (nop, move, li, b, beqz, bnez). Coprocessor instructions are not supported.
+use super::decode::REG_NAMES;
+
+fn parse_reg(s: &str) -> Result<u32, String> {
+    let name = s.trim().trim_start_matches('$');
+    let idx = match name {
+        "zero" | "r0" => Some(0),
+        "s8" => Some(30),
+        _ => REG_NAMES.iter().take(32).position(|n| *n == name),
+    };
+    if let Some(idx) = idx {
+        return Ok(idx as u32);
+    }
+    // Numeric register (r12 or $12)
+    match name.trim_start_matches('r').parse::<u32>() {
+        Ok(idx) if idx < 32 => Ok(idx),
+        _ => Err(format!("invalid register: {}", s.trim())),
+    }
+}
+
+fn parse_imm(s: &str) -> Result<i64, String> {
+    let s = s.trim();
+    let (neg, abs) = if s.starts_with('-') {
+        (true, &s[1..])
+    } else {
+        (false, s)
+    };
+    let val = if abs.starts_with("0x") || abs.starts_with("0X") {
+        i64::from_str_radix(&abs[2..], 16)
+    } else {
+        abs.parse::<i64>()
+    }
+    .map_err(|_| format!("invalid immediate: {}", s))?;
+    Ok(if neg { -val } else { val })
+}
+
+// Parse a 16-bit immediate, accepting both signed and unsigned values.
+fn parse_imm16(s: &str) -> Result<u32, String> {
+    let val = parse_imm(s)?;
+    if val < -0x8000 || val > 0xFFFF {
+        return Err(format!("immediate out of range: {}", s.trim()));
+    }
+    Ok(val as u32 & 0xFFFF)
+}
+
+fn parse_sa(s: &str) -> Result<u32, String> {
+    match parse_imm(s)? {
+        sa @ 0..=31 => Ok(sa as u32),
+        _ => Err(format!("invalid shift amount: {}", s.trim())),
+    }
+}
+
+// Parse a memory operand in the form "offset(base)".
+fn parse_memop(s: &str) -> Result<(u32, u32), String> {
+    let s = s.trim();
+    let open = s
+        .find('(')
+        .ok_or_else(|| format!("invalid memory operand: {}", s))?;
+    if !s.ends_with(')') {
+        return Err(format!("invalid memory operand: {}", s));
+    }
+    let off = if s[..open].trim().is_empty() {
+        0
+    } else {
+        parse_imm16(&s[..open])?
+    };
+    let base = parse_reg(&s[open + 1..s.len() - 1])?;
+    Ok((off, base))
+}
+
+// Encode the offset of a branch at `pc` to `target`.
+fn branch_offset(pc: u64, target: &str) -> Result<u32, String> {
+    let target = parse_imm(target)? as u32;
+    let off = target.wrapping_sub(pc as u32 + 4) as i32;
+    if off & 3 != 0 || off < -0x20000 || off >= 0x20000 {
+        return Err(format!("branch target out of range: {:#x}", target));
+    }
+    Ok((off >> 2) as u32 & 0xFFFF)
+}
+
+// Encode the target of a jump. The upper bits of the address come from the
+// PC, so only the low 28 bits of the target are used.
+fn jump_target(target: &str) -> Result<u32, String> {
+    let target = parse_imm(target)? as u32;
+    if target & 3 != 0 {
+        return Err(format!("misaligned jump target: {:#x}", target));
+    }
+    Ok((target >> 2) & 0x03FF_FFFF)
+}
+
+fn special(rs: u32, rt: u32, rd: u32, sa: u32, funct: u32) -> u32 {
+    (rs << 21) | (rt << 16) | (rd << 11) | (sa << 6) | funct
+}
+
+fn itype(op: u32, rs: u32, rt: u32, imm: u32) -> u32 {
+    (op << 26) | (rs << 21) | (rt << 16) | imm
+}
+
+/// Assemble a single instruction located at `pc` (which is needed to encode
+/// branches), returning its opcode. Arguments are separated by commas, and
+/// branch targets are absolute addresses, as shown by the disassembler.
+pub fn assemble(pc: u64, text: &str) -> Result<u32, String> {
+    let text = text.trim().to_lowercase();
+    let (mnemonic, args) = match text.find(char::is_whitespace) {
+        Some(idx) => (&text[..idx], text[idx..].trim()),
+        None => (&text[..], ""),
+    };
+    let args: Vec<&str> = if args.is_empty() {
+        Vec::new()
+    } else {
+        args.split(',').map(|a| a.trim()).collect()
+    };
+
+    let nargs = |n: usize| -> Result<(), String> {
+        if args.len() != n {
+            Err(format!("{} requires {} arguments", mnemonic, n))
+        } else {
+            Ok(())
+        }
+    };
+
+    let opcode = match mnemonic {
+        "nop" => {
+            nargs(0)?;
+            0
+        }
+        "break" => {
+            nargs(0)?;
+            special(0, 0, 0, 0, 0x0D)
+        }
+        "sync" => {
+            nargs(0)?;
+            special(0, 0, 0, 0, 0x0F)
+        }
+
+        // Three-register ALU ops: rd, rs, rt
+        "add" | "addu" | "sub" | "subu" | "and" | "or" | "xor" | "nor" | "slt" | "sltu"
+        | "dadd" | "daddu" | "dsub" | "dsubu" => {
+            nargs(3)?;
+            let funct = match mnemonic {
+                "add" => 0x20,
+                "addu" => 0x21,
+                "sub" => 0x22,
+                "subu" => 0x23,
+                "and" => 0x24,
+                "or" => 0x25,
+                "xor" => 0x26,
+                "nor" => 0x27,
+                "slt" => 0x2A,
+                "sltu" => 0x2B,
+                "dadd" => 0x2C,
+                "daddu" => 0x2D,
+                "dsub" => 0x2E,
+                _ => 0x2F,
+            };
+            let (rd, rs, rt) = (
+                parse_reg(args[0])?,
+                parse_reg(args[1])?,
+                parse_reg(args[2])?,
+            );
+            special(rs, rt, rd, 0, funct)
+        }
+
+        // Shifts by immediate: rd, rt, sa
+        "sll" | "srl" | "sra" | "dsll" | "dsrl" | "dsra" | "dsll32" | "dsrl32" | "dsra32" => {
+            nargs(3)?;
+            let funct = match mnemonic {
+                "sll" => 0x00,
+                "srl" => 0x02,
+                "sra" => 0x03,
+                "dsll" => 0x38,
+                "dsrl" => 0x3A,
+                "dsra" => 0x3B,
+                "dsll32" => 0x3C,
+                "dsrl32" => 0x3E,
+                _ => 0x3F,
+            };
+            let (rd, rt, sa) = (parse_reg(args[0])?, parse_reg(args[1])?, parse_sa(args[2])?);
+            special(0, rt, rd, sa, funct)
+        }
+
+        // Shifts by register: rd, rt, rs
+        "sllv" | "srlv" | "srav" | "dsllv" | "dsrlv" | "dsrav" => {
+            nargs(3)?;
+            let funct = match mnemonic {
+                "sllv" => 0x04,
+                "srlv" => 0x06,
+                "srav" => 0x07,
+                "dsllv" => 0x14,
+                "dsrlv" => 0x16,
+                _ => 0x17,
+            };
+            let (rd, rt, rs) = (
+                parse_reg(args[0])?,
+                parse_reg(args[1])?,
+                parse_reg(args[2])?,
+            );
+            special(rs, rt, rd, 0, funct)
+        }
+
+        // Multiplications and divisions: rs, rt
+        "mult" | "multu" | "div" | "divu" | "dmult" | "dmultu" | "ddiv" | "ddivu" | "teq" => {
+            nargs(2)?;
+            let funct = match mnemonic {
+                "mult" => 0x18,
+                "multu" => 0x19,
+                "div" => 0x1A,
+                "divu" => 0x1B,
+                "dmult" => 0x1C,
+                "dmultu" => 0x1D,
+                "ddiv" => 0x1E,
+                "ddivu" => 0x1F,
+                _ => 0x34,
+            };
+            special(parse_reg(args[0])?, parse_reg(args[1])?, 0, 0, funct)
+        }
+
+        "mfhi" | "mflo" => {
+            nargs(1)?;
+            let funct = if mnemonic == "mfhi" { 0x10 } else { 0x12 };
+            special(0, 0, parse_reg(args[0])?, 0, funct)
+        }
+        "mthi" | "mtlo" => {
+            nargs(1)?;
+            let funct = if mnemonic == "mthi" { 0x11 } else { 0x13 };
+            special(parse_reg(args[0])?, 0, 0, 0, funct)
+        }
+        "jr" => {
+            nargs(1)?;
+            special(parse_reg(args[0])?, 0, 0, 0, 0x08)
+        }
+        "jalr" => match args.len() {
+            1 => special(parse_reg(args[0])?, 0, 31, 0, 0x09),
+            _ => {
+                nargs(2)?;
+                special(parse_reg(args[1])?, 0, parse_reg(args[0])?, 0, 0x09)
+            }
+        },
+        "move" => {
+            nargs(2)?;
+            special(parse_reg(args[1])?, 0, parse_reg(args[0])?, 0, 0x21)
+        }
+
+        // Immediate ALU ops: rt, rs, imm
+        "addi" | "addiu" | "slti" | "sltiu" | "andi" | "ori" | "xori" | "daddi" | "daddiu" => {
+            nargs(3)?;
+            let op = match mnemonic {
+                "addi" => 0x08,
+                "addiu" => 0x09,
+                "slti" => 0x0A,
+                "sltiu" => 0x0B,
+                "andi" => 0x0C,
+                "ori" => 0x0D,
+                "xori" => 0x0E,
+                "daddi" => 0x18,
+                _ => 0x19,
+            };
+            let (rt, rs, imm) = (
+                parse_reg(args[0])?,
+                parse_reg(args[1])?,
+                parse_imm16(args[2])?,
+            );
+            itype(op, rs, rt, imm)
+        }
+        "lui" => {
+            nargs(2)?;
+            itype(0x0F, 0, parse_reg(args[0])?, parse_imm16(args[1])?)
+        }
+        "li" => {
+            nargs(2)?;
+            let rt = parse_reg(args[0])?;
+            match parse_imm(args[1])? {
+                v @ -0x8000..=0x7FFF => itype(0x09, 0, rt, v as u32 & 0xFFFF),
+                v @ 0x8000..=0xFFFF => itype(0x0D, 0, rt, v as u32),
+                _ => return Err("li immediate needs two instructions (use lui/ori)".into()),
+            }
+        }
+
+        // Branches comparing two registers: rs, rt, target
+        "beq" | "bne" | "beql" | "bnel" => {
+            nargs(3)?;
+            let op = match mnemonic {
+                "beq" => 0x04,
+                "bne" => 0x05,
+                "beql" => 0x14,
+                _ => 0x15,
+            };
+            let (rs, rt) = (parse_reg(args[0])?, parse_reg(args[1])?);
+            itype(op, rs, rt, branch_offset(pc, args[2])?)
+        }
+        "beqz" | "bnez" | "beqzl" | "bnezl" => {
+            nargs(2)?;
+            let op = match mnemonic {
+                "beqz" => 0x04,
+                "bnez" => 0x05,
+                "beqzl" => 0x14,
+                _ => 0x15,
+            };
+            itype(op, parse_reg(args[0])?, 0, branch_offset(pc, args[1])?)
+        }
+        "b" => {
+            nargs(1)?;
+            itype(0x04, 0, 0, branch_offset(pc, args[0])?)
+        }
+
+        // Branches comparing a register with zero: rs, target
+        "blez" | "bgtz" | "blezl" | "bgtzl" => {
+            nargs(2)?;
+            let op = match mnemonic {
+                "blez" => 0x06,
+                "bgtz" => 0x07,
+                "blezl" => 0x16,
+                _ => 0x17,
+            };
+            itype(op, parse_reg(args[0])?, 0, branch_offset(pc, args[1])?)
+        }
+        "bltz" | "bgez" | "bltzl" | "bgezl" | "bltzal" | "bgezal" | "bltzall" | "bgezall" => {
+            nargs(2)?;
+            let rt = match mnemonic {
+                "bltz" => 0x00,
+                "bgez" => 0x01,
+                "bltzl" => 0x02,
+                "bgezl" => 0x03,
+                "bltzal" => 0x10,
+                "bgezal" => 0x11,
+                "bltzall" => 0x12,
+                _ => 0x13,
+            };
+            itype(0x01, parse_reg(args[0])?, rt, branch_offset(pc, args[1])?)
+        }
+
+        "j" | "jal" => {
+            nargs(1)?;
+            let op = if mnemonic == "j" { 0x02 } else { 0x03 };
+            (op << 26) | jump_target(args[0])?
+        }
+
+        // Loads and stores: rt, offset(base)
+        "lb" | "lh" | "lwl" | "lw" | "lbu" | "lhu" | "lwr" | "lwu" | "sb" | "sh" | "swl" | "sw"
+        | "sdl" | "sdr" | "swr" | "ldl" | "ldr" | "ld" | "sd" => {
+            nargs(2)?;
+            let op = match mnemonic {
+                "ldl" => 0x1A,
+                "ldr" => 0x1B,
+                "lb" => 0x20,
+                "lh" => 0x21,
+                "lwl" => 0x22,
+                "lw" => 0x23,
+                "lbu" => 0x24,
+                "lhu" => 0x25,
+                "lwr" => 0x26,
+                "lwu" => 0x27,
+                "sb" => 0x28,
+                "sh" => 0x29,
+                "swl" => 0x2A,
+                "sw" => 0x2B,
+                "sdl" => 0x2C,
+                "sdr" => 0x2D,
+                "swr" => 0x2E,
+                "ld" => 0x37,
+                _ => 0x3F,
+            };
+            let rt = parse_reg(args[0])?;
+            let (off, base) = parse_memop(args[1])?;
+            itype(op, base, rt, off)
+        }
+
+        "" => return Err("empty instruction".into()),
+        _ => return Err(format!("unsupported instruction: {}", mnemonic)),
+    };
+    Ok(opcode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+
+    #[test]
+    fn encodings() {
+        assert_eq!(assemble(0, "nop"), Ok(0));
+        assert_eq!(assemble(0, "addiu a0, a0, 1"), Ok(0x2484_0001));
+        assert_eq!(assemble(0, "addiu sp,sp,-24"), Ok(0x27BD_FFE8));
+        assert_eq!(assemble(0, "lw ra, 0x14(sp)"), Ok(0x8FBF_0014));
+        assert_eq!(assemble(0, "jr $ra"), Ok(0x03E0_0008));
+        assert_eq!(assemble(0, "sll t0, t1, 2"), Ok(0x0009_4080));
+        assert_eq!(assemble(0, "move v0, a0"), Ok(0x0080_1021));
+        assert_eq!(assemble(0, "li v0, 0xffff"), Ok(0x3402_FFFF));
+        assert_eq!(assemble(0x1000, "jal 0x80002000"), Ok(0x0C00_0800));
+        assert_eq!(assemble(0x1000, "beq a0, zero, 0x1010"), Ok(0x1080_0003));
+        assert_eq!(assemble(0x1000, "b 0xffc"), Ok(0x1000_FFFE));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(assemble(0, "foo a0").is_err());
+        assert!(assemble(0, "addiu a0, a0").is_err());
+        assert!(assemble(0, "addiu a0, x9, 1").is_err());
+        assert!(assemble(0, "addiu a0, a0, 0x10000").is_err());
+        assert!(assemble(0, "beq a0, a0, 0x1002").is_err());
+        assert!(assemble(0, "li a0, 0x12345678").is_err());
+    }
+}
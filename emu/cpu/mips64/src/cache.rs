@@ -0,0 +1,141 @@
+use emu::bus::be::Bus;
+use emu::memint::MemInt;
+use emu::state::ArrayField;
+
+use byteorder::BigEndian;
+use serde_derive::{Deserialize, Serialize};
+
+// Largest line size of the VR4300 caches (I-cache lines are 32 bytes).
+const MAX_LINE_SIZE: usize = 32;
+
+#[derive(Copy, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Line {
+    pub(crate) tag: u32,
+    pub(crate) valid: bool,
+    pub(crate) dirty: bool,
+    pub(crate) data: [u8; MAX_LINE_SIZE],
+}
+
+// Direct-mapped cache. Tags are always tracked (they drive the timing model);
+// the contents of the lines are only used when the cache emulation is
+// enabled, otherwise data is read from and written to the bus directly.
+// Lines are part of the emulator state, so that dirty lines survive a
+// snapshot without being written back.
+pub(crate) struct Cache {
+    line_shift: u32,
+    lines: ArrayField<Line>,
+}
+
+impl Cache {
+    pub(crate) fn new(name: &str, size: usize, line_shift: u32) -> Self {
+        Self {
+            line_shift,
+            lines: ArrayField::new(name, Line::default(), size >> line_shift),
+        }
+    }
+
+    pub(crate) fn line_size(&self) -> usize {
+        1 << self.line_shift
+    }
+
+    pub(crate) fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    pub(crate) fn line_addr(&self, tag: u32) -> u32 {
+        tag << self.line_shift
+    }
+
+    pub(crate) fn index(&self, paddr: u32) -> usize {
+        (paddr >> self.line_shift) as usize & (self.lines.len() - 1)
+    }
+
+    pub(crate) fn line(&mut self, idx: usize) -> &mut Line {
+        &mut self.lines[idx]
+    }
+
+    // Index of the line containing the specified address, if it is cached.
+    pub(crate) fn hit(&self, paddr: u32) -> Option<usize> {
+        let idx = self.index(paddr);
+        let line = &self.lines[idx];
+        if line.valid && line.tag == paddr >> self.line_shift {
+            Some(idx)
+        } else {
+            None
+        }
+    }
+
+    // Access the line containing the specified address, allocating it on a
+    // miss. Returns the index of the line, whether it was a hit and, on a
+    // miss, the dirty line that was evicted (if any), which must be written
+    // back. Allocated lines must be filled by the caller.
+    pub(crate) fn access(&mut self, paddr: u32, write: bool) -> (usize, bool, Option<Line>) {
+        if let Some(idx) = self.hit(paddr) {
+            self.lines[idx].dirty |= write;
+            return (idx, true, None);
+        }
+        let idx = self.index(paddr);
+        let old = self.lines[idx];
+        let line = &mut self.lines[idx];
+        line.tag = paddr >> self.line_shift;
+        line.valid = true;
+        line.dirty = write;
+        let victim = if old.valid && old.dirty {
+            Some(old)
+        } else {
+            None
+        };
+        (idx, false, victim)
+    }
+
+    // Fill a line with the memory contents at its address. Areas which are
+    // not memory (eg: registers) are read one word at a time.
+    pub(crate) fn fill(&mut self, idx: usize, bus: &Bus) {
+        let size = self.line_size();
+        let addr = self.line_addr(self.lines[idx].tag);
+        let data = &mut self.lines[idx].data[..size];
+        if let Some(mem) = bus.fetch_read_nolog::<u8>(addr).mem() {
+            if mem.len() >= size {
+                data.copy_from_slice(&mem[..size]);
+                return;
+            }
+        }
+        for (i, word) in data.chunks_mut(4).enumerate() {
+            let val = bus.read::<u32>(addr + i as u32 * 4);
+            u32::endian_write_to::<BigEndian>(word, val);
+        }
+    }
+
+    // Write the contents of a line back to memory.
+    pub(crate) fn writeback(&self, line: &Line, bus: &mut Bus) {
+        let size = self.line_size();
+        let addr = self.line_addr(line.tag);
+        let data = &line.data[..size];
+        if let Some(mem) = bus.fetch_write_nolog::<u8>(addr).mem() {
+            if mem.len() >= size {
+                mem[..size].copy_from_slice(data);
+                return;
+            }
+        }
+        for (i, word) in data.chunks(4).enumerate() {
+            let val = u32::endian_read_from::<BigEndian>(word);
+            bus.write::<u32>(addr + i as u32 * 4, val);
+        }
+    }
+
+    pub(crate) fn read<U: MemInt>(&self, idx: usize, paddr: u32) -> U {
+        let off = paddr as usize & (self.line_size() - 1);
+        U::endian_read_from::<BigEndian>(&self.lines[idx].data[off..off + U::SIZE])
+    }
+
+    pub(crate) fn write<U: MemInt>(&mut self, idx: usize, paddr: u32, val: U) {
+        let off = paddr as usize & (self.line_size() - 1);
+        U::endian_write_to::<BigEndian>(&mut self.lines[idx].data[off..off + U::SIZE], val);
+    }
+
+    pub(crate) fn invalidate(&mut self) {
+        for line in self.lines.iter_mut() {
+            *line = Line::default();
+        }
+    }
+}
@@ -1,12 +1,28 @@
 use bitfield::bitfield;
 
 use super::decode::REG_NAMES;
+use super::mmu::TlbError;
 use super::{Cop, Cop0, CpuContext, Exception};
-use emu::dbg::{DebuggerRenderer, DecodedInsn, Operand, RegisterSize, RegisterView, Result, Tracer};
+#[cfg(feature = "frontend")]
+use emu::dbg::imgui::*;
+#[cfg(feature = "frontend")]
+use emu::dbg::{imgui_input_hex, DebuggerRenderer};
+use emu::dbg::{DecodedInsn, InsnFlags, Operand, Result, Tracer};
+#[cfg(feature = "frontend")]
+use emu::dbg::{RegisterSize, RegisterView};
 use emu::int::Numerics;
-use emu::state::Field;
+use emu::state::{Field, Snapshotable};
 use serde_derive::{Deserialize, Serialize};
 use slog;
+use std::cell::Cell;
+use std::collections::VecDeque;
+
+// Number of exceptions kept in the exception log (for debugging).
+const EXCEPTION_LOG_SIZE: usize = 64;
+
+// Number of CPU cycles after which Count wraps around. Count is incremented
+// every other cycle.
+const COUNT_WRAP_CYCLES: i64 = 1 << 33;
 
 const COP0_REG_NAMES: [&'static str; 32] = [
     "Index",
@@ -90,23 +106,71 @@ struct Cp0Context {
     last_count: u32,
     last_count_clock: i64,
     next_timer_interrupt: i64,
+    #[serde(default)]
+    reg_wired: u32,
+    #[serde(default)]
+    reg_badvaddr: u64,
+    #[serde(default)]
+    reg_context: u64,
+    #[serde(default)]
+    reg_xcontext: u64,
+    #[serde(default)]
+    random_clock: i64, // Clock at which Random was reset to 31
 }
 
 pub struct Cp0 {
     ctx: Field<Cp0Context>,
     logger: slog::Logger,
     cpu_name: &'static str,
+
+    // Debugger state
+    last_tlb_hit: Cell<Option<usize>>, // TLB entry used by the last translation
+    #[cfg_attr(not(feature = "frontend"), allow(dead_code))]
+    tlb_selected: usize, // TLB entry selected for editing
+    exc_log: VecDeque<(i64, Exception, u64)>, // Last exceptions (clock, exception, EPC)
 }
 
 impl Cp0 {
     pub fn new(cpu_name: &'static str, logger: slog::Logger) -> Cp0 {
         Cp0 {
-            ctx: Field::new(&("mips64::".to_owned() + cpu_name + "::cop0"), Cp0Context::default()),
+            ctx: Field::new(
+                &("mips64::".to_owned() + cpu_name + "::cop0"),
+                Cp0Context::default(),
+            ),
             logger: logger,
             cpu_name,
+            last_tlb_hit: Cell::new(None),
+            tlb_selected: 0,
+            exc_log: VecDeque::with_capacity(EXCEPTION_LOG_SIZE),
         }
     }
 
+    /// Current value of the Status register.
+    pub fn status(&self) -> u32 {
+        self.ctx.reg_status.0
+    }
+
+    /// Current value of the Cause register.
+    pub fn cause(&self) -> u32 {
+        self.ctx.reg_cause.0
+    }
+
+    /// Current value of the EPC register.
+    pub fn epc(&self) -> u64 {
+        self.ctx.reg_epc
+    }
+
+    /// Current value of the ErrorEPC register.
+    pub fn error_epc(&self) -> u64 {
+        self.ctx.reg_errorepc
+    }
+
+    /// Return the last exceptions taken by the CPU (oldest first), as tuples
+    /// of CPU clock, exception and EPC. This is meant for debugging.
+    pub fn exception_log(&self) -> impl DoubleEndedIterator<Item = &(i64, Exception, u64)> {
+        self.exc_log.iter()
+    }
+
     fn get_count(&self, cpu: &CpuContext) -> u32 {
         self.ctx
             .last_count
@@ -130,12 +194,32 @@ impl Cp0 {
         self.set_hwint_line(5, false);
     }
 
+    // Random decrements at each cycle, from 31 down to Wired, and then wraps
+    // around.
+    fn get_random(&self, cpu: &CpuContext) -> u32 {
+        if self.ctx.reg_wired >= 31 {
+            return 31;
+        }
+        let range = (32 - self.ctx.reg_wired) as i64;
+        31 - ((cpu.clock - self.ctx.random_clock) % range) as u32
+    }
+
+    fn set_wired(&mut self, cpu: &CpuContext, val: u32) {
+        self.ctx.reg_wired = val & 0x3F;
+        self.ctx.random_clock = cpu.clock;
+    }
+
     fn update_timer_interrupt(&mut self, cpu: &CpuContext) {
-        // Compute the CPU clock at which there will be the next timer interrupt.
-        // There always is a potential timer interrupt in the future because of
-        // the 32-bit wrap-around.
-        self.ctx.next_timer_interrupt =
-            cpu.clock + ((self.ctx.reg_compare.wrapping_sub(self.get_count(cpu)) as i64) << 1);
+        // Compute the CPU clock at which Count will next become equal to
+        // Compare. There always is a potential timer interrupt in the future
+        // because of the 32-bit wrap-around. If Count is already equal to
+        // Compare, the interrupt happens at the next match.
+        let mut next = self.ctx.last_count_clock
+            + ((self.ctx.reg_compare.wrapping_sub(self.ctx.last_count) as i64) << 1);
+        if next <= cpu.clock {
+            next += ((cpu.clock - next) / COUNT_WRAP_CYCLES + 1) * COUNT_WRAP_CYCLES;
+        }
+        self.ctx.next_timer_interrupt = next;
         info!(self.logger, "COP0 update timer IRQ";
             "clock" => cpu.clock,
             "next_irq" => self.ctx.next_timer_interrupt,
@@ -144,6 +228,8 @@ impl Cp0 {
     }
 }
 
+impl Snapshotable for Cp0 {}
+
 impl Cop0 for Cp0 {
     #[inline(always)]
     fn set_hwint_line(&mut self, line: usize, status: bool) {
@@ -160,8 +246,8 @@ impl Cop0 for Cp0 {
         let ctx = unsafe { self.ctx.as_mut() };
         if cpu.clock >= ctx.next_timer_interrupt {
             self.set_hwint_line(5, true);
-            ctx.next_timer_interrupt += 0x8000_0000; // 2**32 / 2
-            info!(self.logger, "COP0 timer IRQ raised");
+            ctx.next_timer_interrupt += COUNT_WRAP_CYCLES;
+            info!(self.logger, "COP0 timer IRQ raised"; "clock" => cpu.clock);
         }
         if ctx.reg_status.ie()
             && !ctx.reg_status.erl()
@@ -172,6 +258,10 @@ impl Cop0 for Cp0 {
         }
     }
 
+    fn next_event(&self) -> i64 {
+        self.ctx.next_timer_interrupt
+    }
+
     fn exception(&mut self, cpu: &mut CpuContext, exc: Exception) {
         use self::Exception::*;
 
@@ -180,8 +270,8 @@ impl Cop0 for Cp0 {
 
         match exc {
             ColdReset => {
-                // ctx.reg_random = 31;
-                // ctx.reg_wired = 0;
+                ctx.reg_wired = 0;
+                ctx.random_clock = cpu.clock;
                 // ctx.reg_config.set_k0(2);
                 // ctx.reg_config[0..3] should be configured as specified in MipsConfig
                 ctx.reg_status.set_rp(false);
@@ -243,6 +333,155 @@ impl Cop0 for Cp0 {
                 }
             }
         };
+
+        if self.exc_log.len() == EXCEPTION_LOG_SIZE {
+            self.exc_log.pop_front();
+        }
+        self.exc_log.push_back((cpu.clock, exc, ctx.reg_epc));
+    }
+
+    fn translate(
+        &self,
+        cpu: &CpuContext,
+        vaddr: u64,
+        write: bool,
+    ) -> std::result::Result<u32, Exception> {
+        if !self.is_mapped(vaddr) {
+            return Ok(vaddr as u32);
+        }
+        let index = cpu
+            .mmu
+            .probe(vaddr, self.ctx.reg_entryhi as u8)
+            .ok_or(Exception::TlbRefill)?;
+        self.last_tlb_hit.set(Some(index));
+        cpu.mmu
+            .translate_entry(index, vaddr, write)
+            .map_err(|err| match err {
+                TlbError::Miss => Exception::TlbRefill,
+                TlbError::Invalid => Exception::TlbInvalid,
+                TlbError::Modified => Exception::TlbModified,
+            })
+    }
+
+    fn is_mapped(&self, vaddr: u64) -> bool {
+        match vaddr as u32 >> 29 {
+            0..=3 => !self.ctx.reg_status.erl(), // KUSEG (unmapped when ERL=1)
+            4 | 5 => false,                      // KSEG0 / KSEG1
+            _ => true,                           // KSSEG / KSEG3
+        }
+    }
+
+    fn tlb_exception(&mut self, cpu: &mut CpuContext, exc: Exception, vaddr: u64, write: bool) {
+        info!(self.logger, "TLB exception";
+            "exc" => ?exc, "vaddr" => vaddr.hex(), "write" => write);
+        let ctx = &mut *self.ctx;
+        ctx.reg_badvaddr = vaddr;
+        ctx.reg_context = (ctx.reg_context & !0x7F_FFFF) | ((vaddr >> 13) & 0x7_FFFF) << 4;
+        ctx.reg_xcontext = (ctx.reg_xcontext & !0x1_FFFF_FFFF)
+            | ((vaddr >> 62) & 3) << 31
+            | ((vaddr >> 13) & 0x7FF_FFFF) << 4;
+        ctx.reg_entryhi = (vaddr & 0xC000_00FF_FFFF_E000) | (ctx.reg_entryhi & 0xFF);
+
+        self.exception(cpu, exc);
+        if write && exc != Exception::TlbModified {
+            // TLBS: TLB exception on store
+            self.ctx.reg_cause.set_exc(0x03);
+        }
+    }
+
+    #[cfg(feature = "frontend")]
+    fn render_regs<'a, 'ui>(&mut self, ctx: &mut CpuContext, dr: &DebuggerRenderer<'a, 'ui>) {
+        dr.render_regview(&mut Cp0Regs {
+            cop0: self,
+            cpu: ctx,
+        });
+    }
+
+    #[cfg(feature = "frontend")]
+    fn render_tlb<'a, 'ui>(&mut self, cpu: &mut CpuContext, dr: &DebuggerRenderer<'a, 'ui>) {
+        let ui = dr.ui();
+        let last_hit = self.last_tlb_hit.get();
+        let (wired, random) = (self.ctx.reg_wired, self.get_random(cpu));
+        let selected = &mut self.tlb_selected;
+
+        Window::new(&im_str!("[{}] TLB", self.cpu_name))
+            .size([520.0, 520.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text(format!("Wired: {}  Random: {}", wired, random));
+                ui.separator();
+
+                ui.columns(6, im_str!("##tlb"), true);
+                for title in ["#", "VPN2", "ASID", "Size", "Even PFN", "Odd PFN"].iter() {
+                    ui.text(title);
+                    ui.next_column();
+                }
+                ui.separator();
+                for idx in 0..32 {
+                    let e = cpu.mmu.read(idx);
+                    let color = if Some(idx) == last_hit {
+                        [1.0, 1.0, 0.3, 1.0]
+                    } else if !e.valid() {
+                        [0.5, 0.5, 0.5, 1.0]
+                    } else {
+                        [1.0, 1.0, 1.0, 1.0]
+                    };
+                    let style = ui.push_style_color(StyleColor::Text, color);
+                    if Selectable::new(&im_str!("{:2}", idx))
+                        .selected(idx == *selected)
+                        .span_all_columns(true)
+                        .build(ui)
+                    {
+                        *selected = idx;
+                    }
+                    ui.next_column();
+                    ui.text(format!("{:08X}", e.hi() as u32 & !0x1FFF));
+                    ui.next_column();
+                    if e.global {
+                        ui.text("global");
+                    } else {
+                        ui.text(format!("{:02X}", e.asid));
+                    }
+                    ui.next_column();
+                    let size = e.page_size();
+                    if size >= 1024 * 1024 {
+                        ui.text(format!("{}M", size / (1024 * 1024)));
+                    } else {
+                        ui.text(format!("{}K", size / 1024));
+                    }
+                    ui.next_column();
+                    for (pfn, valid, dirty) in [
+                        (e.pfn0(), e.valid0(), e.dirty0()),
+                        (e.pfn1(), e.valid1(), e.dirty1()),
+                    ]
+                    .iter()
+                    {
+                        ui.text(format!(
+                            "{:08X} {}{}",
+                            pfn,
+                            if *valid { "V" } else { "-" },
+                            if *dirty { "D" } else { "-" },
+                        ));
+                        ui.next_column();
+                    }
+                    style.pop(&ui);
+                }
+                ui.columns(1, im_str!(""), false);
+                ui.separator();
+
+                // Edit the selected entry. The new values are written to the TLB
+                // as if done by TLBWI.
+                let idx = *selected;
+                let e = *cpu.mmu.read(idx);
+                let (mut mask, mut hi, mut lo0, mut lo1) = (e.page_mask, e.hi(), e.lo0, e.lo1);
+                ui.text(format!("Entry {}:", idx));
+                let mut changed = imgui_input_hex(ui, im_str!("PageMask"), &mut mask, true);
+                changed |= imgui_input_hex(ui, im_str!("EntryHi"), &mut hi, true);
+                changed |= imgui_input_hex(ui, im_str!("EntryLo0"), &mut lo0, true);
+                changed |= imgui_input_hex(ui, im_str!("EntryLo1"), &mut lo1, true);
+                if changed {
+                    cpu.mmu.write(idx, mask, hi, lo0, lo1);
+                }
+            });
     }
 }
 
@@ -250,15 +489,20 @@ impl Cop for Cp0 {
     fn reg(&self, cpu: &CpuContext, idx: usize) -> u128 {
         match idx {
             0 => self.ctx.reg_index as u128,
+            1 => self.get_random(cpu) as u128,
             2 => self.ctx.reg_entrylo0 as u128,
             3 => self.ctx.reg_entrylo1 as u128,
+            4 => self.ctx.reg_context as u128,
             5 => self.ctx.reg_pagemask as u128,
+            6 => self.ctx.reg_wired as u128,
+            8 => self.ctx.reg_badvaddr as u128,
             9 => self.get_count(cpu) as u128,
             10 => self.ctx.reg_entryhi as u128,
             11 => self.ctx.reg_compare as u128,
             12 => self.ctx.reg_status.0 as u128,
             13 => self.ctx.reg_cause.0 as u128,
             14 => self.ctx.reg_epc as u128,
+            20 => self.ctx.reg_xcontext as u128,
             30 => self.ctx.reg_errorepc as u128,
             _ => {
                 error!(
@@ -276,10 +520,25 @@ impl Cop for Cp0 {
             0 => self.ctx.reg_index = val as u32 & 0x3F,
             2 => self.ctx.reg_entrylo0 = val as u64,
             3 => self.ctx.reg_entrylo1 = val as u64,
+            4 => {
+                // Only PTEBase is writable
+                self.ctx.reg_context =
+                    (self.ctx.reg_context & 0x7F_FFFF) | (val as u64 & !0x7F_FFFF)
+            }
             5 => self.ctx.reg_pagemask = val as u32,
-            9 => self.set_count(cpu, val as u32),
+            6 => self.set_wired(cpu, val as u32),
+            8 => {} // BadVAddr is read-only
+            9 => {
+                // Changing the timer requires the CPU to reschedule its next
+                // COP0 event.
+                self.set_count(cpu, val as u32);
+                cpu.tight_exit = true;
+            }
             10 => self.ctx.reg_entryhi = val as u64,
-            11 => self.set_compare(cpu, val as u32),
+            11 => {
+                self.set_compare(cpu, val as u32);
+                cpu.tight_exit = true;
+            }
             12 => {
                 self.ctx.reg_status.0 = val as u32;
                 cpu.fpu64 = self.ctx.reg_status.fr();
@@ -290,6 +549,11 @@ impl Cop for Cp0 {
                 cpu.tight_exit = true;
             }
             14 => self.ctx.reg_epc = val as u64,
+            20 => {
+                // Only PTEBase is writable
+                self.ctx.reg_xcontext =
+                    (self.ctx.reg_xcontext & 0x1_FFFF_FFFF) | (val as u64 & !0x1_FFFF_FFFF)
+            }
             30 => self.ctx.reg_errorepc = val as u64,
             _ => {
                 error!(
@@ -345,6 +609,21 @@ impl Cop for Cp0 {
                         "idx" => ctx.reg_index,
                         "tlb" => ?cpu.mmu.read((ctx.reg_index & 0x1F) as usize));
                 }
+                0x06 => {
+                    // TLBWR
+                    let idx = self.get_random(cpu) as usize;
+                    cpu.mmu.write(
+                        idx,
+                        ctx.reg_pagemask,
+                        ctx.reg_entryhi,
+                        ctx.reg_entrylo0,
+                        ctx.reg_entrylo1,
+                    );
+
+                    info!(self.logger, "wrote random TLB entry";
+                        "idx" => idx,
+                        "tlb" => ?cpu.mmu.read(idx));
+                }
                 0x08 => {
                     // TLBP
                     match cpu.mmu.probe(ctx.reg_entryhi, ctx.reg_entryhi as u8) {
@@ -404,21 +683,47 @@ impl Cop for Cp0 {
             0x10..=0x1F => match func {
                 0x1 => DecodedInsn::new0("tlbr"),
                 0x2 => DecodedInsn::new0("tlbwi"),
+                0x6 => DecodedInsn::new0("tlbwr"),
                 0x8 => DecodedInsn::new0("tlbp"),
-                0x18 => DecodedInsn::new0("eret"),
+                0x18 => DecodedInsn::new0("eret").with_flags(InsnFlags::BRANCH),
                 _ => DecodedInsn::new1("cop0op?", Imm32(func)),
             },
             _ => DecodedInsn::new1("cop0?", Imm32(rs)),
         }
     }
+}
 
-    fn render_debug(&mut self, dr: &DebuggerRenderer) {
-        dr.render_regview(self);
-    }
+// Registers shown in the debugger view: (name, COP0 index, 64-bit).
+#[cfg(feature = "frontend")]
+const VIEW_REGS: [(&'static str, usize, bool); 14] = [
+    ("Status", 12, false),
+    ("Cause", 13, false),
+    ("EPC", 14, true),
+    ("ErrorEPC", 30, true),
+    ("Index", 0, false),
+    ("PageMask", 5, false),
+    ("EntryHi", 10, true),
+    ("EntryLo0", 2, true),
+    ("EntryLo1", 3, true),
+    ("Wired", 6, false),
+    ("BadVAddr", 8, true),
+    ("Context", 4, true),
+    ("Count", 9, false),
+    ("Compare", 11, false),
+];
+
+/// Debugger view of the COP0 registers. Edited registers are written with
+/// `set_reg` (like MTC0), so that their side effects are applied (eg: the
+/// timer interrupt is rescheduled, and read-only bits are preserved).
+#[cfg(feature = "frontend")]
+struct Cp0Regs<'a> {
+    cop0: &'a mut Cp0,
+    cpu: &'a mut CpuContext,
 }
 
-impl RegisterView for Cp0 {
-    const WINDOW_SIZE: [f32; 2] = [180.0, 400.0];
+#[cfg(feature = "frontend")]
+impl<'a> RegisterView for Cp0Regs<'a> {
+    const WINDOW_SIZE: [f32; 2] = [180.0, 500.0];
     const COLUMNS: usize = 1;
 
     fn name(&self) -> &str {
@@ -426,45 +731,133 @@ impl RegisterView for Cp0 {
     }
 
     fn cpu_name(&self) -> &'static str {
-        self.cpu_name
+        self.cop0.cpu_name
     }
 
     fn visit_regs<'s, F>(&'s mut self, col: usize, mut visit: F)
     where
-        F: for<'a> FnMut(&'a str, RegisterSize<'a>, Option<&str>),
+        F: for<'b> FnMut(&'b str, RegisterSize<'b>, Option<&str>),
     {
         use self::RegisterSize::*;
-        let ctx = unsafe { self.ctx.as_mut() };
+        assert!(col == 0);
+
+        let ctx = &self.cop0.ctx;
+        let status = format!(
+            "IM:{:08b} IE:{} EXL:{} ERL:{}",
+            ctx.reg_status.im(),
+            ctx.reg_status.ie() as u8,
+            ctx.reg_status.exl() as u8,
+            ctx.reg_status.erl() as u8,
+        );
+        let cause = format!(
+            "IP:{:08b} EXC:{} BD:{}",
+            ctx.reg_cause.ip(),
+            ctx.reg_cause.exc(),
+            ctx.reg_cause.bd() as u8,
+        );
+
+        for &(name, idx, is64) in VIEW_REGS.iter() {
+            let desc = match idx {
+                12 => Some(status.as_str()),
+                13 => Some(cause.as_str()),
+                _ => None,
+            };
+            let old = self.cop0.reg(self.cpu, idx) as u64;
+            let val = if is64 {
+                let mut val = old;
+                visit(name, Reg64(&mut val), desc);
+                val
+            } else {
+                let mut val = old as u32;
+                visit(name, Reg32(&mut val), desc);
+                val as u64
+            };
+            if val != old {
+                self.cop0.set_reg(self.cpu, idx, val as u128);
+            }
+        }
+    }
+}
 
-        match col {
-            0 => {
-                let status = format!(
-                    "IM:{:08b} IE:{} EXL:{} ERL:{}",
-                    ctx.reg_status.im(),
-                    ctx.reg_status.ie() as u8,
-                    ctx.reg_status.exl() as u8,
-                    ctx.reg_status.erl() as u8,
-                );
-                let cause = format!(
-                    "IP:{:08b} EXC:{} BD:{}",
-                    ctx.reg_cause.ip(),
-                    ctx.reg_cause.exc(),
-                    ctx.reg_cause.bd() as u8,
-                );
-                visit("Status", Reg32(&mut ctx.reg_status.0), Some(&status));
-                visit("Cause", Reg32(&mut ctx.reg_cause.0), Some(&cause));
-                visit("EPC", Reg64(&mut ctx.reg_epc), None);
-                visit("ErrorEPC", Reg64(&mut ctx.reg_errorepc), None);
-
-                visit("Index", Reg32(&mut ctx.reg_index), None);
-                visit("PageMask", Reg32(&mut ctx.reg_pagemask), None);
-                visit("EntryHi", Reg64(&mut ctx.reg_entryhi), None);
-                visit("EntryLo0", Reg64(&mut ctx.reg_entrylo0), None);
-                visit("EntryLo1", Reg64(&mut ctx.reg_entrylo1), None);
-
-                visit("Compare", Reg32(&mut ctx.reg_compare), None);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer_irq(cop0: &Cp0, cpu: &CpuContext) -> bool {
+        cop0.reg(cpu, 13) & (1 << 15) != 0
+    }
+
+    // Run the timer until the specified clock, polling at every cycle.
+    fn run_until(cop0: &mut Cp0, cpu: &mut CpuContext, clock: i64) -> Option<i64> {
+        let mut fired = None;
+        while cpu.clock < clock {
+            cpu.clock += 1;
+            cop0.poll_interrupts(cpu);
+            if fired.is_none() && timer_irq(cop0, cpu) {
+                fired = Some(cpu.clock);
             }
-            _ => unreachable!(),
         }
+        fired
+    }
+
+    fn new_cop0() -> Cp0 {
+        Cp0::new("test", slog::Logger::root(slog::Discard, o!()))
+    }
+
+    #[test]
+    fn test_timer_exact() {
+        let mut cop0 = new_cop0();
+        let mut cpu = CpuContext::default();
+
+        cop0.set_reg(&mut cpu, 9, 0);
+        cop0.set_reg(&mut cpu, 11, 100);
+        assert_eq!(cop0.next_event(), 200);
+        assert_eq!(run_until(&mut cop0, &mut cpu, 300), Some(200));
+        assert_eq!(cop0.reg(&cpu, 9), 150);
+
+        // Writing Compare acknowledges the interrupt
+        cop0.set_reg(&mut cpu, 11, 160);
+        assert!(!timer_irq(&cop0, &cpu));
+        assert_eq!(run_until(&mut cop0, &mut cpu, 400), Some(320));
+    }
+
+    #[test]
+    fn test_timer_odd_clock() {
+        let mut cop0 = new_cop0();
+        let mut cpu = CpuContext::default();
+
+        // Count was written on an odd clock: it increments on odd clocks.
+        cpu.clock = 11;
+        cop0.set_reg(&mut cpu, 9, 0);
+        run_until(&mut cop0, &mut cpu, 20);
+        cop0.set_reg(&mut cpu, 11, 10);
+        assert_eq!(run_until(&mut cop0, &mut cpu, 100), Some(31));
+    }
+
+    #[test]
+    fn test_timer_wraparound() {
+        let mut cop0 = new_cop0();
+        let mut cpu = CpuContext::default();
+
+        cop0.set_reg(&mut cpu, 9, 0xFFFF_FFF0);
+        cop0.set_reg(&mut cpu, 11, 0x10);
+        assert_eq!(cop0.next_event(), 0x20 * 2);
+        assert_eq!(run_until(&mut cop0, &mut cpu, 100), Some(0x40));
+        assert_eq!(cop0.reg(&cpu, 9), 0x22);
+
+        // After firing, the next match is a full wrap-around later
+        assert_eq!(cop0.next_event(), 0x40 + COUNT_WRAP_CYCLES);
+    }
+
+    #[test]
+    fn test_timer_compare_equals_count() {
+        let mut cop0 = new_cop0();
+        let mut cpu = CpuContext::default();
+
+        cpu.clock = 1000;
+        cop0.set_reg(&mut cpu, 9, 1234);
+        cop0.set_reg(&mut cpu, 11, 1234);
+        assert_eq!(cop0.next_event(), 1000 + COUNT_WRAP_CYCLES);
+        assert_eq!(run_until(&mut cop0, &mut cpu, 2000), None);
     }
 }
@@ -1,22 +1,26 @@
 use super::decode::{decode, REG_NAMES};
 use super::mmu::Mmu;
+use super::timing::{MemRegion, MemTiming, Timing};
 use super::{Arch, Config, Cop, Cop0};
 
 use emu::bus::be::{Bus, MemIoR};
+#[cfg(feature = "frontend")]
+use emu::dbg::DebuggerRenderer;
 use emu::dbg::{
-    BusMemoryView, DebuggerRenderer, DecodedInsn, DisasmView, MemoryBank, RegisterSize,
-    RegisterView, Result, Tracer,
+    BusMemoryView, CallStack, CallstackView, DecodedInsn, DisasmView, InsnTraceView, MemoryBank,
+    RegisterSize, RegisterView, Result, SymbolTable, Tracer,
 };
 use emu::int::Numerics;
 use emu::memint::MemInt;
-use emu::state::Field;
+use emu::state::{Field, Snapshotable};
 use emu::sync;
 
 use byteorder::ByteOrder;
 use serde_derive::{Deserialize, Serialize};
 use slog;
+use std::cell::Cell;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Exception {
     Interrupt,  // Interrupt
     Breakpoint, // Breakpoint
@@ -25,7 +29,10 @@ pub enum Exception {
     Nmi,
     TlbRefill,
     XTlbRefill,
+    TlbInvalid,
+    TlbModified,
     Trap,
+    FloatingPoint, // Floating-point exception (from FPU)
 }
 
 impl Exception {
@@ -36,9 +43,12 @@ impl Exception {
             Exception::ColdReset => None,
             Exception::Nmi => None,
             Exception::SoftReset => None,
-            Exception::TlbRefill => None,
-            Exception::XTlbRefill => None,
+            Exception::TlbRefill => Some(0x02),
+            Exception::XTlbRefill => Some(0x02),
+            Exception::TlbInvalid => Some(0x02),
+            Exception::TlbModified => Some(0x01),
             Exception::Trap => Some(0x0D),
+            Exception::FloatingPoint => Some(0x0F),
         }
     }
 }
@@ -61,6 +71,8 @@ pub struct CpuContext {
     pub mmu: Mmu,         // The MMU
     pub fpu64: bool,      // True if the FPU (if any) is in 64-bit mode
     lines: Lines,
+    #[serde(default)]
+    pub(crate) cop_exception: Option<Exception>, // Exception requested by a coprocessor
 }
 
 pub struct Cpu<C: Config> {
@@ -77,6 +89,18 @@ pub struct Cpu<C: Config> {
     until: i64,
 
     last_busy_check: u64,
+    busy_wait_detection: bool,
+    idle_loops: Vec<u32>,
+    callstack: CallStack,
+    symbols: SymbolTable,
+
+    // TLB exception caused by the memory access of the current opcode,
+    // raised once the opcode completes.
+    mem_fault: Cell<Option<(Exception, u64, bool)>>,
+
+    // Memory timing model and caches. Cycles spent by the memory accesses of
+    // the current opcode are added once the opcode completes.
+    timing: Timing,
 }
 
 struct Mipsop<'a, C: Config> {
@@ -171,6 +195,14 @@ impl CpuContext {
         }
     }
 
+    // Request an exception from within a coprocessor opcode. Coprocessors
+    // have no access to COP0, so the exception is raised by the CPU as soon
+    // as the opcode completes.
+    pub fn raise_exception(&mut self, exc: Exception) {
+        self.cop_exception = Some(exc);
+        self.tight_exit = true;
+    }
+
     pub fn set_halt_line(&mut self, stat: bool) {
         self.lines.halt = stat;
         self.tight_exit = true;
@@ -187,6 +219,26 @@ impl CpuContext {
     pub fn get_pc(&self) -> u64 {
         self.pc
     }
+
+    pub fn is_halted(&self) -> bool {
+        self.lines.halt
+    }
+
+    // Lookup a register by name (as used in the disassembly), to evaluate
+    // debugger breakpoint conditions.
+    pub fn reg_by_name(&self, name: &str) -> Option<u64> {
+        match name {
+            "hi" => Some(self.hi),
+            "lo" => Some(self.lo),
+            "pc" => Some(self.pc),
+            "zero" | "r0" => Some(0),
+            _ => REG_NAMES
+                .iter()
+                .take(32)
+                .position(|n| *n == name)
+                .map(|idx| self.regs[idx]),
+        }
+    }
 }
 
 macro_rules! branch {
@@ -204,11 +256,26 @@ macro_rules! branch {
             $op.ctx.regs[31] = $op.ctx.pc + 4;
         }
         let (cond, tgt) = ($cond, $tgt);
+        if $link && cond {
+            // ctx.pc already points to the delay slot.
+            let pc = C::pc_mask($op.ctx.pc as u32 - 4) as u64;
+            let func = C::pc_mask(tgt as u32) as u64;
+            $op.cpu.callstack.call(pc, func, pc + 8, $op.ctx.regs[29]);
+        }
         $op.ctx.branch(cond, tgt, $lkl);
 
+        // Known idle loops (configured by the emulator) are skipped
+        // immediately, without going through the detector.
+        if cond
+            && !$op.cpu.idle_loops.is_empty()
+            && $op.cpu.idle_loops.contains(&C::pc_mask(tgt as u32))
+        {
+            $op.ctx.clock = $op.cpu.until;
+        }
+
         // See if this is a short loop (less than 5 instructions). Short loops
         // go through the busy-wait detector.
-        if cond && tgt != $op.cpu.last_busy_check {
+        if cond && $op.cpu.busy_wait_detection && tgt != $op.cpu.last_busy_check {
             let dist = $op.ctx.pc.wrapping_sub(tgt);
             if dist <= 16 {
                 if !$op.cpu.detect_busy_wait(tgt, (dist as usize >> 2) + 1) {
@@ -253,6 +320,9 @@ macro_rules! if_cop {
 
 macro_rules! if_cop_loadstore {
     ($op:ident, $cop:ident, $loadstore:ident, $t:ident) => {{
+        // Stores are opcodes 0x38-0x3F; 64-bit accesses have bit 2 set.
+        let size = if $op.op() & 4 != 0 { 8 } else { 4 };
+        $op.cpu.cop_mem_access($op.ea(), size, $op.op() >= 0x38);
         if_cop!($op, $cop, {
             return $cop.$loadstore($op.opcode, &mut $op.ctx, &mut $op.cpu.bus, $t);
         })
@@ -277,6 +347,12 @@ impl<C: Config> Cpu<C> {
             logger: logger,
             until: 0,
             last_busy_check: 0,
+            busy_wait_detection: true,
+            idle_loops: Vec::new(),
+            callstack: CallStack::new(),
+            symbols: SymbolTable::new(),
+            mem_fault: Cell::new(None),
+            timing: Timing::new(name),
         };
         cpu.exception(Exception::ColdReset); // Trigger a reset exception at startup
         cpu
@@ -291,13 +367,85 @@ impl<C: Config> Cpu<C> {
     }
 
     pub fn reset(&mut self) {
+        self.callstack.clear();
+        self.timing.reset();
         self.exception(Exception::SoftReset);
     }
 
+    /// Select the timing model of memory accesses. `regions` describes the
+    /// latency of the physical address space, as used by the accurate model;
+    /// accesses outside of all regions take no additional cycles.
+    pub fn set_mem_timing(&mut self, mode: MemTiming, regions: Vec<MemRegion>) {
+        self.timing.set_mode(mode, regions);
+    }
+
+    pub fn mem_timing(&self) -> MemTiming {
+        self.timing.mode()
+    }
+
+    /// Set the additional cycles taken by each memory read because of other
+    /// bus masters competing for memory (eg: RCP DMAs). Only the accurate
+    /// timing model is affected.
+    pub fn set_mem_contention(&mut self, cycles: i64) {
+        self.timing.set_contention(cycles);
+    }
+
+    /// Emulate the contents of the instruction and data caches: cached
+    /// loads and stores go through write-back lines, and opcodes are fetched
+    /// from the instruction cache, so that memory is only coherent with
+    /// the caches after explicit CACHE opcodes, like on real hardware (some
+    /// games depend on stale cache contents). When disabled, only the cache
+    /// tags are tracked, for the accurate timing model.
+    pub fn set_cache_emulation(&mut self, enabled: bool) {
+        self.timing.set_caches(enabled, &mut self.bus);
+    }
+
+    pub fn cache_emulation(&self) -> bool {
+        self.timing.caches()
+    }
+
+    /// Enable or disable the automatic detection of busy-wait loops (enabled
+    /// by default). When disabled, every instruction of the loops is executed,
+    /// like a plain interpreter would do.
+    pub fn set_busy_wait_detection(&mut self, enabled: bool) {
+        self.busy_wait_detection = enabled;
+    }
+
+    /// Configure a list of PCs that are known to be the start of idle loops.
+    /// Whenever the CPU branches to one of them, it will skip ahead to the end
+    /// of the current run slice. This complements the automatic busy-wait
+    /// detection, for loops that are too long or complex to be detected.
+    pub fn set_idle_loops(&mut self, pcs: &[u32]) {
+        self.idle_loops = pcs.iter().map(|pc| C::pc_mask(*pc)).collect();
+    }
+
+    /// Configure the symbols displayed by the debugger. Addresses are
+    /// translated into the same address space used for PCs.
+    pub fn set_symbols(&mut self, syms: &SymbolTable) {
+        self.symbols = SymbolTable::new();
+        for (addr, name, size) in syms.iter() {
+            self.symbols
+                .insert(C::pc_mask(addr as u32) as u64, name, size);
+        }
+    }
+
+    /// Write a COP0 register, as if done by a MTC0 opcode. This can be used
+    /// by emulators to setup the CPU state without running boot code.
+    pub fn set_cop0_reg(&mut self, idx: usize, val: u64) {
+        self.cop0.set_reg(&mut self.ctx, idx, val as u128);
+    }
+
     fn exception(&mut self, exc: Exception) {
         self.cop0.exception(&mut self.ctx, exc);
     }
 
+    // Raise the exception requested by a coprocessor opcode, if any.
+    fn cop_exception(&mut self, ctx: &mut CpuContext) {
+        if let Some(exc) = ctx.cop_exception.take() {
+            self.exception(exc);
+        }
+    }
+
     fn trap_overflow(&mut self) {
         unimplemented!();
     }
@@ -320,7 +468,11 @@ impl<C: Config> Cpu<C> {
                 0x04 if h("sllv") => *op.mrd64() = (op.rt32() << (op.rs32() & 0x1F)).sx64(), // SLLV
                 0x06 if h("srll") => *op.mrd64() = (op.rt32() >> (op.rs32() & 0x1F)).sx64(), // SRLV
                 0x07 if h("srav") => *op.mrd64() = (op.irt32() >> (op.rs32() & 0x1F)).sx64(), // SRAV
-                0x08 if h("jr") => branch!(op, true, op.rs64(), link(false)),                 // JR
+                0x08 if h("jr") => {
+                    // JR
+                    op.cpu.callstack.jump(C::pc_mask(op.rs32()) as u64);
+                    branch!(op, true, op.rs64(), link(false))
+                }
                 0x09 if h("jalr") => branch!(op, true, op.rs64(), link(true)), // JALR
                 0x0D if h("break") => op.cpu.exception(Exception::Breakpoint), // BREAK
                 0x0F if h("sync") => {}                                        // SYNC
@@ -464,10 +616,26 @@ impl<C: Config> Cpu<C> {
             0x0E if h("xori") => *op.mrt64() = op.rs64() ^ op.imm64(),              // XORI
             0x0F if h("lui") => *op.mrt64() = (op.sximm32() << 16).sx64(),          // LUI
 
-            0x10 => if_cop!(op, cop0, { return cop0.op(&mut op.ctx, opcode, t) }), // COP0
-            0x11 => if_cop!(op, cop1, { return cop1.op(&mut op.ctx, opcode, t) }), // COP1
-            0x12 => if_cop!(op, cop2, { return cop2.op(&mut op.ctx, opcode, t) }), // COP2
-            0x13 => if_cop!(op, cop3, { return cop3.op(&mut op.ctx, opcode, t) }), // COP3
+            0x10 => if_cop!(op, cop0, {
+                let res = cop0.op(&mut op.ctx, opcode, t);
+                op.cpu.cop_exception(&mut op.ctx);
+                return res;
+            }), // COP0
+            0x11 => if_cop!(op, cop1, {
+                let res = cop1.op(&mut op.ctx, opcode, t);
+                op.cpu.cop_exception(&mut op.ctx);
+                return res;
+            }), // COP1
+            0x12 => if_cop!(op, cop2, {
+                let res = cop2.op(&mut op.ctx, opcode, t);
+                op.cpu.cop_exception(&mut op.ctx);
+                return res;
+            }), // COP2
+            0x13 => if_cop!(op, cop3, {
+                let res = cop3.op(&mut op.ctx, opcode, t);
+                op.cpu.cop_exception(&mut op.ctx);
+                return res;
+            }), // COP3
             0x14 if h("beql") => branch!(op, op.rs64() == op.rt64(), op.btgt(), likely(true)), // BEQL
             0x15 if h("bnel") => branch!(op, op.rs64() != op.rt64(), op.btgt(), likely(true)), // BNEL
             0x16 if h("blezl") => branch!(op, op.irs64() <= 0, op.btgt(), likely(true)), // BLEZL
@@ -489,26 +657,26 @@ impl<C: Config> Cpu<C> {
             0x29 if h("sh") => op.cpu.write::<u16>(op.ea(), op.rt32() as u16, t)?,    // SH
             0x2A if h("swl") => {
                 // SWL
-                op.cpu
-                    .write::<u32>(op.ea(), op.cpu.swl(op.ea(), op.rt32(), t)?, t)?
+                let val = op.cpu.swl(op.ea(), op.rt32(), t)?;
+                op.cpu.write::<u32>(op.ea(), val, t)?
             }
             0x2B if h("sw") => op.cpu.write::<u32>(op.ea(), op.rt32(), t)?, // SW
             0x2C if h("sdl") => {
                 // SDL
-                op.cpu
-                    .write::<u64>(op.ea(), op.cpu.swl(op.ea(), op.rt64(), t)?, t)?
+                let val = op.cpu.swl(op.ea(), op.rt64(), t)?;
+                op.cpu.write::<u64>(op.ea(), val, t)?
             }
             0x2D if h("sdr") => {
                 // SDR
-                op.cpu
-                    .write::<u64>(op.ea(), op.cpu.swr(op.ea(), op.rt64(), t)?, t)?
+                let val = op.cpu.swr(op.ea(), op.rt64(), t)?;
+                op.cpu.write::<u64>(op.ea(), val, t)?
             }
             0x2E if h("swr") => {
                 // SWR
-                op.cpu
-                    .write::<u32>(op.ea(), op.cpu.swr(op.ea(), op.rt32(), t)?, t)?
+                let val = op.cpu.swr(op.ea(), op.rt32(), t)?;
+                op.cpu.write::<u32>(op.ea(), val, t)?
             }
-            0x2F => {} // CACHE
+            0x2F => op.cpu.cache_op(op.rt() as u32, op.ea()), // CACHE
 
             0x31 if h("lwc1") => if_cop_loadstore!(op, cop1, lwc, t), // LWC1
             0x32 if h("lwc2") => if_cop_loadstore!(op, cop2, lwc, t), // LWC2
@@ -532,28 +700,28 @@ impl<C: Config> Cpu<C> {
         Ok(())
     }
 
-    fn lwl<S: MemInt>(&self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
+    fn lwl<S: MemInt>(&mut self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
         let mem = self.read::<S>(addr, t)?;
         let shift = (addr as usize & (S::SIZE - 1)) * 8;
         let mask = S::truncate_from((1u64 << shift) - 1u64);
         Ok((reg & mask) | ((mem << shift) & !mask))
     }
 
-    fn lwr<S: MemInt>(&self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
+    fn lwr<S: MemInt>(&mut self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
         let mem = self.read::<S>(addr, t)?;
         let shift = (!addr as usize & (S::SIZE - 1)) * 8;
         let mask = S::max_value() >> shift;
         Ok((reg & !mask) | ((mem >> shift) & mask))
     }
 
-    fn swl<S: MemInt>(&self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
+    fn swl<S: MemInt>(&mut self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
         let mem = self.read::<S>(addr, t)?;
         let shift = (addr as usize & (S::SIZE - 1)) * 8;
         let mask = S::max_value() >> shift;
         Ok((mem & !mask) | ((reg >> shift) & mask))
     }
 
-    fn swr<S: MemInt>(&self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
+    fn swr<S: MemInt>(&mut self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
         let mem = self.read::<S>(addr, t)?;
         let shift = (!addr as usize & (S::SIZE - 1)) * 8;
         let mask = S::truncate_from((1 << shift) - 1);
@@ -586,8 +754,10 @@ impl<C: Config> Cpu<C> {
                 let sximm32 = (opcode & 0xffff) as i16 as i32;
                 let rs = ((opcode >> 21) & 0x1f) as usize;
                 let ea = self.ctx.regs[rs] as u32 + sximm32 as u32;
-                let mem = self.bus.fetch_read_nolog::<u32>(C::addr_mask::<u32>(ea));
-                return mem.is_mem();
+                return match self.translate::<u32>(ea, false) {
+                    Ok(paddr) => self.bus.fetch_read_nolog::<u32>(paddr).is_mem(),
+                    Err(_) => false,
+                };
             }
             0x28 | 0x29 | 0x2A | 0x2B | 0x2E => {
                 // Store opcode. Check if the address is raw memory, in which
@@ -595,8 +765,10 @@ impl<C: Config> Cpu<C> {
                 let sximm32 = (opcode & 0xffff) as i16 as i32;
                 let rs = ((opcode >> 21) & 0x1f) as usize;
                 let ea = self.ctx.regs[rs] as u32 + sximm32 as u32;
-                let mem = self.bus.fetch_write_nolog::<u32>(C::addr_mask::<u32>(ea));
-                return mem.is_mem();
+                return match self.translate::<u32>(ea, true) {
+                    Ok(paddr) => self.bus.fetch_write_nolog::<u32>(paddr).is_mem(),
+                    Err(_) => false,
+                };
             }
             // All other opcodes by default are unstable
             _ => return false,
@@ -604,7 +776,10 @@ impl<C: Config> Cpu<C> {
     }
 
     fn detect_busy_wait(&mut self, pc: u64, loop_len: usize) -> bool {
-        let mem = self.fetch(pc);
+        let mem = match self.fetch(pc) {
+            Ok(mem) => mem,
+            Err(_) => return false,
+        };
         let iter = mem.iter().unwrap();
 
         // FIXME: this is buggy if the memory area is shorter than the loop
@@ -617,67 +792,268 @@ impl<C: Config> Cpu<C> {
         return true;
     }
 
-    fn fetch(&mut self, addr: u64) -> MemIoR<u32> {
-        self.bus.fetch_read::<u32>(C::pc_mask(addr as u32))
+    fn fetch(&self, addr: u64) -> std::result::Result<MemIoR<u32>, Exception> {
+        let paddr = self
+            .cop0
+            .translate(&self.ctx, addr as u32 as i32 as u64, false)?;
+        Ok(self.bus.fetch_read::<u32>(C::pc_mask(paddr)))
     }
 
-    fn read<U: MemInt>(&self, addr: u32, t: &Tracer) -> Result<U> {
-        let addr = C::addr_mask::<U>(addr);
-        let val = self.bus.read::<U>(addr);
-        t.trace_mem_read(&self.name, addr.into(), U::ACCESS_SIZE, val.into())?;
+    // Fetch the memory area at the current PC. If the PC is not mapped, the
+    // TLB exception is raised, and the exception vector is fetched instead.
+    fn fetch_pc(&mut self, ctx: &mut CpuContext) -> MemIoR<u32> {
+        loop {
+            match self.fetch(ctx.pc) {
+                Ok(mem) => return mem,
+                Err(exc) => {
+                    let pc = ctx.pc;
+                    self.cop0.tlb_exception(ctx, exc, pc, false);
+                }
+            }
+        }
+    }
+
+    // Number of opcodes that can be run linearly from the specified PC. Mapped
+    // pages are not physically contiguous, so we must not run past the end
+    // of the current page (the smallest page size is 4 KiB).
+    fn linear_len(&self, pc: u64) -> usize {
+        if self.cop0.is_mapped(pc as u32 as i32 as u64) {
+            (0x1000 - (pc as usize & 0xFFF)) / 4
+        } else {
+            usize::max_value()
+        }
+    }
+
+    fn translate<U: MemInt>(&self, addr: u32, write: bool) -> std::result::Result<u32, Exception> {
+        let paddr = self.cop0.translate(&self.ctx, addr as i32 as u64, write)?;
+        Ok(C::addr_mask::<U>(paddr))
+    }
+
+    fn read<U: MemInt>(&mut self, addr: u32, t: &Tracer) -> Result<U> {
+        let vaddr = addr;
+        let addr = match self.translate::<U>(addr, false) {
+            Ok(paddr) => paddr,
+            Err(exc) => {
+                self.mem_fault.set(Some((exc, addr as i32 as u64, false)));
+                return Ok(U::default());
+            }
+        };
+        let val = if self.timing.enabled() {
+            self.timing.load::<U>(&mut self.bus, vaddr, addr)
+        } else {
+            self.bus.read::<U>(addr)
+        };
+        t.trace_mem_read(
+            &self.name,
+            addr.into(),
+            U::ACCESS_SIZE,
+            val.into(),
+            &self.logger,
+        )?;
         Ok(val)
     }
 
     fn write<U: MemInt>(&mut self, addr: u32, val: U, t: &Tracer) -> Result<()> {
-        let addr = C::addr_mask::<U>(addr);
-        self.bus.write::<U>(addr, val);
-        t.trace_mem_write(&self.name, addr.into(), U::ACCESS_SIZE, val.into())
+        let vaddr = addr;
+        let addr = match self.translate::<U>(addr, true) {
+            Ok(paddr) => paddr,
+            Err(exc) => {
+                self.mem_fault.set(Some((exc, addr as i32 as u64, true)));
+                return Ok(());
+            }
+        };
+        if self.timing.enabled() {
+            self.timing.store::<U>(&mut self.bus, vaddr, addr, val);
+        } else {
+            self.bus.write::<U>(addr, val);
+        }
+        t.trace_mem_write(
+            &self.name,
+            addr.into(),
+            U::ACCESS_SIZE,
+            val.into(),
+            &self.logger,
+        )
     }
 
-    pub fn run(&mut self, until: i64, t: &Tracer) -> Result<()> {
-        self.until = until;
+    // Coprocessor loads and stores access the bus directly: go through the
+    // data cache anyway, to account their timing and keep the cache coherent.
+    fn cop_mem_access(&mut self, vaddr: u32, size: usize, write: bool) {
+        if self.timing.enabled() {
+            if let Ok(paddr) = self.translate::<u32>(vaddr, write) {
+                self.timing
+                    .cop_access(&mut self.bus, vaddr, paddr, size, write);
+            }
+        }
+    }
 
+    // Fetch the opcode at the specified PC through the instruction cache.
+    // `op` is the opcode read from memory, used if the address cannot be
+    // translated (the TLB exception is raised by the fetch itself).
+    fn fetch_cached(&mut self, pc: u64, op: u32) -> u32 {
+        let vaddr = pc as u32;
+        match self.cop0.translate(&self.ctx, vaddr as i32 as u64, false) {
+            Ok(paddr) => self.timing.fetch(&self.bus, vaddr, C::pc_mask(paddr), op),
+            Err(_) => op,
+        }
+    }
+
+    fn cache_op(&mut self, op: u32, vaddr: u32) {
+        if self.timing.enabled() {
+            if let Ok(paddr) = self.translate::<u32>(vaddr, false) {
+                self.timing.cache_op(&mut self.bus, op, paddr);
+            }
+        }
+    }
+
+    // Raise the TLB exception caused by the memory access of the last opcode
+    // (if any). The opcode is aborted: its destination register is restored,
+    // and the exception is raised on the opcode itself.
+    fn mem_exception(&mut self, ctx: &mut CpuContext, opcode: u32, pc: u64, delay: bool, rt: u64) {
+        if let Some((exc, vaddr, write)) = self.mem_fault.take() {
+            if !write {
+                ctx.regs[((opcode >> 16) & 0x1f) as usize] = rt;
+            }
+            ctx.pc = pc;
+            ctx.delay_slot = delay;
+            self.cop0.tlb_exception(ctx, exc, vaddr, write);
+        }
+    }
+
+    pub fn run(&mut self, until: i64, t: &Tracer) -> Result<()> {
         let ctx = unsafe { self.ctx.as_mut() };
-        let mut mem = self.fetch(ctx.pc);
+        let mut mem = self.fetch_pc(ctx);
         let mut last_mem_pc = ctx.pc;
 
-        while ctx.clock < self.until {
+        while ctx.clock < until {
             if ctx.lines.halt {
-                ctx.clock = self.until;
+                ctx.clock = until;
                 return Ok(());
             }
 
             // See if there are pending interrupts that COP0 can generate.
             self.cop0.poll_interrupts(ctx);
 
+            // Never run past the next COP0 event, so that it is raised at
+            // the exact cycle (also when skipping idle loops).
+            self.until = until.min(self.cop0.next_event());
+
             // Fetch the next memory area (unless we're looping, in which case
             // we already have the memory pointer).
             if ctx.pc != last_mem_pc {
-                mem = self.fetch(ctx.pc);
+                mem = self.fetch_pc(ctx);
                 last_mem_pc = ctx.pc;
             }
 
             let mut iter = mem
                 .iter()
-                .unwrap_or_else(|| panic!("jumped to non-linear memory: {}", ctx.pc.hex()));
+                .unwrap_or_else(|| panic!("jumped to non-linear memory: {}", ctx.pc.hex()))
+                .take(self.linear_len(ctx.pc));
+
+            // When profiling, the sequence of instructions run by the tight
+            // loop is counted as a basic block.
+            let profiling = t.profiling();
+            let covering = t.covering();
+            let cached = self.timing.enabled();
+            let block_pc = C::pc_mask(ctx.next_pc as u32) as u64;
+            let block_clock = ctx.clock;
+            let mut block_len = 0;
+            if profiling {
+                t.profile_enter_block(&self.name, &self.callstack);
+            }
 
             // Tight loop: go through continuous memory, no branches, no IRQs
-            while let Some(op) = iter.next() {
+            while let Some(mut op) = iter.next() {
+                if cached {
+                    op = self.fetch_cached(ctx.next_pc, op);
+                }
+                let (op_pc, op_delay, op_rt) =
+                    (ctx.pc, ctx.delay_slot, ctx.regs[(op >> 16) as usize & 0x1f]);
                 ctx.tight_exit = ctx.delay_slot;
                 ctx.delay_slot = false;
                 ctx.pc = ctx.next_pc;
                 ctx.next_pc += 4;
-                self.op(ctx, op, t)?;
-                t.trace_insn(&self.name, C::pc_mask(ctx.pc as u32) as u64)?;
+                if covering {
+                    t.cover_insn(&self.name, C::pc_mask(ctx.pc as u32) as u64);
+                }
+                if t.insn_recording() {
+                    let (pc, before) = (ctx.pc, (ctx.regs, ctx.hi, ctx.lo));
+                    self.op(ctx, op, t)?;
+                    self.record_insn(ctx, pc, op, &before, t);
+                } else {
+                    self.op(ctx, op, t)?;
+                }
+                self.mem_exception(ctx, op, op_pc, op_delay, op_rt);
+                ctx.clock += self.timing.take_stall();
+                if profiling {
+                    block_len += 1;
+                    let pc = ctx.pc;
+                    t.profile_insn(&self.name, opcode_class(op), || self.disasm_insn(pc, op));
+                }
+                t.trace_insn(
+                    &self.name,
+                    C::pc_mask(ctx.pc as u32) as u64,
+                    |name| ctx.reg_by_name(name),
+                    &self.logger,
+                )?;
                 if ctx.clock >= self.until || ctx.tight_exit {
                     break;
                 }
             }
+            if profiling && block_len != 0 {
+                let cycles = (ctx.clock - block_clock) as u64;
+                t.profile_block(&self.name, block_pc, block_len, cycles);
+            }
         }
         Ok(())
     }
 }
 
+// Return the opcode class of an instruction, that is the opcode with all the
+// operand fields (registers, immediates) masked out. Instructions with the
+// same class share the same mnemonic.
+fn opcode_class(opcode: u32) -> u32 {
+    match opcode >> 26 {
+        0x00 => opcode & 0xFC00_003F, // SPECIAL
+        0x01 => opcode & 0xFC1F_0000, // REGIMM
+        0x10..=0x13 if opcode & (1 << 25) != 0 => opcode & 0xFFE0_003F, // COPz ops
+        0x10..=0x13 => opcode & 0xFFFF_0000, // COPz moves/branches
+        0x32 | 0x3A => opcode & 0xFC00_F800, // LWC2/SWC2
+        _ => opcode & 0xFC00_0000,
+    }
+}
+
+impl<C: Config> Cpu<C> {
+    // Record an executed instruction into the debugger instruction trace,
+    // together with the registers it modified.
+    #[cold]
+    fn record_insn(
+        &self,
+        ctx: &CpuContext,
+        pc: u64,
+        opcode: u32,
+        before: &([u64; 32], u64, u64),
+        t: &Tracer,
+    ) {
+        let mut changed = Vec::new();
+        for idx in 1..32 {
+            if ctx.regs[idx] != before.0[idx] {
+                changed.push((REG_NAMES[idx], ctx.regs[idx]));
+            }
+        }
+        if ctx.hi != before.1 {
+            changed.push(("hi", ctx.hi));
+        }
+        if ctx.lo != before.2 {
+            changed.push(("lo", ctx.lo));
+        }
+        let pc = C::pc_mask(pc as u32) as u64;
+        t.record_insn(&self.name, pc, opcode, &changed, || {
+            self.disasm_insn(pc, opcode).disasm()
+        });
+    }
+}
+
 impl<C: Config> sync::Subsystem for Cpu<C> {
     fn name(&self) -> &str {
         &self.name
@@ -700,14 +1076,45 @@ impl<C: Config> sync::Subsystem for Cpu<C> {
     }
 }
 
+impl<C: Config> Snapshotable for Cpu<C> {
+    fn before_save(&mut self) {
+        self.cop0.before_save();
+        self.cop1.before_save();
+        self.cop2.before_save();
+        self.cop3.before_save();
+    }
+
+    fn after_load(&mut self) {
+        // The call stack is tracked while running and is not part of the
+        // state, so restart tracking from the restored position.
+        self.callstack.clear();
+        self.last_busy_check = 0;
+        self.timing.after_load();
+
+        self.cop0.after_load();
+        self.cop1.after_load();
+        self.cop2.after_load();
+        self.cop3.after_load();
+    }
+}
+
+#[cfg(feature = "frontend")]
 impl<C: Config> Cpu<C> {
     pub fn render_debug<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>) {
         dr.render_disasmview(self);
         dr.render_regview(self);
         dr.render_memoryview(self);
+        dr.render_callstackview(self);
+        dr.render_insn_trace(self);
+        dr.render_profiler(self);
 
+        if self.timing.enabled() {
+            self.timing.render_debug(&self.name, &mut self.bus, dr);
+        }
         if !self.cop0.is_null_obj() {
             self.cop0.render_debug(dr);
+            self.cop0.render_regs(&mut self.ctx, dr);
+            self.cop0.render_tlb(&mut self.ctx, dr);
         }
         if !self.cop1.is_null_obj() {
             self.cop1.render_debug(dr);
@@ -748,6 +1155,8 @@ impl<C: Config> RegisterView for Cpu<C> {
                 {
                     visit(n, Reg64(v), None);
                 }
+                // zr is hardwired to zero
+                self.ctx.regs[0] = 0;
             }
             2 => {
                 visit("hi", Reg64(&mut self.ctx.hi), None);
@@ -757,13 +1166,55 @@ impl<C: Config> RegisterView for Cpu<C> {
                 if self.ctx.delay_slot {
                     pcdesc += &format!("\nJumpTo:{:x}", C::pc_mask(self.ctx.next_pc as u32));
                 }
-                visit("pc", Reg64(&mut self.ctx.pc), Some(&pcdesc));
+                // Changing PC is a jump (not delayed) to the new address.
+                let mut pc = self.ctx.pc;
+                visit("pc", Reg64(&mut pc), Some(&pcdesc));
+                if pc != self.ctx.pc {
+                    self.ctx.set_pc(pc);
+                    self.ctx.delay_slot = false;
+                }
             }
             _ => unreachable!(),
         };
     }
 }
 
+impl<C: Config> InsnTraceView for Cpu<C> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn disasm_insn(&self, pc: u64, opcode: u32) -> DecodedInsn {
+        decode(self, opcode, pc)
+    }
+
+    fn symbols(&self) -> Option<&SymbolTable> {
+        CallstackView::symbols(self)
+    }
+}
+
+impl<C: Config> CallstackView for Cpu<C> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn pc(&self) -> u64 {
+        C::pc_mask(self.ctx.pc as u32).into()
+    }
+
+    fn callstack(&self) -> &CallStack {
+        &self.callstack
+    }
+
+    fn symbols(&self) -> Option<&SymbolTable> {
+        if self.symbols.is_empty() {
+            None
+        } else {
+            Some(&self.symbols)
+        }
+    }
+}
+
 impl<C: Config> DisasmView for Cpu<C> {
     fn name(&self) -> &str {
         &self.name
@@ -777,6 +1228,55 @@ impl<C: Config> DisasmView for Cpu<C> {
         C::pc_mask(v as u32) as u64
     }
 
+    fn symbols(&self) -> Option<&SymbolTable> {
+        CallstackView::symbols(self)
+    }
+
+    fn call_return_pc(&self, pc: u64, insn: &DecodedInsn) -> Option<u64> {
+        match insn.op {
+            "jal" | "jalr" | "bltzal" | "bgezal" | "bltzall" | "bgezall" => {
+                Some(C::pc_mask(pc as u32 + 8) as u64)
+            }
+            _ => None,
+        }
+    }
+
+    fn return_pc(&self) -> Option<u64> {
+        // Prefer the call stack, as RA is usually overwritten by nested
+        // calls (and restored only in the function epilogue).
+        match self.callstack.frames().back() {
+            Some(frame) => Some(frame.ret_pc),
+            None => Some(C::pc_mask(self.ctx.regs[31] as u32) as u64),
+        }
+    }
+
+    fn stack_pointer(&self) -> Option<(&'static str, u64)> {
+        Some(("sp", self.ctx.regs[29]))
+    }
+
+    fn assemble(&self, pc: u64, text: &str) -> Result<Vec<u8>, String> {
+        let opcode = crate::asm::assemble(pc, text)?;
+        if decode(self, opcode, pc).op == "unsupp?" {
+            return Err(format!("instruction not supported by {}", self.name));
+        }
+        let mut buf = vec![0u8; 4];
+        byteorder::BigEndian::write_u32(&mut buf, opcode);
+        Ok(buf)
+    }
+
+    fn patch(&mut self, pc: u64, bytes: &[u8]) -> Result<Vec<u8>, String> {
+        self.timing.invalidate_code(C::pc_mask(pc as u32));
+        let mut memw = self.bus.fetch_write_nolog::<u8>(C::pc_mask(pc as u32));
+        match memw.mem() {
+            Some(mem) if mem.len() >= bytes.len() => {
+                let old = mem[..bytes.len()].to_vec();
+                mem[..bytes.len()].copy_from_slice(bytes);
+                Ok(old)
+            }
+            _ => Err(format!("cannot patch {:08x}: not writable memory", pc)),
+        }
+    }
+
     fn disasm_block<Func: FnMut(u64, &[u8], &DecodedInsn)>(
         &self,
         pc_range: (u64, u64),
@@ -828,4 +1328,194 @@ impl<C: Config> BusMemoryView for Cpu<C> {
     fn bus_mut(&mut self) -> &mut Bus {
         &mut self.bus
     }
+
+    fn phys_addr(&self, addr: u64) -> u64 {
+        C::addr_mask::<u8>(addr as u32) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ArchIII, CopNull, Cp0, Fpu};
+    use emu::bus::be::{BusFill, Mem, MemFlags};
+    use emu::dbg::{Debugger, TraceEvent};
+
+    struct TestConfig;
+
+    impl Config for TestConfig {
+        type Arch = ArchIII;
+        type Cop0 = Cp0;
+        type Cop1 = Fpu;
+        type Cop2 = CopNull;
+        type Cop3 = CopNull;
+    }
+
+    // A main program calling the same function with JAL and JALR, and
+    // incrementing v0 after each call; the function increments v1 twice.
+    static PROGRAM: [(u32, u32); 14] = [
+        (0x1000, 0x0C00_0440), // jal    0x80001100
+        (0x1004, 0x0000_0000), // nop
+        (0x1008, 0x2442_0001), // addiu  v0, v0, 1
+        (0x100C, 0x3C08_8000), // lui    t0, 0x8000
+        (0x1010, 0x3508_1100), // ori    t0, t0, 0x1100
+        (0x1014, 0x0100_F809), // jalr   t0
+        (0x1018, 0x0000_0000), // nop
+        (0x101C, 0x2442_0001), // addiu  v0, v0, 1
+        (0x1020, 0x1000_FFFF), // b      0x80001020
+        (0x1024, 0x0000_0000), // nop
+        (0x1100, 0x2463_0001), // addiu  v1, v1, 1
+        (0x1104, 0x2463_0001), // addiu  v1, v1, 1
+        (0x1108, 0x03E0_0008), // jr     ra
+        (0x110C, 0x0000_0000), // nop
+    ];
+
+    fn cpu() -> Cpu<TestConfig> {
+        let logger = slog::Logger::root(slog::Discard, o!());
+
+        // The memory is leaked, as the bus refers to it for its whole life.
+        let ram: &'static Mem = Box::leak(Box::new(Mem::new(
+            "ram",
+            0x10000,
+            MemFlags::new(true, true),
+            None,
+        )));
+        let mut bus = Bus::new(logger.new(o!()));
+        bus.map_mem(0x0000_0000, 0x0000_FFFF, ram, BusFill::None)
+            .unwrap();
+        for &(addr, op) in PROGRAM.iter() {
+            bus.write::<u32>(addr, op);
+        }
+
+        let mut cpu = Cpu::new(
+            "cpu",
+            logger.new(o!()),
+            bus,
+            (
+                Cp0::new("cpu", logger.new(o!())),
+                Fpu::new("cpu", logger.new(o!())),
+                CopNull {},
+                CopNull {},
+            ),
+        );
+        cpu.set_cop0_reg(12, 0x3400_0000);
+        let ctx = cpu.ctx_mut();
+        ctx.regs[29] = 0xFFFF_FFFF_8000_8000;
+        ctx.set_pc(0xFFFF_FFFF_8000_1000);
+        cpu
+    }
+
+    // Run until the debugger stops the CPU, and return the event.
+    fn run(cpu: &mut Cpu<TestConfig>, dbg: &Debugger) -> TraceEvent {
+        let until = cpu.ctx().clock + 1000;
+        *cpu.run(until, &dbg.new_tracer())
+            .expect_err("debugger did not stop the CPU")
+    }
+
+    fn run_to(cpu: &mut Cpu<TestConfig>, dbg: &mut Debugger, pc: u64) {
+        dbg.set_breakpoint_oneshot("cpu", Some(pc));
+        match run(cpu, dbg) {
+            TraceEvent::BreakpointOneShot(_, bp) => assert_eq!(bp, pc),
+            ev => panic!("unexpected event: {:?}", ev),
+        }
+    }
+
+    // Set up the debugger like the "Over" button of the disassembly view.
+    fn step_over(cpu: &Cpu<TestConfig>, dbg: &mut Debugger) -> u64 {
+        let mut ret_pc = None;
+        let pc = DisasmView::pc(cpu);
+        cpu.disasm_block((pc, pc + 4), |pc, _, insn| {
+            ret_pc = cpu.call_return_pc(pc, insn)
+        });
+        let ret_pc = ret_pc.expect("not a call");
+        let (reg, sp) = cpu.stack_pointer().unwrap();
+        dbg.set_breakpoint_oneshot("cpu", Some(ret_pc));
+        dbg.set_breakpoint_oneshot_frame("cpu", reg, sp);
+        ret_pc
+    }
+
+    // Set up the debugger like the "Out" button of the disassembly view.
+    fn step_out(cpu: &Cpu<TestConfig>, dbg: &mut Debugger) -> u64 {
+        let ret_pc = cpu.return_pc().unwrap();
+        let (reg, sp) = cpu.stack_pointer().unwrap();
+        dbg.set_breakpoint_oneshot("cpu", Some(ret_pc));
+        dbg.set_breakpoint_oneshot_frame("cpu", reg, sp);
+        ret_pc
+    }
+
+    #[test]
+    fn step_over_calls() {
+        let mut cpu = cpu();
+        let mut dbg = Debugger::new(&vec!["cpu".into()]);
+
+        // JAL: the whole function runs, and the CPU stops right after the
+        // delay slot.
+        assert_eq!(step_over(&cpu, &mut dbg), 0x1008);
+        match run(&mut cpu, &dbg) {
+            TraceEvent::BreakpointOneShot(_, pc) => assert_eq!(pc, 0x1008),
+            ev => panic!("unexpected event: {:?}", ev),
+        }
+        assert_eq!(cpu.ctx().regs[3], 2);
+        assert_eq!(cpu.ctx().regs[2], 0);
+        assert!(cpu.callstack.frames().is_empty());
+
+        // JALR
+        run_to(&mut cpu, &mut dbg, 0x1014);
+        assert_eq!(step_over(&cpu, &mut dbg), 0x101C);
+        match run(&mut cpu, &dbg) {
+            TraceEvent::BreakpointOneShot(_, pc) => assert_eq!(pc, 0x101C),
+            ev => panic!("unexpected event: {:?}", ev),
+        }
+        assert_eq!(cpu.ctx().regs[3], 4);
+        assert_eq!(cpu.ctx().regs[2], 1);
+        assert!(cpu.callstack.frames().is_empty());
+    }
+
+    #[test]
+    fn step_out_function() {
+        let mut cpu = cpu();
+        let mut dbg = Debugger::new(&vec!["cpu".into()]);
+
+        run_to(&mut cpu, &mut dbg, 0x1104);
+        assert_eq!(cpu.ctx().regs[3], 1);
+        let frame = *cpu.callstack.frames().back().unwrap();
+        assert_eq!(
+            (frame.call_pc, frame.func, frame.ret_pc),
+            (0x1000, 0x1100, 0x1008)
+        );
+
+        // The return address comes from the call stack; JR RA then unwinds
+        // the frame.
+        assert_eq!(step_out(&cpu, &mut dbg), 0x1008);
+        match run(&mut cpu, &dbg) {
+            TraceEvent::BreakpointOneShot(_, pc) => assert_eq!(pc, 0x1008),
+            ev => panic!("unexpected event: {:?}", ev),
+        }
+        assert_eq!(cpu.ctx().regs[3], 2);
+        assert_eq!(cpu.ctx().regs[2], 0);
+        assert!(cpu.callstack.frames().is_empty());
+    }
+
+    #[test]
+    fn step_over_breakpoint() {
+        let mut cpu = cpu();
+        let mut dbg = Debugger::new(&vec!["cpu".into()]);
+        dbg.add_breakpoint("cpu", 0x1104, "");
+
+        // A breakpoint within the called function stops the step over.
+        step_over(&cpu, &mut dbg);
+        match run(&mut cpu, &dbg) {
+            TraceEvent::Breakpoint(_, _, pc) => assert_eq!(pc, 0x1104),
+            ev => panic!("unexpected event: {:?}", ev),
+        }
+        assert_eq!(cpu.ctx().regs[3], 1);
+
+        // Stepping out from there completes the call.
+        step_out(&cpu, &mut dbg);
+        match run(&mut cpu, &dbg) {
+            TraceEvent::BreakpointOneShot(_, pc) => assert_eq!(pc, 0x1008),
+            ev => panic!("unexpected event: {:?}", ev),
+        }
+        assert_eq!(cpu.ctx().regs[3], 2);
+    }
 }
@@ -1,6 +1,6 @@
 extern crate emu;
 use super::{Arch, Config, Cop, Cpu};
-use emu::dbg::{Operand, DecodedInsn};
+use emu::dbg::{DecodedInsn, InsnFlags, Operand};
 
 // Decoding format for arguments of load/store ops
 pub const MEMOP_FMT: &'static str = "{},{}({})";
@@ -196,10 +196,44 @@ fn humanize(insn: DecodedInsn) -> DecodedInsn {
     }
 }
 
+// Attach the nominal cycles (from the VR4300 manual) and the flags of an
+// instruction. Coprocessor instructions are annotated by their decoders,
+// except for loads/stores.
+fn annotate(insn: DecodedInsn, opcode: u32) -> DecodedInsn {
+    let branch = InsnFlags::BRANCH | InsnFlags::DELAY_SLOT;
+    let likely = branch | InsnFlags::LIKELY;
+    match opcode >> 26 {
+        0x00 => match opcode & 0x3f {
+            0x08 | 0x09 => insn.with_flags(branch), // JR, JALR
+            0x18 | 0x19 => insn.with_cycles(5),     // MULT, MULTU
+            0x1A | 0x1B => insn.with_cycles(37),    // DIV, DIVU
+            0x1C | 0x1D => insn.with_cycles(8),     // DMULT, DMULTU
+            0x1E | 0x1F => insn.with_cycles(69),    // DDIV, DDIVU
+            _ => insn,
+        },
+        0x01 => match (opcode >> 16) & 0x1f {
+            0x00 | 0x01 | 0x10 | 0x11 => insn.with_flags(branch),
+            0x02 | 0x03 | 0x12 | 0x13 => insn.with_flags(likely),
+            _ => insn,
+        },
+        0x02..=0x07 => insn.with_flags(branch),
+        0x14..=0x17 => insn.with_flags(likely),
+        0x1A | 0x1B | 0x20..=0x27 | 0x31 | 0x32 | 0x35..=0x37 => insn.with_flags(InsnFlags::LOAD),
+        0x28..=0x2E | 0x39 | 0x3A | 0x3D..=0x3F => insn.with_flags(InsnFlags::STORE),
+        _ => insn,
+    }
+}
+
 pub(crate) fn decode<C: Config>(cpu: &Cpu<C>, opcode: u32, pc: u64) -> DecodedInsn {
     let insn = decode1(cpu, opcode, pc);
     if !C::Arch::has_op(insn.op) {
         return DecodedInsn::new0("unsupp?");
     }
-    humanize(insn)
+    // Pseudo-instructions keep the metadata of the original instruction
+    let insn = annotate(insn, opcode);
+    let (cycles, flags) = (insn.cycles, insn.flags);
+    let mut insn = humanize(insn);
+    insn.cycles = cycles;
+    insn.flags = flags;
+    insn
 }
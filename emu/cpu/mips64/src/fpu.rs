@@ -1,14 +1,17 @@
 use super::decode::{MEMOP_FMT, REG_NAMES};
-use super::{Cop, CpuContext};
+use super::{Cop, CpuContext, Exception};
 
-use emu::dbg::{DebuggerRenderer, DecodedInsn, Operand, RegisterSize, RegisterView, Result, Tracer};
+#[cfg(feature = "frontend")]
+use emu::dbg::DebuggerRenderer;
+use emu::dbg::{DecodedInsn, InsnFlags, Operand, RegisterSize, RegisterView, Result, Tracer};
 use emu::int::Numerics;
-use emu::state::Field;
+use emu::state::{Field, Snapshotable};
 
 use num::Float;
 use serde_derive::{Deserialize, Serialize};
 use slog;
 use slog::*;
+use std::cmp::Ordering;
 use std::marker::PhantomData;
 
 const FPU_REG_NAMES: [&'static str; 32] = [
@@ -17,12 +20,54 @@ const FPU_REG_NAMES: [&'static str; 32] = [
     "f28", "f29", "f30", "f31",
 ];
 
+// Names of C.cond.fmt opcodes, indexed by cond (single, double)
+const FPU_COND_NAMES: [(&'static str, &'static str); 16] = [
+    ("c.f.s", "c.f.d"),
+    ("c.un.s", "c.un.d"),
+    ("c.eq.s", "c.eq.d"),
+    ("c.ueq.s", "c.ueq.d"),
+    ("c.olt.s", "c.olt.d"),
+    ("c.ult.s", "c.ult.d"),
+    ("c.ole.s", "c.ole.d"),
+    ("c.ule.s", "c.ule.d"),
+    ("c.sf.s", "c.sf.d"),
+    ("c.ngle.s", "c.ngle.d"),
+    ("c.seq.s", "c.seq.d"),
+    ("c.ngl.s", "c.ngl.d"),
+    ("c.lt.s", "c.lt.d"),
+    ("c.nge.s", "c.nge.d"),
+    ("c.le.s", "c.le.d"),
+    ("c.ngt.s", "c.ngt.d"),
+];
+
 const FPU_CREG_NAMES: [&'static str; 32] = [
-    "?0?", "?1?", "?2?", "?3?", "?4?", "?5?", "?6?", "?7?", "?8?", "?9?", "?10?", "?11?", "?12?",
+    "FCR0", "?1?", "?2?", "?3?", "?4?", "?5?", "?6?", "?7?", "?8?", "?9?", "?10?", "?11?", "?12?",
     "?13?", "?14?", "?15?", "?16?", "?17?", "?18?", "?19?", "?20?", "?21?", "?22?", "?23?", "?24?",
     "?25?", "?26?", "?27?", "?28?", "?29?", "?30?", "FCSR",
 ];
 
+// FPU exceptions, with the same bit layout used by the Flag, Enable and Cause
+// fields of FCSR. Unimplemented Operation only exists in Cause, and is
+// always enabled.
+const FPE_INEXACT: u64 = 1 << 0;
+const FPE_UNDERFLOW: u64 = 1 << 1;
+const FPE_OVERFLOW: u64 = 1 << 2;
+const FPE_DIVBYZERO: u64 = 1 << 3;
+const FPE_INVALID: u64 = 1 << 4;
+const FPE_UNIMPLEMENTED: u64 = 1 << 5;
+
+// Operand formats (fmt field of COP1 opcodes)
+const FMT_S: u32 = 0x10; // Single precision
+const FMT_D: u32 = 0x11; // Double precision
+const FMT_W: u32 = 0x14; // 32-bit fixed point
+const FMT_L: u32 = 0x15; // 64-bit fixed point
+
+const FCSR_FLAG_SHIFT: u64 = 2;
+const FCSR_ENABLE_SHIFT: u64 = 7;
+const FCSR_CAUSE_SHIFT: u64 = 12;
+const FCSR_CAUSE_MASK: u64 = 0x3F << FCSR_CAUSE_SHIFT;
+const FCSR_FS: u64 = 1 << 24; // Flush denormalized results to zero
+
 #[derive(Default, Copy, Clone, Serialize, Deserialize)]
 struct FpuContext {
     regs: [u64; 32],
@@ -35,9 +80,14 @@ struct FpuContext {
 }
 
 impl FpuContext {
+    // In 32-bit mode, 64-bit values are held in even/odd register pairs.
+    // Odd registers can only hold 32-bit values (using them for 64-bit
+    // values is undefined), so their upper half is never accessed.
     fn get_fgr(&self, idx: usize) -> u64 {
         if self.fpu64 {
             self.regs[idx]
+        } else if idx & 1 != 0 {
+            self.regs[idx] & 0xFFFF_FFFF
         } else {
             (self.regs[idx + 0] & 0xFFFF_FFFF) | (self.regs[idx + 1] << 32)
         }
@@ -45,6 +95,8 @@ impl FpuContext {
     fn set_fgr(&mut self, idx: usize, val: u64) {
         if self.fpu64 {
             self.regs[idx] = val;
+        } else if idx & 1 != 0 {
+            self.regs[idx] = val & 0xFFFF_FFFF;
         } else {
             self.regs[idx + 0] = val & 0xFFFF_FFFF;
             self.regs[idx + 1] = val >> 32;
@@ -56,21 +108,49 @@ impl FpuContext {
     fn set_fpr<F: FloatRawConvert>(&mut self, idx: usize, val: F) {
         self.set_fgr(idx, val.to_u64bits());
     }
+
+    // Write FCSR, keeping the condition codes in FCCR in sync.
+    fn set_fcsr(&mut self, val: u64) {
+        self.fcsr = val;
+        self.fccr = (val >> 23) & 1 | (val >> 24) & 0xFE;
+    }
+
+    fn cause(&self) -> u64 {
+        (self.fcsr & FCSR_CAUSE_MASK) >> FCSR_CAUSE_SHIFT
+    }
+    fn enables(&self) -> u64 {
+        ((self.fcsr >> FCSR_ENABLE_SHIFT) & 0x1F) | FPE_UNIMPLEMENTED
+    }
+
+    // Update FCSR with the exceptions raised by an operation: Cause is
+    // overwritten, and Flag accumulates them. If any of the exceptions is
+    // enabled, a Floating-Point exception is raised instead (leaving Flag
+    // untouched), and false is returned: the destination register must
+    // then be left unmodified.
+    fn update_fcsr(&mut self, cpu: &mut CpuContext, exc: u64) -> bool {
+        self.fcsr = (self.fcsr & !FCSR_CAUSE_MASK) | (exc << FCSR_CAUSE_SHIFT);
+        if exc & self.enables() != 0 {
+            cpu.raise_exception(Exception::FloatingPoint);
+            return false;
+        }
+        self.fcsr |= (exc & 0x1F) << FCSR_FLAG_SHIFT;
+        true
+    }
 }
 
 pub struct Fpu {
     ctx: Field<FpuContext>,
     logger: slog::Logger,
     cpu_name: &'static str,
+    ieee: bool,
 }
 
 trait FloatRawConvert {
     fn from_u64bits(v: u64) -> Self;
     fn to_u64bits(self) -> u64;
     fn bankers_round(self) -> Self;
-    fn to_f32(self) -> f32;
     fn to_f64(self) -> f64;
-    fn to_u64(self) -> u64;
+    fn default_nan() -> Self;
 }
 
 impl FloatRawConvert for f32 {
@@ -88,14 +168,11 @@ impl FloatRawConvert for f32 {
             y
         }
     }
-    fn to_f32(self) -> f32 {
-        self as f32
-    }
     fn to_f64(self) -> f64 {
         self as f64
     }
-    fn to_u64(self) -> u64 {
-        self as u64
+    fn default_nan() -> Self {
+        f32::from_bits(0x7FBF_FFFF)
     }
 }
 
@@ -114,15 +191,179 @@ impl FloatRawConvert for f64 {
             y
         }
     }
-    fn to_f32(self) -> f32 {
-        self as f32
-    }
     fn to_f64(self) -> f64 {
         self as f64
     }
-    fn to_u64(self) -> u64 {
-        self as u64
+    fn default_nan() -> Self {
+        f64::from_bits(0x7FF7_FFFF_FFFF_FFFF)
+    }
+}
+
+// Return the next representable value towards +infinity.
+fn next_up<F: Float + FloatRawConvert>(v: F) -> F {
+    if v.is_nan() || v == F::infinity() {
+        return v;
+    }
+    if v == F::zero() {
+        return F::from_u64bits(1); // smallest positive denormal
+    }
+    let bits = v.to_u64bits();
+    F::from_u64bits(if v > F::zero() { bits + 1 } else { bits - 1 })
+}
+
+// Return the next representable value towards -infinity.
+fn next_down<F: Float + FloatRawConvert>(v: F) -> F {
+    -next_up(-v)
+}
+
+/// Rounding mode selected by the RM field of FCSR.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum RoundingMode {
+    Nearest, // RN: round to nearest (ties to even)
+    Zero,    // RZ: round toward zero
+    Up,      // RP: round toward +infinity
+    Down,    // RM: round toward -infinity
+}
+
+impl RoundingMode {
+    fn from_fcsr(fcsr: u64) -> Self {
+        match fcsr & 3 {
+            0 => RoundingMode::Nearest,
+            1 => RoundingMode::Zero,
+            2 => RoundingMode::Up,
+            _ => RoundingMode::Down,
+        }
+    }
+
+    // Round a value to an integral value (used by CVT.W/CVT.L).
+    fn round_int<F: Float + FloatRawConvert>(self, v: F) -> F {
+        match self {
+            RoundingMode::Nearest => v.bankers_round(),
+            RoundingMode::Zero => v.trunc(),
+            RoundingMode::Up => v.ceil(),
+            RoundingMode::Down => v.floor(),
+        }
+    }
+
+    // Flush a denormalized value to zero, or to the smallest normalized value
+    // when rounding away from zero.
+    fn flush<F: Float>(self, v: F) -> F {
+        match self {
+            RoundingMode::Up if v > F::zero() => F::min_positive_value(),
+            RoundingMode::Down if v < F::zero() => -F::min_positive_value(),
+            _ => F::zero() * v.signum(),
+        }
+    }
+
+    // The host FPU always rounds to nearest. Adjust a result computed by the
+    // host to this rounding mode, given how the exact (infinitely precise)
+    // result compares to it. `overflow` must be true if `res` is an infinity
+    // produced by an overflow of finite operands. Returns the adjusted result
+    // together with the inexact/underflow/overflow exceptions it raises.
+    fn adjust<F: Float + FloatRawConvert>(
+        self,
+        res: F,
+        exact: Ordering,
+        overflow: bool,
+    ) -> (F, u64) {
+        use self::RoundingMode::*;
+        if overflow {
+            let res = match self {
+                Nearest => res,
+                Zero if res > F::zero() => F::max_value(),
+                Zero => -F::max_value(),
+                Up if res < F::zero() => -F::max_value(),
+                Down if res > F::zero() => F::max_value(),
+                Up | Down => res,
+            };
+            return (res, FPE_OVERFLOW | FPE_INEXACT);
+        }
+        let res = match (self, exact) {
+            (_, Ordering::Equal) | (Nearest, _) => res,
+            (Zero, Ordering::Less) if res > F::zero() => next_down(res),
+            (Zero, Ordering::Greater) if res < F::zero() => next_up(res),
+            (Up, Ordering::Greater) => next_up(res),
+            (Down, Ordering::Less) => next_down(res),
+            _ => res,
+        };
+        match exact {
+            Ordering::Equal => (res, 0),
+            _ if res.abs() < F::min_positive_value() => (res, FPE_UNDERFLOW | FPE_INEXACT),
+            _ => (res, FPE_INEXACT),
+        }
+    }
+}
+
+// Compare a rounding error against zero. NaN errors are generated only when
+// the result is not finite, and in that case no adjustment is needed.
+fn err_sign<F: Float>(err: F) -> Ordering {
+    err.partial_cmp(&F::zero()).unwrap_or(Ordering::Equal)
+}
+
+// An invalid operation is one that generates a NaN out of non-NaN operands
+// (eg: 0/0, inf-inf, sqrt(-1)).
+fn is_invalid<F: Float>(res: F, a: F, b: F) -> bool {
+    res.is_nan() && !a.is_nan() && !b.is_nan()
+}
+
+// Arithmetic with selectable rounding mode. The rounding error of the
+// host operation is computed exactly through error-free transformations
+// (2Sum for additions, FMA for the others), and then used to adjust the
+// result if required. All functions return the result together with the
+// FPU exceptions raised by the operation.
+fn round_add<F: Float + FloatRawConvert>(a: F, b: F, rm: RoundingMode) -> (F, u64) {
+    let s = a + b;
+    if is_invalid(s, a, b) {
+        return (s, FPE_INVALID);
+    }
+    let bb = s - a;
+    let err = (a - (s - bb)) + (b - bb);
+    let overflow = s.is_infinite() && a.is_finite() && b.is_finite();
+    rm.adjust(s, err_sign(err), overflow)
+}
+
+fn round_mul<F: Float + FloatRawConvert>(a: F, b: F, rm: RoundingMode) -> (F, u64) {
+    let p = a * b;
+    if is_invalid(p, a, b) {
+        return (p, FPE_INVALID);
+    }
+    let err = a.mul_add(b, -p);
+    let overflow = p.is_infinite() && a.is_finite() && b.is_finite();
+    rm.adjust(p, err_sign(err), overflow)
+}
+
+fn round_div<F: Float + FloatRawConvert>(a: F, b: F, rm: RoundingMode) -> (F, u64) {
+    let q = a / b;
+    if is_invalid(q, a, b) {
+        return (q, FPE_INVALID);
     }
+    if b == F::zero() && a.is_finite() {
+        return (q, FPE_DIVBYZERO);
+    }
+    let rem = (-q).mul_add(b, a);
+    let overflow = q.is_infinite() && a.is_finite() && b.is_finite();
+    rm.adjust(q, err_sign(rem * b.signum()), overflow)
+}
+
+fn round_sqrt<F: Float + FloatRawConvert>(a: F, rm: RoundingMode) -> (F, u64) {
+    let s = a.sqrt();
+    if is_invalid(s, a, a) {
+        return (s, FPE_INVALID);
+    }
+    let rem = (-s).mul_add(s, a);
+    rm.adjust(s, err_sign(rem), false)
+}
+
+fn round_f64_to_f32(v: f64, rm: RoundingMode) -> (f32, u64) {
+    let r = v as f32;
+    let overflow = r.is_infinite() && v.is_finite();
+    rm.adjust(r, err_sign(v - r as f64), overflow)
+}
+
+fn round_int_to_float<F: Float + FloatRawConvert>(v: i64, rm: RoundingMode) -> (F, u64) {
+    let r: F = num::NumCast::from(v).unwrap();
+    let exact = (v as i128).cmp(&(r.to_f64() as i128));
+    rm.adjust(r, exact, false)
 }
 
 struct Fop<'a, F: Float + FloatRawConvert> {
@@ -137,6 +378,9 @@ impl<'a, F: Float + FloatRawConvert> Fop<'a, F> {
     fn func(&self) -> u32 {
         self.opcode & 0x3f
     }
+    fn fmt(&self) -> u32 {
+        (self.opcode >> 21) & 0x1f
+    }
     fn cc(&self) -> usize {
         ((self.opcode >> 8) & 7) as usize
     }
@@ -167,13 +411,78 @@ impl<'a, F: Float + FloatRawConvert> Fop<'a, F> {
     fn set_fgd(&mut self, v: u64) {
         self.ctx.set_fgr(self.rd(), v);
     }
+    fn rounding(&self) -> RoundingMode {
+        RoundingMode::from_fcsr(self.ctx.fcsr)
+    }
+    fn update_fcsr(&mut self, exc: u64) -> bool {
+        self.ctx.update_fcsr(self.cpu, exc)
+    }
+
+    // Check an input operand. The R4300 FPU cannot process denormals and
+    // NaNs, and raises an Unimplemented Operation exception instead. Returns
+    // false if the operation must be aborted.
+    fn check_input(&mut self, v: F) -> bool {
+        if self.fpu.ieee || v.is_normal() || v == F::zero() || v.is_infinite() {
+            return true;
+        }
+        self.update_fcsr(FPE_UNIMPLEMENTED);
+        false
+    }
+
+    // Adjust a result (with the exceptions it raised) to what the R4300 FPU
+    // produces: NaNs are replaced by the default NaN, and denormals are
+    // either flushed (if FS is set and neither underflow nor inexact traps
+    // are enabled), or raise an Unimplemented Operation exception.
+    fn check_output<T: Float + FloatRawConvert>(&self, v: T, exc: u64) -> (T, u64) {
+        if self.fpu.ieee {
+            return (v, exc);
+        }
+        if v.is_nan() {
+            return (T::default_nan(), exc);
+        }
+        if v != T::zero() && v.abs() < T::min_positive_value() {
+            let traps = self.ctx.enables() & (FPE_UNDERFLOW | FPE_INEXACT);
+            if self.ctx.fcsr & FCSR_FS == 0 || traps != 0 {
+                return (v, FPE_UNIMPLEMENTED);
+            }
+            return (self.rounding().flush(v), FPE_UNDERFLOW | FPE_INEXACT);
+        }
+        (v, exc)
+    }
+
+    // Write the result of an arithmetic operation, unless it raised an
+    // enabled exception.
+    fn set_fd_checked(&mut self, (v, exc): (F, u64)) {
+        let (v, exc) = self.check_output(v, exc);
+        if self.update_fcsr(exc) {
+            self.set_fd(v);
+        }
+    }
 }
 
+// Conversion to integer. Values that are not representable (NaN, infinities
+// or out of range) raise an invalid operation, and produce the largest
+// positive integer. The R4300 FPU instead raises an Unimplemented Operation
+// for them, and for any result that does not fit into 53 bits.
 macro_rules! approx {
-    ($op:ident, $t:expr, $round:ident, $size:ident, $fallback:expr) => {{
-        match $op.fs().$round().$size() {
-            Some(v) => $op.set_fgd(v as u64),
-            None => $op.set_fgd($fallback as u64),
+    ($op:ident, $val:expr, $size:ident, $fallback:expr) => {{
+        let fs = $op.fs();
+        if !$op.check_input(fs) {
+            return Ok(());
+        }
+        let v = $val;
+        let ieee = $op.fpu.ieee;
+        let (res, exc) = match v.$size() {
+            Some(i) if !ieee && ((i as i64) >= 1 << 53 || (i as i64) <= -(1 << 53)) => {
+                (0, FPE_UNIMPLEMENTED)
+            }
+            Some(i) if v == fs => (i as u64, 0),
+            Some(i) => (i as u64, FPE_INEXACT),
+            None if ieee => ($fallback as u64, FPE_INVALID),
+            None => (0, FPE_UNIMPLEMENTED),
+        };
+        if $op.update_fcsr(exc) {
+            $op.set_fgd(res);
         }
     }};
 }
@@ -185,8 +494,14 @@ macro_rules! cond {
         let nan = fs.is_nan() || ft.is_nan();
         let less = if !nan { fs < ft } else { false };
         let equal = if !nan { fs == ft } else { false };
-        if nan && $func & 8 != 0 {
-            panic!("signal FPU NaN in comparison");
+        // Signaling comparisons raise an invalid operation on NaN operands
+        let exc = if nan && $func & 8 != 0 {
+            FPE_INVALID
+        } else {
+            0
+        };
+        if !$op.update_fcsr(exc) {
+            return Ok(());
         }
 
         let cond =
@@ -208,19 +523,50 @@ macro_rules! fp_suffix {
     };
 }
 
+// Nominal latency of an FPU operation (in pipeline cycles), as documented
+// by the VR4300 manual.
+fn op_cycles(fmt: u32, func: u32) -> u32 {
+    let double = fmt == FMT_D;
+    match func {
+        0x00 | 0x01 => 3,               // ADD, SUB
+        0x02 if double => 8,            // MUL
+        0x02 => 5,                      // MUL
+        0x03 | 0x04 if double => 58,    // DIV, SQRT
+        0x03 | 0x04 => 29,              // DIV, SQRT
+        0x08..=0x0F => 5,               // ROUND, TRUNC, CEIL, FLOOR
+        0x20 if double => 2,            // CVT.S.D
+        0x21 if fmt == FMT_S => 1,      // CVT.D.S
+        0x20 | 0x21 | 0x24 | 0x25 => 5, // CVT
+        _ => 1,                         // ABS, MOV, NEG, C.cond
+    }
+}
+
 impl Fpu {
     pub fn new(cpu_name: &'static str, logger: slog::Logger) -> Fpu {
         Fpu {
-            ctx: Field::new(&("mips64".to_owned() + cpu_name + "::fpu"), FpuContext::default()),
+            ctx: Field::new(
+                &("mips64".to_owned() + cpu_name + "::fpu"),
+                FpuContext::default(),
+            ),
             logger,
             cpu_name,
+            ieee: false,
         }
     }
 
+    /// Select how denormals and NaNs are handled. By default, the FPU behaves
+    /// like the R4300 one, which cannot process them in hardware and raises
+    /// Unimplemented Operation exceptions instead (or flushes denormalized
+    /// results to zero, if the FS bit of FCSR is set). In IEEE mode, they
+    /// are processed as specified by IEEE 754, which is faster but less
+    /// accurate.
+    pub fn set_ieee_mode(&mut self, ieee: bool) {
+        self.ieee = ieee;
+    }
+
+    // Condition codes are 3-bit fields in opcodes, so cc is always in 0..8.
+    // Condition code 0 is mapped to bit 23 of FCSR, the others to bits 25-31.
     fn set_cc(&mut self, cc: usize, val: bool) {
-        if cc > 8 {
-            panic!("invalid cc code");
-        }
         self.ctx.fccr = (self.ctx.fccr & !(1 << cc)) | ((val as u64) << cc);
         let mut cc2 = cc + 23;
         if cc > 0 {
@@ -230,12 +576,45 @@ impl Fpu {
     }
 
     fn get_cc(&mut self, cc: usize) -> bool {
-        if cc > 8 {
-            panic!("invalid cc code");
-        }
         (self.ctx.fccr & (1 << cc)) != 0
     }
 
+    // Handle a reserved or unimplemented COP1 opcode. The R4300 FPU raises
+    // an Unimplemented Operation exception, which the game might handle; we
+    // also break into the debugger, as it is more likely to be caused by a
+    // bug in the emulator.
+    fn unimplemented_op(
+        &mut self,
+        cpu: &mut CpuContext,
+        opcode: u32,
+        t: &Tracer,
+        msg: &str,
+    ) -> Result<()> {
+        error!(self.logger, "{}", msg; "op" => opcode.hex());
+        self.ctx.update_fcsr(cpu, FPE_UNIMPLEMENTED);
+        t.break_here(msg)
+    }
+
+    // Conversion from a fixed-point value (W or L format) to floating point.
+    // The R4300 FPU raises an Unimplemented Operation for values that do not
+    // fit into 56 bits.
+    fn cvt_from_fixed<F: Float + FloatRawConvert>(
+        &mut self,
+        cpu: &mut CpuContext,
+        rd: usize,
+        v: i64,
+    ) {
+        if !self.ieee && (v >= 1 << 55 || v < -(1 << 55)) {
+            self.ctx.update_fcsr(cpu, FPE_UNIMPLEMENTED);
+            return;
+        }
+        let rm = RoundingMode::from_fcsr(self.ctx.fcsr);
+        let (v, exc) = round_int_to_float::<F>(v, rm);
+        if self.ctx.update_fcsr(cpu, exc) {
+            self.ctx.set_fpr(rd, v);
+        }
+    }
+
     fn fop<M: Float + FloatRawConvert>(
         &mut self,
         cpu: &mut CpuContext,
@@ -252,33 +631,50 @@ impl Fpu {
         match op.func() {
             0x00 => {
                 // ADD.fmt
-                let v = op.fs() + op.ft();
-                op.set_fd(v)
+                let (fs, ft) = (op.fs(), op.ft());
+                if op.check_input(fs) && op.check_input(ft) {
+                    let v = round_add(fs, ft, op.rounding());
+                    op.set_fd_checked(v)
+                }
             }
             0x01 => {
                 // SUB.fmt
-                let v = op.fs() - op.ft();
-                op.set_fd(v)
+                let (fs, ft) = (op.fs(), op.ft());
+                if op.check_input(fs) && op.check_input(ft) {
+                    let v = round_add(fs, -ft, op.rounding());
+                    op.set_fd_checked(v)
+                }
             }
             0x02 => {
-                // MUL.with_fmtt
-                let v = op.fs() * op.ft();
-                op.set_fd(v)
+                // MUL.fmt
+                let (fs, ft) = (op.fs(), op.ft());
+                if op.check_input(fs) && op.check_input(ft) {
+                    let v = round_mul(fs, ft, op.rounding());
+                    op.set_fd_checked(v)
+                }
             }
             0x03 => {
                 // DIV.fmt
-                let v = op.fs() / op.ft();
-                op.set_fd(v)
+                let (fs, ft) = (op.fs(), op.ft());
+                if op.check_input(fs) && op.check_input(ft) {
+                    let v = round_div(fs, ft, op.rounding());
+                    op.set_fd_checked(v)
+                }
             }
             0x04 => {
                 // SQRT.fmt
-                let v = op.fs().sqrt();
-                op.set_fd(v)
+                let fs = op.fs();
+                if op.check_input(fs) {
+                    let v = round_sqrt(fs, op.rounding());
+                    op.set_fd_checked(v)
+                }
             }
             0x05 => {
                 // ABS.fmt
-                let v = op.fs().abs();
-                op.set_fd(v)
+                let fs = op.fs();
+                if op.check_input(fs) {
+                    op.set_fd_checked((fs.abs(), 0))
+                }
             }
             0x06 => {
                 // MOV.fmt
@@ -287,31 +683,67 @@ impl Fpu {
             }
             0x07 => {
                 // NEG.fmt
-                let v = op.fs().neg();
-                op.set_fd(v)
+                let fs = op.fs();
+                if op.check_input(fs) {
+                    op.set_fd_checked((fs.neg(), 0))
+                }
+            }
+            0x08 => approx!(op, op.fs().bankers_round(), to_i64, i64::max_value()), // ROUND.L.fmt
+            0x09 => approx!(op, op.fs().trunc(), to_i64, i64::max_value()),         // TRUNC.L.fmt
+            0x0A => approx!(op, op.fs().ceil(), to_i64, i64::max_value()),          // CEIL.L.fmt
+            0x0B => approx!(op, op.fs().floor(), to_i64, i64::max_value()),         // FLOOR.L.fmt
+            0x0C => approx!(op, op.fs().bankers_round(), to_i32, i32::max_value()), // ROUND.W.fmt
+            0x0D => approx!(op, op.fs().trunc(), to_i32, i32::max_value()),         // TRUNC.W.fmt
+            0x0E => approx!(op, op.fs().ceil(), to_i32, i32::max_value()),          // CEIL.W.fmt
+            0x0F => approx!(op, op.fs().floor(), to_i32, i32::max_value()),         // FLOOR.W.fmt
+
+            0x20 if op.fmt() == FMT_S => {
+                // CVT.S.S
+                op.update_fcsr(FPE_UNIMPLEMENTED);
+            }
+            0x21 if op.fmt() == FMT_D => {
+                // CVT.D.D
+                op.update_fcsr(FPE_UNIMPLEMENTED);
+            }
+            0x20 => {
+                // CVT.S.fmt
+                let fs = op.fs();
+                if op.check_input(fs) {
+                    let (v, exc) = round_f64_to_f32(fs.to_f64(), op.rounding());
+                    let (v, exc) = op.check_output(v, exc);
+                    if op.update_fcsr(exc) {
+                        op.set_fgd(v.to_u64bits())
+                    }
+                }
+            }
+            0x21 => {
+                // CVT.D.fmt (always exact)
+                let fs = op.fs();
+                if op.check_input(fs) && op.update_fcsr(0) {
+                    op.set_fgd(fs.to_f64().to_u64bits())
+                }
             }
-            0x08 => approx!(op, t, bankers_round, to_i64, i64::max_value()), // ROUND.L.fmt
-            0x09 => approx!(op, t, trunc, to_i64, i64::max_value()),         // TRUNC.L.fmt
-            0x0A => approx!(op, t, ceil, to_i64, i64::max_value()),          // CEIL.L.fmt
-            0x0B => approx!(op, t, floor, to_i64, i64::max_value()),         // FLOOR.L.fmt
-            0x0C => approx!(op, t, bankers_round, to_i32, i32::max_value()), // ROUND.W.fmt
-            0x0D => approx!(op, t, trunc, to_i32, i32::max_value()),         // TRUNC.W.fmt
-            0x0E => approx!(op, t, ceil, to_i32, i32::max_value()),          // CEIL.W.fmt
-            0x0F => approx!(op, t, floor, to_i32, i32::max_value()),         // FLOOR.W.fmt
-
-            0x20 => op.set_fgd(op.fs().to_f32().to_u64bits()), // CVT.S.fmt
-            0x21 => op.set_fgd(op.fs().to_f64().to_u64bits()), // CVT.D.fmt
-            0x24 => op.set_fgd(op.fs().to_u64() as u32 as u64), // CVT.W.fmt
-            0x25 => op.set_fgd(op.fs().to_u64()),              // CVT.L.fmt
-
-            0x30 => cond!(op, 0x30), // C.T.fmt
+            0x24 => approx!(
+                op,
+                op.rounding().round_int(op.fs()),
+                to_i32,
+                i32::max_value()
+            ), // CVT.W.fmt
+            0x25 => approx!(
+                op,
+                op.rounding().round_int(op.fs()),
+                to_i64,
+                i64::max_value()
+            ), // CVT.L.fmt
+
+            0x30 => cond!(op, 0x30), // C.F.fmt
             0x31 => cond!(op, 0x31), // C.UN.fmt
             0x32 => cond!(op, 0x32), // C.EQ.fmt
             0x33 => cond!(op, 0x33), // C.UEQ.fmt
             0x34 => cond!(op, 0x34), // C.OLT.fmt
             0x35 => cond!(op, 0x35), // C.ULT.fmt
             0x36 => cond!(op, 0x36), // C.OLE.fmt
-            0x37 => cond!(op, 0x37), // C.ULT.fmt
+            0x37 => cond!(op, 0x37), // C.ULE.fmt
             0x38 => cond!(op, 0x38), // C.SF.fmt
             0x39 => cond!(op, 0x39), // C.NGLE.fmt
             0x3A => cond!(op, 0x3A), // C.SEQ.fmt
@@ -322,18 +754,16 @@ impl Fpu {
             0x3F => cond!(op, 0x3F), // C.NGT.fmt
 
             _ => {
-                error!(
-                    op.fpu.logger,
-                    "unimplemented COP1 opcode: func={:x?}",
-                    op.func()
-                );
-                return t.break_here("unimplemented COP1 opcode");
+                let msg = "unimplemented COP1 opcode";
+                return op.fpu.unimplemented_op(op.cpu, opcode, t, msg);
             }
         }
         Ok(())
     }
 }
 
+impl Snapshotable for Fpu {}
+
 impl Cop for Fpu {
     fn reg(&self, _cpu: &CpuContext, idx: usize) -> u128 {
         self.ctx.regs[idx] as u128
@@ -351,6 +781,7 @@ impl Cop for Fpu {
         let rd = ((opcode >> 6) & 0x1F) as usize;
         match fmt {
             0x0 => cpu.regs[rt] = (self.ctx.regs[rs] as u32).sx64(), // MFC1
+            0x1 => cpu.regs[rt] = self.ctx.get_fgr(rs),              // DMFC1
             0x2 => match rs {
                 // CFC1
                 31 => cpu.regs[rt] = self.ctx.fcsr,
@@ -360,10 +791,17 @@ impl Cop for Fpu {
                 }
             },
             0x4 => self.ctx.regs[rs] = (cpu.regs[rt] as u32) as u64, // MTC1
-            0x5 => self.ctx.set_fgr(rs, cpu.regs[rt]), // DMTC1
+            0x5 => self.ctx.set_fgr(rs, cpu.regs[rt]),               // DMTC1
             0x6 => match rs {
                 // CTC1
-                31 => self.ctx.fcsr = cpu.regs[rt],
+                31 => {
+                    // Setting a Cause bit whose exception is enabled
+                    // immediately raises it.
+                    self.ctx.set_fcsr(cpu.regs[rt]);
+                    if self.ctx.cause() & self.ctx.enables() != 0 {
+                        cpu.raise_exception(Exception::FloatingPoint);
+                    }
+                }
                 _ => {
                     error!(self.logger, "CTC1 to unknown register: {:x}", rs);
                     return t.break_here("CTC1 to unknown register");
@@ -371,51 +809,40 @@ impl Cop for Fpu {
             },
             0x8 => {
                 let tgt = cpu.pc + (opcode as u16).sx64() * 4;
-                let cc = ((opcode >> 18) & 3) as usize;
+                let cc = ((opcode >> 18) & 7) as usize;
                 let nd = opcode & (1 << 17) != 0;
                 let tf = opcode & (1 << 16) != 0;
                 let cond = self.get_cc(cc) == tf;
                 cpu.branch(cond, tgt, nd);
             }
-            0x10 => return self.fop::<f32>(cpu, opcode, t),
-            0x11 => return self.fop::<f64>(cpu, opcode, t),
-
-            0x14 => match func {
-                0x20 => {
-                    // CVT.S.W
-                    let fgs = self.ctx.get_fgr(rs);
-                    self.ctx.set_fpr(rd, fgs as i32 as f32);
-                }
-                0x21 => {
-                    // CVT.D.W
-                    let fgs = self.ctx.get_fgr(rs);
-                    self.ctx.set_fpr(rd, fgs as i32 as f64);
-                }
-                _ => {
-                    error!(self.logger, "unimplemented COP1 W: func={:x?}", func);
-                    return t.break_here("unimplemented COP1 W opcode");
-                }
-            },
-            0x15 => match func {
-                0x20 => {
-                    // CVT.S.L
-                    let fgs = self.ctx.get_fgr(rs);
-                    self.ctx.set_fpr(rd, fgs as i64 as f32);
-                }
-                0x21 => {
-                    // CVT.D.L
-                    let fgs = self.ctx.get_fgr(rs);
-                    self.ctx.set_fpr(rd, fgs as i64 as f64);
-                }
-                _ => {
-                    error!(self.logger, "unimplemented COP1 L: func={:x?}", func);
-                    return t.break_here("unimplemented COP1 L opcode");
+            FMT_S => return self.fop::<f32>(cpu, opcode, t),
+            FMT_D => return self.fop::<f64>(cpu, opcode, t),
+
+            FMT_W | FMT_L => {
+                // Fixed-point formats; W is held in the lower 32 bits
+                let fgs = self.ctx.get_fgr(rs);
+                let v = if fmt == FMT_W {
+                    fgs as i32 as i64
+                } else {
+                    fgs as i64
+                };
+                match func {
+                    0x20 => self.cvt_from_fixed::<f32>(cpu, rd, v), // CVT.S.W / CVT.S.L
+                    0x21 => self.cvt_from_fixed::<f64>(cpu, rd, v), // CVT.D.W / CVT.D.L
+                    0x24 | 0x25 => {
+                        // CVT.W.W / CVT.W.L / CVT.L.W / CVT.L.L
+                        self.ctx.update_fcsr(cpu, FPE_UNIMPLEMENTED);
+                    }
+                    _ => {
+                        let msg = "unimplemented COP1 W/L opcode";
+                        return self.unimplemented_op(cpu, opcode, t, msg);
+                    }
                 }
-            },
+            }
 
             _ => {
-                error!(self.logger, "unimplemented COP1 fmt: fmt={:x?}", fmt);
-                return t.break_here("unimplemented COP1 opcode");
+                let msg = "unimplemented COP1 fmt";
+                return self.unimplemented_op(cpu, opcode, t, msg);
             }
         }
         Ok(())
@@ -433,15 +860,17 @@ impl Cop for Fpu {
                 let rt = REG_NAMES[((opcode >> 16) & 0x1f) as usize].into();
                 let fs = FPU_REG_NAMES[((opcode >> 11) & 0x1f) as usize].into();
                 let cfs = FPU_CREG_NAMES[((opcode >> 11) & 0x1f) as usize].into();
+                let fcsr = FPU_CREG_NAMES[31];
                 match fmt {
                     0x0 => DecodedInsn::new2("mfc1", OReg(rt), IReg(fs)),
+                    0x1 => DecodedInsn::new2("dmfc1", OReg(rt), IReg(fs)),
                     0x2 => DecodedInsn::new2("cfc1", OReg(rt), IReg(cfs)),
                     0x4 => DecodedInsn::new2("mtc1", IReg(rt), OReg(fs)),
                     0x5 => DecodedInsn::new2("dmtc1", IReg(rt), OReg(fs)),
                     0x6 => DecodedInsn::new2("ctc1", IReg(rt), OReg(cfs)),
                     0x8 => {
                         let tgt = pc + 4 + (opcode as u16).sx64() * 4;
-                        let cc = ((opcode >> 18) & 3) as usize;
+                        let cc = ((opcode >> 18) & 7) as usize;
                         let nd = opcode & (1 << 17) != 0;
                         let tf = opcode & (1 << 16) != 0;
                         let name = if tf {
@@ -457,17 +886,22 @@ impl Cop for Fpu {
                                 "bc1f"
                             }
                         };
-                        if cc != 0 {
-                            DecodedInsn::new2(name, Imm8(cc as u8), Target(tgt))
-                        } else {
-                            DecodedInsn::new1(name, Target(tgt))
+                        let mut flags = InsnFlags::BRANCH | InsnFlags::DELAY_SLOT;
+                        if nd {
+                            flags |= InsnFlags::LIKELY;
                         }
+                        let insn = if cc != 0 {
+                            DecodedInsn::new3(name, Imm8(cc as u8), Target(tgt), HidIReg(fcsr))
+                        } else {
+                            DecodedInsn::new2(name, Target(tgt), HidIReg(fcsr))
+                        };
+                        insn.with_flags(flags)
                     }
                     // Single/Double precision
-                    0x10 | 0x11 => {
+                    FMT_S | FMT_D => {
                         let ft = FPU_REG_NAMES[((opcode >> 16) & 0x1f) as usize].into();
                         let fd = FPU_REG_NAMES[((opcode >> 6) & 0x1f) as usize].into();
-                        match func {
+                        let insn = match func {
                             0x00 => DecodedInsn::new3(
                                 fp_suffix!("add", fmt),
                                 OReg(fd),
@@ -492,6 +926,8 @@ impl Cop for Fpu {
                                 IReg(fs),
                                 IReg(ft),
                             ),
+                            0x04 => DecodedInsn::new2(fp_suffix!("sqrt", fmt), OReg(fd), IReg(fs)),
+                            0x05 => DecodedInsn::new2(fp_suffix!("abs", fmt), OReg(fd), IReg(fs)),
                             0x06 => DecodedInsn::new2(fp_suffix!("mov", fmt), OReg(fd), IReg(fs)),
                             0x07 => DecodedInsn::new2(fp_suffix!("neg", fmt), OReg(fd), IReg(fs)),
                             0x08 => {
@@ -524,34 +960,34 @@ impl Cop for Fpu {
                             0x24 => DecodedInsn::new2(fp_suffix!("cvt.w", fmt), OReg(fd), IReg(fs)),
                             0x25 => DecodedInsn::new2(fp_suffix!("cvt.l", fmt), OReg(fd), IReg(fs)),
 
-                            0x30 => DecodedInsn::new2(fp_suffix!("c.t", fmt), IReg(fs), IReg(ft)),
-                            0x31 => DecodedInsn::new2(fp_suffix!("c.un", fmt), IReg(fs), IReg(ft)),
-                            0x32 => DecodedInsn::new2(fp_suffix!("c.eq", fmt), IReg(fs), IReg(ft)),
-                            0x33 => DecodedInsn::new2(fp_suffix!("c.ueq", fmt), IReg(fs), IReg(ft)),
-                            0x34 => DecodedInsn::new2(fp_suffix!("c.olt", fmt), IReg(fs), IReg(ft)),
-                            0x35 => DecodedInsn::new2(fp_suffix!("c.ult", fmt), IReg(fs), IReg(ft)),
-                            0x36 => DecodedInsn::new2(fp_suffix!("c.ole", fmt), IReg(fs), IReg(ft)),
-                            0x37 => DecodedInsn::new2(fp_suffix!("c.ult", fmt), IReg(fs), IReg(ft)),
-                            0x38 => DecodedInsn::new2(fp_suffix!("c.sf", fmt), IReg(fs), IReg(ft)),
-                            0x39 => {
-                                DecodedInsn::new2(fp_suffix!("c.ngle", fmt), IReg(fs), IReg(ft))
+                            0x30..=0x3F => {
+                                let (single, double) = FPU_COND_NAMES[(func & 0xF) as usize];
+                                let name = if fmt == FMT_S { single } else { double };
+                                let cc = (opcode >> 8) & 7;
+                                if cc != 0 {
+                                    DecodedInsn::new4(
+                                        name,
+                                        Imm8(cc as u8),
+                                        IReg(fs),
+                                        IReg(ft),
+                                        HidOReg(fcsr),
+                                    )
+                                } else {
+                                    DecodedInsn::new3(name, IReg(fs), IReg(ft), HidOReg(fcsr))
+                                }
                             }
-                            0x3A => DecodedInsn::new2(fp_suffix!("c.seq", fmt), IReg(fs), IReg(ft)),
-                            0x3B => DecodedInsn::new2(fp_suffix!("c.ngl", fmt), IReg(fs), IReg(ft)),
-                            0x3C => DecodedInsn::new2(fp_suffix!("c.lt", fmt), IReg(fs), IReg(ft)),
-                            0x3D => DecodedInsn::new2(fp_suffix!("c.nge", fmt), IReg(fs), IReg(ft)),
-                            0x3E => DecodedInsn::new2(fp_suffix!("c.le", fmt), IReg(fs), IReg(ft)),
-                            0x3F => DecodedInsn::new2(fp_suffix!("c.ngt", fmt), IReg(fs), IReg(ft)),
                             _ => DecodedInsn::new1("cop1op?", Imm32(func)),
-                        }
+                        };
+                        insn.with_cycles(op_cycles(fmt, func))
                     }
-                    0x14 | 0x15 => {
+                    FMT_W | FMT_L => {
                         let fd = FPU_REG_NAMES[((opcode >> 6) & 0x1f) as usize].into();
-                        match func {
+                        let insn = match func {
                             0x20 => DecodedInsn::new2(fp_suffix!("cvt.s", fmt), OReg(fd), IReg(fs)),
                             0x21 => DecodedInsn::new2(fp_suffix!("cvt.d", fmt), OReg(fd), IReg(fs)),
                             _ => DecodedInsn::new1("cop1cvt?", Imm32(func)),
-                        }
+                        };
+                        insn.with_cycles(op_cycles(fmt, func))
                     }
                     _ => DecodedInsn::new1("cop1?", Imm32(fmt)),
                 }
@@ -582,14 +1018,52 @@ impl Cop for Fpu {
         }
     }
 
+    #[cfg(feature = "frontend")]
     fn render_debug(&mut self, dr: &DebuggerRenderer) {
         dr.render_regview(self);
     }
 }
 
+// Editable bit fields of FCSR displayed in the register view
+// (name, shift, width).
+const FCSR_FIELDS: [(&'static str, u64, u64); 5] = [
+    ("RM", 0, 2),
+    ("Flags", FCSR_FLAG_SHIFT, 5),
+    ("Enables", FCSR_ENABLE_SHIFT, 5),
+    ("Cause", FCSR_CAUSE_SHIFT, 6),
+    ("FS", 24, 1),
+];
+
+// Format a float for the register view, switching to scientific notation
+// for values that would produce very long strings.
+fn fmt_float(v: f64) -> String {
+    let abs = v.abs();
+    if abs == 0.0 || !abs.is_finite() {
+        format!("{}", v)
+    } else if abs >= 1e-4 && abs < 1e9 {
+        format!("{:.5}", v)
+    } else {
+        format!("{:.5e}", v)
+    }
+}
+
+// Describe a set of FPU exception bits, eg: "E V Z".
+fn fpe_names(exc: u64) -> String {
+    let names = ["I", "U", "O", "Z", "V", "E"];
+    let mut desc: Vec<&str> = (0..6)
+        .rev()
+        .filter(|&b| exc & (1 << b) != 0)
+        .map(|b| names[b])
+        .collect();
+    if desc.is_empty() {
+        desc.push("-");
+    }
+    desc.join(" ")
+}
+
 impl RegisterView for Fpu {
-    const WINDOW_SIZE: [f32; 2] = [360.0, 400.0];
-    const COLUMNS: usize = 2;
+    const WINDOW_SIZE: [f32; 2] = [720.0, 560.0];
+    const COLUMNS: usize = 3;
 
     fn name(&self) -> &str {
         "FPU"
@@ -605,38 +1079,570 @@ impl RegisterView for Fpu {
     {
         use self::RegisterSize::*;
 
-        if self.ctx.fpu64 {
-            for idx in 0..16 {
-                let idx = idx + col * 8;
-
-                let val = self.ctx.regs[idx];
-                let desc = if val >> 32 == 0 {
-                    format!("S:{:.5}", f32::from_u64bits(val))
-                } else {
-                    format!("D:{:.5}", f64::from_u64bits(val))
-                };
-                visit(
-                    FPU_REG_NAMES[idx],
-                    Reg64(&mut self.ctx.regs[idx]),
-                    Some(&desc),
-                );
+        match col {
+            // FPRs, displayed as raw value, single and double. In 32-bit mode,
+            // doubles are made of even/odd register pairs, so they are only
+            // shown on even registers.
+            0 | 1 => {
+                for idx in col * 16..col * 16 + 16 {
+                    let val = self.ctx.regs[idx];
+                    if self.ctx.fpu64 {
+                        let desc = format!(
+                            "S:{}  D:{}",
+                            fmt_float(f32::from_u64bits(val).to_f64()),
+                            fmt_float(f64::from_u64bits(val))
+                        );
+                        visit(
+                            FPU_REG_NAMES[idx],
+                            Reg64(&mut self.ctx.regs[idx]),
+                            Some(&desc),
+                        );
+                    } else {
+                        let mut desc = format!("S:{}", fmt_float(f32::from_u64bits(val).to_f64()));
+                        if idx % 2 == 0 {
+                            let pair = self.ctx.get_fgr(idx);
+                            desc += &format!("  D:{}", fmt_float(f64::from_u64bits(pair)));
+                        }
+                        let mut reg = val as u32;
+                        visit(FPU_REG_NAMES[idx], Reg32(&mut reg), Some(&desc));
+                        self.ctx.regs[idx] = reg as u64;
+                    }
+                }
             }
-        } else {
-            for idx in 0..8 {
-                let idx = idx * 2 + col * 16;
 
-                let val = self.ctx.get_fgr(idx);
-                let desc = if val >> 32 == 0 {
-                    format!("S:{:.5}", f32::from_u64bits(val))
-                } else {
-                    format!("D:{:.5}", f64::from_u64bits(val))
-                };
+            // FCSR, also split into its bit fields
+            2 => {
+                let mut fcsr = self.ctx.fcsr as u32;
+                visit("FCSR", Reg32(&mut fcsr), None);
+                self.ctx.set_fcsr(fcsr as u64);
+
+                for &(name, shift, width) in FCSR_FIELDS.iter() {
+                    let mask = (1 << width) - 1;
+                    let mut val = ((self.ctx.fcsr >> shift) & mask) as u8;
+                    let desc = match name {
+                        "RM" => ["nearest", "to zero", "to +inf", "to -inf"][val as usize].into(),
+                        "FS" if val != 0 => "flush denormals".into(),
+                        "FS" => "IEEE denormals".into(),
+                        _ => fpe_names(val as u64),
+                    };
+                    visit(name, Reg8(&mut val), Some(&desc));
+                    let fcsr = (self.ctx.fcsr & !(mask << shift)) | ((val as u64 & mask) << shift);
+                    self.ctx.set_fcsr(fcsr);
+                }
 
-                let mut reg0 = self.ctx.regs[idx + 0] as u32;
-                let mut reg1 = self.ctx.regs[idx + 1] as u32;
-                visit(FPU_REG_NAMES[idx + 0], Reg32(&mut reg0), None);
-                visit(FPU_REG_NAMES[idx + 1], Reg32(&mut reg1), Some(&desc));
+                let mut ccs = self.ctx.fccr as u8;
+                let desc = format!("{:08b}", ccs);
+                visit("CC", Reg8(&mut ccs), Some(&desc));
+                for cc in 0..8 {
+                    self.set_cc(cc, ccs & (1 << cc) != 0);
+                }
             }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emu::dbg::Tracer;
+
+    // Run a COP1 opcode (fd=f0, fs=f1, ft=f2) with all rounding modes,
+    // returning the raw result in the order: RN, RZ, RP, RM.
+    fn run(fpu: &mut Fpu, fmt: u32, func: u32, fs: u64, ft: u64) -> [u64; 4] {
+        let mut cpu = CpuContext::default();
+        cpu.fpu64 = true;
+        fpu.ctx.fpu64 = true;
+
+        let opcode = 0x11 << 26 | fmt << 21 | 2 << 16 | 1 << 11 | 0 << 6 | func;
+        let mut res = [0u64; 4];
+        for rm in 0..4 {
+            fpu.ctx.fcsr = rm as u64;
+            fpu.ctx.set_fgr(1, fs);
+            fpu.ctx.set_fgr(2, ft);
+            fpu.op(&mut cpu, opcode, &Tracer::null()).unwrap();
+            res[rm] = fpu.ctx.get_fgr(0);
+        }
+        res
+    }
+
+    fn s(v: f32) -> u64 {
+        v.to_bits() as u64
+    }
+
+    fn d(v: f64) -> u64 {
+        v.to_bits()
+    }
+
+    #[test]
+    fn rounding_modes() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let mut fpu = Fpu::new("test", logger);
+
+        // ADD.S / SUB.S: 1 +/- 2^-30
+        let tiny = s(1.0 / (1u64 << 30) as f32);
+        assert_eq!(
+            run(&mut fpu, FMT_S, 0x00, s(1.0), tiny),
+            [0x3F80_0000, 0x3F80_0000, 0x3F80_0001, 0x3F80_0000]
+        );
+        assert_eq!(
+            run(&mut fpu, FMT_S, 0x01, s(1.0), tiny),
+            [0x3F80_0000, 0x3F7F_FFFF, 0x3F80_0000, 0x3F7F_FFFF]
+        );
+
+        // MUL.S: (1+2^-23)^2, and overflow
+        assert_eq!(
+            run(&mut fpu, FMT_S, 0x02, 0x3F80_0001, 0x3F80_0001),
+            [0x3F80_0002, 0x3F80_0002, 0x3F80_0003, 0x3F80_0002]
+        );
+        assert_eq!(
+            run(&mut fpu, FMT_S, 0x02, s(std::f32::MAX), s(2.0)),
+            [0x7F80_0000, 0x7F7F_FFFF, 0x7F80_0000, 0x7F7F_FFFF]
+        );
+
+        // DIV.S / DIV.D: 1/3 and -1/3
+        assert_eq!(
+            run(&mut fpu, FMT_S, 0x03, s(1.0), s(3.0)),
+            [0x3EAA_AAAB, 0x3EAA_AAAA, 0x3EAA_AAAB, 0x3EAA_AAAA]
+        );
+        assert_eq!(
+            run(&mut fpu, FMT_S, 0x03, s(-1.0), s(3.0)),
+            [0xBEAA_AAAB, 0xBEAA_AAAA, 0xBEAA_AAAA, 0xBEAA_AAAB]
+        );
+        assert_eq!(
+            run(&mut fpu, FMT_D, 0x03, d(1.0), d(3.0)),
+            [
+                0x3FD5_5555_5555_5555,
+                0x3FD5_5555_5555_5555,
+                0x3FD5_5555_5555_5556,
+                0x3FD5_5555_5555_5555
+            ]
+        );
+
+        // SQRT.S: sqrt(2)
+        assert_eq!(
+            run(&mut fpu, FMT_S, 0x04, s(2.0), 0),
+            [0x3FB5_04F3, 0x3FB5_04F3, 0x3FB5_04F4, 0x3FB5_04F3]
+        );
+
+        // CVT.S.D: 1/3
+        assert_eq!(
+            run(&mut fpu, FMT_D, 0x20, d(1.0 / 3.0), 0),
+            [0x3EAA_AAAB, 0x3EAA_AAAA, 0x3EAA_AAAB, 0x3EAA_AAAA]
+        );
+
+        // CVT.W.S: 2.5 and -2.5
+        assert_eq!(run(&mut fpu, FMT_S, 0x24, s(2.5), 0), [2, 2, 3, 2]);
+        assert_eq!(
+            run(&mut fpu, FMT_S, 0x24, s(-2.5), 0),
+            [-2i64 as u64, -2i64 as u64, -2i64 as u64, -3i64 as u64]
+        );
+
+        // CVT.S.W: 2^24+1 is not representable
+        assert_eq!(
+            run(&mut fpu, FMT_W, 0x20, (1 << 24) + 1, 0),
+            [0x4B80_0000, 0x4B80_0000, 0x4B80_0001, 0x4B80_0000]
+        );
+    }
+
+    // Run a single COP1 opcode (fd=f0, fs=f1, ft=f2) without touching FCSR,
+    // returning the raw result.
+    fn run1(fpu: &mut Fpu, cpu: &mut CpuContext, fmt: u32, func: u32, fs: u64, ft: u64) -> u64 {
+        cpu.fpu64 = true;
+        fpu.ctx.fpu64 = true;
+
+        let opcode = 0x11 << 26 | fmt << 21 | 2 << 16 | 1 << 11 | 0 << 6 | func;
+        fpu.ctx.set_fgr(0, 0xDEAD_BEEF);
+        fpu.ctx.set_fgr(1, fs);
+        fpu.ctx.set_fgr(2, ft);
+        fpu.op(cpu, opcode, &Tracer::null()).unwrap();
+        fpu.ctx.get_fgr(0)
+    }
+
+    #[test]
+    fn exception_flags() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let mut fpu = Fpu::new("test", logger);
+        let mut cpu = CpuContext::default();
+
+        let inf = d(std::f64::INFINITY);
+        let max = s(std::f32::MAX);
+        let cases = [
+            // ADD.S: exact
+            (FMT_S, 0x00, s(1.0), s(2.0), 0),
+            // DIV.S: 1/3, 1/0, 0/0
+            (FMT_S, 0x03, s(1.0), s(3.0), FPE_INEXACT),
+            (FMT_S, 0x03, s(1.0), s(0.0), FPE_DIVBYZERO),
+            (FMT_S, 0x03, s(0.0), s(0.0), FPE_INVALID),
+            // SUB.D: inf-inf
+            (FMT_D, 0x01, inf, inf, FPE_INVALID),
+            // SQRT.S: sqrt(-1)
+            (FMT_S, 0x04, s(-1.0), 0, FPE_INVALID),
+            // MUL.S: overflow and underflow
+            (FMT_S, 0x02, max, s(2.0), FPE_OVERFLOW | FPE_INEXACT),
+            (FMT_S, 0x02, s(1e-30), s(1e-30), FPE_UNDERFLOW | FPE_INEXACT),
+            // TRUNC.W.S: inexact
+            (FMT_S, 0x0D, s(1.5), 0, FPE_INEXACT),
+        ];
+        for &(fmt, func, fs, ft, exc) in cases.iter() {
+            fpu.ctx.fcsr = 0;
+            run1(&mut fpu, &mut cpu, fmt, func, fs, ft);
+            let flags = fpu.ctx.fcsr >> FCSR_FLAG_SHIFT & 0x1F;
+            assert_eq!(fpu.ctx.cause(), exc, "func={:x}", func);
+            assert_eq!(flags, exc, "func={:x}", func);
+            assert_eq!(cpu.cop_exception, None);
+        }
+
+        // Flags are sticky, Cause only reflects the last operation
+        fpu.ctx.fcsr = 0;
+        run1(&mut fpu, &mut cpu, FMT_S, 0x03, s(1.0), s(0.0));
+        run1(&mut fpu, &mut cpu, FMT_S, 0x00, s(1.0), s(2.0));
+        assert_eq!(fpu.ctx.cause(), 0);
+        assert_eq!(fpu.ctx.fcsr >> FCSR_FLAG_SHIFT & 0x1F, FPE_DIVBYZERO);
+
+        // Signaling comparisons with NaN raise invalid, quiet ones do not
+        let nan = s(std::f32::NAN);
+        fpu.ctx.fcsr = 0;
+        run1(&mut fpu, &mut cpu, FMT_S, 0x32, nan, s(1.0)); // C.EQ.S
+        assert_eq!(fpu.ctx.cause(), 0);
+        run1(&mut fpu, &mut cpu, FMT_S, 0x3C, nan, s(1.0)); // C.LT.S
+        assert_eq!(fpu.ctx.cause(), FPE_INVALID);
+
+        // Enabled exceptions trap, and leave destination and flags untouched
+        fpu.ctx.fcsr = FPE_DIVBYZERO << FCSR_ENABLE_SHIFT;
+        let res = run1(&mut fpu, &mut cpu, FMT_S, 0x03, s(1.0), s(0.0));
+        assert_eq!(res, 0xDEAD_BEEF);
+        assert_eq!(fpu.ctx.cause(), FPE_DIVBYZERO);
+        assert_eq!(fpu.ctx.fcsr >> FCSR_FLAG_SHIFT & 0x1F, 0);
+        assert_eq!(cpu.cop_exception.take(), Some(Exception::FloatingPoint));
+
+        // Disabled exceptions do not trap
+        let res = run1(&mut fpu, &mut cpu, FMT_S, 0x03, s(1.0), s(3.0));
+        assert_eq!(res, 0x3EAA_AAAB);
+        assert_eq!(cpu.cop_exception, None);
+
+        // CTC1 setting an enabled Cause bit traps
+        cpu.regs[8] = FPE_INEXACT << FCSR_ENABLE_SHIFT | FPE_INEXACT << FCSR_CAUSE_SHIFT;
+        let ctc1 = 0x11 << 26 | 0x6 << 21 | 8 << 16 | 31 << 11;
+        fpu.op(&mut cpu, ctc1, &Tracer::null()).unwrap();
+        assert_eq!(cpu.cop_exception.take(), Some(Exception::FloatingPoint));
+    }
+
+    #[test]
+    fn conversions() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let mut fpu = Fpu::new("test", logger);
+        let mut cpu = CpuContext::default();
+
+        let l = |v: i64| v as u64;
+        let big = d((1u64 << 40) as f64);
+        let cases = [
+            // CVT.S.D / CVT.S.W / CVT.S.L
+            (FMT_D, 0x20, d(1.5), s(1.5), 0),
+            (FMT_D, 0x20, d(0.1), s(0.1), FPE_INEXACT),
+            (
+                FMT_D,
+                0x20,
+                d(1e300),
+                s(std::f32::INFINITY),
+                FPE_OVERFLOW | FPE_INEXACT,
+            ),
+            (FMT_W, 0x20, l(-7), s(-7.0), 0),
+            (FMT_L, 0x20, l(-1 << 40), s(-((1u64 << 40) as f32)), 0),
+            (
+                FMT_L,
+                0x20,
+                l((1 << 40) + 1),
+                s((1u64 << 40) as f32),
+                FPE_INEXACT,
+            ),
+            // CVT.D.S / CVT.D.W / CVT.D.L
+            (FMT_S, 0x21, s(-0.1), d(-0.1f32 as f64), 0),
+            (FMT_W, 0x21, 0xFFFF_FFFF, d(-1.0), 0),
+            (FMT_L, 0x21, l(-3), d(-3.0), 0),
+            (
+                FMT_L,
+                0x21,
+                l((1 << 62) + 1),
+                d((1u64 << 62) as f64),
+                FPE_INEXACT,
+            ),
+            // CVT.W.S / CVT.W.D
+            (FMT_S, 0x24, s(-3.0), l(-3), 0),
+            (FMT_D, 0x24, d(3.75), 4, FPE_INEXACT),
+            // CVT.L.S / CVT.L.D
+            (FMT_S, 0x25, s(-2.5), l(-2), FPE_INEXACT),
+            (FMT_D, 0x25, big, 1 << 40, 0),
+        ];
+        for &(fmt, func, fs, res, exc) in cases.iter() {
+            fpu.ctx.fcsr = 0;
+            let v = run1(&mut fpu, &mut cpu, fmt, func, fs, 0);
+            assert_eq!(v, res, "fmt={:x} func={:x} fs={:x}", fmt, func, fs);
+            assert_eq!(
+                fpu.ctx.cause(),
+                exc,
+                "fmt={:x} func={:x} fs={:x}",
+                fmt,
+                func,
+                fs
+            );
         }
+
+        // CVT.S.S, CVT.D.D and conversions between fixed-point formats are
+        // unimplemented operations, which always trap.
+        let unimpl = [(FMT_S, 0x20), (FMT_D, 0x21), (FMT_W, 0x24), (FMT_L, 0x25)];
+        for &(fmt, func) in unimpl.iter() {
+            fpu.ctx.fcsr = 0;
+            let v = run1(&mut fpu, &mut cpu, fmt, func, 0, 0);
+            assert_eq!(v, 0xDEAD_BEEF);
+            assert_eq!(fpu.ctx.cause(), FPE_UNIMPLEMENTED);
+            assert_eq!(cpu.cop_exception.take(), Some(Exception::FloatingPoint));
+        }
+    }
+
+    #[test]
+    fn denormals_and_nans() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let mut fpu = Fpu::new("test", logger);
+        let mut cpu = CpuContext::default();
+
+        let denorm = 0x0000_0001; // smallest f32 denormal
+        let nan = s(std::f32::NAN);
+        let l = |v: i64| v as u64;
+
+        // Operations that the R4300 FPU does not implement in hardware
+        let unimpl = [
+            (FMT_S, 0x00, denorm, s(1.0)),            // ADD.S with denormal
+            (FMT_S, 0x02, s(2.0), nan),               // MUL.S with NaN
+            (FMT_S, 0x05, nan, 0),                    // ABS.S with NaN
+            (FMT_S, 0x21, denorm, 0),                 // CVT.D.S with denormal
+            (FMT_S, 0x02, s(1e-20), s(1e-20)),        // MUL.S with denormal result
+            (FMT_S, 0x0D, s(1e10), 0),                // TRUNC.W.S out of range
+            (FMT_S, 0x24, nan, 0),                    // CVT.W.S with NaN
+            (FMT_D, 0x25, d((1u64 << 54) as f64), 0), // CVT.L.D beyond 53 bits
+            (FMT_L, 0x21, l(1 << 55), 0),             // CVT.D.L beyond 55 bits
+        ];
+        for &(fmt, func, fs, ft) in unimpl.iter() {
+            fpu.ctx.fcsr = 0;
+            let v = run1(&mut fpu, &mut cpu, fmt, func, fs, ft);
+            assert_eq!(v, 0xDEAD_BEEF, "fmt={:x} func={:x}", fmt, func);
+            assert_eq!(
+                fpu.ctx.cause(),
+                FPE_UNIMPLEMENTED,
+                "fmt={:x} func={:x}",
+                fmt,
+                func
+            );
+            assert_eq!(cpu.cop_exception.take(), Some(Exception::FloatingPoint));
+        }
+
+        // Invalid operations produce the MIPS default NaN
+        fpu.ctx.fcsr = 0;
+        let v = run1(&mut fpu, &mut cpu, FMT_S, 0x03, s(0.0), s(0.0));
+        assert_eq!(v, 0x7FBF_FFFF);
+        assert_eq!(fpu.ctx.cause(), FPE_INVALID);
+        let v = run1(&mut fpu, &mut cpu, FMT_D, 0x04, d(-1.0), 0);
+        assert_eq!(v, 0x7FF7_FFFF_FFFF_FFFF);
+
+        // With FS set, denormal results are flushed according to the
+        // rounding mode.
+        for &(rm, fs, res) in [
+            (0, s(1e-20), 0),
+            (2, s(1e-20), 0x0080_0000),
+            (2, s(-1e-20), 0x8000_0000),
+            (3, s(-1e-20), 0x8080_0000),
+        ]
+        .iter()
+        {
+            fpu.ctx.fcsr = FCSR_FS | rm;
+            let v = run1(&mut fpu, &mut cpu, FMT_S, 0x02, fs, s(1e-20));
+            assert_eq!(v, res, "rm={}", rm);
+            assert_eq!(fpu.ctx.cause(), FPE_UNDERFLOW | FPE_INEXACT);
+            assert_eq!(cpu.cop_exception, None);
+        }
+
+        // ...unless underflow or inexact traps are enabled
+        fpu.ctx.fcsr = FCSR_FS | FPE_INEXACT << FCSR_ENABLE_SHIFT;
+        run1(&mut fpu, &mut cpu, FMT_S, 0x02, s(1e-20), s(1e-20));
+        assert_eq!(fpu.ctx.cause(), FPE_UNIMPLEMENTED);
+        assert_eq!(cpu.cop_exception.take(), Some(Exception::FloatingPoint));
+
+        // In IEEE mode, everything is processed by the host FPU
+        fpu.set_ieee_mode(true);
+        let ieee = [
+            (FMT_S, 0x00, denorm, denorm, 0x0000_0002, 0),
+            (
+                FMT_S,
+                0x02,
+                s(1e-20),
+                s(1e-20),
+                s(1e-20 * 1e-20),
+                FPE_UNDERFLOW | FPE_INEXACT,
+            ),
+            (FMT_S, 0x0D, s(1e10), 0, 0x7FFF_FFFF, FPE_INVALID),
+            (FMT_S, 0x24, nan, 0, 0x7FFF_FFFF, FPE_INVALID),
+            (FMT_D, 0x25, d(1e30), 0, 0x7FFF_FFFF_FFFF_FFFF, FPE_INVALID),
+        ];
+        for &(fmt, func, fs, ft, res, exc) in ieee.iter() {
+            fpu.ctx.fcsr = 0;
+            let v = run1(&mut fpu, &mut cpu, fmt, func, fs, ft);
+            assert_eq!(v, res, "fmt={:x} func={:x}", fmt, func);
+            assert_eq!(fpu.ctx.cause(), exc, "fmt={:x} func={:x}", fmt, func);
+            assert_eq!(cpu.cop_exception, None);
+        }
+    }
+
+    #[test]
+    fn moves() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let mut fpu = Fpu::new("test", logger);
+        let mut cpu = CpuContext::default();
+        let op = |fmt: u32| 0x11 << 26 | fmt << 21 | 2 << 16 | 4 << 11;
+
+        // With FR=1, DMTC1/DMFC1 access a whole 64-bit register.
+        cpu.fpu64 = true;
+        cpu.regs[2] = 0x1122_3344_5566_7788;
+        fpu.op(&mut cpu, op(0x5), &Tracer::null()).unwrap();
+        assert_eq!(fpu.ctx.regs[4], 0x1122_3344_5566_7788);
+        assert_eq!(fpu.ctx.regs[5], 0);
+        cpu.regs[2] = 0;
+        fpu.op(&mut cpu, op(0x1), &Tracer::null()).unwrap();
+        assert_eq!(cpu.regs[2], 0x1122_3344_5566_7788);
+
+        // With FR=0, they access an even/odd pair of 32-bit registers.
+        cpu.fpu64 = false;
+        cpu.regs[2] = 0x99AA_BBCC_DDEE_FF00;
+        fpu.op(&mut cpu, op(0x5), &Tracer::null()).unwrap();
+        assert_eq!(fpu.ctx.regs[4], 0xDDEE_FF00);
+        assert_eq!(fpu.ctx.regs[5], 0x99AA_BBCC);
+        fpu.ctx.regs[5] = 0x0102_0304;
+        fpu.op(&mut cpu, op(0x1), &Tracer::null()).unwrap();
+        assert_eq!(cpu.regs[2], 0x0102_0304_DDEE_FF00);
+
+        // MFC1 sign-extends the low word.
+        fpu.op(&mut cpu, op(0x0), &Tracer::null()).unwrap();
+        assert_eq!(cpu.regs[2], 0xFFFF_FFFF_DDEE_FF00);
+    }
+
+    #[test]
+    fn unimplemented_opcodes() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let mut fpu = Fpu::new("test", logger);
+        let mut cpu = CpuContext::default();
+
+        // Reserved functions and formats raise Unimplemented Operation
+        let reserved = [(FMT_S, 0x10), (FMT_D, 0x1F), (FMT_W, 0x00), (0x12, 0x00)];
+        for &(fmt, func) in reserved.iter() {
+            fpu.ctx.fcsr = 0;
+            let v = run1(&mut fpu, &mut cpu, fmt, func, 0, 0);
+            assert_eq!(v, 0xDEAD_BEEF, "fmt={:x} func={:x}", fmt, func);
+            assert_eq!(fpu.ctx.cause(), FPE_UNIMPLEMENTED);
+            assert_eq!(cpu.cop_exception.take(), Some(Exception::FloatingPoint));
+        }
+
+        // All 8 condition codes can be set and tested
+        fpu.ctx.fcsr = 0;
+        for cc in 0..8 {
+            let c_eq = 0x11 << 26 | FMT_S << 21 | 2 << 16 | 1 << 11 | cc << 8 | 0x32;
+            fpu.ctx.set_fgr(1, s(1.0));
+            fpu.ctx.set_fgr(2, s(1.0));
+            fpu.op(&mut cpu, c_eq, &Tracer::null()).unwrap();
+            assert_eq!(fpu.get_cc(cc as usize), true);
+        }
+        assert_eq!(fpu.ctx.fcsr >> 23, 0x1FD);
+    }
+
+    #[test]
+    fn fcsr_condition_codes() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let mut fpu = Fpu::new("test", logger);
+
+        // FCSR writes (CTC1, debugger) are reflected into condition codes
+        fpu.ctx.set_fcsr(1 << 23 | 1 << 24 | 1 << 31);
+        assert_eq!(fpu.ctx.fccr, 0x81);
+        assert_eq!(fpu.get_cc(0), true);
+        assert_eq!(fpu.get_cc(1), false);
+        assert_eq!(fpu.get_cc(7), true);
+
+        assert_eq!(fpe_names(FPE_UNIMPLEMENTED | FPE_DIVBYZERO), "E Z");
+        assert_eq!(fpe_names(0), "-");
+    }
+
+    #[test]
+    fn fgr_32bit_mode() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let mut fpu = Fpu::new("test", logger);
+        let mut cpu = CpuContext::default();
+
+        // 64-bit values are held in even/odd pairs
+        fpu.ctx.set_fgr(30, 0x1122_3344_5566_7788);
+        assert_eq!(fpu.ctx.regs[30], 0x5566_7788);
+        assert_eq!(fpu.ctx.regs[31], 0x1122_3344);
+
+        // Single-precision ops on odd registers do not touch the next
+        // register (and f31 can be used).
+        let opcode = 0x11 << 26 | FMT_S << 21 | 29 << 16 | 31 << 11 | 31 << 6 | 0x00;
+        fpu.ctx.regs[29] = 2.0f32.to_bits() as u64;
+        fpu.ctx.regs[31] = 1.0f32.to_bits() as u64;
+        fpu.op(&mut cpu, opcode, &Tracer::null()).unwrap();
+        assert_eq!(fpu.ctx.regs[31], 3.0f32.to_bits() as u64);
+        assert_eq!(fpu.ctx.regs[30], 0x5566_7788);
+
+        let opcode = 0x11 << 26 | FMT_S << 21 | 29 << 16 | 31 << 11 | 29 << 6 | 0x06;
+        fpu.op(&mut cpu, opcode, &Tracer::null()).unwrap();
+        assert_eq!(fpu.ctx.regs[29], 3.0f32.to_bits() as u64);
+        assert_eq!(fpu.ctx.regs[30], 0x5566_7788);
+    }
+
+    #[test]
+    fn disasm() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let fpu = Fpu::new("test", logger);
+
+        let cop1 = |fmt: u32, ft: u32, fs: u32, fd: u32, func: u32| {
+            0x11 << 26 | fmt << 21 | ft << 16 | fs << 11 | fd << 6 | func
+        };
+        let cases = [
+            (cop1(FMT_S, 2, 1, 0, 0x00), "add.s\tf0,f1,f2"),
+            (cop1(FMT_D, 4, 6, 8, 0x02), "mul.d\tf8,f6,f4"),
+            (cop1(FMT_S, 0, 3, 5, 0x04), "sqrt.s\tf5,f3"),
+            (cop1(FMT_D, 0, 3, 5, 0x05), "abs.d\tf5,f3"),
+            (cop1(FMT_S, 0, 12, 2, 0x24), "cvt.w.s\tf2,f12"),
+            (cop1(FMT_L, 0, 12, 2, 0x21), "cvt.d.l\tf2,f12"),
+            (cop1(FMT_S, 14, 12, 0, 0x3C), "c.lt.s\tf12,f14"),
+            (cop1(FMT_D, 14, 12, 0, 0x37), "c.ule.d\tf12,f14"),
+            (cop1(FMT_S, 14, 12, 3 << 2, 0x32), "c.eq.s\t3,f12,f14"),
+            (cop1(0x1, 4, 7, 0, 0), "dmfc1\ta0,f7"),
+            (cop1(0x6, 4, 31, 0, 0), "ctc1\ta0,FCSR"),
+        ];
+        for &(opcode, dis) in cases.iter() {
+            assert_eq!(fpu.decode(opcode, 0).disasm(), dis);
+        }
+
+        // Operand metadata, used to highlight registers
+        let insn = fpu.decode(cop1(FMT_S, 2, 1, 0, 0x03), 0);
+        let args: Vec<&Operand> = insn.args().collect();
+        assert!(
+            args == [
+                &Operand::OReg("f0"),
+                &Operand::IReg("f1"),
+                &Operand::IReg("f2")
+            ]
+        );
+        let insn = fpu.decode(cop1(FMT_S, 2, 1, 0, 0x3E), 0);
+        assert!(insn.args().any(|a| *a == Operand::HidOReg("FCSR")));
+
+        // Nominal cycles and flags
+        assert_eq!(fpu.decode(cop1(FMT_S, 2, 1, 0, 0x02), 0).cycles, 5);
+        assert_eq!(fpu.decode(cop1(FMT_D, 2, 1, 0, 0x03), 0).cycles, 58);
+        assert_eq!(fpu.decode(cop1(FMT_D, 0, 1, 0, 0x20), 0).cycles, 2);
+        assert_eq!(fpu.decode(cop1(FMT_S, 2, 1, 0, 0x32), 0).cycles, 1);
+        let bc1tl = fpu.decode(0x4503_0004, 0);
+        assert_eq!(bc1tl.disasm(), "bc1tl\t0x14");
+        assert_eq!(
+            bc1tl.flags,
+            InsnFlags::BRANCH | InsnFlags::DELAY_SLOT | InsnFlags::LIKELY
+        );
+        assert!(fpu.decode(cop1(0x4, 4, 7, 0, 0), 0).flags.is_empty());
     }
 }
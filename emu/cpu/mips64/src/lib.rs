@@ -11,17 +11,22 @@ extern crate num;
 extern crate slog;
 
 mod arch;
+mod asm;
+mod cache;
 mod cp0;
 mod cpu;
 mod fpu;
+mod timing;
 mod traits;
 
 pub(crate) mod decode;
 pub(crate) mod mmu;
 
 pub use self::arch::{ArchI, ArchII, ArchIII};
+pub use self::asm::assemble;
 pub use self::cp0::Cp0;
 pub use self::cpu::{Cpu, CpuContext, Exception};
 pub use self::decode::REG_NAMES;
 pub use self::fpu::Fpu;
+pub use self::timing::{MemRegion, MemTiming};
 pub use self::traits::{Arch, Config, Cop, Cop0, CopNull};
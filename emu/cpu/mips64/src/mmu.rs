@@ -52,6 +52,12 @@ impl TlbEntry {
         self.lo1.get_bit(2)
     }
 
+    /// Size in bytes of each of the two pages mapped by this entry.
+    #[inline]
+    pub fn page_size(&self) -> u32 {
+        ((self.page_mask | 0x1FFF) + 1) / 2
+    }
+
     #[inline]
     pub fn valid(&self) -> bool {
         self.valid0() || self.valid1()
@@ -81,6 +87,17 @@ impl fmt::Debug for TlbEntry {
     }
 }
 
+/// Reason why an address could not be translated through the TLB.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TlbError {
+    /// No entry matches the address (TLB refill)
+    Miss,
+    /// The matching entry is not valid (TLB invalid)
+    Invalid,
+    /// Write to a page that is not dirty (TLB modified)
+    Modified,
+}
+
 // Memory mapping unit of a MIPS processor
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 pub struct Mmu([TlbEntry; 32]);
@@ -103,6 +120,35 @@ impl Mmu {
         None
     }
 
+    /// Translates a virtual address into a physical address. `write` must be
+    /// true for stores, so that writes to clean (read-only) pages fail.
+    pub fn translate(&self, vaddr: u64, vasid: u8, write: bool) -> Result<u32, TlbError> {
+        let index = self.probe(vaddr, vasid).ok_or(TlbError::Miss)?;
+        self.translate_entry(index, vaddr, write)
+    }
+
+    /// Translates a virtual address through a specific TLB index, that must
+    /// have been found with `probe`.
+    pub fn translate_entry(&self, index: usize, vaddr: u64, write: bool) -> Result<u32, TlbError> {
+        let entry = &self.0[index];
+
+        // Each entry maps a pair of consecutive pages (even/odd).
+        let offset_mask = (entry.page_mask | 0x1FFF) >> 1;
+        let (lo, pfn) = if vaddr & (offset_mask as u64 + 1) == 0 {
+            (entry.lo0, entry.pfn0())
+        } else {
+            (entry.lo1, entry.pfn1())
+        };
+
+        if !lo.get_bit(1) {
+            return Err(TlbError::Invalid);
+        }
+        if write && !lo.get_bit(2) {
+            return Err(TlbError::Modified);
+        }
+        Ok((pfn & !offset_mask) | (vaddr as u32 & offset_mask))
+    }
+
     /// Reads a specific TLB index.
     pub fn read(&self, index: usize) -> &TlbEntry {
         &self.0[index]
@@ -215,6 +261,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_translate() {
+        let mut mmu = Mmu::default();
+
+        // 0x0040_0000 => 0x0010_0000 (even, dirty), 0x0040_1000 => 0x0020_0000 (odd, clean)
+        mmu.write(
+            0,
+            PAGE_MASK_4_KB,
+            0x0040_0000,
+            (0x0010_0000 >> 6) | 0b111, // global, valid, dirty
+            (0x0020_0000 >> 6) | 0b011, // global, valid
+        );
+        // 0x0100_0000 => 0x0030_0000 (16 KB pages, only odd page valid)
+        mmu.write(
+            1,
+            PAGE_MASK_16_KB,
+            0x0100_0000 | 0x12,
+            0x0030_0000 >> 6,
+            (0x0030_0000 >> 6) | 0b110, // valid, dirty
+        );
+
+        assert_eq!(mmu.translate(0x0040_0123, 0, false), Ok(0x0010_0123));
+        assert_eq!(mmu.translate(0x0040_0123, 0, true), Ok(0x0010_0123));
+        assert_eq!(mmu.translate(0x0040_1FFC, 0x55, false), Ok(0x0020_0FFC));
+        assert_eq!(mmu.translate(0x0040_1FFC, 0, true), Err(TlbError::Modified));
+        assert_eq!(mmu.translate(0x0040_2000, 0, false), Err(TlbError::Miss));
+
+        assert_eq!(
+            mmu.translate(0x0100_0010, 0x12, false),
+            Err(TlbError::Invalid)
+        );
+        assert_eq!(mmu.translate(0x0100_7FF0, 0x12, true), Ok(0x0030_3FF0));
+        assert_eq!(mmu.translate(0x0100_7FF0, 0x13, false), Err(TlbError::Miss));
+    }
+
     #[bench]
     fn bench_tlb_probe_match(b: &mut Bencher) {
         let mut mmu = Mmu::default();
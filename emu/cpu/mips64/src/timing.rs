@@ -0,0 +1,662 @@
+use super::cache::{Cache, Line};
+
+#[cfg(feature = "frontend")]
+use byteorder::BigEndian;
+use emu::bus::be::Bus;
+#[cfg(feature = "frontend")]
+use emu::dbg::imgui::*;
+#[cfg(feature = "frontend")]
+use emu::dbg::DebuggerRenderer;
+use emu::memint::MemInt;
+use serde_derive::{Deserialize, Serialize};
+
+// Geometry of the VR4300 caches: both are direct-mapped; the instruction
+// cache has 32-byte lines, the data cache 16-byte lines.
+const ICACHE_SIZE: usize = 16 * 1024;
+const ICACHE_LINE_SHIFT: u32 = 5;
+const DCACHE_SIZE: usize = 8 * 1024;
+const DCACHE_LINE_SHIFT: u32 = 4;
+
+/// Timing model of the memory accesses of a CPU.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemTiming {
+    /// Every opcode takes a single cycle, whatever memory it accesses.
+    Fast,
+    /// The tags of the instruction and data caches are tracked, and cache
+    /// misses and uncached accesses stall the CPU for the latency of the
+    /// memory they access. This is slower, but required by some
+    /// timing-sensitive code.
+    Accurate,
+}
+
+/// Latency of a region of the physical address space, in CPU cycles. Reads
+/// (and cache line fills) take `latency` cycles for the first word, plus
+/// `word` cycles for each following word; writes are posted, so they only
+/// stall the CPU for `write` cycles.
+#[derive(Copy, Clone, Debug)]
+pub struct MemRegion {
+    pub start: u32,
+    pub end: u32,
+    pub latency: i64,
+    pub word: i64,
+    pub write: i64,
+}
+
+pub(crate) struct Timing {
+    mode: MemTiming,
+    // Emulate the contents of the caches (and not only their tags).
+    caches: bool,
+    regions: Vec<MemRegion>,
+    contention: i64,
+    icache: Cache,
+    dcache: Cache,
+    // Instruction cache line of the last fetched opcode: opcodes within the
+    // same line are always hits.
+    last_fetch: Option<u32>,
+    // Cycles spent by the memory accesses of the current opcode.
+    stall: i64,
+    // Debugger view: cache being displayed, and whether invalid lines are
+    // hidden.
+    view_icache: bool,
+    view_valid_only: bool,
+}
+
+// Return true if the virtual address goes through the caches: KSEG1 is the
+// only uncached segment (mapped segments are considered always cached).
+fn is_cached(vaddr: u32) -> bool {
+    vaddr & 0xE000_0000 != 0xA000_0000
+}
+
+impl Timing {
+    pub(crate) fn new(name: &str) -> Self {
+        Self {
+            mode: MemTiming::Fast,
+            caches: false,
+            regions: Vec::new(),
+            contention: 0,
+            icache: Cache::new(
+                &("mips64::".to_owned() + name + "::icache"),
+                ICACHE_SIZE,
+                ICACHE_LINE_SHIFT,
+            ),
+            dcache: Cache::new(
+                &("mips64::".to_owned() + name + "::dcache"),
+                DCACHE_SIZE,
+                DCACHE_LINE_SHIFT,
+            ),
+            last_fetch: None,
+            stall: 0,
+            view_icache: false,
+            view_valid_only: true,
+        }
+    }
+
+    pub(crate) fn accurate(&self) -> bool {
+        self.mode == MemTiming::Accurate
+    }
+
+    // Return true if memory accesses must go through the caches, either to
+    // account their timing or to emulate their contents. When false, the
+    // CPU accesses the bus directly (fast path).
+    pub(crate) fn enabled(&self) -> bool {
+        self.accurate() || self.caches
+    }
+
+    pub(crate) fn mode(&self) -> MemTiming {
+        self.mode
+    }
+
+    pub(crate) fn set_mode(&mut self, mode: MemTiming, regions: Vec<MemRegion>) {
+        self.mode = mode;
+        self.regions = regions;
+        // Tags are not tracked while the fast model runs without cache
+        // emulation; lines holding data must instead be preserved.
+        if !self.caches {
+            self.reset();
+        }
+    }
+
+    pub(crate) fn caches(&self) -> bool {
+        self.caches
+    }
+
+    // Enable or disable the emulation of the cache contents. Dirty lines
+    // are written back before disabling it, and the caches start empty
+    // when enabling it.
+    pub(crate) fn set_caches(&mut self, enabled: bool, bus: &mut Bus) {
+        if self.caches == enabled {
+            return;
+        }
+        self.flush(bus);
+        self.caches = enabled;
+        self.reset();
+    }
+
+    pub(crate) fn set_contention(&mut self, cycles: i64) {
+        self.contention = cycles;
+    }
+
+    // Invalidate the caches (eg: at reset), discarding dirty lines.
+    pub(crate) fn reset(&mut self) {
+        self.icache.invalidate();
+        self.dcache.invalidate();
+        self.last_fetch = None;
+    }
+
+    // Write back all the dirty lines of the data cache, so that memory is
+    // coherent with it (eg: before disabling the cache emulation).
+    pub(crate) fn flush(&mut self, bus: &mut Bus) {
+        if !self.caches {
+            return;
+        }
+        for idx in 0..self.dcache.lines().len() {
+            let line = self.dcache.lines()[idx];
+            if line.valid && line.dirty {
+                self.dcache.writeback(&line, bus);
+                self.dcache.line(idx).dirty = false;
+            }
+        }
+    }
+
+    // Called after a snapshot is loaded: the cache lines are restored with
+    // the state, but the last fetched line might not be cached anymore.
+    pub(crate) fn after_load(&mut self) {
+        self.last_fetch = None;
+    }
+
+    // Invalidate the instruction cache line containing the specified address
+    // (eg: when the debugger patches code).
+    pub(crate) fn invalidate_code(&mut self, paddr: u32) {
+        if let Some(idx) = self.icache.hit(paddr) {
+            self.icache.line(idx).valid = false;
+        }
+        self.last_fetch = None;
+    }
+
+    // Return the cycles spent by the memory accesses since the last call.
+    pub(crate) fn take_stall(&mut self) -> i64 {
+        std::mem::replace(&mut self.stall, 0)
+    }
+
+    fn region(&self, paddr: u32) -> Option<&MemRegion> {
+        self.regions
+            .iter()
+            .find(|r| paddr >= r.start && paddr < r.end)
+    }
+
+    // Cycles required to read the specified number of words from memory.
+    fn read(&self, paddr: u32, words: i64) -> i64 {
+        if !self.accurate() {
+            return 0;
+        }
+        self.region(paddr)
+            .map_or(0, |r| r.latency + r.word * (words - 1) + self.contention)
+    }
+
+    fn write(&self, paddr: u32) -> i64 {
+        if !self.accurate() {
+            return 0;
+        }
+        self.region(paddr).map_or(0, |r| r.write)
+    }
+
+    // Write back a line evicted from the data cache.
+    fn writeback(&mut self, victim: Option<Line>, bus: &mut Bus) {
+        if let Some(line) = victim {
+            let words = 1 << (DCACHE_LINE_SHIFT - 2);
+            self.stall += self.read(self.dcache.line_addr(line.tag), words);
+            if self.caches {
+                self.dcache.writeback(&line, bus);
+            }
+        }
+    }
+
+    /// Fetch the opcode at the specified address through the instruction
+    /// cache. `op` is the opcode read from memory, which is returned unless
+    /// the cache contents are emulated.
+    pub(crate) fn fetch(&mut self, bus: &Bus, vaddr: u32, paddr: u32, op: u32) -> u32 {
+        if !is_cached(vaddr) {
+            self.last_fetch = None;
+            self.stall += self.read(paddr, 1);
+            return op;
+        }
+        let line = paddr >> ICACHE_LINE_SHIFT;
+        if self.last_fetch != Some(line) {
+            self.last_fetch = Some(line);
+            let (idx, hit, _) = self.icache.access(paddr, false);
+            if !hit {
+                self.stall += self.read(paddr, 1 << (ICACHE_LINE_SHIFT - 2));
+                if self.caches {
+                    self.icache.fill(idx, bus);
+                }
+            }
+        }
+        if self.caches {
+            self.icache.read(self.icache.index(paddr), paddr)
+        } else {
+            op
+        }
+    }
+
+    // Access the data cache line for a load or store, allocating and filling
+    // it on a miss. Returns the index of the line, if the access must be
+    // served by the cache contents; otherwise, the access goes to the bus.
+    fn data(
+        &mut self,
+        bus: &mut Bus,
+        vaddr: u32,
+        paddr: u32,
+        size: usize,
+        write: bool,
+    ) -> Option<usize> {
+        // Only memory can be cached; with cache emulation, accesses to
+        // registers through cached segments go to the bus anyway.
+        let uncached = !is_cached(vaddr)
+            || (self.caches
+                && self.dcache.hit(paddr).is_none()
+                && !bus.fetch_read_nolog::<u8>(paddr).is_mem());
+        if uncached {
+            self.stall += if write {
+                self.write(paddr)
+            } else {
+                self.read(paddr, (size as i64 + 3) / 4)
+            };
+            return None;
+        }
+        let (idx, hit, victim) = self.dcache.access(paddr, write);
+        if !hit {
+            self.stall += self.read(paddr, 1 << (DCACHE_LINE_SHIFT - 2));
+            self.writeback(victim, bus);
+            if self.caches {
+                self.dcache.fill(idx, bus);
+            }
+        }
+        if self.caches {
+            Some(idx)
+        } else {
+            None
+        }
+    }
+
+    /// Load a value through the data cache.
+    pub(crate) fn load<U: MemInt>(&mut self, bus: &mut Bus, vaddr: u32, paddr: u32) -> U {
+        match self.data(bus, vaddr, paddr, U::SIZE, false) {
+            Some(idx) => self.dcache.read(idx, paddr),
+            None => bus.read::<U>(paddr),
+        }
+    }
+
+    /// Store a value through the data cache.
+    pub(crate) fn store<U: MemInt>(&mut self, bus: &mut Bus, vaddr: u32, paddr: u32, val: U) {
+        match self.data(bus, vaddr, paddr, U::SIZE, true) {
+            Some(idx) => self.dcache.write(idx, paddr, val),
+            None => bus.write::<U>(paddr, val),
+        }
+    }
+
+    /// Account a coprocessor load or store, which accesses the bus directly.
+    /// With cache emulation, the cached line is written back first, so that
+    /// the coprocessor reads up-to-date data; after a store, the line is
+    /// invalidated, so that the CPU reads back the stored data.
+    pub(crate) fn cop_access(
+        &mut self,
+        bus: &mut Bus,
+        vaddr: u32,
+        paddr: u32,
+        size: usize,
+        write: bool,
+    ) {
+        if let Some(idx) = self.data(bus, vaddr, paddr, size, write) {
+            let line = self.dcache.lines()[idx];
+            if line.dirty {
+                self.dcache.writeback(&line, bus);
+            }
+            let line = self.dcache.line(idx);
+            line.dirty = false;
+            line.valid &= !write;
+        }
+    }
+
+    /// Apply a CACHE opcode (`op` is the 5-bit operation field) to the
+    /// line containing the specified address, writing back or filling the
+    /// line as requested.
+    pub(crate) fn cache_op(&mut self, bus: &mut Bus, op: u32, paddr: u32) {
+        if op & 3 == 0 {
+            self.last_fetch = None;
+        }
+        let (cache, words) = match op & 3 {
+            0 => (&mut self.icache, 1 << (ICACHE_LINE_SHIFT - 2)),
+            1 => (&mut self.dcache, 1 << (DCACHE_LINE_SHIFT - 2)),
+            _ => return,
+        };
+        let mut victim = None;
+        let mut fill = None;
+        match op >> 2 {
+            // Index (Writeback) Invalidate
+            0 => {
+                let line = cache.line(cache.index(paddr));
+                if line.valid && line.dirty {
+                    victim = Some(*line);
+                }
+                line.valid = false;
+            }
+            // Index Store Tag: used to initialize caches, with a zero TagLo.
+            2 => cache.line(cache.index(paddr)).valid = false,
+            // Create Dirty Exclusive: allocate the line without filling it,
+            // so that its contents are stale until fully written.
+            3 if op & 3 == 1 => victim = cache.access(paddr, true).2,
+            // Hit Invalidate
+            4 => {
+                if let Some(idx) = cache.hit(paddr) {
+                    cache.line(idx).valid = false;
+                }
+            }
+            // Fill (I-cache) or Hit Writeback Invalidate (D-cache)
+            5 if op & 3 == 0 => fill = Some(cache.access(paddr, false).0),
+            5 => {
+                if let Some(idx) = cache.hit(paddr) {
+                    let line = cache.line(idx);
+                    if line.dirty {
+                        victim = Some(*line);
+                    }
+                    line.valid = false;
+                }
+            }
+            // Hit Writeback
+            6 => {
+                if let Some(idx) = cache.hit(paddr) {
+                    let line = cache.line(idx);
+                    if line.dirty {
+                        victim = Some(*line);
+                    }
+                    line.dirty = false;
+                }
+            }
+            _ => {}
+        }
+        if let Some(idx) = fill {
+            self.stall += self.read(paddr, words);
+            if self.caches {
+                self.icache.fill(idx, bus);
+            }
+        }
+        self.writeback(victim, bus);
+    }
+}
+
+#[cfg(feature = "frontend")]
+impl Timing {
+    // Render the contents of the caches: tag address, flags and data of
+    // each line. Dirty lines are highlighted.
+    pub(crate) fn render_debug<'a, 'ui>(
+        &mut self,
+        cpu_name: &str,
+        bus: &mut Bus,
+        dr: &DebuggerRenderer<'a, 'ui>,
+    ) {
+        let ui = dr.ui();
+        Window::new(&im_str!("[{}] Caches", cpu_name))
+            .size([560.0, 420.0], Condition::FirstUseEver)
+            .build(ui, || {
+                let mut caches = self.caches;
+                if ui.checkbox(im_str!("Emulate cache contents"), &mut caches) {
+                    self.set_caches(caches, bus);
+                }
+                if ui.small_button(im_str!("Write back")) {
+                    self.flush(bus);
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Write back and invalidate")) {
+                    self.flush(bus);
+                    self.reset();
+                }
+                ui.separator();
+                ui.radio_button(im_str!("I-cache"), &mut self.view_icache, true);
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("D-cache"), &mut self.view_icache, false);
+                ui.same_line(0.0);
+                ui.checkbox(im_str!("Valid lines only"), &mut self.view_valid_only);
+                if !self.caches {
+                    ui.text_disabled("Contents not emulated: only tags are tracked.");
+                }
+                ui.separator();
+
+                let cache = if self.view_icache {
+                    &self.icache
+                } else {
+                    &self.dcache
+                };
+                ui.columns(4, im_str!("##cache"), true);
+                for title in ["#", "Address", "Flags", "Data"].iter() {
+                    ui.text(title);
+                    ui.next_column();
+                }
+                ui.separator();
+                for (idx, line) in cache.lines().iter().enumerate() {
+                    if self.view_valid_only && !line.valid {
+                        continue;
+                    }
+                    let color = if !line.valid {
+                        [0.5, 0.5, 0.5, 1.0]
+                    } else if line.dirty {
+                        [1.0, 1.0, 0.3, 1.0]
+                    } else {
+                        [1.0, 1.0, 1.0, 1.0]
+                    };
+                    let style = ui.push_style_color(StyleColor::Text, color);
+                    ui.text(format!("{:3}", idx));
+                    ui.next_column();
+                    ui.text(format!("{:08X}", cache.line_addr(line.tag)));
+                    ui.next_column();
+                    ui.text(format!(
+                        "{}{}",
+                        if line.valid { "V" } else { "-" },
+                        if line.dirty { "D" } else { "-" },
+                    ));
+                    ui.next_column();
+                    if self.caches {
+                        let words: Vec<String> = line.data[..cache.line_size()]
+                            .chunks(4)
+                            .map(|w| format!("{:08X}", u32::endian_read_from::<BigEndian>(w)))
+                            .collect();
+                        ui.text(words.join(" "));
+                    }
+                    ui.next_column();
+                    style.pop(&ui);
+                }
+                ui.columns(1, im_str!(""), false);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emu::bus::be::{BusFill, Mem, MemFlags};
+    use emu::state::CurrentState;
+
+    fn bus() -> Box<Bus> {
+        Bus::new(slog::Logger::root(slog::Discard, o!()))
+    }
+
+    fn timing() -> Timing {
+        let mut t = Timing::new("cpu");
+        t.set_mode(
+            MemTiming::Accurate,
+            vec![MemRegion {
+                start: 0,
+                end: 0x0080_0000,
+                latency: 20,
+                word: 2,
+                write: 4,
+            }],
+        );
+        t
+    }
+
+    fn fetch(t: &mut Timing, bus: &Bus, vaddr: u32, paddr: u32) -> i64 {
+        t.fetch(bus, vaddr, paddr, 0);
+        t.take_stall()
+    }
+
+    fn data(
+        t: &mut Timing,
+        bus: &mut Bus,
+        vaddr: u32,
+        paddr: u32,
+        size: usize,
+        write: bool,
+    ) -> i64 {
+        t.data(bus, vaddr, paddr, size, write);
+        t.take_stall()
+    }
+
+    fn cache_op(t: &mut Timing, bus: &mut Bus, op: u32, paddr: u32) -> i64 {
+        t.cache_op(bus, op, paddr);
+        t.take_stall()
+    }
+
+    #[test]
+    fn fetch_timing() {
+        let (mut t, bus) = (timing(), bus());
+        assert_eq!(fetch(&mut t, &bus, 0x8000_1000, 0x1000), 20 + 2 * 7);
+        assert_eq!(fetch(&mut t, &bus, 0x8000_1004, 0x1004), 0);
+        assert_eq!(fetch(&mut t, &bus, 0x8000_1020, 0x1020), 34);
+        assert_eq!(fetch(&mut t, &bus, 0x8000_1000, 0x1000), 0);
+
+        // Uncached fetches always access memory.
+        assert_eq!(fetch(&mut t, &bus, 0xA000_1000, 0x1000), 20);
+        assert_eq!(fetch(&mut t, &bus, 0xA000_1004, 0x1004), 20);
+
+        // A line at the same index evicts the previous one.
+        assert_eq!(fetch(&mut t, &bus, 0x8000_5000, 0x5000), 34);
+        assert_eq!(fetch(&mut t, &bus, 0x8000_1000, 0x1000), 34);
+
+        // Outside of any region, accesses are free.
+        assert_eq!(fetch(&mut t, &bus, 0x8100_0000, 0x0100_0000), 0);
+    }
+
+    #[test]
+    fn data_timing() {
+        let (mut t, mut bus) = (timing(), bus());
+        assert_eq!(data(&mut t, &mut bus, 0x8000_2000, 0x2000, 4, false), 26);
+        assert_eq!(data(&mut t, &mut bus, 0x8000_200C, 0x200C, 4, true), 0);
+
+        // Evicting a dirty line writes it back.
+        assert_eq!(
+            data(&mut t, &mut bus, 0x8000_4000, 0x4000, 8, false),
+            26 + 26
+        );
+        assert_eq!(data(&mut t, &mut bus, 0x8000_6000, 0x6000, 8, false), 26);
+
+        assert_eq!(data(&mut t, &mut bus, 0xA000_2000, 0x2000, 8, false), 22);
+        assert_eq!(data(&mut t, &mut bus, 0xA000_2000, 0x2000, 4, true), 4);
+
+        t.set_contention(10);
+        assert_eq!(data(&mut t, &mut bus, 0xA000_2000, 0x2000, 4, false), 30);
+    }
+
+    #[test]
+    fn cache_op_timing() {
+        let (mut t, mut bus) = (timing(), bus());
+        data(&mut t, &mut bus, 0x8000_2000, 0x2000, 4, true);
+
+        // Hit Writeback keeps the line, which is clean afterwards.
+        assert_eq!(cache_op(&mut t, &mut bus, 6 << 2 | 1, 0x2000), 26);
+        assert_eq!(cache_op(&mut t, &mut bus, 6 << 2 | 1, 0x2000), 0);
+        assert_eq!(data(&mut t, &mut bus, 0x8000_2000, 0x2000, 4, false), 0);
+
+        // Hit Invalidate discards the line.
+        assert_eq!(cache_op(&mut t, &mut bus, 4 << 2 | 1, 0x2000), 0);
+        assert_eq!(data(&mut t, &mut bus, 0x8000_2000, 0x2000, 4, false), 26);
+
+        // Create Dirty Exclusive allocates without filling.
+        assert_eq!(cache_op(&mut t, &mut bus, 3 << 2 | 1, 0x3000), 0);
+        assert_eq!(data(&mut t, &mut bus, 0x8000_3000, 0x3000, 4, false), 0);
+
+        // I-cache Fill and Index Invalidate.
+        assert_eq!(cache_op(&mut t, &mut bus, 5 << 2, 0x1000), 34);
+        assert_eq!(fetch(&mut t, &bus, 0x8000_1000, 0x1000), 0);
+        assert_eq!(cache_op(&mut t, &mut bus, 0, 0x1000), 0);
+        assert_eq!(fetch(&mut t, &bus, 0x8000_1000, 0x1000), 34);
+    }
+
+    #[test]
+    fn cache_contents() {
+        let ram = Mem::new("ram", 0x10000, MemFlags::default(), None);
+        let mut bus = bus();
+        bus.map_mem(0x0000_0000, 0x0000_FFFF, &ram, BusFill::None)
+            .unwrap();
+        let mut t = Timing::new("cpu");
+        t.set_caches(true, &mut bus);
+        bus.write::<u32>(0x2000, 0x1122_3344);
+
+        // Cached stores stay in the data cache until written back, while
+        // uncached accesses see memory.
+        t.store::<u32>(&mut bus, 0x8000_2000, 0x2000, 0xAABB_CCDD);
+        assert_eq!(t.load::<u16>(&mut bus, 0x8000_2002, 0x2002), 0xCCDD);
+        assert_eq!(t.load::<u32>(&mut bus, 0xA000_2000, 0x2000), 0x1122_3344);
+        t.cache_op(&mut bus, 6 << 2 | 1, 0x2000);
+        assert_eq!(bus.read::<u32>(0x2000), 0xAABB_CCDD);
+
+        // Memory written behind the cache (eg: by DMA) is not seen until
+        // the line is invalidated.
+        bus.write::<u32>(0x2000, 0x5566_7788);
+        assert_eq!(t.load::<u32>(&mut bus, 0x8000_2000, 0x2000), 0xAABB_CCDD);
+        t.cache_op(&mut bus, 4 << 2 | 1, 0x2000);
+        assert_eq!(t.load::<u32>(&mut bus, 0x8000_2000, 0x2000), 0x5566_7788);
+
+        // Evicted dirty lines are written back.
+        t.store::<u8>(&mut bus, 0x8000_2004, 0x2004, 0x99);
+        t.load::<u32>(&mut bus, 0x8000_4000, 0x4000);
+        assert_eq!(bus.read::<u8>(0x2004), 0x99);
+
+        // Instructions are fetched from the instruction cache, so code
+        // modified in memory needs an explicit invalidation.
+        bus.write::<u32>(0x1000, 0x2400_0001);
+        assert_eq!(t.fetch(&bus, 0x8000_1000, 0x1000, 0), 0x2400_0001);
+        bus.write::<u32>(0x1000, 0x2400_0002);
+        assert_eq!(t.fetch(&bus, 0x8000_1000, 0x1000, 0), 0x2400_0001);
+        t.cache_op(&mut bus, 4 << 2, 0x1000);
+        assert_eq!(t.fetch(&bus, 0x8000_1000, 0x1000, 0), 0x2400_0002);
+
+        // Disabling the emulation writes back dirty lines.
+        t.store::<u32>(&mut bus, 0x8000_3000, 0x3000, 0xDEAD_BEEF);
+        t.set_caches(false, &mut bus);
+        assert_eq!(bus.read::<u32>(0x3000), 0xDEAD_BEEF);
+    }
+
+    #[test]
+    fn cache_snapshot() {
+        let ram = Mem::new("ram", 0x10000, MemFlags::default(), None);
+        let mut bus = bus();
+        bus.map_mem(0x0000_0000, 0x0000_FFFF, &ram, BusFill::None)
+            .unwrap();
+        let mut t = Timing::new("cpu");
+        t.set_caches(true, &mut bus);
+        t.store::<u32>(&mut bus, 0x8000_2000, 0x2000, 0xAABB_CCDD);
+        bus.write::<u32>(0x1000, 0x2400_0001);
+        t.fetch(&bus, 0x8000_1000, 0x1000, 0);
+
+        // Saving the state does not write back dirty lines.
+        let snap = CurrentState().clone();
+        assert_eq!(bus.read::<u32>(0x2000), 0);
+
+        t.store::<u32>(&mut bus, 0x8000_2000, 0x2000, 0x1122_3344);
+        t.cache_op(&mut bus, 4 << 2, 0x1000);
+        t.cache_op(&mut bus, 4 << 2 | 1, 0x2000);
+        snap.make_current();
+        t.after_load();
+
+        // Lines are restored exactly, including their dirty bit and the
+        // instructions cached before memory was modified.
+        let idx = t.dcache.hit(0x2000).unwrap();
+        assert!(t.dcache.lines()[idx].dirty);
+        assert_eq!(t.load::<u32>(&mut bus, 0x8000_2000, 0x2000), 0xAABB_CCDD);
+        bus.write::<u32>(0x1000, 0x2400_0002);
+        assert_eq!(t.fetch(&bus, 0x8000_1000, 0x1000, 0), 0x2400_0001);
+        assert_eq!(bus.read::<u32>(0x2000), 0);
+        t.cache_op(&mut bus, 6 << 2 | 1, 0x2000);
+        assert_eq!(bus.read::<u32>(0x2000), 0xAABB_CCDD);
+    }
+}
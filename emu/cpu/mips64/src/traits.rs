@@ -1,7 +1,10 @@
 use super::{CpuContext, Exception};
 use emu::bus::be::Bus;
-use emu::dbg::{DebuggerRenderer, DecodedInsn, Result, Tracer};
+#[cfg(feature = "frontend")]
+use emu::dbg::DebuggerRenderer;
+use emu::dbg::{DecodedInsn, Result, Tracer};
 use emu::memint::MemInt;
+use emu::state::Snapshotable;
 
 /// Arch is a trait that allows to customise the MIPS core at the opcode level.
 /// It is used to implement different MIPS variants (architecture levels).
@@ -42,7 +45,9 @@ pub trait Config {
 }
 
 /// Cop is a MIPS64 coprocessor that can be installed within the core.
-pub trait Cop {
+/// Coprocessors are notified through the `Snapshotable` trait when the CPU
+/// they are installed into is saved or restored.
+pub trait Cop: Snapshotable {
     fn reg(&self, cpu: &CpuContext, idx: usize) -> u128;
     fn set_reg(&mut self, cpu: &mut CpuContext, idx: usize, val: u128);
 
@@ -84,6 +89,7 @@ pub trait Cop {
     }
 
     // Implement some debugger views
+    #[cfg(feature = "frontend")]
     fn render_debug<'a, 'ui>(&mut self, _dr: &DebuggerRenderer<'a, 'ui>) {}
 
     // Internal check to efficiently handle empty coprocessors
@@ -112,12 +118,56 @@ pub trait Cop0: Cop {
     /// performance.
     fn poll_interrupts(&mut self, ctx: &mut CpuContext);
 
+    /// Return the CPU clock at which the next internal event (eg: a timer
+    /// interrupt) will happen. The CPU never runs past this clock without
+    /// calling `poll_interrupts`, so that the event is raised on time.
+    fn next_event(&self) -> i64 {
+        i64::max_value()
+    }
+
     /// Trigger the specified excepion.
     fn exception(&mut self, ctx: &mut CpuContext, exc: Exception);
+
+    /// Translate a virtual address into a physical address. If the address
+    /// cannot be translated, the TLB exception that must be raised is
+    /// returned. The default implementation is for cores without a TLB,
+    /// where all addresses are unmapped.
+    fn translate(
+        &self,
+        _ctx: &CpuContext,
+        vaddr: u64,
+        _write: bool,
+    ) -> std::result::Result<u32, Exception> {
+        Ok(vaddr as u32)
+    }
+
+    /// Return true if the virtual address is mapped through the TLB.
+    fn is_mapped(&self, _vaddr: u64) -> bool {
+        false
+    }
+
+    /// Trigger a TLB exception caused by an access to the specified virtual
+    /// address, as returned by `translate`.
+    fn tlb_exception(&mut self, ctx: &mut CpuContext, exc: Exception, _vaddr: u64, _write: bool) {
+        self.exception(ctx, exc);
+    }
+
+    /// Implement the debugger view of the TLB. The TLB is part of the CPU
+    /// context, so it is not accessible from `Cop::render_debug`.
+    #[cfg(feature = "frontend")]
+    fn render_tlb<'a, 'ui>(&mut self, _ctx: &mut CpuContext, _dr: &DebuggerRenderer<'a, 'ui>) {}
+
+    /// Implement the debugger view of the registers. Registers edited by the
+    /// user must be written as through MTC0, whose side effects (eg: on the
+    /// timer interrupt) need the CPU context.
+    #[cfg(feature = "frontend")]
+    fn render_regs<'a, 'ui>(&mut self, _ctx: &mut CpuContext, _dr: &DebuggerRenderer<'a, 'ui>) {}
 }
 
 pub struct CopNull {}
 
+impl Snapshotable for CopNull {}
+
 impl Cop for CopNull {
     fn reg(&self, _ctx: &CpuContext, _idx: usize) -> u128 {
         0
@@ -62,7 +62,9 @@ impl dbg::DebuggerModel for FakeModel {
         self.curframe += 1;
         Ok(())
     }
-    fn reset(&mut self, hard: bool) {}
+    fn reset(&mut self, hard: bool) -> std::result::Result<(), String> {
+        Ok(())
+    }
 
     fn render_debug<'a, 'ui>(&mut self, dr: &dbg::DebuggerRenderer<'a, 'ui>) {
         dr.render_memoryview(self);
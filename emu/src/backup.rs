@@ -0,0 +1,334 @@
+//! A module to handle battery-backed memories (and similar persistent storage
+//! of game saves) which must be persisted to disk.
+//!
+//! [`BackupMem`](struct.BackupMem.html) holds the memory contents, and keeps
+//! a disk file in sync according to a configurable
+//! [`FlushPolicy`](enum.FlushPolicy.html).
+//!
+//! Files are always replaced atomically: the new contents are written to a
+//! temporary file in the same directory, synced to disk, and then renamed
+//! over the previous file, so that a crash in the middle of a flush never
+//! leaves a truncated save behind.
+use failure::{format_err, Error};
+use slog::{error, info};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// FlushPolicy specifies when the contents of a `BackupMem` are written to disk.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Flush after each write to the memory.
+    WriteThrough,
+    /// Flush at most once every specified number of frames, if the memory
+    /// was modified.
+    Interval(u32),
+    /// Flush only when the emulator exits (the `BackupMem` is dropped).
+    OnExit,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        FlushPolicy::Interval(60)
+    }
+}
+
+impl FromStr for FlushPolicy {
+    type Err = Error;
+
+    /// Parse a policy from its textual form: "write-through", "on-exit",
+    /// or "interval:<frames>".
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "write-through" => Ok(FlushPolicy::WriteThrough),
+            "on-exit" => Ok(FlushPolicy::OnExit),
+            _ if s.starts_with("interval:") => Ok(FlushPolicy::Interval(
+                s["interval:".len()..]
+                    .parse()
+                    .map_err(|_| format_err!("invalid flush interval: {}", s))?,
+            )),
+            _ => Err(format_err!("invalid flush policy: {}", s)),
+        }
+    }
+}
+
+/// A memory buffer which is persisted to a file on disk.
+pub struct BackupMem {
+    logger: slog::Logger,
+    path: Option<PathBuf>, // None if never persisted
+    policy: FlushPolicy,
+    data: Vec<u8>,
+    flushed: Vec<u8>, // contents of the file on disk
+    frames: u32,      // frames since last flush
+}
+
+impl BackupMem {
+    /// Create a new backup memory of the specified size, and load its initial
+    /// contents from the specified path. If the file does not exist, the
+    /// memory is filled with `fill`, and the file will be created at the
+    /// first flush. Without a path, the memory is never persisted.
+    pub fn new(
+        logger: slog::Logger,
+        path: Option<&Path>,
+        size: usize,
+        fill: u8,
+        policy: FlushPolicy,
+    ) -> io::Result<Self> {
+        let mut data = vec![fill; size];
+        let mut flushed = data.clone();
+
+        if let Some(path) = path.filter(|p| p.exists()) {
+            let contents = fs::read(path)?;
+            let n = contents.len().min(size);
+            data[..n].copy_from_slice(&contents[..n]);
+            flushed = contents;
+        }
+
+        Ok(Self {
+            logger,
+            path: path.map(|p| p.to_owned()),
+            policy,
+            data,
+            flushed,
+            frames: 0,
+        })
+    }
+
+    /// Create a backup memory with the specified contents, which were
+    /// already loaded from `path` by the caller (eg: after validating them).
+    pub fn with_data(
+        logger: slog::Logger,
+        path: Option<&Path>,
+        data: Vec<u8>,
+        policy: FlushPolicy,
+    ) -> Self {
+        Self {
+            logger,
+            path: path.map(|p| p.to_owned()),
+            policy,
+            flushed: data.clone(),
+            data,
+            frames: 0,
+        }
+    }
+
+    /// Access the memory contents.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Access the memory contents for modification. Notice that modifications
+    /// performed through this function do not trigger a flush with
+    /// `FlushPolicy::WriteThrough`: use [`write()`](#method.write) instead,
+    /// or call [`written()`](#method.written) afterwards.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Write a buffer at the specified offset into the memory.
+    pub fn write(&mut self, offset: usize, buf: &[u8]) {
+        self.data[offset..offset + buf.len()].copy_from_slice(buf);
+        self.written();
+    }
+
+    /// Notify that the memory was modified through
+    /// [`data_mut()`](#method.data_mut).
+    pub fn written(&mut self) {
+        if self.policy == FlushPolicy::WriteThrough {
+            self.flush_or_log();
+        }
+    }
+
+    /// Must be called once per frame, to implement `FlushPolicy::Interval`.
+    pub fn end_frame(&mut self) -> io::Result<()> {
+        if let FlushPolicy::Interval(frames) = self.policy {
+            self.frames += 1;
+            if self.frames >= frames {
+                return self.flush();
+            }
+        }
+        Ok(())
+    }
+
+    /// Return true if the memory contents differ from the file on disk.
+    pub fn is_dirty(&self) -> bool {
+        self.flushed[..] != self.data[..]
+    }
+
+    /// Write the memory contents to disk (if they were modified), atomically
+    /// replacing the previous file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.frames = 0;
+        if !self.is_dirty() {
+            return Ok(());
+        }
+        if let Some(path) = self.path.as_ref() {
+            write_atomic(path, &self.data)?;
+            info!(self.logger, "backup memory flushed"; "path" => path.display().to_string());
+        }
+        self.flushed = self.data.clone();
+        Ok(())
+    }
+
+    fn flush_or_log(&mut self) {
+        if let Err(err) = self.flush() {
+            error!(self.logger, "cannot flush backup memory";
+                "path" => ?self.path, "err" => err.to_string());
+        }
+    }
+}
+
+impl Drop for BackupMem {
+    fn drop(&mut self) {
+        // Flush on exit is performed for all policies, to make sure that
+        // no pending modification is lost.
+        self.flush_or_log();
+    }
+}
+
+/// Atomically replace the contents of a file: `data` is written to a
+/// temporary file in the same directory, which is then renamed over `path`.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp = path.to_owned().into_os_string();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    {
+        let mut f = File::create(&tmp)?;
+        f.write_all(data)?;
+        f.sync_all()?;
+    }
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::o;
+    use std::env;
+    use std::io::Read;
+
+    fn tmppath(name: &str) -> PathBuf {
+        let mut path = env::temp_dir();
+        path.push(format!("emu-backup-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, o!())
+    }
+
+    fn read(path: &Path) -> Vec<u8> {
+        let mut contents = Vec::new();
+        File::open(path)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    #[test]
+    fn policy_from_str() {
+        use self::FlushPolicy::*;
+        assert_eq!(
+            "write-through".parse::<FlushPolicy>().unwrap(),
+            WriteThrough
+        );
+        assert_eq!("on-exit".parse::<FlushPolicy>().unwrap(), OnExit);
+        assert_eq!("interval:30".parse::<FlushPolicy>().unwrap(), Interval(30));
+        assert!("interval:x".parse::<FlushPolicy>().is_err());
+        assert!("always".parse::<FlushPolicy>().is_err());
+    }
+
+    #[test]
+    fn write_through() {
+        let path = tmppath("wt");
+        let mut mem = BackupMem::new(
+            logger(),
+            Some(path.as_path()),
+            16,
+            0xFF,
+            FlushPolicy::WriteThrough,
+        )
+        .unwrap();
+        assert!(!path.exists());
+
+        mem.write(4, &[1, 2, 3, 4]);
+        let contents = read(&path);
+        assert_eq!(contents.len(), 16);
+        assert_eq!(&contents[3..9], &[0xFF, 1, 2, 3, 4, 0xFF]);
+        drop(mem);
+
+        // Reload the contents from disk
+        let mem = BackupMem::new(
+            logger(),
+            Some(path.as_path()),
+            16,
+            0x00,
+            FlushPolicy::OnExit,
+        )
+        .unwrap();
+        assert_eq!(&mem.data()[3..9], &[0xFF, 1, 2, 3, 4, 0xFF]);
+        assert!(!mem.is_dirty());
+        drop(mem);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn interval() {
+        let path = tmppath("int");
+        let mut mem = BackupMem::new(
+            logger(),
+            Some(path.as_path()),
+            8,
+            0,
+            FlushPolicy::Interval(3),
+        )
+        .unwrap();
+
+        mem.write(0, &[1]);
+        mem.end_frame().unwrap();
+        mem.end_frame().unwrap();
+        assert!(!path.exists());
+        mem.end_frame().unwrap();
+        assert_eq!(read(&path)[0], 1);
+
+        mem.data_mut()[1] = 2;
+        mem.written();
+        assert_eq!(read(&path)[1], 0);
+        assert!(mem.is_dirty());
+        drop(mem);
+        assert_eq!(read(&path)[1], 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn atomic_replace() {
+        let path = tmppath("atomic");
+        fs::write(&path, &[0xAA; 4]).unwrap();
+        let mut mem =
+            BackupMem::new(logger(), Some(path.as_path()), 8, 0, FlushPolicy::OnExit).unwrap();
+        // The file was shorter than the memory: the rest is filled, and the
+        // file is rewritten at the next flush.
+        assert_eq!(mem.data(), &[0xAA, 0xAA, 0xAA, 0xAA, 0, 0, 0, 0]);
+        assert!(mem.is_dirty());
+
+        mem.write(6, &[7]);
+        mem.flush().unwrap();
+        assert_eq!(read(&path), &[0xAA, 0xAA, 0xAA, 0xAA, 0, 0, 7, 0]);
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        assert!(!Path::new(&tmp).exists());
+        drop(mem);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn no_path() {
+        let mut mem = BackupMem::new(logger(), None, 8, 0xFF, FlushPolicy::WriteThrough).unwrap();
+        mem.write(0, &[1, 2]);
+        assert!(!mem.is_dirty());
+        assert_eq!(&mem.data()[..3], &[1, 2, 0xFF]);
+    }
+}
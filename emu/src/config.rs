@@ -0,0 +1,147 @@
+//! Persistent configuration of the emulator.
+//!
+//! The configuration is stored in a single TOML file (by default, in the
+//! platform config directory), divided into sections. Each module that needs
+//! to persist some settings defines its own section type, implementing
+//! [`ConfigSection`](trait.ConfigSection.html), and reads or writes it
+//! through [`Config`](struct.Config.html). Sections unknown to the running
+//! code are preserved when the file is saved.
+use directories::ProjectDirs;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use toml::value::{Table, Value};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A section of the configuration file, holding the settings of a module.
+/// Fields missing from the file should fallback to their default value
+/// (eg: through `#[serde(default)]`), so that the file remains compatible
+/// when new settings are added.
+pub trait ConfigSection: Serialize + DeserializeOwned + Default {
+    /// Name of the section (TOML table) in the configuration file.
+    const NAME: &'static str;
+}
+
+/// The configuration file, with all its sections. It is loaded once at
+/// startup, and saved on exit.
+#[derive(Default)]
+pub struct Config {
+    path: PathBuf,
+    root: Table,
+}
+
+impl Config {
+    /// Return the default path of the configuration file of an application:
+    /// `<app>.toml` in the platform config directory (eg: ~/.config/<app>
+    /// on Linux). Returns None if no home directory is available.
+    pub fn default_path(app: &str) -> Option<PathBuf> {
+        ProjectDirs::from("", "", app).map(|d| d.config_dir().join(format!("{}.toml", app)))
+    }
+
+    /// Load the configuration from the specified file. If the file does not
+    /// exist, the configuration is empty (all sections have their default
+    /// values), and the file is created on first save.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let root = if path.exists() {
+            let text = fs::read_to_string(path)
+                .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+            toml::from_str(&text).map_err(|e| format!("cannot parse {}: {}", path.display(), e))?
+        } else {
+            Table::new()
+        };
+        Ok(Config {
+            path: path.to_owned(),
+            root,
+        })
+    }
+
+    /// Path of the configuration file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the current value of a section, or its default value if the
+    /// section is missing.
+    pub fn get<S: ConfigSection>(&self) -> Result<S, String> {
+        match self.root.get(S::NAME) {
+            Some(v) => v
+                .clone()
+                .try_into()
+                .map_err(|e| format!("invalid config section [{}]: {}", S::NAME, e)),
+            None => Ok(S::default()),
+        }
+    }
+
+    /// Update the value of a section. The file is not saved until
+    /// [`save`](#method.save) is called.
+    pub fn set<S: ConfigSection>(&mut self, section: &S) -> Result<(), String> {
+        let v = Value::try_from(section)
+            .map_err(|e| format!("cannot serialize config section [{}]: {}", S::NAME, e))?;
+        self.root.insert(S::NAME.to_owned(), v);
+        Ok(())
+    }
+
+    /// Save the configuration file, creating its directory if needed.
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)
+                    .map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+            }
+        }
+        let text = toml::to_string(&self.root).map_err(|e| e.to_string())?;
+        fs::write(&self.path, text)
+            .map_err(|e| format!("cannot write {}: {}", self.path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Default, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(default)]
+    struct Window {
+        width: u32,
+        title: String,
+    }
+
+    impl ConfigSection for Window {
+        const NAME: &'static str = "window";
+    }
+
+    #[test]
+    fn missing_section_is_default() {
+        let cfg = Config::default();
+        assert_eq!(cfg.get::<Window>().unwrap(), Window::default());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("emu-config-{}.toml", std::process::id()));
+        let mut cfg = Config::load(&path).unwrap();
+        let win = Window {
+            width: 640,
+            title: "test".into(),
+        };
+        cfg.set(&win).unwrap();
+        cfg.save().unwrap();
+
+        let cfg = Config::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(cfg.get::<Window>().unwrap(), win);
+    }
+
+    #[test]
+    fn missing_fields_are_default() {
+        let cfg = Config {
+            path: PathBuf::new(),
+            root: toml::from_str("[window]\nwidth = 320\n").unwrap(),
+        };
+        let win = cfg.get::<Window>().unwrap();
+        assert_eq!(win.width, 320);
+        assert_eq!(win.title, "");
+    }
+}
@@ -0,0 +1,428 @@
+use super::tracer::Debugger;
+use serde_derive::{Deserialize, Serialize};
+use slog::info;
+
+use std::cell::Cell;
+use std::cmp::Ordering;
+
+#[cfg(feature = "frontend")]
+mod ui;
+#[cfg(feature = "frontend")]
+pub use self::ui::*;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Operand {
+    Reg(String),
+    Imm(u64),
+}
+
+impl Operand {
+    fn eval(&self, regs: &dyn Fn(&str) -> Option<u64>) -> Option<u64> {
+        match self {
+            Operand::Reg(name) => regs(name),
+            Operand::Imm(val) => Some(*val),
+        }
+    }
+}
+
+/// A condition attached to a breakpoint, that is evaluated against the
+/// register values of the CPU each time the breakpoint is reached.
+///
+/// The syntax is a list of comparisons (`==`, `!=`, `<`, `<=`, `>`, `>=`)
+/// between register names and integer literals (decimal, or hexadecimal with
+/// a `0x` prefix), combined with `&&` and `||` (where `&&` has higher
+/// precedence). For instance: `a0 == 0x8000 && v0 != 0`. Comparisons that
+/// refer to a register which is unknown to the CPU always evaluate to false.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub(crate) struct BreakpointCondition {
+    // Disjunction of conjunctions of comparisons
+    any: Vec<Vec<(Operand, CmpOp, Operand)>>,
+}
+
+impl BreakpointCondition {
+    pub(crate) fn parse(s: &str) -> std::result::Result<Self, String> {
+        let tokens = tokenize(s)?;
+        let mut tokens = tokens.iter().map(|t| t.as_str());
+
+        let mut any = Vec::new();
+        let mut all = Vec::new();
+        loop {
+            let lhs = parse_operand(tokens.next())?;
+            let op = match tokens.next() {
+                Some("==") => CmpOp::Eq,
+                Some("!=") => CmpOp::Ne,
+                Some(">") => CmpOp::Gt,
+                Some(">=") => CmpOp::Ge,
+                Some("<") => CmpOp::Lt,
+                Some("<=") => CmpOp::Le,
+                Some(tok) => return Err(format!("expected comparison, found: {}", tok)),
+                None => return Err("unexpected end of condition".into()),
+            };
+            let rhs = parse_operand(tokens.next())?;
+            all.push((lhs, op, rhs));
+
+            match tokens.next() {
+                Some("&&") => {}
+                Some("||") => any.push(std::mem::replace(&mut all, Vec::new())),
+                Some(tok) => return Err(format!("expected && or ||, found: {}", tok)),
+                None => break,
+            }
+        }
+        any.push(all);
+        Ok(Self { any })
+    }
+
+    pub(crate) fn check(&self, regs: &dyn Fn(&str) -> Option<u64>) -> bool {
+        self.any.iter().any(|all| {
+            all.iter()
+                .all(|(lhs, op, rhs)| match (lhs.eval(regs), rhs.eval(regs)) {
+                    (Some(lhs), Some(rhs)) => match op {
+                        CmpOp::Eq => lhs == rhs,
+                        CmpOp::Ne => lhs != rhs,
+                        CmpOp::Gt => lhs > rhs,
+                        CmpOp::Ge => lhs >= rhs,
+                        CmpOp::Lt => lhs < rhs,
+                        CmpOp::Le => lhs <= rhs,
+                    },
+                    _ => false,
+                })
+        })
+    }
+}
+
+fn parse_operand(tok: Option<&str>) -> std::result::Result<Operand, String> {
+    let tok = match tok {
+        Some(tok) => tok,
+        None => return Err("unexpected end of condition".into()),
+    };
+    let c = tok.chars().next().unwrap();
+    if c.is_ascii_digit() {
+        let val = if tok.starts_with("0x") || tok.starts_with("0X") {
+            u64::from_str_radix(&tok[2..], 16)
+        } else {
+            tok.parse()
+        };
+        val.map(Operand::Imm)
+            .map_err(|_| format!("invalid number: {}", tok))
+    } else if c.is_ascii_alphabetic() || c == '_' || c == '$' {
+        Ok(Operand::Reg(tok.trim_start_matches('$').to_lowercase()))
+    } else {
+        Err(format!("expected register or number, found: {}", tok))
+    }
+}
+
+// A piece of the message logged by a tracepoint.
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum MsgPart {
+    Text(String),
+    Reg(String, bool), // Register name, and whether to show it in hex
+}
+
+/// The message logged by a tracepoint each time it is hit, instead of
+/// stopping the emulation.
+///
+/// Register values are interpolated with `{reg}` (decimal) or `{reg:x}`
+/// (hexadecimal), for instance: `memcpy({a0:x}, {a1:x}, {a2})`. Literal
+/// braces are written as `{{` and `}}`. Registers which are unknown to the
+/// CPU are shown as `?`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub(crate) struct TraceMessage {
+    parts: Vec<MsgPart>,
+}
+
+impl TraceMessage {
+    pub(crate) fn parse(s: &str) -> std::result::Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => field.push(c),
+                            None => return Err("unterminated {".into()),
+                        }
+                    }
+                    let mut field = field.splitn(2, ':');
+                    let name = field.next().unwrap().trim().trim_start_matches('$');
+                    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+                        return Err(format!("invalid register name: {:?}", name));
+                    }
+                    let hex = match field.next().map(|f| f.trim()) {
+                        None | Some("") => false,
+                        Some("x") => true,
+                        Some(f) => return Err(format!("invalid format: {:?} (only x)", f)),
+                    };
+                    if !text.is_empty() {
+                        parts.push(MsgPart::Text(std::mem::replace(&mut text, String::new())));
+                    }
+                    parts.push(MsgPart::Reg(name.to_lowercase(), hex));
+                }
+                '}' => return Err("unmatched }".into()),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(MsgPart::Text(text));
+        }
+        Ok(Self { parts })
+    }
+
+    pub(crate) fn format(&self, regs: &dyn Fn(&str) -> Option<u64>) -> String {
+        let mut msg = String::new();
+        for part in self.parts.iter() {
+            match part {
+                MsgPart::Text(text) => msg.push_str(text),
+                MsgPart::Reg(name, hex) => match regs(name) {
+                    Some(val) if *hex => msg.push_str(&format!("{:x}", val)),
+                    Some(val) => msg.push_str(&val.to_string()),
+                    None => msg.push('?'),
+                },
+            }
+        }
+        msg
+    }
+}
+
+fn tokenize(s: &str) -> std::result::Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = s.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '$' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+            {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else {
+            let two: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            match two.as_str() {
+                "==" | "!=" | ">=" | "<=" | "&&" | "||" => {
+                    tokens.push(two);
+                    i += 2;
+                }
+                _ if c == '<' || c == '>' => {
+                    tokens.push(c.to_string());
+                    i += 1;
+                }
+                _ => return Err(format!("unexpected character: {}", c)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Eq, Serialize, Deserialize)]
+pub(crate) struct Breakpoint {
+    pub(super) active: bool,
+    pub(super) pc: u64,
+    pub(super) description: String,
+    #[serde(default)]
+    pub(super) condition: String, // Empty means "always"
+    #[serde(default)]
+    pub(super) message: String, // Non-empty for tracepoints (see TraceMessage)
+
+    #[serde(skip)]
+    pub(super) parsed: Option<BreakpointCondition>,
+    #[serde(skip)]
+    pub(super) parsed_msg: Option<TraceMessage>,
+    #[serde(skip)]
+    pub(super) hits: Cell<u64>,
+}
+
+impl Breakpoint {
+    pub(super) fn new(pc: u64, description: &str) -> Self {
+        Self {
+            active: true,
+            pc,
+            description: description.to_owned(),
+            condition: String::new(),
+            message: String::new(),
+            parsed: None,
+            parsed_msg: None,
+            hits: Cell::new(0),
+        }
+    }
+
+    /// Change the condition of the breakpoint. If the condition cannot be
+    /// parsed, the breakpoint is left unchanged.
+    pub(super) fn set_condition(&mut self, cond: &str) -> std::result::Result<(), String> {
+        let cond = cond.trim();
+        self.parsed = if cond.is_empty() {
+            None
+        } else {
+            Some(BreakpointCondition::parse(cond)?)
+        };
+        self.condition = cond.to_owned();
+        Ok(())
+    }
+
+    /// Change the message logged when the breakpoint is hit. A non-empty
+    /// message turns the breakpoint into a tracepoint, which doesn't stop
+    /// the emulation. If the message cannot be parsed, the breakpoint is
+    /// left unchanged.
+    pub(super) fn set_message(&mut self, msg: &str) -> std::result::Result<(), String> {
+        self.parsed_msg = if msg.is_empty() {
+            None
+        } else {
+            Some(TraceMessage::parse(msg)?)
+        };
+        self.message = msg.to_owned();
+        Ok(())
+    }
+
+    pub(super) fn is_tracepoint(&self) -> bool {
+        self.parsed_msg.is_some()
+    }
+
+    // Called by the tracer when the breakpoint PC is reached. If the
+    // condition is satisfied, the hit count is updated and, for tracepoints,
+    // the message is logged. Returns true if the emulation must be stopped.
+    pub(super) fn hit(&self, regs: &dyn Fn(&str) -> Option<u64>, logger: &slog::Logger) -> bool {
+        if !self.parsed.as_ref().map_or(true, |c| c.check(regs)) {
+            return false;
+        }
+        self.hits.set(self.hits.get() + 1);
+        match self.parsed_msg {
+            Some(ref msg) => {
+                info!(logger, "{}", msg.format(regs); "tracepoint" => &self.description);
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+impl Ord for Breakpoint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.pc.cmp(&other.pc)
+    }
+}
+
+impl PartialOrd for Breakpoint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Breakpoint {
+    fn eq(&self, other: &Self) -> bool {
+        self.pc == other.pc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regs(name: &str) -> Option<u64> {
+        match name {
+            "a0" => Some(0x8000),
+            "v0" => Some(0),
+            "sp" => Some(0x8030_0000),
+            _ => None,
+        }
+    }
+
+    fn check(cond: &str) -> bool {
+        BreakpointCondition::parse(cond).unwrap().check(&regs)
+    }
+
+    #[test]
+    fn condition_eval() {
+        assert_eq!(check("a0 == 0x8000"), true);
+        assert_eq!(check("a0==32768"), true);
+        assert_eq!(check("$A0 != 0x8000"), false);
+        assert_eq!(check("v0 < a0"), true);
+        assert_eq!(check("sp >= 0x80300000 && v0 > 0"), false);
+        assert_eq!(check("sp >= 0x80300000 && v0 > 0 || a0 <= 0x8000"), true);
+        assert_eq!(check("t0 == 0 || t0 != 0"), false);
+    }
+
+    #[test]
+    fn condition_errors() {
+        assert!(BreakpointCondition::parse("").is_err());
+        assert!(BreakpointCondition::parse("a0").is_err());
+        assert!(BreakpointCondition::parse("a0 = 1").is_err());
+        assert!(BreakpointCondition::parse("a0 == 0xZZ").is_err());
+        assert!(BreakpointCondition::parse("a0 == 1 &&").is_err());
+        assert!(BreakpointCondition::parse("a0 == 1 v0").is_err());
+    }
+
+    fn logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    #[test]
+    fn hit_count() {
+        let logger = logger();
+        let mut bp = Breakpoint::new(0x8000_0400, "test");
+        assert!(bp.hit(&regs, &logger));
+        assert!(bp.set_condition("a0 == 1 ||").is_err());
+        assert_eq!(bp.condition, "");
+        bp.set_condition("a0 == 1").unwrap();
+        assert!(!bp.hit(&regs, &logger));
+        bp.set_condition(" a0 == 0x8000 ").unwrap();
+        assert_eq!(bp.condition, "a0 == 0x8000");
+        assert!(bp.hit(&regs, &logger));
+        assert_eq!(bp.hits.get(), 2);
+    }
+
+    #[test]
+    fn trace_message() {
+        let fmt = |s: &str| TraceMessage::parse(s).unwrap().format(&regs);
+        assert_eq!(fmt("a0={a0:x} v0={v0}"), "a0=8000 v0=0");
+        assert_eq!(fmt("{$A0}"), "32768");
+        assert_eq!(fmt("{{sp}} = {sp:x}, t0 = {t0}"), "{sp} = 80300000, t0 = ?");
+        assert_eq!(fmt("no registers"), "no registers");
+
+        assert!(TraceMessage::parse("{a0").is_err());
+        assert!(TraceMessage::parse("a0}").is_err());
+        assert!(TraceMessage::parse("{}").is_err());
+        assert!(TraceMessage::parse("{a0:d}").is_err());
+    }
+
+    #[test]
+    fn tracepoint() {
+        let logger = logger();
+        let mut bp = Breakpoint::new(0x8000_0400, "test");
+        assert!(bp.set_message("{a0").is_err());
+        assert!(!bp.is_tracepoint());
+        bp.set_message("a0={a0:x}").unwrap();
+        assert!(bp.is_tracepoint());
+        assert!(!bp.hit(&regs, &logger));
+        bp.set_condition("a0 == 1").unwrap();
+        assert!(!bp.hit(&regs, &logger));
+        assert_eq!(bp.hits.get(), 1);
+        bp.set_message("").unwrap();
+        bp.set_condition("").unwrap();
+        assert!(bp.hit(&regs, &logger));
+    }
+}
@@ -0,0 +1,191 @@
+use super::*;
+use crate::dbg::uisupport::imgui_input_hex;
+use crate::dbg::{Dock, UiCtx};
+use imgui::*;
+
+impl Debugger {
+    fn render_new_breakpoint(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx, cpu_name: &str) {
+        let cpu = self.cpus.get_mut(cpu_name).unwrap();
+        let popup_name = im_str!("##bp#new#{}", cpu_name);
+
+        ui.popup(&popup_name, || {
+            ui.text(im_str!("PC:"));
+            ui.same_line(80.0);
+            imgui_input_hex(ui, im_str!("###bp#new_pc"), &mut ctx.new_bp_pc, false);
+
+            ui.text(im_str!("Desc:"));
+            ui.same_line(80.0);
+            ui.input_text(im_str!("###bp#new_desc"), &mut ctx.new_bp_desc)
+                .auto_select_all(true)
+                .build();
+
+            ui.text(im_str!("Condition:"));
+            ui.same_line(80.0);
+            ui.input_text(im_str!("###bp#new_cond"), &mut ctx.new_bp_cond)
+                .build();
+
+            ui.text(im_str!("Log:"));
+            ui.same_line(80.0);
+            ui.input_text(im_str!("###bp#new_msg"), &mut ctx.new_bp_msg)
+                .build();
+            if ui.is_item_hovered() {
+                ui.tooltip_text(im_str!(
+                    "If set, log this message instead of stopping (eg: a0={{a0:x}} v0={{v0}})"
+                ));
+            }
+
+            if ui.button(im_str!("Add"), [40.0, 20.0]) {
+                let desc = ctx.new_bp_desc.to_str().to_owned();
+                let mut bp = Breakpoint::new(ctx.new_bp_pc, &desc);
+                let res = bp
+                    .set_condition(ctx.new_bp_cond.to_str())
+                    .map_err(|err| format!("Invalid condition: {}", err))
+                    .and_then(|_| {
+                        bp.set_message(ctx.new_bp_msg.to_str())
+                            .map_err(|err| format!("Invalid log message: {}", err))
+                    });
+                match res {
+                    Ok(()) => {
+                        cpu.add_breakpoint(bp);
+                        ui.close_current_popup();
+                    }
+                    Err(err) => ctx.error_msg = Some(err),
+                }
+            }
+        });
+        if ui.small_button(&im_str!("New BP##{}", cpu_name)) {
+            ctx.new_bp_pc = 0;
+            ctx.new_bp_desc = ImString::new("New breakpoint");
+            ctx.new_bp_cond = ImString::with_capacity(128);
+            ctx.new_bp_msg = ImString::with_capacity(128);
+            ui.open_popup(&popup_name);
+        }
+    }
+
+    fn render_cpu_breakpoints(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx, cpu_name: &str) {
+        self.render_new_breakpoint(ui, ctx, cpu_name);
+        ui.same_line(0.0);
+        let cpu = self.cpus.get_mut(cpu_name).unwrap();
+        if ui.small_button(&im_str!("Reset hits##{}", cpu_name)) {
+            for bp in cpu.breakpoints.iter() {
+                bp.hits.set(0);
+            }
+        }
+
+        let mut bp_changed = false;
+        let mut bp_delete = None;
+
+        ui.columns(7, &im_str!("##breakpoints#{}", cpu_name), true);
+        ui.set_column_offset(1, 30.0);
+        ui.set_column_offset(2, 110.0);
+        ui.set_column_offset(3, 170.0);
+        for (idx, bp) in cpu.breakpoints.iter_mut().enumerate() {
+            let name = im_str!("###breakpoints#{}#active#{}", cpu_name, idx);
+            if ui.checkbox(&name, &mut bp.active) {
+                // Changing activation requires update to fastmap
+                bp_changed = true;
+            }
+            ui.next_column();
+
+            let name = im_str!("###breakpoints#{}#pc#{}", cpu_name, idx);
+            if imgui_input_hex(ui, &name, &mut bp.pc, true) {
+                // Changing PC requires update to fastmap
+                bp_changed = true;
+            }
+            ui.next_column();
+
+            ui.text(im_str!("{}", bp.hits.get()));
+            ui.next_column();
+
+            let name = im_str!("###breakpoints#{}#desc#{}", cpu_name, idx);
+            let mut sdesc = ImString::new(bp.description.clone());
+            if ui
+                .input_text(&name, &mut sdesc)
+                .enter_returns_true(true)
+                .auto_select_all(true)
+                .build()
+            {
+                bp.description = sdesc.to_str().to_owned();
+            }
+            ui.next_column();
+
+            let name = im_str!("###breakpoints#{}#cond#{}", cpu_name, idx);
+            let mut scond = ImString::with_capacity(128);
+            scond.push_str(&bp.condition);
+            if ui
+                .input_text(&name, &mut scond)
+                .enter_returns_true(true)
+                .build()
+            {
+                if let Err(err) = bp.set_condition(scond.to_str()) {
+                    ctx.error_msg = Some(format!("Invalid condition: {}", err));
+                }
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text(im_str!(
+                    "Condition on registers (eg: a0 == 0x8000 && v0 != 0)"
+                ));
+            }
+            ui.next_column();
+
+            let name = im_str!("###breakpoints#{}#msg#{}", cpu_name, idx);
+            let mut smsg = ImString::with_capacity(128);
+            smsg.push_str(&bp.message);
+            if ui
+                .input_text(&name, &mut smsg)
+                .enter_returns_true(true)
+                .build()
+            {
+                if let Err(err) = bp.set_message(smsg.to_str()) {
+                    ctx.error_msg = Some(format!("Invalid log message: {}", err));
+                }
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text(im_str!(
+                    "Tracepoint: log this message instead of stopping (eg: a0={{a0:x}} v0={{v0}})"
+                ));
+            }
+            ui.next_column();
+
+            if ui.small_button(&im_str!("Delete##breakpoints#{}#{}", cpu_name, idx)) {
+                bp_delete = Some(idx);
+            }
+            ui.next_column();
+        }
+        ui.columns(1, im_str!(""), false);
+
+        // Refresh breakpoint hashmap if required
+        if let Some(idx) = bp_delete {
+            cpu.remove_breakpoint(idx);
+        } else if bp_changed {
+            cpu.update_bp_fastmap();
+        }
+    }
+
+    // Render the "Breakpoints" window, listing the breakpoints of all CPUs.
+    pub(crate) fn render_breakpoints(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx) {
+        let title = im_str!("Breakpoints");
+        if !ctx.layout.is_open(title) {
+            return;
+        }
+        let mut opened = true;
+        let window = Window::new(title).size([500.0, 300.0], Condition::FirstUseEver);
+        ctx.layout
+            .place(title, Dock::Right, window)
+            .opened(&mut opened)
+            .build(ui, || {
+                for idx in 0..ctx.cpus.len() {
+                    let cpu_name = ctx.cpus[idx].clone();
+                    let count = self.cpus[&cpu_name].breakpoints.len();
+                    if ui
+                        .collapsing_header(&im_str!("[{}] ({})###{}", cpu_name, count, cpu_name))
+                        .default_open(true)
+                        .build()
+                    {
+                        self.render_cpu_breakpoints(ui, ctx, &cpu_name);
+                    }
+                }
+            });
+        ctx.layout.set_open(title, opened);
+    }
+}
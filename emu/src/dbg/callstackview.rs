@@ -0,0 +1,126 @@
+use super::symbols::SymbolTable;
+use std::collections::VecDeque;
+
+#[cfg(feature = "frontend")]
+mod ui;
+#[cfg(feature = "frontend")]
+pub use self::ui::*;
+
+// Maximum number of frames kept in a call stack. Deeper frames are
+// discarded, as they're most likely caused by calls that never
+// returned (eg: longjmp or a thread switch).
+const MAX_DEPTH: usize = 256;
+
+/// A frame in the call stack of a CPU.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StackFrame {
+    /// PC of the call instruction.
+    pub call_pc: u64,
+    /// Address of the called function.
+    pub func: u64,
+    /// Address where the function is expected to return.
+    pub ret_pc: u64,
+    /// Stack pointer at the moment of the call (if the architecture has one).
+    pub sp: u64,
+}
+
+/// CallStack reconstructs the call stack of a CPU by following calls and
+/// returns as they are executed. CPU cores are responsible for notifying
+/// calls (eg: jump-and-link opcodes) and jumps that might be returns, and
+/// the call stack keeps track of which frames are still active.
+#[derive(Default)]
+pub struct CallStack {
+    frames: VecDeque<StackFrame>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a call to a function.
+    pub fn call(&mut self, call_pc: u64, func: u64, ret_pc: u64, sp: u64) {
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(StackFrame {
+            call_pc,
+            func,
+            ret_pc,
+            sp,
+        });
+    }
+
+    /// Register an indirect jump, that might be a return from one of the
+    /// functions in the call stack. If the target matches the return
+    /// address of a frame, all frames up to (and including) it are removed;
+    /// otherwise, the call stack is left untouched.
+    pub fn jump(&mut self, target: u64) {
+        if let Some(idx) = self.frames.iter().rposition(|f| f.ret_pc == target) {
+            self.frames.truncate(idx);
+        }
+    }
+
+    /// Remove all frames (eg: at reset).
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Return the frames, from the outermost to the innermost.
+    pub fn frames(&self) -> &VecDeque<StackFrame> {
+        &self.frames
+    }
+}
+
+/// A trait for an object that can display its call stack to a debugger view.
+pub trait CallstackView {
+    /// Return the name of this object. The name will be composed
+    /// as "\[NAME\] Call stack".
+    fn name(&self) -> &str;
+
+    /// Return the current program counter.
+    fn pc(&self) -> u64;
+
+    /// Return the current call stack.
+    fn callstack(&self) -> &CallStack;
+
+    /// Return the symbol table used to display function names, if any.
+    fn symbols(&self) -> Option<&SymbolTable> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call_and_return() {
+        let mut cs = CallStack::new();
+        cs.call(0x100, 0x400, 0x108, 0);
+        cs.call(0x410, 0x800, 0x418, 0);
+        cs.call(0x810, 0xC00, 0x818, 0);
+        assert_eq!(cs.frames().len(), 3);
+
+        // A jump that is not a return leaves the stack untouched
+        cs.jump(0x1234);
+        assert_eq!(cs.frames().len(), 3);
+
+        cs.jump(0x818);
+        assert_eq!(cs.frames().len(), 2);
+
+        // Returning to an outer frame unwinds the inner ones
+        cs.jump(0x108);
+        assert_eq!(cs.frames().len(), 0);
+    }
+
+    #[test]
+    fn max_depth() {
+        let mut cs = CallStack::new();
+        for i in 0..MAX_DEPTH as u64 + 10 {
+            cs.call(i * 4, 0x1000, i * 4 + 8, 0);
+        }
+        assert_eq!(cs.frames().len(), MAX_DEPTH);
+        assert_eq!(cs.frames()[0].call_pc, 10 * 4);
+    }
+}
@@ -0,0 +1,74 @@
+use super::*;
+use crate::dbg::{Dock, UiCtx};
+use imgui::*;
+use std::time::Instant;
+
+pub(crate) fn render_callstackview<'a, 'ui, CV: CallstackView>(
+    ui: &'a Ui<'ui>,
+    ctx: &mut UiCtx,
+    v: &CV,
+) {
+    let cpu_name = v.name().to_owned();
+    let mut show_pc = None;
+
+    let title = im_str!("[{}] Call stack", cpu_name);
+    if !ctx.layout.is_open(&title) {
+        return;
+    }
+    let mut opened = true;
+    let window = Window::new(&title).size([340.0, 250.0], Condition::FirstUseEver);
+    ctx.layout
+        .place(&title, Dock::Right, window)
+        .opened(&mut opened)
+        .build(ui, || {
+            let frames = v.callstack().frames();
+            let syms = v.symbols();
+            let addr = |addr: u64| match syms.and_then(|s| s.label(addr)) {
+                Some(label) => format!("{:08x} <{}>", addr, label),
+                None => format!("{:08x}", addr),
+            };
+
+            ui.columns(4, im_str!("##callstack"), true);
+            for title in &["#", "Function", "PC", "Return to"] {
+                ui.text(title);
+                ui.next_column();
+            }
+            ui.separator();
+
+            // Innermost frame first. Each frame is displayed with the PC
+            // currently executing within it: the current PC for the
+            // innermost frame, and the call site for the others.
+            let mut pc = v.pc();
+            for (idx, f) in frames.iter().rev().enumerate() {
+                Selectable::new(&im_str!("{}##callstack#{}", idx, idx))
+                    .span_all_columns(true)
+                    .build(ui);
+                if ui.is_item_hovered() && ui.is_mouse_double_clicked(MouseButton::Left) {
+                    show_pc = Some(pc);
+                }
+                ui.next_column();
+                ui.text(addr(f.func));
+                ui.next_column();
+                ui.text(addr(pc));
+                ui.next_column();
+                ui.text(addr(f.ret_pc));
+                ui.next_column();
+                pc = f.call_pc;
+            }
+            ui.columns(1, im_str!(""), false);
+
+            if frames.is_empty() {
+                ui.text_disabled("No calls tracked yet");
+            }
+        });
+    ctx.layout.set_open(&title, opened);
+
+    // Double-clicking a frame jumps the disassembly view to it.
+    if let Some(pc) = show_pc {
+        if let Some(dctx) = ctx.disasm.get_mut(&cpu_name) {
+            dctx.force_pc = Some(pc);
+            dctx.cursor_pc = Some(pc);
+            dctx.blink_pc = Some((pc, Instant::now()));
+        }
+    }
+}
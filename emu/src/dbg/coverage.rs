@@ -0,0 +1,148 @@
+use super::tracer::IntHashMap;
+
+use std::fs;
+use std::path::Path;
+
+#[cfg(feature = "frontend")]
+mod ui;
+
+// Each page of the coverage bitmap tracks 1024 instructions (4 KiB of code),
+// one bit per instruction.
+const PAGE_SHIFT: u64 = 12;
+const PAGE_WORDS: usize = 16;
+
+type CpuCoverage = IntHashMap<u64, [u64; PAGE_WORDS]>; // page -> bitmap
+
+/// Coverage records which instructions were executed by each CPU, at least
+/// once, since it was enabled. Covered instructions are highlighted in the
+/// disassembly view, and the coverage can be exported as a sorted list of
+/// PCs, to diff the code run by different sessions (eg: to find the code
+/// involved in a specific game feature).
+#[derive(Default)]
+pub(crate) struct Coverage {
+    enabled: bool,
+    cpus: Vec<(String, CpuCoverage)>,
+}
+
+fn bit(pc: u64) -> (u64, usize, u64) {
+    let idx = (pc >> 2) & ((1 << (PAGE_SHIFT - 2)) - 1);
+    (pc >> PAGE_SHIFT, (idx / 64) as usize, 1 << (idx % 64))
+}
+
+impl Coverage {
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn cpu(&mut self, cpu_name: &str) -> &mut CpuCoverage {
+        let idx = match self.cpus.iter().position(|(name, _)| name == cpu_name) {
+            Some(idx) => idx,
+            None => {
+                self.cpus
+                    .push((cpu_name.to_owned(), CpuCoverage::default()));
+                self.cpus.len() - 1
+            }
+        };
+        &mut self.cpus[idx].1
+    }
+
+    pub(crate) fn cover_insn(&mut self, cpu_name: &str, pc: u64) {
+        let (page, word, mask) = bit(pc);
+        self.cpu(cpu_name).entry(page).or_insert([0; PAGE_WORDS])[word] |= mask;
+    }
+
+    pub(crate) fn is_covered(&self, cpu_name: &str, pc: u64) -> bool {
+        let (page, word, mask) = bit(pc);
+        self.cpus
+            .iter()
+            .find(|(name, _)| name == cpu_name)
+            .and_then(|(_, cpu)| cpu.get(&page))
+            .map_or(false, |bitmap| bitmap[word] & mask != 0)
+    }
+
+    // Sorted list of the PCs covered by a CPU.
+    fn pcs(cpu: &CpuCoverage) -> Vec<u64> {
+        let mut pages: Vec<_> = cpu.iter().collect();
+        pages.sort_by_key(|(page, _)| **page);
+        let mut pcs = Vec::new();
+        for (page, bitmap) in pages {
+            for (word, bits) in bitmap.iter().enumerate() {
+                for b in 0..64 {
+                    if bits & (1 << b) != 0 {
+                        let idx = (word * 64 + b) as u64;
+                        pcs.push((page << PAGE_SHIFT) | (idx << 2));
+                    }
+                }
+            }
+        }
+        pcs
+    }
+
+    #[cfg_attr(not(feature = "frontend"), allow(dead_code))]
+    fn count(&self, cpu_name: &str) -> usize {
+        self.cpus
+            .iter()
+            .filter(|(name, _)| name == cpu_name)
+            .flat_map(|(_, cpu)| cpu.values())
+            .flat_map(|bitmap| bitmap.iter())
+            .map(|bits| bits.count_ones() as usize)
+            .sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.cpus.iter().all(|(_, cpu)| cpu.is_empty())
+    }
+
+    fn clear(&mut self) {
+        self.cpus.clear();
+    }
+
+    /// Export the coverage as text, with a line for each covered instruction
+    /// (CPU name and PC), sorted by CPU and PC. Nothing is written if no
+    /// instruction was covered.
+    pub(crate) fn save(&self, path: &Path) -> std::io::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let mut cpus: Vec<_> = self.cpus.iter().collect();
+        cpus.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut text = String::new();
+        for (name, cpu) in cpus {
+            for pc in Self::pcs(cpu) {
+                text += &format!("{} {:08x}\n", name, pc);
+            }
+        }
+        fs::write(path, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cover() {
+        let mut cov = Coverage::default();
+        cov.cover_insn("cpu", 0x0000_0400);
+        cov.cover_insn("cpu", 0x0000_0404);
+        cov.cover_insn("cpu", 0x0000_13fc);
+        cov.cover_insn("cpu", 0x0000_0400);
+        cov.cover_insn("rsp", 0x0000_1000);
+
+        assert!(cov.is_covered("cpu", 0x0000_0400));
+        assert!(cov.is_covered("cpu", 0x0000_13fc));
+        assert!(!cov.is_covered("cpu", 0x0000_0408));
+        assert!(!cov.is_covered("cpu", 0x0000_1000));
+        assert!(cov.is_covered("rsp", 0x0000_1000));
+        assert!(!cov.is_covered("vr4300", 0x0000_0400));
+        assert_eq!(cov.count("cpu"), 3);
+        assert_eq!(
+            Coverage::pcs(&cov.cpus[0].1),
+            vec![0x0000_0400, 0x0000_0404, 0x0000_13fc]
+        );
+
+        cov.clear();
+        assert!(cov.is_empty());
+        assert!(!cov.is_covered("cpu", 0x0000_0400));
+    }
+}
@@ -0,0 +1,29 @@
+use super::*;
+use imgui::*;
+use tinyfiledialogs::save_file_dialog_with_filter;
+
+impl Coverage {
+    // Render the controls of the coverage of a CPU (within the disassembly
+    // view). Returns an error message if the coverage could not be exported.
+    pub(crate) fn render_controls(&mut self, ui: &Ui, cpu_name: &str) -> Option<String> {
+        ui.checkbox(im_str!("Track executed instructions"), &mut self.enabled);
+        ui.text(format!("{} instructions covered", self.count(cpu_name)));
+        if ui.small_button(im_str!("Clear")) {
+            self.clear();
+        }
+        ui.same_line(0.0);
+        if ui.small_button(im_str!("Export...")) {
+            if let Some(path) = save_file_dialog_with_filter(
+                "Export coverage",
+                "coverage.txt",
+                &vec!["*.txt"],
+                "Text files",
+            ) {
+                if let Err(err) = self.save(Path::new(&path)) {
+                    return Some(format!("Cannot export coverage: {}", err));
+                }
+            }
+        }
+        None
+    }
+}
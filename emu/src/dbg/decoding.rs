@@ -1,4 +1,6 @@
 /// Helper classes to write a CPU decoder / disassembler.
+use super::symbols::SymbolTable;
+use bitflags::bitflags;
 use runtime_fmt::rt_format_args;
 
 use std::fmt;
@@ -46,6 +48,21 @@ impl fmt::Display for Operand {
     }
 }
 
+// An operand being displayed, possibly resolved to a symbol.
+struct DisplayOperand<'a> {
+    op: &'a Operand,
+    label: Option<String>,
+}
+
+impl<'a> fmt::Display for DisplayOperand<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.label {
+            Some(ref label) => write!(f, "{}", label),
+            None => self.op.fmt(f),
+        }
+    }
+}
+
 impl Operand {
     pub fn is_hidden(self) -> bool {
         use self::Operand::*;
@@ -72,6 +89,19 @@ impl Operand {
     }
 }
 
+bitflags! {
+    /// Properties of a decoded instruction, used by the debugger views (eg:
+    /// to annotate the disassembly, or to classify profiled instructions).
+    #[derive(Default)]
+    pub struct InsnFlags: u8 {
+        const BRANCH     = 0b00000001; // Branch or jump (including calls/returns)
+        const DELAY_SLOT = 0b00000010; // Followed by a branch delay slot
+        const LIKELY     = 0b00000100; // Delay slot only executed if taken
+        const LOAD       = 0b00001000; // Memory load
+        const STORE      = 0b00010000; // Memory store
+    }
+}
+
 /// A decoded instruction, composed of an opcode and zero to several arguments.
 /// Both opcodes and operands are forced to be static strings, as it's a reasonable
 /// constraint for disassemblers. It would be useless to genericize this structure
@@ -81,11 +111,19 @@ impl Operand {
 ///
 /// fmt is the formatting pattern (in std::fmt format) used to represent the arguments.
 /// If None, the arguments will be displayed as comma-separated.
+///
+/// cycles is the nominal cost of the instruction (eg: as documented by the
+/// CPU manual, without memory stalls), and flags describes its properties;
+/// both are optional metadata that decoders can attach with
+/// [`with_cycles()`](#method.with_cycles) and
+/// [`with_flags()`](#method.with_flags).
 #[derive(Clone, PartialEq)]
 pub struct DecodedInsn {
     pub op: &'static str,
     pub fmt: Option<String>,
     pub args: [Operand; MAX_OPERANDS_PER_INSN],
+    pub cycles: u32,
+    pub flags: InsnFlags,
 }
 
 impl DecodedInsn {
@@ -103,6 +141,8 @@ impl DecodedInsn {
                 Operand::Null,
                 Operand::Null,
             ],
+            cycles: 1,
+            flags: InsnFlags::empty(),
         }
     }
     pub fn new1<I: Into<&'static str>>(op: I, arg1: Operand) -> Self {
@@ -219,15 +259,50 @@ impl DecodedInsn {
         self
     }
 
+    pub fn with_cycles(mut self: Self, cycles: u32) -> Self {
+        self.cycles = cycles;
+        self
+    }
+
+    pub fn with_flags(mut self: Self, flags: InsnFlags) -> Self {
+        self.flags |= flags;
+        self
+    }
+
     pub fn args(&self) -> impl Iterator<Item = &Operand> {
         self.args.iter().take_while(|o| *o != &Operand::Null)
     }
 
+    // Return the target address of a branch or jump instruction, if it is
+    // encoded in the instruction itself (eg: not for jumps through registers).
+    pub fn branch_target(&self) -> Option<u64> {
+        self.args().find_map(|op| match op {
+            Operand::Target(addr) => Some(*addr),
+            _ => None,
+        })
+    }
+
     // Return a string representation of the insn, which represents
     // the disassembled instruction.
     pub fn disasm(&self) -> String {
+        self.disasm_with_symbols(None)
+    }
+
+    // Like disasm(), but display branch targets as symbolic labels when
+    // they are found in the specified symbol table.
+    pub fn disasm_with_symbols(&self, syms: Option<&SymbolTable>) -> String {
         // Get all args which are not hidden
-        let args = self.args().filter(|o| !o.is_hidden()).collect::<Vec<_>>();
+        let args = self
+            .args()
+            .filter(|o| !o.is_hidden())
+            .map(|op| DisplayOperand {
+                op,
+                label: match (op, syms) {
+                    (Operand::Target(addr), Some(syms)) => syms.label(*addr),
+                    _ => None,
+                },
+            })
+            .collect::<Vec<_>>();
 
         if let Some(ref f) = self.fmt {
             // Custom formatting strings. Use rt_format
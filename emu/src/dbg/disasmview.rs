@@ -1,12 +1,13 @@
-use imgui::*;
-use imgui_sys;
-use sdl2::keyboard::Scancode;
+use super::decoding::{DecodedInsn, InsnFlags};
+use super::symbols::SymbolTable;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-use super::decoding::DecodedInsn;
-use super::uisupport::*;
-use super::{RegHighlight, TraceEvent, UiCommand, UiCtx};
-
-use std::time::Instant;
+#[cfg(feature = "frontend")]
+mod ui;
+#[cfg(feature = "frontend")]
+pub use self::ui::*;
 
 /// A trait for an object that can display register contents to
 /// a debugger view.
@@ -31,6 +32,46 @@ pub trait DisasmView {
     /// Disassemble a single instruction at the specified program counter;
     /// Returns the bytes composing the instruction and the string representation.
     fn disasm_block<Func: FnMut(u64, &[u8], &DecodedInsn)>(&self, pc_range: (u64, u64), f: Func);
+
+    /// Return the symbol table used to display function names and branch
+    /// targets, if any. Addresses in the table must be already masked
+    /// with pc_mask().
+    fn symbols(&self) -> Option<&SymbolTable> {
+        None
+    }
+
+    /// If the instruction at the specified PC is a subroutine call, return
+    /// the PC where execution continues once the subroutine returns. This is
+    /// used to implement "step over".
+    fn call_return_pc(&self, _pc: u64, _insn: &DecodedInsn) -> Option<u64> {
+        None
+    }
+
+    /// Return the PC where the current function will return, if known. This
+    /// is used to implement "step out".
+    fn return_pc(&self) -> Option<u64> {
+        None
+    }
+
+    /// Return the name and the current value of the stack pointer register,
+    /// if the architecture has one. "Step over" and "step out" use it to
+    /// avoid stopping within recursive invocations of the same function.
+    fn stack_pointer(&self) -> Option<(&'static str, u64)> {
+        None
+    }
+
+    /// Assemble a single instruction to be placed at the specified PC,
+    /// returning its bytes. This is used to patch instructions from the
+    /// disassembly view; by default, it is not supported.
+    fn assemble(&self, _pc: u64, _text: &str) -> Result<Vec<u8>, String> {
+        Err("assembler not available".into())
+    }
+
+    /// Overwrite memory at the specified PC with the bytes of a patched
+    /// instruction, returning the bytes that were overwritten.
+    fn patch(&mut self, _pc: u64, _bytes: &[u8]) -> Result<Vec<u8>, String> {
+        Err("patching not available".into())
+    }
 }
 
 struct ByteBuf<'a>(&'a [u8]);
@@ -44,306 +85,226 @@ impl<'a> std::fmt::LowerHex for ByteBuf<'a> {
     }
 }
 
-fn color(r: usize, g: usize, b: usize) -> [f32; 4] {
-    [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0]
+/// Export of a disassembly listing into a text file: one line per
+/// instruction with address, bytes, mnemonic and operands (branch targets
+/// are resolved through the symbol table), and a label before the first
+/// instruction of each function. Instructions in delay slots are indented,
+/// as in the disassembly view.
+///
+/// The range is exported in chunks through [`step()`](#method.step), so that
+/// progress can be reported while exporting large ranges.
+pub struct DisasmExport {
+    out: BufWriter<File>,
+    path: PathBuf,
+    range: (u64, u64),
+    next_pc: u64,
+    in_delay_slot: bool,
 }
 
-pub(crate) fn render_disasmview<'a, 'ui, DV: DisasmView>(
-    ui: &'a Ui<'ui>,
-    ctx: &mut UiCtx,
-    v: &mut DV,
-) {
-    let cpu_name = v.name().to_owned();
-    let cur_pc = v.pc();
-    let mut set_command: Option<UiCommand> = None;
-    let dctx = ctx.disasm.get_mut(&cpu_name).unwrap();
-
-    // If we were asked to show a certain PC, then also get focus
-    // as the user probably wants to see this window.
-    if dctx.force_pc.is_some() {
-        unsafe {
-            imgui_sys::igSetNextWindowFocus();
+impl DisasmExport {
+    // Bytes of instructions exported by each step.
+    const CHUNK_SIZE: u64 = 64 * 1024;
+
+    /// Create the output file, to export the instructions in the specified
+    /// PC range (end excluded). PCs must be already masked with pc_mask().
+    pub fn create(path: &Path, range: (u64, u64)) -> io::Result<DisasmExport> {
+        if range.0 >= range.1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid range: {:x}-{:x}", range.0, range.1),
+            ));
         }
+        Ok(DisasmExport {
+            out: BufWriter::new(File::create(path)?),
+            path: path.to_owned(),
+            range,
+            next_pc: range.0,
+            in_delay_slot: false,
+        })
     }
 
-    // Process current event (if any)
-    match ctx.event {
-        Some((ref evt, _)) => match **evt {
-            TraceEvent::Breakpoint(ref bp_cpu_name, _, bp_pc) if *bp_cpu_name == cpu_name => {
-                // Center breakpoint PC
-                dctx.force_pc = Some(bp_pc);
-
-                // Focus this window
-                unsafe {
-                    imgui_sys::igSetNextWindowFocus();
-                }
-
-                dctx.cursor_pc = None;
-
-                // Start blinking effect
-                dctx.blink_pc = Some((bp_pc, Instant::now()));
-            }
-            TraceEvent::WatchpointRead(ref bp_cpu_name, _)
-            | TraceEvent::WatchpointWrite(ref bp_cpu_name, _)
-                if *bp_cpu_name == cpu_name =>
-            {
-                // Center breakpoint PC
-                dctx.force_pc = Some(cur_pc);
-
-                // Focus this window
-                unsafe {
-                    imgui_sys::igSetNextWindowFocus();
-                }
-
-                dctx.cursor_pc = None;
-
-                // Start blinking effect
-                dctx.blink_pc = Some((cur_pc, Instant::now()));
-            }
-            TraceEvent::BreakpointOneShot(ref bp_cpu_name, bp_pc) if *bp_cpu_name == cpu_name => {
-                // Center breakpoint PC
-                dctx.force_pc = Some(bp_pc);
-
-                // Focus this window
-                unsafe {
-                    imgui_sys::igSetNextWindowFocus();
-                }
-
-                dctx.blink_pc = None;
-                dctx.cursor_pc = None;
-            }
-            TraceEvent::Stepped() | TraceEvent::Paused() | TraceEvent::GenericBreak(_) => {
-                dctx.force_pc = Some(cur_pc);
-                dctx.blink_pc = None;
-                dctx.cursor_pc = None;
-            }
-            _ => {}
-        },
-        None => {}
-    };
-
-    Window::new(&im_str!("[{}] Disassembly", cpu_name))
-        .size([450.0, 400.0], Condition::FirstUseEver)
-        .build(ui, || {
-            let has_focus =
-                ui.is_window_focused_with_flags(WindowFocusedFlags::ROOT_AND_CHILD_WINDOWS);
-
-            // *******************************************
-            // Goto popup
-            // *******************************************
-            ui.popup(im_str!("###goto"), || {
-                let mut s = ImString::new("00000000");
-                ui.text(im_str!("Insert PC:"));
-                if ui
-                    .input_text(im_str!("###goto#input"), &mut s)
-                    .chars_hexadecimal(true)
-                    .enter_returns_true(true)
-                    .auto_select_all(true)
-                    .build()
-                {
-                    dctx.force_pc = u64::from_str_radix(s.as_ref(), 16).ok();
-                    ui.close_current_popup();
-                }
-            });
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
 
-            // *******************************************
-            // Cursor input
-            // *******************************************
-            if has_focus {
-                if ui.is_key_pressed(Scancode::Up as _) {
-                    let cpc = match dctx.cursor_pc {
-                        Some(cpc) => cpc - 4,
-                        None => cur_pc - 4,
-                    };
-                    dctx.cursor_pc = Some(cpc);
-                }
-                if ui.is_key_pressed(Scancode::Down as _) {
-                    let cpc = match dctx.cursor_pc {
-                        Some(cpc) => cpc + 4,
-                        None => cur_pc + 4,
-                    };
-                    dctx.cursor_pc = Some(cpc);
-                }
-            }
+    /// Fraction of the range exported so far, between 0 and 1.
+    pub fn progress(&self) -> f32 {
+        (self.next_pc - self.range.0) as f32 / (self.range.1 - self.range.0) as f32
+    }
 
-            // *******************************************
-            // Button toolbar
-            // *******************************************
-            if ui.small_button(im_str!("Goto")) {
-                ui.open_popup(im_str!("###goto"));
-            }
-            ui.same_line(0.0);
-            if ui.small_button(im_str!("Center"))
-                || (has_focus && ui.is_key_pressed(Scancode::C as _))
-            {
-                dctx.force_pc = Some(cur_pc);
-            }
-            ui.same_line(0.0);
-            if ui.small_button(im_str!("Step"))
-                || (has_focus && ui.is_key_pressed(Scancode::S as _))
-            {
-                set_command = Some(UiCommand::CpuStep(cpu_name.clone()));
+    /// Export the next chunk of instructions. Returns true once the whole
+    /// range has been exported, and the file flushed.
+    pub fn step<DV: DisasmView>(&mut self, v: &DV) -> io::Result<bool> {
+        let end = self
+            .next_pc
+            .saturating_add(Self::CHUNK_SIZE)
+            .min(self.range.1);
+        let syms = v.symbols();
+        let (out, in_delay_slot) = (&mut self.out, &mut self.in_delay_slot);
+        let mut res = Ok(());
+        v.disasm_block((self.next_pc, end), |pc, mem, insn| {
+            if res.is_ok() {
+                res = write_insn(out, pc, mem, insn, syms, *in_delay_slot);
             }
-            ui.same_line(0.0);
-            if ui.small_button(im_str!("Here"))
-                || (has_focus && ui.is_key_pressed(Scancode::Return as _))
-            {
-                if let Some(cpc) = dctx.cursor_pc {
-                    set_command = Some(UiCommand::BreakpointOneShot(cpu_name.clone(), cpc));
-                }
-            }
-            ui.separator();
-
-            // *******************************************
-            // Main scroll view with disasm
-            // *******************************************
-            ChildWindow::new(&im_str!("###scrolling"))
-                .size([0.0, 0.0])
-                .always_vertical_scrollbar(true)
-                .build(ui, || {
-                    // Get the full extent of PC. Notice that the range is *inclusive*.
-                    let mut pc_range = (v.pc_mask(u64::min_value()), v.pc_mask(u64::max_value()));
+            *in_delay_slot = insn.flags.contains(InsnFlags::DELAY_SLOT);
+        });
+        res?;
 
-                    // Calculate a range of PC that will be used in the disasm
-                    // view, that could be smaller than the full extent. We select
-                    // up to 1M lines around the current PC.
-                    // Notice that this is the full range of the listbox, not just
-                    // the display range.
-                    const MAX_LINES: u64 = 1024 * 1024;
-                    pc_range.0 =
-                        (cur_pc.saturating_sub(4 * MAX_LINES / 2) / 1024 * 1024).max(pc_range.0);
-                    pc_range.1 = pc_range.0.saturating_add(4 * MAX_LINES - 1).min(pc_range.1);
-                    let num_lines = (pc_range.1 - pc_range.0 + 1) / 4;
+        self.next_pc = end;
+        if self.next_pc < self.range.1 {
+            return Ok(false);
+        }
+        self.out.flush()?;
+        Ok(true)
+    }
+}
 
-                    // Check if we were asked to scroll to a specific PC.
-                    if let Some(force_pc) = dctx.force_pc {
-                        let size = ui.content_region_avail();
-                        let row_height = ui.text_line_height_with_spacing();
-                        let scroll_y = ui.scroll_y();
-                        let force_pc = v.pc_mask(force_pc);
+// Write the line of an instruction into an exported listing.
+fn write_insn<W: Write>(
+    out: &mut W,
+    pc: u64,
+    mem: &[u8],
+    insn: &DecodedInsn,
+    syms: Option<&SymbolTable>,
+    delay_slot: bool,
+) -> io::Result<()> {
+    if let Some(name) = syms.and_then(|s| s.lookup(pc)) {
+        writeln!(out, "\n{}:", name)?;
+    }
+    let dis = insn.disasm_with_symbols(syms);
+    let mut fields = dis.splitn(2, '\t');
+    let op = fields.next().unwrap();
+    let args = fields.next().unwrap_or("");
+    let indent = if delay_slot { " " } else { "" };
+    let line = format!(
+        "{:08x}:  {:10} {:10}{}",
+        pc,
+        format!("{:x}", ByteBuf(mem)),
+        format!("{}{}", indent, op),
+        args
+    );
+    writeln!(out, "{}", line.trim_end())
+}
 
-                        let first_pc = pc_range
-                            .0
-                            .saturating_add((scroll_y / row_height) as u64 * 4);
-                        let last_pc = first_pc.saturating_add((size[1] / row_height) as u64 * 4);
+#[cfg(test)]
+mod tests {
+    use super::super::decoding::Operand;
+    use super::*;
+    use byteorder::{BigEndian, ByteOrder};
+    use std::{env, fs};
+
+    // A toy architecture with 4-byte opcodes: JAL (with a delay slot),
+    // ADDIU, and NOP for all other words.
+    struct View {
+        program: Vec<(u64, u32)>,
+        syms: SymbolTable,
+    }
 
-                        if force_pc < first_pc.saturating_add(4 * 4)
-                            || force_pc > last_pc.saturating_sub(4 * 4)
-                        {
-                            let start_pc = force_pc
-                                .saturating_sub(10 * 4)
-                                .max(pc_range.0)
-                                .min(pc_range.1);
-                            ui.set_scroll_y(row_height * ((start_pc - pc_range.0) / 4) as f32);
-                        }
+    impl DisasmView for View {
+        fn name(&self) -> &str {
+            "cpu"
+        }
+        fn pc(&self) -> u64 {
+            0
+        }
+        fn pc_mask(&self, pc: u64) -> u64 {
+            pc & 0xFFFF_FFFF
+        }
+        fn symbols(&self) -> Option<&SymbolTable> {
+            Some(&self.syms)
+        }
+        fn disasm_block<Func: FnMut(u64, &[u8], &DecodedInsn)>(
+            &self,
+            pc_range: (u64, u64),
+            mut f: Func,
+        ) {
+            for pc in (pc_range.0..pc_range.1).step_by(4) {
+                let op = self
+                    .program
+                    .iter()
+                    .find(|(addr, _)| *addr == pc)
+                    .map_or(0, |(_, op)| *op);
+                let insn = match op >> 26 {
+                    0x03 => {
+                        let tgt = (op as u64 & 0x3FF_FFFF) * 4;
+                        DecodedInsn::new1("jal", Operand::Target(tgt))
+                            .with_flags(InsnFlags::BRANCH | InsnFlags::DELAY_SLOT)
                     }
+                    0x09 => DecodedInsn::new3(
+                        "addiu",
+                        Operand::OReg("v0"),
+                        Operand::IReg("v0"),
+                        Operand::Imm16(op as u16),
+                    ),
+                    _ => DecodedInsn::new0("nop"),
+                };
+                let mut mem = [0u8; 4];
+                BigEndian::write_u32(&mut mem, op);
+                f(pc, &mem, &insn);
+            }
+        }
+    }
 
-                    // Display the non-clipped part of the listbox
-                    let blink_pc = dctx.blink_pc;
-                    let cursor_pc = dctx.cursor_pc;
-                    ImGuiListClipper::new(num_lines as usize).build(|start, end| {
-                        v.disasm_block(
-                            (pc_range.0 + start as u64 * 4, pc_range.0 + end as u64 * 4),
-                            |pc, mem, insn| {
-                                let mut bkg_color = color(0, 0, 0);
-
-                                // Highlight this line if it's the current cursor position
-                                if let Some(cpc) = cursor_pc {
-                                    if cpc == pc {
-                                        let wsize = ui.content_region_avail();
-                                        let dl = ui.get_window_draw_list();
-                                        let pos = ui.cursor_screen_pos();
-                                        let end = [pos[0] + wsize[0], pos[1] + 15.0];
-                                        let c1 = color(151, 39, 77);
-                                        dl.add_rect_filled_multicolor(pos, end, c1, c1, c1, c1);
-                                        bkg_color = c1;
-                                    }
-                                }
-
-                                // Highlight this line if it is PC.
-                                if pc == cur_pc {
-                                    let wsize = ui.content_region_avail();
-                                    let dl = ui.get_window_draw_list();
-                                    let pos = ui.cursor_screen_pos();
-                                    let end = [pos[0] + wsize[0], pos[1] + 15.0];
-                                    let c1 = color(41, 65, 100);
-                                    dl.add_rect_filled_multicolor(pos, end, c1, c1, c1, c1);
-                                    bkg_color = c1;
-
-                                    // If PC changed since last time, update also the context to save
-                                    // input/output regs (that will be used to highlight them).
-                                    if dctx.cur_pc.is_none() || dctx.cur_pc.unwrap() != pc {
-                                        dctx.cur_pc = Some(pc);
-
-                                        dctx.regs_highlight.clear();
-                                        for op in insn.args() {
-                                            if let Some(inp) = op.input() {
-                                                dctx.regs_highlight
-                                                    .insert(inp, RegHighlight::Input);
-                                            }
-                                            if let Some(outp) = op.output() {
-                                                dctx.regs_highlight
-                                                    .insert(outp, RegHighlight::Output);
-                                            }
-                                        }
-                                    }
-                                }
-
-                                // See if we need to do a blink animation over this PC
-                                if let Some((bpc, bwhen)) = blink_pc {
-                                    if v.pc_mask(bpc) == pc {
-                                        match blink_color(bkg_color, bwhen) {
-                                            Some(c1) => {
-                                                let wsize = ui.content_region_avail();
-                                                let dl = ui.get_window_draw_list();
-                                                let pos = ui.cursor_screen_pos();
-                                                let end = [pos[0] + wsize[0], pos[1] + 15.0];
-                                                dl.add_rect_filled_multicolor(
-                                                    pos, end, c1, c1, c1, c1,
-                                                )
-                                            }
-                                            None => {}
-                                        }
-                                    }
-                                }
-
-                                let dis = insn.disasm();
-                                let fields: Vec<&str> = dis.splitn(2, "\t").collect();
-
-                                let gr = ui.begin_group();
-
-                                // Address
-                                ui.text_colored(color(174, 129, 255), im_str!("{:08x}", pc));
-
-                                // Hex dump
-                                ui.same_line(80.0);
-                                ui.text_colored(color(102, 99, 83), im_str!("{:x}", ByteBuf(mem)));
-
-                                // Opcode
-                                ui.same_line(160.0);
-                                ui.text_colored(color(165, 224, 46), im_str!("{}", fields[0]));
+    fn view() -> View {
+        let mut syms = SymbolTable::new();
+        syms.insert(0x1010, "func", None);
+        View {
+            program: vec![
+                (0x1000, 0x0C00_0404),
+                (0x1008, 0x2442_0001),
+                (0x1010, 0x2442_0001),
+            ],
+            syms,
+        }
+    }
 
-                                // Args
-                                ui.same_line(230.0);
-                                ui.text_colored(color(230, 219, 116), im_str!("{:80}", fields[1]));
+    fn export(name: &str, range: (u64, u64)) -> (String, usize) {
+        let path = env::temp_dir().join(format!("r64emu-{}-{}.txt", name, std::process::id()));
+        let v = view();
+        let mut exp = DisasmExport::create(&path, range).unwrap();
+        let mut steps = 0;
+        while !exp.step(&v).unwrap() {
+            steps += 1;
+            assert!(exp.progress() < 1.0);
+        }
+        assert!(exp.progress() >= 1.0);
 
-                                gr.end(&ui);
-                                if ui.is_item_hovered()
-                                    && ui.is_window_focused()
-                                    && ui.is_mouse_clicked(MouseButton::Left)
-                                {
-                                    dctx.cursor_pc = Some(pc);
-                                }
-                            },
-                        );
-                    })
-                })
-        });
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        (text, steps + 1)
+    }
 
-    dctx.force_pc = None;
+    #[test]
+    fn export_listing() {
+        let (text, steps) = export("disasm", (0x1000, 0x1018));
+        assert_eq!(steps, 1);
+        assert_eq!(
+            text,
+            "00001000:  0c000404   jal       func\n\
+             00001004:  00000000    nop\n\
+             00001008:  24420001   addiu     v0,v0,0x1\n\
+             0000100c:  00000000   nop\n\
+             \n\
+             func:\n\
+             00001010:  24420001   addiu     v0,v0,0x1\n\
+             00001014:  00000000   nop\n"
+        );
+    }
 
-    // See if we need to set a UiCommand into the context.
-    if set_command.is_some() {
-        ctx.command = set_command;
+    #[test]
+    fn export_chunks() {
+        // Large ranges are exported in multiple steps, with increasing
+        // progress; all instructions are written exactly once.
+        let size = DisasmExport::CHUNK_SIZE * 2 + 0x100;
+        let (text, steps) = export("disasm-chunks", (0x1000, 0x1000 + size));
+        assert_eq!(steps, 3);
+        assert_eq!(
+            text.lines().filter(|l| l.contains(":  ")).count(),
+            (size / 4) as usize
+        );
+        assert!(text.ends_with(&format!("{:08x}:  00000000   nop\n", 0x1000 + size - 4)));
+
+        let path = env::temp_dir().join("r64emu-disasm-invalid.txt");
+        assert!(DisasmExport::create(&path, (0x2000, 0x1000)).is_err());
     }
 }
@@ -0,0 +1,723 @@
+use super::*;
+use crate::dbg::uisupport::*;
+use crate::dbg::{
+    Debugger, Dock, InsnPatch, RegHighlight, TraceEvent, UiCommand, UiCtx, UiCtxDisasm,
+};
+use crate::hw::HotkeyAction;
+use imgui::*;
+use imgui_sys;
+use sdl2::keyboard::Scancode;
+use std::fs;
+use std::time::Instant;
+use tinyfiledialogs::save_file_dialog_with_filter;
+
+// Number of chunks exported at each frame by the disasm view.
+const EXPORT_STEPS_PER_FRAME: usize = 16;
+
+fn color(r: usize, g: usize, b: usize) -> [f32; 4] {
+    [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0]
+}
+
+// Assemble an instruction and patch it into memory. Patching an instruction
+// again updates the existing patch, so that undo restores the original bytes.
+fn apply_patch<DV: DisasmView>(
+    v: &mut DV,
+    pc: u64,
+    text: &str,
+    dctx: &mut UiCtxDisasm,
+) -> Result<(), String> {
+    let bytes = v.assemble(pc, text)?;
+    let orig = v.patch(pc, &bytes)?;
+    match dctx.patches.iter_mut().find(|p| p.pc == pc) {
+        Some(p) => {
+            p.bytes = bytes;
+            p.text = text.to_owned();
+        }
+        None => dctx.patches.push(InsnPatch {
+            pc,
+            orig,
+            bytes,
+            text: text.to_owned(),
+        }),
+    }
+    Ok(())
+}
+
+// Save the list of patches as text, one per line.
+fn export_patches(cpu_name: &str, patches: &[InsnPatch]) -> Result<(), String> {
+    let path = match save_file_dialog_with_filter(
+        "Export patches",
+        &format!("{}-patches.txt", cpu_name.to_lowercase()),
+        &vec![".txt"],
+        "Patched instructions",
+    ) {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let text: String = patches
+        .iter()
+        .map(|p| {
+            format!(
+                "{:08x}: {:x} -> {:x}  ; {}\n",
+                p.pc,
+                ByteBuf(&p.orig),
+                ByteBuf(&p.bytes),
+                p.text
+            )
+        })
+        .collect();
+    fs::write(&path, text).map_err(|e| format!("cannot write {}: {}", path, e))
+}
+
+// Render the list of applied patches, allowing to undo or export them.
+fn render_patches<'a, 'ui, DV: DisasmView>(
+    ui: &'a Ui<'ui>,
+    cpu_name: &str,
+    v: &mut DV,
+    dctx: &mut UiCtxDisasm,
+) -> Option<String> {
+    let mut error = None;
+    Window::new(&im_str!("[{}] Patches", cpu_name))
+        .size([350.0, 200.0], Condition::FirstUseEver)
+        .build(ui, || {
+            let mut undo = Vec::new();
+            if ui.small_button(im_str!("Undo all")) {
+                undo.extend(0..dctx.patches.len());
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Export")) {
+                if let Err(err) = export_patches(cpu_name, &dctx.patches) {
+                    error = Some(err);
+                }
+            }
+            ui.separator();
+
+            for (idx, p) in dctx.patches.iter().enumerate() {
+                let id = ui.push_id(idx as i32);
+                if ui.small_button(im_str!("Undo")) {
+                    undo.push(idx);
+                }
+                ui.same_line(0.0);
+                ui.text_colored(color(174, 129, 255), im_str!("{:08x}", p.pc));
+                ui.same_line(0.0);
+                ui.text(im_str!("{}", p.text));
+                if ui.is_item_hovered() && ui.is_mouse_double_clicked(MouseButton::Left) {
+                    dctx.force_pc = Some(p.pc);
+                }
+                id.pop(ui);
+            }
+
+            // Undo in reverse order, so that indices remain valid.
+            for idx in undo.into_iter().rev() {
+                let p = dctx.patches.remove(idx);
+                if let Err(err) = v.patch(p.pc, &p.orig) {
+                    error = Some(err);
+                }
+            }
+        });
+    error
+}
+
+// Ask for the output file, and start exporting the range typed in the export
+// popup. Returns None if the user cancelled the file dialog.
+fn start_export<DV: DisasmView>(
+    v: &DV,
+    cpu_name: &str,
+    range: &(ImString, ImString),
+) -> Result<Option<DisasmExport>, String> {
+    let parse = |s: &ImString| {
+        u64::from_str_radix(s.to_str().trim(), 16)
+            .map_err(|_| format!("invalid address: {:?}", s.to_str()))
+    };
+    let (start, end) = (parse(&range.0)?, parse(&range.1)?);
+    if end <= start {
+        return Err(format!("invalid range: {:x}-{:x}", start, end));
+    }
+    let path = match save_file_dialog_with_filter(
+        "Export disassembly",
+        &format!("{}-{:08x}.txt", cpu_name.to_lowercase(), start),
+        &vec![".txt"],
+        "Disassembly listing",
+    ) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    DisasmExport::create(Path::new(&path), (v.pc_mask(start), v.pc_mask(end - 1) + 1))
+        .map(Some)
+        .map_err(|e| format!("cannot write {}: {}", path, e))
+}
+
+// If the instruction at the specified PC is a branch or a jump with an
+// immediate target, move the view to its target.
+fn follow_branch<DV: DisasmView>(v: &DV, pc: u64, dctx: &mut UiCtxDisasm) {
+    let mut target = None;
+    v.disasm_block((pc, pc + 4), |_, _, insn| target = insn.branch_target());
+    if let Some(target) = target {
+        dctx.navigate(target);
+    }
+}
+
+pub(crate) fn render_disasmview<'a, 'ui, DV: DisasmView>(
+    ui: &'a Ui<'ui>,
+    ctx: &mut UiCtx,
+    dbg: &Debugger,
+    v: &mut DV,
+) {
+    let cpu_name = v.name().to_owned();
+    let cur_pc = v.pc();
+    let mut set_command: Option<UiCommand> = None;
+    let mut flash_msg = None;
+    let mut error_msg = None;
+    let mut coverage = dbg.coverage.borrow_mut();
+
+    // Requests to show a PC reopen the window, if it was closed.
+    let title = im_str!("[{}] Disassembly", cpu_name);
+    if ctx.disasm[&cpu_name].force_pc.is_some() {
+        ctx.layout.set_open(&title, true);
+    }
+    if !ctx.layout.is_open(&title) {
+        return;
+    }
+    let hotkeys = ctx.hotkeys.clone();
+    let dctx = ctx.disasm.get_mut(&cpu_name).unwrap();
+
+    // If we were asked to show a certain PC, then also get focus
+    // as the user probably wants to see this window.
+    if dctx.force_pc.is_some() {
+        unsafe {
+            imgui_sys::igSetNextWindowFocus();
+        }
+    }
+
+    // Process current event (if any)
+    match ctx.event {
+        Some((ref evt, _)) => match **evt {
+            TraceEvent::Breakpoint(ref bp_cpu_name, _, bp_pc) if *bp_cpu_name == cpu_name => {
+                // Center breakpoint PC
+                dctx.force_pc = Some(bp_pc);
+
+                // Focus this window
+                unsafe {
+                    imgui_sys::igSetNextWindowFocus();
+                }
+
+                dctx.cursor_pc = None;
+
+                // Start blinking effect
+                dctx.blink_pc = Some((bp_pc, Instant::now()));
+            }
+            TraceEvent::WatchpointRead(ref bp_cpu_name, _)
+            | TraceEvent::WatchpointWrite(ref bp_cpu_name, _)
+                if *bp_cpu_name == cpu_name =>
+            {
+                // Center breakpoint PC
+                dctx.force_pc = Some(cur_pc);
+
+                // Focus this window
+                unsafe {
+                    imgui_sys::igSetNextWindowFocus();
+                }
+
+                dctx.cursor_pc = None;
+
+                // Start blinking effect
+                dctx.blink_pc = Some((cur_pc, Instant::now()));
+            }
+            TraceEvent::BreakpointOneShot(ref bp_cpu_name, bp_pc) if *bp_cpu_name == cpu_name => {
+                // Center breakpoint PC
+                dctx.force_pc = Some(bp_pc);
+
+                // Focus this window
+                unsafe {
+                    imgui_sys::igSetNextWindowFocus();
+                }
+
+                dctx.blink_pc = None;
+                dctx.cursor_pc = None;
+            }
+            TraceEvent::Stepped() | TraceEvent::Paused() | TraceEvent::GenericBreak(_) => {
+                dctx.force_pc = Some(cur_pc);
+                dctx.blink_pc = None;
+                dctx.cursor_pc = None;
+            }
+            _ => {}
+        },
+        None => {}
+    };
+
+    let mut opened = true;
+    let window = Window::new(&title).size([450.0, 400.0], Condition::FirstUseEver);
+    ctx.layout
+        .place(&title, Dock::Left, window)
+        .opened(&mut opened)
+        .build(ui, || {
+            // Keys are used as shortcuts only if the user is not typing.
+            let has_focus = ui
+                .is_window_focused_with_flags(WindowFocusedFlags::ROOT_AND_CHILD_WINDOWS)
+                && !ui.io().want_text_input;
+            let hk = hotkeys.borrow();
+
+            // *******************************************
+            // Goto popup
+            // *******************************************
+            ui.popup(im_str!("###goto"), || {
+                let mut s = ImString::new("00000000");
+                ui.text(im_str!("Insert PC:"));
+                if ui
+                    .input_text(im_str!("###goto#input"), &mut s)
+                    .chars_hexadecimal(true)
+                    .enter_returns_true(true)
+                    .auto_select_all(true)
+                    .build()
+                {
+                    if let Ok(pc) = u64::from_str_radix(s.as_ref(), 16) {
+                        dctx.navigate(pc);
+                    }
+                    ui.close_current_popup();
+                }
+            });
+
+            // *******************************************
+            // Assemble popup
+            // *******************************************
+            ui.popup(im_str!("###asm"), || {
+                ui.text(im_str!("Assemble at {:08x}:", dctx.asm_pc));
+                if ui
+                    .input_text(im_str!("###asm#input"), &mut dctx.asm_text)
+                    .enter_returns_true(true)
+                    .auto_select_all(true)
+                    .build()
+                {
+                    let (pc, text) = (dctx.asm_pc, dctx.asm_text.to_str().trim().to_owned());
+                    match apply_patch(v, pc, &text, dctx) {
+                        Ok(()) => {
+                            dctx.asm_error = None;
+                            ui.close_current_popup();
+                        }
+                        Err(err) => dctx.asm_error = Some(err),
+                    }
+                }
+                if let Some(err) = dctx.asm_error.as_ref() {
+                    ui.text_colored([1.0, 0.3, 0.3, 1.0], im_str!("{}", err));
+                }
+            });
+
+            // *******************************************
+            // Export popup
+            // *******************************************
+            ui.popup(im_str!("###export"), || {
+                ui.text(im_str!("Export range (end excluded):"));
+                ui.input_text(im_str!("Start###export#start"), &mut dctx.export_range.0)
+                    .chars_hexadecimal(true)
+                    .build();
+                ui.input_text(im_str!("End###export#end"), &mut dctx.export_range.1)
+                    .chars_hexadecimal(true)
+                    .build();
+                if ui.small_button(im_str!("Export...")) {
+                    ui.close_current_popup();
+                    match start_export(v, &cpu_name, &dctx.export_range) {
+                        Ok(Some(export)) => dctx.export = Some(export),
+                        Ok(None) => {}
+                        Err(err) => error_msg = Some(err),
+                    }
+                }
+            });
+
+            // *******************************************
+            // Cursor input
+            // *******************************************
+            if has_focus {
+                if ui.is_key_pressed(Scancode::Up as _) {
+                    let cpc = match dctx.cursor_pc {
+                        Some(cpc) => cpc - 4,
+                        None => cur_pc - 4,
+                    };
+                    dctx.cursor_pc = Some(cpc);
+                }
+                if ui.is_key_pressed(Scancode::Down as _) {
+                    let cpc = match dctx.cursor_pc {
+                        Some(cpc) => cpc + 4,
+                        None => cur_pc + 4,
+                    };
+                    dctx.cursor_pc = Some(cpc);
+                }
+
+                // Follow the branch under the cursor, and navigate back and
+                // forward.
+                if hk.pressed(ui, HotkeyAction::FollowBranch) {
+                    follow_branch(v, dctx.cursor_pc.unwrap_or(cur_pc), dctx);
+                }
+                if hk.pressed(ui, HotkeyAction::NavBack) {
+                    dctx.nav_back();
+                }
+                if hk.pressed(ui, HotkeyAction::NavForward) {
+                    dctx.nav_forward();
+                }
+            }
+
+            // *******************************************
+            // Button toolbar
+            // *******************************************
+            if ui.small_button(im_str!("<")) {
+                dctx.nav_back();
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!(">")) {
+                dctx.nav_forward();
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Goto")) {
+                ui.open_popup(im_str!("###goto"));
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Center"))
+                || (has_focus && hk.pressed(ui, HotkeyAction::Center))
+            {
+                dctx.force_pc = Some(cur_pc);
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Patch"))
+                || (has_focus && hk.pressed(ui, HotkeyAction::Assemble))
+            {
+                // Start editing the instruction under the cursor, with its
+                // current disassembly as initial text.
+                let pc = dctx.cursor_pc.unwrap_or(cur_pc);
+                let mut text = String::new();
+                v.disasm_block((pc, pc + 4), |_, _, insn| {
+                    text = insn.disasm().replace('\t', " ")
+                });
+                dctx.asm_pc = pc;
+                dctx.asm_text = ImString::with_capacity(64);
+                dctx.asm_text.push_str(text.trim());
+                dctx.asm_error = None;
+                ui.open_popup(im_str!("###asm"));
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Step")) || (has_focus && hk.pressed(ui, HotkeyAction::Step))
+            {
+                set_command = Some(UiCommand::CpuStep(cpu_name.clone()));
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Over"))
+                || (has_focus && hk.pressed(ui, HotkeyAction::StepOver))
+            {
+                let mut ret_pc = None;
+                v.disasm_block((cur_pc, cur_pc + 4), |pc, _, insn| {
+                    ret_pc = v.call_return_pc(pc, insn)
+                });
+                set_command = Some(match ret_pc {
+                    Some(ret_pc) => {
+                        UiCommand::StepOver(cpu_name.clone(), ret_pc, v.stack_pointer())
+                    }
+                    None => UiCommand::CpuStep(cpu_name.clone()),
+                });
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Out"))
+                || (has_focus && hk.pressed(ui, HotkeyAction::StepOut))
+            {
+                match v.return_pc() {
+                    Some(ret_pc) => {
+                        set_command = Some(UiCommand::StepOut(
+                            cpu_name.clone(),
+                            ret_pc,
+                            v.stack_pointer(),
+                        ))
+                    }
+                    None => flash_msg = Some("Return address unknown"),
+                }
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("To cursor"))
+                || (has_focus && hk.pressed(ui, HotkeyAction::RunToCursor))
+            {
+                if let Some(cpc) = dctx.cursor_pc {
+                    set_command = Some(UiCommand::RunToCursor(cpu_name.clone(), cpc));
+                }
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Coverage")) {
+                ui.open_popup(im_str!("###coverage"));
+            }
+            ui.popup(im_str!("###coverage"), || {
+                error_msg = coverage.render_controls(ui, &cpu_name);
+            });
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Export")) {
+                // Default to the 4 KiB page around the cursor.
+                let pc = dctx.cursor_pc.unwrap_or(cur_pc) & !0xFFF;
+                let hex = |val: u64| {
+                    let mut s = ImString::with_capacity(16);
+                    s.push_str(&format!("{:08x}", val));
+                    s
+                };
+                dctx.export_range = (hex(pc), hex(pc + 0x1000));
+                ui.open_popup(im_str!("###export"));
+            }
+
+            // Export in progress: write a few chunks per frame, so that the
+            // UI stays responsive while exporting large ranges.
+            let mut export_done = false;
+            if let Some(export) = dctx.export.as_mut() {
+                for _ in 0..EXPORT_STEPS_PER_FRAME {
+                    match export.step(&*v) {
+                        Ok(false) => {}
+                        Ok(true) => {
+                            flash_msg = Some("Disassembly exported");
+                            export_done = true;
+                            break;
+                        }
+                        Err(err) => {
+                            error_msg =
+                                Some(format!("cannot write {}: {}", export.path().display(), err));
+                            export_done = true;
+                            break;
+                        }
+                    }
+                }
+                if !export_done {
+                    ui.text(im_str!(
+                        "Exporting to {}: {:.0}%",
+                        export.path().display(),
+                        export.progress() * 100.0
+                    ));
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("Cancel")) {
+                        export_done = true;
+                    }
+                }
+            }
+            if export_done {
+                dctx.export = None;
+            }
+            ui.separator();
+
+            // *******************************************
+            // Main scroll view with disasm
+            // *******************************************
+            ChildWindow::new(&im_str!("###scrolling"))
+                .size([0.0, 0.0])
+                .always_vertical_scrollbar(true)
+                .build(ui, || {
+                    // Get the full extent of PC. Notice that the range is *inclusive*.
+                    let mut pc_range = (v.pc_mask(u64::min_value()), v.pc_mask(u64::max_value()));
+
+                    // Calculate a range of PC that will be used in the disasm
+                    // view, that could be smaller than the full extent. We select
+                    // up to 1M lines around the current PC.
+                    // Notice that this is the full range of the listbox, not just
+                    // the display range.
+                    const MAX_LINES: u64 = 1024 * 1024;
+                    pc_range.0 =
+                        (cur_pc.saturating_sub(4 * MAX_LINES / 2) / 1024 * 1024).max(pc_range.0);
+                    pc_range.1 = pc_range.0.saturating_add(4 * MAX_LINES - 1).min(pc_range.1);
+                    let num_lines = (pc_range.1 - pc_range.0 + 1) / 4;
+
+                    // Check if we were asked to scroll to a specific PC.
+                    if let Some(force_pc) = dctx.force_pc {
+                        let size = ui.content_region_avail();
+                        let row_height = ui.text_line_height_with_spacing();
+                        let scroll_y = ui.scroll_y();
+                        let force_pc = v.pc_mask(force_pc);
+
+                        let first_pc = pc_range
+                            .0
+                            .saturating_add((scroll_y / row_height) as u64 * 4);
+                        let last_pc = first_pc.saturating_add((size[1] / row_height) as u64 * 4);
+
+                        if force_pc < first_pc.saturating_add(4 * 4)
+                            || force_pc > last_pc.saturating_sub(4 * 4)
+                        {
+                            let start_pc = force_pc
+                                .saturating_sub(10 * 4)
+                                .max(pc_range.0)
+                                .min(pc_range.1);
+                            ui.set_scroll_y(row_height * ((start_pc - pc_range.0) / 4) as f32);
+                        }
+                    }
+
+                    // Display the non-clipped part of the listbox
+                    let blink_pc = dctx.blink_pc;
+                    let cursor_pc = dctx.cursor_pc;
+                    let syms = v.symbols();
+                    let mut follow_target = None;
+                    ImGuiListClipper::new(num_lines as usize).build(|start, end| {
+                        // Decode also the instruction before the first line,
+                        // to know whether the latter is in a delay slot.
+                        let first_pc = pc_range.0 + start as u64 * 4;
+                        let mut in_delay_slot = false;
+                        v.disasm_block(
+                            (
+                                first_pc.saturating_sub(4).max(pc_range.0),
+                                pc_range.0 + end as u64 * 4,
+                            ),
+                            |pc, mem, insn| {
+                                let delay_slot = in_delay_slot;
+                                in_delay_slot = insn.flags.contains(InsnFlags::DELAY_SLOT);
+                                if pc < first_pc {
+                                    return;
+                                }
+
+                                let mut bkg_color = color(0, 0, 0);
+
+                                // Highlight this line if it was executed (when
+                                // tracking coverage).
+                                if coverage.is_covered(&cpu_name, pc) {
+                                    let wsize = ui.content_region_avail();
+                                    let dl = ui.get_window_draw_list();
+                                    let pos = ui.cursor_screen_pos();
+                                    let end = [pos[0] + wsize[0], pos[1] + 15.0];
+                                    let c1 = color(30, 60, 30);
+                                    dl.add_rect_filled_multicolor(pos, end, c1, c1, c1, c1);
+                                    bkg_color = c1;
+                                }
+
+                                // Highlight this line if it's the current cursor position
+                                if let Some(cpc) = cursor_pc {
+                                    if cpc == pc {
+                                        let wsize = ui.content_region_avail();
+                                        let dl = ui.get_window_draw_list();
+                                        let pos = ui.cursor_screen_pos();
+                                        let end = [pos[0] + wsize[0], pos[1] + 15.0];
+                                        let c1 = color(151, 39, 77);
+                                        dl.add_rect_filled_multicolor(pos, end, c1, c1, c1, c1);
+                                        bkg_color = c1;
+                                    }
+                                }
+
+                                // Highlight this line if it is PC.
+                                if pc == cur_pc {
+                                    let wsize = ui.content_region_avail();
+                                    let dl = ui.get_window_draw_list();
+                                    let pos = ui.cursor_screen_pos();
+                                    let end = [pos[0] + wsize[0], pos[1] + 15.0];
+                                    let c1 = color(41, 65, 100);
+                                    dl.add_rect_filled_multicolor(pos, end, c1, c1, c1, c1);
+                                    bkg_color = c1;
+
+                                    // If PC changed since last time, update also the context to save
+                                    // input/output regs (that will be used to highlight them).
+                                    if dctx.cur_pc.is_none() || dctx.cur_pc.unwrap() != pc {
+                                        dctx.cur_pc = Some(pc);
+
+                                        dctx.regs_highlight.clear();
+                                        for op in insn.args() {
+                                            if let Some(inp) = op.input() {
+                                                dctx.regs_highlight
+                                                    .insert(inp, RegHighlight::Input);
+                                            }
+                                            if let Some(outp) = op.output() {
+                                                dctx.regs_highlight
+                                                    .insert(outp, RegHighlight::Output);
+                                            }
+                                        }
+                                    }
+                                }
+
+                                // See if we need to do a blink animation over this PC
+                                if let Some((bpc, bwhen)) = blink_pc {
+                                    if v.pc_mask(bpc) == pc {
+                                        match blink_color(bkg_color, bwhen) {
+                                            Some(c1) => {
+                                                let wsize = ui.content_region_avail();
+                                                let dl = ui.get_window_draw_list();
+                                                let pos = ui.cursor_screen_pos();
+                                                let end = [pos[0] + wsize[0], pos[1] + 15.0];
+                                                dl.add_rect_filled_multicolor(
+                                                    pos, end, c1, c1, c1, c1,
+                                                )
+                                            }
+                                            None => {}
+                                        }
+                                    }
+                                }
+
+                                let dis = insn.disasm_with_symbols(syms);
+                                let fields: Vec<&str> = dis.splitn(2, "\t").collect();
+
+                                let gr = ui.begin_group();
+
+                                // Address
+                                ui.text_colored(color(174, 129, 255), im_str!("{:08x}", pc));
+
+                                // Hex dump
+                                ui.same_line(80.0);
+                                ui.text_colored(color(102, 99, 83), im_str!("{:x}", ByteBuf(mem)));
+
+                                // Nominal cycles, if more than one
+                                if insn.cycles > 1 {
+                                    ui.same_line(140.0);
+                                    ui.text_colored(
+                                        color(253, 151, 31),
+                                        im_str!("{}", insn.cycles),
+                                    );
+                                }
+
+                                // Opcode (indented if in a delay slot)
+                                ui.same_line(160.0);
+                                let indent = if delay_slot { " " } else { "" };
+                                ui.text_colored(
+                                    color(165, 224, 46),
+                                    im_str!("{}{}", indent, fields[0]),
+                                );
+
+                                // Args (followed by the function name, if this
+                                // is the first instruction of a function)
+                                ui.same_line(230.0);
+                                let args_color = color(230, 219, 116);
+                                match syms.and_then(|s| s.lookup(pc)) {
+                                    Some(name) => {
+                                        ui.text_colored(args_color, im_str!("{}", fields[1]));
+                                        ui.same_line(0.0);
+                                        ui.text_colored(
+                                            color(102, 217, 239),
+                                            im_str!("{:80}", format!("  <{}>", name)),
+                                        );
+                                    }
+                                    None => {
+                                        ui.text_colored(args_color, im_str!("{:80}", fields[1]));
+                                    }
+                                }
+
+                                gr.end(&ui);
+                                if ui.is_item_hovered() && ui.is_window_focused() {
+                                    if ui.is_mouse_double_clicked(MouseButton::Left) {
+                                        // Double-click follows the branch
+                                        if let Some(target) = insn.branch_target() {
+                                            dctx.cursor_pc = Some(pc);
+                                            follow_target = Some(target);
+                                        }
+                                    } else if ui.is_mouse_clicked(MouseButton::Left) {
+                                        dctx.cursor_pc = Some(pc);
+                                    }
+                                }
+                            },
+                        );
+                    });
+                    if let Some(target) = follow_target {
+                        dctx.navigate(target);
+                    }
+                })
+        });
+    ctx.layout.set_open(&title, opened);
+
+    dctx.force_pc = None;
+
+    if !dctx.patches.is_empty() {
+        if let Some(err) = render_patches(ui, &cpu_name, v, dctx) {
+            ctx.error_msg = Some(err);
+        }
+    }
+
+    if let Some(msg) = flash_msg {
+        ctx.add_flash_msg(msg);
+    }
+    if error_msg.is_some() {
+        ctx.error_msg = error_msg;
+    }
+
+    // See if we need to set a UiCommand into the context.
+    if set_command.is_some() {
+        ctx.command = set_command;
+    }
+}
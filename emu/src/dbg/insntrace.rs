@@ -0,0 +1,311 @@
+use super::decoding::DecodedInsn;
+use super::symbols::SymbolTable;
+use super::tracer::Debugger;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[cfg(feature = "frontend")]
+mod ui;
+#[cfg(feature = "frontend")]
+pub use self::ui::*;
+
+// Maximum number of modified registers recorded for each instruction.
+const MAX_CHANGED_REGS: usize = 3;
+
+// Default number of instructions kept in the ring buffer (per CPU).
+const DEFAULT_CAPACITY: usize = 1024 * 1024;
+
+#[derive(Copy, Clone, Default)]
+struct TraceEntry {
+    pc: u64,
+    opcode: u32,
+    changed: [(&'static str, u64); MAX_CHANGED_REGS], // unused slots have an empty name
+}
+
+impl TraceEntry {
+    fn changed(&self) -> impl Iterator<Item = &(&'static str, u64)> {
+        self.changed.iter().take_while(|(name, _)| !name.is_empty())
+    }
+
+    fn changed_to_string(&self) -> String {
+        self.changed()
+            .map(|(name, val)| format!("{}={:x}", name, val))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+// Output file of a streamed trace: LZ4-compressed, or plain text.
+enum TraceStream {
+    Lz4(lz4::Encoder<BufWriter<File>>),
+    Text(BufWriter<File>),
+}
+
+impl TraceStream {
+    fn create(path: &str) -> io::Result<Self> {
+        let w = BufWriter::new(File::create(path)?);
+        if path.ends_with(".lz4") {
+            Ok(TraceStream::Lz4(
+                lz4::EncoderBuilder::new().level(4).build(w)?,
+            ))
+        } else {
+            Ok(TraceStream::Text(w))
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            TraceStream::Lz4(enc) => {
+                let (mut w, res) = enc.finish();
+                res.and_then(|_| w.flush())
+            }
+            TraceStream::Text(mut w) => w.flush(),
+        }
+    }
+}
+
+impl Write for TraceStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            TraceStream::Lz4(enc) => enc.write(buf),
+            TraceStream::Text(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            TraceStream::Lz4(enc) => enc.flush(),
+            TraceStream::Text(w) => w.flush(),
+        }
+    }
+}
+
+/// A trait for an object whose executed instructions can be recorded into
+/// an instruction trace, and then browsed in a debugger view.
+pub trait InsnTraceView {
+    /// Return the name of this object. The name will be composed
+    /// as "\[NAME\] Instruction trace".
+    fn name(&self) -> &str;
+
+    /// Disassemble an instruction that was executed at the specified PC.
+    fn disasm_insn(&self, pc: u64, opcode: u32) -> DecodedInsn;
+
+    /// Return the symbol table used to label the entries of the trace with
+    /// the function containing their PC, if any.
+    fn symbols(&self) -> Option<&SymbolTable> {
+        None
+    }
+}
+
+/// InsnTrace records all the instructions executed by the CPUs into a
+/// ring buffer (one per CPU), and optionally streams them to a text file
+/// (LZ4-compressed if its extension is .lz4).
+pub(crate) struct InsnTrace {
+    enabled: bool,
+    capacity: usize,
+    cpus: Vec<(String, VecDeque<TraceEntry>)>,
+    stream: Option<(String, TraceStream)>,
+    stream_error: Option<String>,
+}
+
+impl Default for InsnTrace {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: DEFAULT_CAPACITY,
+            cpus: Vec::new(),
+            stream: None,
+            stream_error: None,
+        }
+    }
+}
+
+impl InsnTrace {
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Change the number of instructions kept for each CPU, discarding the
+    // oldest ones if they don't fit anymore.
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        for (_, buf) in self.cpus.iter_mut() {
+            while buf.len() > self.capacity {
+                buf.pop_front();
+            }
+        }
+    }
+
+    fn buffer(&self, cpu_name: &str) -> Option<&VecDeque<TraceEntry>> {
+        self.cpus
+            .iter()
+            .find(|(name, _)| name == cpu_name)
+            .map(|(_, buf)| buf)
+    }
+
+    pub(crate) fn record<D: FnOnce() -> String>(
+        &mut self,
+        cpu_name: &str,
+        pc: u64,
+        opcode: u32,
+        changed: &[(&'static str, u64)],
+        disasm: D,
+    ) {
+        let mut entry = TraceEntry {
+            pc,
+            opcode,
+            ..Default::default()
+        };
+        for (dst, src) in entry.changed.iter_mut().zip(changed) {
+            *dst = *src;
+        }
+
+        if let Some((_, ref mut enc)) = self.stream {
+            let res = writeln!(
+                enc,
+                "[{}] {:08x} {:08x} {:40} {}",
+                cpu_name,
+                pc,
+                opcode,
+                disasm().replace('\t', " "),
+                entry.changed_to_string(),
+            );
+            if let Err(err) = res {
+                self.stream_error = Some(format!("Error writing trace file: {}", err));
+                self.stop_stream();
+            }
+        }
+
+        let capacity = self.capacity;
+        let idx = match self.cpus.iter().position(|(name, _)| name == cpu_name) {
+            Some(idx) => idx,
+            None => {
+                self.cpus.push((cpu_name.to_owned(), VecDeque::new()));
+                self.cpus.len() - 1
+            }
+        };
+        let buf = &mut self.cpus[idx].1;
+        if buf.len() >= capacity {
+            buf.pop_front();
+        }
+        buf.push_back(entry);
+    }
+
+    fn clear(&mut self) {
+        self.cpus.clear();
+    }
+
+    fn start_stream(&mut self, path: &str) -> io::Result<()> {
+        self.stop_stream();
+        self.stream = Some((path.to_owned(), TraceStream::create(path)?));
+        Ok(())
+    }
+
+    fn stop_stream(&mut self) {
+        if let Some((path, stream)) = self.stream.take() {
+            if let Err(err) = stream.finish() {
+                self.stream_error = Some(format!("Error closing trace file {}: {}", path, err));
+            }
+        }
+    }
+}
+
+impl Drop for InsnTrace {
+    fn drop(&mut self) {
+        self.stop_stream();
+    }
+}
+
+impl Debugger {
+    /// Start recording the executed instructions, streaming them to the
+    /// specified text file (LZ4-compressed if its extension is .lz4).
+    pub fn start_insn_trace(&mut self, path: &Path) -> io::Result<()> {
+        let itrace = self.itrace.get_mut();
+        itrace.start_stream(&path.to_string_lossy())?;
+        itrace.enabled = true;
+        Ok(())
+    }
+
+    /// Stop recording the executed instructions, and close the trace file.
+    /// Returns the first error that happened while writing it, if any.
+    pub fn stop_insn_trace(&mut self) -> Result<(), String> {
+        let itrace = self.itrace.get_mut();
+        itrace.enabled = false;
+        itrace.stop_stream();
+        match itrace.stream_error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn record(itrace: &mut InsnTrace, cpu: &str, pc: u64) {
+        itrace.record(cpu, pc, pc as u32, &[("t0", pc)], || "nop".to_owned());
+    }
+
+    fn pcs(itrace: &InsnTrace, cpu: &str) -> Vec<u64> {
+        itrace.buffer(cpu).unwrap().iter().map(|e| e.pc).collect()
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let mut itrace = InsnTrace::default();
+        itrace.set_capacity(4);
+        for pc in 0..3 {
+            record(&mut itrace, "cpu", pc);
+        }
+        assert_eq!(pcs(&itrace, "cpu"), vec![0, 1, 2]);
+
+        // When full, the oldest instructions are overwritten, and iteration
+        // still goes from the oldest to the newest one.
+        for pc in 3..10 {
+            record(&mut itrace, "cpu", pc);
+        }
+        assert_eq!(pcs(&itrace, "cpu"), vec![6, 7, 8, 9]);
+        let last = itrace.buffer("cpu").unwrap().back().unwrap();
+        assert_eq!(last.opcode, 9);
+        assert_eq!(last.changed_to_string(), "t0=9");
+
+        // Each CPU has its own buffer.
+        record(&mut itrace, "rsp", 100);
+        assert_eq!(pcs(&itrace, "rsp"), vec![100]);
+        assert_eq!(pcs(&itrace, "cpu"), vec![6, 7, 8, 9]);
+        assert!(itrace.buffer("other").is_none());
+
+        // Shrinking the buffers keeps the newest instructions.
+        itrace.set_capacity(2);
+        assert_eq!(pcs(&itrace, "cpu"), vec![8, 9]);
+        record(&mut itrace, "cpu", 10);
+        assert_eq!(pcs(&itrace, "cpu"), vec![9, 10]);
+
+        itrace.clear();
+        assert!(itrace.buffer("cpu").is_none());
+    }
+
+    #[test]
+    fn stream_text() {
+        let path = env::temp_dir().join(format!("r64emu-itrace-{}.txt", std::process::id()));
+        let mut itrace = InsnTrace::default();
+        itrace.start_stream(&path.to_string_lossy()).unwrap();
+        itrace.record("cpu", 0x8000_1000, 0x2408_0001, &[("t0", 1)], || {
+            "addiu\tt0, zero, 1".to_owned()
+        });
+        itrace.stop_stream();
+        assert!(itrace.stream_error.is_none());
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            text,
+            format!("[cpu] 80001000 24080001 {:40} t0=1\n", "addiu t0, zero, 1")
+        );
+    }
+}
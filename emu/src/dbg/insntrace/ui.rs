@@ -0,0 +1,141 @@
+use super::*;
+use crate::dbg::uisupport::*;
+use crate::dbg::{Dock, UiCtx};
+use imgui::*;
+use std::time::Instant;
+use tinyfiledialogs::save_file_dialog_with_filter;
+
+impl Debugger {
+    // Render the window with the global settings of the instruction trace.
+    pub(crate) fn render_insn_trace_settings(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx) {
+        let itrace = self.itrace.get_mut();
+        Window::new(im_str!("Instruction trace"))
+            .size([300.0, 150.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.checkbox(im_str!("Record executed instructions"), &mut itrace.enabled);
+
+                let mut millions = (itrace.capacity / DEFAULT_CAPACITY) as i32;
+                ui.set_next_item_width(100.0);
+                if ui
+                    .input_int(im_str!("Million insns per CPU"), &mut millions)
+                    .build()
+                {
+                    itrace.set_capacity(millions.max(1) as usize * DEFAULT_CAPACITY);
+                }
+
+                if ui.small_button(im_str!("Clear")) {
+                    itrace.clear();
+                }
+                ui.same_line(0.0);
+                if itrace.stream.is_none() {
+                    if ui.small_button(im_str!("Stream to file...")) {
+                        if let Some(path) = save_file_dialog_with_filter(
+                            "Stream instruction trace",
+                            "trace.txt.lz4",
+                            &vec!["*.lz4"],
+                            "LZ4-compressed traces",
+                        ) {
+                            if let Err(err) = itrace.start_stream(&path) {
+                                ctx.error_msg = Some(format!("Cannot create trace file: {}", err));
+                            }
+                        }
+                    }
+                } else if ui.small_button(im_str!("Stop streaming")) {
+                    itrace.stop_stream();
+                }
+
+                if let Some((ref path, _)) = itrace.stream {
+                    ui.text(format!("Streaming to: {}", path));
+                }
+                for (name, buf) in itrace.cpus.iter() {
+                    ui.text(format!("[{}] {} instructions", name, buf.len()));
+                }
+                if let Some(err) = itrace.stream_error.take() {
+                    ctx.error_msg = Some(err);
+                }
+            });
+    }
+}
+
+// Render the window that allows to browse the instructions recorded for
+// a CPU. Double-clicking an instruction shows it in the disassembly view.
+pub(crate) fn render_insn_trace<'a, 'ui, V: InsnTraceView>(
+    ui: &'a Ui<'ui>,
+    ctx: &mut UiCtx,
+    dbg: &Debugger,
+    v: &V,
+) {
+    let itrace = dbg.itrace.borrow();
+    let buf = match itrace.buffer(v.name()) {
+        Some(buf) => buf,
+        None => return,
+    };
+    let cpu_name = v.name().to_owned();
+    let syms = v.symbols();
+    let mut show_pc = None;
+
+    let title = im_str!("[{}] Instruction trace", cpu_name);
+    if !ctx.layout.is_open(&title) {
+        return;
+    }
+    let mut opened = true;
+    let window = Window::new(&title).size([500.0, 400.0], Condition::FirstUseEver);
+    ctx.layout
+        .place(&title, Dock::Right, window)
+        .opened(&mut opened)
+        .build(ui, || {
+            ui.text(format!("{} instructions recorded", buf.len()));
+            ui.separator();
+            ChildWindow::new(&im_str!("###scrolling"))
+                .size([0.0, 0.0])
+                .always_vertical_scrollbar(true)
+                .build(ui, || {
+                    // When emulation stops, show the last executed instructions.
+                    if ctx.event.is_some() {
+                        ui.set_scroll_y(ui.scroll_max_y());
+                    }
+                    ImGuiListClipper::new(buf.len()).build(|start, end| {
+                        for idx in start..end {
+                            let e = &buf[idx];
+                            let dis = v.disasm_insn(e.pc, e.opcode).disasm();
+                            let fields: Vec<&str> = dis.splitn(2, "\t").collect();
+
+                            let gr = ui.begin_group();
+                            ui.text_disabled(format!("{:8}", idx as isize - buf.len() as isize));
+                            ui.same_line(80.0);
+                            ui.text(format!("{:08x}", e.pc));
+                            ui.same_line(150.0);
+                            ui.text(format!("{:08x}", e.opcode));
+                            ui.same_line(220.0);
+                            ui.text(fields[0]);
+                            ui.same_line(290.0);
+                            ui.text(fields.get(1).unwrap_or(&""));
+                            ui.same_line(450.0);
+                            ui.text_colored(
+                                [0.6, 0.6, 0.6, 1.0],
+                                format!("{:80}", e.changed_to_string()),
+                            );
+                            gr.end(&ui);
+
+                            if ui.is_item_hovered() {
+                                if let Some(label) = syms.and_then(|s| s.label(e.pc)) {
+                                    ui.tooltip_text(label);
+                                }
+                                if ui.is_mouse_double_clicked(MouseButton::Left) {
+                                    show_pc = Some(e.pc);
+                                }
+                            }
+                        }
+                    });
+                });
+        });
+    ctx.layout.set_open(&title, opened);
+
+    if let Some(pc) = show_pc {
+        if let Some(dctx) = ctx.disasm.get_mut(&cpu_name) {
+            dctx.force_pc = Some(pc);
+            dctx.cursor_pc = Some(pc);
+            dctx.blink_pc = Some((pc, Instant::now()));
+        }
+    }
+}
@@ -0,0 +1,876 @@
+use super::*;
+use crate::dbg::uisupport::{render_reg_tooltip, ImGuiListClipper};
+use crate::dbg::{Dock, UiCtxLayout};
+use crate::hw::RegDatabase;
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use imgui::*;
+use imgui_sys::igCaptureKeyboardFromApp;
+use sdl2::keyboard::Scancode;
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+use tinyfiledialogs::{open_file_dialog, save_file_dialog_with_filter};
+
+const NUM_COLUMNS: usize = 16;
+
+impl MemoryBank {
+    fn size(&self) -> usize {
+        (self.end - self.begin + 1) as usize
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct MemWindow {
+    contents_width_changed: bool,
+    curr_bank: usize,                   // current bank within MemoryView
+    edit_addr: Option<u64>,             // address currently being edited by user (if any)
+    inspect_addr: Option<u64>,          // address currently inspected in footer (if any)
+    inspect_size: usize,                // size in bytes of the memory being inspected
+    highlight_addr: Option<(u64, u64)>, // bytes currently highlighted in view
+    force_addr: Option<u64>,            // address that user requested to go to
+    edit_buf: ImString, // edit buffer used by input box to hold data written by user
+    edit_addr_focus: bool, // if true, this frame the edit input box must take focus
+    inspect_type: usize, // type of inspection (u8, i16, etc.)
+    inspect_endian: usize, // endianess of inspection
+    // Address and length typed in the dump/load popup, and the outcome of
+    // the last dump/load.
+    file_addr: ImString,
+    file_len: ImString,
+    file_msg: Option<Result<String, String>>,
+}
+
+#[derive(Default, Debug)]
+struct Sizes {
+    addr_digits_count: usize, // number of hex digits to print in the address column
+    line_height: f32,         // height of a line
+    glyph_width: f32,         // width of a char (monospace assumed)
+    hex_cell_width: f32,      // size of a single hex cell, including spacing (2 bytes)
+    hex_cell_spacing: f32,    // spacing between hex cells
+    spacing_between_mid_cols: f32, // central spacing between different 8-byte sequences
+    pos_hex_start: f32,       // X position where the hex dump starts
+    pos_hex_end: f32,         // X poistion where the hex dump ends
+    pos_ascii_start: f32,     // X position where the ASCII dump starts
+    pos_ascii_end: f32,       // X position where the ASCII dump ends
+    window_width: f32,        // total window width
+}
+
+fn color(r: usize, g: usize, b: usize) -> [f32; 4] {
+    [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0]
+}
+
+impl MemWindow {
+    fn calc_sizes(&self, ui: &Ui, addr: u64) -> Sizes {
+        let style = ui.clone_style();
+        let mut s = Sizes::default();
+        s.addr_digits_count = ((64 - addr.leading_zeros() as usize) + 3) / 4;
+        s.line_height = ui.text_line_height();
+        s.glyph_width = ui.calc_text_size(im_str!("F"), false, 0.0)[0];
+        s.hex_cell_spacing = s.glyph_width * 0.5;
+        s.hex_cell_width = s.glyph_width * 2.0 + s.hex_cell_spacing;
+        s.spacing_between_mid_cols = (s.hex_cell_width + s.hex_cell_spacing) * 0.25;
+        s.pos_hex_start = (s.addr_digits_count + 2) as f32 * s.glyph_width;
+        s.pos_hex_end =
+            s.pos_hex_start + s.hex_cell_width * NUM_COLUMNS as f32 + s.spacing_between_mid_cols;
+        s.pos_ascii_start = s.pos_hex_end + s.glyph_width;
+        s.pos_ascii_end = s.pos_ascii_start + NUM_COLUMNS as f32 * s.glyph_width;
+        s.window_width =
+            s.pos_ascii_end + style.scrollbar_size + style.window_padding[0] * 2.0 + s.glyph_width;
+        return s;
+    }
+
+    fn draw_highlight_rect(
+        &self,
+        ui: &Ui,
+        dl: &WindowDrawList,
+        s: &Sizes,
+        n: usize,
+        ncells: usize,
+    ) {
+        let color_highlight = [1.0, 1.0, 1.0, 0.2];
+        let pos = ui.cursor_screen_pos();
+        let has_mid = (n < NUM_COLUMNS / 2) && (n + ncells > NUM_COLUMNS / 2);
+        let width = ncells as f32 * s.hex_cell_width - s.hex_cell_spacing
+            + if has_mid {
+                s.spacing_between_mid_cols
+            } else {
+                0.0
+            };
+        dl.add_rect(
+            pos,
+            [pos[0] + width, pos[1] + s.line_height],
+            color_highlight,
+        )
+        .filled(true)
+        .build();
+    }
+
+    /// Request the view to scroll to the specified address. If the address
+    /// belongs to a different bank, the view switches to it.
+    pub(crate) fn goto(&mut self, addr: u64) {
+        self.force_addr = Some(addr);
+        self.edit_addr = None;
+        self.inspect_addr = None;
+        self.highlight_addr = None;
+    }
+
+    pub(crate) fn render(
+        &mut self,
+        ui: &Ui,
+        layout: &mut UiCtxLayout,
+        memview: &mut dyn MemoryView,
+        regdb: &RegDatabase,
+    ) {
+        // Requests to show an address reopen the window, if it was closed.
+        let title = im_str!("[{}]: Memory view", memview.name());
+        if self.force_addr.is_some() {
+            layout.set_open(&title, true);
+        }
+        if !layout.is_open(&title) {
+            return;
+        }
+
+        let banks = memview.banks();
+        if let Some(addr) = self.force_addr {
+            if let Some(idx) = banks.iter().position(|b| b.begin <= addr && addr <= b.end) {
+                self.curr_bank = idx;
+            }
+        }
+        let bank = &banks[self.curr_bank];
+        let s = self.calc_sizes(ui, bank.end);
+
+        let mut opened = true;
+        let window = Window::new(&title)
+            .scroll_bar(false)
+            .size([s.window_width, 300.0], Condition::FirstUseEver)
+            .size_constraints([0.0, 0.0], [s.window_width, 1e+9]);
+        layout
+            .place(&title, Dock::Right, window)
+            .opened(&mut opened)
+            .build(ui, || {
+                let mut curr_bank = self.curr_bank;
+
+                ui.set_next_item_width(130.0);
+                if ComboBox::new(im_str!("##cb")).build_simple(
+                    ui,
+                    &mut curr_bank,
+                    &banks,
+                    &|b: &MemoryBank| Cow::Owned(im_str!("{}", b.name)),
+                ) {
+                    self.edit_addr = None;
+                    self.edit_addr_focus = false;
+                    self.highlight_addr = None;
+                    self.inspect_addr = None;
+                }
+                ui.same_line(0.0);
+
+                if ui.button(im_str!("Goto.."), [0.0, 0.0]) {
+                    ui.open_popup(im_str!("##goto"));
+                }
+                ui.popup(im_str!("##goto"), || {
+                    let mut s = ImString::new("00000000");
+                    ui.text(im_str!("Address:"));
+                    if ui
+                        .input_text(im_str!("##input"), &mut s)
+                        .chars_hexadecimal(true)
+                        .enter_returns_true(true)
+                        .auto_select_all(true)
+                        .build()
+                    {
+                        if let Ok(addr) = u64::from_str_radix(s.as_ref(), 16) {
+                            self.goto(addr);
+                        }
+                        ui.close_current_popup();
+                    }
+                });
+                ui.same_line(0.0);
+
+                if ui.button(im_str!("File.."), [0.0, 0.0]) {
+                    // Default to the whole current bank.
+                    self.file_addr = ImString::with_capacity(16);
+                    self.file_addr.push_str(&format!("{:08x}", bank.begin));
+                    self.file_len = ImString::with_capacity(16);
+                    self.file_len.push_str(&format!("{:x}", bank.size()));
+                    self.file_msg = None;
+                    ui.open_popup(im_str!("##file"));
+                }
+                ui.popup(im_str!("##file"), || self.render_file_popup(ui, memview));
+
+                // Render main hex view area
+                self.render_contents(ui, memview, regdb, &s);
+                ui.separator();
+
+                // Footer
+                self.render_footer(ui, memview, &s);
+
+                self.curr_bank = curr_bank;
+            });
+        layout.set_open(&title, opened);
+    }
+
+    // Render the popup to dump a memory range into a binary file, or to load
+    // a binary file into memory.
+    fn render_file_popup(&mut self, ui: &Ui, memview: &mut dyn MemoryView) {
+        ui.text(im_str!("Address range (hex):"));
+        ui.input_text(im_str!("Address##file#addr"), &mut self.file_addr)
+            .chars_hexadecimal(true)
+            .build();
+        ui.input_text(im_str!("Length##file#len"), &mut self.file_len)
+            .chars_hexadecimal(true)
+            .build();
+        let addr = u64::from_str_radix(self.file_addr.to_str(), 16);
+        let len = u64::from_str_radix(self.file_len.to_str(), 16);
+
+        if ui.button(im_str!("Dump.."), [0.0, 0.0]) {
+            self.file_msg = match (addr, len) {
+                (Ok(addr), Ok(len)) => save_file_dialog_with_filter(
+                    "Dump memory",
+                    &format!("{}-{:08x}.bin", memview.name().to_lowercase(), addr),
+                    &vec!["*.bin"],
+                    "Binary files",
+                )
+                .map(|path| {
+                    describe_file_op(
+                        dump_mem(memview, addr, len, Path::new(&path)),
+                        addr,
+                        "Dumped",
+                    )
+                }),
+                _ => Some(Err("invalid address or length".into())),
+            };
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Load.."), [0.0, 0.0]) {
+            self.file_msg = match addr {
+                Ok(addr) => open_file_dialog("Load into memory", "", None).map(|path| {
+                    describe_file_op(load_mem(memview, addr, Path::new(&path)), addr, "Loaded")
+                }),
+                Err(_) => Some(Err("invalid address".into())),
+            };
+        }
+
+        match self.file_msg.as_ref() {
+            Some(Ok(msg)) => ui.text(msg),
+            Some(Err(err)) => ui.text_colored([1.0, 0.3, 0.3, 1.0], err),
+            None => {}
+        }
+    }
+
+    fn render_contents(
+        &mut self,
+        ui: &Ui,
+        memview: &mut dyn MemoryView,
+        regdb: &RegDatabase,
+        s: &Sizes,
+    ) {
+        let banks = memview.banks();
+        let bank = &banks[self.curr_bank];
+
+        let style = ui.clone_style();
+        let height_separator = style.item_spacing[1];
+        let mut footer_height = 0.0;
+
+        let color_disabled = ui.style_color(StyleColor::TextDisabled);
+        let color_text = ui.style_color(StyleColor::Text);
+        let color_border = ui.style_color(StyleColor::Border);
+
+        footer_height += height_separator + ui.frame_height_with_spacing(); // options
+        footer_height += height_separator
+            + ui.frame_height_with_spacing()
+            + ui.text_line_height_with_spacing() * 3.0; // data preview
+        ChildWindow::new(im_str!("##scrolling"))
+            .size([0.0, -footer_height])
+            .flags(WindowFlags::NO_MOVE)
+            .build(ui, || {
+                let st = ui.push_style_vars(&[
+                    StyleVar::FramePadding([0.0, 0.0]),
+                    StyleVar::ItemSpacing([0.0, 0.0]),
+                ]);
+
+                let num_lines = (bank.size() + NUM_COLUMNS - 1) / NUM_COLUMNS;
+
+                let mut clip = ImGuiListClipper::new(num_lines)
+                    .items_height(s.line_height)
+                    .begin();
+                let visible_start = clip.display_start();
+                let visible_end = clip.display_end();
+                let screen_lines = visible_end - visible_start;
+
+                if let Some(mut addr) = self.edit_addr {
+                    let prev_addr = addr;
+
+                    if ui.is_window_focused_with_flags(WindowFocusedFlags::ROOT_AND_CHILD_WINDOWS) {
+                        if ui.is_key_pressed(ui.key_index(Key::UpArrow)) {
+                            addr = addr.saturating_sub(NUM_COLUMNS as u64);
+                            self.edit_addr_focus = true;
+                        }
+                        if ui.is_key_pressed(ui.key_index(Key::DownArrow)) {
+                            addr = addr.saturating_add(NUM_COLUMNS as u64);
+                            self.edit_addr_focus = true;
+                        }
+                        if ui.is_key_pressed(ui.key_index(Key::LeftArrow)) {
+                            addr = addr.saturating_sub(1);
+                            self.edit_addr_focus = true;
+                        }
+                        if ui.is_key_pressed(ui.key_index(Key::RightArrow)) {
+                            addr = addr.saturating_add(1);
+                            self.edit_addr_focus = true;
+                        }
+                        if ui.is_key_pressed(Scancode::PageUp as _) {
+                            addr = addr.saturating_add((screen_lines * NUM_COLUMNS) as u64);
+                            self.edit_addr_focus = true;
+                        }
+                        if ui.is_key_pressed(Scancode::PageDown as _) {
+                            addr = addr.saturating_add((screen_lines * NUM_COLUMNS) as u64);
+                            self.edit_addr_focus = true;
+                        }
+                    }
+                    addr = bank.clamp(addr);
+                    self.edit_addr = Some(addr);
+                    if let Some((mut h1, mut h2)) = self.highlight_addr {
+                        let hsize = h2 - h1 + 1;
+                        if addr < h1 {
+                            h1 -= (h1 - addr + hsize - 1) / hsize * hsize;
+                        } else if addr > h2 {
+                            h1 += (addr - h2 + hsize - 1) / hsize * hsize;
+                        }
+                        h2 = h1 + hsize - 1;
+                        self.highlight_addr = Some((h1, h2));
+                        self.inspect_addr = Some(h1);
+                    }
+
+                    let addr_line = addr as usize / NUM_COLUMNS;
+                    let prev_addr_line = prev_addr as usize / NUM_COLUMNS;
+                    if (addr < prev_addr && addr_line < visible_start + 2)
+                        || (addr > prev_addr && addr_line > visible_end - 3)
+                    {
+                        ui.set_scroll_y(
+                            ui.scroll_y()
+                                + (addr_line.wrapping_sub(prev_addr_line) as i64) as f32
+                                    * s.line_height,
+                        );
+                    }
+                }
+
+                if let Some(mut addr) = self.force_addr {
+                    addr = bank.clamp(addr);
+
+                    let addr_line = addr as usize / NUM_COLUMNS;
+                    if addr_line < visible_start + 2 || addr_line > visible_end - 2 {
+                        ui.set_scroll_y(
+                            addr_line.wrapping_sub(screen_lines / 2) as f32 * s.line_height,
+                        );
+                    }
+                    self.force_addr = None;
+                }
+
+                let window_pos = ui.window_pos();
+                let dl = ui.get_window_draw_list();
+                let addr_separator_x = window_pos[0] + s.pos_hex_start - s.glyph_width;
+                let ascii_separator_x = window_pos[0] + s.pos_ascii_start - s.glyph_width;
+                dl.add_line(
+                    [addr_separator_x, window_pos[1]],
+                    [addr_separator_x, window_pos[1] + 1e5],
+                    color_border,
+                )
+                .build();
+                dl.add_line(
+                    [ascii_separator_x, window_pos[1]],
+                    [ascii_separator_x, window_pos[1] + 1e5],
+                    color_border,
+                )
+                .build();
+
+                let mut write_data = None;
+                clip.run(|start, end| {
+                    for line in start..end {
+                        let line_addr_start = bank.begin + (line as u64 * NUM_COLUMNS as u64);
+                        let line_addr_end =
+                            (line_addr_start + (NUM_COLUMNS as u64) - 1).min(bank.end);
+
+                        let mem = memview.mem_slice(self.curr_bank, line_addr_start, line_addr_end);
+                        ui.text_colored(
+                            color(174, 129, 255),
+                            &im_str!("{:01$X}", line_addr_start, s.addr_digits_count),
+                        );
+
+                        for addr in line_addr_start..=line_addr_end {
+                            let n = (addr - line_addr_start) as usize;
+                            ui.same_line(
+                                s.pos_hex_start
+                                    + n as f32 * s.hex_cell_width
+                                    + (n / 8) as f32 * s.spacing_between_mid_cols,
+                            );
+
+                            // Start of highlighting
+                            if let Some((h1, h2)) = self.highlight_addr {
+                                if addr == h1 {
+                                    // Draw the the highlight section
+                                    let hsize = (h2 - h1 + 1) as usize;
+                                    let ncells = hsize.min(NUM_COLUMNS - n);
+                                    self.draw_highlight_rect(ui, &dl, &s, n, ncells);
+                                } else if n == 0 && addr <= h2 && addr > h1 {
+                                    // The highlight section might span across two lines.
+                                    // Draw the remainder if so
+                                    let hsize = (h2 - addr + 1) as usize;
+                                    let ncells = hsize.min(NUM_COLUMNS - n);
+                                    self.draw_highlight_rect(ui, &dl, &s, n, ncells);
+                                }
+                            }
+
+                            if self.edit_addr == Some(addr) {
+                                let idt = ui.push_id((addr) as i32);
+                                let iwt = ui.push_item_width(s.glyph_width * 2.0);
+                                self.edit_buf = ImString::with_capacity(2);
+                                self.edit_buf.push_str(&format!("{:02X}", mem[n]));
+
+                                if self.edit_addr_focus {
+                                    ui.set_keyboard_focus_here(FocusedWidget::Previous);
+                                    unsafe {
+                                        igCaptureKeyboardFromApp(true);
+                                    }
+                                    self.edit_addr_focus = false;
+                                }
+
+                                if ui
+                                    .input_text(im_str!("##data"), &mut self.edit_buf)
+                                    .auto_select_all(true)
+                                    .chars_hexadecimal(true)
+                                    .chars_uppercase(true)
+                                    .enter_returns_true(true)
+                                    .no_horizontal_scroll(true)
+                                    .always_insert_mode(true)
+                                    .resize_buffer(false)
+                                    .build()
+                                {
+                                    write_data = Some((
+                                        addr,
+                                        u8::from_str_radix(self.edit_buf.to_str(), 16).unwrap(),
+                                    ));
+                                    self.edit_addr = Some(self.edit_addr.unwrap() + 1);
+                                    self.edit_addr_focus = true;
+                                }
+
+                                iwt.pop(&ui);
+                                idt.pop(&ui);
+                            } else {
+                                let val = mem[n];
+                                if val == 0 {
+                                    ui.text_disabled(&im_str!("{:02X}", mem[n]));
+                                } else {
+                                    ui.text(&im_str!("{:02X}", mem[n]));
+                                }
+                                // Decode hardware registers (assumed to be
+                                // 32-bit and naturally aligned).
+                                let reg_n = n & !3;
+                                let reg_val = mem.get(reg_n..reg_n + 4).map(|m| {
+                                    if self.inspect_endian == 0 {
+                                        LittleEndian::read_u32(m) as u64
+                                    } else {
+                                        BigEndian::read_u32(m) as u64
+                                    }
+                                });
+                                render_reg_tooltip(ui, regdb, addr, reg_val);
+                                if ui.is_item_hovered() && ui.is_mouse_clicked(MouseButton::Left) {
+                                    self.inspect_addr = Some(addr);
+                                    self.highlight_addr = Some((
+                                        addr,
+                                        addr.saturating_add(self.inspect_size as u64 - 1),
+                                    ));
+                                    if bank.rw {
+                                        self.edit_addr = Some(addr);
+                                        self.edit_addr_focus = true;
+                                    }
+                                }
+                            }
+                        }
+
+                        ui.same_line(s.pos_ascii_start);
+                        let mut pos = ui.cursor_screen_pos();
+
+                        let aid = ui.push_id(start as i32);
+                        if ui.invisible_button(
+                            im_str!("##ascii"),
+                            [s.pos_ascii_end - s.pos_ascii_start, s.line_height],
+                        ) {}
+                        aid.pop(ui);
+
+                        for val in mem {
+                            if *val < 32 || *val >= 128 {
+                                dl.add_text(pos, color_disabled, ".");
+                            } else {
+                                dl.add_text(pos, color_text, (*val as char).to_string());
+                            }
+                            pos[0] += s.glyph_width;
+                        }
+                    }
+                });
+                clip.end();
+                st.pop(&ui);
+
+                // If the user performed a memory write, execute it now.
+                if let Some((addr, val)) = write_data {
+                    let wmem = memview.mem_slice_mut(self.curr_bank, addr, addr);
+                    wmem[0] = val;
+                }
+            });
+    }
+
+    fn render_footer(&mut self, ui: &Ui, memview: &mut dyn MemoryView, s: &Sizes) {
+        let isizes: [&ImStr; 8] = [
+            im_str!("Uint8"),
+            im_str!("Uint16"),
+            im_str!("Uint32"),
+            im_str!("Uint64"),
+            im_str!("Int8"),
+            im_str!("Int16"),
+            im_str!("Int32"),
+            im_str!("Int64"),
+        ];
+        let endians: [&ImStr; 2] = [im_str!("LE"), im_str!("BE")];
+        let style = ui.clone_style();
+        let mut update_highlight = false;
+
+        ui.align_text_to_frame_padding();
+        if let Some(addr) = self.inspect_addr {
+            ui.text(im_str!("Inspect {:01$X} as:", addr, s.addr_digits_count));
+        } else {
+            ui.text(im_str!("Inspect as:"));
+        }
+        ui.same_line(0.0);
+        ui.set_next_item_width(
+            s.glyph_width * 10.0 + style.frame_padding[0] * 2.0 + style.item_inner_spacing[0],
+        );
+        if ComboBox::new(im_str!("##type"))
+            .height(ComboBoxHeight::Largest)
+            .build_simple_string(ui, &mut self.inspect_type, &isizes)
+        {
+            update_highlight = true;
+        }
+        ui.same_line(0.0);
+        ui.set_next_item_width(
+            s.glyph_width * 6.0 + style.frame_padding[0] * 2.0 + style.item_inner_spacing[0],
+        );
+        ComboBox::new(im_str!("##endian")).build_simple_string(
+            ui,
+            &mut self.inspect_endian,
+            &endians,
+        );
+
+        self.inspect_size = 1 << (self.inspect_type & 7);
+
+        if let Some(addr) = self.inspect_addr {
+            // Try to read enough bytes for the inspection, and ignore it if
+            // it's not possible (eg: we're at the end of the buffer).
+            let mem = memview.mem_slice(self.curr_bank, addr, addr + self.inspect_size as u64 - 1);
+            if mem.len() == self.inspect_size {
+                let (dec, hex, bin) = self.render_inspect_type(mem);
+                let x = s.glyph_width * 6.0;
+
+                ui.text(im_str!("Dec:"));
+                ui.same_line(x);
+                ui.text(im_str!("{}", dec));
+
+                ui.text(im_str!("Hex:"));
+                ui.same_line(x);
+                for (i, v) in hex.as_bytes().chunks(4).enumerate() {
+                    if i != 0 {
+                        ui.same_line(0.0);
+                    }
+                    ui.text(im_str!("{}", std::str::from_utf8_unchecked(v)));
+                }
+
+                ui.text(im_str!("Bin:"));
+                ui.same_line(x);
+                for (i, v) in bin.as_bytes().chunks(4).enumerate() {
+                    if i != 0 {
+                        if (i & 7) == 0 {
+                            ui.text(im_str!("")); // force newline
+                            ui.same_line(x);
+                        } else {
+                            ui.same_line(0.0);
+                        }
+                    }
+                    ui.text(im_str!("{}", std::str::from_utf8_unchecked(v)));
+                }
+
+                if update_highlight {
+                    self.highlight_addr =
+                        Some((addr, addr.saturating_add(self.inspect_size as u64 - 1)));
+                }
+            }
+        }
+    }
+
+    fn render_inspect_type(&self, mem: &[u8]) -> (String, String, String) {
+        match self.inspect_type {
+            0 | 4 => {
+                let val = mem[0];
+                if self.inspect_type == 0 {
+                    (
+                        format!("{}", val),
+                        format!("{:02x}", val),
+                        format!("{:08b}", val),
+                    )
+                } else {
+                    (
+                        format!("{}", val as i8),
+                        format!("{:02x}", val as i8),
+                        format!("{:08b}", val as i8),
+                    )
+                }
+            }
+            1 | 5 => {
+                let val = if self.inspect_endian == 0 {
+                    LittleEndian::read_u16(mem)
+                } else {
+                    BigEndian::read_u16(mem)
+                };
+                if self.inspect_type == 1 {
+                    (
+                        format!("{}", val),
+                        format!("{:04x}", val),
+                        format!("{:016b}", val),
+                    )
+                } else {
+                    (
+                        format!("{}", val as i16),
+                        format!("{:04x}", val as i16),
+                        format!("{:016b}", val as i16),
+                    )
+                }
+            }
+            2 | 6 => {
+                let val = if self.inspect_endian == 0 {
+                    LittleEndian::read_u32(mem)
+                } else {
+                    BigEndian::read_u32(mem)
+                };
+                if self.inspect_type == 1 {
+                    (
+                        format!("{}", val),
+                        format!("{:08x}", val),
+                        format!("{:032b}", val),
+                    )
+                } else {
+                    (
+                        format!("{}", val as i32),
+                        format!("{:08x}", val as i32),
+                        format!("{:032b}", val as i32),
+                    )
+                }
+            }
+            3 | 7 => {
+                let val = if self.inspect_endian == 0 {
+                    LittleEndian::read_u64(mem)
+                } else {
+                    BigEndian::read_u64(mem)
+                };
+                if self.inspect_type == 1 {
+                    (
+                        format!("{}", val),
+                        format!("{:016x}", val),
+                        format!("{:064b}", val),
+                    )
+                } else {
+                    (
+                        format!("{}", val as i64),
+                        format!("{:016x}", val as i64),
+                        format!("{:064b}", val as i64),
+                    )
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+// Save `len` bytes of memory starting at `addr` (translated with phys_addr)
+// into a binary file. The range can span multiple contiguous banks; it is
+// truncated at the first unmapped address. Returns the number of bytes
+// saved, or None if `addr` is not within any bank.
+pub(crate) fn dump_mem(
+    memview: &dyn MemoryView,
+    addr: u64,
+    len: u64,
+    path: &Path,
+) -> Option<Result<u64, String>> {
+    let start = memview.phys_addr(addr);
+    let mut data = read_mem(memview, start, len)?;
+    while (data.len() as u64) < len {
+        let next = start + data.len() as u64;
+        match read_mem(memview, next, len - data.len() as u64) {
+            Some(more) if !more.is_empty() => data.extend(more),
+            _ => break,
+        }
+    }
+    data.truncate(len as usize);
+    Some(
+        fs::write(path, &data)
+            .map(|_| data.len() as u64)
+            .map_err(|e| format!("cannot write {}: {}", path.display(), e)),
+    )
+}
+
+// Load a binary file into memory at `addr` (translated with phys_addr). The
+// whole file must fit within a single writable bank. Returns the number of
+// bytes loaded, or None if `addr` is not within any bank.
+pub(crate) fn load_mem(
+    memview: &mut dyn MemoryView,
+    addr: u64,
+    path: &Path,
+) -> Option<Result<u64, String>> {
+    let start = memview.phys_addr(addr);
+    if !memview
+        .banks()
+        .iter()
+        .any(|b| start >= b.begin && start <= b.end)
+    {
+        return None;
+    }
+    let res = fs::read(path)
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))
+        .and_then(|data| write_mem(memview, start, &data).map(|_| data.len() as u64));
+    Some(res)
+}
+
+// Describe the outcome of dump_mem / load_mem, for the user.
+pub(crate) fn describe_file_op(
+    res: Option<Result<u64, String>>,
+    addr: u64,
+    what: &str,
+) -> Result<String, String> {
+    match res {
+        Some(Ok(n)) => Ok(format!("{} {:#x} bytes at {:x}", what, n, addr)),
+        Some(Err(err)) => Err(err),
+        None => Err(format!("address {:x} not found in any memory bank", addr)),
+    }
+}
+
+// Format `len` bytes of memory starting at `addr` as a classic hex dump
+// (16 bytes per line, followed by their ASCII representation). The dump is
+// truncated at the end of the bank containing `addr`. Returns None if `addr`
+// is not within any bank.
+pub(crate) fn hex_dump(memview: &dyn MemoryView, addr: u64, len: u64) -> Option<Vec<String>> {
+    let data = read_mem(memview, addr, len)?;
+    let lines = data
+        .chunks(NUM_COLUMNS)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| if b >= 32 && b < 127 { b as char } else { '.' })
+                .collect();
+            format!(
+                "{:08x}: {:width$} |{}|",
+                addr + (i * NUM_COLUMNS) as u64,
+                hex.join(" "),
+                ascii,
+                width = NUM_COLUMNS * 3 - 1
+            )
+        })
+        .collect();
+    Some(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // A RAM bank followed by a contiguous ROM bank, also visible through a
+    // KSEG0-like mirror.
+    struct View {
+        ram: Vec<u8>,
+        rom: Vec<u8>,
+    }
+
+    impl MemoryView for View {
+        fn name(&self) -> &str {
+            "cpu"
+        }
+        fn banks(&self) -> Vec<MemoryBank> {
+            vec![
+                MemoryBank::new("ram", 0x0000, 0x0FFF, true),
+                MemoryBank::new("rom", 0x1000, 0x1FFF, false),
+            ]
+        }
+        fn mem_slice(&self, bank_idx: usize, start: u64, end: u64) -> &[u8] {
+            match bank_idx {
+                0 => &self.ram[start as usize..=end as usize],
+                _ => &self.rom[start as usize - 0x1000..=end as usize - 0x1000],
+            }
+        }
+        fn mem_slice_mut(&mut self, bank_idx: usize, start: u64, end: u64) -> &mut [u8] {
+            match bank_idx {
+                0 => &mut self.ram[start as usize..=end as usize],
+                _ => &mut self.rom[start as usize - 0x1000..=end as usize - 0x1000],
+            }
+        }
+        fn phys_addr(&self, addr: u64) -> u64 {
+            addr & 0x1FFF_FFFF
+        }
+    }
+
+    fn view() -> View {
+        View {
+            ram: (0..0x1000).map(|i| i as u8).collect(),
+            rom: vec![0xFF; 0x1000],
+        }
+    }
+
+    #[test]
+    fn dump() {
+        let v = view();
+        let path = env::temp_dir().join(format!("r64emu-dump-{}.bin", std::process::id()));
+
+        // Dumps span contiguous banks, and stop at the first unmapped address.
+        assert_eq!(dump_mem(&v, 0x8000_0FF8, 0x10, &path), Some(Ok(0x10)));
+        let mut exp = (0xF8..=0xFF).collect::<Vec<u8>>();
+        exp.extend(&[0xFF; 8]);
+        assert_eq!(fs::read(&path).unwrap(), exp);
+
+        assert_eq!(dump_mem(&v, 0x1FF8, 0x100, &path), Some(Ok(8)));
+        assert_eq!(fs::read(&path).unwrap(), vec![0xFF; 8]);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(dump_mem(&v, 0x2000, 0x10, &path), None);
+        assert_eq!(
+            describe_file_op(None, 0x2000, "Dumped"),
+            Err("address 2000 not found in any memory bank".into())
+        );
+    }
+
+    #[test]
+    fn load() {
+        let mut v = view();
+        let path = env::temp_dir().join(format!("r64emu-load-{}.bin", std::process::id()));
+        fs::write(&path, &[0xAA, 0xBB, 0xCC, 0xDD]).unwrap();
+
+        let res = load_mem(&mut v, 0x8000_0010, &path);
+        assert_eq!(
+            describe_file_op(res, 0x8000_0010, "Loaded"),
+            Ok("Loaded 0x4 bytes at 80000010".into())
+        );
+        assert_eq!(&v.ram[0x0F..0x15], &[0x0F, 0xAA, 0xBB, 0xCC, 0xDD, 0x14]);
+
+        // Files must fit within a single writable bank.
+        assert_eq!(
+            load_mem(&mut v, 0x1000, &path),
+            Some(Err("memory bank is read-only: rom".into()))
+        );
+        assert_eq!(
+            load_mem(&mut v, 0x0FFE, &path),
+            Some(Err("invalid address range: ffe-1001".into()))
+        );
+        assert_eq!(load_mem(&mut v, 0x2000, &path), None);
+        assert_eq!(v.rom, vec![0xFF; 0x1000]);
+        assert_eq!(&v.ram[0xFFE..], &[0xFE, 0xFF]);
+        fs::remove_file(&path).unwrap();
+
+        match load_mem(&mut v, 0, &path) {
+            Some(Err(err)) => assert!(err.starts_with("cannot read")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,447 @@
+use super::callstackview::CallStack;
+use super::decoding::{DecodedInsn, InsnFlags};
+use super::symbols::SymbolTable;
+use super::tracer::{Debugger, IntHashMap};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+#[cfg(feature = "frontend")]
+mod ui;
+#[cfg(feature = "frontend")]
+pub use self::ui::*;
+
+// Maximum number of rows displayed in the profiler tables.
+const MAX_ROWS: usize = 500;
+
+#[derive(Copy, Clone, Default)]
+struct BlockStats {
+    count: u64, // number of times the block was entered
+    insns: u64, // total number of instructions executed within the block
+}
+
+// Executions of an opcode class, together with the metadata of its
+// instructions (see DecodedInsn).
+struct OpcodeStats {
+    name: &'static str,
+    cycles: u32, // nominal cycles of each instruction
+    flags: InsnFlags,
+    count: u64,
+}
+
+// Instruction mix: number of executed branches, loads and stores, and the
+// total of the nominal cycles of the executed instructions.
+#[derive(Debug, Default, PartialEq)]
+struct InsnMix {
+    branches: u64,
+    loads: u64,
+    stores: u64,
+    cycles: u64,
+}
+
+#[derive(Default)]
+struct CpuProfile {
+    insns: u64,
+    cycles: u64,
+    opcodes: IntHashMap<u32, OpcodeStats>, // class -> stats
+    blocks: IntHashMap<u64, BlockStats>,   // start pc -> stats
+    // Cycles spent in each block, indexed by the addresses of the functions
+    // in the call stack (outermost first) followed by the block PC.
+    stacks: HashMap<Vec<u64>, u64>,
+    // Call stack at the beginning of the block being executed.
+    cur_stack: Vec<u64>,
+}
+
+// A node of the call tree: cycles spent in a function (including its
+// callees), when called through the path from the root.
+#[derive(Default)]
+struct CallNode {
+    cycles: u64,
+    children: BTreeMap<String, CallNode>,
+}
+
+impl CallNode {
+    fn self_cycles(&self) -> u64 {
+        self.cycles - self.children.values().map(|c| c.cycles).sum::<u64>()
+    }
+
+    // Children sorted by decreasing cycles.
+    fn sorted_children(&self) -> Vec<(&String, &CallNode)> {
+        let mut res: Vec<_> = self.children.iter().collect();
+        res.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles));
+        res
+    }
+}
+
+// Resolve a sampled call stack into function names (outermost first). The
+// block PC is resolved to the function containing it; if it has no symbol,
+// the block is attributed to the innermost called function. Addresses
+// without a symbol are shown in hex, and consecutive duplicates are merged
+// (eg: a block within the innermost called function).
+fn stack_names(stack: &[u64], syms: Option<&SymbolTable>) -> Vec<String> {
+    let resolve = |addr: u64| {
+        syms.and_then(|s| s.resolve(addr))
+            .map(|(name, _)| name.to_owned())
+    };
+    let (block_pc, funcs) = stack.split_last().unwrap();
+    let mut names: Vec<String> = Vec::new();
+    for &func in funcs {
+        let name = resolve(func).unwrap_or_else(|| format!("{:08x}", func));
+        if names.last() != Some(&name) {
+            names.push(name);
+        }
+    }
+    match resolve(*block_pc) {
+        Some(name) if names.last() != Some(&name) => names.push(name),
+        Some(_) => {}
+        None if names.is_empty() => names.push(format!("{:08x}", block_pc)),
+        None => {}
+    }
+    names
+}
+
+impl CpuProfile {
+    // Opcode counts and nominal cycles, aggregated by mnemonic (multiple
+    // classes can map to the same mnemonic), sorted by decreasing count.
+    fn opcodes(&self) -> Vec<(&'static str, u64, u64)> {
+        let mut agg = BTreeMap::new();
+        for (_, op) in self.opcodes.iter() {
+            let e = agg.entry(op.name).or_insert((0, 0));
+            e.0 += op.count;
+            e.1 += op.count * op.cycles as u64;
+        }
+        let mut res: Vec<_> = agg.into_iter().map(|(n, (c, cy))| (n, c, cy)).collect();
+        res.sort_by(|a, b| b.1.cmp(&a.1));
+        res
+    }
+
+    fn mix(&self) -> InsnMix {
+        let mut mix = InsnMix::default();
+        for (_, op) in self.opcodes.iter() {
+            if op.flags.contains(InsnFlags::BRANCH) {
+                mix.branches += op.count;
+            }
+            if op.flags.contains(InsnFlags::LOAD) {
+                mix.loads += op.count;
+            }
+            if op.flags.contains(InsnFlags::STORE) {
+                mix.stores += op.count;
+            }
+            mix.cycles += op.count * op.cycles as u64;
+        }
+        mix
+    }
+
+    // Basic blocks sorted by decreasing number of executions or executed
+    // instructions.
+    fn blocks(&self, by_insns: bool) -> Vec<(u64, BlockStats)> {
+        let mut res: Vec<_> = self.blocks.iter().map(|(pc, st)| (*pc, *st)).collect();
+        if by_insns {
+            res.sort_by(|a, b| b.1.insns.cmp(&a.1.insns));
+        } else {
+            res.sort_by(|a, b| b.1.count.cmp(&a.1.count));
+        }
+        res
+    }
+
+    // Cycles spent in each function: (name, self cycles, total cycles
+    // including callees), sorted by decreasing self or total cycles.
+    fn functions(&self, syms: Option<&SymbolTable>, by_total: bool) -> Vec<(String, u64, u64)> {
+        let mut funcs: HashMap<String, (u64, u64)> = HashMap::new();
+        for (stack, cycles) in self.stacks.iter() {
+            let mut names = stack_names(stack, syms);
+            funcs.entry(names.last().unwrap().clone()).or_default().0 += cycles;
+            // Recursive functions are counted once
+            names.sort();
+            names.dedup();
+            for name in names {
+                funcs.entry(name).or_default().1 += cycles;
+            }
+        }
+        let mut res: Vec<_> = funcs.into_iter().map(|(n, (s, t))| (n, s, t)).collect();
+        if by_total {
+            res.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        } else {
+            res.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        }
+        res
+    }
+
+    fn call_tree(&self, syms: Option<&SymbolTable>) -> CallNode {
+        let mut root = CallNode::default();
+        for (stack, cycles) in self.stacks.iter() {
+            root.cycles += cycles;
+            let mut node = &mut root;
+            for name in stack_names(stack, syms) {
+                node = node.children.entry(name).or_default();
+                node.cycles += cycles;
+            }
+        }
+        root
+    }
+
+    // Call stacks in the "collapsed" format used by flamegraph tools: one
+    // line per stack, with function names separated by semicolons, followed
+    // by the number of cycles.
+    fn collapsed(&self, syms: Option<&SymbolTable>) -> String {
+        let mut stacks: BTreeMap<String, u64> = BTreeMap::new();
+        for (stack, cycles) in self.stacks.iter() {
+            *stacks
+                .entry(stack_names(stack, syms).join(";"))
+                .or_default() += cycles;
+        }
+        stacks
+            .into_iter()
+            .map(|(stack, cycles)| format!("{} {}\n", stack, cycles))
+            .collect()
+    }
+}
+
+/// Profiler counts the instructions executed by each CPU (grouped by
+/// opcode), and the number of times each basic block is executed. It is
+/// meant to find the hot spots of the emulated code, to guide
+/// optimizations of the interpreter.
+///
+/// The emulated cycles spent in each block are also recorded together with
+/// the call stack, so that they can be attributed to functions (through the
+/// symbol table), to profile the emulated code itself.
+#[derive(Default)]
+pub(crate) struct Profiler {
+    enabled: bool,
+    sort_by_insns: bool,
+    sort_by_total: bool,
+    cpus: Vec<(String, CpuProfile)>,
+}
+
+impl Profiler {
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn cpu(&mut self, cpu_name: &str) -> &mut CpuProfile {
+        let idx = match self.cpus.iter().position(|(name, _)| name == cpu_name) {
+            Some(idx) => idx,
+            None => {
+                self.cpus.push((cpu_name.to_owned(), CpuProfile::default()));
+                self.cpus.len() - 1
+            }
+        };
+        &mut self.cpus[idx].1
+    }
+
+    pub(crate) fn count_insn<D: FnOnce() -> DecodedInsn>(
+        &mut self,
+        cpu_name: &str,
+        class: u32,
+        decode: D,
+    ) {
+        let cpu = self.cpu(cpu_name);
+        cpu.insns += 1;
+        cpu.opcodes
+            .entry(class)
+            .or_insert_with(|| {
+                let insn = decode();
+                OpcodeStats {
+                    name: insn.op,
+                    cycles: insn.cycles,
+                    flags: insn.flags,
+                    count: 0,
+                }
+            })
+            .count += 1;
+    }
+
+    // Record the call stack at the beginning of a block, to attribute its
+    // cycles when it ends.
+    pub(crate) fn enter_block(&mut self, cpu_name: &str, stack: &CallStack) {
+        let cpu = self.cpu(cpu_name);
+        cpu.cur_stack.clear();
+        cpu.cur_stack
+            .extend(stack.frames().iter().map(|frame| frame.func));
+    }
+
+    pub(crate) fn count_block(&mut self, cpu_name: &str, pc: u64, len: u64, cycles: u64) {
+        let cpu = self.cpu(cpu_name);
+        let stats = cpu.blocks.entry(pc).or_default();
+        stats.count += 1;
+        stats.insns += len;
+
+        cpu.cycles += cycles;
+        cpu.cur_stack.push(pc);
+        match cpu.stacks.get_mut(&cpu.cur_stack[..]) {
+            Some(c) => *c += cycles,
+            None => {
+                cpu.stacks.insert(cpu.cur_stack.clone(), cycles);
+            }
+        }
+        cpu.cur_stack.pop();
+    }
+
+    /// Export the call stacks of a CPU in the collapsed format used by
+    /// flamegraph tools.
+    pub(crate) fn save_collapsed(
+        &mut self,
+        cpu_name: &str,
+        syms: Option<&SymbolTable>,
+        path: &Path,
+    ) -> std::io::Result<()> {
+        fs::write(path, self.cpu(cpu_name).collapsed(syms))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.cpus.iter().all(|(_, cpu)| cpu.insns == 0)
+    }
+
+    fn clear(&mut self) {
+        self.cpus.clear();
+    }
+
+    /// Dump the profile into a JSON file. Nothing is written if no
+    /// instruction was profiled.
+    pub(crate) fn save(&self, path: &Path) -> std::io::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let mut cpus = serde_json::Map::new();
+        for (name, cpu) in self.cpus.iter() {
+            let opcodes: serde_json::Map<_, _> = cpu
+                .opcodes()
+                .into_iter()
+                .map(|(name, count, _)| (name.to_owned(), json!(count)))
+                .collect();
+            let opcode_cycles: serde_json::Map<_, _> = cpu
+                .opcodes()
+                .into_iter()
+                .map(|(name, _, cycles)| (name.to_owned(), json!(cycles)))
+                .collect();
+            let mix = cpu.mix();
+            let blocks: Vec<_> = cpu
+                .blocks(true)
+                .into_iter()
+                .map(|(pc, st)| {
+                    json!({
+                        "pc": format!("0x{:08x}", pc),
+                        "count": st.count,
+                        "insns": st.insns,
+                    })
+                })
+                .collect();
+            cpus.insert(
+                name.clone(),
+                json!({
+                    "instructions": cpu.insns,
+                    "cycles": cpu.cycles,
+                    "opcodes": opcodes,
+                    "opcode_cycles": opcode_cycles,
+                    "mix": {
+                        "branches": mix.branches,
+                        "loads": mix.loads,
+                        "stores": mix.stores,
+                        "nominal_cycles": mix.cycles,
+                    },
+                    "blocks": blocks,
+                }),
+            );
+        }
+        fs::write(path, serde_json::to_string_pretty(&cpus)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregate() {
+        let mut p = Profiler::default();
+        let lw = DecodedInsn::new0("lw").with_flags(InsnFlags::LOAD);
+        p.count_insn("cpu", 1, || DecodedInsn::new0("addiu"));
+        p.count_insn("cpu", 2, || DecodedInsn::new0("addiu"));
+        p.count_insn("cpu", 2, || unreachable!());
+        p.count_insn("cpu", 3, || lw);
+        p.count_insn("cpu", 4, || DecodedInsn::new0("div").with_cycles(37));
+        p.count_block("cpu", 0x1000, 2, 2);
+        p.count_block("cpu", 0x2000, 1, 1);
+        p.count_block("cpu", 0x2000, 1, 1);
+        p.count_block("cpu", 0x2000, 1, 1);
+        p.count_block("cpu", 0x1000, 10, 10);
+
+        let cpu = p.cpu("cpu");
+        assert_eq!(cpu.insns, 5);
+        assert_eq!(
+            cpu.opcodes(),
+            vec![("addiu", 3, 3), ("div", 1, 37), ("lw", 1, 1)]
+        );
+        assert_eq!(
+            cpu.mix(),
+            InsnMix {
+                branches: 0,
+                loads: 1,
+                stores: 0,
+                cycles: 41,
+            }
+        );
+
+        let by_count: Vec<u64> = cpu.blocks(false).iter().map(|b| b.0).collect();
+        assert_eq!(by_count, vec![0x2000, 0x1000]);
+        let by_insns: Vec<u64> = cpu.blocks(true).iter().map(|b| b.0).collect();
+        assert_eq!(by_insns, vec![0x1000, 0x2000]);
+    }
+
+    #[test]
+    fn functions() {
+        let mut syms = SymbolTable::new();
+        syms.insert(0x1000, "main", Some(0x100));
+        syms.insert(0x2000, "draw", Some(0x100));
+        syms.insert(0x3000, "memcpy", Some(0x100));
+
+        let mut stack = CallStack::new();
+        let mut p = Profiler::default();
+        p.enter_block("cpu", &stack);
+        p.count_block("cpu", 0x1000, 4, 10);
+        stack.call(0x1010, 0x2000, 0x1018, 0);
+        p.enter_block("cpu", &stack);
+        p.count_block("cpu", 0x2000, 8, 100);
+        stack.call(0x2020, 0x3000, 0x2028, 0);
+        p.enter_block("cpu", &stack);
+        p.count_block("cpu", 0x3000, 2, 50);
+        p.count_block("cpu", 0x3000, 2, 50);
+        stack.jump(0x2028);
+        p.enter_block("cpu", &stack);
+        p.count_block("cpu", 0x2028, 1, 5);
+        // Unknown function, called from draw
+        stack.call(0x2030, 0x4000, 0x2038, 0);
+        p.enter_block("cpu", &stack);
+        p.count_block("cpu", 0x4000, 1, 7);
+
+        let cpu = p.cpu("cpu");
+        assert_eq!(cpu.cycles, 222);
+        assert_eq!(
+            cpu.functions(Some(&syms), false),
+            vec![
+                ("draw".to_owned(), 105, 212),
+                ("memcpy".to_owned(), 100, 100),
+                ("main".to_owned(), 10, 10),
+                ("00004000".to_owned(), 7, 7),
+            ]
+        );
+        assert_eq!(cpu.functions(Some(&syms), true)[0].0, "draw");
+
+        let tree = cpu.call_tree(Some(&syms));
+        assert_eq!(tree.cycles, 222);
+        assert_eq!(tree.children["main"].self_cycles(), 10);
+        let draw = &tree.children["draw"];
+        assert_eq!((draw.cycles, draw.self_cycles()), (212, 105));
+        assert_eq!(draw.children["memcpy"].cycles, 100);
+
+        assert_eq!(
+            cpu.collapsed(Some(&syms)),
+            "draw 105\ndraw;00004000 7\ndraw;memcpy 100\nmain 10\n"
+        );
+        assert_eq!(
+            cpu.collapsed(None),
+            "00001000 10\n00002000 105\n00002000;00003000 100\n00002000;00004000 7\n"
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use super::*;
+use crate::dbg::insntrace::InsnTraceView;
+use crate::dbg::{Dock, UiCtx};
+use imgui::*;
+use std::time::Instant;
+use tinyfiledialogs::save_file_dialog_with_filter;
+
+// Render the profiler window for a CPU. Double-clicking a basic block shows
+// it in the disassembly view.
+pub(crate) fn render_profiler<'a, 'ui, V: InsnTraceView>(
+    ui: &'a Ui<'ui>,
+    ctx: &mut UiCtx,
+    dbg: &Debugger,
+    v: &V,
+) {
+    let mut profiler = dbg.profiler.borrow_mut();
+    let cpu_name = v.name().to_owned();
+    let syms = v.symbols();
+    let mut show_pc = None;
+
+    let title = im_str!("[{}] Profiler", cpu_name);
+    if !ctx.layout.is_open(&title) {
+        return;
+    }
+    let mut opened = true;
+    let window = Window::new(&title).size([400.0, 450.0], Condition::FirstUseEver);
+    ctx.layout
+        .place(&title, Dock::Right, window)
+        .opened(&mut opened)
+        .build(ui, || {
+            ui.checkbox(
+                im_str!("Profile executed instructions"),
+                &mut profiler.enabled,
+            );
+            if ui.small_button(im_str!("Clear")) {
+                profiler.clear();
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Save JSON...")) {
+                if let Some(path) = save_file_dialog_with_filter(
+                    "Save profile",
+                    "profile.json",
+                    &vec!["*.json"],
+                    "JSON files",
+                ) {
+                    if let Err(err) = profiler.save(Path::new(&path)) {
+                        ctx.error_msg = Some(format!("Cannot save profile: {}", err));
+                    }
+                }
+            }
+
+            let cpu = profiler.cpu(&cpu_name);
+            let (total, mix) = (cpu.insns.max(1) as f64, cpu.mix());
+            ui.text(format!("{} instructions profiled", cpu.insns));
+            ui.text(format!(
+                "Branches: {:.1}%  Loads: {:.1}%  Stores: {:.1}%",
+                mix.branches as f64 * 100.0 / total,
+                mix.loads as f64 * 100.0 / total,
+                mix.stores as f64 * 100.0 / total,
+            ));
+            ui.separator();
+
+            if ui
+                .collapsing_header(im_str!("Hottest blocks"))
+                .default_open(true)
+                .build()
+            {
+                ui.text("Sort by:");
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("Executions"), &mut profiler.sort_by_insns, false);
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("Instructions"), &mut profiler.sort_by_insns, true);
+
+                let by_insns = profiler.sort_by_insns;
+                let cpu = profiler.cpu(&cpu_name);
+                let total = cpu.insns.max(1) as f64;
+                ChildWindow::new(&im_str!("###blocks"))
+                    .size([0.0, 200.0])
+                    .always_vertical_scrollbar(true)
+                    .build(ui, || {
+                        ui.text_disabled("PC");
+                        ui.same_line(100.0);
+                        ui.text_disabled("Executions");
+                        ui.same_line(200.0);
+                        ui.text_disabled("Insns");
+                        ui.same_line(300.0);
+                        ui.text_disabled("%");
+                        for (pc, st) in cpu.blocks(by_insns).into_iter().take(MAX_ROWS) {
+                            let gr = ui.begin_group();
+                            ui.text(format!("{:08x}", pc));
+                            ui.same_line(100.0);
+                            ui.text(format!("{}", st.count));
+                            ui.same_line(200.0);
+                            ui.text(format!("{}", st.insns));
+                            ui.same_line(300.0);
+                            ui.text(format!("{:.2}", st.insns as f64 * 100.0 / total));
+                            gr.end(&ui);
+
+                            if ui.is_item_hovered() && ui.is_mouse_double_clicked(MouseButton::Left)
+                            {
+                                show_pc = Some(pc);
+                            }
+                        }
+                    });
+            }
+
+            if ui
+                .collapsing_header(im_str!("Functions"))
+                .default_open(true)
+                .build()
+            {
+                ui.text("Sort by:");
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("Self"), &mut profiler.sort_by_total, false);
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("Total"), &mut profiler.sort_by_total, true);
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Export flamegraph...")) {
+                    if let Some(path) = save_file_dialog_with_filter(
+                        "Export collapsed stacks",
+                        "stacks.folded",
+                        &vec!["*.folded", "*.txt"],
+                        "Collapsed stacks",
+                    ) {
+                        if let Err(err) = profiler.save_collapsed(&cpu_name, syms, Path::new(&path))
+                        {
+                            ctx.error_msg = Some(format!("Cannot export stacks: {}", err));
+                        }
+                    }
+                }
+
+                let by_total = profiler.sort_by_total;
+                let cpu = profiler.cpu(&cpu_name);
+                let total = cpu.cycles.max(1) as f64;
+                ChildWindow::new(&im_str!("###functions"))
+                    .size([0.0, 200.0])
+                    .always_vertical_scrollbar(true)
+                    .build(ui, || {
+                        ui.text_disabled("Function");
+                        ui.same_line(200.0);
+                        ui.text_disabled("Self %");
+                        ui.same_line(280.0);
+                        ui.text_disabled("Total %");
+                        for (name, selfc, totalc) in
+                            cpu.functions(syms, by_total).into_iter().take(MAX_ROWS)
+                        {
+                            ui.text(&name);
+                            ui.same_line(200.0);
+                            ui.text(format!("{:.2}", selfc as f64 * 100.0 / total));
+                            ui.same_line(280.0);
+                            ui.text(format!("{:.2}", totalc as f64 * 100.0 / total));
+                            if ui.is_item_hovered() {
+                                ui.tooltip_text(format!("{} / {} cycles", selfc, totalc));
+                            }
+                        }
+                    });
+            }
+
+            if ui
+                .collapsing_header(im_str!("Call tree"))
+                .default_open(false)
+                .build()
+            {
+                let cpu = profiler.cpu(&cpu_name);
+                let tree = cpu.call_tree(syms);
+                ChildWindow::new(&im_str!("###calltree"))
+                    .size([0.0, 200.0])
+                    .always_vertical_scrollbar(true)
+                    .build(ui, || {
+                        render_call_tree(ui, &tree, tree.cycles.max(1) as f64, "");
+                    });
+            }
+
+            if ui
+                .collapsing_header(im_str!("Opcodes"))
+                .default_open(true)
+                .build()
+            {
+                // Opcodes are also weighted by their nominal cycles, so that
+                // expensive ones (eg: divisions) stand out.
+                let cpu = profiler.cpu(&cpu_name);
+                let total = cpu.insns.max(1) as f64;
+                let total_cycles = cpu.mix().cycles.max(1) as f64;
+                ChildWindow::new(&im_str!("###opcodes"))
+                    .size([0.0, 0.0])
+                    .always_vertical_scrollbar(true)
+                    .build(ui, || {
+                        ui.text_disabled("Opcode");
+                        ui.same_line(100.0);
+                        ui.text_disabled("Count");
+                        ui.same_line(200.0);
+                        ui.text_disabled("%");
+                        ui.same_line(270.0);
+                        ui.text_disabled("Cycles %");
+                        for (name, count, cycles) in cpu.opcodes().into_iter().take(MAX_ROWS) {
+                            ui.text(name);
+                            ui.same_line(100.0);
+                            ui.text(format!("{}", count));
+                            ui.same_line(200.0);
+                            ui.text(format!("{:.2}", count as f64 * 100.0 / total));
+                            ui.same_line(270.0);
+                            ui.text(format!("{:.2}", cycles as f64 * 100.0 / total_cycles));
+                        }
+                    });
+            }
+        });
+    ctx.layout.set_open(&title, opened);
+
+    if let Some(pc) = show_pc {
+        if let Some(dctx) = ctx.disasm.get_mut(&cpu_name) {
+            dctx.force_pc = Some(pc);
+            dctx.cursor_pc = Some(pc);
+            dctx.blink_pc = Some((pc, Instant::now()));
+        }
+    }
+}
+
+// Render the children of a call tree node, as tree nodes showing the
+// percentage of total and self cycles. `path` makes node IDs unique.
+fn render_call_tree(ui: &Ui, node: &CallNode, total: f64, path: &str) {
+    for (name, child) in node.sorted_children().into_iter().take(MAX_ROWS) {
+        let path = format!("{};{}", path, name);
+        let label = im_str!(
+            "{}  {:.2}% (self {:.2}%)###{}",
+            name,
+            child.cycles as f64 * 100.0 / total,
+            child.self_cycles() as f64 * 100.0 / total,
+            path
+        );
+        ui.tree_node(&label)
+            .leaf(child.children.is_empty())
+            .build(|| render_call_tree(ui, child, total, &path));
+    }
+}
@@ -0,0 +1,190 @@
+use super::*;
+use crate::dbg::uisupport::*;
+use crate::dbg::{CmdOutput, Dock, RegHighlight, TraceEvent, UiCtx};
+use imgui::*;
+
+const COLOR_BG_NORMAL: [f32; 4] = [41.0 / 255.0, 74.0 / 255.0, 122.0 / 255.0, 138.0 / 255.0];
+const COLOR_BG_INPUT: [f32; 4] = [86.0 / 255.0, 171.0 / 255.0, 60.0 / 255.0, 138.0 / 255.0];
+const COLOR_BG_OUTPUT: [f32; 4] = [204.0 / 255.0, 61.0 / 255.0, 61.0 / 255.0, 138.0 / 255.0];
+const COLOR_TEXT_CHANGED: [f32; 4] = [255.0 / 255.0, 200.0 / 255.0, 60.0 / 255.0, 1.0];
+
+// Return the value of a register, to check whether it changed.
+fn reg_value(val: &RegisterSize) -> u128 {
+    use super::RegisterSize::*;
+    match val {
+        Reg8(v) => **v as u128,
+        Reg16(v) => **v as u128,
+        Reg32(v) => **v as u128,
+        Reg64(v) => **v as u128,
+        Reg16x8(v) => v.iter().fold(0, |acc, &lane| (acc << 16) | lane as u128),
+    }
+}
+
+// Show an editable register. Edits are applied only if `editable` is true
+// (otherwise, they're discarded, and the current value is shown again).
+fn input_reg<T: HexableInt>(ui: &Ui, name: &ImStr, val: &mut T, editable: bool) {
+    let mut v = *val;
+    if imgui_input_hex(ui, name, &mut v, true) && editable {
+        *val = v;
+    }
+}
+
+// Serve a register access requested from the command line, if it refers to
+// a register of this view. Writes are truncated to the register size.
+fn serve_reg_access<RV: RegisterView>(ctx: &mut UiCtx, v: &mut RV) {
+    let (reg, newval) = match ctx.cmdline.reg_access {
+        Some((ref cpu, ref reg, newval)) if cpu == v.cpu_name() => (reg.clone(), newval),
+        _ => return,
+    };
+    let mut result = None;
+    for col in 0..RV::COLUMNS {
+        v.visit_regs(col, |rname, mut val, _| {
+            use super::RegisterSize::*;
+            if result.is_some() || !rname.eq_ignore_ascii_case(&reg) {
+                return;
+            }
+            if let Some(nv) = newval {
+                match &mut val {
+                    Reg8(v) => **v = nv as u8,
+                    Reg16(v) => **v = nv as u16,
+                    Reg32(v) => **v = nv as u32,
+                    Reg64(v) => **v = nv,
+                    Reg16x8(_) => {
+                        let err = format!("reg: cannot change vector register {}", rname);
+                        result = Some(Err(err));
+                        return;
+                    }
+                }
+            }
+            let text = match val {
+                Reg8(v) => format!("{:02x}", v),
+                Reg16(v) => format!("{:04x}", v),
+                Reg32(v) => format!("{:08x}", v),
+                Reg64(v) => format!("{:016x}", v),
+                Reg16x8(v) => v
+                    .iter()
+                    .map(|l| format!("{:04x}", l))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            };
+            result = Some(Ok(format!("{} = {}", rname, text)));
+        });
+    }
+    match result {
+        Some(Ok(msg)) => ctx.cmdline.print(CmdOutput::Info, &msg),
+        Some(Err(msg)) => ctx.cmdline.print(CmdOutput::Error, &msg),
+        None => return, // Maybe in another view of the same CPU
+    }
+    ctx.cmdline.reg_access = None;
+}
+
+pub(crate) fn render_regview<'a, 'ui, RV: RegisterView>(
+    ui: &'a Ui<'ui>,
+    ctx: &mut UiCtx,
+    v: &mut RV,
+) {
+    serve_reg_access(ctx, v);
+
+    let title = im_str!("[{}] Registers", v.name());
+    if !ctx.layout.is_open(&title) {
+        return;
+    }
+    let disasm = ctx.disasm.get(v.cpu_name());
+    let editable = ctx.paused;
+
+    // When the emulation stops, compare registers with their value at the
+    // previous stop, to highlight the ones that changed.
+    let stopped = match ctx.event {
+        Some((ref evt, _)) => match **evt {
+            TraceEvent::Poll() => false,
+            _ => true,
+        },
+        None => false,
+    };
+    let regs = ctx
+        .regs
+        .entry(format!("{}:{}", v.cpu_name(), v.name()))
+        .or_default();
+    if stopped {
+        regs.changed.clear();
+    }
+
+    let mut opened = true;
+    let window = Window::new(&title).size(RV::WINDOW_SIZE, Condition::FirstUseEver);
+    ctx.layout
+        .place(&title, Dock::Center, window)
+        .opened(&mut opened)
+        .build(ui, || {
+            if !editable {
+                ui.text_disabled("Pause the emulation to edit registers");
+            }
+            // Iterate on all the columns
+            ui.columns(RV::COLUMNS as _, im_str!("##columns"), true);
+            for col in 0..RV::COLUMNS {
+                // Visit regs for this column
+                v.visit_regs(col, |rname, val, desc| {
+                    use super::RegisterSize::*;
+
+                    // Check if this register requires some special
+                    // highlight.
+                    let bgcolor = match disasm {
+                        None => COLOR_BG_NORMAL,
+                        Some(d) => match d.regs_highlight.get(rname) {
+                            None => COLOR_BG_NORMAL,
+                            Some(RegHighlight::Input) => COLOR_BG_INPUT,
+                            Some(RegHighlight::Output) => COLOR_BG_OUTPUT,
+                        },
+                    };
+
+                    let cur = reg_value(&val);
+                    if stopped {
+                        match regs.values.insert(rname.to_owned(), cur) {
+                            Some(prev) if prev != cur => {
+                                regs.changed.insert(rname.to_owned());
+                            }
+                            _ => {}
+                        }
+                    }
+                    let changed = regs.changed.contains(rname);
+
+                    // Draw the register box
+                    let name = &im_str!("{}", rname);
+                    let color = ui.push_style_color(StyleColor::FrameBg, bgcolor);
+                    let text_color = if changed {
+                        Some(ui.push_style_color(StyleColor::Text, COLOR_TEXT_CHANGED))
+                    } else {
+                        None
+                    };
+
+                    match val {
+                        Reg8(v) => input_reg(ui, name, v, editable),
+                        Reg16(v) => input_reg(ui, name, v, editable),
+                        Reg32(v) => input_reg(ui, name, v, editable),
+                        Reg64(v) => input_reg(ui, name, v, editable),
+                        Reg16x8(v) => {
+                            let id = ui.push_id(name);
+                            let left = ui.cursor_pos()[0];
+                            for i in 0..7 {
+                                let id = ui.push_id(i as i32);
+                                input_reg(ui, &im_str!(""), &mut v[i], editable);
+                                ui.same_line(left + (i + 1) as f32 * 40.0);
+                                id.pop(&ui);
+                            }
+                            input_reg(ui, name, &mut v[7], editable);
+                            id.pop(&ui);
+                        }
+                    };
+                    if let Some(text_color) = text_color {
+                        text_color.pop(&ui);
+                    }
+                    if let Some(desc) = desc {
+                        ui.text(im_str!("{}", desc));
+                    }
+
+                    color.pop(&ui);
+                });
+                ui.next_column();
+            }
+        });
+    ctx.layout.set_open(&title, opened);
+}
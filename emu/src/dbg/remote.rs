@@ -0,0 +1,589 @@
+use super::breakpoints::Breakpoint;
+use super::tracer::{Debugger, WatchpointAction, WatchpointCondition, WatchpointType};
+use super::{
+    apply_command, read_mem, write_mem, DebuggerModel, RegisterSize, TraceEvent, UiCommand,
+};
+use crate::gfx::{GfxBufferMutLE, Rgb888};
+use crate::snd::{SampleFormat, SndBufferMut};
+
+use serde_json::{json, Value};
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Maximum number of bytes read or written by a single memory request.
+const MAX_MEM_ACCESS: u64 = 0x10000;
+
+// JSON-RPC error codes.
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+struct RpcError(i64, String);
+
+impl RpcError {
+    fn params<S: Into<String>>(msg: S) -> Self {
+        RpcError(INVALID_PARAMS, msg.into())
+    }
+    fn server<S: Into<String>>(msg: S) -> Self {
+        RpcError(SERVER_ERROR, msg.into())
+    }
+}
+
+type RpcResult = std::result::Result<Value, RpcError>;
+
+/// RemoteServer exposes the debugger over TCP, so that external tools
+/// (editors, scripts) can control the emulation without the debugger UI.
+///
+/// The protocol is JSON-RPC 2.0, with one message per line. Methods (all
+/// parameters are named; addresses and values are numbers, or strings with
+/// hexadecimal numbers):
+///
+/// | Method              | Parameters                              | Result                          |
+/// |---------------------|-----------------------------------------|---------------------------------|
+/// | `cpus`              |                                         | names of the CPUs               |
+/// | `status`            |                                         | `paused`, `cycles`, `frames`    |
+/// | `pause`, `continue` |                                         |                                 |
+/// | `frame_advance`     |                                         | run until the end of the frame  |
+/// | `step`              | `cpu`                                   | step a single instruction       |
+/// | `run_to`            | `cpu`, `pc`                             | run until `pc` is reached       |
+/// | `reset`             | `hard` (optional)                       |                                 |
+/// | `breakpoints`       | `cpu`                                   | list of breakpoints             |
+/// | `add_breakpoint`    | `cpu`, `pc`, `condition` (optional)     |                                 |
+/// | `remove_breakpoint` | `cpu`, `pc`                             |                                 |
+/// | `watchpoints`       | `cpu`                                   | list of watchpoints             |
+/// | `add_watchpoint`    | `cpu`, `addr`, `len`, `type` (r/w/rw)   |                                 |
+/// | `remove_watchpoint` | `cpu`, `addr`                           |                                 |
+/// | `read_memory`       | `cpu`, `addr`, `len`                    | bytes, as a hex string          |
+/// | `write_memory`      | `cpu`, `addr`, `data` (hex string)      |                                 |
+/// | `read_registers`    | `cpu`                                   | object with register values     |
+/// | `write_register`    | `cpu`, `name`, `value`                  | only while paused               |
+/// | `quit`              |                                         | end a headless session          |
+///
+/// Whenever the emulation stops, a `stopped` notification is sent to all
+/// clients, with a `reason` ("pause", "step", "breakpoint", "run_to",
+/// "watchpoint" or "break") and, depending on it, the `cpu`, `pc`, `access`
+/// and `message` that caused the stop.
+pub struct RemoteServer {
+    addr: SocketAddr,
+    requests: Receiver<(usize, String)>,
+    clients: Arc<Mutex<Vec<(usize, TcpStream)>>>,
+    quit: bool,
+}
+
+impl RemoteServer {
+    /// Start listening for clients on the specified address. Clients are
+    /// accepted in background, while requests are only served by
+    /// [`serve`](#method.serve).
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let (tx, requests) = channel();
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let accepted = clients.clone();
+        thread::spawn(move || {
+            for (id, stream) in listener.incoming().enumerate() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                if let Ok(writer) = stream.try_clone() {
+                    accepted.lock().unwrap().push((id, writer));
+                }
+                let tx = tx.clone();
+                let clients = accepted.clone();
+                thread::spawn(move || {
+                    for line in BufReader::new(stream).lines() {
+                        let line = match line {
+                            Ok(line) => line,
+                            Err(_) => break,
+                        };
+                        if tx.send((id, line)).is_err() {
+                            break;
+                        }
+                    }
+                    // The client disconnected (or the server was dropped):
+                    // stop sending notifications to it.
+                    clients.lock().unwrap().retain(|(cid, _)| *cid != id);
+                });
+            }
+        });
+        Ok(Self {
+            addr,
+            requests,
+            clients,
+            quit: false,
+        })
+    }
+
+    /// Return the address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Send a message to a client, or to all clients if not specified.
+    // Clients that cannot be written to (eg: disconnected) are dropped.
+    fn send(&self, client: Option<usize>, msg: &Value) {
+        let mut line = msg.to_string();
+        line.push('\n');
+        self.clients.lock().unwrap().retain(|(id, stream)| {
+            if client.map_or(false, |c| c != *id) {
+                return true;
+            }
+            let mut stream: &TcpStream = stream;
+            stream.write_all(line.as_bytes()).is_ok()
+        });
+    }
+
+    /// Notify all clients that the emulation stopped because of the
+    /// specified event.
+    pub(crate) fn notify_event(&self, event: &TraceEvent) {
+        let params = match event {
+            TraceEvent::Poll() => return,
+            TraceEvent::Paused() => json!({ "reason": "pause" }),
+            TraceEvent::Stepped() => json!({ "reason": "step" }),
+            TraceEvent::Breakpoint(cpu, _, pc) => {
+                json!({ "reason": "breakpoint", "cpu": cpu, "pc": pc })
+            }
+            TraceEvent::BreakpointOneShot(cpu, pc) => {
+                json!({ "reason": "run_to", "cpu": cpu, "pc": pc })
+            }
+            TraceEvent::WatchpointRead(cpu, _) => {
+                json!({ "reason": "watchpoint", "cpu": cpu, "access": "read" })
+            }
+            TraceEvent::WatchpointWrite(cpu, _) => {
+                json!({ "reason": "watchpoint", "cpu": cpu, "access": "write" })
+            }
+            TraceEvent::GenericBreak(msg) => json!({ "reason": "break", "message": msg }),
+        };
+        self.send(
+            None,
+            &json!({ "jsonrpc": "2.0", "method": "stopped", "params": params }),
+        );
+    }
+
+    /// Serve the requests received from the clients. Requests that control
+    /// the execution (eg: "step" or "continue") are returned as a command,
+    /// to be executed by the caller; the following requests are served at
+    /// the next call.
+    pub(crate) fn serve<T: DebuggerModel>(
+        &mut self,
+        dbg: &mut Debugger,
+        model: &mut T,
+        paused: bool,
+    ) -> Option<UiCommand> {
+        let mut command = None;
+        while command.is_none() {
+            let (client, line) = match self.requests.try_recv() {
+                Ok(req) => req,
+                Err(_) => break,
+            };
+            let (id, res) = match serde_json::from_str::<Value>(&line) {
+                Ok(req) => {
+                    let method = req["method"].as_str().unwrap_or("").to_owned();
+                    let res = self.call(&method, &req["params"], dbg, model, paused, &mut command);
+                    (req.get("id").cloned(), res)
+                }
+                Err(err) => (
+                    Some(Value::Null),
+                    Err(RpcError(PARSE_ERROR, err.to_string())),
+                ),
+            };
+            // Requests without id are notifications, which are not answered.
+            let id = match id {
+                Some(id) => id,
+                None => continue,
+            };
+            let msg = match res {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err(RpcError(code, message)) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": code, "message": message },
+                }),
+            };
+            self.send(Some(client), &msg);
+        }
+        command
+    }
+
+    fn call<T: DebuggerModel>(
+        &mut self,
+        method: &str,
+        params: &Value,
+        dbg: &mut Debugger,
+        model: &mut T,
+        paused: bool,
+        command: &mut Option<UiCommand>,
+    ) -> RpcResult {
+        match method {
+            "cpus" => Ok(json!(model.all_cpus())),
+            "status" => Ok(json!({
+                "paused": paused,
+                "cycles": model.cycles(),
+                "frames": model.frames(),
+            })),
+            "pause" => {
+                *command = Some(UiCommand::Pause(true));
+                Ok(Value::Null)
+            }
+            "continue" => {
+                *command = Some(UiCommand::Pause(false));
+                Ok(Value::Null)
+            }
+            "frame_advance" => {
+                *command = Some(UiCommand::FrameAdvance);
+                Ok(Value::Null)
+            }
+            "step" => {
+                *command = Some(UiCommand::CpuStep(param_cpu(params, dbg)?));
+                Ok(Value::Null)
+            }
+            "run_to" => {
+                *command = Some(UiCommand::RunToCursor(
+                    param_cpu(params, dbg)?,
+                    param_u64(params, "pc")?,
+                ));
+                Ok(Value::Null)
+            }
+            "reset" => {
+                model
+                    .reset(params["hard"].as_bool().unwrap_or(false))
+                    .map_err(RpcError::server)?;
+                Ok(Value::Null)
+            }
+            "breakpoints" => {
+                let bps: Vec<Value> = dbg.cpus[&param_cpu(params, dbg)?]
+                    .breakpoints
+                    .iter()
+                    .map(|bp| {
+                        json!({
+                            "pc": bp.pc,
+                            "active": bp.active,
+                            "description": bp.description,
+                            "condition": bp.condition,
+                            "hits": bp.hits.get(),
+                        })
+                    })
+                    .collect();
+                Ok(json!(bps))
+            }
+            "add_breakpoint" => {
+                let cpu = param_cpu(params, dbg)?;
+                let pc = param_u64(params, "pc")?;
+                let mut bp = Breakpoint::new(pc, params["description"].as_str().unwrap_or(""));
+                bp.set_condition(params["condition"].as_str().unwrap_or(""))
+                    .map_err(RpcError::params)?;
+                let cpu = dbg.cpus.get_mut(&cpu).unwrap();
+                if cpu.breakpoints.iter().any(|bp| bp.pc == pc) {
+                    return Err(RpcError::server(format!(
+                        "breakpoint at {:x} already exists",
+                        pc
+                    )));
+                }
+                cpu.add_breakpoint(bp);
+                Ok(Value::Null)
+            }
+            "remove_breakpoint" => {
+                let cpu = param_cpu(params, dbg)?;
+                let cpu = dbg.cpus.get_mut(&cpu).unwrap();
+                let pc = param_u64(params, "pc")?;
+                let idx = cpu
+                    .breakpoints
+                    .iter()
+                    .position(|bp| bp.pc == pc)
+                    .ok_or_else(|| RpcError::server(format!("no breakpoint at {:x}", pc)))?;
+                cpu.remove_breakpoint(idx);
+                Ok(Value::Null)
+            }
+            "watchpoints" => {
+                let wps: Vec<Value> = dbg.cpus[&param_cpu(params, dbg)?]
+                    .watchpoints
+                    .iter()
+                    .map(|wp| {
+                        let wtype = match wp.wtype {
+                            WatchpointType::Read => "r",
+                            WatchpointType::Write => "w",
+                            WatchpointType::ReadWrite => "rw",
+                        };
+                        json!({
+                            "addr": wp.addr,
+                            "len": wp.len,
+                            "type": wtype,
+                            "active": wp.active,
+                            "description": wp.description,
+                        })
+                    })
+                    .collect();
+                Ok(json!(wps))
+            }
+            "add_watchpoint" => {
+                let cpu = param_cpu(params, dbg)?;
+                let (addr, len) = watchpoint_range(params)?;
+                let wtype = match params["type"].as_str().unwrap_or("rw") {
+                    "r" => WatchpointType::Read,
+                    "w" => WatchpointType::Write,
+                    "rw" => WatchpointType::ReadWrite,
+                    t => return Err(RpcError::params(format!("invalid watchpoint type: {}", t))),
+                };
+                dbg.cpus.get_mut(&cpu).unwrap().add_watchpoint(
+                    addr,
+                    len,
+                    params["description"].as_str().unwrap_or(""),
+                    wtype,
+                    WatchpointCondition::Always,
+                    WatchpointAction::Break,
+                );
+                Ok(Value::Null)
+            }
+            "remove_watchpoint" => {
+                let cpu = param_cpu(params, dbg)?;
+                let cpu = dbg.cpus.get_mut(&cpu).unwrap();
+                let addr = param_u64(params, "addr")?;
+                let idx = cpu
+                    .watchpoints
+                    .iter()
+                    .position(|wp| wp.addr == addr)
+                    .ok_or_else(|| RpcError::server(format!("no watchpoint at {:x}", addr)))?;
+                cpu.remove_watchpoint(idx);
+                Ok(Value::Null)
+            }
+            "read_memory" => {
+                let cpu = param_cpu(params, dbg)?;
+                let addr = param_u64(params, "addr")?;
+                let len = param_u64(params, "len")?.min(MAX_MEM_ACCESS);
+                let view = model
+                    .memory_view(&cpu)
+                    .ok_or_else(|| RpcError::server(format!("no memory view for {}", cpu)))?;
+                let data = read_mem(view, addr, len)
+                    .ok_or_else(|| RpcError::server(format!("invalid address: {:x}", addr)))?;
+                let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+                Ok(json!(hex))
+            }
+            "write_memory" => {
+                let cpu = param_cpu(params, dbg)?;
+                let addr = param_u64(params, "addr")?;
+                let data = parse_bytes(params["data"].as_str().unwrap_or(""))?;
+                if data.len() as u64 > MAX_MEM_ACCESS {
+                    return Err(RpcError::params("too much data"));
+                }
+                let view = model
+                    .memory_view(&cpu)
+                    .ok_or_else(|| RpcError::server(format!("no memory view for {}", cpu)))?;
+                write_mem(view, addr, &data).map_err(RpcError::server)?;
+                Ok(Value::Null)
+            }
+            "read_registers" => {
+                let mut regs = serde_json::Map::new();
+                model.visit_regs(&param_cpu(params, dbg)?, &mut |name, val| {
+                    let val = match val {
+                        RegisterSize::Reg8(v) => json!(*v),
+                        RegisterSize::Reg16(v) => json!(*v),
+                        RegisterSize::Reg32(v) => json!(*v),
+                        RegisterSize::Reg64(v) => json!(*v),
+                        RegisterSize::Reg16x8(v) => json!(v.to_vec()),
+                    };
+                    regs.insert(name.to_owned(), val);
+                });
+                Ok(Value::Object(regs))
+            }
+            "write_register" => {
+                if !paused {
+                    return Err(RpcError::server("pause the emulation to change registers"));
+                }
+                let reg = params["name"]
+                    .as_str()
+                    .ok_or_else(|| RpcError::params("missing parameter: name"))?;
+                let value = param_u64(params, "value")?;
+                let mut found = false;
+                model.visit_regs(&param_cpu(params, dbg)?, &mut |name, val| {
+                    if found || !name.eq_ignore_ascii_case(reg) {
+                        return;
+                    }
+                    found = true;
+                    match val {
+                        RegisterSize::Reg8(v) => *v = value as u8,
+                        RegisterSize::Reg16(v) => *v = value as u16,
+                        RegisterSize::Reg32(v) => *v = value as u32,
+                        RegisterSize::Reg64(v) => *v = value,
+                        RegisterSize::Reg16x8(_) => found = false,
+                    }
+                });
+                if !found {
+                    return Err(RpcError::server(format!("cannot change register {}", reg)));
+                }
+                Ok(Value::Null)
+            }
+            "quit" => {
+                self.quit = true;
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError(
+                METHOD_NOT_FOUND,
+                format!("unknown method: {:?}", method),
+            )),
+        }
+    }
+
+    /// Run an emulator under the remote debugger, without any UI, until a
+    /// client sends a "quit" request. The emulation starts paused, so that
+    /// clients can connect and set breakpoints before it runs.
+    pub fn run_headless<T: DebuggerModel, SF: SampleFormat>(
+        &mut self,
+        model: &mut T,
+        screen: &mut GfxBufferMutLE<Rgb888>,
+        sound: &mut SndBufferMut<SF>,
+    ) {
+        let mut dbg = Debugger::new(&model.all_cpus());
+        let (mut paused, mut frame_pause) = (true, false);
+        while !self.quit {
+            if let Some(cmd) = self.serve(&mut dbg, model, paused) {
+                let event = apply_command(cmd, &mut dbg, model, &mut paused, &mut frame_pause);
+                if let Some(event) = event {
+                    self.notify_event(&event);
+                }
+            }
+            if paused {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+
+            // Come back regularly to serve requests (eg: "pause").
+            dbg.set_poll_event(Instant::now() + Duration::from_millis(50));
+            match model.trace_frame(screen, sound, &dbg.new_tracer()) {
+                Ok(()) if frame_pause => {
+                    paused = true;
+                    frame_pause = false;
+                    self.notify_event(&TraceEvent::Paused());
+                }
+                Ok(()) => {}
+                Err(event) => {
+                    if let TraceEvent::Poll() = *event {
+                        continue;
+                    }
+                    paused = true;
+                    dbg.disable_breakpoint_oneshot();
+                    self.notify_event(&event);
+                }
+            }
+        }
+    }
+}
+
+// Parse a number parameter: either a JSON number, or a string with a
+// hexadecimal number (with optional 0x prefix), as 64-bit addresses cannot
+// be represented exactly by most JSON implementations.
+fn param_u64(params: &Value, name: &str) -> Result<u64, RpcError> {
+    match &params[name] {
+        Value::Number(n) => n
+            .as_u64()
+            .ok_or_else(|| RpcError::params(format!("invalid {}: {}", name, n))),
+        Value::String(s) => {
+            let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+            u64::from_str_radix(digits, 16)
+                .map_err(|_| RpcError::params(format!("invalid {}: {:?}", name, s)))
+        }
+        Value::Null => Err(RpcError::params(format!("missing parameter: {}", name))),
+        v => Err(RpcError::params(format!("invalid {}: {}", name, v))),
+    }
+}
+
+// Return the CPU named by the "cpu" parameter (case insensitive).
+fn param_cpu(params: &Value, dbg: &Debugger) -> Result<String, RpcError> {
+    let name = params["cpu"]
+        .as_str()
+        .ok_or_else(|| RpcError::params("missing parameter: cpu"))?;
+    dbg.cpus
+        .keys()
+        .find(|c| c.eq_ignore_ascii_case(name))
+        .cloned()
+        .ok_or_else(|| RpcError::params(format!("unknown CPU: {}", name)))
+}
+
+// Parse a string of hexadecimal bytes (eg: "0102ff").
+fn parse_bytes(hex: &str) -> Result<Vec<u8>, RpcError> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err(RpcError::params("invalid data"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| RpcError::params("invalid data"))
+}
+
+// Parse the address range of a watchpoint (4 bytes by default). Ranges are
+// limited like memory requests, and cannot wrap around the address space.
+fn watchpoint_range(params: &Value) -> Result<(u64, u64), RpcError> {
+    let addr = param_u64(params, "addr")?;
+    let len = match params.get("len") {
+        Some(_) => param_u64(params, "len")?.min(MAX_MEM_ACCESS),
+        None => 4,
+    };
+    if addr.checked_add(len).is_none() {
+        return Err(RpcError::params(
+            "watchpoint range overflows the address space",
+        ));
+    }
+    Ok((addr, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params() {
+        let params = json!({ "pc": "0x80001234", "addr": 4096, "neg": -1, "data": "01ff" });
+        assert_eq!(param_u64(&params, "pc").ok(), Some(0x8000_1234));
+        assert_eq!(param_u64(&params, "addr").ok(), Some(4096));
+        assert!(param_u64(&params, "neg").is_err());
+        assert!(param_u64(&params, "missing").is_err());
+
+        assert_eq!(parse_bytes("01ff").ok(), Some(vec![1, 0xff]));
+        assert!(parse_bytes("1ff").is_err());
+        assert!(parse_bytes("zz").is_err());
+    }
+
+    #[test]
+    fn watchpoint_params() {
+        let range = |v: Value| watchpoint_range(&v).ok();
+        assert_eq!(range(json!({ "addr": 0x1000 })), Some((0x1000, 4)));
+        assert_eq!(
+            range(json!({ "addr": 0x1000, "len": 16 })),
+            Some((0x1000, 16))
+        );
+        assert_eq!(
+            range(json!({ "addr": 0, "len": u64::max_value() })),
+            Some((0, MAX_MEM_ACCESS))
+        );
+        assert_eq!(
+            range(json!({ "addr": u64::max_value() - 1, "len": 4 })),
+            None
+        );
+        assert_eq!(range(json!({ "len": 4 })), None);
+    }
+
+    #[test]
+    fn disconnected_clients() {
+        let server = RemoteServer::bind("127.0.0.1:0").unwrap();
+        let num_clients = || server.clients.lock().unwrap().len();
+        let wait_for = |n: usize| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while num_clients() != n {
+                assert!(Instant::now() < deadline, "expected {} clients", n);
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+
+        let client = TcpStream::connect(server.local_addr()).unwrap();
+        wait_for(1);
+        drop(client);
+        wait_for(0);
+    }
+}
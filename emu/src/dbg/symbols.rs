@@ -0,0 +1,277 @@
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use failure::{bail, format_err, Error};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// Largest offset resolved within the last symbol of the table, when its size
+// is unknown: addresses beyond it are unlikely to belong to the symbol.
+const MAX_UNSIZED_OFFSET: u64 = 0x1_0000;
+
+#[derive(Clone, Debug)]
+struct Symbol {
+    name: String,
+    size: Option<u64>,
+}
+
+/// A table of symbols (function and data names), used by the debugger views
+/// to display names instead of raw addresses.
+///
+/// Symbols can be loaded from an ELF file (using its symbol table), or from
+/// a text file in either of the following formats (one symbol per line):
+///
+/// ```text
+/// 80000400 main
+/// 0x80000400 T main
+/// ```
+///
+/// The second format is the output of `nm`; linker map files generated by
+/// GNU ld also match the first format for the lines listing symbols.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    syms: BTreeMap<u64, Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a symbol table from a file, autodetecting its format.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let data = fs::read(path)?;
+        if data.starts_with(b"\x7FELF") {
+            Self::parse_elf(&data)
+        } else {
+            Self::parse_text(&String::from_utf8_lossy(&data))
+        }
+    }
+
+    /// Add a symbol. If a symbol already exists at the same address,
+    /// it is replaced.
+    pub fn insert(&mut self, addr: u64, name: &str, size: Option<u64>) {
+        self.syms.insert(
+            addr,
+            Symbol {
+                name: name.to_owned(),
+                size,
+            },
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        self.syms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.syms.is_empty()
+    }
+
+    /// Iterate over all symbols, returning (address, name, size).
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &str, Option<u64>)> {
+        self.syms
+            .iter()
+            .map(|(addr, sym)| (*addr, sym.name.as_str(), sym.size))
+    }
+
+    /// Return the name of the symbol at exactly the specified address.
+    pub fn lookup(&self, addr: u64) -> Option<&str> {
+        self.syms.get(&addr).map(|s| s.name.as_str())
+    }
+
+    /// Return the symbol which contains the specified address, and the offset
+    /// of the address within it. If the size of the symbol is unknown, it is
+    /// assumed to extend up to the next symbol (or, for the last symbol, up
+    /// to a maximum offset).
+    pub fn resolve(&self, addr: u64) -> Option<(&str, u64)> {
+        let (saddr, sym) = self.syms.range(..=addr).next_back()?;
+        let off = addr - saddr;
+        let size = match sym.size {
+            Some(size) => size.max(1),
+            None => match self.syms.range(saddr + 1..).next() {
+                Some((next, _)) => next - saddr,
+                None => MAX_UNSIZED_OFFSET,
+            },
+        };
+        if off < size {
+            Some((&sym.name, off))
+        } else {
+            None
+        }
+    }
+
+    /// Format an address as a symbolic label (eg: "func" or "func+0x24"),
+    /// if there is a symbol containing it.
+    pub fn label(&self, addr: u64) -> Option<String> {
+        self.resolve(addr).map(|(name, off)| {
+            if off == 0 {
+                name.to_owned()
+            } else {
+                format!("{}+0x{:x}", name, off)
+            }
+        })
+    }
+
+    fn parse_text(text: &str) -> Result<Self, Error> {
+        let mut table = Self::new();
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (addr, name) = match fields.len() {
+                2 => (fields[0], fields[1]),
+                3 if fields[1].len() == 1 => (fields[0], fields[2]),
+                _ => continue,
+            };
+            let addr = addr.trim_start_matches("0x").trim_start_matches("0X");
+            if let Ok(addr) = u64::from_str_radix(addr, 16) {
+                table.insert(addr, name, None);
+            }
+        }
+        if table.is_empty() {
+            bail!("no symbols found");
+        }
+        Ok(table)
+    }
+
+    fn parse_elf(data: &[u8]) -> Result<Self, Error> {
+        match data.get(5) {
+            Some(1) => Self::parse_elf_order::<LittleEndian>(data),
+            Some(2) => Self::parse_elf_order::<BigEndian>(data),
+            _ => bail!("invalid ELF endianness"),
+        }
+    }
+
+    fn parse_elf_order<O: ByteOrder>(data: &[u8]) -> Result<Self, Error> {
+        let is64 = match data.get(4) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => bail!("invalid ELF class"),
+        };
+        let get = |off: usize, len: usize| {
+            data.get(off..off + len)
+                .ok_or_else(|| format_err!("truncated ELF file"))
+        };
+        let word = |off: usize| -> Result<u64, Error> {
+            if is64 {
+                Ok(O::read_u64(get(off, 8)?))
+            } else {
+                Ok(O::read_u32(get(off, 4)?) as u64)
+            }
+        };
+        let half = |off: usize| -> Result<usize, Error> { Ok(O::read_u16(get(off, 2)?) as usize) };
+        let u32at = |off: usize| -> Result<u64, Error> { Ok(O::read_u32(get(off, 4)?) as u64) };
+
+        // ELF header
+        let (shoff, shentsize, shnum) = if is64 {
+            (word(0x28)? as usize, half(0x3A)?, half(0x3C)?)
+        } else {
+            (word(0x20)? as usize, half(0x2E)?, half(0x30)?)
+        };
+
+        // Section header: (type, offset, size, link)
+        let section = |idx: usize| -> Result<(u64, usize, usize, usize), Error> {
+            let sh = shoff + idx * shentsize;
+            if is64 {
+                Ok((
+                    u32at(sh + 0x04)?,
+                    word(sh + 0x18)? as usize,
+                    word(sh + 0x20)? as usize,
+                    u32at(sh + 0x28)? as usize,
+                ))
+            } else {
+                Ok((
+                    u32at(sh + 0x04)?,
+                    word(sh + 0x10)? as usize,
+                    word(sh + 0x14)? as usize,
+                    u32at(sh + 0x18)? as usize,
+                ))
+            }
+        };
+
+        const SHT_SYMTAB: u64 = 2;
+        const STT_OBJECT: u8 = 1;
+        const STT_FUNC: u8 = 2;
+
+        let mut table = Self::new();
+        for idx in 0..shnum {
+            let (stype, off, size, link) = section(idx)?;
+            if stype != SHT_SYMTAB {
+                continue;
+            }
+            let (_, stroff, strsize, _) = section(link)?;
+            let strtab = get(stroff, strsize)?;
+
+            let entsize = if is64 { 24 } else { 16 };
+            for sym in (off..off + size).step_by(entsize) {
+                let (name, info, value, size) = if is64 {
+                    (u32at(sym)?, get(sym + 4, 1)?[0], word(sym + 8)?, word(sym + 16)?)
+                } else {
+                    (u32at(sym)?, get(sym + 12, 1)?[0], word(sym + 4)?, word(sym + 8)?)
+                };
+                let stt = info & 0xF;
+                if stt != STT_FUNC && stt != STT_OBJECT {
+                    continue;
+                }
+                let name = strtab
+                    .get(name as usize..)
+                    .and_then(|s| s.split(|c| *c == 0).next())
+                    .ok_or_else(|| format_err!("invalid symbol name"))?;
+                if name.is_empty() {
+                    continue;
+                }
+                let size = if size != 0 { Some(size) } else { None };
+                table.insert(value, &String::from_utf8_lossy(name), size);
+            }
+        }
+        if table.is_empty() {
+            bail!("no symbols found in ELF file");
+        }
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_text() {
+        let table = SymbolTable::parse_text(
+            "80000400 main\n\
+             0x80000500 T helper\n\
+             garbage line with words\n\
+             .text 0x80000400 0x1000 main.o\n",
+        )
+        .unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.lookup(0x8000_0400), Some("main"));
+        assert_eq!(table.lookup(0x8000_0404), None);
+        assert_eq!(table.label(0x8000_0404), Some("main+0x4".to_owned()));
+        assert_eq!(table.label(0x8000_0500), Some("helper".to_owned()));
+        assert_eq!(table.label(0x8000_0000), None);
+    }
+
+    #[test]
+    fn resolve_with_size() {
+        let mut table = SymbolTable::new();
+        table.insert(0x1000, "func", Some(0x10));
+        assert_eq!(table.resolve(0x100C), Some(("func", 0xC)));
+        assert_eq!(table.resolve(0x1010), None);
+    }
+
+    #[test]
+    fn resolve_without_size() {
+        let mut table = SymbolTable::new();
+        table.insert(0x1000, "first", None);
+        table.insert(0x2000, "last", None);
+        assert_eq!(table.resolve(0xFFF), None);
+        assert_eq!(table.resolve(0x1FFC), Some(("first", 0xFFC)));
+        assert_eq!(table.resolve(0x2000), Some(("last", 0)));
+        assert_eq!(
+            table.resolve(0x2000 + MAX_UNSIZED_OFFSET - 1),
+            Some(("last", MAX_UNSIZED_OFFSET - 1))
+        );
+        assert_eq!(table.resolve(0x2000 + MAX_UNSIZED_OFFSET), None);
+        assert_eq!(table.label(0x8000_0000), None);
+    }
+}
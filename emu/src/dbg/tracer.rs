@@ -1,13 +1,18 @@
-use super::uisupport::imgui_input_hex;
-use super::UiCtx;
+use super::breakpoints::Breakpoint;
+use super::callstackview::CallStack;
+use super::coverage::Coverage;
+use super::decoding::DecodedInsn;
+use super::insntrace::InsnTrace;
+use super::profiler::Profiler;
+use super::HexableInt;
 use array_macro::array;
 use bitflags::bitflags;
-use imgui::*;
 use serde_derive::{Serialize, Deserialize};
+use slog::info;
 
 use crate::memint::{AccessSize, MemInt};
 
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::time::Instant;
@@ -49,6 +54,9 @@ impl TraceGuard {
 pub struct Tracer<'a> {
     dbg: Option<&'a Debugger>,
     trace_guards: [TraceGuard; 256],
+    recording: bool,
+    profiling: bool,
+    covering: bool,
 }
 
 impl Tracer<'_> {
@@ -58,6 +66,9 @@ impl Tracer<'_> {
         Tracer {
             dbg: None,
             trace_guards: array![TraceGuard::empty(); 256],
+            recording: false,
+            profiling: false,
+            covering: false,
         }
     }
 
@@ -82,18 +93,116 @@ impl Tracer<'_> {
         self.dbg.map(|t| t.trace_gpu(line)).unwrap_or(Ok(()))
     }
 
+    /// Trace the execution of the instruction at the specified PC. `regs`
+    /// is used to lookup register values by name when evaluating breakpoint
+    /// conditions and tracepoint messages, so it is only called if a
+    /// breakpoint is reached. `logger` is the CPU logger, used to log the
+    /// messages of tracepoints.
     #[inline(always)]
-    pub fn trace_insn(&self, cpu_name: &str, pc: u64) -> Result<()> {
+    pub fn trace_insn<R: Fn(&str) -> Option<u64>>(
+        &self,
+        cpu_name: &str,
+        pc: u64,
+        regs: R,
+        logger: &slog::Logger,
+    ) -> Result<()> {
         if self.dbg.is_none() {
             return Ok(());
         }
         if self.trace_guards[TraceGuard::index(pc)].contains(TraceGuard::INSN) {
-            self.dbg.unwrap().trace_insn(cpu_name, pc)
+            self.dbg.unwrap().trace_insn(cpu_name, pc, &regs, logger)
         } else {
             Ok(())
         }
     }
 
+    /// Return true if executed instructions must be recorded into the
+    /// instruction trace, through [`record_insn()`](#method.record_insn).
+    #[inline(always)]
+    pub fn insn_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Record an instruction that was just executed, together with the
+    /// registers that it modified. `disasm` is only called if the trace
+    /// is being streamed to a file.
+    pub fn record_insn<D: FnOnce() -> String>(
+        &self,
+        cpu_name: &str,
+        pc: u64,
+        opcode: u32,
+        changed: &[(&'static str, u64)],
+        disasm: D,
+    ) {
+        if let Some(dbg) = self.dbg {
+            dbg.itrace
+                .borrow_mut()
+                .record(cpu_name, pc, opcode, changed, disasm);
+        }
+    }
+
+    /// Return true if executed instructions must be counted by the profiler,
+    /// through [`profile_insn()`](#method.profile_insn) and
+    /// [`profile_block()`](#method.profile_block).
+    #[inline(always)]
+    pub fn profiling(&self) -> bool {
+        self.profiling
+    }
+
+    /// Count an executed instruction. `class` identifies the opcode (with
+    /// all operand fields masked out); `decode` is only called the first
+    /// time a class is seen, to get its mnemonic and metadata (nominal
+    /// cycles and flags).
+    pub fn profile_insn<D: FnOnce() -> DecodedInsn>(
+        &self,
+        cpu_name: &str,
+        class: u32,
+        decode: D,
+    ) {
+        if let Some(dbg) = self.dbg {
+            dbg.profiler
+                .borrow_mut()
+                .count_insn(cpu_name, class, decode);
+        }
+    }
+
+    /// Notify the profiler that a basic block is being entered, with the
+    /// specified call stack. The cycles spent in the block (see
+    /// [`profile_block()`](#method.profile_block)) are attributed to the
+    /// functions in this call stack.
+    pub fn profile_enter_block(&self, cpu_name: &str, stack: &CallStack) {
+        if let Some(dbg) = self.dbg {
+            dbg.profiler.borrow_mut().enter_block(cpu_name, stack);
+        }
+    }
+
+    /// Count the execution of a basic block of `len` instructions, starting
+    /// at the specified PC, which took `cycles` emulated cycles.
+    pub fn profile_block(&self, cpu_name: &str, pc: u64, len: u64, cycles: u64) {
+        if let Some(dbg) = self.dbg {
+            dbg.profiler
+                .borrow_mut()
+                .count_block(cpu_name, pc, len, cycles);
+        }
+    }
+
+    /// Return true if executed instructions must be recorded into the code
+    /// coverage, through [`cover_insn()`](#method.cover_insn).
+    #[inline(always)]
+    pub fn covering(&self) -> bool {
+        self.covering
+    }
+
+    /// Record that the instruction at the specified PC was executed.
+    pub fn cover_insn(&self, cpu_name: &str, pc: u64) {
+        if let Some(dbg) = self.dbg {
+            dbg.coverage.borrow_mut().cover_insn(cpu_name, pc);
+        }
+    }
+
+    /// Trace a memory access at the specified physical address. `logger`
+    /// is the CPU logger, used to log accesses hit by log-only watchpoints
+    /// (it is expected to carry the current PC).
     #[inline(always)]
     pub fn trace_mem_write(
         &self,
@@ -101,17 +210,21 @@ impl Tracer<'_> {
         addr: u64,
         size: AccessSize,
         val: u64,
+        logger: &slog::Logger,
     ) -> Result<()> {
         if self.dbg.is_none() {
             return Ok(());
         }
         if self.trace_guards[TraceGuard::index(addr)].contains(TraceGuard::MEM_WRITE) {
-            self.dbg.unwrap().trace_mem_write(cpu_name, addr, size, val)
+            self.dbg.unwrap().trace_mem_write(cpu_name, addr, size, val, logger)
         } else {
             Ok(())
         }
     }
 
+    /// Trace a memory access at the specified physical address. `logger`
+    /// is the CPU logger, used to log accesses hit by log-only watchpoints
+    /// (it is expected to carry the current PC).
     #[inline(always)]
     pub fn trace_mem_read(
         &self,
@@ -119,49 +232,35 @@ impl Tracer<'_> {
         addr: u64,
         size: AccessSize,
         val: u64,
+        logger: &slog::Logger,
     ) -> Result<()> {
         if self.dbg.is_none() {
             return Ok(());
         }
         if self.trace_guards[TraceGuard::index(addr)].contains(TraceGuard::MEM_READ) {
-            self.dbg.unwrap().trace_mem_read(cpu_name, addr, size, val)
+            self.dbg.unwrap().trace_mem_read(cpu_name, addr, size, val, logger)
         } else {
             Ok(())
         }
     }
 }
 
-#[derive(Eq, Serialize, Deserialize)]
-pub(crate) struct Breakpoint {
-    active: bool,
-    pc: u64,
-    description: String,
-}
-
-impl Ord for Breakpoint {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.pc.cmp(&other.pc)
-    }
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize)]
+pub(crate) enum WatchpointType {
+    Read,
+    Write,
+    ReadWrite,
 }
 
-impl PartialOrd for Breakpoint {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+impl WatchpointType {
+    fn matches_read(self) -> bool {
+        self != WatchpointType::Write
     }
-}
-
-impl PartialEq for Breakpoint {
-    fn eq(&self, other: &Self) -> bool {
-        self.pc == other.pc
+    fn matches_write(self) -> bool {
+        self != WatchpointType::Read
     }
 }
 
-#[derive(Copy, Clone, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize)]
-pub(crate) enum WatchpointType {
-    Read,
-    Write,
-}
-
 #[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) enum WatchpointCondition {
     Always,
@@ -189,17 +288,88 @@ impl WatchpointCondition {
     }
 }
 
+/// What happens when a watchpoint is hit: either the emulation is stopped,
+/// or the access is just logged (which is much cheaper, so it can be used
+/// to monitor busy device registers or RAM areas while running).
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum WatchpointAction {
+    Break,
+    Log,
+}
+
+impl Default for WatchpointAction {
+    fn default() -> Self {
+        WatchpointAction::Break
+    }
+}
+
+fn default_watchpoint_len() -> u64 {
+    1
+}
+
 #[derive(Eq, Serialize, Deserialize)]
 pub(crate) struct Watchpoint {
-    active: bool,
-    addr: u64,
-    wtype: WatchpointType,
-    condition: WatchpointCondition,
-    description: String,
+    pub(super) active: bool,
+    pub(super) addr: u64,
+    #[serde(default = "default_watchpoint_len")]
+    pub(super) len: u64, // Size of the watched address range (in bytes)
+    pub(super) wtype: WatchpointType,
+    pub(super) condition: WatchpointCondition,
+    #[serde(default)]
+    pub(super) action: WatchpointAction,
+    pub(super) description: String,
 }
 
+// Watchpoints are indexed by 8-byte blocks, so that a single lookup is
+// required for each (naturally aligned) memory access.
+const WP_BLOCK_MASK: u64 = !7;
+
+/// Maximum size of the address range watched by a watchpoint; longer ranges
+/// are truncated, to bound the size of the lookup tables.
+pub(crate) const MAX_WATCHPOINT_LEN: u64 = 0x10000;
+
 impl Watchpoint {
-    fn cond_to_string(&self) -> String {
+    // Last address of the watched range (inclusive). The range never wraps
+    // around the end of the address space.
+    fn last_addr(&self) -> u64 {
+        let len = self.len.max(1).min(MAX_WATCHPOINT_LEN);
+        self.addr.saturating_add(len - 1)
+    }
+
+    // Check whether an access of the specified size at the specified
+    // address overlaps with the watched range.
+    fn overlaps(&self, addr: u64, size: AccessSize) -> bool {
+        let size = match size {
+            AccessSize::Size8 => 1,
+            AccessSize::Size16 => 2,
+            AccessSize::Size32 => 4,
+            AccessSize::Size64 => 8,
+        };
+        addr <= self.last_addr() && self.addr <= addr.saturating_add(size - 1)
+    }
+
+    // Process a hit on this watchpoint. Returns true if the emulation must
+    // be stopped.
+    fn hit(
+        &self,
+        logger: &slog::Logger,
+        write: bool,
+        addr: u64,
+        size: AccessSize,
+        val: u64,
+    ) -> bool {
+        match self.action {
+            WatchpointAction::Break => true,
+            WatchpointAction::Log => {
+                info!(logger, "bus watch"; "wp" => &self.description,
+                    "op" => if write { "write" } else { "read" },
+                    "addr" => addr.hex(), "size" => format!("{:?}", size), "val" => val.hex());
+                false
+            }
+        }
+    }
+
+    pub(super) fn cond_to_string(&self) -> String {
         use self::WatchpointCondition::*;
         use self::WatchpointType::*;
         match self.wtype {
@@ -221,6 +391,15 @@ impl Watchpoint {
                 Lt(cmp) => format!("Value written < 0x{:x}", cmp),
                 Le(cmp) => format!("Value written <= 0x{:x}", cmp),
             },
+            ReadWrite => match self.condition {
+                Always => format!("Any access"),
+                Eq(cmp) => format!("Value accessed == 0x{:x}", cmp),
+                Ne(cmp) => format!("Value accessed != 0x{:x}", cmp),
+                Gt(cmp) => format!("Value accessed > 0x{:x}", cmp),
+                Ge(cmp) => format!("Value accessed >= 0x{:x}", cmp),
+                Lt(cmp) => format!("Value accessed < 0x{:x}", cmp),
+                Le(cmp) => format!("Value accessed <= 0x{:x}", cmp),
+            },
         }
     }
 }
@@ -229,6 +408,7 @@ impl Ord for Watchpoint {
     fn cmp(&self, other: &Self) -> Ordering {
         self.addr
             .cmp(&other.addr)
+            .then(self.len.cmp(&other.len))
             .then(self.wtype.cmp(&other.wtype))
     }
 }
@@ -241,63 +421,87 @@ impl PartialOrd for Watchpoint {
 
 impl PartialEq for Watchpoint {
     fn eq(&self, other: &Self) -> bool {
-        self.addr == other.addr && self.wtype == other.wtype
+        self.addr == other.addr && self.len == other.len && self.wtype == other.wtype
     }
 }
 
 #[derive(Default, Serialize, Deserialize)]
-struct DbgCpu {
-    breakpoints: Vec<Breakpoint>,
-    watchpoints: Vec<Watchpoint>,
+pub(super) struct DbgCpu {
+    pub(super) breakpoints: Vec<Breakpoint>,
+    pub(super) watchpoints: Vec<Watchpoint>,
 
     #[serde(skip)]
     bp_oneshot: Option<u64>, // Special one-shot breakpoint
 
+    #[serde(skip)]
+    bp_oneshot_frame: Option<(&'static str, u64)>, // Minimum stack pointer for the one-shot breakpoint
+
     #[serde(skip)]
     bp_fastmap: IntHashMap<u64, usize>,
 
     #[serde(skip)]
-    wp_fastmap: IntHashMap<u64, usize>,
+    wp_fastmap: IntHashMap<u64, Vec<usize>>, // 8-byte block => watchpoints
 }
 
 impl DbgCpu {
     fn after_deserialize(&mut self) {
+        for bp in self.breakpoints.iter_mut() {
+            // Conditions are validated when set, so parsing cannot fail
+            // unless the configuration file was edited by hand.
+            let cond = bp.condition.clone();
+            let msg = bp.message.clone();
+            if bp.set_condition(&cond).is_err() || bp.set_message(&msg).is_err() {
+                bp.active = false;
+            }
+        }
         self.update_bp_fastmap();
         self.update_wp_fastmap();
         self.bp_oneshot = None;
+        self.bp_oneshot_frame = None;
     }
 
-    fn add_breakpoint(&mut self, pc: u64, description: &str) {
-        self.breakpoints.push(Breakpoint {
-            active: true,
-            pc: pc,
-            description: description.to_owned(),
-        });
+    pub(super) fn add_breakpoint(&mut self, bp: Breakpoint) {
+        self.breakpoints.push(bp);
+        self.update_bp_fastmap();
+    }
+
+    pub(super) fn remove_breakpoint(&mut self, idx: usize) {
+        self.breakpoints.remove(idx);
         self.update_bp_fastmap();
     }
 
     fn set_breakpoint_oneshot(&mut self, pc: Option<u64>) {
         self.bp_oneshot = pc;
+        self.bp_oneshot_frame = None;
     }
 
-    fn add_watchpoint(
+    pub(super) fn add_watchpoint(
         &mut self,
         addr: u64,
+        len: u64,
         description: &str,
         wtype: WatchpointType,
         condition: WatchpointCondition,
+        action: WatchpointAction,
     ) {
         self.watchpoints.push(Watchpoint {
             active: true,
             addr,
+            len: len.min(MAX_WATCHPOINT_LEN),
             description: description.to_owned(),
             wtype,
             condition,
+            action,
         });
         self.update_wp_fastmap();
     }
 
-    fn update_bp_fastmap(&mut self) {
+    pub(super) fn remove_watchpoint(&mut self, idx: usize) {
+        self.watchpoints.remove(idx);
+        self.update_wp_fastmap();
+    }
+
+    pub(super) fn update_bp_fastmap(&mut self) {
         self.breakpoints.sort();
         self.bp_fastmap = self
             .breakpoints
@@ -307,23 +511,33 @@ impl DbgCpu {
             .map(|(idx, bp)| (bp.pc, idx))
             .collect();
     }
-    fn update_wp_fastmap(&mut self) {
-        self.breakpoints.sort();
-        self.wp_fastmap = self
-            .watchpoints
-            .iter()
-            .enumerate()
-            .filter(|(_, wp)| wp.active)
-            .map(|(idx, wp)| (wp.addr, idx))
-            .collect();
+    pub(super) fn update_wp_fastmap(&mut self) {
+        self.watchpoints.sort();
+        self.wp_fastmap = IntHashMap::default();
+        for (idx, wp) in self.watchpoints.iter().enumerate() {
+            if !wp.active {
+                continue;
+            }
+            let first = wp.addr & WP_BLOCK_MASK;
+            let last = wp.last_addr() & WP_BLOCK_MASK;
+            for block in (first..=last).step_by(8) {
+                self.wp_fastmap.entry(block).or_insert_with(Vec::new).push(idx);
+            }
+        }
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Debugger {
-    cpus: HashMap<String, DbgCpu>,
+    pub(super) cpus: HashMap<String, DbgCpu>,
     #[serde(skip)]
     next_poll: Cell<Option<Instant>>,
+    #[serde(skip)]
+    pub(super) itrace: RefCell<InsnTrace>,
+    #[serde(skip)]
+    pub(super) profiler: RefCell<Profiler>,
+    #[serde(skip)]
+    pub(super) coverage: RefCell<Coverage>,
 }
 
 impl Debugger {
@@ -336,6 +550,9 @@ impl Debugger {
         Self {
             cpus: cpumap,
             next_poll: Cell::new(None),
+            itrace: RefCell::new(InsnTrace::default()),
+            profiler: RefCell::new(Profiler::default()),
+            coverage: RefCell::new(Coverage::default()),
         }
     }
 
@@ -352,6 +569,14 @@ impl Debugger {
             .set_breakpoint_oneshot(pc);
     }
 
+    /// Restrict the one-shot breakpoint of the specified CPU to the current
+    /// stack frame or its callers: it is only hit if the stack pointer
+    /// register `reg` is not below `sp`. This is used to step over or out of
+    /// a function without stopping within a recursive invocation of it.
+    pub fn set_breakpoint_oneshot_frame(&mut self, cpu_name: &str, reg: &'static str, sp: u64) {
+        self.cpus.get_mut(cpu_name).unwrap().bp_oneshot_frame = Some((reg, sp));
+    }
+
     pub fn disable_breakpoint_oneshot(&mut self) {
         for (_, cpu) in &mut self.cpus {
             cpu.set_breakpoint_oneshot(None);
@@ -366,7 +591,7 @@ impl Debugger {
         self.cpus
             .get_mut(cpu_name)
             .unwrap()
-            .add_breakpoint(pc, description);
+            .add_breakpoint(Breakpoint::new(pc, description));
     }
 }
 
@@ -381,65 +606,97 @@ impl Debugger {
                 trace_guards[TraceGuard::index(pc)].insert(TraceGuard::INSN);
             }
             for wp in &cpu.watchpoints {
-                trace_guards[TraceGuard::index(wp.addr)].insert(match wp.wtype {
+                let guard = match wp.wtype {
                     WatchpointType::Read => TraceGuard::MEM_READ,
                     WatchpointType::Write => TraceGuard::MEM_WRITE,
-                });
+                    WatchpointType::ReadWrite => TraceGuard::MEM_READ | TraceGuard::MEM_WRITE,
+                };
+                // Guards are indexed by word, so cover all words of the range
+                // (including the partial words at the beginning and the end).
+                let first = wp.addr & !3;
+                let words = ((wp.last_addr() - first) / 4 + 1).min(256);
+                for w in 0..words {
+                    trace_guards[TraceGuard::index(first + w * 4)].insert(guard);
+                }
             }
         }
         Tracer {
             dbg: Some(&self),
             trace_guards: trace_guards,
+            recording: self.itrace.borrow().enabled(),
+            profiling: self.profiler.borrow().enabled(),
+            covering: self.coverage.borrow().enabled(),
         }
     }
 
-    fn trace_insn(&self, cpu_name: &str, pc: u64) -> Result<()> {
+    fn trace_insn(
+        &self,
+        cpu_name: &str,
+        pc: u64,
+        regs: &dyn Fn(&str) -> Option<u64>,
+        logger: &slog::Logger,
+    ) -> Result<()> {
         let cpu = &self.cpus[cpu_name];
-        match cpu.bp_fastmap.get(&pc) {
-            Some(idx) => Err(box TraceEvent::Breakpoint(cpu_name.to_owned(), *idx, pc)),
-            None => match cpu.bp_oneshot {
-                Some(bp_pc) if bp_pc == pc => {
-                    Err(box TraceEvent::BreakpointOneShot(cpu_name.to_owned(), pc))
-                }
-                _ => Ok(()),
+        if let Some(idx) = cpu.bp_fastmap.get(&pc) {
+            if cpu.breakpoints[*idx].hit(regs, logger) {
+                return Err(box TraceEvent::Breakpoint(cpu_name.to_owned(), *idx, pc));
+            }
+        }
+        match cpu.bp_oneshot {
+            Some(bp_pc) if bp_pc == pc => match cpu.bp_oneshot_frame {
+                Some((reg, sp)) if regs(reg).map_or(false, |v| v < sp) => Ok(()),
+                _ => Err(box TraceEvent::BreakpointOneShot(cpu_name.to_owned(), pc)),
             },
+            _ => Ok(()),
         }
     }
 
-    fn trace_mem_read(&self, cpu_name: &str, addr: u64, _size: AccessSize, val: u64) -> Result<()> {
+    fn trace_mem_read(
+        &self,
+        cpu_name: &str,
+        addr: u64,
+        size: AccessSize,
+        val: u64,
+        logger: &slog::Logger,
+    ) -> Result<()> {
         let cpu = &self.cpus[cpu_name];
-        match cpu.wp_fastmap.get(&addr) {
-            Some(idx) => {
+        if let Some(idxs) = cpu.wp_fastmap.get(&(addr & WP_BLOCK_MASK)) {
+            for idx in idxs {
                 let wp = &cpu.watchpoints[*idx];
-                if wp.wtype == WatchpointType::Read && wp.condition.check(val) {
-                    Err(box TraceEvent::WatchpointRead(cpu_name.to_owned(), *idx))
-                } else {
-                    Ok(())
+                if wp.wtype.matches_read()
+                    && wp.overlaps(addr, size)
+                    && wp.condition.check(val)
+                    && wp.hit(logger, false, addr, size, val)
+                {
+                    return Err(box TraceEvent::WatchpointRead(cpu_name.to_owned(), *idx));
                 }
             }
-            None => Ok(()),
         }
+        Ok(())
     }
 
     fn trace_mem_write(
         &self,
         cpu_name: &str,
         addr: u64,
-        _size: AccessSize,
+        size: AccessSize,
         val: u64,
+        logger: &slog::Logger,
     ) -> Result<()> {
         let cpu = &self.cpus[cpu_name];
-        match cpu.wp_fastmap.get(&addr) {
-            Some(idx) => {
+        if let Some(idxs) = cpu.wp_fastmap.get(&(addr & WP_BLOCK_MASK)) {
+            for idx in idxs {
                 let wp = &cpu.watchpoints[*idx];
-                if wp.wtype == WatchpointType::Write && wp.condition.check(val) {
-                    Err(box TraceEvent::WatchpointWrite(cpu_name.to_owned(), *idx))
-                } else {
-                    Ok(())
+                if wp.wtype.matches_write()
+                    && wp.overlaps(addr, size)
+                    && wp.condition.check(val)
+                    && wp.hit(logger, true, addr, size, val)
+                {
+                    return Err(box TraceEvent::WatchpointWrite(cpu_name.to_owned(), *idx));
                 }
             }
-            None => Ok(()),
         }
+        Ok(())
     }
 
     fn trace_gpu(&self, _line: usize) -> Result<()> {
@@ -456,220 +713,7 @@ impl Debugger {
     }
 }
 
-impl Debugger {
-    fn render_breakpoints(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx, cpu_name: &str) {
-        let cpu = self.cpus.get_mut(cpu_name).unwrap();
-
-        ui.popup(im_str!("##bp#new"), || {
-            ui.text(im_str!("PC:"));
-            ui.same_line(60.0);
-            imgui_input_hex(ui, im_str!("###bp#new_pc"), &mut ctx.new_bp_pc, false);
-
-            ui.text(im_str!("Desc:"));
-            ui.same_line(60.0);
-            ui.input_text(im_str!("###bp#new_desc"), &mut ctx.new_bp_desc)
-                .auto_select_all(true)
-                .build();
-
-            if ui.button(im_str!("Add"), [40.0, 20.0]) {
-                let desc = ctx.new_bp_desc.to_str().to_owned();
-                cpu.add_breakpoint(ctx.new_bp_pc, &desc);
-                ui.close_current_popup();
-            }
-        });
-        if ui.small_button(im_str!("New BP")) {
-            ctx.new_bp_pc = 0;
-            ctx.new_bp_desc = ImString::new("New breakpoint");
-            ui.open_popup(im_str!("##bp#new"));
-        }
-
-        let mut bp_changed = false;
-
-        ui.columns(4, im_str!(""), true);
-        ui.set_column_offset(1, 30.0);
-        ui.set_column_offset(2, 110.0);
-        for (idx, bp) in cpu.breakpoints.iter_mut().enumerate() {
-            let name = im_str!("###breakpoints#active#{}", idx);
-            if ui.checkbox(&name, &mut bp.active) {
-                // Changing activation requires update to fastmap
-                bp_changed = true;
-            }
-            ui.next_column();
-
-            let name = im_str!("###breakpoints#pc#{}", idx);
-            if imgui_input_hex(ui, &name, &mut bp.pc, true) {
-                // Changing PC requires update to fastmap
-                bp_changed = true;
-            }
-            ui.next_column();
-
-            let name = im_str!("###breakpoints#desc#{}", idx);
-            let mut sdesc = ImString::new(bp.description.clone());
-            if ui
-                .input_text(&name, &mut sdesc)
-                .enter_returns_true(true)
-                .auto_select_all(true)
-                .build()
-            {
-                bp.description = sdesc.to_str().to_owned();
-            }
-            ui.next_column();
-
-            ui.text(im_str!("{}", "Always"));
-            ui.next_column();
-        }
-        ui.columns(1, im_str!(""), false);
-
-        // Refresh breakpoint hashmap if required
-        if bp_changed {
-            cpu.update_bp_fastmap();
-        }
-    }
-
-    fn render_watchpoints(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx, cpu_name: &str) {
-        let cpu = self.cpus.get_mut(cpu_name).unwrap();
-
-        ui.popup(im_str!("##wp#new"), || {
-            ui.text(im_str!("Address:"));
-            ui.same_line(80.0);
-            imgui_input_hex(ui, im_str!("###wp#new_addr"), &mut ctx.new_wp_addr, false);
-
-            ui.text(im_str!("Desc:"));
-            ui.same_line(80.0);
-            ui.input_text(im_str!("###wp#new_desc"), &mut ctx.new_wp_desc)
-                .auto_select_all(true)
-                .build();
-
-            ui.text(im_str!("Type:"));
-            ui.same_line(80.0);
-            ui.radio_button(im_str!("Read"), &mut ctx.new_wp_type, 0);
-            ui.same_line(150.0);
-            ui.radio_button(im_str!("Write"), &mut ctx.new_wp_type, 1);
-
-            ui.text(im_str!("Condition:"));
-            ui.same_line(80.0);
-            ComboBox::new(&im_str!("###wp#new_cond"))
-                .build_simple_string(&ui, &mut ctx.new_wp_cond,
-                    &[
-                        im_str!("Always"),
-                        im_str!("=="),
-                        im_str!("!="),
-                        im_str!(">="),
-                        im_str!("<="),
-                        im_str!(">"),
-                        im_str!("<"),
-                    ]);
-
-            if ctx.new_wp_cond != 0 {
-                ui.text(im_str!("Value:"));
-                ui.same_line(80.0);
-                imgui_input_hex(ui, im_str!("###wp#new_value"), &mut ctx.new_wp_value, false);
-            }
-
-            if ui.button(im_str!("Add"), [40.0, 20.0]) {
-                let desc = ctx.new_wp_desc.to_str().to_owned();
-                let wtype = if ctx.new_wp_type == 0 {
-                    WatchpointType::Read
-                } else {
-                    WatchpointType::Write
-                };
-                let cond = match ctx.new_wp_cond {
-                    0 => WatchpointCondition::Always,
-                    1 => WatchpointCondition::Eq(ctx.new_wp_value),
-                    2 => WatchpointCondition::Ne(ctx.new_wp_value),
-                    3 => WatchpointCondition::Ge(ctx.new_wp_value),
-                    4 => WatchpointCondition::Le(ctx.new_wp_value),
-                    5 => WatchpointCondition::Gt(ctx.new_wp_value),
-                    6 => WatchpointCondition::Lt(ctx.new_wp_value),
-                    _ => unreachable!(),
-                };
-                cpu.add_watchpoint(ctx.new_wp_addr, &desc, wtype, cond);
-                ui.close_current_popup();
-            }
-        });
-        if ui.small_button(im_str!("New WP")) {
-            ctx.new_wp_addr = 0;
-            ctx.new_wp_desc = ImString::new("New watchpoint");
-            ctx.new_wp_type = 0;
-            ctx.new_wp_cond = 0;
-            ctx.new_wp_value = 0;
-            ui.open_popup(im_str!("##wp#new"));
-        }
-
-        let mut wp_changed = false;
-
-        ui.columns(4, im_str!(""), true);
-        ui.set_column_offset(1, 30.0);
-        ui.set_column_offset(2, 110.0);
-        for (idx, wp) in cpu.watchpoints.iter_mut().enumerate() {
-            let name = im_str!("###watchpoints#active#{}", idx);
-            if ui.checkbox(&name, &mut wp.active) {
-                // Changing activation requires update to fastmap
-                wp_changed = true;
-            }
-            ui.next_column();
-
-            let name = im_str!("###watchpoints#addr#{}", idx);
-            if imgui_input_hex(ui, &name, &mut wp.addr, true) {
-                // Changing addr requires update to fastmap
-                wp_changed = true;
-            }
-            ui.next_column();
-
-            let name = im_str!("###watchpoint#desc#{}", idx);
-            let mut sdesc = ImString::new(wp.description.clone());
-            if ui
-                .input_text(&name, &mut sdesc)
-                .enter_returns_true(true)
-                .auto_select_all(true)
-                .build()
-            {
-                wp.description = sdesc.to_str().to_owned();
-            }
-            ui.next_column();
-
-            ui.text(im_str!("{}", wp.cond_to_string()));
-            ui.next_column();
-        }
-        ui.columns(1, im_str!(""), false);
-
-        // Refresh breakpoint hashmap if required
-        if wp_changed {
-            cpu.update_wp_fastmap();
-        }
-    }
-
-    fn render_points(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx) {
-        for idx in 0..ctx.cpus.len() {
-            let cpu_name = ctx.cpus[idx].clone();
-
-            Window::new(&im_str!("[{}] Breakpoints & Watchpoints", cpu_name))
-                .size([200.0, 400.0], Condition::FirstUseEver)
-                .build(ui, || {
-                    if ui
-                        .collapsing_header(im_str!("Breakpoints"))
-                        .default_open(true)
-                        .build()
-                    {
-                        self.render_breakpoints(ui, ctx, &cpu_name);
-                    }
-                    if ui
-                        .collapsing_header(im_str!("Watchpoints"))
-                        .default_open(true)
-                        .build()
-                    {
-                        self.render_watchpoints(ui, ctx, &cpu_name);
-                    }
-                });
-        }
-    }
-
-    pub(crate) fn render_main(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx) {
-        self.render_points(ui, ctx);
-    }
-}
-
-use self::inthashmap::IntHashMap;
+pub(super) use self::inthashmap::IntHashMap;
 mod inthashmap {
     // Simple integer hasher from:
     // https://users.rust-lang.org/t/hashmap-performance/6476/14
@@ -711,3 +755,55 @@ mod inthashmap {
     use std::hash::BuildHasherDefault;
     pub type IntHashMap<K, V> = HashMap<K, V, BuildHasherDefault<SimpleHasher>>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_wp(cpu: &mut DbgCpu, addr: u64, len: u64) {
+        cpu.add_watchpoint(
+            addr,
+            len,
+            "",
+            WatchpointType::ReadWrite,
+            WatchpointCondition::Always,
+            WatchpointAction::Break,
+        );
+    }
+
+    #[test]
+    fn watchpoint_fastmap() {
+        let mut cpu = DbgCpu::default();
+        add_wp(&mut cpu, 0x1004, 0x10);
+        let mut blocks: Vec<u64> = cpu.wp_fastmap.keys().cloned().collect();
+        blocks.sort();
+        assert_eq!(blocks, vec![0x1000, 0x1008, 0x1010]);
+
+        let wp = &cpu.watchpoints[0];
+        assert!(!wp.overlaps(0x1000, AccessSize::Size32));
+        assert!(wp.overlaps(0x1000, AccessSize::Size64));
+        assert!(wp.overlaps(0x1013, AccessSize::Size8));
+        assert!(!wp.overlaps(0x1014, AccessSize::Size8));
+    }
+
+    #[test]
+    fn watchpoint_huge_range() {
+        let mut cpu = DbgCpu::default();
+        add_wp(&mut cpu, 0x8000_0000, u64::max_value());
+        assert_eq!(cpu.watchpoints[0].len, MAX_WATCHPOINT_LEN);
+        assert_eq!(cpu.wp_fastmap.len() as u64, MAX_WATCHPOINT_LEN / 8);
+
+        // A range crossing the end of the address space is truncated there.
+        add_wp(&mut cpu, u64::max_value() - 3, 0x100);
+        let wp = cpu
+            .watchpoints
+            .iter()
+            .find(|wp| wp.addr == u64::max_value() - 3)
+            .unwrap();
+        assert_eq!(wp.last_addr(), u64::max_value());
+        assert!(wp.overlaps(u64::max_value(), AccessSize::Size8));
+        assert!(wp.overlaps(u64::max_value() - 7, AccessSize::Size64));
+        assert!(!wp.overlaps(0, AccessSize::Size64));
+        assert!(cpu.wp_fastmap[&(u64::max_value() & WP_BLOCK_MASK)].len() == 1);
+    }
+}
@@ -0,0 +1,878 @@
+//! Debugger user interface: the ImGui windows drawn over the emulator
+//! screen, and the loop that runs a DebuggerModel under the debugger.
+use super::insntrace::render_insn_trace;
+use super::profiler::render_profiler;
+pub use super::uisupport::imgui_input_hex;
+use super::uisupport::render_reg_tooltip;
+use super::*;
+use crate::config::ConfigSection;
+use crate::gfx::GfxBufferLE;
+use crate::hw::{
+    AudioControl, HotkeyAction, Hotkeys, InputMapping, ScaleMode, SlowAudio, SpeedControl,
+    VideoOptions,
+};
+use crate::input::InputManager;
+use crate::log::LogPoolPtr;
+pub use imgui;
+use imgui::im_str;
+use imgui_opengl_renderer::Renderer;
+use imgui_sdl2::ImguiSdl2;
+use imgui_sys::{igSetNextWindowSizeConstraints, ImGuiSizeCallbackData};
+use serde_derive::{Deserialize, Serialize};
+use tinyfiledialogs::save_file_dialog_with_filter;
+
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+mod uictx;
+pub(crate) use self::uictx::*;
+mod miscview;
+pub(crate) use self::miscview::*;
+mod layout;
+pub(crate) use self::layout::*;
+mod cmdline;
+pub(crate) use self::cmdline::*;
+mod logview;
+pub use self::logview::*;
+mod logconfig;
+use self::logconfig::render_log_config;
+mod logconsole;
+use self::logconsole::render_log_console;
+mod inputview;
+pub(crate) use self::inputview::*;
+mod imageview;
+pub use self::imageview::ImageView;
+pub(crate) use self::imageview::*;
+mod watchpoints;
+
+/// Layout of the debugger windows (position, size, docking), persisted in
+/// the "debugger" section of the config file.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DebuggerLayout {
+    imgui: String,              // ImGui settings, in INI format
+    closed: Vec<String>,        // Titles of the windows closed by the user
+    log_windows: Option<usize>, // Number of log windows (if ever saved)
+}
+
+impl ConfigSection for DebuggerLayout {
+    const NAME: &'static str = "debugger";
+}
+
+pub struct DebuggerUI {
+    imgui: Rc<RefCell<imgui::Context>>,
+    imgui_sdl2: ImguiSdl2,
+    backend: Renderer,
+    hidpi_factor: f32,
+    screen: ImageView,
+    screen_size: (usize, usize),
+
+    pub dbg: Debugger,
+    uictx: RefCell<UiCtx>,
+    logpool: LogPoolPtr,
+
+    paused: bool,
+    frame_pause: bool,    // pause as soon as the current frame is complete
+    last_render: Instant, // last instant the debugger refreshed its UI
+    audio: Option<AudioControl>,
+    speed: Option<SpeedControl>,
+    video: Option<Rc<Cell<VideoOptions>>>,
+    input: Option<Rc<RefCell<InputMapping>>>,
+    remote: Option<RemoteServer>,
+}
+
+impl DebuggerUI {
+    pub fn new<T: DebuggerModel>(
+        video: sdl2::VideoSubsystem,
+        window: &sdl2::video::Window,
+        producer: &mut T,
+        logpool: LogPoolPtr,
+    ) -> Self {
+        let hidpi_factor = 1.0;
+
+        let mut imgui = imgui::Context::create();
+        imgui.set_ini_filename(None); // see load_layout() and save_layout()
+
+        let imgui_sdl2 = ImguiSdl2::new(&mut imgui, &window);
+        let backend = Renderer::new(&mut imgui, move |s| video.gl_get_proc_address(s) as _);
+
+        let mut uictx = UiCtx::default();
+        uictx.cpus = producer.all_cpus();
+        uictx.regdb = producer.reg_database();
+        for idx in 0..uictx.cpus.len() {
+            let name = &uictx.cpus[idx];
+            uictx.disasm.insert(name.clone(), UiCtxDisasm::default());
+        }
+
+        // Initial event
+        uictx.event = Some((box TraceEvent::Paused(), Instant::now()));
+
+        let mut dbg = Self {
+            imgui: Rc::new(RefCell::new(imgui)),
+            imgui_sdl2,
+            backend,
+            hidpi_factor,
+            logpool,
+            screen: ImageView::new(),
+            screen_size: (320, 240),
+            dbg: Debugger::new(&uictx.cpus),
+            uictx: RefCell::new(uictx),
+            paused: true,
+            frame_pause: false,
+            last_render: Instant::now(),
+            audio: None,
+            speed: None,
+            video: None,
+            input: None,
+            remote: None,
+        };
+        dbg.logs_new_window(); // immediately create and show at least one log window
+        dbg
+    }
+
+    // Handle an incoming SDL2 event within the debugger. Returns true if
+    // the event was internally handled and should not be further processed
+    // by caller.
+    //
+    // Example:
+    //   for event in event_pump.poll_iter() {
+    //       if dbgui.handle_event(event) {
+    //           continue;
+    //       }
+    //       match event {
+    //           [...]
+    //       }
+    //   }
+    //
+    pub fn handle_event(&mut self, event: &sdl2::event::Event) -> bool {
+        let imgui = self.imgui.clone();
+        let mut imgui = imgui.borrow_mut();
+        self.imgui_sdl2.handle_event(&mut imgui, &event);
+        return self.imgui_sdl2.ignore_event(&event);
+    }
+
+    /// Run an emulator (DebuggerModel) under the debugger for a little while.
+    /// Returns true if during this call the emulator completed a frame, or false otherwise.
+    pub fn trace<T: DebuggerModel, SF: SampleFormat>(
+        &mut self,
+        producer: &mut T,
+        screen: &mut GfxBufferMutLE<Rgb888>,
+        sound: &mut SndBufferMut<SF>,
+    ) -> bool {
+        // If the emulation core is paused, we can simply wait here to avoid hogging CPU.
+        // Refresh every 16ms / 60FPS.
+        if self.paused {
+            match Duration::from_millis(16).checked_sub(self.last_render.elapsed()) {
+                Some(d) => std::thread::sleep(d),
+                None => {}
+            }
+            return false;
+        }
+
+        // Request a Poll event after 50ms to keep the debugger at least at 20 FPS during emulation.
+        let trace_until = self.last_render + Duration::from_millis(50);
+        self.dbg.set_poll_event(trace_until);
+
+        match producer.trace_frame(screen, sound, &self.dbg.new_tracer()) {
+            Ok(()) => {
+                // A frame is finished. Copy it into the texture so that it's available
+                // starting from next render().
+                let (width, height) = (screen.width(), screen.height());
+                let (pixels, pitch) = screen.raw();
+                self.screen
+                    .update(&GfxBufferLE::<Rgb888>::new(pixels, width, height, pitch).unwrap());
+                self.screen_size = (width, height);
+                if self.frame_pause {
+                    self.frame_pause = false;
+                    self.paused = true;
+                    self.set_event(box TraceEvent::Paused());
+                }
+                return true;
+            }
+            Err(event) => {
+                self.set_event(event.clone());
+                match *event {
+                    TraceEvent::Poll() => return false, // Polling
+                    TraceEvent::Breakpoint(_, _, _) => {
+                        self.paused = true;
+                        self.dbg.disable_breakpoint_oneshot();
+                        return false;
+                    }
+                    TraceEvent::WatchpointRead(cpu_name, _) => {
+                        self.paused = true;
+                        self.dbg.disable_breakpoint_oneshot();
+                        self.uictx
+                            .get_mut()
+                            .add_flash_msg(&format!("Watchpoint (read) hit on {}", cpu_name));
+                        return false;
+                    }
+                    TraceEvent::WatchpointWrite(cpu_name, _) => {
+                        self.paused = true;
+                        self.dbg.disable_breakpoint_oneshot();
+                        self.uictx
+                            .get_mut()
+                            .add_flash_msg(&format!("Watchpoint (write) hit on {}", cpu_name));
+                        return false;
+                    }
+                    TraceEvent::BreakpointOneShot(_, _) => {
+                        self.paused = true;
+                        self.dbg.disable_breakpoint_oneshot();
+                        return false;
+                    }
+                    TraceEvent::GenericBreak(msg) => {
+                        self.paused = true;
+                        self.dbg.disable_breakpoint_oneshot();
+                        self.uictx
+                            .get_mut()
+                            .add_flash_msg(&format!("Emulation stopped:\n{}", msg));
+                        return false;
+                    }
+                    _ => unimplemented!(),
+                }
+            }
+        };
+    }
+
+    /// Show an error message in a modal popup.
+    pub fn show_error(&mut self, msg: String) {
+        self.uictx.get_mut().error_msg = Some(msg);
+    }
+
+    /// Show a short notification message, that fades out automatically.
+    pub fn show_message(&mut self, msg: &str) {
+        self.uictx.get_mut().add_flash_msg(msg);
+    }
+
+    /// Connect the debugger to the audio output, to show volume and mute
+    /// controls.
+    pub fn set_audio_control(&mut self, ctl: AudioControl) {
+        self.audio = Some(ctl);
+    }
+
+    /// Connect the debugger to the speed control, to show the "Speed" menu.
+    pub fn set_speed_control(&mut self, ctl: SpeedControl) {
+        self.speed = Some(ctl);
+    }
+
+    /// Connect the debugger to the video output options, to show the "Video"
+    /// menu. They apply to the emulator output outside of the debugger.
+    pub(crate) fn set_video_options(&mut self, opts: Rc<Cell<VideoOptions>>) {
+        self.video = Some(opts);
+    }
+
+    /// Connect the debugger to the hotkeys of the main emulator window, so
+    /// that they're shared, and can be rebound through the "Hotkeys" window.
+    pub(crate) fn set_hotkeys(&mut self, hotkeys: Rc<RefCell<Hotkeys>>) {
+        self.uictx.get_mut().hotkeys = hotkeys;
+    }
+
+    /// Connect the debugger to the input mapping, to allow rebinding inputs
+    /// through the "Input Config" window.
+    pub(crate) fn set_input_mapping(&mut self, map: Rc<RefCell<InputMapping>>) {
+        self.input = Some(map);
+    }
+
+    /// Serve a remote debugger while the UI is running: its clients
+    /// control the emulation together with the UI.
+    pub fn set_remote_server(&mut self, server: RemoteServer) {
+        self.remote = Some(server);
+    }
+
+    /// Render the current debugger UI.
+    pub fn render<T: DebuggerModel>(
+        &mut self,
+        window: &sdl2::video::Window,
+        event_pump: &sdl2::EventPump,
+        model: &mut T,
+    ) {
+        let imgui = self.imgui.clone();
+        let mut imgui = imgui.borrow_mut();
+        self.imgui_sdl2
+            .prepare_frame(imgui.io_mut(), &window, &event_pump.mouse_state());
+
+        let now = Instant::now();
+        let delta = now - self.last_render;
+        let delta_s = delta.as_secs() as f32 + delta.subsec_nanos() as f32 / 1_000_000_000.0;
+        self.last_render = Instant::now();
+        imgui.io_mut().delta_time = delta_s;
+
+        let ui = imgui.frame();
+        self.uictx.get_mut().paused = self.paused;
+        self.render_main(&ui, model);
+        ui.show_demo_window(&mut true);
+
+        {
+            let dr = DebuggerRenderer {
+                ui: &ui,
+                ctx: &self.uictx,
+                dbg: &self.dbg,
+            };
+            model.render_debug(&dr);
+        }
+
+        // Actually flush commands batched in imgui to OpenGL
+        unsafe {
+            gl::ClearColor(0.45, 0.55, 0.60, 0.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+        }
+
+        self.backend.render(ui);
+
+        let uictx = self.uictx.get_mut();
+        uictx.event = None;
+        let mut command = uictx.command.take();
+        if command.is_none() {
+            if let Some(remote) = self.remote.as_mut() {
+                command = remote.serve(&mut self.dbg, model, self.paused);
+            }
+        }
+        if let Some(cmd) = command {
+            let event = apply_command(
+                cmd,
+                &mut self.dbg,
+                model,
+                &mut self.paused,
+                &mut self.frame_pause,
+            );
+            if let Some(event) = event {
+                self.set_event(event);
+            }
+        }
+    }
+
+    // Record the event that stopped the emulation, to be shown by the UI
+    // and notified to the remote debugger clients (if any).
+    fn set_event(&mut self, event: Box<TraceEvent>) {
+        if let Some(remote) = self.remote.as_ref() {
+            remote.notify_event(&event);
+        }
+        self.uictx.get_mut().event = Some((event, Instant::now()));
+    }
+
+    fn render_main<'ui, T: DebuggerModel>(&mut self, ui: &imgui::Ui<'ui>, model: &mut T) {
+        self.uictx.get_mut().layout.new_frame();
+
+        let hotkeys = self.uictx.get_mut().hotkeys.clone();
+        let hk = hotkeys.borrow();
+        let shortcut = |action| imgui::ImString::new(hk.describe(action));
+
+        let use_global_keys = !ui.io().want_text_input;
+        if use_global_keys && hk.pressed(ui, HotkeyAction::RunStop) {
+            self.paused = !self.paused;
+            self.frame_pause = false;
+            if self.paused {
+                self.set_event(box TraceEvent::Paused());
+            }
+        }
+        if use_global_keys && hk.pressed(ui, HotkeyAction::Pause) {
+            self.uictx.get_mut().command = Some(if self.paused {
+                UiCommand::Pause(false)
+            } else {
+                UiCommand::PauseAtFrameEnd
+            });
+        }
+        if use_global_keys && hk.pressed(ui, HotkeyAction::FrameAdvance) {
+            self.uictx.get_mut().command = Some(UiCommand::FrameAdvance);
+        }
+
+        render_flash_msgs(ui, self.uictx.get_mut());
+
+        let help = render_help(ui, &hk);
+        if use_global_keys && hk.pressed(ui, HotkeyAction::Help) {
+            ui.open_popup(&help);
+        }
+
+        let mut open_hotkeys = false;
+        ui.main_menu_bar(|| {
+            ui.menu(im_str!("Emulation"), true, || {
+                if imgui::MenuItem::new(im_str!("Soft Reset")).build(ui) {
+                    if let Err(err) = model.reset(false) {
+                        self.uictx.get_mut().error_msg = Some(err);
+                    }
+                }
+                if imgui::MenuItem::new(im_str!("Hard Reset")).build(ui) {
+                    if let Err(err) = model.reset(true) {
+                        self.uictx.get_mut().error_msg = Some(err);
+                    }
+                }
+                ui.separator();
+                if imgui::MenuItem::new(im_str!("Pause at End of Frame"))
+                    .shortcut(&shortcut(HotkeyAction::Pause))
+                    .enabled(!self.paused)
+                    .build(ui)
+                {
+                    self.uictx.get_mut().command = Some(UiCommand::PauseAtFrameEnd);
+                }
+                if imgui::MenuItem::new(im_str!("Frame Advance"))
+                    .shortcut(&shortcut(HotkeyAction::FrameAdvance))
+                    .build(ui)
+                {
+                    self.uictx.get_mut().command = Some(UiCommand::FrameAdvance);
+                }
+                ui.separator();
+                if imgui::MenuItem::new(im_str!("Save State"))
+                    .shortcut(&shortcut(HotkeyAction::SaveState))
+                    .build(ui)
+                {
+                    if let Err(err) = model.save_state() {
+                        self.uictx.get_mut().error_msg = Some(err);
+                    }
+                }
+                if imgui::MenuItem::new(im_str!("Load State"))
+                    .shortcut(&shortcut(HotkeyAction::LoadState))
+                    .build(ui)
+                {
+                    if let Err(err) = model.load_state() {
+                        self.uictx.get_mut().error_msg = Some(err);
+                    }
+                }
+            });
+
+            ui.menu(im_str!("Logs"), true, || {
+                if imgui::MenuItem::new(im_str!("Save all to disk...")).build(ui) {
+                    self.logs_save(ui);
+                }
+                if imgui::MenuItem::new(im_str!("New window")).build(ui) {
+                    self.logs_new_window();
+                }
+            });
+
+            ui.menu(im_str!("Windows"), true, || {
+                let layout = &mut self.uictx.get_mut().layout;
+                if imgui::MenuItem::new(im_str!("Arrange")).build(ui) {
+                    layout.arrange(ui.io().display_size);
+                }
+                ui.separator();
+                layout.render_menu(ui);
+            });
+
+            if let Some(audio) = self.audio.as_ref() {
+                ui.menu(im_str!("Audio"), true, || {
+                    let mut muted = audio.muted();
+                    if ui.checkbox(im_str!("Mute"), &mut muted) {
+                        audio.set_muted(muted);
+                    }
+                    let mut volume = audio.volume();
+                    if ui
+                        .slider_float(im_str!("Volume"), &mut volume, 0.0, 1.0)
+                        .build()
+                    {
+                        audio.set_volume(volume);
+                    }
+                    ui.separator();
+                    ui.text(format!("Latency: {} ms", audio.latency().as_millis()));
+                    ui.text(format!("Underruns: {}", audio.underruns()));
+                });
+            }
+
+            if let Some(video) = self.video.as_ref() {
+                ui.menu(im_str!("Video"), true, || {
+                    let mut opts = video.get();
+                    for (mode, label) in &[
+                        (ScaleMode::Stretch, im_str!("Stretch to window")),
+                        (ScaleMode::Aspect, im_str!("Keep aspect ratio")),
+                        (ScaleMode::Integer, im_str!("Integer scaling")),
+                    ] {
+                        if imgui::MenuItem::new(*label)
+                            .selected(opts.scale == *mode)
+                            .build(ui)
+                        {
+                            opts.scale = *mode;
+                        }
+                    }
+                    ui.separator();
+                    imgui::MenuItem::new(im_str!("Bilinear filtering"))
+                        .build_with_ref(ui, &mut opts.bilinear);
+                    ui.slider_float(im_str!("Scanlines"), &mut opts.scanlines, 0.0, 1.0)
+                        .build();
+                    ui.separator();
+                    ui.text("Internal resolution:");
+                    for (upscale, label) in &[
+                        (1, im_str!("Native")),
+                        (2, im_str!("2x")),
+                        (4, im_str!("4x")),
+                    ] {
+                        if imgui::MenuItem::new(*label)
+                            .selected(opts.upscale == *upscale)
+                            .build(ui)
+                        {
+                            opts.upscale = *upscale;
+                        }
+                    }
+                    ui.separator();
+                    imgui::MenuItem::new(im_str!("Show FPS and speed"))
+                        .shortcut(&shortcut(HotkeyAction::ToggleOsd))
+                        .build_with_ref(ui, &mut opts.osd_stats);
+                    video.set(opts);
+                });
+            }
+
+            if let Some(speed) = self.speed.as_ref() {
+                ui.menu(im_str!("Speed"), true, || {
+                    let mut ff = speed.fast_forward();
+                    if imgui::MenuItem::new(im_str!("Fast Forward"))
+                        .shortcut(&im_str!("Hold {}", hk.describe(HotkeyAction::FastForward)))
+                        .build_with_ref(ui, &mut ff)
+                    {
+                        speed.set_fast_forward(ff);
+                    }
+                    ui.separator();
+                    let mut turbo = speed.turbo();
+                    if imgui::MenuItem::new(im_str!("Turbo"))
+                        .shortcut(&shortcut(HotkeyAction::Turbo))
+                        .build_with_ref(ui, &mut turbo)
+                    {
+                        speed.set_turbo(turbo);
+                    }
+                    let mut ratio = speed.turbo_ratio();
+                    if ui
+                        .slider_float(
+                            im_str!("Turbo speed"),
+                            &mut ratio,
+                            SpeedControl::MIN_TURBO,
+                            SpeedControl::MAX_TURBO,
+                        )
+                        .display_format(im_str!("%.1fx"))
+                        .build()
+                    {
+                        speed.set_turbo_ratio(ratio);
+                    }
+                    ui.separator();
+                    let mut slow = speed.slow_motion();
+                    if imgui::MenuItem::new(im_str!("Slow Motion"))
+                        .shortcut(&shortcut(HotkeyAction::SlowMotion))
+                        .build_with_ref(ui, &mut slow)
+                    {
+                        speed.set_slow_motion(slow);
+                    }
+                    let mut ratio = speed.slow_ratio() * 100.0;
+                    if ui
+                        .slider_float(
+                            im_str!("Slow speed"),
+                            &mut ratio,
+                            SpeedControl::MIN_SLOW * 100.0,
+                            SpeedControl::MAX_SLOW * 100.0,
+                        )
+                        .display_format(im_str!("%.0f%%"))
+                        .build()
+                    {
+                        speed.set_slow_ratio(ratio / 100.0);
+                    }
+                    let mut mute = speed.slow_audio() == SlowAudio::Mute;
+                    if ui.checkbox(im_str!("Mute audio in slow motion"), &mut mute) {
+                        speed.set_slow_audio(if mute {
+                            SlowAudio::Mute
+                        } else {
+                            SlowAudio::Stretch
+                        });
+                    }
+                });
+            }
+
+            ui.menu(im_str!("Input"), true, || {
+                if let Some(input) = self.input.as_ref() {
+                    if imgui::MenuItem::new(im_str!("Configure...")).build(ui) {
+                        input.borrow_mut().open_window();
+                    }
+                }
+                if imgui::MenuItem::new(im_str!("Hotkeys...")).build(ui) {
+                    open_hotkeys = true;
+                }
+            });
+
+            ui.same_line(200.0);
+            ui.text(im_str!("State:"));
+            if self.paused {
+                ui.text(im_str!("PAUSED"));
+                if ui.button(im_str!("Run"), [40.0, 20.0]) {
+                    self.paused = false;
+                    self.frame_pause = false;
+                }
+            } else {
+                ui.text(im_str!("RUNNING"));
+                if ui.button(im_str!("Pause"), [40.0, 20.0]) {
+                    self.paused = true;
+                    self.set_event(box TraceEvent::Paused());
+                }
+            }
+
+            ui.same_line(400.0);
+            ui.text(format!(
+                "Cycles: {}, Frames: {}",
+                model.cycles(),
+                model.frames()
+            ));
+        });
+
+        // Render screen (framebuffer)
+        unsafe {
+            // Set constraint to avoid distortion of the screen window
+            igSetNextWindowSizeConstraints(
+                (100.0, 100.0).into(),
+                (10000.0, 10000.0).into(),
+                Some(screen_resize_callback),
+                (&mut self.screen_size as *mut (usize, usize)) as *mut ::std::ffi::c_void,
+            );
+        }
+        let screen = &self.screen;
+        let uictx = self.uictx.get_mut();
+        let window = imgui::Window::new(im_str!("Screen"))
+            .size([320.0, 240.0], imgui::Condition::FirstUseEver);
+        uictx
+            .layout
+            .place(im_str!("Screen"), Dock::Right, window)
+            .build(ui, || {
+                let reg = ui.content_region_avail();
+                screen.render(ui, uictx, reg);
+            });
+
+        // Render CPU debugger
+        self.dbg.render_main(ui, self.uictx.get_mut());
+
+        // Render input and hotkeys configuration
+        if let Some(input) = self.input.as_ref() {
+            input.borrow_mut().render_window(ui);
+        }
+        drop(hk);
+        let mut hotkeys = hotkeys.borrow_mut();
+        if open_hotkeys {
+            hotkeys.open_window();
+        }
+        hotkeys.render_window(ui);
+        drop(hotkeys);
+
+        // Render logger views
+        let numframes = model.frames();
+        let mut logviewcmd = None;
+        let mut logpool = self.logpool.clone();
+        let uictx = self.uictx.get_mut();
+        for mut ctxlog in uictx.logviews.iter_mut() {
+            let cmd = render_logview(ui, &mut ctxlog, &mut uictx.layout, &mut logpool, numframes);
+            logviewcmd = logviewcmd.or(cmd);
+        }
+        self.uictx.get_mut().logviews.retain(|view| view.opened);
+        render_log_config(ui);
+        let cmd = render_log_console(ui, &mut self.uictx.get_mut().console);
+        logviewcmd = logviewcmd.or(cmd);
+
+        // Apply logview commands (if any)
+        match logviewcmd {
+            Some(LogViewCommand::ShowPc(cpu, pc)) => {
+                match self.uictx.get_mut().disasm.get_mut(&cpu) {
+                    Some(cctx) => {
+                        cctx.force_pc = Some(pc);
+                        cctx.blink_pc = Some((pc, Instant::now()));
+                    }
+                    None => {} // Requested CPU does not exist? Ignore
+                }
+            }
+            Some(LogViewCommand::ShowAddr(cpu, addr)) => {
+                for (name, memview) in self.uictx.get_mut().memviews.iter_mut() {
+                    if cpu.as_ref().map_or(true, |cpu| cpu == name) {
+                        memview.goto(addr);
+                    }
+                }
+            }
+            None => {}
+        };
+
+        // Error modal popup
+        unsafe {
+            imgui_sys::igSetNextWindowSize(imgui_sys::ImVec2 { x: 250.0, y: 150.0 }, 0);
+        }
+        ui.popup_modal(im_str!("Error")).build(|| {
+            let mut uictx = self.uictx.borrow_mut();
+            ui.text_wrapped(&im_str!("{}", uictx.error_msg.as_ref().unwrap()));
+            ui.separator();
+            if ui.button(im_str!("OK"), [0.0, 0.0]) {
+                uictx.error_msg = None;
+                ui.close_current_popup();
+            }
+        });
+        if self.uictx.borrow_mut().error_msg.is_some() {
+            ui.open_popup(im_str!("Error"));
+        }
+    }
+
+    fn logs_new_window(&mut self) {
+        let view = self.logpool.lock().unwrap().new_view();
+        let mut uictx = self.uictx.borrow_mut();
+        let name = if uictx.logviews.len() == 0 {
+            uictx.logviewid = 1;
+            "Logs".to_owned()
+        } else {
+            uictx.logviewid += 1;
+            format!("Logs #{}", uictx.logviewid)
+        };
+        uictx.logviews.push(UiCtxLog::new(view, &name));
+    }
+
+    fn logs_save(&mut self, ui: &imgui::Ui) {
+        if let Some(path) = save_file_dialog_with_filter(
+            "Save log file",
+            "emu.log",
+            &vec![".log"],
+            "Save all the logs to disk",
+        ) {
+            match self.logpool.lock().unwrap().save(&path) {
+                Ok(()) => {}
+                Err(err) => {
+                    self.uictx.borrow_mut().error_msg =
+                        Some(format!("An error occurred while saving logs: {}", err))
+                }
+            };
+        }
+    }
+
+    pub fn load_conf(
+        &mut self,
+        filename: &Path,
+    ) -> std::result::Result<(), Box<dyn std::error::Error + 'static>> {
+        self.dbg = serde_json::from_str(&fs::read_to_string(filename)?)?;
+        self.dbg.after_deserialize();
+        Ok(())
+    }
+
+    pub fn save_conf(&self, filename: &Path) -> std::result::Result<(), std::io::Error> {
+        let c = serde_json::to_string(&self.dbg).unwrap();
+        fs::write(filename, c)
+    }
+
+    /// Restore the layout of the debugger windows, including which windows
+    /// are open.
+    pub fn load_layout(&mut self, layout: &DebuggerLayout) {
+        self.imgui.borrow_mut().load_ini_settings(&layout.imgui);
+        self.uictx.get_mut().layout.set_closed(&layout.closed);
+        if let Some(count) = layout.log_windows {
+            self.uictx.get_mut().logviews.truncate(count);
+            while self.uictx.get_mut().logviews.len() < count {
+                self.logs_new_window();
+            }
+        }
+    }
+
+    /// Return the current layout of the debugger windows.
+    pub fn save_layout(&self) -> DebuggerLayout {
+        let mut imgui = String::new();
+        self.imgui.borrow_mut().save_ini_settings(&mut imgui);
+        let uictx = self.uictx.borrow();
+        DebuggerLayout {
+            imgui,
+            closed: uictx.layout.closed(),
+            log_windows: Some(uictx.logviews.len()),
+        }
+    }
+
+    /// Save the profile collected by the profiler (if any) as JSON.
+    pub fn save_profile(&self, filename: &Path) -> std::result::Result<(), std::io::Error> {
+        self.dbg.profiler.borrow().save(filename)
+    }
+    /// Export the code coverage collected so far (if any), as a sorted list
+    /// of executed PCs.
+    pub fn save_coverage(&self, filename: &Path) -> std::result::Result<(), std::io::Error> {
+        self.dbg.coverage.borrow().save(filename)
+    }
+}
+
+extern "C" fn screen_resize_callback(data: *mut ImGuiSizeCallbackData) {
+    unsafe {
+        // Constraint the screen window to the ratio of the actual framebuffer
+        // (as stored in the last frame).
+        let screen_size = (*data).UserData as *mut (usize, usize);
+        let ratio = ((*screen_size).1 as f32) / ((*screen_size).0 as f32);
+        (*data).DesiredSize.y = (*data).DesiredSize.x * ratio;
+    }
+}
+
+pub struct DebuggerRenderer<'a, 'ui> {
+    ui: &'a imgui::Ui<'ui>,
+    ctx: &'a RefCell<UiCtx>,
+    dbg: &'a Debugger,
+}
+
+impl<'a, 'ui> DebuggerRenderer<'a, 'ui> {
+    /// Access the underlying imgui frame, so that emulators can draw custom
+    /// debugger windows that don't fit into one of the generic views.
+    pub fn ui(&self) -> &'a imgui::Ui<'ui> {
+        self.ui
+    }
+    pub fn render_regview<V: RegisterView>(&self, v: &mut V) {
+        render_regview(self.ui, &mut self.ctx.borrow_mut(), v)
+    }
+    pub fn render_disasmview<V: DisasmView>(&self, v: &mut V) {
+        render_disasmview(self.ui, &mut self.ctx.borrow_mut(), self.dbg, v)
+    }
+    pub fn render_callstackview<V: CallstackView>(&self, v: &V) {
+        render_callstackview(self.ui, &mut self.ctx.borrow_mut(), v)
+    }
+    pub fn render_insn_trace<V: InsnTraceView>(&self, v: &V) {
+        render_insn_trace(self.ui, &mut self.ctx.borrow_mut(), self.dbg, v)
+    }
+    pub fn render_profiler<V: InsnTraceView>(&self, v: &V) {
+        render_profiler(self.ui, &mut self.ctx.borrow_mut(), self.dbg, v)
+    }
+    pub fn render_memoryview<V: MemoryView>(&self, v: &mut V) {
+        let ctx = &mut *self.ctx.borrow_mut();
+        if let Some((addr, len)) = ctx.cmdline.mem_dump {
+            if let Some(lines) = hex_dump(&*v, addr, len) {
+                ctx.cmdline.print(CmdOutput::Info, &lines.join("\n"));
+                ctx.cmdline.mem_dump = None;
+            }
+        }
+        if let Some((addr, len, path)) = ctx.cmdline.mem_save.clone() {
+            if let Some(res) = dump_mem(&*v, addr, len, Path::new(&path)) {
+                ctx.cmdline.mem_save = None;
+                match describe_file_op(Some(res), addr, "dump: saved") {
+                    Ok(msg) => ctx.cmdline.print(CmdOutput::Info, &msg),
+                    Err(err) => ctx.cmdline.print(CmdOutput::Error, &err),
+                }
+            }
+        }
+        if let Some((addr, path)) = ctx.cmdline.mem_load.clone() {
+            if let Some(res) = load_mem(&mut *v, addr, Path::new(&path)) {
+                ctx.cmdline.mem_load = None;
+                match describe_file_op(Some(res), addr, "load: loaded") {
+                    Ok(msg) => ctx.cmdline.print(CmdOutput::Info, &msg),
+                    Err(err) => ctx.cmdline.print(CmdOutput::Error, &err),
+                }
+            }
+        }
+        ctx.memviews
+            .entry(v.name().to_string())
+            .or_insert_with(|| MemWindow::default())
+            .render(self.ui, &mut ctx.layout, v, &ctx.regdb);
+    }
+    /// If the last drawn item is hovered and the address is a documented
+    /// hardware register, show a tooltip describing it. If `val` is
+    /// specified, the register bitfields are decoded.
+    pub fn reg_tooltip(&self, addr: u64, val: Option<u64>) {
+        render_reg_tooltip(self.ui, &self.ctx.borrow().regdb, addr, val)
+    }
+    pub fn render_imageview(&self, title: &str, v: &ImageView) {
+        render_imageview(self.ui, &mut self.ctx.borrow_mut(), title, v)
+    }
+    /// Draw an image within the current window, with the specified size.
+    pub fn render_image(&self, v: &ImageView, size: [f32; 2]) {
+        v.render(self.ui, &mut self.ctx.borrow_mut(), size)
+    }
+    /// Resume emulation if it is paused, eg: to run until a condition set
+    /// by a custom debugger window (like a single RDP command) is hit.
+    pub fn resume(&self) {
+        self.ctx.borrow_mut().command = Some(UiCommand::Pause(false));
+    }
+    pub fn render_input_calibration(&self, im: &mut InputManager) {
+        render_input_calibration(self.ui, &mut self.ctx.borrow_mut(), im)
+    }
+}
+
+impl Debugger {
+    pub(crate) fn render_main(&mut self, ui: &imgui::Ui<'_>, ctx: &mut UiCtx) {
+        self.render_breakpoints(ui, ctx);
+        self.render_watchpoints(ui, ctx);
+        self.render_insn_trace_settings(ui, ctx);
+        self.render_cmdline(ui, ctx);
+    }
+}
@@ -0,0 +1,455 @@
+use super::{Dock, UiCommand, UiCtx};
+use crate::dbg::tracer::{Debugger, WatchpointAction, WatchpointCondition, WatchpointType};
+
+use imgui::*;
+use tinyfiledialogs::open_file_dialog;
+
+use std::fs;
+
+// Maximum number of lines kept in the output of the command line.
+const MAX_LINES: usize = 1000;
+
+// Maximum number of bytes dumped by the "mem" command.
+const MAX_DUMP: u64 = 0x1000;
+
+const HELP: &str = "\
+b <pc>                  add a breakpoint
+bd <pc>                 delete a breakpoint
+w <addr> [r|w|rw] [len] add a watchpoint (default: rw, 4 bytes)
+wd <addr>               delete a watchpoint
+mem <addr> [len]        dump memory (default: 64 bytes)
+dump <addr> <len> <file> save memory into a binary file
+load <addr> <file>      load a binary file into memory
+reg <name>[=<value>]    show or change a register (only while paused)
+d <pc>                  show an address in the disassembly
+s                       step one instruction
+c                       continue emulation
+p                       pause emulation
+n                       frame advance
+cpu [name]              show or select the CPU commands apply to
+source <file>           run the commands in a file (one per line)
+Numbers are hexadecimal (0x prefix is optional).";
+
+// A command parsed from the command line.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Help,
+    Cpu(Option<String>),
+    Break(u64),
+    DeleteBreak(u64),
+    Watch(u64, WatchpointType, u64),
+    DeleteWatch(u64),
+    Mem(u64, u64),
+    Dump(u64, u64, String),
+    Load(u64, String),
+    Reg(String, Option<u64>),
+    Disasm(u64),
+    Step,
+    Continue,
+    Pause,
+    FrameAdvance,
+    Source(String),
+}
+
+fn parse_hex(s: &str) -> Result<u64, String> {
+    let digits = if s.starts_with("0x") || s.starts_with("0X") {
+        &s[2..]
+    } else {
+        s
+    };
+    u64::from_str_radix(digits, 16).map_err(|_| format!("invalid number: {:?}", s))
+}
+
+// Return the rest of the line after the first `n` words, eg: a file name
+// that may contain spaces.
+fn rest_of_line(line: &str, n: usize) -> &str {
+    let mut rest = line.trim();
+    for _ in 0..n {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        rest = rest[end..].trim_start();
+    }
+    rest.trim_end()
+}
+
+impl Command {
+    fn parse(line: &str) -> Result<Command, String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let name = *args.first().ok_or("empty command")?;
+        let missing = || format!("{}: missing argument (see \"help\")", name);
+        let arg = |idx: usize| args.get(idx).cloned().ok_or_else(missing);
+        let cmd = match name {
+            "help" | "h" | "?" => Command::Help,
+            "cpu" => Command::Cpu(args.get(1).map(|s| s.to_string())),
+            "b" | "bp" => Command::Break(parse_hex(arg(1)?)?),
+            "bd" => Command::DeleteBreak(parse_hex(arg(1)?)?),
+            "w" | "wp" => {
+                let wtype = match args.get(2).cloned() {
+                    Some("r") => WatchpointType::Read,
+                    Some("w") => WatchpointType::Write,
+                    Some("rw") | None => WatchpointType::ReadWrite,
+                    Some(t) => return Err(format!("invalid watchpoint type: {:?}", t)),
+                };
+                let len = args.get(3).map_or(Ok(4), |l| parse_hex(l))?;
+                Command::Watch(parse_hex(arg(1)?)?, wtype, len)
+            }
+            "wd" => Command::DeleteWatch(parse_hex(arg(1)?)?),
+            "mem" | "m" => {
+                let len = args.get(2).map_or(Ok(64), |l| parse_hex(l))?;
+                Command::Mem(parse_hex(arg(1)?)?, len.min(MAX_DUMP))
+            }
+            "dump" => {
+                let (addr, len) = (parse_hex(arg(1)?)?, parse_hex(arg(2)?)?);
+                let path = rest_of_line(line, 3);
+                if path.is_empty() {
+                    return Err(missing());
+                }
+                Command::Dump(addr, len, path.to_owned())
+            }
+            "load" => {
+                let addr = parse_hex(arg(1)?)?;
+                let path = rest_of_line(line, 2);
+                if path.is_empty() {
+                    return Err(missing());
+                }
+                Command::Load(addr, path.to_owned())
+            }
+            "reg" | "r" => {
+                // Accept both "reg t0=5" and "reg t0 = 5".
+                let assign = args[1..].concat();
+                let mut parts = assign.splitn(2, '=');
+                let name = parts.next().unwrap();
+                if name.is_empty() {
+                    return Err(missing());
+                }
+                let val = match parts.next() {
+                    Some(v) => Some(parse_hex(v)?),
+                    None => None,
+                };
+                Command::Reg(name.to_lowercase(), val)
+            }
+            "d" | "goto" => Command::Disasm(parse_hex(arg(1)?)?),
+            "s" | "step" => Command::Step,
+            "c" | "continue" => Command::Continue,
+            "p" | "pause" => Command::Pause,
+            "n" | "next" => Command::FrameAdvance,
+            "source" => Command::Source(line.trim()["source".len()..].trim().to_owned()),
+            cmd => return Err(format!("unknown command: {:?} (see \"help\")", cmd)),
+        };
+        Ok(cmd)
+    }
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum CmdOutput {
+    Echo,
+    Info,
+    Error,
+}
+
+// Local state of the command line window. Some commands need to access the
+// memory or the registers of a CPU: they're stored here as requests, and
+// served by the memory and register views while they're rendered.
+pub(crate) struct UiCtxCmdLine {
+    input: ImString,
+    lines: Vec<(CmdOutput, String)>,
+    scroll: bool,
+    cpu: Option<String>, // CPU commands apply to (None: the first one)
+
+    pub mem_dump: Option<(u64, u64)>,         // (addr, len)
+    pub mem_save: Option<(u64, u64, String)>, // (addr, len, file)
+    pub mem_load: Option<(u64, String)>,      // (addr, file)
+    pub reg_access: Option<(String, String, Option<u64>)>, // (cpu, register, new value)
+}
+
+impl Default for UiCtxCmdLine {
+    fn default() -> Self {
+        Self {
+            input: ImString::with_capacity(256),
+            lines: Vec::new(),
+            scroll: false,
+            cpu: None,
+            mem_dump: None,
+            mem_save: None,
+            mem_load: None,
+            reg_access: None,
+        }
+    }
+}
+
+impl UiCtxCmdLine {
+    pub(crate) fn print(&mut self, kind: CmdOutput, msg: &str) {
+        for line in msg.lines() {
+            self.lines.push((kind, line.to_owned()));
+        }
+        if self.lines.len() > MAX_LINES {
+            let excess = self.lines.len() - MAX_LINES;
+            self.lines.drain(..excess);
+        }
+        self.scroll = true;
+    }
+
+    // Report requests that were not served by any view in the last frame.
+    fn flush_requests(&mut self) {
+        if let Some((addr, _)) = self.mem_dump.take() {
+            self.print(
+                CmdOutput::Error,
+                &format!("mem: address {:x} not found in any memory view", addr),
+            );
+        }
+        if let Some((addr, _, _)) = self.mem_save.take() {
+            self.print(
+                CmdOutput::Error,
+                &format!("dump: address {:x} not found in any memory view", addr),
+            );
+        }
+        if let Some((addr, _)) = self.mem_load.take() {
+            self.print(
+                CmdOutput::Error,
+                &format!("load: address {:x} not found in any memory view", addr),
+            );
+        }
+        if let Some((cpu, reg, _)) = self.reg_access.take() {
+            self.print(
+                CmdOutput::Error,
+                &format!("reg: no register {} in {}", reg, cpu),
+            );
+        }
+    }
+}
+
+impl Debugger {
+    // Execute a command line, printing the result in the command line window.
+    fn exec_command(&mut self, ctx: &mut UiCtx, line: &str, depth: usize) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return;
+        }
+        ctx.cmdline.print(CmdOutput::Echo, &format!("> {}", line));
+        if let Err(err) = self.exec_parsed(ctx, line, depth) {
+            ctx.cmdline.print(CmdOutput::Error, &err);
+        }
+    }
+
+    fn exec_parsed(&mut self, ctx: &mut UiCtx, line: &str, depth: usize) -> Result<(), String> {
+        let cpu_name = ctx
+            .cmdline
+            .cpu
+            .clone()
+            .unwrap_or_else(|| ctx.cpus[0].clone());
+        let info = |ctx: &mut UiCtx, msg: String| ctx.cmdline.print(CmdOutput::Info, &msg);
+
+        match Command::parse(line)? {
+            Command::Help => info(ctx, HELP.to_owned()),
+            Command::Cpu(None) => {
+                let msg = format!("{} (all: {})", cpu_name, ctx.cpus.join(", "));
+                info(ctx, msg);
+            }
+            Command::Cpu(Some(name)) => {
+                let name = ctx
+                    .cpus
+                    .iter()
+                    .find(|c| c.eq_ignore_ascii_case(&name))
+                    .ok_or_else(|| format!("unknown CPU: {}", name))?
+                    .clone();
+                ctx.cmdline.cpu = Some(name);
+            }
+            Command::Break(pc) => {
+                let cpu = self.cpus.get_mut(&cpu_name).unwrap();
+                if cpu.breakpoints.iter().any(|bp| bp.pc == pc) {
+                    return Err(format!("breakpoint at {:x} already exists", pc));
+                }
+                self.add_breakpoint(&cpu_name, pc, "");
+                info(ctx, format!("[{}] breakpoint at {:x}", cpu_name, pc));
+            }
+            Command::DeleteBreak(pc) => {
+                let cpu = self.cpus.get_mut(&cpu_name).unwrap();
+                let idx = cpu
+                    .breakpoints
+                    .iter()
+                    .position(|bp| bp.pc == pc)
+                    .ok_or_else(|| format!("no breakpoint at {:x}", pc))?;
+                cpu.remove_breakpoint(idx);
+            }
+            Command::Watch(addr, wtype, len) => {
+                self.cpus.get_mut(&cpu_name).unwrap().add_watchpoint(
+                    addr,
+                    len,
+                    "",
+                    wtype,
+                    WatchpointCondition::Always,
+                    WatchpointAction::Break,
+                );
+                info(ctx, format!("[{}] watchpoint at {:x}", cpu_name, addr));
+            }
+            Command::DeleteWatch(addr) => {
+                let cpu = self.cpus.get_mut(&cpu_name).unwrap();
+                let idx = cpu
+                    .watchpoints
+                    .iter()
+                    .position(|wp| wp.addr == addr)
+                    .ok_or_else(|| format!("no watchpoint at {:x}", addr))?;
+                cpu.remove_watchpoint(idx);
+            }
+            Command::Mem(addr, len) => ctx.cmdline.mem_dump = Some((addr, len)),
+            Command::Dump(addr, len, path) => ctx.cmdline.mem_save = Some((addr, len, path)),
+            Command::Load(addr, path) => ctx.cmdline.mem_load = Some((addr, path)),
+            Command::Reg(name, val) => {
+                if val.is_some() && !ctx.paused {
+                    return Err("pause the emulation to change registers".into());
+                }
+                ctx.cmdline.reg_access = Some((cpu_name, name, val));
+            }
+            Command::Disasm(pc) => {
+                ctx.disasm.entry(cpu_name).or_default().navigate(pc);
+            }
+            Command::Step => ctx.command = Some(UiCommand::CpuStep(cpu_name)),
+            Command::Continue => ctx.command = Some(UiCommand::Pause(false)),
+            Command::Pause => ctx.command = Some(UiCommand::Pause(true)),
+            Command::FrameAdvance => ctx.command = Some(UiCommand::FrameAdvance),
+            Command::Source(path) => self.exec_file(ctx, &path, depth)?,
+        }
+        Ok(())
+    }
+
+    // Execute all the commands in a file, one per line (empty lines and
+    // lines starting with # are ignored).
+    fn exec_file(&mut self, ctx: &mut UiCtx, path: &str, depth: usize) -> Result<(), String> {
+        // Avoid infinite recursion if a file sources itself.
+        if depth >= 8 {
+            return Err("source: too many nested files".into());
+        }
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        for line in text.lines() {
+            self.exec_command(ctx, line, depth + 1);
+        }
+        Ok(())
+    }
+
+    // Render the "Command line" window, which allows to control the debugger
+    // by typing commands (see HELP).
+    pub(crate) fn render_cmdline(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx) {
+        ctx.cmdline.flush_requests();
+
+        let title = im_str!("Command line");
+        if !ctx.layout.is_open(title) {
+            return;
+        }
+        let mut exec = None;
+        let mut source = false;
+        let mut opened = true;
+        let window = Window::new(title).size([600.0, 250.0], Condition::FirstUseEver);
+        let cmdline = &mut ctx.cmdline;
+        ctx.layout
+            .place(title, Dock::Bottom, window)
+            .opened(&mut opened)
+            .build(ui, || {
+                let footer = ui.frame_height_with_spacing();
+                ChildWindow::new(im_str!("##cmdline#scrolling"))
+                    .size([0.0, -footer])
+                    .build(ui, || {
+                        for (kind, line) in cmdline.lines.iter() {
+                            match kind {
+                                CmdOutput::Echo => ui.text_disabled(line),
+                                CmdOutput::Info => ui.text(line),
+                                CmdOutput::Error => ui.text_colored([1.0, 0.3, 0.3, 1.0], line),
+                            }
+                        }
+                        if cmdline.scroll {
+                            ui.set_scroll_here_y();
+                            cmdline.scroll = false;
+                        }
+                    });
+
+                ui.set_next_item_width(-70.0);
+                if ui
+                    .input_text(im_str!("##cmdline#input"), &mut cmdline.input)
+                    .enter_returns_true(true)
+                    .build()
+                {
+                    exec = Some(cmdline.input.to_str().to_owned());
+                    cmdline.input.clear();
+                    // Keep typing commands
+                    ui.set_keyboard_focus_here(FocusedWidget::Previous);
+                }
+                ui.same_line(0.0);
+                if ui.button(im_str!("Source"), [60.0, 0.0]) {
+                    source = true;
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Run the commands in a file");
+                }
+            });
+        ctx.layout.set_open(title, opened);
+
+        if let Some(line) = exec {
+            self.exec_command(ctx, &line, 0);
+        }
+        if source {
+            if let Some(path) = open_file_dialog("Run commands from file", "", None) {
+                self.exec_command(ctx, &format!("source {}", path), 0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Command::parse("b 80001234"),
+            Ok(Command::Break(0x8000_1234))
+        );
+        assert_eq!(
+            Command::parse("bd 0x80001234"),
+            Ok(Command::DeleteBreak(0x8000_1234))
+        );
+        assert_eq!(
+            Command::parse("w 0x8033ABCD rw"),
+            Ok(Command::Watch(0x8033_ABCD, WatchpointType::ReadWrite, 4))
+        );
+        assert_eq!(
+            Command::parse("w 8033abcd r 8"),
+            Ok(Command::Watch(0x8033_ABCD, WatchpointType::Read, 8))
+        );
+        assert_eq!(
+            Command::parse("mem 80000000 64"),
+            Ok(Command::Mem(0x8000_0000, 0x64))
+        );
+        assert_eq!(
+            Command::parse("reg t0=5"),
+            Ok(Command::Reg("t0".into(), Some(5)))
+        );
+        assert_eq!(
+            Command::parse("reg T0 = ff"),
+            Ok(Command::Reg("t0".into(), Some(0xff)))
+        );
+        assert_eq!(
+            Command::parse("reg pc"),
+            Ok(Command::Reg("pc".into(), None))
+        );
+        assert_eq!(
+            Command::parse("source /tmp/my cmds.txt"),
+            Ok(Command::Source("/tmp/my cmds.txt".into()))
+        );
+        assert_eq!(
+            Command::parse("dump 0x80000400 1000 /tmp/my ram.bin"),
+            Ok(Command::Dump(0x8000_0400, 0x1000, "/tmp/my ram.bin".into()))
+        );
+        assert_eq!(
+            Command::parse("load  a4000000   patch.bin "),
+            Ok(Command::Load(0xA400_0000, "patch.bin".into()))
+        );
+        assert_eq!(Command::parse("cpu"), Ok(Command::Cpu(None)));
+
+        assert!(Command::parse("b").is_err());
+        assert!(Command::parse("b xyz").is_err());
+        assert!(Command::parse("w 1000 x").is_err());
+        assert!(Command::parse("reg").is_err());
+        assert!(Command::parse("dump 1000 10").is_err());
+        assert!(Command::parse("load 1000").is_err());
+        assert!(Command::parse("frobnicate").is_err());
+    }
+}
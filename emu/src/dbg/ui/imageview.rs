@@ -0,0 +1,109 @@
+use super::{Dock, UiCtx};
+use crate::gfx::{ColorFormat, GfxBufferLE, OwnedGfxBufferLE, Rgba8888};
+use crate::hw::glutils::Texture;
+
+use failure::Error;
+use imgui::*;
+use png::HasParameters;
+use tinyfiledialogs::save_file_dialog_with_filter;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// ImageView holds an image produced by the emulator (eg: a framebuffer or a
+/// texture) to be displayed in a debugger window. The image shown is always
+/// exactly the last one passed to [`update()`](#method.update), and can be
+/// saved as PNG from the window's context menu.
+pub struct ImageView {
+    tex: Texture,
+    image: OwnedGfxBufferLE<Rgba8888>,
+}
+
+impl ImageView {
+    pub fn new() -> Self {
+        Self {
+            tex: Texture::new(),
+            image: OwnedGfxBufferLE::new(0, 0),
+        }
+    }
+
+    /// Update the image, converting it from any color format.
+    pub fn update<CF: ColorFormat>(&mut self, buf: &GfxBufferLE<CF>) {
+        self.image = OwnedGfxBufferLE::from_buf(buf);
+        let (pixels, _pitch) = self.image.buf().raw();
+        self.tex
+            .copy_from::<Rgba8888>(pixels, self.image.width(), self.image.height());
+    }
+
+    pub fn width(&self) -> usize {
+        self.image.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.image.height()
+    }
+
+    /// Save the image as a PNG file.
+    pub fn save_png(&self, path: &Path) -> Result<(), Error> {
+        let w = BufWriter::new(File::create(path)?);
+        let mut enc = png::Encoder::new(w, self.width() as u32, self.height() as u32);
+        enc.set(png::ColorType::RGBA).set(png::BitDepth::Eight);
+        let mut w = enc.write_header()?;
+        let buf = self.image.buf();
+        let (pixels, _pitch) = buf.raw();
+        w.write_image_data(pixels)?;
+        Ok(())
+    }
+
+    // Draw the image with the specified size, attaching the context
+    // menu to it.
+    pub(crate) fn render(&self, ui: &Ui, ctx: &mut UiCtx, size: [f32; 2]) {
+        Image::new(self.tex.id().into(), size).build(ui);
+        if ui.is_item_hovered() && ui.is_item_clicked(MouseButton::Right) {
+            ui.open_popup(im_str!("##image#context"));
+        }
+        ui.popup(im_str!("##image#context"), || {
+            if MenuItem::new(im_str!("Save as PNG..."))
+                .enabled(self.width() != 0)
+                .build(ui)
+            {
+                if let Some(path) = save_file_dialog_with_filter(
+                    "Save image",
+                    "image.png",
+                    &vec!["*.png"],
+                    "PNG images",
+                ) {
+                    if let Err(err) = self.save_png(Path::new(&path)) {
+                        ctx.error_msg =
+                            Some(format!("An error occurred while saving the image: {}", err));
+                    } else {
+                        ctx.add_flash_msg(&format!("Image saved: {}", path));
+                    }
+                }
+            }
+        });
+    }
+}
+
+// Render a window displaying an image, scaled to the window size.
+pub(crate) fn render_imageview(ui: &Ui, ctx: &mut UiCtx, title: &str, v: &ImageView) {
+    let title = im_str!("{}", title);
+    if !ctx.layout.is_open(&title) {
+        return;
+    }
+    let mut opened = true;
+    let window = Window::new(&title).size(
+        [v.width().max(64) as f32, v.height().max(64) as f32 + 20.0],
+        Condition::FirstUseEver,
+    );
+    ctx.layout
+        .place(&title, Dock::Right, window)
+        .opened(&mut opened)
+        .build(ui, || {
+            ui.text(format!("{}x{}", v.width(), v.height()));
+            let size = ui.content_region_avail();
+            v.render(ui, ctx, size);
+        });
+    ctx.layout.set_open(&title, opened);
+}
@@ -0,0 +1,141 @@
+use super::UiCtx;
+use crate::input::{InputDeviceKind, InputManager, StickCalibration};
+use imgui::*;
+
+const CANVAS_SIZE: f32 = 200.0;
+
+// Render the analog stick calibration window: it shows the raw physical
+// position and the mapped position of the stick, and allows to tune the
+// calibration parameters while moving the stick.
+pub(crate) fn render_input_calibration(ui: &Ui, ctx: &mut UiCtx, im: &mut InputManager) {
+    let mut names = Vec::new();
+    im.visit(|dev| {
+        if dev.kind() == InputDeviceKind::Joystick && dev.has_stick() {
+            names.push(ImString::new(dev.name()));
+        }
+    });
+    if names.is_empty() {
+        return;
+    }
+
+    Window::new(im_str!("Input calibration"))
+        .size(
+            [CANVAS_SIZE + 200.0, CANVAS_SIZE + 150.0],
+            Condition::FirstUseEver,
+        )
+        .build(ui, || {
+            let mut curr = ctx.calib_device.min(names.len() - 1);
+            let items: Vec<&ImStr> = names.iter().map(|n| n.as_ref()).collect();
+            ui.set_next_item_width(130.0);
+            ComboBox::new(im_str!("Device")).build_simple_string(ui, &mut curr, &items);
+            ctx.calib_device = curr;
+
+            let dev = im.device(names[curr].to_str()).unwrap();
+            let mut enabled = dev.calibration().is_some();
+            let mut cal = dev.calibration().unwrap_or_default();
+            let raw = dev.raw_stick();
+            let mapped = dev.stick();
+
+            let mut changed = ui.checkbox(im_str!("Enable calibration"), &mut enabled);
+            if enabled {
+                let mut gate = cal.gate.is_some();
+                let mut gate_val = cal.gate.unwrap_or(0.8);
+                changed |= ui
+                    .slider_float(im_str!("Deadzone"), &mut cal.deadzone, 0.0, 0.5)
+                    .build();
+                changed |= ui
+                    .slider_float(im_str!("Saturation"), &mut cal.saturation, 0.5, 1.0)
+                    .build();
+                changed |= ui
+                    .slider_float(im_str!("Curve"), &mut cal.curve, 0.5, 3.0)
+                    .build();
+                changed |= ui.checkbox(im_str!("Octagonal gate"), &mut gate);
+                if gate {
+                    changed |= ui
+                        .slider_float(im_str!("Diagonal"), &mut gate_val, 0.5, 1.0)
+                        .build();
+                }
+                cal.gate = if gate { Some(gate_val) } else { None };
+            }
+
+            ui.text(format!("Raw:    {:6} {:6}", raw.0, raw.1));
+            ui.text(format!("Mapped: {:6} {:6}", mapped.0, mapped.1));
+            render_stick_canvas(ui, &cal, enabled, raw, mapped);
+
+            if changed {
+                let name = names[curr].to_str().to_owned();
+                im.set_calibration(&name, if enabled { Some(cal) } else { None });
+            }
+        });
+}
+
+fn render_stick_canvas(
+    ui: &Ui,
+    cal: &StickCalibration,
+    enabled: bool,
+    raw: (i16, i16),
+    mapped: (i16, i16),
+) {
+    let color_border = [0.5, 0.5, 0.5, 1.0];
+    let color_zones = [0.4, 0.4, 0.8, 1.0];
+    let color_raw = [1.0, 0.3, 0.3, 1.0];
+    let color_mapped = [0.3, 1.0, 0.3, 1.0];
+
+    let dl = ui.get_window_draw_list();
+    let pos = ui.cursor_screen_pos();
+    let half = CANVAS_SIZE / 2.0;
+    let center = [pos[0] + half, pos[1] + half];
+    let point = |x: f32, y: f32| [center[0] + x * half, center[1] - y * half];
+
+    dl.add_rect(
+        pos,
+        [pos[0] + CANVAS_SIZE, pos[1] + CANVAS_SIZE],
+        color_border,
+    )
+    .build();
+    dl.add_circle(center, half, color_border)
+        .num_segments(48)
+        .build();
+
+    if enabled {
+        dl.add_circle(center, cal.deadzone * half, color_zones)
+            .num_segments(32)
+            .build();
+        dl.add_circle(center, cal.saturation * half, color_zones)
+            .num_segments(48)
+            .build();
+        if let Some(g) = cal.gate {
+            // Draw the octagonal gate, with vertices on the axes and on
+            // the diagonals.
+            let vertices = [
+                (1.0, 0.0),
+                (g, g),
+                (0.0, 1.0),
+                (-g, g),
+                (-1.0, 0.0),
+                (-g, -g),
+                (0.0, -1.0),
+                (g, -g),
+            ];
+            for i in 0..vertices.len() {
+                let (x0, y0) = vertices[i];
+                let (x1, y1) = vertices[(i + 1) % vertices.len()];
+                dl.add_line(point(x0, y0), point(x1, y1), color_zones)
+                    .build();
+            }
+        }
+    }
+
+    let raw = point(raw.0 as f32 / 32767.0, raw.1 as f32 / 32767.0);
+    let mapped = point(mapped.0 as f32 / 32767.0, mapped.1 as f32 / 32767.0);
+    dl.add_line(center, mapped, color_mapped).build();
+    dl.add_circle(raw, 4.0, color_raw).filled(true).build();
+    dl.add_circle(mapped, 4.0, color_mapped)
+        .filled(true)
+        .build();
+
+    ui.dummy([CANVAS_SIZE, CANVAS_SIZE]);
+    ui.text_colored(color_raw, "raw");
+    ui.same_line(0.0);
+    ui.text_colored(color_mapped, "mapped");
+}
@@ -0,0 +1,147 @@
+use imgui::*;
+
+use std::collections::{BTreeMap, HashMap};
+
+// Height of the main menu bar, which windows must not overlap when arranged.
+const MENU_BAR_HEIGHT: f32 = 20.0;
+
+// Fraction of the display occupied by each dock area, when all areas
+// contain at least a window.
+const LEFT_WIDTH: f32 = 0.40;
+const CENTER_WIDTH: f32 = 0.25;
+const RIGHT_WIDTH: f32 = 0.35;
+const BOTTOM_HEIGHT: f32 = 0.30;
+
+// Area of the display where a window is docked when windows are arranged:
+// the three columns (disassembly, registers, everything else) on top, and
+// a row for logs at the bottom.
+#[derive(Copy, Clone, PartialEq)]
+pub(crate) enum Dock {
+    Left,
+    Center,
+    Right,
+    Bottom,
+}
+
+// Layout of the debugger windows: which windows are open, and where they
+// must be moved when the user asks to arrange them. Positions and sizes
+// are otherwise handled (and persisted) by imgui itself.
+#[derive(Default)]
+pub(crate) struct UiCtxLayout {
+    // Windows that can be closed and reopened from the "Windows" menu,
+    // with their open status (including those not created yet in this
+    // session, but closed in a previous one).
+    opened: BTreeMap<String, bool>,
+    // Windows drawn in the current and in the previous frame.
+    frame: Vec<(String, Dock)>,
+    last_frame: Vec<(String, Dock)>,
+    // Position and size that must be applied to windows being arranged.
+    pending: HashMap<String, ([f32; 2], [f32; 2])>,
+}
+
+impl UiCtxLayout {
+    // Must be called at the beginning of each frame.
+    pub fn new_frame(&mut self) {
+        self.last_frame = std::mem::replace(&mut self.frame, Vec::new());
+    }
+
+    // Return true if the window with the specified title is open (and thus
+    // must be drawn). Windows are open by default.
+    pub fn is_open(&mut self, title: &ImStr) -> bool {
+        *self.opened.entry(title.to_str().to_owned()).or_insert(true)
+    }
+
+    // Record whether a window is still open after drawing it (the user
+    // might have closed it).
+    pub fn set_open(&mut self, title: &ImStr, open: bool) {
+        self.opened.insert(title.to_str().to_owned(), open);
+    }
+
+    // Register a window being drawn in the specified dock area, and move
+    // it there if windows are being arranged.
+    pub fn place<'p>(&mut self, title: &ImStr, dock: Dock, w: Window<'p>) -> Window<'p> {
+        let title = title.to_str();
+        self.frame.push((title.to_owned(), dock));
+        match self.pending.remove(title) {
+            Some((pos, size)) => w
+                .position(pos, Condition::Always)
+                .size(size, Condition::Always)
+                .collapsed(false, Condition::Always),
+            None => w,
+        }
+    }
+
+    // Arrange all the windows drawn in the last frame into their dock
+    // areas, splitting each area evenly among its windows. Empty areas are
+    // left to the others.
+    pub fn arrange(&mut self, display: [f32; 2]) {
+        let area = |dock| {
+            self.last_frame
+                .iter()
+                .filter(|(_, d)| *d == dock)
+                .map(|(t, _)| t.clone())
+                .collect::<Vec<_>>()
+        };
+        let columns = [
+            (area(Dock::Left), LEFT_WIDTH),
+            (area(Dock::Center), CENTER_WIDTH),
+            (area(Dock::Right), RIGHT_WIDTH),
+        ];
+        let bottom = area(Dock::Bottom);
+
+        let height = display[1] - MENU_BAR_HEIGHT;
+        let top_height = if bottom.is_empty() {
+            height
+        } else {
+            height * (1.0 - BOTTOM_HEIGHT)
+        };
+        let used: f32 = columns
+            .iter()
+            .filter(|(w, _)| !w.is_empty())
+            .map(|(_, frac)| frac)
+            .sum();
+
+        // Top columns: windows are stacked vertically
+        let mut x = 0.0;
+        for (windows, frac) in columns.iter().filter(|(w, _)| !w.is_empty()) {
+            let width = display[0] * frac / used;
+            let h = top_height / windows.len() as f32;
+            for (i, title) in windows.iter().enumerate() {
+                let pos = [x, MENU_BAR_HEIGHT + h * i as f32];
+                self.pending.insert(title.clone(), (pos, [width, h]));
+            }
+            x += width;
+        }
+
+        // Bottom row: windows are side by side
+        let w = display[0] / bottom.len().max(1) as f32;
+        for (i, title) in bottom.iter().enumerate() {
+            let pos = [w * i as f32, MENU_BAR_HEIGHT + top_height];
+            self.pending
+                .insert(title.clone(), (pos, [w, height - top_height]));
+        }
+    }
+
+    // Render the "Windows" menu, to reopen closed windows (or close them).
+    pub fn render_menu(&mut self, ui: &Ui) {
+        for (title, open) in self.opened.iter_mut() {
+            MenuItem::new(&im_str!("{}", title)).build_with_ref(ui, open);
+        }
+    }
+
+    // Titles of the windows that are currently closed.
+    pub fn closed(&self) -> Vec<String> {
+        self.opened
+            .iter()
+            .filter(|(_, &open)| !open)
+            .map(|(title, _)| title.clone())
+            .collect()
+    }
+
+    // Mark the specified windows as closed (eg: when restoring a layout).
+    pub fn set_closed(&mut self, titles: &[String]) {
+        for title in titles {
+            self.opened.insert(title.clone(), false);
+        }
+    }
+}
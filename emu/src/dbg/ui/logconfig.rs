@@ -0,0 +1,50 @@
+use crate::log::log_levels;
+use imgui::*;
+use slog::{FilterLevel, LOG_LEVEL_NAMES};
+
+// Draw a slider to select a log level. Returns the new level if changed.
+fn level_slider(ui: &Ui, label: &ImStr, level: FilterLevel) -> Option<FilterLevel> {
+    let mut val = level.as_usize() as i32;
+    ui.set_next_item_width(120.0);
+    if ui
+        .slider_int(label, &mut val, 0, 6)
+        .display_format(&im_str!("{}", LOG_LEVEL_NAMES[val as usize]))
+        .build()
+    {
+        FilterLevel::from_usize(val as usize)
+    } else {
+        None
+    }
+}
+
+// Render the "Log Config" window, that allows to change at runtime the log
+// level of each known tag (usually, the module of the logging subsystem).
+pub(crate) fn render_log_config(ui: &Ui) {
+    let levels = log_levels();
+
+    Window::new(im_str!("Log Config"))
+        .size([450.0, 400.0], Condition::FirstUseEver)
+        .build(ui, || {
+            if let Some(lvl) = level_slider(ui, im_str!("Default"), levels.default_level()) {
+                levels.set_default_level(lvl);
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text(im_str!(
+                    "Also applied to all tags which are using the current default"
+                ));
+            }
+            ui.separator();
+
+            ChildWindow::new(im_str!("##logconfig#tags"))
+                .size([0.0, 0.0])
+                .build(ui, || {
+                    for (tag, level) in levels.tags() {
+                        if let Some(lvl) =
+                            level_slider(ui, &im_str!("{}##logconfig#{}", tag, tag), level)
+                        {
+                            levels.set_level(&tag, lvl);
+                        }
+                    }
+                });
+        });
+}
@@ -0,0 +1,229 @@
+use super::LogViewCommand;
+use crate::dbg::uisupport::ImGuiListClipper;
+use crate::log::{dropped_log_records, log_ring, RingLine};
+
+use imgui::*;
+use slog::LOG_LEVEL_SHORT_NAMES;
+
+const LOG_LEVEL_COLOR: [[f32; 4]; 7] = [
+    [1.0, 1.0, 1.0, 1.0],    // none
+    [0.91, 0.20, 0.20, 1.0], // critical
+    [0.91, 0.20, 0.20, 1.0], // error
+    [0.90, 0.86, 0.45, 1.0], // warning
+    [0.65, 0.88, 0.18, 1.0], // info
+    [1.0, 1.0, 1.0, 1.0],    // debug
+    [0.40, 0.39, 0.33, 1.0], // trace
+];
+
+// Local state of the log console window
+pub(crate) struct UiCtxConsole {
+    lines: Vec<RingLine>,
+    generation: u64,
+    paused: bool,
+    following: bool,
+    search: ImString,
+    max_level: usize,
+    tag: usize, // 0 = all tags, otherwise index+1 into tags
+    tags: Vec<String>,
+}
+
+impl Default for UiCtxConsole {
+    fn default() -> Self {
+        Self {
+            lines: Vec::new(),
+            generation: u64::max_value(),
+            paused: false,
+            following: true,
+            search: ImString::with_capacity(64),
+            max_level: 6,
+            tag: 0,
+            tags: Vec::new(),
+        }
+    }
+}
+
+// Find the first hexadecimal address in a string, that is a word prefixed
+// by "0x", or made of exactly 8 hex digits.
+fn find_address(s: &str) -> Option<u64> {
+    s.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter_map(|w| {
+            if w.starts_with("0x") && w.len() > 2 {
+                u64::from_str_radix(&w[2..], 16).ok()
+            } else if w.len() == 8 {
+                u64::from_str_radix(w, 16).ok()
+            } else {
+                None
+            }
+        })
+        .next()
+}
+
+impl UiCtxConsole {
+    // Refresh the local copy of the log ring, unless the console is paused.
+    fn refresh(&mut self) {
+        let ring = log_ring();
+        let generation = ring.generation();
+        if self.paused || generation == self.generation {
+            return;
+        }
+        self.generation = generation;
+        self.lines = ring.snapshot();
+        for line in &self.lines {
+            if !self.tags.contains(&line.tag) {
+                self.tags.push(line.tag.clone());
+            }
+        }
+        self.tags.sort();
+    }
+
+    fn matches(&self, line: &RingLine) -> bool {
+        if line.level as usize > self.max_level {
+            return false;
+        }
+        if self.tag != 0 && line.tag != self.tags[self.tag - 1] {
+            return false;
+        }
+        let search = self.search.to_str();
+        search.is_empty() || line.msg.contains(search) || line.kv.contains(search)
+    }
+}
+
+// Render the "Log Console" window, showing the most recent log records
+// (mirrored from the log ring). Clicking on a line that has a PC jumps
+// to it in the disassembly; addresses found in a line can be clicked to
+// show them in the memory view.
+pub(crate) fn render_log_console(ui: &Ui, ctx: &mut UiCtxConsole) -> Option<LogViewCommand> {
+    let mut cmd = None;
+    ctx.refresh();
+
+    Window::new(im_str!("Log Console"))
+        .size([700.0, 300.0], Condition::FirstUseEver)
+        .build(ui, || {
+            if ui.checkbox(im_str!("Pause"), &mut ctx.paused) && !ctx.paused {
+                ctx.refresh();
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip_text(im_str!("Freeze the console (logs are still collected)"));
+            }
+            ui.same_line(0.0);
+            ui.checkbox(im_str!("Follow"), &mut ctx.following);
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Clear")) {
+                log_ring().clear();
+                ctx.lines.clear();
+            }
+            ui.same_line(0.0);
+
+            let levels: [&ImStr; 7] = [
+                im_str!("Off"),
+                im_str!("Critical"),
+                im_str!("Error"),
+                im_str!("Warning"),
+                im_str!("Info"),
+                im_str!("Debug"),
+                im_str!("Trace"),
+            ];
+            ui.set_next_item_width(90.0);
+            ComboBox::new(im_str!("##console#level")).build_simple_string(
+                ui,
+                &mut ctx.max_level,
+                &levels,
+            );
+            ui.same_line(0.0);
+
+            let tags: Vec<ImString> = std::iter::once(ImString::new("All tags"))
+                .chain(ctx.tags.iter().map(|t| ImString::new(t.as_str())))
+                .collect();
+            let tag_refs: Vec<&ImStr> = tags.iter().map(|t| t.as_ref()).collect();
+            ui.set_next_item_width(160.0);
+            ComboBox::new(im_str!("##console#tag")).build_simple_string(
+                ui,
+                &mut ctx.tag,
+                &tag_refs,
+            );
+            ui.same_line(0.0);
+
+            ui.set_next_item_width(200.0);
+            ui.input_text(im_str!("Search##console"), &mut ctx.search)
+                .auto_select_all(true)
+                .build();
+            let dropped = dropped_log_records();
+            if dropped != 0 {
+                ui.same_line(0.0);
+                ui.text_colored(
+                    LOG_LEVEL_COLOR[3],
+                    im_str!("{} records dropped on console", dropped),
+                );
+            }
+            ui.separator();
+
+            let visible: Vec<&RingLine> = ctx.lines.iter().filter(|l| ctx.matches(l)).collect();
+            ChildWindow::new(im_str!("##console#scrolling"))
+                .size([0.0, 0.0])
+                .horizontal_scrollbar(true)
+                .build(ui, || {
+                    ImGuiListClipper::new(visible.len())
+                        .items_height(ui.text_line_height_with_spacing())
+                        .build(|start, end| {
+                            for idx in start as usize..end as usize {
+                                let line = visible[idx];
+                                let text = im_str!(
+                                    "[{}] {} {}: {} {}##console#{}",
+                                    line.frame,
+                                    LOG_LEVEL_SHORT_NAMES[line.level as usize],
+                                    line.tag,
+                                    line.msg,
+                                    line.kv,
+                                    idx
+                                );
+                                let color = ui.push_style_color(
+                                    StyleColor::Text,
+                                    LOG_LEVEL_COLOR[line.level as usize],
+                                );
+                                let clicked = Selectable::new(&text).build(ui);
+                                color.pop(ui);
+                                if let (Some(sub), Some(pc)) = (&line.sub, line.pc) {
+                                    if ui.is_item_hovered() {
+                                        ui.tooltip_text(im_str!("Click to show {}@{:x}", sub, pc));
+                                    }
+                                    if clicked {
+                                        cmd = Some(LogViewCommand::ShowPc(sub.clone(), pc));
+                                    }
+                                }
+
+                                let addr =
+                                    find_address(&line.msg).or_else(|| find_address(&line.kv));
+                                if let Some(addr) = addr {
+                                    ui.same_line(0.0);
+                                    if ui.small_button(&im_str!(
+                                        "{:08X}##console#addr{}",
+                                        addr,
+                                        idx
+                                    )) {
+                                        cmd =
+                                            Some(LogViewCommand::ShowAddr(line.sub.clone(), addr));
+                                    }
+                                }
+                            }
+                        });
+                    if ctx.following && !ctx.paused {
+                        ui.set_scroll_y(ui.scroll_max_y());
+                    }
+                });
+        });
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_address;
+
+    #[test]
+    fn test_find_address() {
+        assert_eq!(find_address("write to 0x4400010"), Some(0x4400010));
+        assert_eq!(find_address("addr=04400010 val=12"), Some(0x0440_0010));
+        assert_eq!(find_address("DMA from cart=10001000"), Some(0x1000_1000));
+        assert_eq!(find_address("no address here"), None);
+        assert_eq!(find_address("word abcdefgh"), None);
+    }
+}
@@ -1,5 +1,5 @@
-use super::uisupport::{ctext, is_shortcut_pressed, ImGuiListClipper};
-use super::{LogViewCommand, UiCtx, UiCtxLog};
+use super::{Dock, LogViewCommand, UiCtx, UiCtxLayout, UiCtxLog};
+use crate::dbg::uisupport::{ctext, is_shortcut_pressed, ImGuiListClipper};
 use crate::log::{LogPool, LogPoolPtr, LogView};
 use sdl2::keyboard::Scancode;
 
@@ -317,14 +317,17 @@ fn render_save<'a, 'ui>(ui: &'a Ui<'ui>, view: &mut LogView, pool: &LogPool) {
 pub(crate) fn render_logview<'a, 'ui>(
     ui: &'a Ui<'ui>,
     ctx: &mut UiCtxLog,
+    layout: &mut UiCtxLayout,
     pool: &mut LogPoolPtr,
     num_frames: i64,
 ) -> Option<LogViewCommand> {
     let mut opened = ctx.opened;
     let mut force_loc = None;
 
-    Window::new(&im_str!("{}", ctx.name))
-        .size([600.0, 300.0], Condition::FirstUseEver)
+    let title = im_str!("{}", ctx.name);
+    let window = Window::new(&title).size([600.0, 300.0], Condition::FirstUseEver);
+    layout
+        .place(&title, Dock::Bottom, window)
         .opened(&mut opened)
         .build(ui, || {
             let pool = pool.lock().unwrap();
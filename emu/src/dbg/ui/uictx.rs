@@ -0,0 +1,223 @@
+use super::cmdline::UiCtxCmdLine;
+use super::layout::UiCtxLayout;
+use super::logconsole::UiCtxConsole;
+use super::{DisasmExport, MemWindow, TraceEvent, UiCommand};
+use crate::hw::{Hotkeys, RegDatabase};
+use crate::log::{LogLine, LogView};
+use imgui::ImString;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::time::Instant;
+
+pub(crate) enum RegHighlight {
+    Input,
+    Output,
+}
+
+// Global state for a disasm view
+#[derive(Default)]
+pub(crate) struct UiCtxDisasm {
+    // Current PC on this CPU (copied here from emulator).
+    pub cur_pc: Option<u64>,
+    // Display a blinking effect over this PC; initialize with Instant::now.
+    // NOTE: this only works if this PC is visible (within the scrolling area), otherwise
+    // the animation is not performed.
+    pub blink_pc: Option<(u64, Instant)>,
+    // PC being currently selected by the user using cursor keys / mouse.
+    pub cursor_pc: Option<u64>,
+    // If set, the disasmview will automatically scroll to display this PC
+    pub force_pc: Option<u64>,
+    // Map of registers that must be highlighted (because are involved in cur_pc's opcode).
+    pub regs_highlight: HashMap<&'static str, RegHighlight>,
+    // Navigation history when following branches: positions to go back to
+    // (most recent last), and positions to go forward to after going back.
+    pub nav_back: Vec<u64>,
+    pub nav_forward: Vec<u64>,
+    // Instruction being assembled: PC, text typed by the user, and the
+    // error of the last attempt (if any).
+    pub asm_pc: u64,
+    pub asm_text: ImString,
+    pub asm_error: Option<String>,
+    // Instructions patched through the assembler, at most one per PC.
+    pub patches: Vec<InsnPatch>,
+    // Range typed in the export popup (hex start and end PCs), and the
+    // export currently in progress (if any).
+    pub export_range: (ImString, ImString),
+    pub export: Option<DisasmExport>,
+}
+
+// An instruction patched in memory from the disasm view.
+pub(crate) struct InsnPatch {
+    pub pc: u64,
+    pub orig: Vec<u8>, // Bytes before the patch, restored on undo
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl UiCtxDisasm {
+    // Move the view to the specified PC (eg: the target of a branch),
+    // recording the current position in the navigation history.
+    pub fn navigate(&mut self, pc: u64) {
+        if let Some(from) = self.cursor_pc.or(self.cur_pc) {
+            self.nav_back.push(from);
+        }
+        self.nav_forward.clear();
+        self.goto(pc);
+    }
+
+    // Go back to the previous position in the navigation history.
+    pub fn nav_back(&mut self) {
+        if let Some(pc) = self.nav_back.pop() {
+            if let Some(from) = self.cursor_pc.or(self.cur_pc) {
+                self.nav_forward.push(from);
+            }
+            self.goto(pc);
+        }
+    }
+
+    // Go forward again, after having gone back in the navigation history.
+    pub fn nav_forward(&mut self) {
+        if let Some(pc) = self.nav_forward.pop() {
+            if let Some(from) = self.cursor_pc.or(self.cur_pc) {
+                self.nav_back.push(from);
+            }
+            self.goto(pc);
+        }
+    }
+
+    fn goto(&mut self, pc: u64) {
+        self.force_pc = Some(pc);
+        self.cursor_pc = Some(pc);
+    }
+}
+
+// Global state for a register view, to highlight registers that changed
+// between two stops of the emulation (step, breakpoint, pause).
+#[derive(Default)]
+pub(crate) struct UiCtxRegs {
+    // Value of all registers at the last stop.
+    pub values: HashMap<String, u128>,
+    // Registers whose value changed at the last stop.
+    pub changed: HashSet<String>,
+}
+
+// A command that can be requested by a log view (returned
+// by the render function).
+pub(crate) enum LogViewCommand {
+    // User requested to see a certain PC in a specific CPU
+    ShowPc(String, u64),
+    // User requested to see a certain address in the memory view
+    // of a specific CPU (or all memory views, if not specified)
+    ShowAddr(Option<String>, u64),
+}
+
+// Global state for log view
+pub(crate) struct UiCtxLog {
+    pub view: LogView,
+    pub name: String,
+    pub cached_lines: Vec<LogLine>,
+    pub cached_start_line: usize,
+    pub last_filter_count: Instant,
+    pub filter_count: Option<usize>,
+    pub following: bool,
+    pub configured_columns: bool,
+    pub selected: LogLine,
+    pub opened: bool,
+}
+
+impl UiCtxLog {
+    pub(crate) fn new(view: LogView, name: &str) -> Box<UiCtxLog> {
+        Box::new(UiCtxLog {
+            view,
+            name: name.to_owned(),
+            last_filter_count: Instant::now(),
+            cached_lines: Vec::new(),
+            cached_start_line: 0,
+            filter_count: None,
+            selected: LogLine::default(),
+            following: true,
+            configured_columns: false,
+            opened: true,
+        })
+    }
+}
+
+// Global state shared by all debugger UIs, passed to all rendere functions.
+//
+// This is useful for two main reasons:
+// 1) Keep local state of a ImgUi window; in C++, this is done with static variables,
+// but in Rust we need to store it in a different way.
+// 2) Propagate cross-window information (eg: specific events that affect multiple windows).
+#[derive(Default)]
+pub(crate) struct UiCtx {
+    pub cpus: Vec<String>,
+
+    // True if the emulation is paused (registers can be edited only while paused).
+    pub paused: bool,
+
+    // An event that was just triggered. This is kept only for one frame.
+    pub event: Option<(Box<TraceEvent>, Instant)>,
+
+    // A command requested by the UI to the debugger
+    pub command: Option<UiCommand>,
+
+    // Disasm views
+    pub disasm: HashMap<String, UiCtxDisasm>,
+
+    // Register views (indexed by CPU and view name)
+    pub regs: HashMap<String, UiCtxRegs>,
+
+    // Log view
+    pub logviews: Vec<Box<UiCtxLog>>,
+    pub logviewid: usize,
+
+    // Memory views
+    pub memviews: HashMap<String, MemWindow>,
+
+    // Log console
+    pub console: UiCtxConsole,
+
+    // Command line
+    pub cmdline: UiCtxCmdLine,
+
+    // Layout of the debugger windows
+    pub layout: UiCtxLayout,
+
+    // Hotkeys (shared with the main emulator window)
+    pub hotkeys: Rc<RefCell<Hotkeys>>,
+
+    // Flash messages (auto-hide after 2s)
+    pub flash_msg: Option<(String, Instant)>,
+
+    // Error message that will be displayed in a modal
+    pub error_msg: Option<String>,
+
+    // Popup "New breakpoint": local state
+    pub new_bp_pc: u64,
+    pub new_bp_desc: ImString,
+    pub new_bp_cond: ImString,
+    pub new_bp_msg: ImString,
+
+    // Popup "New watchpoint": local state
+    pub new_wp_addr: u64,
+    pub new_wp_len: u64,
+    pub new_wp_desc: ImString,
+    pub new_wp_type: i32,
+    pub new_wp_cond: usize,
+    pub new_wp_value: u64,
+    pub new_wp_action: i32,
+
+    // Documentation of hardware registers (provided by the emulator)
+    pub regdb: RegDatabase,
+
+    // Input calibration: index of the selected device
+    pub calib_device: usize,
+}
+
+impl UiCtx {
+    pub fn add_flash_msg(&mut self, msg: &str) {
+        self.flash_msg = Some((msg.to_owned(), Instant::now()));
+    }
+}
@@ -0,0 +1,212 @@
+use super::{Dock, UiCtx};
+use crate::dbg::tracer::{Debugger, WatchpointAction, WatchpointCondition, WatchpointType};
+use crate::dbg::uisupport::{imgui_input_hex, render_reg_tooltip};
+use imgui::*;
+
+impl Debugger {
+    fn render_new_watchpoint(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx, cpu_name: &str) {
+        let cpu = self.cpus.get_mut(cpu_name).unwrap();
+        let popup_name = im_str!("##wp#new#{}", cpu_name);
+
+        ui.popup(&popup_name, || {
+            ui.text(im_str!("Addr:"));
+            ui.same_line(80.0);
+            imgui_input_hex(ui, im_str!("###wp#new_addr"), &mut ctx.new_wp_addr, false);
+
+            ui.text(im_str!("Length:"));
+            ui.same_line(80.0);
+            imgui_input_hex(ui, im_str!("###wp#new_len"), &mut ctx.new_wp_len, false);
+
+            ui.text(im_str!("Desc:"));
+            ui.same_line(80.0);
+            ui.input_text(im_str!("###wp#new_desc"), &mut ctx.new_wp_desc)
+                .auto_select_all(true)
+                .build();
+
+            ui.text(im_str!("Type:"));
+            ui.same_line(80.0);
+            ui.radio_button(im_str!("Read"), &mut ctx.new_wp_type, 0);
+            ui.same_line(150.0);
+            ui.radio_button(im_str!("Write"), &mut ctx.new_wp_type, 1);
+            ui.same_line(220.0);
+            ui.radio_button(im_str!("Both"), &mut ctx.new_wp_type, 2);
+
+            ui.text(im_str!("Condition:"));
+            ui.same_line(80.0);
+            ComboBox::new(&im_str!("###wp#new_cond")).build_simple_string(
+                &ui,
+                &mut ctx.new_wp_cond,
+                &[
+                    im_str!("Always"),
+                    im_str!("=="),
+                    im_str!("!="),
+                    im_str!(">="),
+                    im_str!("<="),
+                    im_str!(">"),
+                    im_str!("<"),
+                ],
+            );
+
+            ui.text(im_str!("Action:"));
+            ui.same_line(80.0);
+            ui.radio_button(im_str!("Break"), &mut ctx.new_wp_action, 0);
+            ui.same_line(150.0);
+            ui.radio_button(im_str!("Log"), &mut ctx.new_wp_action, 1);
+
+            if ctx.new_wp_cond != 0 {
+                ui.text(im_str!("Value:"));
+                ui.same_line(80.0);
+                imgui_input_hex(ui, im_str!("###wp#new_value"), &mut ctx.new_wp_value, false);
+            }
+
+            if ui.button(im_str!("Add"), [40.0, 20.0]) {
+                let desc = ctx.new_wp_desc.to_str().to_owned();
+                let wtype = match ctx.new_wp_type {
+                    0 => WatchpointType::Read,
+                    1 => WatchpointType::Write,
+                    _ => WatchpointType::ReadWrite,
+                };
+                let cond = match ctx.new_wp_cond {
+                    0 => WatchpointCondition::Always,
+                    1 => WatchpointCondition::Eq(ctx.new_wp_value),
+                    2 => WatchpointCondition::Ne(ctx.new_wp_value),
+                    3 => WatchpointCondition::Ge(ctx.new_wp_value),
+                    4 => WatchpointCondition::Le(ctx.new_wp_value),
+                    5 => WatchpointCondition::Gt(ctx.new_wp_value),
+                    6 => WatchpointCondition::Lt(ctx.new_wp_value),
+                    _ => unreachable!(),
+                };
+                let action = match ctx.new_wp_action {
+                    0 => WatchpointAction::Break,
+                    _ => WatchpointAction::Log,
+                };
+                cpu.add_watchpoint(
+                    ctx.new_wp_addr,
+                    ctx.new_wp_len.max(1),
+                    &desc,
+                    wtype,
+                    cond,
+                    action,
+                );
+                ui.close_current_popup();
+            }
+        });
+        if ui.small_button(&im_str!("New WP##{}", cpu_name)) {
+            ctx.new_wp_addr = 0;
+            ctx.new_wp_len = 4;
+            ctx.new_wp_desc = ImString::new("New watchpoint");
+            ctx.new_wp_type = 1;
+            ctx.new_wp_cond = 0;
+            ctx.new_wp_value = 0;
+            ctx.new_wp_action = 0;
+            ui.open_popup(&popup_name);
+        }
+    }
+
+    fn render_cpu_watchpoints(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx, cpu_name: &str) {
+        self.render_new_watchpoint(ui, ctx, cpu_name);
+
+        let cpu = self.cpus.get_mut(cpu_name).unwrap();
+        let mut wp_changed = false;
+        let mut wp_delete = None;
+
+        ui.columns(7, &im_str!("##watchpoints#{}", cpu_name), true);
+        ui.set_column_offset(1, 30.0);
+        ui.set_column_offset(2, 110.0);
+        ui.set_column_offset(3, 170.0);
+        for (idx, wp) in cpu.watchpoints.iter_mut().enumerate() {
+            let name = im_str!("###watchpoints#{}#active#{}", cpu_name, idx);
+            if ui.checkbox(&name, &mut wp.active) {
+                // Changing activation requires update to fastmap
+                wp_changed = true;
+            }
+            ui.next_column();
+
+            let name = im_str!("###watchpoints#{}#addr#{}", cpu_name, idx);
+            if imgui_input_hex(ui, &name, &mut wp.addr, true) {
+                // Changing addr requires update to fastmap
+                wp_changed = true;
+            }
+            render_reg_tooltip(ui, &ctx.regdb, wp.addr, None);
+            ui.next_column();
+
+            let name = im_str!("###watchpoints#{}#len#{}", cpu_name, idx);
+            if imgui_input_hex(ui, &name, &mut wp.len, true) {
+                // Changing length requires update to fastmap
+                wp.len = wp.len.max(1);
+                wp_changed = true;
+            }
+            ui.next_column();
+
+            let name = im_str!("###watchpoints#{}#desc#{}", cpu_name, idx);
+            let mut sdesc = ImString::new(wp.description.clone());
+            if ui
+                .input_text(&name, &mut sdesc)
+                .enter_returns_true(true)
+                .auto_select_all(true)
+                .build()
+            {
+                wp.description = sdesc.to_str().to_owned();
+            }
+            ui.next_column();
+
+            ui.text(im_str!("{}", wp.cond_to_string()));
+            ui.next_column();
+
+            // Log-only watchpoints just log the access (with the PC) and
+            // let the emulation continue.
+            let name = im_str!("Log###watchpoints#{}#log#{}", cpu_name, idx);
+            let mut log = wp.action == WatchpointAction::Log;
+            if ui.checkbox(&name, &mut log) {
+                wp.action = if log {
+                    WatchpointAction::Log
+                } else {
+                    WatchpointAction::Break
+                };
+            }
+            ui.next_column();
+
+            if ui.small_button(&im_str!("Delete##watchpoints#{}#{}", cpu_name, idx)) {
+                wp_delete = Some(idx);
+            }
+            ui.next_column();
+        }
+        ui.columns(1, im_str!(""), false);
+
+        // Refresh watchpoint hashmap if required
+        if let Some(idx) = wp_delete {
+            cpu.remove_watchpoint(idx);
+        } else if wp_changed {
+            cpu.update_wp_fastmap();
+        }
+    }
+
+    // Render the "Watchpoints" window, listing the watchpoints of all CPUs.
+    // Watchpoints are part of the debugger configuration, so they are
+    // persisted across sessions together with breakpoints.
+    pub(crate) fn render_watchpoints(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx) {
+        let title = im_str!("Watchpoints");
+        if !ctx.layout.is_open(title) {
+            return;
+        }
+        let mut opened = true;
+        let window = Window::new(title).size([500.0, 300.0], Condition::FirstUseEver);
+        ctx.layout
+            .place(title, Dock::Right, window)
+            .opened(&mut opened)
+            .build(ui, || {
+                for idx in 0..ctx.cpus.len() {
+                    let cpu_name = ctx.cpus[idx].clone();
+                    let count = self.cpus[&cpu_name].watchpoints.len();
+                    if ui
+                        .collapsing_header(&im_str!("[{}] ({})###{}", cpu_name, count, cpu_name))
+                        .default_open(true)
+                        .build()
+                    {
+                        self.render_cpu_watchpoints(ui, ctx, &cpu_name);
+                    }
+                }
+            });
+        ctx.layout.set_open(title, opened);
+    }
+}
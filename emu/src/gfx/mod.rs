@@ -1,7 +1,9 @@
 mod buffer;
 mod color;
+mod font;
 mod geom;
 
 pub use self::buffer::*;
 pub use self::color::*;
+pub use self::font::*;
 pub use self::geom::*;
@@ -0,0 +1,133 @@
+use crate::gfx::{ColorFormat, GfxBufferLE, OwnedGfxBufferLE, Rgba8888};
+
+use png::HasParameters;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Save a framebuffer produced by the emulator as a PNG file, at its native
+/// resolution. This can be used by test automation to capture the emulator
+/// output without any window.
+pub fn screenshot<CF: ColorFormat>(buf: &GfxBufferLE<CF>, path: &Path) -> Result<(), String> {
+    let image = OwnedGfxBufferLE::<Rgba8888>::from_buf(buf);
+    let buf = image.buf();
+    let (pixels, _pitch) = buf.raw();
+    save_png(pixels, image.width(), image.height(), path)
+}
+
+/// Save a RGBA image as a PNG file.
+pub(crate) fn save_png(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    path: &Path,
+) -> Result<(), String> {
+    let err = |e: &dyn ::std::fmt::Display| format!("cannot save {}: {}", path.display(), e);
+    let w = BufWriter::new(File::create(path).map_err(|e| err(&e))?);
+    let mut enc = png::Encoder::new(w, width as u32, height as u32);
+    enc.set(png::ColorType::RGBA).set(png::BitDepth::Eight);
+    let mut w = enc.write_header().map_err(|e| err(&e))?;
+    w.write_image_data(pixels).map_err(|e| err(&e))
+}
+
+/// CaptureNamer generates the filenames of screenshots and recordings, using
+/// the name of the game and the current time (eg:
+/// "mario-20190412-153042.png").
+pub struct CaptureNamer {
+    dir: PathBuf,
+    prefix: String,
+}
+
+impl CaptureNamer {
+    pub fn new(dir: &Path, name: &str) -> Self {
+        // Keep only characters that are safe in filenames on all platforms.
+        let prefix: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        Self {
+            dir: dir.to_owned(),
+            prefix: if prefix.is_empty() {
+                "screenshot".into()
+            } else {
+                prefix
+            },
+        }
+    }
+
+    /// Return the path for a new capture with the specified extension (or
+    /// a directory, if `ext` is empty), making sure not to overwrite
+    /// existing files (if more captures are taken in the same second).
+    pub fn next_path(&self, suffix: &str, ext: &str) -> PathBuf {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let base = format!("{}-{}{}", self.prefix, timestamp(secs), suffix);
+        let mut path = self.dir.join(&base).with_extension(ext);
+        let mut n = 1;
+        while path.exists() {
+            n += 1;
+            path = self.dir.join(format!("{}-{}", base, n)).with_extension(ext);
+        }
+        path
+    }
+}
+
+// Format a UNIX timestamp as YYYYMMDD-HHMMSS (UTC).
+fn timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Convert days since epoch into a civil date (proleptic Gregorian
+    // calendar), see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem / 60) % 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(timestamp(0), "19700101-000000");
+        assert_eq!(timestamp(951_782_400), "20000229-000000");
+        assert_eq!(timestamp(1_555_083_042), "20190412-153042");
+    }
+
+    #[test]
+    fn names() {
+        let namer = CaptureNamer::new(Path::new("shots"), "Super Mario 64");
+        let path = namer.next_path("", "png");
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("Super_Mario_64-"));
+        assert!(name.ends_with(".png"));
+        assert!(path.starts_with("shots"));
+    }
+}
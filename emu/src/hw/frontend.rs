@@ -0,0 +1,17 @@
+//! Host frontend: SDL window, audio and gamepads, OpenGL rendering, and the
+//! mapping of host input to the emulated controllers.
+mod audio;
+mod gamepad;
+pub(crate) mod glutils;
+mod hotkeys;
+mod input_mapping;
+mod launcher;
+mod output;
+
+pub use self::audio::AudioControl;
+pub use self::glutils::{gl_context_current, RenderTarget, ScaleMode, ShaderProgram, VideoOptions};
+pub use self::hotkeys::{HotkeyAction, HotkeyConfig, Hotkeys, HOTKEYS};
+pub(crate) use self::input_mapping::InputMapping;
+pub use self::launcher::Launcher;
+pub use self::output::{AudioConfig, Output, VideoConfig};
+pub use gl;
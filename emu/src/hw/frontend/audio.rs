@@ -0,0 +1,381 @@
+use crate::hw::SlowAudio;
+use crate::snd::{SampleFormat, SampleInt, SndBuffer};
+
+use byteorder::NativeEndian;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioFormatNum, AudioSpecDesired};
+
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Number of emulated frames that are buffered before playback starts (or
+// restarts after an underrun). A higher value makes underruns less likely
+// but increases latency.
+const PREFILL_FRAMES: usize = 2;
+
+// Length (in samples per channel) of the grains repeated when stretching
+// audio at lower speeds: about 6ms at 44.1kHz, short enough to not be heard
+// as an echo.
+const STRETCH_GRAIN: usize = 256;
+
+#[derive(Default)]
+struct ControlState {
+    volume: AtomicU32, // f32 bits
+    muted: AtomicBool,
+    underruns: AtomicU64,
+    buffered: AtomicUsize, // samples per channel currently buffered
+}
+
+/// AudioControl is a handle to change volume and mute of the audio output
+/// while it is playing, and to inspect its status. It can be freely cloned,
+/// and is safe to use from any thread.
+#[derive(Clone)]
+pub struct AudioControl {
+    state: Arc<ControlState>,
+    freq: usize,
+}
+
+impl AudioControl {
+    fn new(freq: usize) -> Self {
+        let ctl = Self {
+            state: Arc::new(ControlState::default()),
+            freq,
+        };
+        ctl.set_volume(1.0);
+        ctl
+    }
+
+    /// Return the current volume (from 0.0 to 1.0).
+    pub fn volume(&self) -> f32 {
+        f32::from_bits(self.state.volume.load(Ordering::Relaxed))
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        let volume = volume.max(0.0).min(1.0);
+        self.state.volume.store(volume.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn muted(&self) -> bool {
+        self.state.muted.load(Ordering::Relaxed)
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.state.muted.store(muted, Ordering::Relaxed);
+    }
+
+    /// Return the number of times that the host ran out of audio samples
+    /// since the beginning of playback (eg: because emulation was too slow).
+    pub fn underruns(&self) -> u64 {
+        self.state.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Return the amount of audio currently buffered for playback.
+    pub fn latency(&self) -> Duration {
+        let samples = self.state.buffered.load(Ordering::Relaxed) as u64;
+        Duration::from_micros(samples * 1_000_000 / self.freq as u64)
+    }
+}
+
+// Playback is the SDL audio callback. It consumes samples from a queue
+// which is filled by the emulation thread, and applies volume.
+struct Playback<SI> {
+    queue: VecDeque<SI>,
+    channels: usize,
+    prefill: usize, // number of samples to buffer before (re)starting playback
+    starved: bool,
+    ctl: AudioControl,
+}
+
+impl<SI: SampleInt> Playback<SI> {
+    fn scale(s: SI, volume: f32) -> SI {
+        // Samples are converted to unsigned 16-bit, so that the
+        // center of the amplitude range is always at 0x8000.
+        let v = s.to_u16() as i32 - 0x8000;
+        let v = (v as f32 * volume) as i32 + 0x8000;
+        SI::from_u16(v.max(0).min(0xFFFF) as u16)
+    }
+
+    fn update_status(&self) {
+        self.ctl
+            .state
+            .buffered
+            .store(self.queue.len() / self.channels, Ordering::Relaxed);
+    }
+}
+
+impl<SI: SampleInt + AudioFormatNum + 'static> AudioCallback for Playback<SI> {
+    type Channel = SI;
+
+    fn callback(&mut self, out: &mut [SI]) {
+        if self.starved && self.queue.len() >= self.prefill {
+            self.starved = false;
+        }
+
+        let volume = if self.ctl.muted() {
+            0.0
+        } else {
+            self.ctl.volume()
+        };
+        let mut n = 0;
+        if !self.starved {
+            if self.queue.len() < out.len() {
+                // Underrun: play whatever is left, and then wait for the
+                // queue to fill up again, to avoid stuttering with very short
+                // bursts of audio.
+                self.starved = true;
+                self.ctl.state.underruns.fetch_add(1, Ordering::Relaxed);
+            }
+            n = out.len().min(self.queue.len());
+            n -= n % self.channels;
+            for (d, s) in out[..n].iter_mut().zip(self.queue.drain(..n)) {
+                *d = Self::scale(s, volume);
+            }
+        }
+        for d in out[n..].iter_mut() {
+            *d = SI::MUTE;
+        }
+        self.update_status();
+    }
+}
+
+/// Audio is the host audio backend. It receives the samples produced by the
+/// emulator frame by frame, resamples them to the frequency of the host
+/// audio device, and plays them through a SDL audio callback.
+pub(crate) struct Audio<SI, SF>
+where
+    SI: SampleInt + AudioFormatNum + 'static,
+    SF: SampleFormat<ORDER = NativeEndian, SAMPLE = SI>,
+{
+    device: AudioDevice<Playback<SI>>,
+    ctl: AudioControl,
+    samples_per_frame: usize, // samples per frame produced by the emulator
+    host_freq: usize,
+    src_freq: usize,
+    phase: f64,       // resampling phase (fraction of source sample)
+    last: [SI; 2],    // last source sample of the previous frame
+    frame_len: usize, // length of one frame in the playback queue
+    credit: f64,      // fraction of frames to play, when running faster
+    phantom: PhantomData<SF>,
+}
+
+impl<SI, SF> Audio<SI, SF>
+where
+    SI: SampleInt + AudioFormatNum + 'static,
+    SF: SampleFormat<ORDER = NativeEndian, SAMPLE = SI>,
+{
+    pub(crate) fn new(context: &sdl2::Sdl, fps: isize, frequency: isize) -> Result<Self, String> {
+        let audio = context
+            .audio()
+            .or_else(|e| Err(format!("error creating audio subsystem: {:?}", e)))?;
+
+        if frequency % fps != 0 {
+            // We need to generate the exact number of samples per frame, so for
+            // now only allows exact multiples. This is not impossible to make it
+            // work more generally (we should request a possible different amount
+            // of samples each frame), but let's punt for now.
+            return Err("audio frequency not a perfect multiple of framerate".into());
+        }
+        let samples_per_frame = (frequency / fps) as usize;
+        let channels = SF::CHANNELS;
+
+        // Ask for the emulator frequency, but accept whatever the host
+        // provides, as we will resample anyway.
+        let spec = AudioSpecDesired {
+            freq: Some(frequency as i32),
+            channels: Some(channels as u8),
+            samples: Some((samples_per_frame / 2).next_power_of_two() as u16),
+        };
+        let device = audio.open_playback(None, &spec, |spec| {
+            let frame_len = spec.freq as usize / fps as usize * channels;
+            Playback {
+                queue: VecDeque::with_capacity(frame_len * PREFILL_FRAMES * 2),
+                channels,
+                prefill: (frame_len * PREFILL_FRAMES).max(spec.samples as usize * channels),
+                starved: true,
+                ctl: AudioControl::new(spec.freq as usize),
+            }
+        })?;
+
+        let host_freq = device.spec().freq as usize;
+        let ctl = device.lock().ctl.clone();
+        device.resume();
+
+        Ok(Self {
+            device,
+            ctl,
+            samples_per_frame,
+            host_freq,
+            src_freq: frequency as usize,
+            phase: 0.0,
+            last: [SI::MUTE; 2],
+            frame_len: host_freq / fps as usize * channels,
+            credit: 0.0,
+            phantom: PhantomData,
+        })
+    }
+
+    pub(crate) fn samples_per_frame(&self) -> usize {
+        self.samples_per_frame
+    }
+
+    pub(crate) fn control(&self) -> AudioControl {
+        self.ctl.clone()
+    }
+
+    // Resample the buffer to the host frequency, using linear interpolation,
+    // and append it to the output.
+    fn resample(&mut self, buf: &SndBuffer<SF>, out: &mut VecDeque<SI>) {
+        let channels = SF::CHANNELS;
+        let count = buf.count();
+        if count == 0 {
+            return;
+        }
+        let step = self.src_freq as f64 / self.host_freq as f64;
+
+        // Source sample at index i, where -1 is the last sample of the
+        // previous frame.
+        let get = |i: isize, ch: usize| -> i32 {
+            if i < 0 {
+                self.last[ch].to_u16() as i32
+            } else {
+                buf.get_sample(i as usize, ch).to_u16() as i32
+            }
+        };
+
+        let mut pos = self.phase;
+        while pos < count as f64 - 1.0 {
+            let i = pos.floor() as isize;
+            let frac = pos - i as f64;
+            for ch in 0..channels {
+                let (a, b) = (get(i, ch), get(i + 1, ch));
+                let v = a as f64 + (b - a) as f64 * frac;
+                out.push_back(SI::from_u16(v as u16));
+            }
+            pos += step;
+        }
+        self.phase = pos - count as f64;
+        for ch in 0..channels {
+            self.last[ch] = buf.get_sample(count - 1, ch);
+        }
+    }
+
+    fn queue_full(&mut self) -> bool {
+        let pb = self.device.lock();
+        pb.queue.len() > pb.prefill
+    }
+
+    pub(crate) fn render_frame(&mut self, buf: &SndBuffer<SF>, throttle: bool) {
+        if throttle {
+            // Wait until the queue is below the prefill level. This creates
+            // some lag, but should keep the audio playing with no cracks.
+            while self.queue_full() {
+                std::thread::sleep(Duration::from_micros(100));
+            }
+        } else {
+            // If we're not throttling there are two possibilities:
+            // we're either running too slow (in which case, there would be
+            // audio cracks), or too fast; in the latter case, we want to skip
+            // some audio frames to avoid desyncing audio and video.
+            if self.queue_full() {
+                return;
+            }
+        }
+
+        // Resample outside of the audio lock, to avoid blocking playback.
+        let mut samples = VecDeque::with_capacity(self.frame_len + SF::CHANNELS * 2);
+        self.resample(buf, &mut samples);
+
+        let mut pb = self.device.lock();
+        pb.queue.extend(samples);
+        pb.update_status();
+    }
+
+    /// Play a frame while the emulation runs at the specified speed (as a
+    /// multiplier of the nominal speed; None means unthrottled). As samples
+    /// are played at the nominal rate, this throttles the emulation to the
+    /// requested speed: when running faster, some frames are skipped; when
+    /// running slower, each frame is stretched (or replaced with silence).
+    pub(crate) fn render_frame_at_speed(
+        &mut self,
+        buf: &SndBuffer<SF>,
+        speed: Option<f32>,
+        slow_audio: SlowAudio,
+    ) {
+        match speed {
+            None => self.render_frame(buf, false),
+            Some(speed) if speed >= 1.0 => {
+                self.credit += 1.0 / speed as f64;
+                if self.credit >= 1.0 {
+                    self.credit -= 1.0;
+                    self.render_frame(buf, true);
+                }
+            }
+            Some(speed) => {
+                let stretched = Self::stretch(buf, speed, slow_audio);
+                if !stretched.is_empty() {
+                    self.render_frame(&SndBuffer::new_typed(&stretched), true);
+                }
+            }
+        }
+    }
+
+    // Stretch a buffer to play it at a lower speed, without changing its
+    // pitch: the buffer is split into short grains, and each grain is
+    // repeated as many times as required.
+    fn stretch(buf: &SndBuffer<SF>, speed: f32, mode: SlowAudio) -> Vec<SI> {
+        let channels = SF::CHANNELS;
+        let count = buf.count();
+        let len = (count as f32 / speed) as usize;
+        if mode == SlowAudio::Mute {
+            return vec![SI::MUTE; len * channels];
+        }
+
+        let mut out = Vec::with_capacity(len * channels + STRETCH_GRAIN * channels);
+        let mut acc = 0.0f32;
+        for start in (0..count).step_by(STRETCH_GRAIN) {
+            let end = (start + STRETCH_GRAIN).min(count);
+            acc += 1.0 / speed;
+            while acc >= 1.0 {
+                for i in start..end {
+                    for ch in 0..channels {
+                        out.push(buf.get_sample(i, ch));
+                    }
+                }
+                acc -= 1.0;
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snd::S16_STEREO;
+
+    #[test]
+    fn scale() {
+        assert_eq!(Playback::<i16>::scale(1000, 1.0), 1000);
+        assert_eq!(Playback::<i16>::scale(1000, 0.5), 500);
+        assert_eq!(Playback::<i16>::scale(-1000, 0.0), 0);
+        assert_eq!(Playback::<u8>::scale(0xC0, 0.5), 0xA0);
+    }
+
+    #[test]
+    fn stretch() {
+        let samples: Vec<i16> = (0..1024).map(|i| i as i16).collect();
+        let buf = SndBuffer::<S16_STEREO>::new_typed(&samples);
+        let out = Audio::<i16, S16_STEREO>::stretch(&buf, 0.5, SlowAudio::Stretch);
+        assert_eq!(out.len(), samples.len() * 2);
+        // Each grain is played twice, unchanged.
+        let grain = STRETCH_GRAIN * 2;
+        assert_eq!(&out[..grain], &samples[..grain]);
+        assert_eq!(&out[grain..grain * 2], &samples[..grain]);
+
+        let out = Audio::<i16, S16_STEREO>::stretch(&buf, 0.25, SlowAudio::Mute);
+        assert_eq!(out.len(), samples.len() * 4);
+        assert!(out.iter().all(|&s| s == 0));
+    }
+}
@@ -0,0 +1,102 @@
+use super::InputMapping;
+use crate::hw::osd_message;
+
+use sdl2::controller::{GameController, GameControllerSubsystem};
+use sdl2::event::Event;
+use sdl2::haptic::{Haptic, HapticSubsystem};
+use slog::error;
+
+// Duration of a single rumble effect. Rumble is refreshed every frame while
+// it's active, so this only needs to be longer than a frame.
+const RUMBLE_DURATION_MS: u32 = 100;
+
+struct Pad {
+    ctrl: GameController,
+    haptic: Option<Haptic>,
+    rumble: bool,
+}
+
+/// Gamepads tracks the SDL game controllers connected to the host, and
+/// registers them into the input mapping (in connection order), so that
+/// their buttons and axes can be bound to emulated inputs. It also forwards
+/// force feedback requested by the emulated devices (eg: Rumble Pak) to the
+/// physical controllers, when supported.
+pub(crate) struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    haptic: Option<HapticSubsystem>,
+    pads: Vec<Pad>,
+    logger: slog::Logger,
+}
+
+impl Gamepads {
+    pub(crate) fn new(context: &sdl2::Sdl, logger: slog::Logger) -> Result<Self, String> {
+        Ok(Self {
+            subsystem: context.game_controller()?,
+            // Force feedback is optional: go on without it if the platform
+            // does not support it.
+            haptic: context.haptic().ok(),
+            pads: Vec::new(),
+            logger,
+        })
+    }
+
+    /// Process connection and disconnection of controllers. SDL sends a
+    /// ControllerDeviceAdded event for each controller already connected at
+    /// startup, so there is no need to enumerate them.
+    pub(crate) fn handle_event(&mut self, event: &Event, map: &mut InputMapping) {
+        match event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                let ctrl = match self.subsystem.open(*which as u32) {
+                    Ok(ctrl) => ctrl,
+                    Err(err) => {
+                        error!(self.logger, "cannot open game controller"; "index" => *which, "err" => err);
+                        return;
+                    }
+                };
+                let id = ctrl.instance_id() as u32;
+                if self.pads.iter().any(|p| p.ctrl.instance_id() as u32 == id) {
+                    return;
+                }
+                let haptic = self
+                    .haptic
+                    .as_ref()
+                    .and_then(|h| h.open_from_joystick_id(*which as u32).ok());
+                map.add_pad(id);
+                osd_message(format!("Controller connected: {}", ctrl.name()));
+                self.pads.push(Pad {
+                    ctrl,
+                    haptic,
+                    rumble: false,
+                });
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                let id = *which as u32;
+                map.remove_pad(id);
+                if let Some(pad) = self.pads.iter().find(|p| p.ctrl.instance_id() as u32 == id) {
+                    osd_message(format!("Controller disconnected: {}", pad.ctrl.name()));
+                }
+                self.pads.retain(|p| p.ctrl.instance_id() as u32 != id);
+            }
+            _ => {}
+        }
+    }
+
+    /// Update the force feedback state of the controllers, given the state
+    /// of the emulated devices.
+    pub(crate) fn update_rumble<F: Fn(&str) -> bool>(&mut self, map: &InputMapping, rumbling: F) {
+        for (idx, on) in map.rumble_pads(rumbling) {
+            let pad = match self.pads.get_mut(idx) {
+                Some(pad) => pad,
+                None => continue,
+            };
+            if let Some(h) = pad.haptic.as_mut() {
+                if on {
+                    h.rumble_play(0.75, RUMBLE_DURATION_MS);
+                } else if pad.rumble {
+                    h.rumble_stop();
+                }
+            }
+            pad.rumble = on;
+        }
+    }
+}
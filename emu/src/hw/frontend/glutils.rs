@@ -0,0 +1,884 @@
+extern crate gl;
+
+use self::gl::types::*;
+use crate::config::ConfigSection;
+use crate::gfx::{ColorFormat, GfxBufferLE, GfxBufferMutLE, Rgb888, Rgba8888};
+use serde_derive::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::ffi;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+fn return_param<T, F>(f: F) -> T
+where
+    F: FnOnce(&mut T),
+{
+    let mut val = unsafe { ::std::mem::uninitialized() };
+    f(&mut val);
+    val
+}
+
+pub trait ColorForTexture: ColorFormat {
+    fn src_format() -> GLenum;
+    fn dst_format() -> GLenum;
+    fn byte_size() -> i32;
+}
+
+impl ColorForTexture for Rgb888 {
+    fn src_format() -> GLenum {
+        gl::RGBA
+    }
+    fn dst_format() -> GLenum {
+        gl::RGB
+    }
+    fn byte_size() -> i32 {
+        return 3;
+    }
+}
+
+impl ColorForTexture for Rgba8888 {
+    fn src_format() -> GLenum {
+        gl::RGBA
+    }
+    fn dst_format() -> GLenum {
+        gl::RGBA
+    }
+    fn byte_size() -> i32 {
+        return 4;
+    }
+}
+
+/// ScaleMode selects how the emulator output is scaled to the window.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScaleMode {
+    /// Stretch to fill the whole window.
+    Stretch,
+    /// Scale as much as possible preserving the 4:3 aspect ratio, with
+    /// black bars (letterboxing) on the sides.
+    Aspect,
+    /// Scale by the largest integer factor that fits the window, so that
+    /// all pixels have the same size.
+    Integer,
+}
+
+/// Options for rendering the emulator output into the window. They are
+/// persisted in the "video" section of the config file.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoOptions {
+    pub scale: ScaleMode,
+    /// Use bilinear filtering instead of nearest-neighbor.
+    pub bilinear: bool,
+    /// Strength of the CRT-style scanline effect (0.0: disabled).
+    pub scanlines: f32,
+    /// Internal resolution of emulators drawing frames with the GPU, as a
+    /// multiplier of the native resolution (1: native).
+    pub upscale: usize,
+    /// Show the frames per second and the emulation speed over the output.
+    pub osd_stats: bool,
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        Self {
+            scale: ScaleMode::Aspect,
+            bilinear: true,
+            scanlines: 0.0,
+            upscale: 1,
+            osd_stats: false,
+        }
+    }
+}
+
+impl ConfigSection for VideoOptions {
+    const NAME: &'static str = "video";
+}
+
+impl VideoOptions {
+    // Output display aspect ratio of the console.
+    const ASPECT_RATIO: f32 = 4.0 / 3.0;
+
+    /// Compute the viewport (x, y, width, height) where an image of the
+    /// specified size is drawn within a window of the specified size.
+    pub fn viewport(&self, img: (usize, usize), win: (usize, usize)) -> (i32, i32, i32, i32) {
+        let (ww, wh) = (win.0 as f32, win.1 as f32);
+        let (w, h) = match self.scale {
+            ScaleMode::Stretch => (ww, wh),
+            ScaleMode::Aspect => {
+                if ww / wh > Self::ASPECT_RATIO {
+                    (wh * Self::ASPECT_RATIO, wh)
+                } else {
+                    (ww, ww / Self::ASPECT_RATIO)
+                }
+            }
+            ScaleMode::Integer => {
+                let factor = (win.0 / img.0.max(1)).min(win.1 / img.1.max(1)).max(1);
+                ((img.0 * factor) as f32, (img.1 * factor) as f32)
+            }
+        };
+        (
+            ((ww - w) / 2.0) as i32,
+            ((wh - h) / 2.0) as i32,
+            w as i32,
+            h as i32,
+        )
+    }
+}
+
+pub struct Texture {
+    id: GLuint,
+}
+
+impl Texture {
+    pub fn new() -> Self {
+        unsafe {
+            let id = return_param(|x| gl::GenTextures(1, x as *mut u32));
+            Self { id }
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id as usize
+    }
+
+    pub fn copy_from<CF: ColorForTexture>(&self, pixels: &[u8], width: usize, height: usize) {
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                CF::dst_format() as i32,
+                width as i32,
+                height as i32,
+                0,
+                CF::src_format(),
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *mut ffi::c_void,
+            );
+        }
+    }
+
+    pub fn copy_from_buffer<CF: ColorForTexture>(&self, buffer: &GfxBufferLE<CF>) {
+        let (pixels, _pitch) = buffer.raw();
+        self.copy_from::<CF>(pixels, buffer.width(), buffer.height())
+    }
+
+    pub fn copy_from_buffer_mut<CF: ColorForTexture>(&self, buffer: &mut GfxBufferMutLE<CF>) {
+        let (width, heigth) = (buffer.width(), buffer.height());
+        let (pixels, _pitch) = buffer.raw();
+        self.copy_from::<CF>(pixels, width, heigth)
+    }
+}
+
+// Bind a texture, selecting bilinear or nearest-neighbor filtering.
+unsafe fn bind_texture_filtered(id: GLuint, bilinear: bool) {
+    let filter = if bilinear { gl::LINEAR } else { gl::NEAREST } as i32;
+    gl::BindTexture(gl::TEXTURE_2D, id);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter);
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+struct VertexBuffer {
+    id: GLuint,
+}
+
+impl VertexBuffer {
+    unsafe fn new() -> Self {
+        let id = return_param(|x| gl::GenBuffers(1, x as *mut u32));
+        Self { id }
+    }
+}
+
+impl Drop for VertexBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}
+
+struct VertexArray {
+    id: GLuint,
+}
+
+impl VertexArray {
+    unsafe fn new() -> Self {
+        let id = return_param(|x| gl::GenVertexArrays(1, x as *mut u32));
+        Self { id }
+    }
+}
+
+impl Drop for VertexArray {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.id);
+        }
+    }
+}
+
+struct Program {
+    id: GLuint,
+}
+
+impl Program {
+    unsafe fn new() -> Self {
+        let id = gl::CreateProgram();
+        Self { id }
+    }
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteProgram(self.id);
+        }
+    }
+}
+
+// Attribute locations of the vertex shader, bound explicitly so that all
+// programs can share the same vertex array.
+const ATTR_POSITION: GLuint = 0;
+const ATTR_TEXCOORD: GLuint = 1;
+
+const VERTEX_SHADER: &str = "
+    #version 150
+    in vec2 a_position;
+    in vec2 a_texcoord;
+    out vec2 v_texcoord;
+    void main() {
+        gl_Position = vec4(a_position * 2.0 - 1.0, 0.0, 1.0);
+        v_texcoord = a_texcoord;
+    }
+";
+
+// Scanlines are simulated by modulating each source line with the profile of
+// a CRT beam: full intensity at its center, and darker towards its edges.
+const FRAGMENT_SHADER: &str = "
+    #version 150
+    uniform sampler2D u_texture;
+    uniform float u_scanlines;
+    uniform vec2 u_resolution;
+    in vec2 v_texcoord;
+    out vec4 v_fragcolor;
+    void main() {
+        vec4 color = texture(u_texture, v_texcoord);
+        float line = fract(v_texcoord.y * u_resolution.y);
+        float beam = 0.5 + 0.5 * cos(6.2831853 * (line - 0.5));
+        v_fragcolor = vec4(color.rgb * mix(1.0, beam, u_scanlines), color.a);
+    }
+";
+
+unsafe fn compile_shader(kind: GLenum, source: &str) -> Result<GLuint, String> {
+    let shader = gl::CreateShader(kind);
+    gl::ShaderSource(
+        shader,
+        1,
+        &(source.as_ptr() as *const GLchar),
+        &(source.len() as GLint),
+    );
+    gl::CompileShader(shader);
+
+    let status: GLint = return_param(|x| gl::GetShaderiv(shader, gl::COMPILE_STATUS, x));
+    if status == gl::FALSE as GLint {
+        let len: GLint = return_param(|x| gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, x));
+        let mut log = vec![0u8; len.max(1) as usize];
+        gl::GetShaderInfoLog(shader, len, ::std::ptr::null_mut(), log.as_mut_ptr() as _);
+        gl::DeleteShader(shader);
+        return Err(String::from_utf8_lossy(&log)
+            .trim_end_matches('\0')
+            .to_owned());
+    }
+    Ok(shader)
+}
+
+// Compile and link a program, binding the specified attribute locations.
+unsafe fn link_program(
+    vert_source: &str,
+    frag_source: &str,
+    attrs: &[(GLuint, &str)],
+) -> Result<Program, String> {
+    let vert_shader = compile_shader(gl::VERTEX_SHADER, vert_source)?;
+    let frag_shader = match compile_shader(gl::FRAGMENT_SHADER, frag_source) {
+        Ok(shader) => shader,
+        Err(err) => {
+            gl::DeleteShader(vert_shader);
+            return Err(err);
+        }
+    };
+
+    let program = Program::new();
+    gl::AttachShader(program.id, vert_shader);
+    gl::AttachShader(program.id, frag_shader);
+    for (loc, name) in attrs {
+        let name = ffi::CString::new(*name).unwrap();
+        gl::BindAttribLocation(program.id, *loc, name.as_ptr());
+    }
+    gl::LinkProgram(program.id);
+    gl::DeleteShader(vert_shader);
+    gl::DeleteShader(frag_shader);
+
+    let status: GLint = return_param(|x| gl::GetProgramiv(program.id, gl::LINK_STATUS, x));
+    if status == gl::FALSE as GLint {
+        let len: GLint = return_param(|x| gl::GetProgramiv(program.id, gl::INFO_LOG_LENGTH, x));
+        let mut log = vec![0u8; len.max(1) as usize];
+        gl::GetProgramInfoLog(
+            program.id,
+            len,
+            ::std::ptr::null_mut(),
+            log.as_mut_ptr() as _,
+        );
+        return Err(String::from_utf8_lossy(&log)
+            .trim_end_matches('\0')
+            .to_owned());
+    }
+    Ok(program)
+}
+
+/// ShaderProgram is a GLSL program, for emulators that render through OpenGL
+/// (eg: a hardware-accelerated GPU backend). It must be used on the thread
+/// owning the OpenGL context of the output window.
+pub struct ShaderProgram {
+    program: Program,
+}
+
+impl ShaderProgram {
+    /// Compile and link a program from the sources of its vertex and
+    /// fragment shaders, binding the vertex attributes to the specified
+    /// locations. Returns the compilation log on errors.
+    pub fn new(
+        vert_source: &str,
+        frag_source: &str,
+        attrs: &[(u32, &str)],
+    ) -> Result<Self, String> {
+        let program = unsafe { link_program(vert_source, frag_source, attrs)? };
+        Ok(Self { program })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.program.id
+    }
+
+    /// Location of a uniform (-1 if it's not used by the program).
+    pub fn uniform(&self, name: &str) -> i32 {
+        let name = ffi::CString::new(name).unwrap();
+        unsafe { gl::GetUniformLocation(self.program.id, name.as_ptr()) }
+    }
+}
+
+/// RenderTarget is an offscreen framebuffer, with a RGBA color buffer and a
+/// depth buffer, that can be loaded from and read back into memory.
+pub struct RenderTarget {
+    fbo: GLuint,
+    color: Texture,
+    depth: GLuint,
+    width: usize,
+    height: usize,
+}
+
+impl RenderTarget {
+    pub fn new(width: usize, height: usize) -> Self {
+        unsafe {
+            let color = Texture::new();
+            gl::BindTexture(gl::TEXTURE_2D, color.id);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA8 as i32,
+                width as i32,
+                height as i32,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                ::std::ptr::null(),
+            );
+
+            let depth = return_param(|x| gl::GenRenderbuffers(1, x as *mut u32));
+            gl::BindRenderbuffer(gl::RENDERBUFFER, depth);
+            gl::RenderbufferStorage(
+                gl::RENDERBUFFER,
+                gl::DEPTH_COMPONENT24,
+                width as i32,
+                height as i32,
+            );
+
+            let fbo = return_param(|x| gl::GenFramebuffers(1, x as *mut u32));
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            gl::FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                color.id,
+                0,
+            );
+            gl::FramebufferRenderbuffer(
+                gl::FRAMEBUFFER,
+                gl::DEPTH_ATTACHMENT,
+                gl::RENDERBUFFER,
+                depth,
+            );
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+            Self {
+                fbo,
+                color,
+                depth,
+                width,
+                height,
+            }
+        }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Texture holding the color buffer (eg: to display it with
+    /// `SurfaceRenderer::render_texture`).
+    pub fn color_texture(&self) -> u32 {
+        self.color.id
+    }
+
+    /// Make this the target of drawing operations (until `unbind`).
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::Viewport(0, 0, self.width as i32, self.height as i32);
+        }
+    }
+
+    /// Restore drawing into the window.
+    pub fn unbind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    /// Replace the color buffer with RGBA pixels, from bottom to top.
+    pub fn write_color(&self, pixels: &[u8]) {
+        assert_eq!(pixels.len(), self.width * self.height * 4);
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.color.id);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                self.width as i32,
+                self.height as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as _,
+            );
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        }
+    }
+
+    /// Read back the color buffer as RGBA pixels, from bottom to top.
+    pub fn read_color(&self) -> Vec<u8> {
+        let mut pixels = vec![0u8; self.width * self.height * 4];
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.fbo);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(
+                0,
+                0,
+                self.width as i32,
+                self.height as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_mut_ptr() as _,
+            );
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+        pixels
+    }
+
+    /// Read back the depth buffer (0.0-1.0), from bottom to top.
+    pub fn read_depth(&self) -> Vec<f32> {
+        let mut depth = vec![0f32; self.width * self.height];
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.fbo);
+            gl::ReadPixels(
+                0,
+                0,
+                self.width as i32,
+                self.height as i32,
+                gl::DEPTH_COMPONENT,
+                gl::FLOAT,
+                depth.as_mut_ptr() as _,
+            );
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+        depth
+    }
+}
+
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteRenderbuffers(1, &self.depth);
+        }
+    }
+}
+
+// A program drawing the framebuffer texture, with the locations of its
+// uniforms (-1 if not used by the shader).
+struct SurfaceProgram {
+    program: Program,
+    loc_u_scanlines: GLint,
+    loc_u_resolution: GLint,
+    loc_u_output_size: GLint,
+    loc_u_frame_count: GLint,
+}
+
+impl SurfaceProgram {
+    unsafe fn new(frag_source: &str) -> Result<Self, String> {
+        let program = link_program(
+            VERTEX_SHADER,
+            frag_source,
+            &[(ATTR_POSITION, "a_position"), (ATTR_TEXCOORD, "a_texcoord")],
+        )?;
+
+        let loc = |name: &[u8]| gl::GetUniformLocation(program.id, name.as_ptr() as _);
+        let loc_u_texture = loc(b"u_texture\0");
+        let prog = Self {
+            loc_u_scanlines: loc(b"u_scanlines\0"),
+            loc_u_resolution: loc(b"u_resolution\0"),
+            loc_u_output_size: loc(b"u_output_size\0"),
+            loc_u_frame_count: loc(b"u_frame_count\0"),
+            program,
+        };
+        gl::UseProgram(prog.program.id);
+        gl::Uniform1i(loc_u_texture, 0);
+        Ok(prog)
+    }
+}
+
+// An external post-processing shader, reloaded when the file changes.
+struct CustomShader {
+    path: PathBuf,
+    mtime: Option<SystemTime>,
+    last_check: Instant,
+    program: Option<SurfaceProgram>, // None if the shader failed to compile
+}
+
+const SHADER_RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+thread_local! {
+    // Set on the thread where the OpenGL context of the window is current.
+    static GL_CONTEXT: Cell<bool> = Cell::new(false);
+}
+
+/// Return true if the calling thread can issue OpenGL calls, that is it
+/// created the output window (and its context). This is not the case in
+/// headless mode, nor on the emulation thread of `Output::run_threaded`.
+pub fn gl_context_current() -> bool {
+    GL_CONTEXT.with(|c| c.get())
+}
+
+/// SurfaceRenderer draws the emulator framebuffer into the window.
+///
+/// An external GLSL fragment shader can be loaded to post-process the
+/// framebuffer (see `load_shader`). It must be a GLSL 1.50 shader, that
+/// receives the following inputs and uniforms (all optional):
+///
+/// ```glsl
+/// in vec2 v_texcoord;           // texture coordinates (0.0-1.0)
+/// uniform sampler2D u_texture;  // emulated framebuffer
+/// uniform vec2 u_resolution;    // size of the framebuffer, in pixels
+/// uniform vec2 u_output_size;   // size of the output viewport, in pixels
+/// uniform int u_frame_count;    // number of frames rendered so far
+/// uniform float u_scanlines;    // scanline strength set by the user
+/// ```
+///
+/// and writes the final color into `out vec4 v_fragcolor`.
+pub struct SurfaceRenderer {
+    vao: VertexArray,
+    _vbo_pos: VertexBuffer, // saved here for Drop
+    _vbo_tex: VertexBuffer, // saved here for Drop
+    program: SurfaceProgram,
+    custom: Option<CustomShader>,
+    tex: Texture,
+    osd_tex: Texture,
+    frame_count: i32,
+
+    // Backend storage for vertex buffers (must be heap allocated)
+    _pos_data: Vec<GLfloat>,
+    _tex_data: Vec<GLfloat>,
+}
+
+impl SurfaceRenderer {
+    pub fn new<F>(load_fn: F) -> Self
+    where
+        F: FnMut(&'static str) -> *const ::std::os::raw::c_void,
+    {
+        unsafe {
+            gl::load_with(load_fn);
+            GL_CONTEXT.with(|c| c.set(true));
+            let program = SurfaceProgram::new(FRAGMENT_SHADER).unwrap();
+
+            let vao = VertexArray::new();
+            gl::BindVertexArray(vao.id);
+
+            let vbo_pos = VertexBuffer::new();
+            let vbo_tex = VertexBuffer::new();
+            let pos_data = vec![0.0, 1.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0];
+            let tex_data = vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo_pos.id);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                pos_data.len() as isize * ::std::mem::size_of::<GLfloat>() as isize,
+                pos_data.as_ptr() as _,
+                gl::STATIC_DRAW,
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo_tex.id);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                tex_data.len() as isize * ::std::mem::size_of::<GLfloat>() as isize,
+                tex_data.as_ptr() as _,
+                gl::STATIC_DRAW,
+            );
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo_pos.id);
+            gl::VertexAttribPointer(
+                ATTR_POSITION,
+                2,
+                gl::FLOAT,
+                gl::FALSE,
+                0,
+                ::std::ptr::null(),
+            );
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo_tex.id);
+            gl::VertexAttribPointer(
+                ATTR_TEXCOORD,
+                2,
+                gl::FLOAT,
+                gl::FALSE,
+                0,
+                ::std::ptr::null(),
+            );
+
+            gl::EnableVertexAttribArray(ATTR_POSITION);
+            gl::EnableVertexAttribArray(ATTR_TEXCOORD);
+
+            let surf = Self {
+                tex: Texture::new(),
+                osd_tex: Texture::new(),
+                vao: vao,
+                _vbo_pos: vbo_pos,
+                _vbo_tex: vbo_tex,
+                _pos_data: pos_data,
+                _tex_data: tex_data,
+                program: program,
+                custom: None,
+                frame_count: 0,
+            };
+
+            surf
+        }
+    }
+
+    /// Load an external post-processing shader from the specified file, to
+    /// be used instead of the builtin one. The shader is automatically
+    /// reloaded whenever the file is modified. If the shader fails to
+    /// compile, the error is returned and the builtin shader is used until
+    /// the file is fixed.
+    pub fn load_shader(&mut self, path: &Path) -> Result<(), String> {
+        self.custom = Some(CustomShader {
+            path: path.to_owned(),
+            mtime: None,
+            last_check: Instant::now(),
+            program: None,
+        });
+        self.reload_shader()
+    }
+
+    /// Stop using the external shader (if any).
+    pub fn unload_shader(&mut self) {
+        self.custom = None;
+    }
+
+    fn reload_shader(&mut self) -> Result<(), String> {
+        let custom = match self.custom.as_mut() {
+            Some(custom) => custom,
+            None => return Ok(()),
+        };
+        custom.mtime = fs::metadata(&custom.path).and_then(|m| m.modified()).ok();
+        custom.program = None;
+        let source = fs::read_to_string(&custom.path)
+            .map_err(|e| format!("cannot read shader {}: {}", custom.path.display(), e))?;
+        let program = unsafe { SurfaceProgram::new(&source) }
+            .map_err(|e| format!("cannot compile shader {}: {}", custom.path.display(), e))?;
+        custom.program = Some(program);
+        Ok(())
+    }
+
+    /// Check whether the external shader file was modified, and if so, reload
+    /// it. Returns an error if the new version fails to compile.
+    pub fn poll_reload(&mut self) -> Result<bool, String> {
+        let custom = match self.custom.as_mut() {
+            Some(custom) => custom,
+            None => return Ok(false),
+        };
+        if custom.last_check.elapsed() < SHADER_RELOAD_INTERVAL {
+            return Ok(false);
+        }
+        custom.last_check = Instant::now();
+        let mtime = fs::metadata(&custom.path).and_then(|m| m.modified()).ok();
+        if mtime == custom.mtime {
+            return Ok(false);
+        }
+        self.reload_shader()?;
+        Ok(true)
+    }
+
+    /// Render the buffer into a window of the specified size (in pixels).
+    pub fn render<C: ColorForTexture>(
+        &mut self,
+        buffer: &GfxBufferLE<C>,
+        opts: &VideoOptions,
+        win: (usize, usize),
+    ) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+        self.tex.copy_from_buffer(buffer);
+        let tex = self.tex.id;
+        self.render_texture(tex, (buffer.width(), buffer.height()), opts, win);
+    }
+
+    /// Render a texture into a window of the specified size (in pixels), as
+    /// if it was a buffer of the specified size. This is used to display
+    /// frames drawn by the emulator on the GPU, at a higher resolution than
+    /// the video buffer.
+    pub fn render_texture(
+        &mut self,
+        tex: u32,
+        size: (usize, usize),
+        opts: &VideoOptions,
+        win: (usize, usize),
+    ) {
+        self.frame_count = self.frame_count.wrapping_add(1);
+        let prog = match self.custom.as_ref().and_then(|c| c.program.as_ref()) {
+            Some(prog) => prog,
+            None => &self.program,
+        };
+
+        unsafe {
+            // Clear the whole window, to draw black bars around the image
+            // (if any).
+            gl::Viewport(0, 0, win.0 as i32, win.1 as i32);
+            gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            let (x, y, w, h) = opts.viewport(size, win);
+            gl::Viewport(x, y, w, h);
+
+            gl::UseProgram(prog.program.id);
+            gl::Uniform1f(prog.loc_u_scanlines, opts.scanlines);
+            gl::Uniform2f(prog.loc_u_resolution, size.0 as f32, size.1 as f32);
+            gl::Uniform2f(prog.loc_u_output_size, w as f32, h as f32);
+            gl::Uniform1i(prog.loc_u_frame_count, self.frame_count);
+            gl::ActiveTexture(gl::TEXTURE0);
+            bind_texture_filtered(tex, opts.bilinear);
+
+            gl::BindVertexArray(self.vao.id);
+            gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+            gl::Viewport(0, 0, win.0 as i32, win.1 as i32);
+        }
+    }
+
+    /// Draw an overlay (eg: the on-screen display) stretched over the whole
+    /// window, on top of the frame drawn by `render`. The overlay is blended
+    /// using its alpha channel, and is never post-processed.
+    pub fn render_overlay(&mut self, buffer: &GfxBufferLE<Rgba8888>, win: (usize, usize)) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+        self.osd_tex.copy_from_buffer(buffer);
+        let size = (buffer.width() as f32, buffer.height() as f32);
+        let prog = &self.program;
+
+        unsafe {
+            gl::Viewport(0, 0, win.0 as i32, win.1 as i32);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+
+            gl::UseProgram(prog.program.id);
+            gl::Uniform1f(prog.loc_u_scanlines, 0.0);
+            gl::Uniform2f(prog.loc_u_resolution, size.0, size.1);
+            gl::ActiveTexture(gl::TEXTURE0);
+            bind_texture_filtered(self.osd_tex.id, false);
+
+            gl::BindVertexArray(self.vao.id);
+            gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+            gl::Disable(gl::BLEND);
+        }
+    }
+
+    /// Read back the contents of the window (as drawn by the last call to
+    /// `render`, before swapping), as RGBA pixels from top to bottom.
+    pub fn read_pixels(&self, win: (usize, usize)) -> Vec<u8> {
+        let (w, h) = win;
+        let mut pixels = vec![0u8; w * h * 4];
+        unsafe {
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(
+                0,
+                0,
+                w as i32,
+                h as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_mut_ptr() as _,
+            );
+        }
+
+        // OpenGL returns lines from bottom to top.
+        let mut flipped = Vec::with_capacity(pixels.len());
+        for line in pixels.chunks(w * 4).rev() {
+            flipped.extend_from_slice(line);
+        }
+        flipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn viewport() {
+        let mut opts = VideoOptions::default();
+        opts.scale = ScaleMode::Stretch;
+        assert_eq!(opts.viewport((640, 480), (1000, 500)), (0, 0, 1000, 500));
+
+        opts.scale = ScaleMode::Aspect;
+        assert_eq!(opts.viewport((640, 480), (1000, 600)), (100, 0, 800, 600));
+        assert_eq!(opts.viewport((640, 480), (800, 800)), (0, 100, 800, 600));
+
+        opts.scale = ScaleMode::Integer;
+        assert_eq!(opts.viewport((320, 240), (1000, 800)), (20, 40, 960, 720));
+        assert_eq!(opts.viewport((640, 480), (600, 400)), (-20, -40, 640, 480));
+    }
+}
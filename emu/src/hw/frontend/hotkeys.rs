@@ -0,0 +1,418 @@
+use crate::config::ConfigSection;
+
+use imgui::{im_str, Condition, Ui, Window};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A named action that can be triggered by a hotkey.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HotkeyAction {
+    // Emulator (both in the main window and in the debugger)
+    ToggleDebugger,
+    Pause,
+    FrameAdvance,
+    FastForward,
+    Turbo,
+    SlowMotion,
+    SaveState,
+    LoadState,
+    ToggleRecording,
+    ToggleOsd,
+    Screenshot,
+    ScreenshotScaled,
+
+    // Debugger
+    RunStop,
+    Help,
+    Center,
+    Step,
+    StepOver,
+    StepOut,
+    RunToCursor,
+    FollowBranch,
+    NavBack,
+    NavForward,
+    Assemble,
+}
+
+/// Description of a hotkey action: name in the config file, default key
+/// combos (comma-separated), and description shown in the UI.
+pub struct HotkeyInfo {
+    pub action: HotkeyAction,
+    pub name: &'static str,
+    pub default: &'static str,
+    pub desc: &'static str,
+    pub debugger: bool, // true if the action is only available in the debugger
+}
+
+const fn hotkey(
+    action: HotkeyAction,
+    name: &'static str,
+    default: &'static str,
+    desc: &'static str,
+    debugger: bool,
+) -> HotkeyInfo {
+    HotkeyInfo {
+        action,
+        name,
+        default,
+        desc,
+        debugger,
+    }
+}
+
+/// All the actions, in the order they're listed in the UI.
+#[rustfmt::skip]
+pub const HOTKEYS: [HotkeyInfo; 23] = [
+    hotkey(HotkeyAction::ToggleDebugger,   "toggle_debugger",   "Escape",              "Enter/exit debugger", false),
+    hotkey(HotkeyAction::Pause,            "pause",             "P",                   "Pause (in the debugger: at end of frame)", false),
+    hotkey(HotkeyAction::FrameAdvance,     "frame_advance",     "N",                   "Frame advance", false),
+    hotkey(HotkeyAction::FastForward,      "fast_forward",      "Tab",                 "Fast forward (hold, outside debugger)", false),
+    hotkey(HotkeyAction::Turbo,            "turbo",             "T",                   "Toggle turbo (outside debugger)", false),
+    hotkey(HotkeyAction::SlowMotion,       "slow_motion",       "L",                   "Toggle slow motion (outside debugger)", false),
+    hotkey(HotkeyAction::SaveState,        "save_state",        "F5",                  "Save state", false),
+    hotkey(HotkeyAction::LoadState,        "load_state",        "F7",                  "Load state", false),
+    hotkey(HotkeyAction::ToggleRecording,  "toggle_recording",  "F10",                 "Start/stop video recording", false),
+    hotkey(HotkeyAction::ToggleOsd,        "toggle_osd",        "F2",                  "Show/hide FPS and speed", false),
+    hotkey(HotkeyAction::Screenshot,       "screenshot",        "F12",                 "Screenshot", false),
+    hotkey(HotkeyAction::ScreenshotScaled, "screenshot_scaled", "Shift+F12",           "Screenshot of the scaled output (outside debugger)", false),
+    hotkey(HotkeyAction::RunStop,          "run_stop",          "Space",               "Start/stop emulation", true),
+    hotkey(HotkeyAction::Help,             "help",              "H",                   "Show keyboard shortcuts", true),
+    hotkey(HotkeyAction::Center,           "center",            "C",                   "Center view", true),
+    hotkey(HotkeyAction::Step,             "step",              "S",                   "Step into", true),
+    hotkey(HotkeyAction::StepOver,         "step_over",         "O",                   "Step over", true),
+    hotkey(HotkeyAction::StepOut,          "step_out",          "U",                   "Step out", true),
+    hotkey(HotkeyAction::RunToCursor,      "run_to_cursor",     "R",                   "Run to selection", true),
+    hotkey(HotkeyAction::FollowBranch,     "follow_branch",     "Return",              "Follow branch", true),
+    hotkey(HotkeyAction::NavBack,          "nav_back",          "Backspace, Alt+Left", "Go back after following a branch", true),
+    hotkey(HotkeyAction::NavForward,       "nav_forward",       "Alt+Right",           "Go forward again", true),
+    hotkey(HotkeyAction::Assemble,         "assemble",          "A",                   "Assemble and patch the selected instruction", true),
+];
+
+/// A key combination: a key, with the modifiers that must be held.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct KeyCombo {
+    key: Scancode,
+    ctrl: bool,
+    shift: bool,
+    alt: bool,
+}
+
+impl KeyCombo {
+    /// Parse a combo like `F5`, `Shift+F12` or `Ctrl+Alt+S` (keys by SDL
+    /// key name).
+    pub fn parse(s: &str) -> Result<KeyCombo, String> {
+        let mut combo = KeyCombo {
+            key: Scancode::Unknown,
+            ctrl: false,
+            shift: false,
+            alt: false,
+        };
+        let parts: Vec<&str> = s.split('+').map(|p| p.trim()).collect();
+        let (key, mods) = parts.split_last().unwrap();
+        for m in mods {
+            match m.to_lowercase().as_str() {
+                "ctrl" => combo.ctrl = true,
+                "shift" => combo.shift = true,
+                "alt" => combo.alt = true,
+                _ => return Err(format!("unknown modifier in {:?}: {:?}", s, m)),
+            }
+        }
+        combo.key = Keycode::from_name(key)
+            .and_then(Scancode::from_keycode)
+            .ok_or_else(|| format!("unknown key: {:?}", key))?;
+        Ok(combo)
+    }
+
+    fn from_keymod(key: Scancode, keymod: Mod) -> KeyCombo {
+        KeyCombo {
+            key,
+            ctrl: keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD),
+            shift: keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD),
+            alt: keymod.intersects(Mod::LALTMOD | Mod::RALTMOD),
+        }
+    }
+
+    fn is_modifier(key: Scancode) -> bool {
+        match key {
+            Scancode::LCtrl
+            | Scancode::RCtrl
+            | Scancode::LShift
+            | Scancode::RShift
+            | Scancode::LAlt
+            | Scancode::RAlt
+            | Scancode::LGui
+            | Scancode::RGui => true,
+            _ => false,
+        }
+    }
+
+    // Check whether the combo was just pressed, according to imgui.
+    fn pressed(&self, ui: &Ui) -> bool {
+        let io = ui.io();
+        ui.is_key_pressed(self.key as _)
+            && io.key_ctrl == self.ctrl
+            && io.key_shift == self.shift
+            && io.key_alt == self.alt
+    }
+}
+
+impl ToString for KeyCombo {
+    fn to_string(&self) -> String {
+        let mut s = String::new();
+        if self.ctrl {
+            s += "Ctrl+";
+        }
+        if self.shift {
+            s += "Shift+";
+        }
+        if self.alt {
+            s += "Alt+";
+        }
+        s + &Keycode::from_scancode(self.key).unwrap().name()
+    }
+}
+
+fn parse_combos(s: &str) -> Result<Vec<KeyCombo>, String> {
+    s.split(',')
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+        .map(KeyCombo::parse)
+        .collect()
+}
+
+fn combos_to_string(combos: &[KeyCombo]) -> String {
+    combos
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The hotkeys persisted in the "hotkeys" section of the config file, as
+/// action name = comma-separated key combos. Actions missing from the file
+/// keep their default hotkeys.
+#[derive(Default, Serialize, Deserialize)]
+pub struct HotkeyConfig(BTreeMap<String, String>);
+
+impl ConfigSection for HotkeyConfig {
+    const NAME: &'static str = "hotkeys";
+}
+
+/// Hotkeys maps named actions to user-configurable key combos. It is shared
+/// by the main emulator window (which matches SDL events) and the debugger
+/// (which matches keys through imgui), and allows to rebind them through
+/// the "Hotkeys" window.
+pub struct Hotkeys {
+    combos: BTreeMap<HotkeyAction, Vec<KeyCombo>>,
+    capture: Option<HotkeyAction>,
+    opened: bool,
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        let combos = HOTKEYS
+            .iter()
+            .map(|hk| (hk.action, parse_combos(hk.default).unwrap()))
+            .collect();
+        Hotkeys {
+            combos,
+            capture: None,
+            opened: false,
+        }
+    }
+}
+
+impl Hotkeys {
+    /// Create the hotkeys from the config, falling back to the default
+    /// hotkeys for actions that are not configured.
+    pub fn new(cfg: &HotkeyConfig) -> Result<Hotkeys, String> {
+        let mut hk = Hotkeys::default();
+        for (name, combos) in cfg.0.iter() {
+            let info = HOTKEYS
+                .iter()
+                .find(|hk| hk.name == name.as_str())
+                .ok_or_else(|| format!("unknown hotkey action: {:?}", name))?;
+            hk.combos.insert(info.action, parse_combos(combos)?);
+        }
+        Ok(hk)
+    }
+
+    /// Return the config with all the hotkeys, so that the config file
+    /// lists them (and they can be edited there as well).
+    pub fn config(&self) -> HotkeyConfig {
+        HotkeyConfig(
+            HOTKEYS
+                .iter()
+                .map(|hk| (hk.name.to_owned(), self.describe(hk.action)))
+                .collect(),
+        )
+    }
+
+    /// Return the key combos bound to an action, for display.
+    pub fn describe(&self, action: HotkeyAction) -> String {
+        combos_to_string(&self.combos[&action])
+    }
+
+    /// Return the action triggered by a SDL key press (if any). Actions only
+    /// available in the debugger are ignored.
+    pub fn action(&self, key: Scancode, keymod: Mod) -> Option<HotkeyAction> {
+        let combo = KeyCombo::from_keymod(key, keymod);
+        HOTKEYS
+            .iter()
+            .filter(|hk| !hk.debugger)
+            .find(|hk| self.combos[&hk.action].contains(&combo))
+            .map(|hk| hk.action)
+    }
+
+    /// Return true if the key released in a SDL event is bound to the
+    /// action (whatever the modifiers), eg: to stop a hold action.
+    pub fn released(&self, action: HotkeyAction, key: Scancode) -> bool {
+        self.combos[&action].iter().any(|c| c.key == key)
+    }
+
+    /// Return true if the hotkey of an action was just pressed, according
+    /// to imgui (key repeat included).
+    pub fn pressed(&self, ui: &Ui, action: HotkeyAction) -> bool {
+        self.combos[&action].iter().any(|c| c.pressed(ui))
+    }
+
+    /// Return true if an action is being rebound through the UI. In this
+    /// state, all key events are consumed by the hotkeys.
+    pub fn capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    /// Process an event while rebinding an action: the first key pressed
+    /// (with its modifiers) becomes its hotkey, and Escape cancels.
+    pub fn capture_event(&mut self, event: &Event) {
+        match event {
+            Event::KeyDown {
+                scancode: Some(Scancode::Escape),
+                ..
+            } => {
+                self.capture = None;
+            }
+            Event::KeyDown {
+                scancode: Some(scode),
+                keymod,
+                repeat: false,
+                ..
+            } if !KeyCombo::is_modifier(*scode) => {
+                let action = self.capture.take().unwrap();
+                self.combos
+                    .insert(action, vec![KeyCombo::from_keymod(*scode, *keymod)]);
+            }
+            _ => {}
+        }
+    }
+
+    /// Open the "Hotkeys" window (eg: from a menu).
+    pub fn open_window(&mut self) {
+        self.opened = true;
+    }
+
+    /// Render the "Hotkeys" window, which allows to rebind actions
+    /// interactively. Changes are saved with the config file.
+    pub fn render_window<'ui>(&mut self, ui: &Ui<'ui>) {
+        if !self.opened {
+            return;
+        }
+        let mut opened = self.opened;
+        let mut capture = None;
+        let mut reset = false;
+
+        Window::new(im_str!("Hotkeys"))
+            .size([480.0, 500.0], Condition::FirstUseEver)
+            .opened(&mut opened)
+            .build(ui, || {
+                if let Some(action) = self.capture {
+                    let info = HOTKEYS.iter().find(|hk| hk.action == action).unwrap();
+                    ui.text_colored(
+                        [1.0, 1.0, 0.0, 1.0],
+                        format!("{}: press a key (ESC to cancel)...", info.name),
+                    );
+                }
+                if ui.small_button(im_str!("Reset to defaults")) {
+                    reset = true;
+                }
+                ui.separator();
+
+                for (debugger, title) in &[(false, "Emulator"), (true, "Debugger")] {
+                    if !ui
+                        .collapsing_header(&im_str!("{}", title))
+                        .default_open(true)
+                        .build()
+                    {
+                        continue;
+                    }
+                    for hk in HOTKEYS.iter().filter(|hk| hk.debugger == *debugger) {
+                        ui.text(self.describe(hk.action));
+                        if ui.is_item_hovered() {
+                            ui.tooltip_text(hk.desc);
+                        }
+                        ui.same_line(150.0);
+                        ui.text(hk.name);
+                        ui.same_line(330.0);
+                        if ui.small_button(&im_str!("Rebind###{}", hk.name)) {
+                            capture = Some(hk.action);
+                        }
+                        ui.same_line(0.0);
+                        if ui.small_button(&im_str!("Default###{}", hk.name)) {
+                            self.combos
+                                .insert(hk.action, parse_combos(hk.default).unwrap());
+                        }
+                    }
+                }
+            });
+
+        self.opened = opened;
+        if capture.is_some() {
+            self.capture = capture;
+        }
+        if reset {
+            self.combos = Hotkeys::default().combos;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combo_syntax() {
+        for s in &["F5", "Shift+F12", "Ctrl+Alt+S", "Return"] {
+            assert_eq!(KeyCombo::parse(s).unwrap().to_string(), *s);
+        }
+        assert_eq!(
+            KeyCombo::parse("shift + f12").unwrap(),
+            KeyCombo::parse("Shift+F12").unwrap()
+        );
+        assert!(KeyCombo::parse("Hyper+F1").is_err());
+        assert!(KeyCombo::parse("NotAKey").is_err());
+    }
+
+    #[test]
+    fn config() {
+        let mut cfg = HotkeyConfig::default();
+        cfg.0.insert("save_state".into(), "Ctrl+S, F2".into());
+        let hk = Hotkeys::new(&cfg).unwrap();
+        assert_eq!(hk.describe(HotkeyAction::SaveState), "Ctrl+S, F2");
+        assert_eq!(hk.describe(HotkeyAction::LoadState), "F7");
+        assert_eq!(
+            hk.action(Scancode::S, Mod::LCTRLMOD),
+            Some(HotkeyAction::SaveState)
+        );
+        assert_eq!(hk.action(Scancode::S, Mod::NOMOD), None);
+        assert_eq!(
+            hk.action(Scancode::F12, Mod::RSHIFTMOD),
+            Some(HotkeyAction::ScreenshotScaled)
+        );
+
+        cfg.0.insert("no_such_action".into(), "F1".into());
+        assert!(Hotkeys::new(&cfg).is_err());
+    }
+}
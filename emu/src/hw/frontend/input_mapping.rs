@@ -0,0 +1,700 @@
+use crate::input::{InputDeviceKind, InputEvent, InputKind, InputManager, StickCalibration};
+
+use imgui::{im_str, Condition, Ui, Window};
+use sdl2;
+use sdl2::controller::{Axis, Button};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Scancode};
+use serde_derive::{Deserialize, Serialize};
+use slog::error;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// Threshold used when a gamepad axis is bound to a digital input.
+const AXIS_DIGITAL_THRESHOLD: i16 = 16384;
+
+// How often the config file is checked for modifications.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize)]
+struct InputDeviceConfig {
+    #[serde(default)]
+    gamepad: usize, // gamepad used for "pad:" bindings (in connection order)
+    #[serde(default)]
+    stick: Option<StickCalibration>, // dead zone and sensitivity of the analog stick
+    mapping: BTreeMap<String, String>, // input name = binding
+}
+
+// Which part of a gamepad axis is bound to an input.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum AxisDir {
+    Full, // the whole axis, for analog inputs
+    Neg,  // negative half, as a digital input
+    Pos,  // positive half, as a digital input
+}
+
+/// A Binding is a physical input (key, gamepad button or axis) bound to an
+/// emulated input. In the config file, bindings are written as strings:
+///
+///   * `Z`, `Return`: a keyboard key (by SDL key name).
+///   * `Left|Right`: a pair of keys driving an analog input (negative and
+///     positive direction).
+///   * `pad:a`, `pad:start`, `pad:dpup`: a gamepad button (by SDL
+///     GameController name).
+///   * `pad:leftx`: a gamepad axis driving an analog input.
+///   * `pad:lefty-`, `pad:triggerleft+`: half of a gamepad axis, used as a
+///     digital input.
+#[derive(Clone, Debug, PartialEq)]
+enum Binding {
+    Key(Scancode),
+    KeyAxis(Scancode, Scancode),
+    PadButton(Button),
+    PadAxis(Axis, AxisDir),
+}
+
+fn parse_key(name: &str) -> Result<Scancode, String> {
+    Keycode::from_name(name)
+        .and_then(Scancode::from_keycode)
+        .ok_or_else(|| format!("unknown key: {:?}", name))
+}
+
+impl Binding {
+    fn parse(s: &str) -> Result<Binding, String> {
+        if s.starts_with("pad:") {
+            let name = &s[4..];
+            if let Some(b) = Button::from_string(name) {
+                return Ok(Binding::PadButton(b));
+            }
+            let (name, dir) = if name.ends_with('-') {
+                (&name[..name.len() - 1], AxisDir::Neg)
+            } else if name.ends_with('+') {
+                (&name[..name.len() - 1], AxisDir::Pos)
+            } else {
+                (name, AxisDir::Full)
+            };
+            return Axis::from_string(name)
+                .map(|a| Binding::PadAxis(a, dir))
+                .ok_or_else(|| format!("unknown gamepad button or axis: {:?}", s));
+        }
+        match s.find('|') {
+            Some(idx) if idx > 0 && idx < s.len() - 1 => Ok(Binding::KeyAxis(
+                parse_key(&s[..idx])?,
+                parse_key(&s[idx + 1..])?,
+            )),
+            _ => Ok(Binding::Key(parse_key(s)?)),
+        }
+    }
+
+    fn to_string(&self) -> String {
+        let key_name = |s: Scancode| Keycode::from_scancode(s).unwrap().name();
+        match self {
+            Binding::Key(s) => key_name(*s),
+            Binding::KeyAxis(n, p) => format!("{}|{}", key_name(*n), key_name(*p)),
+            Binding::PadButton(b) => format!("pad:{}", b.string()),
+            Binding::PadAxis(a, dir) => format!(
+                "pad:{}{}",
+                a.string(),
+                match dir {
+                    AxisDir::Full => "",
+                    AxisDir::Neg => "-",
+                    AxisDir::Pos => "+",
+                }
+            ),
+        }
+    }
+
+    fn is_analog(&self) -> bool {
+        match self {
+            Binding::KeyAxis(_, _) | Binding::PadAxis(_, AxisDir::Full) => true,
+            _ => false,
+        }
+    }
+}
+
+fn default_binding(kind: InputKind) -> Option<&'static str> {
+    use self::InputKind::*;
+    match kind {
+        Start => Some("Return"),
+        Select => Some("Backspace"),
+        Up => Some("Up"),
+        Down => Some("Down"),
+        Left => Some("Left"),
+        Right => Some("Right"),
+        Button1 => Some("Z"),
+        Button2 => Some("X"),
+        Button3 => Some("C"),
+        Button4 => Some("V"),
+        Horizontal => Some("A|D"),
+        Vertical => Some("S|W"),
+        _ => None,
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InputConfig {
+    devices: BTreeMap<String, InputDeviceConfig>, // device name => mapped device
+}
+
+impl InputConfig {
+    pub fn default(im: &InputManager) -> InputConfig {
+        let mut devices = BTreeMap::new();
+        let mut num_joysticks = 0;
+
+        im.visit(|dev| {
+            let mut mapping = BTreeMap::new();
+            let joystick = dev.kind() == InputDeviceKind::Joystick;
+            if joystick && num_joysticks == 0 {
+                // Bind the first input of each kind (eg: if there are two
+                // inputs of kind Up, only the first one is bound by default).
+                let mut used = Vec::new();
+                dev.visit(|inp| {
+                    if used.contains(&inp.kind()) {
+                        return;
+                    }
+                    if let Some(b) = default_binding(inp.kind()) {
+                        mapping.insert(inp.name().to_owned(), b.to_owned());
+                        used.push(inp.kind());
+                    }
+                });
+            }
+
+            // Each joystick is driven by the gamepad with the same index
+            // (for "pad:" bindings), with a default dead zone on its stick.
+            devices.insert(
+                dev.name().to_owned(),
+                InputDeviceConfig {
+                    gamepad: num_joysticks,
+                    stick: if joystick && dev.has_stick() {
+                        Some(StickCalibration::default())
+                    } else {
+                        None
+                    },
+                    mapping: mapping,
+                },
+            );
+            if joystick {
+                num_joysticks += 1;
+            }
+        });
+
+        InputConfig { devices }
+    }
+
+    /// Load an input configuration from a TOML file.
+    pub fn load(path: &Path) -> Result<InputConfig, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("cannot parse {}: {}", path.display(), e))
+    }
+
+    /// Save the input configuration into a TOML file. The directory is
+    /// created if missing (eg: the config directory, on first run).
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)
+                    .map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+            }
+        }
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    }
+
+    // Parse all the bindings, checking that they refer to existing inputs.
+    fn bindings(&self, im: &InputManager) -> Result<Vec<(String, String, Binding)>, String> {
+        let mut res = Vec::new();
+        for (dev_name, d) in self.devices.iter() {
+            let dev = im
+                .device(dev_name)
+                .ok_or_else(|| format!("unknown device: {:?}", dev_name))?;
+            for (inp_name, b) in d.mapping.iter() {
+                let inp = dev
+                    .input(inp_name)
+                    .ok_or_else(|| format!("unknown input: {}/{}", dev_name, inp_name))?;
+                let b = Binding::parse(b)?;
+                if b.is_analog() != inp.analog().is_some() {
+                    return Err(format!(
+                        "invalid binding for {}/{}: {} (analog/digital mismatch)",
+                        dev_name,
+                        inp_name,
+                        b.to_string(),
+                    ));
+                }
+                res.push((dev_name.clone(), inp_name.clone(), b));
+            }
+        }
+        Ok(res)
+    }
+}
+
+// An input being rebound through the UI.
+struct Capture {
+    dev: String,
+    inp: String,
+    analog: bool,
+    first: Option<Scancode>, // first key of a KeyAxis binding
+}
+
+pub struct InputMapping {
+    im: InputManager, // copy of the input manager, used to list inputs
+    cfg: InputConfig,
+    bindings: Vec<(String, String, Binding)>,
+    key_lookup: HashMap<Scancode, Vec<usize>>,
+    keys_down: Vec<Scancode>,
+    pads: Vec<u32>, // instance ids of the connected gamepads
+
+    path: Option<PathBuf>,
+    mtime: Option<SystemTime>,
+    last_check: Instant,
+    capture: Option<Capture>,
+    opened: bool,
+    logger: slog::Logger,
+}
+
+impl InputMapping {
+    pub fn new(cfg: InputConfig, im: &InputManager, logger: slog::Logger) -> Self {
+        let mut map = Self {
+            im: im.clone(),
+            cfg: InputConfig::default(im),
+            bindings: Vec::new(),
+            key_lookup: HashMap::new(),
+            keys_down: Vec::new(),
+            pads: Vec::new(),
+            path: None,
+            mtime: None,
+            last_check: Instant::now(),
+            capture: None,
+            opened: false,
+            logger,
+        };
+        if let Err(err) = map.set_config(cfg) {
+            // Fallback to the default configuration, that is always valid.
+            error!(map.logger, "invalid input config"; "err" => err);
+        }
+        map
+    }
+
+    /// Create an input mapping from the specified config file. If the file
+    /// does not exist, it is created with the default configuration, so
+    /// that it can be edited. The file is reloaded whenever it's modified
+    /// (see [`poll_reload()`](#method.poll_reload)).
+    pub fn with_config_file(
+        path: &Path,
+        im: &InputManager,
+        logger: slog::Logger,
+    ) -> Result<Self, String> {
+        let cfg = if path.exists() {
+            InputConfig::load(path)?
+        } else {
+            let cfg = InputConfig::default(im);
+            cfg.save(path)?;
+            cfg
+        };
+        let mut map = Self::new(InputConfig::default(im), im, logger);
+        map.set_config(cfg)?;
+        map.path = Some(path.to_owned());
+        map.mtime = Self::file_mtime(path);
+        Ok(map)
+    }
+
+    fn file_mtime(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    fn set_config(&mut self, cfg: InputConfig) -> Result<(), String> {
+        let bindings = cfg.bindings(&self.im)?;
+        let mut key_lookup: HashMap<Scancode, Vec<usize>> = HashMap::new();
+        for (idx, (_, _, b)) in bindings.iter().enumerate() {
+            match b {
+                Binding::Key(s) => key_lookup.entry(*s).or_default().push(idx),
+                Binding::KeyAxis(n, p) => {
+                    key_lookup.entry(*n).or_default().push(idx);
+                    key_lookup.entry(*p).or_default().push(idx);
+                }
+                _ => {}
+            }
+        }
+        self.cfg = cfg;
+        self.bindings = bindings;
+        self.key_lookup = key_lookup;
+        Ok(())
+    }
+
+    // Save the current config (after a change through the UI), if it's
+    // backed by a file.
+    fn save(&mut self) -> Result<(), String> {
+        if let Some(path) = self.path.as_ref() {
+            self.cfg.save(path)?;
+            self.mtime = Self::file_mtime(path);
+        }
+        Ok(())
+    }
+
+    /// Check if the config file was modified and, if so, reload it. This is
+    /// meant to be called often (eg: every frame), as the actual check is
+    /// only performed once in a while. Returns an error if the new config
+    /// file is invalid; in this case, the previous config is kept.
+    pub fn poll_reload(&mut self) -> Result<bool, String> {
+        if self.last_check.elapsed() < RELOAD_INTERVAL {
+            return Ok(false);
+        }
+        self.last_check = Instant::now();
+
+        let path = match self.path.as_ref() {
+            Some(path) => path.clone(),
+            None => return Ok(false),
+        };
+        let mtime = Self::file_mtime(&path);
+        if mtime == self.mtime {
+            return Ok(false);
+        }
+        self.mtime = mtime;
+        self.set_config(InputConfig::load(&path)?)?;
+        Ok(true)
+    }
+
+    /// Return the stick calibration configured for each device. These must
+    /// be applied to the input manager of the emulator (through
+    /// `InputManager::set_calibration`) after loading or reloading the
+    /// configuration.
+    pub fn calibrations(&self) -> Vec<(String, Option<StickCalibration>)> {
+        self.cfg
+            .devices
+            .iter()
+            .map(|(name, d)| (name.clone(), d.stick))
+            .collect()
+    }
+
+    /// Compute the force feedback state of each connected gamepad, given a
+    /// function that returns whether an emulated device is rumbling. Only
+    /// devices with at least a gamepad binding are forwarded to their
+    /// gamepad.
+    pub fn rumble_pads<F: Fn(&str) -> bool>(&self, rumbling: F) -> Vec<(usize, bool)> {
+        let mut pads = vec![false; self.pads.len()];
+        for (dev, d) in self.cfg.devices.iter() {
+            let has_pad = self.bindings.iter().any(|(bdev, _, b)| {
+                bdev == dev
+                    && match b {
+                        Binding::PadButton(_) | Binding::PadAxis(_, _) => true,
+                        _ => false,
+                    }
+            });
+            if has_pad && d.gamepad < pads.len() && rumbling(dev) {
+                pads[d.gamepad] = true;
+            }
+        }
+        pads.into_iter().enumerate().collect()
+    }
+
+    /// Register a gamepad that was connected, so that its events can be
+    /// mapped. Gamepads are numbered in connection order.
+    pub fn add_pad(&mut self, instance_id: u32) {
+        if !self.pads.contains(&instance_id) {
+            self.pads.push(instance_id);
+        }
+    }
+
+    pub fn remove_pad(&mut self, instance_id: u32) {
+        self.pads.retain(|id| *id != instance_id);
+    }
+
+    // Return true if the binding at the specified index refers to the
+    // gamepad with the specified instance id.
+    fn pad_matches(&self, idx: usize, which: u32) -> bool {
+        let n = self.cfg.devices[&self.bindings[idx].0].gamepad;
+        self.pads.get(n) == Some(&which)
+    }
+
+    fn key_axis_value(&self, neg: Scancode, pos: Scancode) -> i16 {
+        match (self.keys_down.contains(&neg), self.keys_down.contains(&pos)) {
+            (true, false) => -32767,
+            (false, true) => 32767,
+            _ => 0,
+        }
+    }
+
+    fn map_key(&mut self, scode: Scancode, down: bool) -> Vec<InputEvent> {
+        self.keys_down.retain(|s| *s != scode);
+        if down {
+            self.keys_down.push(scode);
+        }
+
+        let mut evts = Vec::new();
+        for idx in self.key_lookup.get(&scode).into_iter().flatten() {
+            let (dev, inp, b) = &self.bindings[*idx];
+            match b {
+                Binding::Key(_) => evts.push(InputEvent::Digital(dev.clone(), inp.clone(), down)),
+                Binding::KeyAxis(n, p) => evts.push(InputEvent::Analog(
+                    dev.clone(),
+                    inp.clone(),
+                    self.key_axis_value(*n, *p),
+                )),
+                _ => unreachable!(),
+            }
+        }
+        evts
+    }
+
+    fn map_pad_button(&self, which: u32, button: Button, down: bool) -> Vec<InputEvent> {
+        let mut evts = Vec::new();
+        for (idx, (dev, inp, b)) in self.bindings.iter().enumerate() {
+            if *b == Binding::PadButton(button) && self.pad_matches(idx, which) {
+                evts.push(InputEvent::Digital(dev.clone(), inp.clone(), down));
+            }
+        }
+        evts
+    }
+
+    fn map_pad_axis(&self, which: u32, axis: Axis, value: i16) -> Vec<InputEvent> {
+        let mut evts = Vec::new();
+        for (idx, (dev, inp, b)) in self.bindings.iter().enumerate() {
+            let dir = match b {
+                Binding::PadAxis(a, dir) if *a == axis && self.pad_matches(idx, which) => dir,
+                _ => continue,
+            };
+            let (dev, inp) = (dev.clone(), inp.clone());
+            evts.push(match dir {
+                AxisDir::Full => InputEvent::Analog(dev, inp, value),
+                AxisDir::Neg => InputEvent::Digital(dev, inp, value <= -AXIS_DIGITAL_THRESHOLD),
+                AxisDir::Pos => InputEvent::Digital(dev, inp, value >= AXIS_DIGITAL_THRESHOLD),
+            });
+        }
+        evts
+    }
+
+    /// Map a SDL event into the emulated input events it generates (if any).
+    pub fn map_event(&mut self, event: &Event) -> Vec<InputEvent> {
+        if self.capture.is_some() {
+            self.capture_event(event);
+            return Vec::new();
+        }
+
+        match event {
+            Event::KeyDown {
+                scancode: Some(scode),
+                repeat: false,
+                ..
+            } => self.map_key(*scode, true),
+            Event::KeyUp {
+                scancode: Some(scode),
+                ..
+            } => self.map_key(*scode, false),
+            Event::ControllerButtonDown { which, button, .. } => {
+                self.map_pad_button(*which as u32, *button, true)
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                self.map_pad_button(*which as u32, *button, false)
+            }
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => self.map_pad_axis(*which as u32, *axis, *value),
+            _ => Vec::new(),
+        }
+    }
+
+    // Process an event while rebinding an input through the UI.
+    fn capture_event(&mut self, event: &Event) {
+        let cap = self.capture.as_mut().unwrap();
+        let binding = match event {
+            Event::KeyDown {
+                scancode: Some(Scancode::Escape),
+                ..
+            } => {
+                self.capture = None;
+                return;
+            }
+            Event::KeyDown {
+                scancode: Some(scode),
+                repeat: false,
+                ..
+            } => match (cap.analog, cap.first) {
+                (false, _) => Binding::Key(*scode),
+                (true, None) => {
+                    cap.first = Some(*scode);
+                    return;
+                }
+                (true, Some(first)) => Binding::KeyAxis(first, *scode),
+            },
+            Event::ControllerButtonDown { button, .. } if !cap.analog => {
+                Binding::PadButton(*button)
+            }
+            Event::ControllerAxisMotion { axis, value, .. }
+                if value.abs() >= AXIS_DIGITAL_THRESHOLD =>
+            {
+                match (cap.analog, *value < 0) {
+                    (true, _) => Binding::PadAxis(*axis, AxisDir::Full),
+                    (false, true) => Binding::PadAxis(*axis, AxisDir::Neg),
+                    (false, false) => Binding::PadAxis(*axis, AxisDir::Pos),
+                }
+            }
+            _ => return,
+        };
+
+        let cap = self.capture.take().unwrap();
+        self.rebind(&cap.dev, &cap.inp, Some(binding));
+    }
+
+    fn rebind(&mut self, dev: &str, inp: &str, binding: Option<Binding>) {
+        let mut cfg = InputConfig {
+            devices: BTreeMap::new(),
+        };
+        std::mem::swap(&mut cfg, &mut self.cfg);
+        let d = cfg
+            .devices
+            .entry(dev.to_owned())
+            .or_insert_with(|| InputDeviceConfig {
+                gamepad: 0,
+                stick: None,
+                mapping: BTreeMap::new(),
+            });
+        match binding {
+            Some(b) => d.mapping.insert(inp.to_owned(), b.to_string()),
+            None => d.mapping.remove(inp),
+        };
+        // Bindings created through the UI are always valid.
+        self.set_config(cfg).unwrap();
+        if let Err(err) = self.save() {
+            error!(self.logger, "cannot save input config"; "err" => err);
+        }
+    }
+
+    /// Return true if an input is being rebound through the UI. In this
+    /// state, all events are consumed by the input mapping.
+    pub fn capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    /// Open the "Input Config" window (eg: from a menu).
+    pub fn open_window(&mut self) {
+        self.opened = true;
+    }
+
+    /// Render the "Input Config" window, which allows to rebind inputs
+    /// interactively. Changes are immediately saved to the config file.
+    pub fn render_window<'ui>(&mut self, ui: &Ui<'ui>) {
+        if !self.opened {
+            return;
+        }
+        let mut opened = self.opened;
+        let mut changes = Vec::new();
+        let mut capture = None;
+
+        Window::new(im_str!("Input Config"))
+            .size([420.0, 400.0], Condition::FirstUseEver)
+            .opened(&mut opened)
+            .build(ui, || {
+                if let Some(path) = self.path.as_ref() {
+                    ui.text_disabled(format!("Config file: {}", path.display()));
+                }
+                ui.text_disabled(format!("Gamepads connected: {}", self.pads.len()));
+                if let Some(cap) = self.capture.as_ref() {
+                    ui.text_colored(
+                        [1.0, 1.0, 0.0, 1.0],
+                        match (cap.analog, cap.first) {
+                            (false, _) => {
+                                format!("{}/{}: press a key or button...", cap.dev, cap.inp)
+                            }
+                            (true, None) => format!(
+                                "{}/{}: press the negative key, or move an axis...",
+                                cap.dev, cap.inp
+                            ),
+                            (true, Some(_)) => {
+                                format!("{}/{}: press the positive key...", cap.dev, cap.inp)
+                            }
+                        },
+                    );
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("Cancel")) {
+                        capture = Some(None);
+                    }
+                }
+                if ui.small_button(im_str!("Reset to defaults")) {
+                    changes.push(None);
+                }
+                ui.separator();
+
+                self.im.visit(|dev| {
+                    let dev_name = dev.name();
+                    let mapping = self.cfg.devices.get(dev_name).map(|d| &d.mapping);
+                    if !ui
+                        .collapsing_header(&im_str!("{}", dev_name))
+                        .default_open(mapping.map_or(false, |m| !m.is_empty()))
+                        .build()
+                    {
+                        return;
+                    }
+                    dev.visit(|inp| {
+                        let binding = mapping
+                            .and_then(|m| m.get(inp.name()))
+                            .map_or("-", |b| b.as_str());
+                        ui.text(inp.name());
+                        ui.same_line(100.0);
+                        ui.text(binding);
+                        ui.same_line(280.0);
+                        if ui.small_button(&im_str!("Rebind###{}/{}", dev_name, inp.name())) {
+                            capture = Some(Some(Capture {
+                                dev: dev_name.to_owned(),
+                                inp: inp.name().to_owned(),
+                                analog: inp.analog().is_some(),
+                                first: None,
+                            }));
+                        }
+                        ui.same_line(0.0);
+                        if ui.small_button(&im_str!("Clear###{}/{}", dev_name, inp.name())) {
+                            changes.push(Some((dev_name.to_owned(), inp.name().to_owned())));
+                        }
+                    });
+                });
+            });
+
+        self.opened = opened;
+        if let Some(cap) = capture {
+            self.capture = cap;
+        }
+        for change in changes {
+            match change {
+                Some((dev, inp)) => self.rebind(&dev, &inp, None),
+                None => {
+                    self.set_config(InputConfig::default(&self.im)).unwrap();
+                    if let Err(err) = self.save() {
+                        error!(self.logger, "cannot save input config"; "err" => err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binding_syntax() {
+        for s in &[
+            "Z",
+            "Return",
+            "Left|Right",
+            "pad:a",
+            "pad:leftx",
+            "pad:lefty-",
+            "pad:triggerleft+",
+        ] {
+            assert_eq!(Binding::parse(s).unwrap().to_string(), *s);
+        }
+        assert_eq!(
+            Binding::parse("Left|Right"),
+            Ok(Binding::KeyAxis(Scancode::Left, Scancode::Right))
+        );
+        assert_eq!(
+            Binding::parse("pad:lefty-"),
+            Ok(Binding::PadAxis(Axis::LeftY, AxisDir::Neg))
+        );
+        assert!(Binding::parse("pad:leftx").unwrap().is_analog());
+        assert!(!Binding::parse("pad:leftx+").unwrap().is_analog());
+        assert!(Binding::parse("NotAKey").is_err());
+        assert!(Binding::parse("pad:foo").is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use super::output::Video;
+
+use imgui::{im_str, Condition, Ui, Window};
+use imgui_opengl_renderer::Renderer;
+use imgui_sdl2::ImguiSdl2;
+use sdl2::event::Event;
+
+use std::path::PathBuf;
+use std::time::Instant;
+
+/// A Launcher draws a window that lets the user pick the game to run
+/// before the emulator is created (eg: a ROM browser).
+pub trait Launcher {
+    /// Draw the launcher UI. Returns the path of the game to run, as soon as
+    /// the user selected it.
+    fn render(&mut self, ui: &Ui) -> Option<PathBuf>;
+}
+
+// Run the launcher UI until a game is selected, or the window is closed (in
+// which case, None is returned).
+pub(crate) fn run_launcher<L: Launcher>(
+    v: &Video,
+    context: &sdl2::Sdl,
+    launcher: &mut L,
+) -> Result<Option<PathBuf>, String> {
+    let mut imgui = imgui::Context::create();
+    imgui.set_ini_filename(None);
+    let mut imgui_sdl2 = ImguiSdl2::new(&mut imgui, &v.window);
+    let video = v.video.clone();
+    let backend = Renderer::new(&mut imgui, move |s| video.gl_get_proc_address(s) as _);
+
+    let mut event_pump = context.event_pump()?;
+    let mut last_render = Instant::now();
+    loop {
+        for event in event_pump.poll_iter() {
+            imgui_sdl2.handle_event(&mut imgui, &event);
+            if let Event::Quit { .. } = event {
+                return Ok(None);
+            }
+        }
+
+        imgui_sdl2.prepare_frame(imgui.io_mut(), &v.window, &event_pump.mouse_state());
+        let now = Instant::now();
+        let delta = now - last_render;
+        last_render = now;
+        imgui.io_mut().delta_time =
+            delta.as_secs() as f32 + delta.subsec_nanos() as f32 / 1_000_000_000.0;
+
+        let ui = imgui.frame();
+        let (w, h) = v.window.size();
+        let mut selected = None;
+        Window::new(im_str!("Launcher"))
+            .position([0.0, 0.0], Condition::Always)
+            .size([w as f32, h as f32], Condition::Always)
+            .title_bar(false)
+            .resizable(false)
+            .movable(false)
+            .build(&ui, || {
+                selected = launcher.render(&ui);
+            });
+
+        unsafe {
+            gl::ClearColor(0.45, 0.55, 0.60, 0.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+        }
+        backend.render(ui);
+        v.window.gl_swap_window();
+
+        if selected.is_some() {
+            return Ok(selected);
+        }
+    }
+}
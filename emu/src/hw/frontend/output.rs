@@ -0,0 +1,957 @@
+use super::audio::Audio;
+use super::gamepad::Gamepads;
+use super::glutils::{SurfaceRenderer, VideoOptions};
+use super::hotkeys::{HotkeyAction, Hotkeys};
+use super::input_mapping::{InputConfig, InputMapping};
+use super::launcher::{self, Launcher};
+use crate::config::Config;
+use crate::dbg::{DebuggerLayout, DebuggerModel, DebuggerUI, RemoteServer};
+use crate::gfx::{GfxBufferLE, OwnedGfxBufferLE, Rgb888};
+use crate::hw::{
+    osd_message, set_osd_logger, CaptureNamer, Osd, OutputProducer, RecordFormat, Recorder,
+    SpeedControl,
+};
+use crate::input::{InputEvent, InputManager, StickCalibration};
+use crate::log::LogPoolPtr;
+use crate::snd::{OwnedSndBuffer, SampleFormat, SampleInt, SndBuffer};
+
+use byteorder::NativeEndian;
+use sdl2::audio::AudioFormatNum;
+use sdl2::event::Event;
+use sdl2::video::{GLContext, GLProfile, Window};
+use sdl2::VideoSubsystem;
+use slog::{error, o, warn};
+
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone)]
+pub struct VideoConfig {
+    pub window_title: String,
+    pub width: isize,
+    pub height: isize,
+    pub fps: isize,
+}
+
+pub struct AudioConfig {
+    pub frequency: isize,
+}
+
+pub(super) struct Video {
+    pub(super) video: VideoSubsystem,
+    pub(super) window: Window,
+    renderer: SurfaceRenderer,
+    opts: Rc<Cell<VideoOptions>>,
+    osd: Osd,
+    _gl_context: GLContext,
+
+    cfg: Rc<VideoConfig>,
+    fps_clock: Instant,
+    fps_counter: isize,
+    logger: slog::Logger,
+}
+
+impl Video {
+    fn new(
+        cfg: Rc<VideoConfig>,
+        context: &sdl2::Sdl,
+        logger: slog::Logger,
+    ) -> Result<Video, String> {
+        let video = context
+            .video()
+            .or_else(|e| Err(format!("error creating video subsystem: {:?}", e)))?;
+
+        // Request OpenGL Core profile (for GL 3.2 extensions, required by imgui-opengl-renderer).
+        {
+            let gl_attr = video.gl_attr();
+            gl_attr.set_context_profile(GLProfile::Core);
+            gl_attr.set_context_version(3, 0);
+        }
+
+        let window = video
+            .window(&cfg.window_title, 640 * 2, 480 * 2)
+            .resizable()
+            .position_centered()
+            .opengl()
+            .allow_highdpi()
+            .build()
+            .or_else(|e| Err(format!("error creating window: {:?}", e)))?;
+
+        let gl_context = window
+            .gl_create_context()
+            .expect("couldn't create GL context");
+
+        let video2 = video.clone();
+        let renderer = SurfaceRenderer::new(move |s| video2.gl_get_proc_address(s) as _);
+
+        Ok(Video {
+            osd: Osd::new(cfg.fps),
+            cfg,
+            video,
+            window,
+            renderer,
+            opts: Rc::new(Cell::new(VideoOptions::default())),
+            _gl_context: gl_context,
+            fps_clock: Instant::now(),
+            fps_counter: 0,
+            logger,
+        })
+    }
+
+    // Render a frame into the window. If the producer drew it at a higher
+    // resolution on the GPU, its texture is displayed instead of the buffer.
+    fn render_frame(&mut self, frame: &GfxBufferLE<Rgb888>, upscaled: Option<u32>) {
+        match self.renderer.poll_reload() {
+            Ok(true) => osd_message("Shader reloaded"),
+            Ok(false) => {}
+            Err(err) => error!(self.logger, "cannot reload shader"; "err" => err),
+        }
+        let (w, h) = self.window.drawable_size();
+        let win = (w as usize, h as usize);
+        match upscaled {
+            Some(tex) => {
+                let size = (frame.width(), frame.height());
+                self.renderer
+                    .render_texture(tex, size, &self.opts.get(), win)
+            }
+            None => self.renderer.render(frame, &self.opts.get(), win),
+        }
+    }
+
+    // Show the frame rendered into the window. The on-screen display is
+    // drawn over it, unless hidden (eg: within the debugger, which has its
+    // own UI for messages); messages expire even while it is hidden.
+    fn swap_window(&mut self, show_osd: bool) {
+        let (w, h) = self.window.drawable_size();
+        let win = (w as usize, h as usize);
+        self.osd.show_stats = self.opts.get().osd_stats;
+        if let Some(overlay) = self.osd.render(win) {
+            if show_osd {
+                self.renderer.render_overlay(&overlay.buf(), win);
+            }
+        }
+        self.window.gl_swap_window();
+        self.osd.frame_displayed();
+    }
+
+    // Save a screenshot of the current frame: either the emulator
+    // framebuffer at its native resolution, or the window contents as
+    // rendered (after scaling and post-processing). Must be called before
+    // swapping the window.
+    fn screenshot(
+        &self,
+        namer: &CaptureNamer,
+        frame: &GfxBufferLE<Rgb888>,
+        scaled: bool,
+    ) -> Result<PathBuf, String> {
+        if scaled {
+            let path = namer.next_path("-scaled", "png");
+            let (w, h) = self.window.drawable_size();
+            let (w, h) = (w as usize, h as usize);
+            capture::save_png(&self.renderer.read_pixels((w, h)), w, h, &path)?;
+            Ok(path)
+        } else {
+            let path = namer.next_path("", "png");
+            screenshot(frame, &path)?;
+            Ok(path)
+        }
+    }
+
+    fn update_fps(&mut self) {
+        self.fps_counter += 1;
+        if self.fps_clock.elapsed() >= Duration::new(1, 0) {
+            self.window
+                .set_title(&format!(
+                    "{} - {} FPS",
+                    &self.cfg.window_title, self.fps_counter
+                ))
+                .unwrap();
+            self.fps_counter = 0;
+            self.fps_clock += Duration::new(1, 0);
+        }
+    }
+}
+
+// A savestate operation requested through a hotkey.
+#[derive(Copy, Clone, Debug)]
+enum StateCommand {
+    Save,
+    Load,
+}
+
+impl StateCommand {
+    // Apply the command, and notify the user of the result through the
+    // on-screen display.
+    fn apply<P: OutputProducer>(self, producer: &mut P) -> Result<(), String> {
+        let res = match self {
+            StateCommand::Save => producer.save_state(),
+            StateCommand::Load => producer.load_state(),
+        };
+        match (self, &res) {
+            (StateCommand::Save, Ok(())) => osd_message("State saved"),
+            (StateCommand::Load, Ok(())) => osd_message("State loaded"),
+            (_, Err(err)) => osd_message(err.clone()),
+        }
+        res
+    }
+}
+
+// A screenshot requested through a hotkey: either at native resolution, or
+// of the scaled output.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ShotCommand {
+    Native,
+    Scaled,
+}
+
+// Add a frame to the video recording (if any). If the recording fails, it
+// is stopped.
+fn record_frame<SF: SampleFormat>(
+    rec: &mut Option<Recorder>,
+    video: &GfxBufferLE<Rgb888>,
+    audio: &SndBuffer<SF>,
+) -> Result<(), String> {
+    if let Some(r) = rec.as_mut() {
+        if let Err(err) = r.add_frame(video, audio) {
+            let _ = rec.take().unwrap().finish();
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+// Emulation pause and frame advance, requested through hotkeys. The
+// emulation is always paused at a frame boundary.
+#[derive(Copy, Clone, Debug)]
+enum RunCommand {
+    Pause(bool),
+    FrameAdvance,
+}
+
+pub struct Output {
+    vcfg: Rc<VideoConfig>,
+    acfg: Rc<AudioConfig>,
+    context: sdl2::Sdl,
+    video: Option<Video>,
+    audio: bool,
+    debug: bool,
+    quit: bool,
+    state_cmd: Option<StateCommand>,
+    run_cmd: Option<RunCommand>,
+    shot_cmd: Option<ShotCommand>,
+    shots: CaptureNamer,
+    recorder: Option<Recorder>,
+    rec_toggle: bool,
+    paused: bool,
+    speed: SpeedControl,
+    framecount: i64,
+    input_cfg: Option<PathBuf>,
+    hotkeys: Rc<RefCell<Hotkeys>>,
+    dbg_layout: DebuggerLayout,
+    remote: Option<RemoteServer>,
+    logger: slog::Logger,
+}
+
+impl Output {
+    /// Create the output. Errors of the frontend (eg: while recording) are
+    /// reported through `logger`, which also receives a copy of the
+    /// on-screen messages.
+    pub fn new(
+        vcfg: VideoConfig,
+        acfg: AudioConfig,
+        logger: slog::Logger,
+    ) -> Result<Output, String> {
+        set_osd_logger(logger.clone());
+        Ok(Output {
+            vcfg: Rc::new(vcfg),
+            acfg: Rc::new(acfg),
+            context: sdl2::init()?,
+            video: None,
+            audio: false,
+            debug: true,
+            quit: false,
+            state_cmd: None,
+            run_cmd: None,
+            shot_cmd: None,
+            shots: CaptureNamer::new(Path::new("."), "screenshot"),
+            recorder: None,
+            rec_toggle: false,
+            paused: false,
+            speed: SpeedControl::new(),
+            framecount: 0,
+            input_cfg: None,
+            hotkeys: Rc::new(RefCell::new(Hotkeys::default())),
+            dbg_layout: DebuggerLayout::default(),
+            remote: None,
+            logger,
+        })
+    }
+
+    /// Apply the settings persisted in the config file: the video options
+    /// (video must be enabled), the hotkeys and the layout of the debugger
+    /// windows.
+    pub fn load_config(&mut self, config: &Config) -> Result<(), String> {
+        if let Some(v) = self.video.as_ref() {
+            v.opts.set(config.get()?);
+        }
+        *self.hotkeys.borrow_mut() = Hotkeys::new(&config.get()?)?;
+        self.dbg_layout = config.get()?;
+        Ok(())
+    }
+
+    /// Store the current settings into the config file, including those
+    /// changed at runtime from the debugger. The file itself is not saved.
+    pub fn save_config(&self, config: &mut Config) -> Result<(), String> {
+        if let Some(v) = self.video.as_ref() {
+            config.set(&v.opts.get())?;
+        }
+        config.set(&self.hotkeys.borrow().config())?;
+        config.set(&self.dbg_layout)
+    }
+
+    /// Load the input mapping from the specified TOML file (which is created
+    /// with a default mapping if it does not exist). The file is reloaded
+    /// whenever it's modified while the emulator is running.
+    pub fn set_input_config(&mut self, path: &Path) {
+        self.input_cfg = Some(path.to_owned());
+    }
+
+    /// Serve a remote debugger (see RemoteServer) while running under the
+    /// debugger UI.
+    pub fn set_remote_debugger(&mut self, server: RemoteServer) {
+        self.remote = Some(server);
+    }
+
+    fn input_mapping(&self, im: &InputManager) -> Result<InputMapping, String> {
+        match self.input_cfg.as_ref() {
+            Some(path) => InputMapping::with_config_file(path, im, self.logger.new(o!())),
+            None => Ok(InputMapping::new(
+                InputConfig::default(im),
+                im,
+                self.logger.new(o!()),
+            )),
+        }
+    }
+
+    /// Configure where screenshots taken with F12 are saved: `dir` is the
+    /// destination directory, and `name` (usually the name of the game) is
+    /// used as filename prefix, followed by a timestamp.
+    pub fn set_screenshot_dir(&mut self, dir: &Path, name: &str) {
+        self.shots = CaptureNamer::new(dir, name);
+    }
+
+    /// Start recording the emulator output (video and audio) into the
+    /// specified path. The format is selected through the extension: video
+    /// files (.mkv, .avi, .mov) are encoded losslessly by ffmpeg, which must
+    /// be installed; otherwise, `path` is a directory in which a PNG file
+    /// per frame and a WAV file are written. Recording can also be toggled
+    /// with F10.
+    pub fn start_video_recording(&mut self, path: &Path) -> Result<(), String> {
+        self.stop_video_recording()?;
+        self.recorder = Some(Recorder::start(
+            path,
+            RecordFormat::from_path(path),
+            (self.vcfg.width as usize, self.vcfg.height as usize),
+            self.vcfg.fps,
+            self.acfg.frequency,
+        )?);
+        Ok(())
+    }
+
+    /// Stop the current video recording (if any), and finalize it.
+    pub fn stop_video_recording(&mut self) -> Result<(), String> {
+        match self.recorder.take() {
+            Some(rec) => rec.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // Start or stop a recording through the hotkey. New recordings are named
+    // like screenshots, and use ffmpeg if available. Returns the message to
+    // show to the user.
+    fn toggle_video_recording(&mut self) -> Result<String, String> {
+        if let Some(rec) = self.recorder.take() {
+            let path = rec.path().to_owned();
+            rec.finish()?;
+            return Ok(format!("Recording saved: {}", path.display()));
+        }
+        let ext = if recorder::ffmpeg_available() {
+            "mkv"
+        } else {
+            ""
+        };
+        let path = self.shots.next_path("", ext);
+        self.start_video_recording(&path)?;
+        Ok(format!("Recording started: {}", path.display()))
+    }
+
+    /// Return a handle to control the emulation speed (fast-forward, turbo
+    /// and slow motion), which are also bound to hotkeys.
+    pub fn speed_control(&self) -> SpeedControl {
+        self.speed.clone()
+    }
+
+    /// Change the number of frames per second at which the emulation is
+    /// paced (eg: 50 for PAL games). Must be called before running the
+    /// emulation and starting a recording.
+    pub fn set_fps(&mut self, fps: isize) {
+        Rc::make_mut(&mut self.vcfg).fps = fps;
+        if let Some(v) = self.video.as_mut() {
+            v.cfg = self.vcfg.clone();
+            v.osd = Osd::new(fps);
+        }
+    }
+
+    pub fn enable_video(&mut self) -> Result<(), String> {
+        self.video = Some(Video::new(
+            self.vcfg.clone(),
+            &self.context,
+            self.logger.new(o!()),
+        )?);
+        Ok(())
+    }
+
+    /// Change the options used to render the emulator output (scaling,
+    /// filtering, scanlines). They can also be changed at runtime from the
+    /// "Video" menu of the debugger. Video must be enabled.
+    pub fn set_video_options(&mut self, opts: VideoOptions) {
+        if let Some(v) = self.video.as_ref() {
+            v.opts.set(opts);
+        }
+    }
+
+    /// Return the options currently used to render the emulator output.
+    pub fn video_options(&self) -> VideoOptions {
+        self.video
+            .as_ref()
+            .map_or(VideoOptions::default(), |v| v.opts.get())
+    }
+
+    /// Load an external GLSL post-processing shader, applied to the emulator
+    /// output, and reloaded whenever the file is modified. See
+    /// `SurfaceRenderer` for the shader interface. Video must be enabled.
+    pub fn set_shader(&mut self, path: &Path) -> Result<(), String> {
+        match self.video.as_mut() {
+            Some(v) => v.renderer.load_shader(path),
+            None => Err("video is not enabled".into()),
+        }
+    }
+
+    /// Show a launcher (eg: a ROM browser) in the output window, until the
+    /// user selects a game. Returns None if the window was closed instead.
+    /// Video must be enabled.
+    pub fn run_launcher<L: Launcher>(
+        &mut self,
+        launcher: &mut L,
+    ) -> Result<Option<PathBuf>, String> {
+        let v = self.video.as_ref().ok_or("video is not enabled")?;
+        let res = launcher::run_launcher(v, &self.context, launcher)?;
+        if res.is_none() {
+            self.quit = true;
+        }
+        Ok(res)
+    }
+
+    pub fn enable_audio(&mut self) -> Result<(), String> {
+        self.audio = true;
+        Ok(())
+    }
+
+    fn process_event(&mut self, event: &Event) {
+        let action = match event {
+            Event::KeyDown {
+                scancode: Some(scode),
+                keymod,
+                repeat,
+                ..
+            } => match self.hotkeys.borrow().action(*scode, *keymod) {
+                // Frame advance and fast forward can be held down.
+                Some(action @ HotkeyAction::FrameAdvance)
+                | Some(action @ HotkeyAction::FastForward) => action,
+                Some(action) if !*repeat => action,
+                _ => return,
+            },
+            Event::KeyUp {
+                scancode: Some(scode),
+                ..
+            } => {
+                if self
+                    .hotkeys
+                    .borrow()
+                    .released(HotkeyAction::FastForward, *scode)
+                {
+                    self.speed.set_fast_forward(false);
+                }
+                return;
+            }
+            Event::Quit { .. } => {
+                self.quit = true;
+                return;
+            }
+            _ => return,
+        };
+
+        match action {
+            // Toggle debugger activation
+            HotkeyAction::ToggleDebugger => self.debug = !self.debug,
+            HotkeyAction::SaveState => self.state_cmd = Some(StateCommand::Save),
+            HotkeyAction::LoadState => self.state_cmd = Some(StateCommand::Load),
+            HotkeyAction::Screenshot => self.shot_cmd = Some(ShotCommand::Native),
+            HotkeyAction::ScreenshotScaled => self.shot_cmd = Some(ShotCommand::Scaled),
+            HotkeyAction::ToggleRecording => self.rec_toggle = true,
+            HotkeyAction::ToggleOsd => {
+                if let Some(v) = self.video.as_ref() {
+                    let mut opts = v.opts.get();
+                    opts.osd_stats = !opts.osd_stats;
+                    v.opts.set(opts);
+                }
+            }
+
+            // Within the debugger, pause and frame advance are handled by
+            // the debugger itself.
+            HotkeyAction::Pause if !self.debug => {
+                self.paused = !self.paused;
+                self.run_cmd = Some(RunCommand::Pause(self.paused));
+            }
+            HotkeyAction::FrameAdvance if !self.debug => {
+                self.paused = true;
+                self.run_cmd = Some(RunCommand::FrameAdvance);
+            }
+            HotkeyAction::FastForward if !self.debug => self.speed.set_fast_forward(true),
+            HotkeyAction::Turbo if !self.debug => self.speed.set_turbo(!self.speed.turbo()),
+            HotkeyAction::SlowMotion if !self.debug => {
+                self.speed.set_slow_motion(!self.speed.slow_motion())
+            }
+            _ => {}
+        }
+    }
+
+    pub fn run_and_debug<SI, SF, P>(
+        &mut self,
+        producer: &mut P,
+        dbg_conf_filename: &Path,
+        logpool: LogPoolPtr,
+    ) where
+        SI: SampleInt + AudioFormatNum,
+        SF: SampleFormat<SAMPLE = SI, ORDER = NativeEndian>,
+        P: OutputProducer<AudioSampleFormat = SF> + DebuggerModel,
+    {
+        let width = self.vcfg.width as usize;
+        let height = self.vcfg.height as usize;
+        assert_eq!(self.video.is_some(), true); // TODO: debugger could work without video as well
+
+        let video = self.video.as_ref().unwrap();
+        let mut dbg_ui = DebuggerUI::new(video.video.clone(), &video.window, producer, logpool);
+        if dbg_conf_filename.exists() {
+            dbg_ui.load_conf(dbg_conf_filename);
+        }
+        dbg_ui.load_layout(&self.dbg_layout);
+
+        let mut audio =
+            Audio::<SI, SF>::new(&self.context, self.vcfg.fps, self.acfg.frequency).unwrap();
+        dbg_ui.set_audio_control(audio.control());
+        dbg_ui.set_speed_control(self.speed.clone());
+        dbg_ui.set_video_options(video.opts.clone());
+        dbg_ui.set_hotkeys(self.hotkeys.clone());
+        if let Some(server) = self.remote.take() {
+            dbg_ui.set_remote_server(server);
+        }
+        let mut audio_buf = OwnedSndBuffer::with_capacity(audio.samples_per_frame());
+
+        let mut event_pump = self.context.event_pump().unwrap();
+        let mut screen = OwnedGfxBufferLE::<Rgb888>::new(width, height);
+
+        let mut gamepads = Gamepads::new(&self.context, self.logger.new(o!())).unwrap();
+        let input = match producer.input_manager() {
+            Some(im) => {
+                let map = self.input_mapping(im).unwrap_or_else(|err| {
+                    dbg_ui.show_error(format!("Cannot load input config: {}", err));
+                    InputMapping::new(InputConfig::default(im), im, self.logger.new(o!()))
+                });
+                for (name, cal) in map.calibrations() {
+                    im.set_calibration(&name, cal);
+                }
+                let map = Rc::new(RefCell::new(map));
+                dbg_ui.set_input_mapping(map.clone());
+                Some(map)
+            }
+            None => None,
+        };
+
+        while !self.quit {
+            for event in event_pump.poll_iter() {
+                if self.hotkeys.borrow().capturing() {
+                    // Keys pressed while rebinding a hotkey must not
+                    // trigger any other action.
+                    match event {
+                        Event::KeyDown { .. } | Event::KeyUp { .. } => {
+                            self.hotkeys.borrow_mut().capture_event(&event);
+                            continue;
+                        }
+                        _ => {}
+                    }
+                }
+                dbg_ui.handle_event(&event);
+
+                if let Some(map) = input.as_ref() {
+                    let mut map = map.borrow_mut();
+                    gamepads.handle_event(&event, &mut map);
+                    if map.capturing() {
+                        // Keys pressed while rebinding an input must not
+                        // trigger any other action.
+                        map.map_event(&event);
+                        continue;
+                    }
+                    if let Some(im) = producer.input_manager() {
+                        for evt in map.map_event(&event) {
+                            im.process_event(evt);
+                        }
+                    }
+                }
+                self.process_event(&event);
+            }
+
+            if let Some(map) = input.as_ref() {
+                let mut map = map.borrow_mut();
+                match map.poll_reload() {
+                    Ok(true) => {
+                        if let Some(im) = producer.input_manager() {
+                            for (name, cal) in map.calibrations() {
+                                im.set_calibration(&name, cal);
+                            }
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(err) => dbg_ui.show_error(format!("Cannot reload input config: {}", err)),
+                }
+                if let Some(im) = producer.input_manager() {
+                    gamepads
+                        .update_rumble(&map, |dev| im.device(dev).map_or(false, |d| d.rumble()));
+                }
+            }
+
+            if let Some(cmd) = self.state_cmd.take() {
+                if let Err(err) = cmd.apply(producer) {
+                    dbg_ui.show_error(err);
+                }
+            }
+            if self.rec_toggle {
+                self.rec_toggle = false;
+                match self.toggle_video_recording() {
+                    Ok(msg) => {
+                        dbg_ui.show_message(&msg);
+                        osd_message(msg);
+                    }
+                    Err(err) => dbg_ui.show_error(err),
+                }
+            }
+
+            // Run a frame, unless paused (in which case, the last frame is
+            // displayed again).
+            let run = match self.run_cmd.take() {
+                Some(RunCommand::FrameAdvance) => true,
+                _ => !self.paused,
+            };
+
+            let v = self.video.as_mut().unwrap();
+            producer.set_upscale(v.opts.get().upscale);
+            if !self.debug {
+                if run {
+                    producer.render_frame(&mut screen.buf_mut(), &mut audio_buf.buf_mut());
+                    v.osd.frame_emulated();
+                    if let Err(err) =
+                        record_frame(&mut self.recorder, &screen.buf(), &audio_buf.buf())
+                    {
+                        dbg_ui.show_error(err);
+                    }
+                    audio.render_frame_at_speed(
+                        &audio_buf.buf(),
+                        self.speed.speed(),
+                        self.speed.slow_audio(),
+                    );
+                    v.update_fps();
+                }
+                v.render_frame(&screen.buf(), producer.upscaled_frame());
+            } else {
+                if dbg_ui.trace(producer, &mut screen.buf_mut(), &mut audio_buf.buf_mut()) {
+                    v.osd.frame_emulated();
+                    if let Err(err) =
+                        record_frame(&mut self.recorder, &screen.buf(), &audio_buf.buf())
+                    {
+                        dbg_ui.show_error(err);
+                    }
+                    v.update_fps();
+                }
+                dbg_ui.render(&v.window, &event_pump, producer);
+            }
+
+            // Within the debugger, the window contains the debugger UI, so
+            // only native screenshots are available.
+            if let Some(cmd) = self.shot_cmd.take() {
+                let scaled = !self.debug && cmd == ShotCommand::Scaled;
+                match v.screenshot(&self.shots, &screen.buf(), scaled) {
+                    Ok(path) => {
+                        let msg = format!("Screenshot saved: {}", path.display());
+                        dbg_ui.show_message(&msg);
+                        osd_message(msg);
+                    }
+                    Err(err) => dbg_ui.show_error(err),
+                }
+            }
+
+            v.swap_window(!self.debug);
+
+            self.framecount += 1;
+        }
+
+        if let Err(err) = self.stop_video_recording() {
+            error!(self.logger, "cannot stop video recording"; "err" => err);
+        }
+        dbg_ui.save_conf(dbg_conf_filename);
+        self.dbg_layout = dbg_ui.save_layout();
+        let profile_filename = dbg_conf_filename.with_extension("profile.json");
+        if let Err(err) = dbg_ui.save_profile(&profile_filename) {
+            error!(self.logger, "cannot save profile"; "file" => profile_filename.display().to_string(), "err" => err);
+        }
+        let coverage_filename = dbg_conf_filename.with_extension("coverage.txt");
+        if let Err(err) = dbg_ui.save_coverage(&coverage_filename) {
+            error!(self.logger, "cannot save coverage"; "file" => coverage_filename.display().to_string(), "err" => err);
+        }
+    }
+
+    /// Run a blocking loop in which output is produced by a OutputProducer,
+    /// until the producer exits by itself, or the user closes the window.
+    /// The OutputProducer is run in a background thread, so to parallelize
+    /// display visualization and vsync with actual output generation.
+    ///
+    /// create is a FnOnce callback that creates a OutputProducer, and is invoked
+    /// in the background thread so that OutputProducer needs not to implement
+    /// Send.
+    pub fn run_threaded<F, P, SI, SF>(&mut self, create: F)
+    where
+        SI: SampleInt + AudioFormatNum,
+        SF: SampleFormat<SAMPLE = SI, ORDER = NativeEndian>,
+        P: OutputProducer<AudioSampleFormat = SF>,
+        F: FnOnce() -> Result<Box<P>, String> + Send + 'static,
+    {
+        let width = self.vcfg.width as usize;
+        let height = self.vcfg.height as usize;
+        let (tx_frame, rx_frame) = mpsc::sync_channel(3);
+        let (tx_event, rx_event) = mpsc::sync_channel::<Vec<InputEvent>>(3);
+        let (tx_input, rx_input) = mpsc::sync_channel(1);
+        let (tx_calib, rx_calib) = mpsc::channel::<Vec<(String, Option<StickCalibration>)>>();
+        let (tx_state, rx_state) = mpsc::channel::<StateCommand>();
+        let (tx_run, rx_run) = mpsc::channel::<RunCommand>();
+
+        // The debugger is not available in threaded mode.
+        self.debug = false;
+
+        let mut audio =
+            Audio::<SI, SF>::new(&self.context, self.vcfg.fps, self.acfg.frequency).unwrap();
+        let audio_frame_size = audio.samples_per_frame();
+
+        let mut event_pump = self.context.event_pump().unwrap();
+        let mut gamepads = Gamepads::new(&self.context, self.logger.new(o!())).unwrap();
+
+        thread::spawn(move || {
+            let mut producer = create().unwrap();
+
+            // Send a clone of the input manager to the main thread,
+            // for input mapping initialization.
+            tx_input.send(producer.input_manager().map(|im| im.clone()));
+
+            let mut paused = false;
+            loop {
+                // Pause is only checked between frames, so that the emulation
+                // always stops at a frame boundary.
+                let mut advance = false;
+                while let Ok(cmd) = rx_run.try_recv() {
+                    match cmd {
+                        RunCommand::Pause(p) => paused = p,
+                        RunCommand::FrameAdvance => {
+                            paused = true;
+                            advance = true;
+                        }
+                    }
+                }
+
+                if !paused || advance {
+                    let mut sound = OwnedSndBuffer::with_capacity(audio_frame_size);
+                    let mut screen = OwnedGfxBufferLE::<Rgb888>::new(width, height);
+                    producer.render_frame(&mut screen.buf_mut(), &mut sound.buf_mut());
+
+                    // Send the list of emulated devices requesting force
+                    // feedback along with the frame.
+                    let mut rumble = Vec::new();
+                    if let Some(im) = producer.input_manager() {
+                        im.visit(|dev| {
+                            if dev.rumble() {
+                                rumble.push(dev.name().to_owned());
+                            }
+                        });
+                    }
+
+                    if !tx_frame.send((screen, sound, rumble)).is_ok() {
+                        return;
+                    }
+                } else {
+                    thread::sleep(Duration::from_millis(10));
+                }
+
+                // Apply savestate commands between frames. Errors are
+                // already logged by the producer.
+                while let Ok(cmd) = rx_state.try_recv() {
+                    let _ = cmd.apply(&mut *producer);
+                }
+
+                // If we received any input event from the main thread, process
+                // them through the input manager.
+                if let Ok(evts) = rx_event.try_recv() {
+                    if let Some(im) = producer.input_manager() {
+                        for e in evts.iter() {
+                            im.process_event(e.clone());
+                        }
+                    }
+                }
+                while let Ok(cals) = rx_calib.try_recv() {
+                    if let Some(im) = producer.input_manager() {
+                        for (name, cal) in cals {
+                            im.set_calibration(&name, cal);
+                        }
+                    }
+                }
+            }
+        });
+
+        // Initialize input mapping, using the configured file (if any), or
+        // the default config for the current input manager.
+        let mut input = match rx_input.recv() {
+            Ok(Some(im)) => Some(self.input_mapping(&im).unwrap_or_else(|err| {
+                error!(self.logger, "cannot load input config"; "err" => err);
+                InputMapping::new(InputConfig::default(&im), &im, self.logger.new(o!()))
+            })),
+            Ok(None) => None,
+            Err(_) => panic!("error while receiving input manager?"),
+        };
+        if let Some(map) = input.as_ref() {
+            let _ = tx_calib.send(map.calibrations());
+        }
+
+        let polling_interval = Duration::from_millis(20);
+        while !self.quit {
+            let mut events = Vec::new();
+            for event in event_pump.poll_iter() {
+                self.process_event(&event);
+
+                // Try to pass the even through the input mapping.
+                // If it's mapped to an emulator input, accumulate
+                // to send it
+                if let Some(map) = input.as_mut() {
+                    gamepads.handle_event(&event, map);
+                    events.extend(map.map_event(&event));
+                }
+            }
+            if let Some(map) = input.as_mut() {
+                match map.poll_reload() {
+                    Ok(true) => {
+                        let _ = tx_calib.send(map.calibrations());
+                    }
+                    Ok(false) => {}
+                    Err(err) => warn!(self.logger, "cannot reload input config"; "err" => err),
+                }
+            }
+            if events.len() > 0 {
+                tx_event.send(events);
+            }
+            if let Some(cmd) = self.state_cmd.take() {
+                let _ = tx_state.send(cmd);
+            }
+            if let Some(cmd) = self.run_cmd.take() {
+                let _ = tx_run.send(cmd);
+            }
+            if self.rec_toggle {
+                self.rec_toggle = false;
+                match self.toggle_video_recording() {
+                    Ok(msg) => osd_message(msg),
+                    Err(err) => osd_message(err),
+                }
+            }
+
+            match rx_frame.recv_timeout(polling_interval) {
+                Ok(mut frame) => {
+                    let speed = self.speed.speed();
+                    if let Some(v) = self.video.as_mut() {
+                        v.osd.frame_emulated();
+                    }
+                    audio.render_frame_at_speed(&frame.1.buf(), speed, self.speed.slow_audio());
+                    if let Err(err) =
+                        record_frame(&mut self.recorder, &frame.0.buf(), &frame.1.buf())
+                    {
+                        error!(self.logger, "cannot record frame"; "err" => err);
+                    }
+
+                    // When running faster than the nominal speed, only show
+                    // the last of the available frames, so that video output
+                    // (and vsync) does not slow down emulation.
+                    if speed.map_or(true, |s| s > 1.0) {
+                        while let Ok(next) = rx_frame.try_recv() {
+                            if let Some(v) = self.video.as_mut() {
+                                v.osd.frame_emulated();
+                            }
+                            audio.render_frame_at_speed(
+                                &next.1.buf(),
+                                speed,
+                                self.speed.slow_audio(),
+                            );
+                            if let Err(err) =
+                                record_frame(&mut self.recorder, &next.0.buf(), &next.1.buf())
+                            {
+                                error!(self.logger, "cannot record frame"; "err" => err);
+                            }
+                            frame = next;
+                        }
+                    }
+
+                    let (ref screen, _, ref rumble) = frame;
+                    if let Some(v) = self.video.as_mut() {
+                        v.render_frame(&screen.buf(), None);
+                        if let Some(cmd) = self.shot_cmd.take() {
+                            let scaled = cmd == ShotCommand::Scaled;
+                            match v.screenshot(&self.shots, &screen.buf(), scaled) {
+                                Ok(path) => {
+                                    osd_message(format!("Screenshot saved: {}", path.display()))
+                                }
+                                Err(err) => osd_message(err),
+                            }
+                        }
+                        v.swap_window(true);
+                        v.update_fps();
+                    }
+                    if let Some(map) = input.as_ref() {
+                        gamepads.update_rumble(map, |dev| rumble.iter().any(|r| r == dev));
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+            }
+        }
+
+        if let Err(err) = self.stop_video_recording() {
+            error!(self.logger, "cannot stop video recording"; "err" => err);
+        }
+    }
+
+    /// Render a single frame to the video output.
+    pub fn render_frame(&mut self, screen: &GfxBufferLE<Rgb888>) {
+        if let Some(v) = self.video.as_mut() {
+            v.render_frame(&screen, None);
+            v.osd.frame_emulated();
+            v.swap_window(true);
+            v.update_fps();
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use super::capture::screenshot;
+use super::OutputProducer;
+use crate::gfx::{BufferLineGetter, GfxBufferLE, OwnedGfxBufferLE, Rgb888};
+use crate::snd::OwnedSndBuffer;
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Configuration of a headless run.
+pub struct HeadlessConfig {
+    /// Size of the framebuffer passed to the producer.
+    pub width: usize,
+    pub height: usize,
+    /// Number of audio frames (samples per channel) produced in each video
+    /// frame.
+    pub audio_frames: usize,
+    /// Number of frames to run.
+    pub frames: usize,
+    /// If set, each frame is saved as PNG into this directory.
+    pub png_dir: Option<PathBuf>,
+    /// If true, the hash of each frame is printed on stdout.
+    pub print_hashes: bool,
+}
+
+/// Compute a hash of the contents of a framebuffer (64-bit FNV-1a), which is
+/// stable across runs and platforms, so that it can be compared with
+/// reference values in automated tests.
+pub fn frame_hash(buf: &GfxBufferLE<Rgb888>) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for y in 0..buf.height() {
+        let line = buf.line(y);
+        for x in 0..buf.width() {
+            let (r, g, b, _) = line.get(x).components();
+            for c in [r, g, b].iter() {
+                hash ^= *c as u64;
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        }
+    }
+    hash
+}
+
+/// Run the producer for a fixed number of frames, without any window or
+/// audio device (SDL is never initialized), for CI and automated testing.
+/// Frames are produced in memory, and optionally saved as PNG. Returns the
+/// hash of each frame (see [`frame_hash`](fn.frame_hash.html)).
+pub fn run_headless<P: OutputProducer>(
+    producer: &mut P,
+    cfg: &HeadlessConfig,
+) -> Result<Vec<u64>, String> {
+    if let Some(dir) = cfg.png_dir.as_ref() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("cannot create directory {}: {}", dir.display(), e))?;
+    }
+
+    let mut screen = OwnedGfxBufferLE::<Rgb888>::new(cfg.width, cfg.height);
+    let mut sound = OwnedSndBuffer::with_capacity(cfg.audio_frames);
+    let mut hashes = Vec::with_capacity(cfg.frames);
+    for i in 0..cfg.frames {
+        producer.render_frame(&mut screen.buf_mut(), &mut sound.buf_mut());
+
+        let hash = frame_hash(&screen.buf());
+        if cfg.print_hashes {
+            println!("frame {}: {:016x}", i, hash);
+        }
+        if let Some(dir) = cfg.png_dir.as_ref() {
+            screenshot(&screen.buf(), &dir.join(format!("frame{:06}.png", i)))?;
+        }
+        hashes.push(hash);
+    }
+    Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::{BufferLineSetter, Color, GfxBufferMutLE};
+    use crate::input::InputManager;
+    use crate::snd::{SndBufferMut, S16_STEREO};
+
+    // Draw a pixel whose position changes every frame.
+    struct Dots {
+        frame: usize,
+    }
+
+    impl OutputProducer for Dots {
+        type AudioSampleFormat = S16_STEREO;
+
+        fn input_manager(&mut self) -> Option<&mut InputManager> {
+            None
+        }
+
+        fn render_frame(
+            &mut self,
+            video: &mut GfxBufferMutLE<Rgb888>,
+            _audio: &mut SndBufferMut<S16_STEREO>,
+        ) {
+            let w = video.width();
+            video
+                .line(0)
+                .set(self.frame % w, Color::new_clamped(255, 255, 255, 255));
+            self.frame += 1;
+        }
+    }
+
+    #[test]
+    fn headless() {
+        let cfg = HeadlessConfig {
+            width: 16,
+            height: 16,
+            audio_frames: 800,
+            frames: 4,
+            png_dir: None,
+            print_hashes: false,
+        };
+        let hashes = run_headless(&mut Dots { frame: 0 }, &cfg).unwrap();
+        assert_eq!(hashes.len(), 4);
+        assert_ne!(hashes[0], hashes[1]);
+
+        // Runs must be reproducible.
+        let hashes2 = run_headless(&mut Dots { frame: 0 }, &cfg).unwrap();
+        assert_eq!(hashes, hashes2);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::gfx::{draw_text, Color, GfxBufferMutLE, OwnedGfxBufferLE, Rgba8888, FONT_HEIGHT};
+
+use lazy_static::lazy_static;
+use slog::info;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How long a message stays on screen.
+const MESSAGE_DURATION: Duration = Duration::from_secs(3);
+
+// Maximum number of messages on screen (older ones are dropped).
+const MAX_MESSAGES: usize = 6;
+
+// Text is scaled with the window, so that about this many lines fit its
+// height.
+const TEXT_LINES: usize = 40;
+
+lazy_static! {
+    // Messages posted and not yet collected by the display.
+    static ref PENDING: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    // Logger receiving a copy of the messages.
+    static ref LOGGER: Mutex<Option<slog::Logger>> = Mutex::new(None);
+}
+
+/// Set the logger which receives a copy of the messages posted with
+/// [`osd_message`](fn.osd_message.html), as they are not displayed within
+/// the debugger or in headless mode.
+pub fn set_osd_logger(logger: slog::Logger) {
+    *LOGGER.lock().unwrap() = Some(logger);
+}
+
+/// Show a transient message over the emulator output (eg: "State saved")
+/// for a few seconds. It can be called from any thread, including the
+/// emulation thread. Messages are also logged (see
+/// [`set_osd_logger`](fn.set_osd_logger.html)).
+pub fn osd_message<S: Into<String>>(msg: S) {
+    let msg = msg.into();
+    if let Some(logger) = LOGGER.lock().unwrap().as_ref() {
+        info!(logger, "{}", msg);
+    }
+    let mut pending = PENDING.lock().unwrap();
+    if pending.len() == MAX_MESSAGES {
+        pending.remove(0);
+    }
+    pending.push(msg);
+}
+
+/// Osd (on-screen display) composes the information drawn over the emulator
+/// output, in the window: frames per second and emulation speed, and the
+/// transient messages posted with [`osd_message`](fn.osd_message.html).
+pub struct Osd {
+    /// Show the frames per second and the emulation speed.
+    pub show_stats: bool,
+    nominal_fps: f32,
+    messages: VecDeque<(String, Instant)>,
+    clock: Instant,
+    displayed: usize,
+    emulated: usize,
+    fps: usize,
+    speed: f32,
+    overlay: OwnedGfxBufferLE<Rgba8888>,
+}
+
+impl Osd {
+    /// Create an OSD for an emulator running at the specified frame rate
+    /// (used to compute the emulation speed).
+    pub fn new(nominal_fps: isize) -> Self {
+        Self {
+            show_stats: false,
+            nominal_fps: nominal_fps as f32,
+            messages: VecDeque::new(),
+            clock: Instant::now(),
+            displayed: 0,
+            emulated: 0,
+            fps: 0,
+            speed: 0.0,
+            overlay: OwnedGfxBufferLE::new(1, 1),
+        }
+    }
+
+    /// Count a frame produced by the emulator.
+    pub fn frame_emulated(&mut self) {
+        self.emulated += 1;
+    }
+
+    /// Count a frame displayed in the window. Statistics are updated once
+    /// per second.
+    pub fn frame_displayed(&mut self) {
+        self.displayed += 1;
+        let elapsed = self.clock.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let secs = elapsed.as_secs() as f32 + elapsed.subsec_micros() as f32 / 1e6;
+            self.fps = (self.displayed as f32 / secs).round() as usize;
+            self.speed = self.emulated as f32 / secs / self.nominal_fps;
+            self.displayed = 0;
+            self.emulated = 0;
+            self.clock = Instant::now();
+        }
+    }
+
+    // Draw a line of text, with a shadow to keep it readable over any
+    // background.
+    fn text(buf: &mut GfxBufferMutLE<Rgba8888>, x: isize, y: isize, text: &str) {
+        draw_text(buf, x + 1, y + 1, text, Color::new_clamped(0, 0, 0, 255));
+        draw_text(buf, x, y, text, Color::new_clamped(255, 255, 255, 255));
+    }
+
+    /// Draw the OSD for a window of the specified size. Returns an RGBA
+    /// buffer (transparent where there is nothing to show) to be stretched
+    /// over the whole window, or None if there is nothing to show.
+    pub fn render(&mut self, win: (usize, usize)) -> Option<&OwnedGfxBufferLE<Rgba8888>> {
+        let now = Instant::now();
+        for msg in PENDING.lock().unwrap().drain(..) {
+            self.messages.push_back((msg, now));
+        }
+        while self.messages.len() > MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages
+            .retain(|(_, time)| now.duration_since(*time) < MESSAGE_DURATION);
+        if !self.show_stats && self.messages.is_empty() {
+            return None;
+        }
+
+        let scale = (win.1 / (TEXT_LINES * FONT_HEIGHT)).max(1);
+        let (w, h) = ((win.0 / scale).max(1), (win.1 / scale).max(1));
+        self.overlay = OwnedGfxBufferLE::new(w, h);
+        let mut buf = self.overlay.buf_mut();
+        if self.show_stats {
+            let stats = format!("{} FPS  {:.0}%", self.fps, self.speed * 100.0);
+            Self::text(&mut buf, 4, 4, &stats);
+        }
+        let line = (FONT_HEIGHT + 2) as isize;
+        let bottom = h as isize - 4 - line * self.messages.len() as isize;
+        for (idx, (msg, _)) in self.messages.iter().enumerate() {
+            Self::text(&mut buf, 4, bottom + line * idx as isize, msg);
+        }
+        Some(&self.overlay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let mut osd = Osd::new(60);
+        assert!(osd.render((640, 480)).is_none());
+
+        osd_message("hello");
+        let overlay = osd.render((640, 480)).unwrap().buf();
+        assert_eq!((overlay.width(), overlay.height()), (640, 480));
+        assert_eq!(osd.messages.len(), 1);
+
+        for i in 0..MAX_MESSAGES + 2 {
+            osd_message(format!("message {}", i));
+        }
+        osd.render((1280, 640));
+        assert_eq!(osd.messages.len(), MAX_MESSAGES);
+        assert_eq!(osd.messages.back().unwrap().0, "message 7");
+        assert_eq!(osd.overlay.buf().width(), 640);
+    }
+}
@@ -0,0 +1,279 @@
+use super::capture::save_png;
+use crate::gfx::{GfxBufferLE, OwnedGfxBufferLE, Rgb888, Rgba8888};
+use crate::snd::{OwnedSndBuffer, SampleFormat, SndBuffer, S16LE_STEREO};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+/// RecordFormat selects how the emulator output is recorded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// A lossless video file (FFV1 video, PCM audio), encoded by an external
+    /// ffmpeg process. The container (eg: .mkv or .avi) is selected through
+    /// the file extension.
+    Ffmpeg,
+    /// A directory containing one PNG file per frame, and a WAV file with
+    /// the audio stream.
+    PngSequence,
+}
+
+impl RecordFormat {
+    /// Select the format from the path of a recording: video files are
+    /// recorded through ffmpeg, anything else is a directory for a PNG
+    /// sequence.
+    pub fn from_path(path: &Path) -> RecordFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("mkv") | Some("avi") | Some("mov") => RecordFormat::Ffmpeg,
+            _ => RecordFormat::PngSequence,
+        }
+    }
+}
+
+/// Return true if ffmpeg is available in PATH.
+pub fn ffmpeg_available() -> bool {
+    Command::new("ffmpeg")
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+// A WAV file with 16-bit stereo PCM. The header is updated with the final
+// size when the file is finished.
+struct WavWriter {
+    file: BufWriter<File>,
+    size: u32,
+}
+
+impl WavWriter {
+    fn create(path: &Path, frequency: u32) -> io::Result<WavWriter> {
+        let mut hdr = [0u8; 44];
+        hdr[0..4].copy_from_slice(b"RIFF");
+        hdr[8..16].copy_from_slice(b"WAVEfmt ");
+        LittleEndian::write_u32(&mut hdr[16..], 16);
+        LittleEndian::write_u16(&mut hdr[20..], 1); // PCM
+        LittleEndian::write_u16(&mut hdr[22..], 2); // channels
+        LittleEndian::write_u32(&mut hdr[24..], frequency);
+        LittleEndian::write_u32(&mut hdr[28..], frequency * 4); // bytes per second
+        LittleEndian::write_u16(&mut hdr[32..], 4); // bytes per frame
+        LittleEndian::write_u16(&mut hdr[34..], 16); // bits per sample
+        hdr[36..40].copy_from_slice(b"data");
+
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&hdr)?;
+        Ok(WavWriter { file, size: 0 })
+    }
+
+    fn write(&mut self, pcm: &[u8]) -> io::Result<()> {
+        self.size += pcm.len() as u32;
+        self.file.write_all(pcm)
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        let mut buf = [0u8; 4];
+        LittleEndian::write_u32(&mut buf, self.size + 36);
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&buf)?;
+        LittleEndian::write_u32(&mut buf, self.size);
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&buf)?;
+        self.file.flush()
+    }
+}
+
+enum Sink {
+    Ffmpeg {
+        ffmpeg: Child,
+        video_path: PathBuf,
+        audio_path: PathBuf,
+    },
+    PngSequence {
+        dir: PathBuf,
+    },
+}
+
+/// Recorder writes the emulator output (video frames and audio) to disk,
+/// to produce lossless footage (eg: for comparisons with real hardware).
+///
+/// With ffmpeg, video and audio are first recorded into two temporary files
+/// next to the output file, which are muxed together by `finish`.
+pub struct Recorder {
+    path: PathBuf,
+    sink: Sink,
+    wav: WavWriter,
+    size: (usize, usize),
+    frames: usize,
+}
+
+impl Recorder {
+    /// Start a new recording into `path`. `size` is the resolution of the
+    /// video frames, `fps` their frequency, and `frequency` is the sample
+    /// rate of the audio.
+    pub fn start(
+        path: &Path,
+        format: RecordFormat,
+        size: (usize, usize),
+        fps: isize,
+        frequency: isize,
+    ) -> Result<Recorder, String> {
+        let err = |e: &dyn ::std::fmt::Display| {
+            format!("cannot start recording {}: {}", path.display(), e)
+        };
+
+        let (sink, audio_path) = match format {
+            RecordFormat::Ffmpeg => {
+                let video_path = path.with_extension("video.mkv");
+                let audio_path = path.with_extension("audio.wav");
+                let ffmpeg = Command::new("ffmpeg")
+                    .args(&["-y", "-loglevel", "error", "-f", "rawvideo"])
+                    .args(&["-pix_fmt", "rgba"])
+                    .args(&["-s", &format!("{}x{}", size.0, size.1)])
+                    .args(&["-r", &fps.to_string()])
+                    .args(&["-i", "-", "-c:v", "ffv1"])
+                    .arg(&video_path)
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .map_err(|e| err(&format!("cannot run ffmpeg: {}", e)))?;
+                let sink = Sink::Ffmpeg {
+                    ffmpeg,
+                    video_path,
+                    audio_path: audio_path.clone(),
+                };
+                (sink, audio_path)
+            }
+            RecordFormat::PngSequence => {
+                fs::create_dir_all(path).map_err(|e| err(&e))?;
+                let sink = Sink::PngSequence {
+                    dir: path.to_owned(),
+                };
+                (sink, path.join("audio.wav"))
+            }
+        };
+        let wav = WavWriter::create(&audio_path, frequency as u32).map_err(|e| err(&e))?;
+
+        Ok(Recorder {
+            path: path.to_owned(),
+            sink,
+            wav,
+            size,
+            frames: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of frames recorded so far.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Add a frame of output to the recording.
+    pub fn add_frame<SF: SampleFormat>(
+        &mut self,
+        video: &GfxBufferLE<Rgb888>,
+        audio: &SndBuffer<SF>,
+    ) -> Result<(), String> {
+        let err = |e: &dyn ::std::fmt::Display| format!("error while recording: {}", e);
+        if (video.width(), video.height()) != self.size {
+            return Err(err(&"resolution changed during recording"));
+        }
+
+        let image = OwnedGfxBufferLE::<Rgba8888>::from_buf(video);
+        let buf = image.buf();
+        let (pixels, _pitch) = buf.raw();
+        match self.sink {
+            Sink::Ffmpeg { ref mut ffmpeg, .. } => {
+                let stdin = ffmpeg.stdin.as_mut().unwrap();
+                stdin.write_all(pixels).map_err(|e| err(&e))?;
+            }
+            Sink::PngSequence { ref dir } => {
+                let path = dir.join(format!("frame{:06}.png", self.frames));
+                save_png(pixels, self.size.0, self.size.1, &path)?;
+            }
+        }
+
+        let mut pcm = OwnedSndBuffer::<S16LE_STEREO>::with_capacity(audio.count());
+        audio.sconv_into(&mut pcm.buf_mut());
+        let mut bytes = vec![0u8; pcm.count() * 4];
+        let pcm = pcm.buf();
+        for i in 0..pcm.count() {
+            LittleEndian::write_i16(&mut bytes[i * 4..], pcm.get_sample(i, 0));
+            LittleEndian::write_i16(&mut bytes[i * 4 + 2..], pcm.get_sample(i, 1));
+        }
+        self.wav.write(&bytes).map_err(|e| err(&e))?;
+
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Stop the recording, and finalize the output files.
+    pub fn finish(self) -> Result<(), String> {
+        let err = |e: &dyn ::std::fmt::Display| format!("cannot finish recording: {}", e);
+        self.wav.finish().map_err(|e| err(&e))?;
+
+        if let Sink::Ffmpeg {
+            mut ffmpeg,
+            video_path,
+            audio_path,
+        } = self.sink
+        {
+            // Close the pipe, so that ffmpeg terminates the video stream.
+            drop(ffmpeg.stdin.take());
+            let status = ffmpeg.wait().map_err(|e| err(&e))?;
+            if !status.success() {
+                return Err(err(&"ffmpeg failed while encoding video"));
+            }
+
+            let status = Command::new("ffmpeg")
+                .args(&["-y", "-loglevel", "error", "-i"])
+                .arg(&video_path)
+                .arg("-i")
+                .arg(&audio_path)
+                .args(&["-c:v", "copy", "-c:a", "pcm_s16le"])
+                .arg(&self.path)
+                .status()
+                .map_err(|e| err(&e))?;
+            if !status.success() {
+                return Err(err(&"ffmpeg failed while muxing audio"));
+            }
+            let _ = fs::remove_file(&video_path);
+            let _ = fs::remove_file(&audio_path);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn png_sequence() {
+        let dir = env::temp_dir().join("r64emu-recorder-test");
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut rec = Recorder::start(&dir, RecordFormat::PngSequence, (4, 2), 60, 48000).unwrap();
+        let video = OwnedGfxBufferLE::<Rgb888>::new(4, 2);
+        let audio = OwnedSndBuffer::<S16LE_STEREO>::with_capacity(800);
+        for _ in 0..3 {
+            rec.add_frame(&video.buf(), &audio.buf()).unwrap();
+        }
+        assert_eq!(rec.frames(), 3);
+        rec.finish().unwrap();
+
+        assert!(dir.join("frame000002.png").exists());
+        let wav = fs::read(dir.join("audio.wav")).unwrap();
+        assert_eq!(wav.len(), 44 + 3 * 800 * 4);
+        assert_eq!(LittleEndian::read_u32(&wav[40..]), 3 * 800 * 4);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ops::Range;
+
+/// A bitfield within a hardware register.
+pub struct RegField {
+    pub name: &'static str,
+    pub bits: Range<usize>,
+    pub desc: &'static str,
+}
+
+/// Documentation of a memory-mapped hardware register: its name, a short
+/// description and the layout of its bitfields.
+pub struct RegInfo {
+    pub addr: u64,
+    pub name: &'static str,
+    pub desc: &'static str,
+    pub fields: &'static [RegField],
+}
+
+impl RegInfo {
+    /// Return a multi-line description of the register, suitable for a
+    /// tooltip. If the value of the register is known, all bitfields are
+    /// decoded; otherwise, just their position is shown.
+    pub fn describe(&self, val: Option<u64>) -> String {
+        let mut out = format!("{} ({:08X})", self.name, self.addr);
+        if !self.desc.is_empty() {
+            write!(out, "\n{}", self.desc).unwrap();
+        }
+        if let Some(val) = val {
+            write!(out, "\nValue: {:08X}", val).unwrap();
+        }
+        for f in self.fields {
+            let range = if f.bits.len() == 1 {
+                format!("[{}]", f.bits.start)
+            } else {
+                format!("[{}:{}]", f.bits.end - 1, f.bits.start)
+            };
+            write!(out, "\n  {:<7} {:<20}", range, f.name).unwrap();
+            if let Some(val) = val {
+                let mask = (1u64 << f.bits.len()) - 1;
+                write!(out, " = {:X}", (val >> f.bits.start) & mask).unwrap();
+            }
+            if !f.desc.is_empty() {
+                write!(out, "  {}", f.desc).unwrap();
+            }
+        }
+        out
+    }
+}
+
+/// RegDatabase is a collection of register documentation, indexed by the
+/// physical address of each register. It is filled by the emulator and
+/// used by the debugger views to show register names and decode their
+/// contents.
+#[derive(Default)]
+pub struct RegDatabase {
+    regs: BTreeMap<u64, RegInfo>,
+}
+
+impl RegDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the documentation of a register. A previous entry with the same
+    /// address is replaced.
+    pub fn add(&mut self, reg: RegInfo) {
+        self.regs.insert(reg.addr, reg);
+    }
+
+    /// Lookup the register that contains the specified address (registers
+    /// are assumed to be 32-bit wide).
+    pub fn lookup(&self, addr: u64) -> Option<&RegInfo> {
+        self.regs.get(&(addr & !3))
+    }
+
+    /// Lookup a register by name.
+    pub fn find(&self, name: &str) -> Option<&RegInfo> {
+        self.regs.values().find(|r| r.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RegInfo> {
+        self.regs.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.regs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_and_describe() {
+        let mut db = RegDatabase::new();
+        db.add(RegInfo {
+            addr: 0x0430_000C,
+            name: "MI_INTR_MASK",
+            desc: "",
+            fields: &[
+                RegField {
+                    name: "SP",
+                    bits: 0..1,
+                    desc: "",
+                },
+                RegField {
+                    name: "VERSION",
+                    bits: 8..16,
+                    desc: "",
+                },
+            ],
+        });
+
+        assert_eq!(db.lookup(0x0430_000E).unwrap().name, "MI_INTR_MASK");
+        assert!(db.lookup(0x0430_0010).is_none());
+        assert_eq!(db.find("MI_INTR_MASK").unwrap().addr, 0x0430_000C);
+
+        let desc = db.lookup(0x0430_000C).unwrap().describe(Some(0x1201));
+        assert!(desc.contains("Value: 00001201"));
+        assert!(desc.contains("SP                   = 1"));
+        assert!(desc.contains("[15:8]"));
+        assert!(desc.contains("VERSION              = 12"));
+    }
+}
@@ -0,0 +1,163 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use std::sync::Arc;
+
+/// SlowAudio selects how audio is played while in slow motion.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SlowAudio {
+    /// Stretch the audio to the emulation speed, preserving its pitch.
+    Stretch,
+    /// Play silence.
+    Mute,
+}
+
+struct SpeedState {
+    fast_forward: AtomicBool,
+    turbo: AtomicBool,
+    slow_motion: AtomicBool,
+    turbo_ratio: AtomicU32, // f32 bits
+    slow_ratio: AtomicU32,  // f32 bits
+    slow_audio: AtomicU8,
+}
+
+/// SpeedControl is a handle to change the emulation speed while running:
+///
+///   * fast-forward runs the emulation unthrottled (usually while a key is
+///     held), skipping audio frames;
+///   * turbo runs the emulation at a configurable multiple of its nominal
+///     speed;
+///   * slow motion runs the emulation at a configurable fraction of its
+///     nominal speed, stretching or muting the audio.
+///
+/// Speed is enforced by the audio output, which consumes samples at the
+/// nominal rate. It can be freely cloned, and is safe to use from any thread.
+#[derive(Clone)]
+pub struct SpeedControl {
+    state: Arc<SpeedState>,
+}
+
+impl SpeedControl {
+    pub const MIN_TURBO: f32 = 1.5;
+    pub const MAX_TURBO: f32 = 8.0;
+    pub const MIN_SLOW: f32 = 0.1;
+    pub const MAX_SLOW: f32 = 0.9;
+
+    pub(crate) fn new() -> Self {
+        let ctl = Self {
+            state: Arc::new(SpeedState {
+                fast_forward: AtomicBool::new(false),
+                turbo: AtomicBool::new(false),
+                slow_motion: AtomicBool::new(false),
+                turbo_ratio: AtomicU32::new(0),
+                slow_ratio: AtomicU32::new(0),
+                slow_audio: AtomicU8::new(SlowAudio::Stretch as u8),
+            }),
+        };
+        ctl.set_turbo_ratio(2.0);
+        ctl.set_slow_ratio(0.25);
+        ctl
+    }
+
+    pub fn fast_forward(&self) -> bool {
+        self.state.fast_forward.load(Ordering::Relaxed)
+    }
+
+    pub fn set_fast_forward(&self, on: bool) {
+        self.state.fast_forward.store(on, Ordering::Relaxed);
+    }
+
+    pub fn turbo(&self) -> bool {
+        self.state.turbo.load(Ordering::Relaxed)
+    }
+
+    /// Enable or disable turbo. Turbo and slow motion are mutually exclusive.
+    pub fn set_turbo(&self, on: bool) {
+        self.state.turbo.store(on, Ordering::Relaxed);
+        if on {
+            self.state.slow_motion.store(false, Ordering::Relaxed);
+        }
+    }
+
+    pub fn slow_motion(&self) -> bool {
+        self.state.slow_motion.load(Ordering::Relaxed)
+    }
+
+    /// Enable or disable slow motion. Turbo and slow motion are mutually
+    /// exclusive.
+    pub fn set_slow_motion(&self, on: bool) {
+        self.state.slow_motion.store(on, Ordering::Relaxed);
+        if on {
+            self.state.turbo.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Return the speed multiplier used in turbo mode.
+    pub fn turbo_ratio(&self) -> f32 {
+        f32::from_bits(self.state.turbo_ratio.load(Ordering::Relaxed))
+    }
+
+    pub fn set_turbo_ratio(&self, ratio: f32) {
+        let ratio = ratio.max(Self::MIN_TURBO).min(Self::MAX_TURBO);
+        self.state
+            .turbo_ratio
+            .store(ratio.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Return the speed multiplier used in slow motion (eg: 0.25 means 25%
+    /// of the nominal speed).
+    pub fn slow_ratio(&self) -> f32 {
+        f32::from_bits(self.state.slow_ratio.load(Ordering::Relaxed))
+    }
+
+    pub fn set_slow_ratio(&self, ratio: f32) {
+        let ratio = ratio.max(Self::MIN_SLOW).min(Self::MAX_SLOW);
+        self.state
+            .slow_ratio
+            .store(ratio.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn slow_audio(&self) -> SlowAudio {
+        if self.state.slow_audio.load(Ordering::Relaxed) == SlowAudio::Mute as u8 {
+            SlowAudio::Mute
+        } else {
+            SlowAudio::Stretch
+        }
+    }
+
+    pub fn set_slow_audio(&self, mode: SlowAudio) {
+        self.state.slow_audio.store(mode as u8, Ordering::Relaxed);
+    }
+
+    /// Return the current emulation speed, as a multiplier of the nominal
+    /// speed, or None if the emulation is unthrottled.
+    pub fn speed(&self) -> Option<f32> {
+        if self.fast_forward() {
+            None
+        } else if self.turbo() {
+            Some(self.turbo_ratio())
+        } else if self.slow_motion() {
+            Some(self.slow_ratio())
+        } else {
+            Some(1.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed() {
+        let ctl = SpeedControl::new();
+        assert_eq!(ctl.speed(), Some(1.0));
+        ctl.set_turbo(true);
+        assert_eq!(ctl.speed(), Some(2.0));
+        ctl.set_slow_motion(true);
+        assert!(!ctl.turbo());
+        assert_eq!(ctl.speed(), Some(0.25));
+        ctl.set_fast_forward(true);
+        assert_eq!(ctl.speed(), None);
+        ctl.set_turbo_ratio(100.0);
+        assert_eq!(ctl.turbo_ratio(), SpeedControl::MAX_TURBO);
+    }
+}
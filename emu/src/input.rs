@@ -1,4 +1,5 @@
 use indexmap::map::IndexMap;
+use serde_derive::{Deserialize, Serialize};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum InputKind {
@@ -116,12 +117,91 @@ impl Input {
     }
 }
 
+/// Calibration of an analog stick, applied to the pair of analog inputs of
+/// a device with kind `Horizontal` and `Vertical`.
+///
+/// All values are expressed as a fraction of the full range of the physical
+/// stick (0.0 - 1.0).
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StickCalibration {
+    /// Inner deadzone: positions closer than this to the center are
+    /// reported as centered.
+    pub deadzone: f32,
+
+    /// Outer saturation: positions farther than this from the center are
+    /// reported as fully tilted.
+    pub saturation: f32,
+
+    /// Octagonal gate correction. Modern pads have a circular range, while
+    /// some consoles (eg: N64) have an octagonal gate, so that diagonals
+    /// reach a lower value on each axis. If set, this is the value reached
+    /// by each axis at the diagonal corners of the octagon, and the circular
+    /// range is mapped onto the octagon.
+    pub gate: Option<f32>,
+
+    /// Sensitivity curve: exponent applied to the magnitude of the stick
+    /// after rescaling. 1.0 is linear; higher values give finer control
+    /// around the center, lower values make the stick more reactive.
+    #[serde(default = "StickCalibration::default_curve")]
+    pub curve: f32,
+}
+
+impl Default for StickCalibration {
+    fn default() -> Self {
+        StickCalibration {
+            deadzone: 0.1,
+            saturation: 0.95,
+            gate: None,
+            curve: 1.0,
+        }
+    }
+}
+
+impl StickCalibration {
+    fn default_curve() -> f32 {
+        1.0
+    }
+
+    /// Map a raw physical stick position to the calibrated position.
+    pub fn apply(&self, x: i16, y: i16) -> (i16, i16) {
+        let (x, y) = (x as f32 / 32767.0, y as f32 / 32767.0);
+        let r = (x * x + y * y).sqrt();
+        if r <= self.deadzone || r == 0.0 {
+            return (0, 0);
+        }
+
+        // Rescale the magnitude between deadzone and saturation.
+        let range = (self.saturation - self.deadzone).max(1e-3);
+        let mut mag = ((r - self.deadzone) / range).min(1.0);
+        if self.curve != 1.0 {
+            mag = mag.powf(self.curve.max(0.1));
+        }
+
+        if let Some(g) = self.gate {
+            // Find where the ray in the direction of the stick intersects
+            // the edge of the octagon between the cardinal vertex (1,0)
+            // and the diagonal vertex (g,g). By symmetry, we fold the
+            // direction into the first octant.
+            let (ux, uy) = (x.abs() / r, y.abs() / r);
+            let (ux, uy) = if uy > ux { (uy, ux) } else { (ux, uy) };
+            mag *= g / (ux * g - uy * (g - 1.0));
+        }
+
+        let cx = (x / r * mag).max(-1.0).min(1.0);
+        let cy = (y / r * mag).max(-1.0).min(1.0);
+        ((cx * 32767.0) as i16, (cy * 32767.0) as i16)
+    }
+}
+
 #[derive(Clone)]
 pub struct InputDevice {
     name: String,
     kind: InputDeviceKind,
     inputs: IndexMap<String, Input>,
     active: bool,
+    calibration: Option<StickCalibration>,
+    raw_stick: (i16, i16),
+    rumble: bool,
 }
 
 impl InputDevice {
@@ -131,6 +211,9 @@ impl InputDevice {
             kind,
             inputs: inputs.iter().map(|i| (i.name.clone(), i.clone())).collect(),
             active: false,
+            calibration: None,
+            raw_stick: (0, 0),
+            rumble: false,
         }
     }
 
@@ -157,6 +240,74 @@ impl InputDevice {
             cb(val);
         }
     }
+
+    /// Return true if the device has an analog stick (a pair of
+    /// `Horizontal` and `Vertical` analog inputs).
+    pub fn has_stick(&self) -> bool {
+        let is_analog = |kind| {
+            self.inputs
+                .values()
+                .any(|i| i.kind == kind && i.analog().is_some())
+        };
+        is_analog(InputKind::Horizontal) && is_analog(InputKind::Vertical)
+    }
+
+    /// Current calibration of the analog stick (if any).
+    pub fn calibration(&self) -> Option<StickCalibration> {
+        self.calibration
+    }
+
+    /// Position of the analog stick as received by the physical device,
+    /// before calibration.
+    pub fn raw_stick(&self) -> (i16, i16) {
+        self.raw_stick
+    }
+
+    /// Return true if the emulated device requested force feedback (eg:
+    /// through a rumble accessory). It is up to the host to forward it to
+    /// the physical device.
+    pub fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    /// Position of the analog stick after calibration.
+    pub fn stick(&self) -> (i16, i16) {
+        let mut pos = (0, 0);
+        for i in self.inputs.values() {
+            match (i.kind, i.value) {
+                (InputKind::Horizontal, InputValue::Analog(v)) => pos.0 = v,
+                (InputKind::Vertical, InputValue::Analog(v)) => pos.1 = v,
+                _ => {}
+            }
+        }
+        pos
+    }
+
+    fn update_analog(&mut self, name: &str, val: i16) {
+        let inp = self.inputs.get_mut(name).unwrap();
+        inp.prev = inp.value;
+        inp.value = InputValue::Analog(val);
+        match inp.kind {
+            InputKind::Horizontal => self.raw_stick.0 = val,
+            InputKind::Vertical => self.raw_stick.1 = val,
+            _ => return,
+        };
+        self.apply_calibration();
+    }
+
+    fn apply_calibration(&mut self) {
+        let (x, y) = match self.calibration {
+            Some(cal) => cal.apply(self.raw_stick.0, self.raw_stick.1),
+            None => self.raw_stick,
+        };
+        for i in self.inputs.values_mut() {
+            match (i.kind, i.value) {
+                (InputKind::Horizontal, InputValue::Analog(_)) => i.value = InputValue::Analog(x),
+                (InputKind::Vertical, InputValue::Analog(_)) => i.value = InputValue::Analog(y),
+                _ => {}
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -204,15 +355,7 @@ impl InputManager {
                 inp.value = InputValue::Digital(*val);
             }
             InputEvent::Analog(dev, inp, val) => {
-                let inp = &mut self
-                    .devices
-                    .get_mut(dev)
-                    .unwrap()
-                    .inputs
-                    .get_mut(inp)
-                    .unwrap();
-                inp.prev = inp.value;
-                inp.value = InputValue::Analog(*val);
+                self.devices.get_mut(dev).unwrap().update_analog(inp, *val);
             }
             InputEvent::Coordinate(dev, inp, val) => {
                 let inp = &mut self
@@ -239,6 +382,24 @@ impl InputManager {
         self.devices.get(name)
     }
 
+    /// Configure the calibration of the analog stick of the specified device.
+    /// Passing None disables calibration, so that the raw physical position
+    /// is reported.
+    pub fn set_calibration(&mut self, name: &str, cal: Option<StickCalibration>) {
+        if let Some(dev) = self.devices.get_mut(name) {
+            dev.calibration = cal;
+            dev.apply_calibration();
+        }
+    }
+
+    /// Turn force feedback of the specified device on or off. This is
+    /// meant to be called by the emulated hardware.
+    pub fn set_rumble(&mut self, name: &str, on: bool) {
+        if let Some(dev) = self.devices.get_mut(name) {
+            dev.rumble = on;
+        }
+    }
+
     /// Visit all the defined [InputDevice](struct.InputDevice.html)
     /// instances (in insertion order).
     pub fn visit<F: FnMut(&InputDevice)>(&self, mut f: F) {
@@ -247,3 +408,88 @@ impl InputManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calibration_deadzone_saturation() {
+        let cal = StickCalibration {
+            deadzone: 0.2,
+            saturation: 0.8,
+            gate: None,
+            curve: 1.0,
+        };
+        assert_eq!(cal.apply(0, 0), (0, 0));
+        assert_eq!(cal.apply(6000, 0), (0, 0));
+        assert_eq!(cal.apply(0, -32767), (0, -32767));
+        assert_eq!(cal.apply(28000, 0), (32767, 0));
+
+        // Midpoint between deadzone and saturation
+        let (x, y) = cal.apply(16383, 0);
+        assert!((x - 16383).abs() < 10);
+        assert_eq!(y, 0);
+    }
+
+    #[test]
+    fn calibration_gate() {
+        let cal = StickCalibration {
+            deadzone: 0.0,
+            saturation: 1.0,
+            gate: Some(0.8),
+            curve: 1.0,
+        };
+
+        // Cardinal directions are unaffected
+        assert_eq!(cal.apply(32767, 0), (32767, 0));
+        assert_eq!(cal.apply(0, -32767), (0, -32767));
+
+        // A full diagonal on a circular pad maps to the octagon corner.
+        let d = (32767.0 * std::f32::consts::FRAC_1_SQRT_2) as i16;
+        let (x, y) = cal.apply(d, -d);
+        assert!((x as f32 - 0.8 * 32767.0).abs() < 50.0);
+        assert!((y as f32 + 0.8 * 32767.0).abs() < 50.0);
+    }
+
+    #[test]
+    fn calibration_curve() {
+        let cal = StickCalibration {
+            deadzone: 0.0,
+            saturation: 1.0,
+            gate: None,
+            curve: 2.0,
+        };
+
+        // Full tilt is unaffected, half tilt is reduced to a quarter.
+        assert_eq!(cal.apply(32767, 0), (32767, 0));
+        let (x, y) = cal.apply(0, 16383);
+        assert_eq!(x, 0);
+        assert!((y - 8191).abs() < 10);
+    }
+
+    #[test]
+    fn device_calibration() {
+        let mut im = InputManager::new(vec![InputDevice::new(
+            "joy",
+            InputDeviceKind::Joystick,
+            vec![
+                Input::new_analog("X", InputKind::Horizontal, 0),
+                Input::new_analog("Y", InputKind::Vertical, 1),
+            ],
+        )]);
+        assert!(im.device("joy").unwrap().has_stick());
+
+        im.process_event(InputEvent::Analog("joy".into(), "X".into(), 1000));
+        assert_eq!(im.device("joy").unwrap().stick(), (1000, 0));
+
+        im.set_calibration("joy", Some(StickCalibration::default()));
+        assert_eq!(im.device("joy").unwrap().stick(), (0, 0));
+        assert_eq!(im.device("joy").unwrap().raw_stick(), (1000, 0));
+
+        im.process_event(InputEvent::Analog("joy".into(), "Y".into(), 32767));
+        let (x, y) = im.device("joy").unwrap().stick();
+        assert!(x > 0 && x < 1000);
+        assert!(y > 32000);
+    }
+}
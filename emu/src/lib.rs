@@ -6,7 +6,9 @@
 #![feature(thread_local)]
 #![feature(integer_atomics)]
 
+pub mod backup;
 pub mod bus;
+pub mod config;
 pub mod dbg;
 pub mod fp;
 pub mod gfx;
@@ -9,24 +9,47 @@
 //! which returns a `slog::Logger` instance that can be used according to standard
 //! `slog` documentation.
 //!
+//! To also save the logs to a file as newline-delimited JSON (one object per
+//! record, for post-processing of long sessions), use
+//! [`new_console_json_logger()`](fn.new_console_json_logger.html) instead.
+//!
+//! Console and file output is performed by a separate thread
+//! ([`AsyncWriter`](struct.AsyncWriter.html)), so that heavy logging does not
+//! slow down emulation; if the output cannot keep up, records are dropped.
+//!
+//! All loggers created by this module filter records through a global registry
+//! of log levels keyed by tag ([`log_levels()`](fn.log_levels.html)), so that the
+//! verbosity of each subsystem can be changed at runtime.
+//!
 //! TODO: explain conventions
 //! TODO: explain interaction with dbg
 //! TODO: explain interaction with sync
 //!
-use atty;
+use serde_json::{Map, Value};
 use slog;
 use slog::*;
 use std::fmt;
+use std::fs::File;
 use std::io;
 use std::io::Write;
 use std::panic::RefUnwindSafe;
 use std::panic::UnwindSafe;
+use std::path::Path;
 use std::result;
 use std::sync;
-use std::time::Instant;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
+mod asyncwriter;
+mod console;
+mod levels;
 mod logpool;
+mod ring;
+pub use asyncwriter::*;
+use console::{console_emulated_time, console_output};
+pub use console::{set_console_colors, set_console_emulated_time};
+pub use levels::*;
 pub use logpool::*;
+pub use ring::*;
 
 /// KEY_FRAME is the key that can be used to specifiy the number of the frame
 /// at which the logging was generated. Loggers can use this value specially.
@@ -35,6 +58,12 @@ pub use logpool::*;
 /// as this is usually a more useful time-based information in emulators.
 pub const KEY_FRAME: &'static str = "@f";
 
+/// KEY_CYCLES is the key that can be used to specify the emulated clock cycle
+/// (within the current frame) at which the logging was generated. Together with
+/// KEY_FRAME, it is used as timestamp by drains configured with
+/// `use_emulated_time()`.
+pub const KEY_CYCLES: &'static str = "@c";
+
 // KEY_SUBSYSTEM is the key that can be used to specify the subsystem in which
 // the logging was generated. This could be one of the CPUs (in which case,
 // )
@@ -109,9 +138,6 @@ impl<W: io::Write> LogPrinter for ColorPrinter<W> {
             io: self.w.clone(),
             buf: Vec::with_capacity(128),
             color: self.color,
-            ts_start: 0,
-            ts_end: 0,
-            frame: None,
         })
     }
 }
@@ -120,9 +146,6 @@ pub struct ColorRecordPrinter<W: io::Write> {
     io: sync::Arc<sync::Mutex<W>>,
     buf: Vec<u8>,
     color: bool,
-    ts_start: usize,
-    ts_end: usize,
-    frame: Option<String>,
 }
 
 impl<W: io::Write> LogRecordPrinter for ColorRecordPrinter<W> {
@@ -136,9 +159,7 @@ impl<W: io::Write> LogRecordPrinter for ColorRecordPrinter<W> {
         if self.color {
             write!(rd.w, "\x1b[34m")?;
         }
-        self.ts_start = rd.count();
         fn_timestamp(&mut rd)?;
-        self.ts_end = rd.count();
         write!(rd, " ")?;
 
         let level = record.level();
@@ -181,11 +202,9 @@ impl<W: io::Write> LogRecordPrinter for ColorRecordPrinter<W> {
     }
 
     fn print_kv<K: fmt::Display, V: fmt::Display>(&mut self, k: K, v: V) -> io::Result<()> {
-        // If the key is the special KEY_FRAME key, save the frame aside; we will reuse
-        // it later to substitute it to the timestamp.
+        // Emulated time keys are already shown in place of the timestamp
         let k = format!("{}", k);
-        if k == KEY_FRAME {
-            self.frame = Some(format!("{}", v).to_string());
+        if k == KEY_FRAME || k == KEY_CYCLES {
             return Ok(());
         }
         write!(&mut self.buf, " {}=", k)?;
@@ -210,20 +229,89 @@ impl<W: io::Write> LogRecordPrinter for ColorRecordPrinter<W> {
             .lock()
             .map_err(|_| io::Error::new(io::ErrorKind::Other, "mutex locking error"))?;
 
-        match self.frame {
-            Some(frame) => {
-                io.write(&self.buf[..self.ts_start])?;
-                io.write(frame.as_bytes())?;
-                io.write(&self.buf[self.ts_end..])?;
-            }
-            None => io.write_all(&self.buf)?,
-        };
-
+        io.write_all(&self.buf)?;
         self.buf.clear();
         io.flush()
     }
 }
 
+/// JsonPrinter is a [`LogPrinter`](trait.LogPrinter.html) that writes each
+/// record as a JSON object on a single line (NDJSON). Each object contains
+/// the fields `ts` (seconds since UNIX epoch), `level`, `tag`, `msg`, plus
+/// an object `kv` with all the key-value pairs of the record.
+pub struct JsonPrinter<W: io::Write> {
+    w: sync::Arc<sync::Mutex<W>>,
+}
+
+impl<W: io::Write> JsonPrinter<W> {
+    pub fn new(io: W) -> Self {
+        Self {
+            w: sync::Arc::new(sync::Mutex::new(io)),
+        }
+    }
+}
+
+impl<W: io::Write> LogPrinter for JsonPrinter<W> {
+    type RecordPrinter = JsonRecordPrinter<W>;
+
+    fn with_record<F>(&self, _record: &Record, f: F) -> io::Result<()>
+    where
+        F: FnOnce(Self::RecordPrinter) -> io::Result<()>,
+    {
+        f(JsonRecordPrinter {
+            io: self.w.clone(),
+            obj: Map::new(),
+            kv: Map::new(),
+        })
+    }
+}
+
+pub struct JsonRecordPrinter<W: io::Write> {
+    io: sync::Arc<sync::Mutex<W>>,
+    obj: Map<String, Value>,
+    kv: Map<String, Value>,
+}
+
+impl<W: io::Write> LogRecordPrinter for JsonRecordPrinter<W> {
+    fn print_header(
+        &mut self,
+        record: &Record,
+        _fn_timestamp: &ThreadSafeTimestampFn<Output = io::Result<()>>,
+    ) -> io::Result<()> {
+        // The console timestamp is relative to the start of the emulator; use
+        // an absolute wall-clock time instead, so that files can be correlated.
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as f64 + d.subsec_micros() as f64 / 1e6)
+            .unwrap_or(0.0);
+        self.obj.insert("ts".into(), ts.into());
+        self.obj
+            .insert("level".into(), record.level().as_str().into());
+        self.obj.insert("tag".into(), record_tag(record).into());
+        self.obj
+            .insert("msg".into(), record.msg().to_string().into());
+        Ok(())
+    }
+
+    fn print_kv<K: fmt::Display, V: fmt::Display>(&mut self, k: K, v: V) -> io::Result<()> {
+        self.kv.insert(k.to_string(), v.to_string().into());
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.obj.insert("kv".into(), Value::Object(self.kv));
+        let mut buf = serde_json::to_vec(&self.obj)?;
+        buf.push(b'\n');
+
+        let mut io = self
+            .io
+            .lock()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "mutex locking error"))?;
+        io.write_all(&buf)?;
+        io.flush()
+    }
+}
+
 struct Serializer<'a, RP: LogRecordPrinter> {
     printer: &'a mut RP,
     reverse: bool,
@@ -347,6 +435,25 @@ impl<'a, RP: LogRecordPrinter> slog::ser::Serializer for Serializer<'a, RP> {
     }
 }
 
+// Serializer that extracts the emulated time (frame and cycles) of a record,
+// ignoring all other key-value pairs.
+#[derive(Default)]
+struct EmuTimeSerializer {
+    frame: Option<String>,
+    cycles: Option<String>,
+}
+
+impl slog::ser::Serializer for EmuTimeSerializer {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        if key == KEY_FRAME && self.frame.is_none() {
+            self.frame = Some(format!("{}", val));
+        } else if key == KEY_CYCLES && self.cycles.is_none() {
+            self.cycles = Some(format!("{}", val));
+        }
+        Ok(())
+    }
+}
+
 // Wrapper for `Write` types that counts total bytes written.
 struct CountingWriter<W: io::Write> {
     w: W,
@@ -390,6 +497,7 @@ where
     printer: RP,
     fn_timestamp: Box<ThreadSafeTimestampFn<Output = io::Result<()>>>,
     use_original_order: bool,
+    use_emulated_time: bool,
 }
 
 pub(crate) struct LogDrainBuilder<RP>
@@ -399,6 +507,7 @@ where
     printer: RP,
     fn_timestamp: Box<ThreadSafeTimestampFn<Output = io::Result<()>>>,
     original_order: bool,
+    emulated_time: bool,
 }
 
 impl<RP> LogDrainBuilder<RP>
@@ -423,12 +532,25 @@ where
         self
     }
 
+    /// Timestamp records with the emulated time, as "[frame:cycles]"
+    ///
+    /// By default, records carrying a frame number (see [`KEY_FRAME`](constant.KEY_FRAME.html))
+    /// are timestamped with just the frame, and other records with the host time.
+    /// With this option, the cycle count within the frame (see [`KEY_CYCLES`](constant.KEY_CYCLES.html))
+    /// is added, so that logs of different runs of a deterministic emulation
+    /// can be aligned exactly.
+    pub fn use_emulated_time(mut self) -> Self {
+        self.emulated_time = true;
+        self
+    }
+
     /// Build `FullFormat`
     pub fn build(self) -> LogDrain<RP> {
         LogDrain {
             printer: self.printer,
             fn_timestamp: self.fn_timestamp,
             use_original_order: self.original_order,
+            use_emulated_time: self.emulated_time,
         }
     }
 }
@@ -451,12 +573,25 @@ impl<RP: LogPrinter> LogDrain<RP> {
             }),
             printer: p,
             original_order: false,
+            emulated_time: false,
         }
     }
 
     fn format_full(&self, record: &Record, values: &OwnedKVList) -> io::Result<()> {
+        let mut emutime = EmuTimeSerializer::default();
+        record.kv().serialize(record, &mut emutime)?;
+        values.serialize(record, &mut emutime)?;
+
         self.printer.with_record(record, |mut printer| {
-            printer.print_header(&record, &*self.fn_timestamp)?;
+            match (emutime.frame, emutime.cycles) {
+                (Some(frame), Some(cycles)) if self.use_emulated_time => printer
+                    .print_header(&record, &move |w: &mut io::Write| {
+                        write!(w, "[{}:{}]", frame, cycles)
+                    })?,
+                (Some(frame), _) => printer
+                    .print_header(&record, &move |w: &mut io::Write| write!(w, "{}", frame))?,
+                _ => printer.print_header(&record, &*self.fn_timestamp)?,
+            };
             {
                 let mut serializer = Serializer::new(&mut printer, self.use_original_order);
                 record.kv().serialize(record, &mut serializer)?;
@@ -468,9 +603,69 @@ impl<RP: LogPrinter> LogDrain<RP> {
     }
 }
 
+fn console_drain<W: io::Write>(printer: ColorPrinter<W>) -> LogDrain<ColorPrinter<W>> {
+    let builder = LogDrain::new(printer);
+    if console_emulated_time() {
+        builder.use_emulated_time().build()
+    } else {
+        builder.build()
+    }
+}
+
 /// Create a slog::Logger that ouputs to the console. The output will be colored.
 pub fn new_console_logger() -> slog::Logger {
-    let printer = ColorPrinter::new(std::io::stdout(), atty::is(atty::Stream::Stdout));
-    let drain = LogDrain::new(printer).build().fuse();
+    let (out, color) = console_output();
+    let printer = ColorPrinter::new(AsyncWriter::new(out), color);
+    let drain = LevelsDrain::new(console_drain(printer)).fuse();
     slog::Logger::root(drain, o!())
 }
+
+// Create a drain that writes all records as newline-delimited JSON into
+// the specified file.
+pub(crate) fn json_drain(path: &Path) -> io::Result<LogDrain<JsonPrinter<AsyncWriter>>> {
+    let file = AsyncWriter::new(io::BufWriter::new(File::create(path)?));
+    Ok(LogDrain::new(JsonPrinter::new(file)).build())
+}
+
+/// Create a slog::Logger that outputs to the console (like
+/// [`new_console_logger()`](fn.new_console_logger.html)), and also
+/// writes all records as newline-delimited JSON into the specified file.
+pub fn new_console_json_logger(path: &Path) -> io::Result<slog::Logger> {
+    let json = json_drain(path)?;
+    let (out, color) = console_output();
+    let console = ColorPrinter::new(AsyncWriter::new(out), color);
+    let drain = LevelsDrain::new(Duplicate::new(console_drain(console), json)).fuse();
+    Ok(slog::Logger::root(drain, o!()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_json_record() {
+        let path = env::temp_dir().join(format!("r64emu-log-{}.json", std::process::id()));
+        {
+            let logger = slog::Logger::root(json_drain(&path).unwrap().fuse(), o!());
+            let logger = logger.new(o!("sub" => "cpu"));
+            info!(logger, #"dma", "copy \"{}\"\n", "rom"; "len" => 4, "path" => "a\\b\tc");
+        }
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // A single record, on a single line.
+        assert_eq!(text.lines().count(), 1);
+        assert!(text.ends_with('\n'));
+        let rec: Value = serde_json::from_str(text.trim_end()).unwrap();
+        assert!(rec["ts"].as_f64().unwrap() > 0.0);
+        assert_eq!(rec["level"], "INFO");
+        assert_eq!(rec["tag"], "dma");
+        assert_eq!(rec["msg"], "copy \"rom\"\n");
+        assert_eq!(rec["kv"]["len"], "4");
+        assert_eq!(rec["kv"]["path"], "a\\b\tc");
+        assert_eq!(rec["kv"]["sub"], "cpu");
+        assert!(text.contains(r#""msg":"copy \"rom\"\n""#));
+    }
+}
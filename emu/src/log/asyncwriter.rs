@@ -0,0 +1,142 @@
+use std::io;
+use std::io::Write;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::thread;
+
+// Maximum number of records that can be queued before dropping them.
+const ASYNC_QUEUE_SIZE: usize = 4096;
+
+// Total number of records dropped by all async writers.
+static DROPPED_RECORDS: AtomicUsize = AtomicUsize::new(0);
+
+/// Return the total number of log records dropped because the log output
+/// could not keep up with the emulator.
+pub fn dropped_log_records() -> usize {
+    DROPPED_RECORDS.load(Ordering::Relaxed)
+}
+
+/// AsyncWriter is a writer that moves the actual I/O into a separate thread,
+/// so that the thread generating logs never blocks on a slow output (eg: a
+/// terminal, or a file on a slow disk).
+///
+/// Writes are accumulated in a local buffer, and each call to `flush()`
+/// enqueues it as a single record. Log printers flush once per record, so
+/// records are never split or interleaved. If the queue is full, the record
+/// is dropped (and counted, see [`dropped_log_records()`](fn.dropped_log_records.html));
+/// the writer thread reports the number of lost records in the output itself
+/// as soon as it catches up.
+pub struct AsyncWriter {
+    buf: Vec<u8>,
+    tx: Option<SyncSender<Vec<u8>>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl AsyncWriter {
+    pub fn new<W: io::Write + Send + 'static>(mut w: W) -> Self {
+        let (tx, rx) = sync_channel::<Vec<u8>>(ASYNC_QUEUE_SIZE);
+        let thread = thread::Builder::new()
+            .name("log writer".into())
+            .spawn(move || {
+                let mut reported = 0;
+                while let Ok(mut rec) = rx.recv() {
+                    loop {
+                        let dropped = dropped_log_records();
+                        if dropped != reported {
+                            let _ =
+                                writeln!(w, "... {} log records dropped ...", dropped - reported);
+                            reported = dropped;
+                        }
+                        // Nothing sensible can be done if the output fails.
+                        let _ = w.write_all(&rec);
+                        match rx.try_recv() {
+                            Ok(next) => rec = next,
+                            Err(_) => break,
+                        }
+                    }
+                    // Flush only once the queue is empty, so that buffered
+                    // outputs write records in batches.
+                    let _ = w.flush();
+                }
+            })
+            .expect("cannot spawn log writer thread");
+
+        Self {
+            buf: Vec::with_capacity(256),
+            tx: Some(tx),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl io::Write for AsyncWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let rec = mem::replace(&mut self.buf, Vec::with_capacity(256));
+        match self.tx.as_ref().unwrap().try_send(rec) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                DROPPED_RECORDS.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "log writer thread exited",
+            )),
+        }
+    }
+}
+
+impl Drop for AsyncWriter {
+    fn drop(&mut self) {
+        // Send pending data, then close the channel and wait for the thread
+        // to write out all queued records.
+        let _ = self.flush();
+        self.tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_async_writer() {
+        let out = SharedBuf(Arc::new(Mutex::new(Vec::new())));
+        {
+            let mut w = AsyncWriter::new(out.clone());
+            write!(w, "hello ").unwrap();
+            write!(w, "world\n").unwrap();
+            w.flush().unwrap();
+            write!(w, "unflushed\n").unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(out.0.lock().unwrap().clone()).unwrap(),
+            "hello world\nunflushed\n"
+        );
+    }
+}
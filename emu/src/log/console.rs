@@ -0,0 +1,207 @@
+use atty;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static CONSOLE_COLORS: AtomicBool = AtomicBool::new(true);
+static CONSOLE_EMULATED_TIME: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable colors in console loggers created afterwards. Colors are
+/// enabled by default, but they are never used if the standard output is not
+/// a terminal (eg: it is piped to a file).
+pub fn set_console_colors(enabled: bool) {
+    CONSOLE_COLORS.store(enabled, Ordering::Relaxed);
+}
+
+/// Timestamp records of console loggers created afterwards with the emulated
+/// time (frame and cycles) rather than the host time.
+pub fn set_console_emulated_time(enabled: bool) {
+    CONSOLE_EMULATED_TIME.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn console_emulated_time() -> bool {
+    CONSOLE_EMULATED_TIME.load(Ordering::Relaxed)
+}
+
+/// Return the writer to be used for console output, and whether the output
+/// should contain color escape sequences (ANSI).
+///
+/// On Windows, the console is switched to VT processing mode (supported by
+/// Windows 10 and later) to interpret ANSI sequences; on older consoles, the
+/// sequences are translated into calls to the console API instead.
+pub(crate) fn console_output() -> (Box<dyn io::Write + Send>, bool) {
+    let color = CONSOLE_COLORS.load(Ordering::Relaxed) && atty::is(atty::Stream::Stdout);
+    if !color {
+        return (Box::new(io::stdout()), false);
+    }
+
+    #[cfg(windows)]
+    {
+        if !win::enable_vt_processing() {
+            return (
+                Box::new(AnsiConsoleWriter::new(
+                    io::stdout(),
+                    win::set_text_attribute,
+                )),
+                true,
+            );
+        }
+    }
+    (Box::new(io::stdout()), true)
+}
+
+#[cfg(windows)]
+mod win {
+    use winapi::um::consoleapi::{GetConsoleMode, SetConsoleMode};
+    use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+    use winapi::um::processenv::GetStdHandle;
+    use winapi::um::winbase::STD_OUTPUT_HANDLE;
+    use winapi::um::wincon::{SetConsoleTextAttribute, ENABLE_VIRTUAL_TERMINAL_PROCESSING};
+
+    // Try enabling ANSI escape sequences on the console. Returns false
+    // if not supported (Windows versions before 10).
+    pub(super) fn enable_vt_processing() -> bool {
+        unsafe {
+            let h = GetStdHandle(STD_OUTPUT_HANDLE);
+            if h == INVALID_HANDLE_VALUE {
+                return false;
+            }
+            let mut mode = 0;
+            if GetConsoleMode(h, &mut mode) == 0 {
+                return false;
+            }
+            SetConsoleMode(h, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+        }
+    }
+
+    pub(super) fn set_text_attribute(attr: u16) {
+        unsafe {
+            SetConsoleTextAttribute(GetStdHandle(STD_OUTPUT_HANDLE), attr);
+        }
+    }
+}
+
+// Console attributes, as used by the Windows console API.
+const ATTR_BLUE: u16 = 0x1;
+const ATTR_GREEN: u16 = 0x2;
+const ATTR_RED: u16 = 0x4;
+const ATTR_INTENSITY: u16 = 0x8;
+const ATTR_DEFAULT: u16 = ATTR_RED | ATTR_GREEN | ATTR_BLUE;
+
+// Apply a SGR escape sequence (the parameters of "ESC [ ... m") to a
+// console attribute. Only the subset used by the log printers is supported.
+fn apply_sgr(mut attr: u16, params: &[u8]) -> u16 {
+    for p in params.split(|&c| c == b';') {
+        match std::str::from_utf8(p).ok().and_then(|p| p.parse().ok()) {
+            Some(0) | None => attr = ATTR_DEFAULT,
+            Some(1) => attr |= ATTR_INTENSITY,
+            Some(n @ 30..=37) => {
+                let n = n - 30;
+                attr &= !(ATTR_RED | ATTR_GREEN | ATTR_BLUE);
+                if n & 1 != 0 {
+                    attr |= ATTR_RED;
+                }
+                if n & 2 != 0 {
+                    attr |= ATTR_GREEN;
+                }
+                if n & 4 != 0 {
+                    attr |= ATTR_BLUE;
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    attr
+}
+
+// A writer that strips ANSI color sequences from the output, and calls
+// a function to change the console attribute instead.
+#[cfg_attr(not(windows), allow(dead_code))]
+struct AnsiConsoleWriter<W: io::Write> {
+    w: W,
+    set_attr: fn(u16),
+    attr: u16,
+    esc: Option<Vec<u8>>, // escape sequence being parsed (if any)
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+impl<W: io::Write> AnsiConsoleWriter<W> {
+    fn new(w: W, set_attr: fn(u16)) -> Self {
+        Self {
+            w,
+            set_attr,
+            attr: ATTR_DEFAULT,
+            esc: None,
+        }
+    }
+}
+
+impl<W: io::Write> io::Write for AnsiConsoleWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut start = 0;
+        for (idx, &c) in buf.iter().enumerate() {
+            match self.esc.as_mut() {
+                Some(esc) => {
+                    if c == b'm' {
+                        // Text must hit the console before changing color
+                        self.w.flush()?;
+                        self.attr = apply_sgr(self.attr, &esc[1..]);
+                        (self.set_attr)(self.attr);
+                        self.esc = None;
+                        start = idx + 1;
+                    } else {
+                        esc.push(c);
+                    }
+                }
+                None if c == 0x1b => {
+                    self.w.write_all(&buf[start..idx])?;
+                    self.esc = Some(Vec::new());
+                }
+                None => {}
+            }
+        }
+        if self.esc.is_none() {
+            self.w.write_all(&buf[start..])?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::Write;
+
+    thread_local! {
+        static ATTRS: RefCell<Vec<u16>> = RefCell::new(Vec::new());
+    }
+
+    fn record_attr(attr: u16) {
+        ATTRS.with(|a| a.borrow_mut().push(attr));
+    }
+
+    #[test]
+    fn test_ansi_console_writer() {
+        let mut out = Vec::new();
+        {
+            let mut w = AnsiConsoleWriter::new(&mut out, record_attr);
+            write!(w, "\x1b[34m[1] \x1b[31mE \x1b[35;1m|tag|\x1b[0m msg\n").unwrap();
+        }
+        assert_eq!(String::from_utf8(out).unwrap(), "[1] E |tag| msg\n");
+        ATTRS.with(|a| {
+            assert_eq!(
+                *a.borrow(),
+                vec![
+                    ATTR_BLUE,
+                    ATTR_RED,
+                    ATTR_RED | ATTR_BLUE | ATTR_INTENSITY,
+                    ATTR_DEFAULT
+                ]
+            )
+        });
+    }
+}
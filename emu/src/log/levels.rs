@@ -0,0 +1,155 @@
+use lazy_static::lazy_static;
+use slog::{Drain, FilterLevel, Level, OwnedKVList, Record};
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+struct LevelsInner {
+    default: FilterLevel,
+    tags: BTreeMap<String, FilterLevel>,
+}
+
+/// LogLevels is a registry of log levels, keyed by tag. It allows to change
+/// at runtime the verbosity of each subsystem (device, CPU, etc.) which is
+/// logging through a logger created by this module.
+///
+/// The tag of a log record is its slog tag, if specified, or otherwise the
+/// module path that generated it. Tags are automatically registered (with
+/// the default level) the first time they log something, so that they can
+/// be listed and configured (eg: by the debugger).
+pub struct LogLevels {
+    inner: Mutex<LevelsInner>,
+}
+
+lazy_static! {
+    static ref LOG_LEVELS: LogLevels = LogLevels {
+        inner: Mutex::new(LevelsInner {
+            default: FilterLevel::Trace,
+            tags: BTreeMap::new(),
+        }),
+    };
+}
+
+/// Access the global registry of log levels, used by all loggers created
+/// through this module.
+pub fn log_levels() -> &'static LogLevels {
+    &LOG_LEVELS
+}
+
+/// Return the tag of a record, as used by [`LogLevels`](struct.LogLevels.html).
+pub fn record_tag<'a>(record: &'a Record) -> &'a str {
+    if !record.tag().is_empty() {
+        record.tag()
+    } else {
+        record.module()
+    }
+}
+
+impl LogLevels {
+    /// Level used by tags that were not explicitly configured.
+    pub fn default_level(&self) -> FilterLevel {
+        self.inner.lock().unwrap().default
+    }
+
+    /// Change the default level. Tags which were configured to a level
+    /// different from the previous default are not affected.
+    pub fn set_default_level(&self, level: FilterLevel) {
+        let mut inner = self.inner.lock().unwrap();
+        let old = inner.default;
+        inner.default = level;
+        for lvl in inner.tags.values_mut() {
+            if *lvl == old {
+                *lvl = level;
+            }
+        }
+    }
+
+    pub fn level(&self, tag: &str) -> FilterLevel {
+        let inner = self.inner.lock().unwrap();
+        inner.tags.get(tag).cloned().unwrap_or(inner.default)
+    }
+
+    pub fn set_level(&self, tag: &str, level: FilterLevel) {
+        self.inner
+            .lock()
+            .unwrap()
+            .tags
+            .insert(tag.to_owned(), level);
+    }
+
+    /// Return all known tags, with their current level, sorted by name.
+    pub fn tags(&self) -> Vec<(String, FilterLevel)> {
+        let inner = self.inner.lock().unwrap();
+        inner.tags.iter().map(|(t, l)| (t.clone(), *l)).collect()
+    }
+
+    /// Check whether a record with the specified tag and level must be
+    /// logged. The tag is registered if it was not known yet.
+    pub fn enabled(&self, tag: &str, level: Level) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let filter = match inner.tags.get(tag) {
+            Some(filter) => *filter,
+            None => {
+                let filter = inner.default;
+                inner.tags.insert(tag.to_owned(), filter);
+                filter
+            }
+        };
+        filter.accepts(level)
+    }
+}
+
+/// LevelsDrain is a drain that filters records according to the global
+/// registry of log levels.
+pub(crate) struct LevelsDrain<D: Drain> {
+    drain: D,
+}
+
+impl<D: Drain> LevelsDrain<D> {
+    pub(crate) fn new(drain: D) -> Self {
+        Self { drain }
+    }
+}
+
+impl<D: Drain> Drain for LevelsDrain<D> {
+    type Ok = Option<D::Ok>;
+    type Err = D::Err;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        if log_levels().enabled(record_tag(record), record.level()) {
+            self.drain.log(record, values).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        let levels = LogLevels {
+            inner: Mutex::new(LevelsInner {
+                default: FilterLevel::Info,
+                tags: BTreeMap::new(),
+            }),
+        };
+
+        assert!(levels.enabled("a", Level::Info));
+        assert!(!levels.enabled("a", Level::Debug));
+        assert_eq!(levels.tags(), vec![("a".to_owned(), FilterLevel::Info)]);
+
+        levels.set_level("b", FilterLevel::Trace);
+        assert!(levels.enabled("b", Level::Trace));
+        assert!(!levels.enabled("a", Level::Trace));
+
+        // Changing the default only affects tags that were not configured
+        levels.set_default_level(FilterLevel::Error);
+        assert_eq!(levels.level("a"), FilterLevel::Error);
+        assert_eq!(levels.level("b"), FilterLevel::Trace);
+        assert_eq!(levels.level("c"), FilterLevel::Error);
+        assert!(!levels.enabled("c", Level::Warning));
+    }
+}
@@ -1,10 +1,10 @@
 use rusqlite::{params, Connection, Result, Row, NO_PARAMS};
 use slog;
-use slog::{o, Drain, Logger};
+use slog::{o, Drain, Duplicate, Logger};
 
 use super::{
-    LogDrain, LogPrinter, LogRecordPrinter, Record, ThreadSafeTimestampFn, KEY_FRAME, KEY_PC,
-    KEY_SUBSYSTEM,
+    json_drain, new_ring_drain, record_tag, LevelsDrain, LogDrain, LogPrinter, LogRecordPrinter,
+    Record, ThreadSafeTimestampFn, KEY_FRAME, KEY_PC, KEY_SUBSYSTEM,
 };
 
 use std::collections::hash_map::{Entry, HashMap};
@@ -426,11 +426,7 @@ impl LogRecordPrinter for PoolRecordPrinter {
         let mut pool = self.pool.lock().unwrap();
         self.line.level = record.level().as_usize().try_into().unwrap();
 
-        let tag = if !record.tag().is_empty() {
-            record.tag()
-        } else {
-            record.module()
-        };
+        let tag = record_tag(record);
 
         let num_modules = pool.modules.len();
         let mut insert = false;
@@ -511,14 +507,34 @@ impl LogPrinter for PoolPrinter {
 }
 
 /// Create a `slog::Logger` whose output is piped into an in-memory buffer ([`LogPool`](struct.LogPool.html)).
+/// The most recent records are also mirrored into the global [`LogRing`](struct.LogRing.html).
 pub fn new_pool_logger() -> (slog::Logger, LogPoolPtr) {
     let pool = LogPool::new();
     let printer = PoolPrinter::new(pool.clone());
-    let drain = LogDrain::new(printer).build().fuse();
+    let drain = LevelsDrain::new(Duplicate::new(
+        LogDrain::new(printer).build(),
+        new_ring_drain(),
+    ))
+    .fuse();
     let logger = slog::Logger::root(drain, o!());
     (logger, pool)
 }
 
+/// Create a `slog::Logger` like [`new_pool_logger()`](fn.new_pool_logger.html),
+/// that also writes all records as newline-delimited JSON into the specified file.
+pub fn new_pool_json_logger(path: &Path) -> io::Result<(slog::Logger, LogPoolPtr)> {
+    let json = json_drain(path)?;
+    let pool = LogPool::new();
+    let printer = PoolPrinter::new(pool.clone());
+    let drain = LevelsDrain::new(Duplicate::new(
+        Duplicate::new(LogDrain::new(printer).build(), new_ring_drain()),
+        json,
+    ))
+    .fuse();
+    let logger = slog::Logger::root(drain, o!());
+    Ok((logger, pool))
+}
+
 #[cfg(test)]
 mod tests {
     use super::{new_pool_logger, LogPool};
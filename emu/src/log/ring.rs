@@ -0,0 +1,148 @@
+use super::{
+    record_tag, LogDrain, LogPrinter, LogRecordPrinter, Record, ThreadSafeTimestampFn, KEY_FRAME,
+    KEY_PC, KEY_SUBSYSTEM, VALUE_NONE,
+};
+use lazy_static::lazy_static;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::sync::Mutex;
+
+// Number of log records kept in the ring buffer.
+const LOG_RING_SIZE: usize = 16384;
+
+/// A log record stored in the [`LogRing`](struct.LogRing.html).
+#[derive(Default, Clone)]
+pub struct RingLine {
+    pub level: u8,
+    pub frame: u32,
+    pub tag: String,
+    pub sub: Option<String>,
+    pub pc: Option<u64>,
+    pub msg: String,
+    pub kv: String,
+}
+
+struct RingInner {
+    lines: VecDeque<RingLine>,
+    generation: u64,
+}
+
+/// LogRing is an in-memory ring buffer that mirrors the most recent log
+/// records. Contrary to [`LogPool`](struct.LogPool.html), it doesn't index
+/// the records, so it is very cheap and can be used for a quick console.
+pub struct LogRing {
+    inner: Mutex<RingInner>,
+}
+
+lazy_static! {
+    static ref LOG_RING: LogRing = LogRing {
+        inner: Mutex::new(RingInner {
+            lines: VecDeque::with_capacity(LOG_RING_SIZE),
+            generation: 0,
+        }),
+    };
+}
+
+/// Access the global log ring buffer, filled by loggers created with
+/// [`new_pool_logger()`](fn.new_pool_logger.html).
+pub fn log_ring() -> &'static LogRing {
+    &LOG_RING
+}
+
+impl LogRing {
+    fn push(&self, line: RingLine) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.lines.len() == LOG_RING_SIZE {
+            inner.lines.pop_front();
+        }
+        inner.lines.push_back(line);
+        inner.generation += 1;
+    }
+
+    /// Return a counter that is incremented every time the contents of the
+    /// ring change, to avoid taking useless snapshots.
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().unwrap().generation
+    }
+
+    /// Return a copy of all the lines currently in the ring.
+    pub fn snapshot(&self) -> Vec<RingLine> {
+        self.inner.lock().unwrap().lines.iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.lines.clear();
+        inner.generation += 1;
+    }
+}
+
+pub(crate) struct RingRecordPrinter {
+    line: RingLine,
+    kv: Vec<u8>,
+}
+
+impl LogRecordPrinter for RingRecordPrinter {
+    fn print_header(
+        &mut self,
+        record: &Record,
+        _fn_timestamp: &ThreadSafeTimestampFn<Output = io::Result<()>>,
+    ) -> io::Result<()> {
+        self.line.level = record.level().as_usize() as u8;
+        self.line.tag = record_tag(record).to_owned();
+        self.line.msg = record.msg().to_string();
+        Ok(())
+    }
+
+    fn print_kv<K: fmt::Display, V: fmt::Display>(&mut self, k: K, v: V) -> io::Result<()> {
+        let k = format!("{}", k);
+        let v = format!("{}", v);
+        match k.as_ref() {
+            KEY_FRAME => self.line.frame = v.parse().unwrap_or(0),
+            KEY_PC => {
+                self.line.pc =
+                    u64::from_str_radix(if v.starts_with("0x") { &v[2..] } else { &v }, 16).ok()
+            }
+            KEY_SUBSYSTEM if v != VALUE_NONE => self.line.sub = Some(v),
+            KEY_SUBSYSTEM => {}
+            _ => {
+                if !self.kv.is_empty() {
+                    write!(&mut self.kv, " ")?;
+                }
+                write!(&mut self.kv, "{}={}", k, v)?;
+            }
+        };
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<()> {
+        let mut line = self.line;
+        line.kv = String::from_utf8_lossy(&self.kv).into_owned();
+        log_ring().push(line);
+        Ok(())
+    }
+}
+
+pub(crate) struct RingPrinter;
+
+impl LogPrinter for RingPrinter {
+    type RecordPrinter = RingRecordPrinter;
+
+    fn with_record<F>(&self, _record: &Record, f: F) -> io::Result<()>
+    where
+        F: FnOnce(Self::RecordPrinter) -> io::Result<()>,
+    {
+        f(RingRecordPrinter {
+            line: RingLine::default(),
+            kv: Vec::new(),
+        })
+    }
+}
+
+/// Create a drain that mirrors all records into the global log ring.
+pub(crate) fn new_ring_drain() -> LogDrain<RingPrinter> {
+    LogDrain::new(RingPrinter).build()
+}
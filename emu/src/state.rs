@@ -121,6 +121,28 @@
 //! and a version number. Attempting to deserialize with different magic string
 //! or version number will result in an error.
 //!
+//! ### Versioning and migrations
+//!
+//! Emulators evolve, and the fields that compose the state change over time.
+//! Fields are grouped into "components", which by convention is the prefix of
+//! the field name before the first `::` (eg: field `Ai::fifo` belongs to
+//! component `Ai`). Each component can be registered with an explicit
+//! version number through
+//! [`State::register_component()`](struct.State.html#method.register_component),
+//! which should be bumped whenever its fields change in an incompatible way
+//! (eg: a field changes meaning or type). Components that are not registered
+//! are considered at version 0.
+//!
+//! The version of all registered components is stored in the serialized state.
+//! While deserializing a state created by an older version of a component,
+//! the migrations registered with
+//! [`State::add_migration()`](struct.State.html#method.add_migration) are
+//! run in sequence to bring the serialized fields up to date, through the
+//! [`StateMigration`](struct.StateMigration.html) helper. If a migration is
+//! missing, or the state was created by a newer version of a component, the
+//! deserialization fails with an error that mentions the offending component,
+//! without touching the current state.
+//!
 //! Serialization is currently performed using the
 //! [MessagePack](https://msgpack.org) format, and then compressed using
 //! [LZ4](https://www.lz4.org), but this is considered an implementation detail.
@@ -129,12 +151,23 @@
 //! compression algorithm) will be gracefully handled without breaking
 //! previously serialized states.
 //!
+//! ### Savestates
+//!
+//! Some components inevitably keep data outside of the state: caches, data
+//! derived from fields, or pointers into memory areas. These components can
+//! implement the [`Snapshotable`](trait.Snapshotable.html) trait to be
+//! notified before a state is saved and after it is loaded.
+//! [`save_state()`](fn.save_state.html) and
+//! [`load_state()`](fn.load_state.html) wrap serialization of the current
+//! state and invoke the hooks on the specified components.
+//!
 
 use crate::memint::{ByteOrderCombiner, MemInt};
 
 use futures::*;
 use lz4;
 use rmp_serde;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_bytes;
 
@@ -160,8 +193,38 @@ enum SerializationFailure {
     InvalidMagic { magic: String },
     #[fail(display = "invalid version: {}", version)]
     InvalidVersion { version: u32 },
+
+    #[fail(display = "unsupported state serialization format: {}", format)]
+    UnsupportedFormat { format: u8 },
+    #[fail(
+        display = "component {} is saved with version {}, but only version {} is supported",
+        component, version, supported
+    )]
+    ComponentTooNew {
+        component: String,
+        version: u32,
+        supported: u32,
+    },
+    #[fail(
+        display = "component {} cannot be migrated from version {}",
+        component, version
+    )]
+    MissingMigration { component: String, version: u32 },
+    #[fail(
+        display = "component {} is at version {}, but legacy states cannot be migrated",
+        component, version
+    )]
+    LegacyFormat { component: String, version: u32 },
 }
 
+// Version of the serialization stream format, stored in the last byte
+// of the header.
+//   0: initial version
+//   1: added component versions, and fields are serialized as opaque
+//      byte buffers (so that they can be migrated).
+const HEADER: &'static [u8] = b"EMUSTATE";
+const FORMAT_VERSION: u8 = 1;
+
 // Global per-thread state. Notice that we use #[thread_local] rather than
 // thread_local!() as it's much faster at accessing the state, and also allows
 // non-scoped access (that is, without `with`).
@@ -678,6 +741,67 @@ impl FieldInfo {
     }
 }
 
+type MigrationFn = Box<dyn Fn(&mut StateMigration) -> Result<(), Error>>;
+
+// ComponentInfo contains the current version of a component, and the
+// migrations that bring it there from previous versions.
+struct ComponentInfo {
+    version: u32,
+    migrations: BTreeMap<u32, MigrationFn>, // from version => migration
+}
+
+/// `StateMigration` gives access to the fields of a serialized state while it
+/// is being migrated to a newer component version (see
+/// [`State::add_migration()`](struct.State.html#method.add_migration)).
+///
+/// Fields are identified by name, and can be renamed, removed, or converted
+/// to a different type by reading them with the old type and writing them
+/// back with the new one. Fields that are still missing after all migrations
+/// have run simply keep their current value in the `State`.
+pub struct StateMigration {
+    fields: BTreeMap<String, Vec<u8>>,
+}
+
+impl StateMigration {
+    /// Return true if the specified field is present in the serialized state.
+    pub fn contains(&self, name: &str) -> bool {
+        self.fields.contains_key(name)
+    }
+
+    /// Rename a field. Nothing happens if the field does not exist.
+    pub fn rename(&mut self, old: &str, new: &str) {
+        if let Some(data) = self.fields.remove(old) {
+            self.fields.insert(new.to_owned(), data);
+        }
+    }
+
+    /// Remove a field, so that it will keep its current value in the `State`.
+    pub fn remove(&mut self, name: &str) {
+        self.fields.remove(name);
+    }
+
+    /// Decode the value of a field. Returns `None` if the field does not
+    /// exist. Notice that byte arrays (`ArrayField<u8>`) must be decoded as
+    /// `serde_bytes::ByteBuf`.
+    pub fn get<F: DeserializeOwned>(&self, name: &str) -> Result<Option<F>, Error> {
+        match self.fields.get(name) {
+            Some(data) => {
+                let mut de = rmp_serde::Deserializer::new(&data[..]);
+                Ok(Some(Deserialize::deserialize(&mut de)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Set the value of a field, creating it if it does not exist.
+    pub fn set<F: Serialize>(&mut self, name: &str, val: &F) -> Result<(), Error> {
+        let mut data = Vec::new();
+        val.serialize(&mut rmp_serde::Serializer::new_named(&mut data))?;
+        self.fields.insert(name.to_owned(), data);
+        Ok(())
+    }
+}
+
 /// State holds a serializable state for the emulator, composed from multiple
 /// fields.
 ///
@@ -692,6 +816,7 @@ impl FieldInfo {
 pub struct State {
     data: Vec<u8>,
     info: Rc<RefCell<BTreeMap<String, FieldInfo>>>,
+    components: Rc<RefCell<BTreeMap<String, ComponentInfo>>>,
 }
 
 #[inline]
@@ -704,6 +829,7 @@ impl State {
         Self {
             data: Vec::with_capacity(1024),
             info: Rc::new(RefCell::new(BTreeMap::default())),
+            components: Rc::new(RefCell::new(BTreeMap::default())),
         }
     }
 
@@ -790,6 +916,50 @@ impl State {
         f
     }
 
+    /// Register a component with its current version. The version must be
+    /// bumped each time the fields of the component change in a way that
+    /// is incompatible with previously-serialized states.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the component was already registered.
+    pub fn register_component(&mut self, name: &str, version: u32) {
+        if self.components.borrow().contains_key(name) {
+            panic!("duplicated component in state: {}", name);
+        }
+        self.components.borrow_mut().insert(
+            name.to_owned(),
+            ComponentInfo {
+                version,
+                migrations: BTreeMap::new(),
+            },
+        );
+    }
+
+    /// Add a migration for the specified component, that converts the
+    /// serialized fields from version `from_version` to `from_version+1`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the component was not registered, or if
+    /// `from_version` is not older than the current version of the component.
+    pub fn add_migration<F>(&mut self, name: &str, from_version: u32, f: F)
+    where
+        F: Fn(&mut StateMigration) -> Result<(), Error> + 'static,
+    {
+        let mut components = self.components.borrow_mut();
+        let ci = components
+            .get_mut(name)
+            .unwrap_or_else(|| panic!("migration for unregistered component: {}", name));
+        if from_version >= ci.version {
+            panic!(
+                "invalid migration for component {} from version {}",
+                name, from_version
+            );
+        }
+        ci.migrations.insert(from_version, Box::new(f));
+    }
+
     /// The size of the state, in bytes.
     pub fn len(&self) -> usize {
         self.data.len()
@@ -821,19 +991,32 @@ impl State {
         use serde::Serializer;
 
         // Write the header
-        let header = "EMUSTATE\x00";
-        writer.write(header.as_bytes())?;
+        writer.write(HEADER)?;
+        writer.write(&[FORMAT_VERSION])?;
 
-        // Serialize the whole state like a struct. Each `Field` is a field
-        // of this struct, using its name as name of the struct field.
         let mut output = Vec::new();
         let mut ser = rmp_serde::Serializer::new_named(&mut output);
         ser.serialize_str(magic)?;
         ser.serialize_u32(version)?;
+
+        // Write the version of all components
+        ser.serialize_u32(self.components.borrow().len() as u32)?;
+        for (name, ci) in self.components.borrow().iter() {
+            ser.serialize_str(name)?;
+            ser.serialize_u32(ci.version)?;
+        }
+
+        // Serialize the whole state like a struct. Each `Field` is a field
+        // of this struct, using its name as name of the struct field. Each
+        // value is wrapped into a byte buffer, so that it can be decoded
+        // on its own while migrating.
         ser.serialize_u32(self.info.borrow().len() as u32)?;
+        let mut fdata = Vec::new();
         for fi in self.info.borrow().values() {
+            fdata.clear();
+            (*fi.serialize)(&mut rmp_serde::Serializer::new_named(&mut fdata), &self)?;
             ser.serialize_str(&fi.name)?;
-            (*fi.serialize)(&mut ser, &self)?;
+            ser.serialize_bytes(&fdata)?;
         }
 
         // Compress the output
@@ -849,6 +1032,10 @@ impl State {
     /// Notice that any field not present in the serialized state
     /// maintain their current value, and no error is returned. It is thus
     /// suggested to deserialize over a default initial state.
+    ///
+    /// If the serialized state was created by older versions of some
+    /// components, the registered migrations are run. If the state cannot be
+    /// migrated, an error is returned and the state is left untouched.
     pub fn deserialize<R: io::Read>(
         &mut self,
         mut reader: R,
@@ -857,9 +1044,13 @@ impl State {
     ) -> Result<(), Error> {
         let mut header = vec![0u8; 9];
         reader.read_exact(&mut header)?;
-        if header != "EMUSTATE\x00".as_bytes() {
+        if &header[..8] != HEADER {
             return Err(SerializationFailure::InvalidFormat.into());
         }
+        let format = header[8];
+        if format > FORMAT_VERSION {
+            return Err(SerializationFailure::UnsupportedFormat { format }.into());
+        }
 
         let mut buf = Vec::new();
         reader.read_to_end(&mut buf)?;
@@ -877,13 +1068,86 @@ impl State {
             return Err(SerializationFailure::InvalidVersion { version }.into());
         }
 
+        if format == 0 {
+            return self.deserialize_legacy(&mut de);
+        }
+
+        let num_components: u32 = Deserialize::deserialize(&mut de)?;
+        let mut versions = BTreeMap::new();
+        for _ in 0..num_components {
+            let name: String = Deserialize::deserialize(&mut de)?;
+            let version: u32 = Deserialize::deserialize(&mut de)?;
+            versions.insert(name, version);
+        }
+
         let num_fields: u32 = Deserialize::deserialize(&mut de)?;
-        let info = self.info.clone(); // avoid borrowing self
+        let mut mig = StateMigration {
+            fields: BTreeMap::new(),
+        };
         for _ in 0..num_fields {
             let fname: String = Deserialize::deserialize(&mut de)?;
+            let fdata: serde_bytes::ByteBuf = Deserialize::deserialize(&mut de)?;
+            mig.fields.insert(fname, fdata.into_vec());
+        }
+
+        // Bring all components up to date before touching the state.
+        for (name, ci) in self.components.borrow().iter() {
+            let saved = versions.get(name).cloned().unwrap_or(0);
+            if saved > ci.version {
+                return Err(SerializationFailure::ComponentTooNew {
+                    component: name.clone(),
+                    version: saved,
+                    supported: ci.version,
+                }
+                .into());
+            }
+            for v in saved..ci.version {
+                match ci.migrations.get(&v) {
+                    Some(m) => m(&mut mig)?,
+                    None => {
+                        return Err(SerializationFailure::MissingMigration {
+                            component: name.clone(),
+                            version: v,
+                        }
+                        .into());
+                    }
+                }
+            }
+        }
+
+        let info = self.info.clone(); // avoid borrowing self
+        for (fname, fdata) in mig.fields.iter() {
+            if let Some(fi) = info.borrow_mut().get_mut(fname) {
+                let mut fde = rmp_serde::Deserializer::new(&fdata[..]);
+                (*fi.deserialize)(&mut fde, self)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Deserialize a state in the initial stream format, which did not
+    // contain component versions. Since fields are not delimited, they
+    // cannot be migrated: we can only load them if no component has
+    // been versioned yet.
+    fn deserialize_legacy<'de>(&mut self, de: &mut Deser<'de>) -> Result<(), Error> {
+        for (name, ci) in self.components.borrow().iter() {
+            if ci.version != 0 {
+                return Err(SerializationFailure::LegacyFormat {
+                    component: name.clone(),
+                    version: ci.version,
+                }
+                .into());
+            }
+        }
+
+        let num_fields: u32 = Deserialize::deserialize(&mut *de)?;
+        let info = self.info.clone(); // avoid borrowing self
+        for _ in 0..num_fields {
+            let fname: String = Deserialize::deserialize(&mut *de)?;
             match info.borrow_mut().get_mut(&fname) {
                 Some(fi) => {
-                    (*fi.deserialize)(&mut de, self)?;
+                    (*fi.deserialize)(de, self)?;
                 }
                 None => {}
             };
@@ -902,6 +1166,7 @@ pub struct CompressedState {
     data: RefCell<Vec<u8>>,
     future_data: RefCell<Option<Oneshot<Vec<u8>>>>,
     info: Rc<RefCell<BTreeMap<String, FieldInfo>>>,
+    components: Rc<RefCell<BTreeMap<String, ComponentInfo>>>,
 }
 
 impl CompressedState {
@@ -928,6 +1193,7 @@ impl CompressedState {
             data: RefCell::new(Vec::new()),
             future_data: RefCell::new(Some(p)),
             info: state.info.clone(),
+            components: state.components.clone(),
         }
     }
 
@@ -946,10 +1212,64 @@ impl CompressedState {
         State {
             data: udata,
             info: self.info.clone(),
+            components: self.components.clone(),
         }
     }
 }
 
+/// Snapshotable is implemented by emulator components that keep part of
+/// their status outside of the [`State`](struct.State.html), and thus need to
+/// be notified when a savestate is created or restored.
+///
+/// Components whose status is entirely stored in fields do not need to
+/// implement this trait.
+pub trait Snapshotable {
+    /// Called right before the current state is serialized. Components
+    /// can use it to copy into fields any data that must be persisted.
+    fn before_save(&mut self) {}
+
+    /// Called right after a state has been made current. Components can use
+    /// it to restore data saved by `before_save()`, and to recompute any
+    /// data derived from fields.
+    fn after_load(&mut self) {}
+}
+
+/// Save the current state into a savestate, using the serialization format
+/// of [`State::serialize()`](struct.State.html#method.serialize).
+/// `before_save()` is called on all the specified components first.
+pub fn save_state<W: io::Write>(
+    writer: W,
+    magic: &str,
+    version: u32,
+    components: &mut [&mut dyn Snapshotable],
+) -> Result<(), Error> {
+    for c in components.iter_mut() {
+        c.before_save();
+    }
+    CurrentState().serialize(writer, magic, version)
+}
+
+/// Load a savestate into the current state, and then call `after_load()` on
+/// all the specified components.
+///
+/// The savestate is deserialized over a copy of the current state, which is
+/// made current only if the whole process succeeds: if an error is returned,
+/// the current state is left untouched.
+pub fn load_state<R: io::Read>(
+    reader: R,
+    magic: &str,
+    version: u32,
+    components: &mut [&mut dyn Snapshotable],
+) -> Result<(), Error> {
+    let mut state = CurrentState().clone();
+    state.deserialize(reader, magic, version)?;
+    state.make_current();
+    for c in components.iter_mut() {
+        c.after_load();
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1152,6 +1472,78 @@ mod tests {
         assert_eq!(e[3], 3);
     }
 
+    #[test]
+    fn serialize_migration() {
+        let mut a = Field::new("Foo::a", 4u32);
+        let mut b = Field::new("Foo::b", 0u64);
+        CurrentState().register_component("Foo", 1);
+
+        let mut s1 = Vec::new();
+        CurrentState().serialize(&mut s1, "test", 1).unwrap();
+
+        // A newer version of the component cannot be loaded by older code.
+        let mut s2 = CurrentState().clone();
+        s2.components.borrow_mut().get_mut("Foo").unwrap().version = 0;
+        assert!(s2.deserialize(&s1[..], "test", 1).is_err());
+
+        // Bump the version: without a migration, loading fails.
+        CurrentState()
+            .components
+            .borrow_mut()
+            .get_mut("Foo")
+            .unwrap()
+            .version = 2;
+        *a = 0;
+        assert!(CurrentState().deserialize(&s1[..], "test", 1).is_err());
+        assert_eq!(*a, 0);
+
+        // Version 2 renamed "a" to "b" and changed its type to u64.
+        CurrentState().add_migration("Foo", 1, |mig| {
+            let a: u32 = mig.get("Foo::a")?.unwrap();
+            mig.remove("Foo::a");
+            mig.set("Foo::b", &(a as u64 * 2))?;
+            Ok(())
+        });
+        CurrentState().deserialize(&s1[..], "test", 1).unwrap();
+        assert_eq!(*a, 0);
+        assert_eq!(*b, 8);
+    }
+
+    #[test]
+    fn save_load_state() {
+        struct Comp {
+            field: Field<u32>,
+            cache: u32,
+        }
+        impl Snapshotable for Comp {
+            fn before_save(&mut self) {
+                *self.field = self.cache;
+            }
+            fn after_load(&mut self) {
+                self.cache = *self.field;
+            }
+        }
+
+        let mut c = Comp {
+            field: Field::new("comp", 0),
+            cache: 12,
+        };
+
+        let mut bin = Vec::new();
+        save_state(&mut bin, "test", 1, &mut [&mut c]).unwrap();
+        assert_eq!(*c.field, 12);
+
+        c.cache = 34;
+        *c.field = 34;
+        assert!(load_state(&bin[..], "test", 2, &mut [&mut c]).is_err());
+        assert_eq!(*c.field, 34);
+        assert_eq!(c.cache, 34);
+
+        load_state(&bin[..], "test", 1, &mut [&mut c]).unwrap();
+        assert_eq!(*c.field, 12);
+        assert_eq!(c.cache, 12);
+    }
+
     #[test]
     #[should_panic]
     fn double_state_borrow() {
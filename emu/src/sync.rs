@@ -2,7 +2,12 @@ use slog::*;
 
 use crate::dbg;
 use crate::int::Numerics;
-use crate::log::{KEY_FRAME, KEY_PC, KEY_SUBSYSTEM, VALUE_NONE};
+use crate::log::{KEY_CYCLES, KEY_FRAME, KEY_PC, KEY_SUBSYSTEM, VALUE_NONE};
+use crate::state::{Field, Snapshotable};
+#[cfg(feature = "frontend")]
+use imgui::{im_str, Condition, Window};
+
+use serde_derive::{Deserialize, Serialize};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Event {
@@ -40,11 +45,50 @@ pub trait Subsystem {
 
     // Return the program counter for this subsystem (if any)
     fn pc(&self) -> Option<u64>;
+
+    /// Return the cycle (in subsystem cycles) at which the subsystem will
+    /// raise its next event visible by other subsystems (eg: an interrupt
+    /// at the end of a DMA), if known. All subsystems are synchronized at
+    /// that point, so that they observe the event at the exact cycle.
+    fn next_event(&self) -> Option<i64> {
+        None
+    }
 }
 
 pub trait SyncEmu {
     fn config(&self) -> Config;
     fn subsystem(&self, idx: usize) -> Option<(&mut dyn Subsystem, i64)>;
+
+    /// Return true if all subsystems must be synchronized exactly at the
+    /// specified event (eg: because it raises an interrupt). At other
+    /// events, subsystems might lag behind, up to their sync granularity.
+    fn needs_sync(&self, _evt: Event) -> bool {
+        true
+    }
+}
+
+// Clock multiplier of a subsystem. `base` is the point (in main clock cycles
+// and subsystem cycles) from which the current ratio applies, so that
+// changing the ratio at runtime doesn't make the subsystem jump in time.
+#[derive(Copy, Clone)]
+struct SubClock {
+    ratio: f64,
+    base: (i64, i64),
+}
+
+impl Default for SubClock {
+    fn default() -> Self {
+        Self {
+            ratio: 1.0,
+            base: (0, 0),
+        }
+    }
+}
+
+#[derive(Default, Copy, Clone, Serialize, Deserialize)]
+struct SyncContext {
+    frames: i64,
+    cycles: i64,
 }
 
 pub struct Sync<E: SyncEmu + 'static> {
@@ -53,12 +97,15 @@ pub struct Sync<E: SyncEmu + 'static> {
     logger: slog::Logger,
 
     current_sub: Option<usize>,
-    frames: i64,
-    cycles: i64,
+    ctx: Field<SyncContext>,
     line_cycles: i64,
     frame_cycles: i64,
     frame_syncs: Vec<(i64, Event)>,
     curr_frame: Option<(i64, usize)>,
+    clocks: Vec<SubClock>,
+    granularity: Vec<i64>,
+    rebase: bool,
+    reconfig: bool,
 }
 
 impl<E: SyncEmu + 'static> Sync<E> {
@@ -67,22 +114,39 @@ impl<E: SyncEmu + 'static> Sync<E> {
             cfg: emu.config(),
             emu,
             logger,
-            frames: 0,
-            cycles: 0,
+            ctx: Field::new("sync", SyncContext::default()),
             current_sub: None,
             line_cycles: 0,
             frame_cycles: 0,
             frame_syncs: vec![],
             curr_frame: None,
+            clocks: vec![],
+            granularity: vec![],
+            rebase: false,
+            reconfig: false,
         });
         s.calc();
         s
     }
 
+    /// Access the emulator-specific synchronization configuration.
+    pub fn emu_mut(&mut self) -> &mut E {
+        &mut self.emu
+    }
+
+    /// Reload the configuration from the emulator (eg: after the emulated
+    /// machine switched to a different video mode). Frames can't change
+    /// their geometry while being emulated, so the new configuration takes
+    /// effect from the beginning of the next frame.
+    pub fn reconfigure(&mut self) {
+        self.reconfig = true;
+    }
+
     pub fn new_logger(&self) -> slog::Logger {
         let sync2: *const Self = &*self;
         let sync3: *const Self = &*self;
         let sync4: *const Self = &*self;
+        let sync5: *const Self = &*self;
         self.logger.new(o!(
             KEY_PC => slog::FnValue(move |_| {
                 let sync2 = unsafe { &*sync2 };
@@ -102,6 +166,10 @@ impl<E: SyncEmu + 'static> Sync<E> {
                 let sync4 = unsafe { &*sync4 };
                 sync4.frames()
             }),
+            KEY_CYCLES => slog::FnValue(move |_| {
+                let sync5 = unsafe { &*sync5 };
+                sync5.frame_pos()
+            }),
         ))
     }
 
@@ -130,6 +198,22 @@ impl<E: SyncEmu + 'static> Sync<E> {
         self.frame_syncs.sort_by_key(|k| k.0);
     }
 
+    fn apply_config(&mut self) {
+        self.reconfig = false;
+        let cfg = self.emu.config();
+        if cfg == self.cfg {
+            return;
+        }
+        // Subsystem clocks are relative to the main clock, so they must
+        // restart counting from here if it changes.
+        if cfg.main_clock != self.cfg.main_clock {
+            self.rebase = true;
+        }
+        info!(self.logger, "new sync config"; "hdots" => cfg.hdots, "vdots" => cfg.vdots);
+        self.cfg = cfg;
+        self.calc();
+    }
+
     fn current_sub(&self) -> Option<(&mut dyn Subsystem, i64)> {
         self.current_sub.map(|idx| self.emu.subsystem(idx).unwrap())
     }
@@ -139,27 +223,193 @@ impl<E: SyncEmu + 'static> Sync<E> {
     }
 
     pub fn reset(&mut self) {
-        self.frames = 0;
-        self.cycles = 0;
+        self.ctx.frames = 0;
+        self.ctx.cycles = 0;
         self.curr_frame = None;
+        self.rebase = true;
+    }
+
+    fn clock(&self, idx: usize) -> SubClock {
+        self.clocks.get(idx).copied().unwrap_or_default()
+    }
+
+    // Convert main clock cycles into cycles of the specified subsystem.
+    fn sub_cycles(&self, idx: usize, freq: i64, cycles: i64) -> i64 {
+        let clk = self.clock(idx);
+        let (main_base, sub_base) = clk.base;
+        sub_base
+            + ((cycles - main_base) as f64 * freq as f64 * clk.ratio / self.cfg.main_clock as f64)
+                as i64
+    }
+
+    // Convert cycles of the specified subsystem into main clock cycles.
+    fn main_cycles(&self, idx: usize, freq: i64, cycles: i64) -> i64 {
+        let clk = self.clock(idx);
+        let (main_base, sub_base) = clk.base;
+        main_base
+            + ((cycles - sub_base) as f64 * self.cfg.main_clock as f64 / (freq as f64 * clk.ratio))
+                as i64
+    }
+
+    fn find_sub(&self, name: &str) -> Option<usize> {
+        let mut idx = 0;
+        while let Some((sub, _)) = self.emu.subsystem(idx) {
+            if sub.name() == name {
+                return Some(idx);
+            }
+            idx += 1;
+        }
+        None
+    }
+
+    /// Return the clock multiplier of the specified subsystem (1.0 means that
+    /// the subsystem runs at its nominal frequency).
+    pub fn clock_ratio(&self, name: &str) -> Option<f64> {
+        self.find_sub(name).map(|idx| self.clock(idx).ratio)
+    }
+
+    /// Change the clock multiplier of the specified subsystem (eg: 2.0 to
+    /// overclock it at twice its nominal frequency). This can be called at
+    /// any time, also in the middle of a frame: the new ratio only affects
+    /// the cycles that will be emulated from now on. Returns false if there
+    /// is no subsystem with the specified name, or if the ratio is not a
+    /// positive number.
+    pub fn set_clock_ratio(&mut self, name: &str, ratio: f64) -> bool {
+        if !ratio.is_finite() || ratio <= 0.0 {
+            return false;
+        }
+        let idx = match self.find_sub(name) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        if self.rebase {
+            self.do_rebase();
+        }
+        let (_, freq) = self.emu.subsystem(idx).unwrap();
+        let base = (self.ctx.cycles, self.sub_cycles(idx, freq, self.ctx.cycles));
+        if self.clocks.len() <= idx {
+            self.clocks.resize(idx + 1, SubClock::default());
+        }
+        self.clocks[idx] = SubClock { ratio, base };
+        true
+    }
+
+    /// Return the sync granularity of the specified subsystem, in main clock
+    /// cycles.
+    pub fn granularity(&self, name: &str) -> Option<i64> {
+        self.find_sub(name)
+            .map(|idx| self.granularity.get(idx).copied().unwrap_or(0))
+    }
+
+    /// Change the sync granularity of the specified subsystem: the number
+    /// of main clock cycles it can lag behind the others before being run
+    /// again. With 0 (the default), it is run up to every sync event;
+    /// larger values make it run in longer timeslices, which is faster,
+    /// but delays its view of the events that don't need exact timing.
+    /// Returns false if there is no subsystem with the specified name.
+    pub fn set_granularity(&mut self, name: &str, cycles: i64) -> bool {
+        assert!(cycles >= 0, "invalid sync granularity: {}", cycles);
+        let idx = match self.find_sub(name) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        if self.granularity.len() <= idx {
+            self.granularity.resize(idx + 1, 0);
+        }
+        self.granularity[idx] = cycles;
+        true
+    }
+
+    /// Render a debugger window that allows to change the clock multipliers
+    /// and the sync granularity of all subsystems at runtime.
+    #[cfg(feature = "frontend")]
+    pub fn render_debug<'a, 'ui>(&mut self, dr: &dbg::DebuggerRenderer<'a, 'ui>) {
+        let ui = dr.ui();
+        let mut changes = Vec::new();
+        let mut granularity = Vec::new();
+        Window::new(im_str!("Clocks"))
+            .size([360.0, 300.0], Condition::FirstUseEver)
+            .build(ui, || {
+                let mut idx = 0;
+                while let Some((sub, freq)) = self.emu.subsystem(idx) {
+                    let mut ratio = self.clock(idx).ratio as f32;
+                    if ui
+                        .slider_float(&im_str!("{}", sub.name()), &mut ratio, 0.25, 4.0)
+                        .build()
+                    {
+                        changes.push((sub.name().to_owned(), ratio as f64));
+                    }
+                    ui.same_line(0.0);
+                    ui.text(format!("{:.2} MHz", freq as f64 * ratio as f64 / 1e6));
+                    idx += 1;
+                }
+
+                ui.separator();
+                ui.text("Sync granularity (cycles)");
+                let mut idx = 0;
+                while let Some((sub, _)) = self.emu.subsystem(idx) {
+                    let mut cycles = self.granularity.get(idx).copied().unwrap_or(0) as i32;
+                    if ui
+                        .slider_int(&im_str!("{}##sync", sub.name()), &mut cycles, 0, 20_000)
+                        .build()
+                    {
+                        granularity.push((sub.name().to_owned(), cycles as i64));
+                    }
+                    idx += 1;
+                }
+            });
+        for (name, ratio) in changes {
+            self.set_clock_ratio(&name, ratio);
+        }
+        for (name, cycles) in granularity {
+            self.set_granularity(&name, cycles);
+        }
+    }
+
+    // After a reset or a savestate load, the cycle counters of the subsystems
+    // don't match the clock bases anymore, so start counting again from the
+    // current position.
+    fn do_rebase(&mut self) {
+        let mut idx = 0;
+        while let Some((sub, _)) = self.emu.subsystem(idx) {
+            if self.clocks.len() <= idx {
+                self.clocks.push(SubClock::default());
+            }
+            self.clocks[idx].base = (self.ctx.cycles, sub.cycles());
+            idx += 1;
+        }
+        self.rebase = false;
     }
 
     pub fn frames(&self) -> i64 {
-        self.frames
+        self.ctx.frames
+    }
+
+    /// Return true if the emulation was stopped in the middle of a frame
+    /// (eg: by the debugger).
+    pub fn in_frame(&self) -> bool {
+        self.curr_frame.is_some()
     }
 
     pub fn cycles(&self) -> i64 {
         match self.current_sub() {
-            Some((sub, freq)) => {
-                ((sub.cycles() as f64 * self.cfg.main_clock as f64) / freq as f64) as i64
-            }
-            None => self.cycles,
+            Some((sub, freq)) => self.main_cycles(self.current_sub.unwrap(), freq, sub.cycles()),
+            None => self.ctx.cycles,
         }
     }
 
+    /// Return the number of cycles elapsed since the beginning of the
+    /// current frame.
+    pub fn frame_pos(&self) -> i64 {
+        // Frames don't necessarily have all the same length (the
+        // configuration can change), so count from the actual frame start.
+        let frame_start = self.curr_frame.map_or(self.ctx.cycles, |(start, _)| start);
+        self.cycles() - frame_start
+    }
+
     // Return the (x,y) dot position of the emulation in the current frame.
     pub fn dot_pos(&self) -> (usize, usize) {
-        let clk = self.cycles();
+        let clk = self.frame_pos();
         let y = clk / self.line_cycles as i64;
         let x = clk % self.line_cycles as i64;
         (x as usize, y as usize)
@@ -167,16 +417,19 @@ impl<E: SyncEmu + 'static> Sync<E> {
 
     fn do_frame<F: FnMut(Event)>(&mut self, mut cb: F, tracer: &dbg::Tracer) -> dbg::Result<()> {
         if self.curr_frame.is_none() {
+            if self.reconfig {
+                self.apply_config();
+            }
             cb(Event::BeginFrame);
         }
-        let (frame_start, idx) = self.curr_frame.unwrap_or((self.cycles, 0));
+        let (frame_start, idx) = self.curr_frame.unwrap_or((self.ctx.cycles, 0));
         let frame_end = frame_start + self.frame_cycles;
-        assert_eq!(frame_start % self.frame_cycles, 0);
 
         for idx in idx..self.frame_syncs.len() {
             self.curr_frame = Some((frame_start, idx));
             let (cyc, evt) = self.frame_syncs[idx];
-            self.run_until(frame_start + cyc, tracer)?;
+            let exact = self.emu.needs_sync(evt);
+            self.run_until(frame_start + cyc, exact, tracer)?;
             cb(evt);
 
             // Trace GPU lines.
@@ -189,8 +442,8 @@ impl<E: SyncEmu + 'static> Sync<E> {
         }
 
         self.curr_frame = Some((frame_start, self.frame_syncs.len()));
-        self.run_until(frame_end, tracer)?;
-        self.frames = self.frames + 1;
+        self.run_until(frame_end, true, tracer)?;
+        self.ctx.frames += 1;
         self.curr_frame = None;
         cb(Event::EndFrame);
         Ok(())
@@ -206,23 +459,68 @@ impl<E: SyncEmu + 'static> Sync<E> {
         self.do_frame(cb, &dbg::Tracer::null()).unwrap();
     }
 
-    fn run_until(&mut self, target: i64, tracer: &dbg::Tracer) -> dbg::Result<()> {
+    // Return the first event that a subsystem will raise after the last
+    // sync point and before the specified target, in main clock cycles.
+    fn next_sub_event(&self, target: i64) -> Option<i64> {
+        let mut next = None;
         let mut idx: usize = 0;
         while let Some((sub, freq)) = self.emu.subsystem(idx) {
-            self.current_sub = Some(idx);
-            let res = sub.run(
-                (target as f64 * freq as f64 / self.cfg.main_clock as f64) as i64,
-                tracer,
-            );
-            self.current_sub = None;
-            res?;
+            if let Some(evt) = sub.next_event() {
+                // Round up, so that running up to this point reaches the
+                // event in subsystem cycles.
+                let mut cycles = self.main_cycles(idx, freq, evt);
+                if self.sub_cycles(idx, freq, cycles) < evt {
+                    cycles += 1;
+                }
+                if cycles > self.ctx.cycles && cycles < target {
+                    next = Some(next.map_or(cycles, |n: i64| n.min(cycles)));
+                }
+            }
             idx += 1;
         }
-        self.cycles = target;
+        next
+    }
+
+    // Run the subsystems up to the specified target. Events raised by the
+    // subsystems in the meantime are exact sync points; otherwise, unless
+    // `exact` is set, subsystems are only run if they lag behind the target
+    // by at least their sync granularity.
+    fn run_until(&mut self, target: i64, exact: bool, tracer: &dbg::Tracer) -> dbg::Result<()> {
+        if self.rebase {
+            self.do_rebase();
+        }
+        while let Some(evt) = self.next_sub_event(target) {
+            self.run_slice(evt, true, tracer)?;
+        }
+        self.run_slice(target, exact, tracer)
+    }
+
+    fn run_slice(&mut self, target: i64, exact: bool, tracer: &dbg::Tracer) -> dbg::Result<()> {
+        let mut idx: usize = 0;
+        while let Some((sub, freq)) = self.emu.subsystem(idx) {
+            let granularity = self.granularity.get(idx).copied().unwrap_or(0);
+            if exact || target - self.main_cycles(idx, freq, sub.cycles()) >= granularity {
+                self.current_sub = Some(idx);
+                let res = sub.run(self.sub_cycles(idx, freq, target), tracer);
+                self.current_sub = None;
+                res?;
+            }
+            idx += 1;
+        }
+        self.ctx.cycles = target;
         Ok(())
     }
 }
 
+impl<E: SyncEmu + 'static> Snapshotable for Sync<E> {
+    fn after_load(&mut self) {
+        // Savestates are only created between frames, so the loaded state
+        // always begins a new frame.
+        self.curr_frame = None;
+        self.rebase = true;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -230,15 +528,58 @@ mod tests {
 
     struct FakeEmu {
         cfg: Config,
+        sub: Option<*mut FakeSub>,
+        // Only line needing exact sync (all lines, if None).
+        exact_line: Option<usize>,
     }
 
     impl SyncEmu for FakeEmu {
         fn config(&self) -> Config {
             self.cfg.clone()
         }
-        fn subsystem(&self, _idx: usize) -> Option<(&mut dyn Subsystem, i64)> {
+        fn subsystem(&self, idx: usize) -> Option<(&mut dyn Subsystem, i64)> {
+            match (idx, self.sub) {
+                (0, Some(sub)) => Some((unsafe { &mut *sub }, 64)),
+                _ => None,
+            }
+        }
+        fn needs_sync(&self, evt: Event) -> bool {
+            match evt {
+                Event::HSync(_, y) => self.exact_line.map_or(true, |line| line == y),
+                _ => true,
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeSub {
+        cycles: i64,
+        targets: Vec<i64>,
+        event: Option<i64>,
+    }
+
+    impl Subsystem for FakeSub {
+        fn name(&self) -> &str {
+            "fake"
+        }
+        fn run(&mut self, target_cycles: i64, _tracer: &dbg::Tracer) -> dbg::Result<()> {
+            self.cycles = self.cycles.max(target_cycles);
+            self.targets.push(target_cycles);
+            Ok(())
+        }
+        fn step(&mut self, _tracer: &dbg::Tracer) -> dbg::Result<()> {
+            self.cycles += 1;
+            Ok(())
+        }
+        fn cycles(&self) -> i64 {
+            self.cycles
+        }
+        fn pc(&self) -> Option<u64> {
             None
         }
+        fn next_event(&self) -> Option<i64> {
+            self.event.filter(|evt| *evt > self.cycles)
+        }
     }
 
     #[test]
@@ -254,6 +595,8 @@ mod tests {
                     hsyncs: vec![0, 2],
                     vsyncs: vec![2],
                 },
+                sub: None,
+                exact_line: None,
             },
         );
 
@@ -282,4 +625,124 @@ mod tests {
             events.iter().map(|(_, evt)| *evt).collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn clock_ratio() {
+        let mut sub = FakeSub::default();
+        let mut sync = Sync::new(
+            new_console_logger(),
+            FakeEmu {
+                cfg: Config {
+                    main_clock: 128,
+                    dot_clock_divider: 2,
+                    hdots: 4,
+                    vdots: 4,
+                    hsyncs: vec![0],
+                    vsyncs: vec![],
+                },
+                sub: Some(&mut sub as *mut _),
+                exact_line: None,
+            },
+        );
+
+        // Frames are 32 cycles long; the subsystem runs at half the main clock.
+        sync.run_frame(|_| {});
+        assert_eq!(sub.cycles, 16);
+        assert_eq!(sync.clock_ratio("fake"), Some(1.0));
+
+        // Overclocking only affects the cycles emulated from now on.
+        assert!(sync.set_clock_ratio("fake", 2.0));
+        assert!(!sync.set_clock_ratio("missing", 2.0));
+        assert!(!sync.set_clock_ratio("fake", 0.0));
+        assert!(!sync.set_clock_ratio("fake", std::f64::NAN));
+        assert_eq!(sync.clock_ratio("fake"), Some(2.0));
+        sync.run_frame(|_| {});
+        assert_eq!(sub.cycles, 16 + 32);
+
+        assert!(sync.set_clock_ratio("fake", 0.5));
+        sync.run_frame(|_| {});
+        assert_eq!(sub.cycles, 16 + 32 + 8);
+
+        // After a reset, all counters restart from zero.
+        sub.cycles = 0;
+        sync.reset();
+        sync.run_frame(|_| {});
+        assert_eq!(sub.cycles, 8);
+    }
+
+    #[test]
+    fn reconfigure() {
+        let mut sub = FakeSub::default();
+        let mut sync = Sync::new(
+            new_console_logger(),
+            FakeEmu {
+                cfg: Config {
+                    main_clock: 128,
+                    dot_clock_divider: 2,
+                    hdots: 4,
+                    vdots: 4,
+                    hsyncs: vec![0],
+                    vsyncs: vec![],
+                },
+                sub: Some(&mut sub as *mut _),
+                exact_line: None,
+            },
+        );
+        sync.run_frame(|_| {});
+        assert_eq!(sync.cycles(), 32);
+
+        // A new configuration is only applied at the next frame boundary.
+        sync.emu_mut().cfg.vdots = 6;
+        sync.reconfigure();
+        let mut lines = Vec::new();
+        sync.run_frame(|evt| {
+            if let Event::HSync(_, y) = evt {
+                lines.push(y);
+            }
+        });
+        assert_eq!(lines, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(sync.cycles(), 32 + 48);
+        assert_eq!(sync.frame_pos(), 0);
+        assert_eq!(sub.cycles, 40);
+    }
+
+    #[test]
+    fn granularity() {
+        let mut sub = FakeSub::default();
+        let mut sync = Sync::new(
+            new_console_logger(),
+            FakeEmu {
+                cfg: Config {
+                    main_clock: 128,
+                    dot_clock_divider: 2,
+                    hdots: 4,
+                    vdots: 4,
+                    hsyncs: vec![0],
+                    vsyncs: vec![],
+                },
+                sub: Some(&mut sub as *mut _),
+                exact_line: Some(2),
+            },
+        );
+        assert_eq!(sync.granularity("fake"), Some(0));
+
+        // By default, the subsystem runs up to every event.
+        sync.run_frame(|_| {});
+        assert_eq!(sub.targets, vec![0, 4, 8, 12, 16]);
+
+        // With a coarser granularity, it only runs when lagging behind
+        // enough, or at events that need exact sync (line 2 and the end of
+        // the frame).
+        assert!(sync.set_granularity("fake", 12));
+        assert!(!sync.set_granularity("missing", 12));
+        sub.targets.clear();
+        sync.run_frame(|_| {});
+        assert_eq!(sub.targets, vec![24, 32]);
+
+        // Events raised by subsystems are exact sync points.
+        sub.targets.clear();
+        sub.event = Some(37);
+        sync.run_frame(|_| {});
+        assert_eq!(sub.targets, vec![37, 40, 48]);
+    }
 }
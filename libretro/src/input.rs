@@ -0,0 +1,96 @@
+use emu::input::{InputEvent, InputManager};
+use libretro_sys::*;
+
+// Only the first controller is connected.
+const JOY_NAME: &'static str = "joy1";
+
+// RetroPad buttons mapped to the controller buttons (RetroPad id, N64 input,
+// description), with the same layout used by other N64 cores: the N64 A and B
+// buttons are on the bottom and left face buttons. Descriptions are passed to
+// the frontend as C strings.
+pub(crate) const BUTTONS: [(u32, &'static str, &'static str); 10] = [
+    (DEVICE_ID_JOYPAD_UP, "up", "D-Pad Up\0"),
+    (DEVICE_ID_JOYPAD_DOWN, "down", "D-Pad Down\0"),
+    (DEVICE_ID_JOYPAD_LEFT, "left", "D-Pad Left\0"),
+    (DEVICE_ID_JOYPAD_RIGHT, "right", "D-Pad Right\0"),
+    (DEVICE_ID_JOYPAD_B, "A", "A\0"),
+    (DEVICE_ID_JOYPAD_Y, "B", "B\0"),
+    (DEVICE_ID_JOYPAD_L2, "Z", "Z Trigger\0"),
+    (DEVICE_ID_JOYPAD_L, "L", "L Trigger\0"),
+    (DEVICE_ID_JOYPAD_R, "R", "R Trigger\0"),
+    (DEVICE_ID_JOYPAD_START, "S", "Start\0"),
+];
+
+// The C buttons are mapped to the right analog stick: each direction is
+// pressed when the stick is pushed past this threshold.
+const C_THRESHOLD: i16 = 0x4000;
+pub(crate) const C_BUTTONS: [(&'static str, &'static str); 4] = [
+    ("c-up", "C Up\0"),
+    ("c-down", "C Down\0"),
+    ("c-left", "C Left\0"),
+    ("c-right", "C Right\0"),
+];
+
+/// PadState tracks the state of the RetroPad, so that only changes are
+/// forwarded to the input manager.
+#[derive(Default)]
+pub(crate) struct PadState {
+    buttons: [bool; BUTTONS.len()],
+    cbuttons: [bool; C_BUTTONS.len()],
+    stick: (i16, i16),
+}
+
+impl PadState {
+    /// Poll the RetroPad through the input state callback, and send the
+    /// changed inputs to the input manager.
+    pub(crate) fn update<F>(&mut self, im: &mut InputManager, state: F)
+    where
+        F: Fn(u32, u32, u32) -> i16,
+    {
+        let mut evts = Vec::new();
+
+        for (idx, (id, name, _)) in BUTTONS.iter().enumerate() {
+            let down = state(DEVICE_JOYPAD, 0, *id) != 0;
+            if down != self.buttons[idx] {
+                self.buttons[idx] = down;
+                evts.push(InputEvent::Digital(JOY_NAME.into(), (*name).into(), down));
+            }
+        }
+
+        let cx = state(DEVICE_ANALOG, DEVICE_INDEX_ANALOG_RIGHT, DEVICE_ID_ANALOG_X);
+        let cy = state(DEVICE_ANALOG, DEVICE_INDEX_ANALOG_RIGHT, DEVICE_ID_ANALOG_Y);
+        let cdown = [
+            cy < -C_THRESHOLD,
+            cy > C_THRESHOLD,
+            cx < -C_THRESHOLD,
+            cx > C_THRESHOLD,
+        ];
+        for (idx, (name, _)) in C_BUTTONS.iter().enumerate() {
+            if cdown[idx] != self.cbuttons[idx] {
+                self.cbuttons[idx] = cdown[idx];
+                evts.push(InputEvent::Digital(
+                    JOY_NAME.into(),
+                    (*name).into(),
+                    cdown[idx],
+                ));
+            }
+        }
+
+        // On the RetroPad, a negative Y means up, while it means down on the
+        // N64 stick.
+        let x = state(DEVICE_ANALOG, DEVICE_INDEX_ANALOG_LEFT, DEVICE_ID_ANALOG_X);
+        let y = state(DEVICE_ANALOG, DEVICE_INDEX_ANALOG_LEFT, DEVICE_ID_ANALOG_Y);
+        let y = (-(y as i32)).min(i16::max_value() as i32) as i16;
+        if x != self.stick.0 {
+            evts.push(InputEvent::Analog(JOY_NAME.into(), "X".into(), x));
+        }
+        if y != self.stick.1 {
+            evts.push(InputEvent::Analog(JOY_NAME.into(), "Y".into(), y));
+        }
+        self.stick = (x, y);
+
+        for evt in evts {
+            im.process_event(evt);
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! libretro core of r64emu.
+//!
+//! This crate exports the libretro API, so that the emulator can be loaded
+//! by libretro frontends (eg: RetroArch). The emulation core is built without
+//! the "frontend" feature: video, audio and input are all exchanged through
+//! the callbacks registered by the libretro frontend, and savestates are
+//! serialized in memory through the savestate subsystem.
+mod input;
+
+use self::input::{PadState, BUTTONS, C_BUTTONS};
+
+use emu::gfx::{BufferLineGetter, OwnedGfxBufferLE, Rgb888};
+use emu::hw::OutputProducer;
+use emu::snd::{OwnedSndBuffer, S16_STEREO};
+use libretro_sys::*;
+use r64emu::{BootMode, N64};
+use slog::error;
+
+use std::cell::RefCell;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::ptr;
+use std::slice;
+
+const WIDTH: usize = 640;
+const HEIGHT: usize = 480;
+const FPS: f64 = 60.0;
+
+// Callbacks registered by the frontend.
+#[derive(Default)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+// The emulator, once a game has been loaded.
+struct Core {
+    n64: N64,
+    logger: slog::Logger,
+    screen: OwnedGfxBufferLE<Rgb888>,
+    sound: OwnedSndBuffer<S16_STEREO>,
+    pixels: Vec<u32>,
+    samples: Vec<i16>,
+    pad: PadState,
+}
+
+impl Core {
+    fn run_frame(&mut self, cbs: &Callbacks) {
+        if let (Some(poll), Some(state)) = (cbs.input_poll, cbs.input_state) {
+            unsafe { poll() };
+            if let Some(im) = self.n64.input_manager() {
+                self.pad
+                    .update(im, |dev, idx, id| unsafe { state(0, dev, idx, id) });
+            }
+        }
+
+        self.n64
+            .render_frame(&mut self.screen.buf_mut(), &mut self.sound.buf_mut());
+
+        // Convert the framebuffer into XRGB8888 (in host endianness).
+        let screen = self.screen.buf();
+        for y in 0..HEIGHT {
+            let line = screen.line(y);
+            for x in 0..WIDTH {
+                let (r, g, b, _) = line.get(x).components();
+                self.pixels[y * WIDTH + x] = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+            }
+        }
+        if let Some(video_refresh) = cbs.video_refresh {
+            unsafe {
+                video_refresh(
+                    self.pixels.as_ptr() as *const c_void,
+                    WIDTH as c_uint,
+                    HEIGHT as c_uint,
+                    WIDTH * 4,
+                )
+            };
+        }
+
+        let sound = self.sound.buf();
+        self.samples.clear();
+        for i in 0..sound.count() {
+            self.samples.push(sound.get_sample(i, 0));
+            self.samples.push(sound.get_sample(i, 1));
+        }
+        if let Some(audio_sample_batch) = cbs.audio_sample_batch {
+            unsafe { audio_sample_batch(self.samples.as_ptr(), sound.count()) };
+        }
+    }
+
+    fn serialize(&mut self) -> Option<Vec<u8>> {
+        let mut buf = Vec::new();
+        match self.n64.save_state_into(&mut buf) {
+            Ok(()) => Some(buf),
+            Err(err) => {
+                error!(self.logger, "error saving state"; "err" => err.to_string());
+                None
+            }
+        }
+    }
+}
+
+thread_local!(
+    static CALLBACKS: RefCell<Callbacks> = RefCell::new(Callbacks::default());
+    static CORE: RefCell<Option<Core>> = RefCell::new(None);
+);
+
+fn with_core<R, F: FnOnce(&mut Core) -> R>(f: F) -> Option<R> {
+    CORE.with(|core| core.borrow_mut().as_mut().map(f))
+}
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    CALLBACKS.with(|cbs| match cbs.borrow().environment {
+        Some(env) => unsafe { env(cmd, data) },
+        None => false,
+    })
+}
+
+// Describe the RetroPad mapping to the frontend.
+fn set_input_descriptors() {
+    let desc = |device, index, id, text: &'static str| InputDescriptor {
+        port: 0,
+        device,
+        index,
+        id,
+        description: text.as_ptr() as *const c_char,
+    };
+
+    let mut descs = Vec::new();
+    for (id, _, text) in BUTTONS.iter() {
+        descs.push(desc(DEVICE_JOYPAD, 0, *id, text));
+    }
+    for (idx, (_, text)) in C_BUTTONS.iter().enumerate() {
+        let id = [DEVICE_ID_ANALOG_Y, DEVICE_ID_ANALOG_X][idx / 2];
+        descs.push(desc(DEVICE_ANALOG, DEVICE_INDEX_ANALOG_RIGHT, id, text));
+    }
+    let stick = DEVICE_INDEX_ANALOG_LEFT;
+    descs.push(desc(
+        DEVICE_ANALOG,
+        stick,
+        DEVICE_ID_ANALOG_X,
+        "Control Stick X\0",
+    ));
+    descs.push(desc(
+        DEVICE_ANALOG,
+        stick,
+        DEVICE_ID_ANALOG_Y,
+        "Control Stick Y\0",
+    ));
+    descs.push(InputDescriptor {
+        port: 0,
+        device: 0,
+        index: 0,
+        id: 0,
+        description: ptr::null(),
+    });
+    environment(
+        ENVIRONMENT_SET_INPUT_DESCRIPTORS,
+        descs.as_ptr() as *mut c_void,
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: EnvironmentFn) {
+    CALLBACKS.with(|cbs| cbs.borrow_mut().environment = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: VideoRefreshFn) {
+    CALLBACKS.with(|cbs| cbs.borrow_mut().video_refresh = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: AudioSampleFn) {
+    // Audio is always sent in batches.
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: AudioSampleBatchFn) {
+    CALLBACKS.with(|cbs| cbs.borrow_mut().audio_sample_batch = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: InputPollFn) {
+    CALLBACKS.with(|cbs| cbs.borrow_mut().input_poll = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: InputStateFn) {
+    CALLBACKS.with(|cbs| cbs.borrow_mut().input_state = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    CORE.with(|core| *core.borrow_mut() = None);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: "r64emu\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: "n64|v64|z64|bin\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: 4.0 / 3.0,
+        },
+        timing: SystemTiming {
+            fps: FPS,
+            sample_rate: N64::AUDIO_OUTPUT_FREQUENCY as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core(|core| {
+        if let Err(err) = emu::dbg::DebuggerModel::reset(&mut core.n64, true) {
+            error!(core.logger, "error resetting"; "err" => err);
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    CALLBACKS.with(|cbs| with_core(|core| core.run_frame(&cbs.borrow())));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(|core| core.serialize().map_or(0, |s| s.len())).unwrap_or(0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    match with_core(|core| core.serialize()) {
+        Some(Some(state)) if state.len() <= size => {
+            ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+            true
+        }
+        _ => false,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let state = slice::from_raw_parts(data as *const u8, size);
+    with_core(|core| match core.n64.load_state_from(state) {
+        Ok(()) => true,
+        Err(err) => {
+            error!(core.logger, "error loading state"; "err" => err.to_string());
+            false
+        }
+    })
+    .unwrap_or(false)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let rom = slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+
+    let mut fmt = PixelFormat::ARGB8888;
+    if !environment(
+        ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut fmt as *mut PixelFormat as *mut c_void,
+    ) {
+        return false;
+    }
+    set_input_descriptors();
+
+    let logger = emu::log::new_console_logger();
+    let n64 = N64::from_rom_data(logger.clone(), rom).and_then(|mut n64| {
+        n64.setup_boot(BootMode::Hle)?;
+        Ok(n64)
+    });
+    let n64 = match n64 {
+        Ok(n64) => n64,
+        Err(err) => {
+            error!(logger, "cannot load game"; "err" => err.to_string());
+            return false;
+        }
+    };
+
+    let audio_frames = N64::AUDIO_OUTPUT_FREQUENCY as usize / FPS as usize;
+    CORE.with(|core| {
+        *core.borrow_mut() = Some(Core {
+            n64,
+            logger,
+            screen: OwnedGfxBufferLE::new(WIDTH, HEIGHT),
+            sound: OwnedSndBuffer::with_capacity(audio_frames),
+            pixels: vec![0; WIDTH * HEIGHT],
+            samples: Vec::with_capacity(audio_frames * 2),
+            pad: PadState::default(),
+        })
+    });
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const GameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    CORE.with(|core| *core.borrow_mut() = None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
@@ -1,7 +1,13 @@
+use super::dmalog::{self, DmaDevice};
 use super::mi::{IrqMask, Mi};
 use super::r4300::R4300;
+use super::vi::Vi;
 use emu::bus::be::{Device, Reg32};
 use emu::dbg;
+#[cfg(feature = "frontend")]
+use emu::dbg::imgui::*;
+#[cfg(feature = "frontend")]
+use emu::dbg::DebuggerRenderer;
 use emu::int::Numerics;
 use emu::snd::{SampleFormat, SampleInt, SndBuffer, SndBufferMut, S16_STEREO};
 use emu::state::{ArrayField, Field};
@@ -58,6 +64,15 @@ pub struct Ai {
     // the state right now, so after reload there might be some missing samples.
     sndbuffer: Vec<i16>,
 
+    // Debugger information: initial address and length of the DMA buffers
+    // in the FIFO, samples played in the last frame, and number of them
+    // that were silence because the FIFO was empty.
+    dbg_dma: [(u32, u32); 2],
+    dbg_lastframe: Vec<i16>,
+    #[cfg(feature = "frontend")]
+    dbg_silence: usize,
+    dbg_silence_cnt: usize,
+
     logger: slog::Logger,
 }
 
@@ -76,6 +91,11 @@ impl Ai {
             fifo_cur: Field::new("Ai::fifo_cur", 0),
             cycles: Field::new("Ai::cycles", 0),
             sndbuffer: Vec::new(),
+            dbg_dma: [(0, 0); 2],
+            dbg_lastframe: Vec::new(),
+            #[cfg(feature = "frontend")]
+            dbg_silence: 0,
+            dbg_silence_cnt: 0,
             logger,
         })
     }
@@ -121,11 +141,13 @@ impl Ai {
         }
 
         info!(self.logger, "start DMA"; "src" => src.hex(), "len" => len);
+        dmalog::record(DmaDevice::Ai, src, 0x0450_0000, len);
         self.fifo[widx] = AudioFifo {
             src,
             len,
             full: true,
         };
+        self.dbg_dma[widx] = (src, len);
         self.update_status();
     }
 
@@ -152,6 +174,117 @@ impl Ai {
         let buf = SndBuffer::<S16_STEREO>::new_typed(&self.sndbuffer[..]);
         buf.sconv_into(output);
         info!(self.logger, "end frame"; "src" => buf.count(), "dst" => output.count());
+
+        self.dbg_lastframe.clear();
+        self.dbg_lastframe.extend_from_slice(&self.sndbuffer);
+        #[cfg(feature = "frontend")]
+        {
+            self.dbg_silence = self.dbg_silence_cnt;
+        }
+        self.dbg_silence_cnt = 0;
+    }
+
+    // Current DAC sample rate in Hz.
+    #[cfg(feature = "frontend")]
+    fn sample_rate(&self) -> i64 {
+        Vi::get().tv_type().vclk() / (self.reg_dac_sample_period.get() as i64 + 1)
+    }
+
+    // Read a DMA buffer from RDRAM, and convert it into a waveform (mixing
+    // the two channels), for visualization.
+    #[cfg(feature = "frontend")]
+    fn dma_waveform(src: u32, len: u32) -> Vec<f32> {
+        let bus = &R4300::get().bus;
+        (0..len / 4)
+            .map(|i| {
+                let sample = bus.fetch_read_nolog::<u32>(src + i * 4).read();
+                let left = (sample >> 16) as i16 as f32;
+                let right = (sample & 0xFFFF) as i16 as f32;
+                (left + right) / 2.0 / 32768.0
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "frontend")]
+    pub fn render_debug<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>) {
+        let ui = dr.ui();
+        Window::new(im_str!("[AI] Audio buffers"))
+            .size([420.0, 420.0], Condition::FirstUseEver)
+            .build(ui, || {
+                let rate = self.sample_rate();
+                ui.text(format!(
+                    "Sample rate: {} Hz (period: {}, bits: {})",
+                    rate,
+                    self.reg_dac_sample_period.get() + 1,
+                    self.reg_bit_rate.get() + 1
+                ));
+
+                // Fill level: how much audio is still queued in the FIFO.
+                let queued: u32 = self.fifo.iter().filter(|f| f.full).map(|f| f.len).sum();
+                ui.text(format!(
+                    "Queued: {} bytes ({:.1} ms)",
+                    queued,
+                    queued as f64 / 4.0 * 1000.0 / rate.max(1) as f64
+                ));
+                ui.text(format!(
+                    "Silence in last frame: {} samples",
+                    self.dbg_silence
+                ));
+                ui.separator();
+
+                for idx in 0..2 {
+                    let fifo = self.fifo[idx];
+                    let (start, len) = self.dbg_dma[idx];
+                    let cursor = if fifo.full {
+                        fifo.src.wrapping_sub(start).min(len)
+                    } else {
+                        len
+                    };
+                    ui.text(format!(
+                        "FIFO {}{}: {} at {} - {}/{} bytes played",
+                        idx,
+                        if idx == *self.fifo_cur {
+                            " (playing)"
+                        } else {
+                            ""
+                        },
+                        if fifo.full { "FULL" } else { "empty" },
+                        start.hex(),
+                        cursor,
+                        len
+                    ));
+                    if len == 0 {
+                        continue;
+                    }
+                    let wave = Self::dma_waveform(start, len);
+                    ui.plot_lines(&im_str!("###fifo{}", idx), &wave)
+                        .graph_size([0.0, 60.0])
+                        .scale_min(-1.0)
+                        .scale_max(1.0)
+                        .overlay_text(&im_str!(
+                            "cursor: {:.0}%",
+                            cursor as f32 * 100.0 / len as f32
+                        ))
+                        .build();
+                }
+                ui.separator();
+
+                ui.text("Last frame output:");
+                for ch in 0..2 {
+                    let wave: Vec<f32> = self
+                        .dbg_lastframe
+                        .iter()
+                        .skip(ch)
+                        .step_by(2)
+                        .map(|s| *s as f32 / 32768.0)
+                        .collect();
+                    ui.plot_lines(&im_str!("{}", ["Left", "Right"][ch]), &wave)
+                        .graph_size([0.0, 60.0])
+                        .scale_min(-1.0)
+                        .scale_max(1.0)
+                        .build();
+                }
+            });
     }
 }
 
@@ -195,6 +328,7 @@ impl sync::Subsystem for Ai {
             } else {
                 self.sndbuffer.push(i16::MUTE);
                 self.sndbuffer.push(i16::MUTE);
+                self.dbg_silence_cnt += 1;
             }
             *self.cycles += self.reg_dac_sample_period.get() as i64 + 1;
         }
@@ -216,4 +350,15 @@ impl sync::Subsystem for Ai {
     fn pc(&self) -> Option<u64> {
         None // No program counter
     }
+    fn next_event(&self) -> Option<i64> {
+        // The interrupt is raised when the current buffer is finished while
+        // the other one is queued: one audio frame is played per period.
+        let (cur, other) = (&self.fifo[*self.fifo_cur], &self.fifo[*self.fifo_cur ^ 1]);
+        if !cur.full || !other.full {
+            return None;
+        }
+        let frame_bytes = ((self.reg_bit_rate.get() + 1) / 8 * 2).max(1);
+        let frames = ((cur.len + frame_bytes - 1) / frame_bytes) as i64;
+        Some(*self.cycles + frames * (self.reg_dac_sample_period.get() as i64 + 1))
+    }
 }
@@ -1,10 +1,13 @@
 use crate::errors::*;
 use emu::bus::be::{Mem, MemFlags, Reg32};
 
+use byteorder::{BigEndian, ByteOrder};
 use crc::crc32;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
+use std::process::Command;
+use zip::ZipArchive;
 
 #[derive(DeviceBE)]
 pub struct Cartridge {
@@ -18,6 +21,7 @@ pub struct Cartridge {
     drive64_cmd: Reg32,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum CicModel {
     Cic6101 = 6101,
     Cic6102 = 6102,
@@ -26,27 +30,246 @@ pub enum CicModel {
     Cic6106 = 6106,
 }
 
-pub fn romswap(rom: Vec<u8>) -> Vec<u8> {
-    if rom[0] == 0x80 {
-        // ROM is big-endian: nothing to do
-        return rom;
-    } else if rom[1] == 0x80 {
-        // ROM is byteswapped
-        return rom
-            .iter()
-            .enumerate()
-            .map(|(idx, _)| rom[idx ^ 1])
-            .collect();
-    } else {
-        panic!("unsupported ROM format")
+impl CicModel {
+    /// Parse a CIC model from its number (eg: "6105").
+    pub fn from_name(name: &str) -> Result<CicModel> {
+        match name.trim_start_matches("CIC-NUS-") {
+            "6101" => Ok(CicModel::Cic6101),
+            "6102" | "7101" => Ok(CicModel::Cic6102),
+            "6103" | "7103" => Ok(CicModel::Cic6103),
+            "6105" | "7105" => Ok(CicModel::Cic6105),
+            "6106" | "7106" => Ok(CicModel::Cic6106),
+            _ => bail!("unknown CIC model: {}", name),
+        }
+    }
+
+    /// Seed exchanged between CIC and PIF at boot, which is used by IPL3 to
+    /// checksum the game code.
+    pub fn seed(self) -> u32 {
+        match self {
+            CicModel::Cic6101 => 0x3F, // starfox
+            CicModel::Cic6102 => 0x3F, // mario
+            CicModel::Cic6103 => 0x78, // banjo
+            CicModel::Cic6105 => 0x91, // zelda
+            CicModel::Cic6106 => 0x85, // f-zero x
+        }
+    }
+
+    /// The IPL3 of some CIC variants relocates the entry point found in the
+    /// ROM header before jumping to it.
+    pub fn entry_point(self, header_entry: u32) -> u32 {
+        match self {
+            CicModel::Cic6103 => header_entry.wrapping_sub(0x10_0000),
+            CicModel::Cic6106 => header_entry.wrapping_sub(0x20_0000),
+            _ => header_entry,
+        }
+    }
+}
+
+/// Compute the response of the CIC-NUS-6105 to a challenge sent by the game
+/// through PIF. Both challenge and response are sequences of nibbles.
+pub fn cic_6105_response(challenge: &[u8]) -> Vec<u8> {
+    const LUT0: [u8; 16] = [
+        0x4, 0x7, 0xA, 0x7, 0xE, 0x5, 0xE, 0x1, 0xC, 0xF, 0x8, 0xF, 0x6, 0x3, 0x6, 0x9,
+    ];
+    const LUT1: [u8; 16] = [
+        0x4, 0x1, 0xA, 0x7, 0xE, 0x5, 0xE, 0x1, 0xC, 0x9, 0x8, 0x5, 0x6, 0x3, 0xC, 0x9,
+    ];
+
+    let mut key = 0xBu8;
+    let mut lut = &LUT0;
+    let mut response = Vec::with_capacity(challenge.len());
+    for &c in challenge {
+        let r = key.wrapping_add(c.wrapping_mul(5)) & 0xF;
+        key = lut[r as usize];
+        let sgn = (r >> 3) & 1;
+        let mag = if sgn == 1 { !r } else { r } & 7;
+        let mut modifier = if mag % 3 == 1 { sgn } else { 1 - sgn };
+        if lut == &LUT1 && (r == 0x1 || r == 0x9) {
+            modifier = 1;
+        }
+        if lut == &LUT1 && (r == 0xB || r == 0xE) {
+            modifier = 0;
+        }
+        lut = if modifier == 1 { &LUT1 } else { &LUT0 };
+        response.push(r);
+    }
+    response
+}
+
+/// Byte order of a ROM image, as dumped by different devices.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RomFormat {
+    /// Big-endian (.z64), the native format.
+    BigEndian,
+    /// Byteswapped in 16-bit units (.v64).
+    ByteSwapped,
+    /// Little-endian in 32-bit units (.n64).
+    LittleEndian,
+}
+
+impl RomFormat {
+    /// Detect the byte order from the first word of the ROM, which holds
+    /// the PI domain configuration (0x80371240 in all commercial games).
+    pub fn detect(rom: &[u8]) -> Result<RomFormat> {
+        match rom.get(0..4) {
+            Some([0x80, _, _, _]) => Ok(RomFormat::BigEndian),
+            Some([_, 0x80, _, _]) => Ok(RomFormat::ByteSwapped),
+            Some([_, _, _, 0x80]) => Ok(RomFormat::LittleEndian),
+            _ => bail!("unsupported ROM format"),
+        }
+    }
+}
+
+/// Convert a ROM image into big-endian format, detecting its byte order.
+pub fn romswap(mut rom: Vec<u8>) -> Result<Vec<u8>> {
+    match RomFormat::detect(&rom)? {
+        RomFormat::BigEndian => {}
+        RomFormat::ByteSwapped => {
+            for w in rom.chunks_exact_mut(2) {
+                w.swap(0, 1);
+            }
+        }
+        RomFormat::LittleEndian => {
+            for w in rom.chunks_exact_mut(4) {
+                w.reverse();
+            }
+        }
+    }
+    Ok(rom)
+}
+
+const ROM_EXTENSIONS: [&str; 3] = ["z64", "n64", "v64"];
+
+/// Return true if the path is a ROM image or an archive that can be loaded
+/// by the emulator.
+pub fn is_rom_file(path: &Path) -> bool {
+    match extension(path).as_ref().map(|e| e.as_str()) {
+        Some("zip") | Some("7z") => true,
+        Some(ext) => ROM_EXTENSIONS.contains(&ext),
+        None => false,
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+}
+
+// Extract the first ROM image found within a .zip archive.
+fn read_zip(romfn: &Path) -> Result<Vec<u8>> {
+    let mut zip = ZipArchive::new(File::open(romfn)?).chain_err(|| "invalid zip archive")?;
+    for idx in 0..zip.len() {
+        let mut file = zip.by_index(idx).chain_err(|| "invalid zip archive")?;
+        if !is_rom_file(Path::new(file.name())) {
+            continue;
+        }
+        let mut contents = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut contents)?;
+        return Ok(contents);
+    }
+    bail!("no ROM found in archive")
+}
+
+// Extract the first ROM image found within a .7z archive, through the 7z
+// command line tool (which must be available in PATH).
+fn read_7z(romfn: &Path) -> Result<Vec<u8>> {
+    let list = Command::new("7z")
+        .args(&["l", "-slt", "-ba"])
+        .arg(romfn)
+        .output()
+        .chain_err(|| "cannot run 7z")?;
+    if !list.status.success() {
+        bail!("invalid 7z archive");
+    }
+    let list = String::from_utf8_lossy(&list.stdout);
+    let name = match list
+        .lines()
+        .filter(|l| l.starts_with("Path = "))
+        .map(|l| &l[7..])
+        .find(|name| is_rom_file(Path::new(name)))
+    {
+        Some(name) => name.to_owned(),
+        None => bail!("no ROM found in archive"),
+    };
+
+    let out = Command::new("7z")
+        .args(&["e", "-so"])
+        .arg(romfn)
+        .arg(&name)
+        .output()
+        .chain_err(|| "cannot run 7z")?;
+    if !out.status.success() {
+        bail!("cannot extract {} from archive", name);
+    }
+    Ok(out.stdout)
+}
+
+/// Load a ROM file (in any byte order, possibly within a .zip or .7z
+/// archive), and convert it to big-endian format.
+pub fn load_rom(romfn: &Path) -> Result<Vec<u8>> {
+    let contents = match extension(romfn).as_ref().map(|e| e.as_str()) {
+        Some("zip") => read_zip(romfn)?,
+        Some("7z") => read_7z(romfn)?,
+        _ => {
+            let mut contents = vec![];
+            File::open(romfn)?.read_to_end(&mut contents)?;
+            contents
+        }
+    };
+    romswap(contents)
+}
+
+/// Information from the header of a ROM file, that can be read without
+/// loading the whole ROM (eg: to show it in a ROM browser).
+#[derive(Clone, Debug)]
+pub struct RomHeader {
+    pub name: String,
+    pub country: u8,
+    pub crc: (u32, u32),
+}
+
+impl RomHeader {
+    pub fn read(romfn: &Path) -> Result<RomHeader> {
+        let hdr = match extension(romfn).as_ref().map(|e| e.as_str()) {
+            // Archives must be extracted anyway.
+            Some("zip") | Some("7z") => load_rom(romfn)?,
+            _ => {
+                let mut hdr = vec![0u8; 0x40];
+                File::open(romfn)?.read_exact(&mut hdr)?;
+                romswap(hdr)?
+            }
+        };
+        if hdr.len() < 0x40 {
+            bail!("ROM is too small");
+        }
+        Ok(RomHeader {
+            name: hdr[0x20..0x34]
+                .iter()
+                .take_while(|&&c| c != 0)
+                .map(|&c| c as char)
+                .collect::<String>()
+                .trim_end()
+                .to_owned(),
+            country: hdr[0x3E],
+            crc: (
+                BigEndian::read_u32(&hdr[0x10..]),
+                BigEndian::read_u32(&hdr[0x14..]),
+            ),
+        })
     }
 }
 
 impl Cartridge {
     pub fn new(romfn: &Path) -> Result<Box<Cartridge>> {
-        let mut file = File::open(romfn)?;
-        let mut contents = vec![];
-        file.read_to_end(&mut contents)?;
+        Self::from_data(load_rom(romfn)?)
+    }
+
+    /// Create a cartridge from a ROM image already in memory (eg: passed by
+    /// the libretro frontend), in any of the supported byte orders.
+    pub fn from_data(contents: Vec<u8>) -> Result<Box<Cartridge>> {
+        let mut contents = romswap(contents)?;
 
         if !contents.len().is_power_of_two() {
             let newsize = contents.len().next_power_of_two();
@@ -56,11 +279,12 @@ impl Cartridge {
         Ok(Box::new(Cartridge {
             drive64_status: Reg32::default(),
             drive64_cmd: Reg32::default(),
-            rom: Mem::from_buffer("rom", romswap(contents), MemFlags::READACCESS),
+            rom: Mem::from_buffer("rom", contents, MemFlags::READACCESS),
         }))
     }
 
-    // Detect the CIC model by checksumming the header of the ROM.
+    // Detect the CIC model by checksumming the boot code of the ROM (IPL3),
+    // which is different for each CIC variant.
     pub fn detect_cic_model(&self) -> Result<CicModel> {
         match crc32::checksum_ieee(&self.rom[0x40..0x1000]) {
             0x6170A4A1 => Ok(CicModel::Cic6101),
@@ -71,4 +295,135 @@ impl Cartridge {
             chk => bail!("cannot detect CIC model in ROM (chk = {:08x})", chk),
         }
     }
+
+    // Return the game code stored in the ROM header (eg: "NSME"): media
+    // type, two characters identifying the game, and region.
+    pub fn game_code(&self) -> String {
+        self.rom[0x3B..0x3F].iter().map(|&c| c as char).collect()
+    }
+
+    // Return the internal name of the game, as stored in the ROM header.
+    pub fn header_name(&self) -> String {
+        self.rom[0x20..0x34]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as char)
+            .collect::<String>()
+            .trim_end()
+            .to_owned()
+    }
+
+    // Return the country code stored in the ROM header (eg: 'E' for USA,
+    // 'P' for Europe).
+    pub fn country_code(&self) -> u8 {
+        self.rom[0x3E]
+    }
+
+    // Return the CRC pair stored in the ROM header, which is commonly
+    // used to identify a game.
+    pub fn header_crc(&self) -> (u32, u32) {
+        (
+            BigEndian::read_u32(&self.rom[0x10..]),
+            BigEndian::read_u32(&self.rom[0x14..]),
+        )
+    }
+
+    // Return the entry point stored in the ROM header.
+    pub fn header_entry_point(&self) -> u32 {
+        BigEndian::read_u32(&self.rom[0x08..])
+    }
+
+    /// Compute the CRC pair of the game code (the first MiB after the boot
+    /// code), as done by IPL3 at boot to verify the CRC in the ROM header.
+    /// The algorithm depends on the CIC model.
+    pub fn compute_crc(&self, cic: CicModel) -> Result<(u32, u32)> {
+        const START: usize = 0x1000;
+        const LEN: usize = 0x10_0000;
+        if self.rom.len() < START + LEN {
+            bail!("ROM too small to compute its CRC");
+        }
+
+        let seed: u32 = match cic {
+            CicModel::Cic6101 | CicModel::Cic6102 => 0xF8CA_4DDC,
+            CicModel::Cic6103 => 0xA388_6759,
+            CicModel::Cic6105 => 0xDF26_F436,
+            CicModel::Cic6106 => 0x1FEA_617A,
+        };
+        let (mut t1, mut t2, mut t3, mut t4, mut t5, mut t6) = (seed, seed, seed, seed, seed, seed);
+        for i in (START..START + LEN).step_by(4) {
+            let d = BigEndian::read_u32(&self.rom[i..]);
+            if t6.wrapping_add(d) < t6 {
+                t4 = t4.wrapping_add(1);
+            }
+            t6 = t6.wrapping_add(d);
+            t3 ^= d;
+            let r = d.rotate_left(d & 0x1F);
+            t5 = t5.wrapping_add(r);
+            if t2 > d {
+                t2 ^= r;
+            } else {
+                t2 ^= t6 ^ d;
+            }
+            if cic == CicModel::Cic6105 {
+                // 6105 also mixes in the words of a table within its IPL3.
+                let x = BigEndian::read_u32(&self.rom[0x750 + (i & 0xFF)..]);
+                t1 = t1.wrapping_add(x ^ d);
+            } else {
+                t1 = t1.wrapping_add(t5 ^ d);
+            }
+        }
+
+        Ok(match cic {
+            CicModel::Cic6103 => ((t6 ^ t4).wrapping_add(t3), (t5 ^ t2).wrapping_add(t1)),
+            CicModel::Cic6106 => (
+                t6.wrapping_mul(t4).wrapping_add(t3),
+                t5.wrapping_mul(t2).wrapping_add(t1),
+            ),
+            _ => (t6 ^ t4 ^ t3, t5 ^ t2 ^ t1),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // First 8 bytes of a ROM header: PI configuration and clock rate.
+    const Z64: [u8; 8] = [0x80, 0x37, 0x12, 0x40, 0x00, 0x00, 0x00, 0x0F];
+    const V64: [u8; 8] = [0x37, 0x80, 0x40, 0x12, 0x00, 0x00, 0x0F, 0x00];
+    const N64: [u8; 8] = [0x40, 0x12, 0x37, 0x80, 0x0F, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(RomFormat::detect(&Z64).unwrap(), RomFormat::BigEndian);
+        assert_eq!(RomFormat::detect(&V64).unwrap(), RomFormat::ByteSwapped);
+        assert_eq!(RomFormat::detect(&N64).unwrap(), RomFormat::LittleEndian);
+
+        assert!(RomFormat::detect(&[0x12, 0x34, 0x56, 0x78]).is_err());
+        assert!(RomFormat::detect(&[0x80, 0x37, 0x12]).is_err());
+        assert!(RomFormat::detect(&[]).is_err());
+    }
+
+    #[test]
+    fn swap() {
+        assert_eq!(romswap(Z64.to_vec()).unwrap(), Z64.to_vec());
+        assert_eq!(romswap(V64.to_vec()).unwrap(), Z64.to_vec());
+        assert_eq!(romswap(N64.to_vec()).unwrap(), Z64.to_vec());
+        assert!(romswap(vec![0x00; 8]).is_err());
+        assert!(romswap(vec![0x37]).is_err());
+    }
+
+    fn nibbles(s: &str) -> Vec<u8> {
+        s.chars().map(|c| c.to_digit(16).unwrap() as u8).collect()
+    }
+
+    #[test]
+    fn cic_6105() {
+        // Computed with n64_cic_nus_6105() from mupen64plus.
+        assert_eq!(
+            cic_6105_response(&nibbles("B53C9F0E71A2D68403F5E7BC2A9D18")),
+            nibbles("236A50441C86F79370F207886012F1")
+        );
+        assert_eq!(cic_6105_response(&[]), vec![]);
+    }
 }
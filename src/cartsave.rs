@@ -0,0 +1,448 @@
+//! Cartridge save memory.
+//!
+//! Cartridges persist game saves using one of these chips:
+//!
+//!   * EEPROM (4 Kbit or 16 Kbit), accessed by the PIF through the joybus,
+//!     as if it was a fifth controller.
+//!   * SRAM (256 Kbit), battery-backed and mapped in PI domain 2, accessed
+//!     through PI DMA.
+//!   * FlashRAM (1 Mbit), also mapped in PI domain 2, with a command
+//!     register to select the operation mode (read, status, erase, write).
+//!
+//! The save type can't be detected from the cartridge itself, so it's looked
+//! up into the per-game database (see hacks.rs), falling back to 4 Kbit
+//! EEPROM (the most common type). The contents are persisted into a file next to the ROM,
+//! according to the configured flush policy.
+use super::cartridge::Cartridge;
+use super::hacks::GameHacks;
+use emu::backup::{BackupMem, FlushPolicy};
+use emu::bus::be::Reg32;
+use emu::bus::Device;
+use emu::int::Numerics;
+use emu_derive::DeviceBE;
+use slog;
+
+use std::io;
+use std::path::Path;
+
+// Base address of PI domain 2, where SRAM and FlashRAM are mapped.
+pub(crate) const DOMAIN2_BASE: u32 = 0x0800_0000;
+pub(crate) const DOMAIN2_END: u32 = 0x1000_0000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SaveType {
+    None,
+    Eeprom4K,
+    Eeprom16K,
+    Sram,
+    FlashRam,
+}
+
+impl SaveType {
+    /// Detect the save type of the cartridge, looking up the game in the
+    /// per-game database (see `GameHacks`).
+    pub fn detect(cart: &Cartridge) -> SaveType {
+        GameHacks::lookup(cart)
+            .save_type
+            .unwrap_or(SaveType::Eeprom4K)
+    }
+
+    /// Parse a save type from its name, as used in the command line and in
+    /// the config file (eg: "eeprom4k", "flash").
+    pub fn from_name(name: &str) -> Option<SaveType> {
+        match name {
+            "none" => Some(SaveType::None),
+            "eeprom4k" => Some(SaveType::Eeprom4K),
+            "eeprom16k" => Some(SaveType::Eeprom16K),
+            "sram" => Some(SaveType::Sram),
+            "flash" => Some(SaveType::FlashRam),
+            _ => None,
+        }
+    }
+
+    /// Size of the save memory in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            SaveType::None => 0,
+            SaveType::Eeprom4K => 512,
+            SaveType::Eeprom16K => 2048,
+            SaveType::Sram => 32 * 1024,
+            SaveType::FlashRam => 128 * 1024,
+        }
+    }
+
+    /// Extension of the save file, following the convention used by most
+    /// emulators.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SaveType::None => "",
+            SaveType::Eeprom4K | SaveType::Eeprom16K => "eep",
+            SaveType::Sram => "sra",
+            SaveType::FlashRam => "fla",
+        }
+    }
+
+    pub fn is_eeprom(&self) -> bool {
+        *self == SaveType::Eeprom4K || *self == SaveType::Eeprom16K
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum FlashMode {
+    Idle,
+    Read,
+    Status,
+    Erase,
+    Write,
+}
+
+// FlashRAM chip identification (Macronix MX29L1100), returned in the low
+// word of the status.
+const FLASH_ID: u64 = 0x00C2_001E;
+const FLASH_PAGE_SIZE: usize = 128;
+const FLASH_SECTOR_SIZE: usize = 16 * 1024;
+
+#[derive(DeviceBE)]
+pub struct CartSave {
+    // FlashRAM status (high word)
+    #[reg(bank = 0, offset = 0x0_0000, rwmask = 0, rcb)]
+    flash_status: Reg32,
+
+    // FlashRAM command register
+    #[reg(bank = 0, offset = 0x1_0000, writeonly, wcb)]
+    flash_cmd: Reg32,
+
+    kind: SaveType,
+    mem: BackupMem,
+
+    flash_mode: FlashMode,
+    flash_status_val: u64,
+    flash_offset: usize,
+    flash_page: Vec<u8>,
+
+    logger: slog::Logger,
+}
+
+impl CartSave {
+    pub fn new(logger: slog::Logger) -> Box<CartSave> {
+        Box::new(CartSave {
+            flash_status: Reg32::default(),
+            flash_cmd: Reg32::default(),
+            kind: SaveType::None,
+            mem: BackupMem::with_data(logger.clone(), None, Vec::new(), FlushPolicy::default()),
+            flash_mode: FlashMode::Idle,
+            flash_status_val: 0,
+            flash_offset: 0,
+            flash_page: vec![0xFF; FLASH_PAGE_SIZE],
+            logger,
+        })
+    }
+
+    /// Select the save type, and load the save memory contents from the
+    /// specified file (if it exists). The file is created when the game
+    /// first writes to the save memory, and then kept in sync according to
+    /// the flush policy. Any pending write to the previous file is flushed.
+    pub fn setup(
+        &mut self,
+        kind: SaveType,
+        path: Option<&Path>,
+        policy: FlushPolicy,
+    ) -> io::Result<()> {
+        let mem = BackupMem::new(self.logger.clone(), path, kind.size(), 0xFF, policy)?;
+
+        info!(self.logger, "cartridge save";
+            "type" => ?kind, "path" => ?path, "flush" => ?policy);
+        self.kind = kind;
+        self.mem = mem;
+        self.flash_mode = FlashMode::Idle;
+        Ok(())
+    }
+
+    pub fn kind(&self) -> SaveType {
+        self.kind
+    }
+
+    /// Must be called at the end of each frame, to persist the save memory
+    /// according to the flush policy.
+    pub fn end_frame(&mut self) -> io::Result<()> {
+        self.mem.end_frame()
+    }
+
+    /// Execute a joybus command addressed to the EEPROM (channel 4).
+    /// Returns false if the cartridge has no EEPROM.
+    pub(crate) fn eeprom_cmd(&mut self, cmd: &[u8], out: &mut [u8]) -> Result<bool, &'static str> {
+        if !self.kind.is_eeprom() {
+            return Ok(false);
+        }
+        match cmd[0] {
+            0x00 | 0xFF => {
+                // Status / reset: identification bytes
+                if out.len() < 3 {
+                    return Err("eeprom: invalid status command");
+                }
+                out[0] = 0x00;
+                out[1] = if self.kind == SaveType::Eeprom16K {
+                    0xC0
+                } else {
+                    0x80
+                };
+                out[2] = 0x00;
+            }
+            0x04 => {
+                // Read 8-byte block
+                if cmd.len() < 2 || out.len() < 8 {
+                    return Err("eeprom: invalid read command");
+                }
+                let data = self.mem.data();
+                let off = (cmd[1] as usize * 8) % data.len();
+                out[..8].copy_from_slice(&data[off..off + 8]);
+            }
+            0x05 => {
+                // Write 8-byte block
+                if cmd.len() < 10 {
+                    return Err("eeprom: invalid write command");
+                }
+                let off = (cmd[1] as usize * 8) % self.mem.data().len();
+                self.mem.write(off, &cmd[2..10]);
+                if let Some(o) = out.get_mut(0) {
+                    *o = 0x00; // not busy
+                }
+            }
+            _ => return Err("eeprom: invalid command"),
+        }
+        Ok(true)
+    }
+
+    /// PI DMA from domain 2 (cartridge to RDRAM).
+    pub(crate) fn dma_read(&mut self, addr: u32, buf: &mut [u8]) {
+        let off = (addr - DOMAIN2_BASE) as usize;
+        match (self.kind, self.flash_mode) {
+            (SaveType::Sram, _) => {
+                let data = self.mem.data();
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = data[(off + i) % data.len()];
+                }
+            }
+            (SaveType::FlashRam, FlashMode::Status) => {
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = (self.flash_status_val >> (56 - (i % 8) * 8)) as u8;
+                }
+            }
+            (SaveType::FlashRam, FlashMode::Read) => {
+                // In read mode, the flash is addressed in 16-bit words.
+                let off = off * 2;
+                let data = self.mem.data();
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = data[(off + i) % data.len()];
+                }
+            }
+            _ => {
+                warn!(self.logger, "unexpected DMA read from save memory"; "addr" => addr.hex());
+                for b in buf.iter_mut() {
+                    *b = 0;
+                }
+            }
+        }
+    }
+
+    /// PI DMA to domain 2 (RDRAM to cartridge).
+    pub(crate) fn dma_write(&mut self, addr: u32, buf: &[u8]) {
+        let off = (addr - DOMAIN2_BASE) as usize;
+        match (self.kind, self.flash_mode) {
+            (SaveType::Sram, _) => {
+                let data = self.mem.data_mut();
+                let len = data.len();
+                for (i, b) in buf.iter().enumerate() {
+                    data[(off + i) % len] = *b;
+                }
+                self.mem.written();
+            }
+            (SaveType::FlashRam, FlashMode::Write) => {
+                // Fill the page buffer; it's written into the flash by the
+                // execute command.
+                for (i, b) in buf.iter().take(FLASH_PAGE_SIZE).enumerate() {
+                    self.flash_page[i] = *b;
+                }
+            }
+            _ => {
+                warn!(self.logger, "unexpected DMA write to save memory"; "addr" => addr.hex());
+            }
+        }
+    }
+
+    fn cb_read_flash_status(&self, _old: u32) -> u32 {
+        (self.flash_status_val >> 32) as u32
+    }
+
+    fn cb_write_flash_cmd(&mut self, _old: u32, cmd: u32) {
+        if self.kind != SaveType::FlashRam {
+            return;
+        }
+        let page = (cmd & 0xFFFF) as usize;
+        match cmd >> 24 {
+            0x4B => {
+                // Select sector to erase
+                self.flash_offset = (page * FLASH_PAGE_SIZE) & !(FLASH_SECTOR_SIZE - 1);
+            }
+            0x78 => {
+                self.flash_mode = FlashMode::Erase;
+                self.flash_status_val = 0x1111_8008_0000_0000 | FLASH_ID;
+            }
+            0xA5 => {
+                // Select page to write
+                self.flash_offset = page * FLASH_PAGE_SIZE;
+                self.flash_status_val = 0x1111_8004_0000_0000 | FLASH_ID;
+            }
+            0xB4 => {
+                self.flash_mode = FlashMode::Write;
+            }
+            0xD2 => {
+                // Execute the pending erase or write
+                let off = self.flash_offset % self.mem.data().len();
+                match self.flash_mode {
+                    FlashMode::Erase => {
+                        self.mem.write(off, &[0xFF; FLASH_SECTOR_SIZE]);
+                    }
+                    FlashMode::Write => {
+                        self.mem.write(off, &self.flash_page);
+                    }
+                    _ => {}
+                }
+            }
+            0xE1 => {
+                self.flash_mode = FlashMode::Status;
+                self.flash_status_val = 0x1111_8001_0000_0000 | FLASH_ID;
+            }
+            0xF0 => {
+                self.flash_mode = FlashMode::Read;
+                self.flash_status_val = 0x1111_8004_F000_0000;
+            }
+            _ => {
+                warn!(self.logger, "unknown FlashRAM command"; "cmd" => cmd.hex());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cartsave(kind: SaveType) -> Box<CartSave> {
+        let mut save = CartSave::new(slog::Logger::root(slog::Discard, o!()));
+        save.setup(kind, None, FlushPolicy::default()).unwrap();
+        save
+    }
+
+    #[test]
+    fn eeprom_status() {
+        let mut out = [0xAA; 3];
+        assert_eq!(
+            cartsave(SaveType::Sram).eeprom_cmd(&[0x00], &mut out),
+            Ok(false)
+        );
+        assert_eq!(out, [0xAA; 3]);
+
+        assert_eq!(
+            cartsave(SaveType::Eeprom4K).eeprom_cmd(&[0x00], &mut out),
+            Ok(true)
+        );
+        assert_eq!(out, [0x00, 0x80, 0x00]);
+        assert_eq!(
+            cartsave(SaveType::Eeprom16K).eeprom_cmd(&[0xFF], &mut out),
+            Ok(true)
+        );
+        assert_eq!(out, [0x00, 0xC0, 0x00]);
+    }
+
+    #[test]
+    fn eeprom_read_write() {
+        for &kind in &[SaveType::Eeprom4K, SaveType::Eeprom16K] {
+            let mut save = cartsave(kind);
+            let block = [1, 2, 3, 4, 5, 6, 7, 8];
+            let mut cmd = vec![0x05, 3];
+            cmd.extend_from_slice(&block);
+            let mut out = [0xAA; 1];
+            assert_eq!(save.eeprom_cmd(&cmd, &mut out), Ok(true));
+            assert_eq!(out, [0x00]);
+            assert_eq!(&save.mem.data()[3 * 8..4 * 8], &block);
+
+            let mut out = [0; 8];
+            assert_eq!(save.eeprom_cmd(&[0x04, 3], &mut out), Ok(true));
+            assert_eq!(out, block);
+
+            // The 4K EEPROM has 64 blocks, so block numbers wrap around.
+            assert_eq!(save.eeprom_cmd(&[0x04, 64 + 3], &mut out), Ok(true));
+            if kind == SaveType::Eeprom4K {
+                assert_eq!(out, block);
+            } else {
+                assert_eq!(out, [0xFF; 8]);
+            }
+            assert_eq!(save.eeprom_cmd(&[0x04, 4], &mut out), Ok(true));
+            assert_eq!(out, [0xFF; 8]);
+        }
+    }
+
+    #[test]
+    fn eeprom_invalid() {
+        let mut save = cartsave(SaveType::Eeprom4K);
+        let mut out = [0; 8];
+        assert!(save.eeprom_cmd(&[0x04], &mut out).is_err());
+        assert!(save.eeprom_cmd(&[0x05, 0, 1, 2], &mut out).is_err());
+        assert!(save.eeprom_cmd(&[0x00], &mut out[..2]).is_err());
+        assert!(save.eeprom_cmd(&[0x42], &mut out).is_err());
+    }
+
+    // Read the FlashRAM status through a PI DMA.
+    fn flash_status(save: &mut CartSave) -> u64 {
+        let mut buf = [0; 8];
+        save.dma_read(DOMAIN2_BASE, &mut buf);
+        u64::from_be_bytes(buf)
+    }
+
+    #[test]
+    fn flash_program_erase() {
+        let mut save = cartsave(SaveType::FlashRam);
+
+        save.cb_write_flash_cmd(0, 0xE100_0000);
+        assert_eq!(flash_status(&mut save), 0x1111_8001_00C2_001E);
+        assert_eq!(save.cb_read_flash_status(0), 0x1111_8001);
+
+        // Program page 130: set write mode, fill the page buffer through
+        // DMA, select the page and execute.
+        let page: Vec<u8> = (0..FLASH_PAGE_SIZE as u8).collect();
+        save.cb_write_flash_cmd(0, 0xB400_0000);
+        save.dma_write(DOMAIN2_BASE, &page);
+        save.cb_write_flash_cmd(0, 0xA500_0000 | 130);
+        assert_eq!(save.cb_read_flash_status(0), 0x1111_8004);
+        save.cb_write_flash_cmd(0, 0xD200_0000);
+        let off = 130 * FLASH_PAGE_SIZE;
+        assert_eq!(&save.mem.data()[off..off + FLASH_PAGE_SIZE], &page[..]);
+        assert_eq!(save.mem.data()[off - 1], 0xFF);
+        assert_eq!(save.mem.data()[off + FLASH_PAGE_SIZE], 0xFF);
+
+        // In read mode, the flash is addressed in 16-bit words.
+        save.cb_write_flash_cmd(0, 0xF000_0000);
+        let mut buf = vec![0; FLASH_PAGE_SIZE];
+        save.dma_read(DOMAIN2_BASE + off as u32 / 2, &mut buf);
+        assert_eq!(buf, page);
+
+        // Erase the sector containing the page (sector 1, pages 128-255).
+        save.cb_write_flash_cmd(0, 0x4B00_0000 | 200);
+        save.cb_write_flash_cmd(0, 0x7800_0000);
+        assert_eq!(save.cb_read_flash_status(0), 0x1111_8008);
+        save.cb_write_flash_cmd(0, 0xD200_0000);
+        assert!(save.mem.data()[FLASH_SECTOR_SIZE..2 * FLASH_SECTOR_SIZE]
+            .iter()
+            .all(|&b| b == 0xFF));
+    }
+
+    #[test]
+    fn flash_ignored_for_other_types() {
+        let mut save = cartsave(SaveType::Sram);
+        save.cb_write_flash_cmd(0, 0xE100_0000);
+        assert_eq!(save.cb_read_flash_status(0), 0);
+        save.dma_write(DOMAIN2_BASE + 4, &[1, 2, 3]);
+        assert_eq!(&save.mem.data()[4..7], &[1, 2, 3]);
+    }
+}
@@ -0,0 +1,475 @@
+//! GameShark / Action Replay cheat codes.
+//!
+//! Cheats are stored in a per-ROM text file (next to the ROM, with the
+//! `.cht` extension). Each cheat starts with a header line holding its state
+//! and name, followed by its codes, one per line:
+//!
+//! ```text
+//! # Super Mario 64 (U)
+//! [x] Infinite lives
+//! 8033B21D 0064
+//! [ ] Have 120 stars
+//! D033AFA1 0020
+//! 8033B218 0078
+//! ```
+//!
+//! Supported code types:
+//!
+//! | Code              | Description                                          |
+//! |-------------------|------------------------------------------------------|
+//! | `80XXXXXX 00YY`   | write 8-bit value YY at XXXXXX                       |
+//! | `81XXXXXX YYYY`   | write 16-bit value YYYY at XXXXXX                    |
+//! | `A0`/`A1`         | same as 80/81 (uncached writes)                      |
+//! | `F0`/`F1`         | same as 80/81, but applied only once at boot         |
+//! | `D0XXXXXX 00YY`   | apply next code only if 8-bit value at XXXXXX == YY  |
+//! | `D1XXXXXX YYYY`   | apply next code only if 16-bit value == YYYY         |
+//! | `D2`/`D3`         | same as D0/D1, but checking for inequality           |
+//! | `5000NNSS VVVV`   | repeat next code NN times, adding SS to the address  |
+//! |                   | and VVVV to the value at each iteration              |
+//!
+//! Codes are applied at each VI interrupt, which is what the real GameShark
+//! does (it hooks the interrupt handler).
+use super::errors::*;
+use super::r4300::R4300;
+use emu::backup::write_atomic;
+use emu::bus::be::Device;
+#[cfg(feature = "frontend")]
+use emu::dbg::imgui::*;
+#[cfg(feature = "frontend")]
+use emu::dbg::DebuggerRenderer;
+
+use slog;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A single GameShark code.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CheatCode {
+    pub kind: u8,
+    pub addr: u32,
+    pub value: u16,
+}
+
+impl CheatCode {
+    /// Parse a code in the "XXXXXXXX YYYY" format.
+    pub fn parse(s: &str) -> Result<CheatCode> {
+        let mut fields = s.split_whitespace();
+        let (code, value) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(c), Some(v), None) if c.len() == 8 && v.len() == 4 => (c, v),
+            _ => bail!("invalid cheat code: {:?}", s),
+        };
+        let code =
+            u32::from_str_radix(code, 16).chain_err(|| format!("invalid cheat code: {:?}", s))?;
+        let value =
+            u16::from_str_radix(value, 16).chain_err(|| format!("invalid cheat code: {:?}", s))?;
+        let kind = (code >> 24) as u8;
+        match kind {
+            0x80 | 0x81 | 0xA0 | 0xA1 | 0xF0 | 0xF1 | 0xD0 | 0xD1 | 0xD2 | 0xD3 | 0x50 => {}
+            _ => bail!("unsupported cheat code type {:02X}: {:?}", kind, s),
+        }
+        Ok(CheatCode {
+            kind,
+            addr: code & 0xFF_FFFF,
+            value,
+        })
+    }
+
+    fn is_write(&self) -> bool {
+        match self.kind {
+            0x80 | 0x81 | 0xA0 | 0xA1 | 0xF0 | 0xF1 => true,
+            _ => false,
+        }
+    }
+
+    fn is_boot(&self) -> bool {
+        self.kind == 0xF0 || self.kind == 0xF1
+    }
+
+    // Write the value into RDRAM.
+    fn write(&self, addr: u32, value: u16) {
+        let bus = &mut R4300::get_mut().bus;
+        match self.kind & 1 {
+            0 => bus.write::<u8>(addr, value as u8),
+            _ => bus.write::<u16>(addr & !1, value),
+        }
+    }
+
+    // Evaluate a conditional code.
+    fn check(&self) -> bool {
+        let bus = &R4300::get().bus;
+        let cur = match self.kind & 1 {
+            0 => bus.read::<u8>(self.addr) as u16,
+            _ => bus.read::<u16>(self.addr & !1),
+        };
+        match self.kind {
+            0xD0 | 0xD1 => cur == self.value,
+            _ => cur != self.value,
+        }
+    }
+}
+
+impl fmt::Display for CheatCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02X}{:06X} {:04X}", self.kind, self.addr, self.value)
+    }
+}
+
+/// A named list of codes, that can be enabled or disabled as a whole.
+#[derive(Clone, Debug)]
+pub struct Cheat {
+    pub name: String,
+    pub codes: Vec<CheatCode>,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    // Check that the codes form a valid sequence: conditionals and repeaters
+    // must be followed by the code they apply to.
+    fn validate(&self) -> Result<()> {
+        for (idx, c) in self.codes.iter().enumerate() {
+            let next = self.codes.get(idx + 1);
+            match c.kind {
+                0xD0..=0xD3 if next.is_none() => {
+                    bail!("cheat {:?}: conditional code at the end", self.name)
+                }
+                0x50 if !next.map_or(false, |n| n.is_write() && !n.is_boot()) => {
+                    bail!(
+                        "cheat {:?}: repeater must be followed by a write",
+                        self.name
+                    )
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Number of codes starting at `idx` that form a single operation: a
+    // repeater is followed by the write it applies to.
+    fn op_len(&self, idx: usize) -> usize {
+        match self.codes.get(idx) {
+            Some(c) if c.kind == 0x50 => 2,
+            _ => 1,
+        }
+    }
+
+    // Run the codes. If `boot` is true, only boot codes are applied,
+    // otherwise they are skipped.
+    fn apply(&self, boot: bool) {
+        let mut idx = 0;
+        while idx < self.codes.len() {
+            let c = &self.codes[idx];
+            idx += 1;
+            match c.kind {
+                0xD0..=0xD3 => {
+                    if !c.check() {
+                        idx += self.op_len(idx);
+                    }
+                }
+                0x50 => {
+                    let next = &self.codes[idx];
+                    idx += 1;
+                    // Repeated writes are never boot codes (see validate).
+                    if boot {
+                        continue;
+                    }
+                    let (count, step) = ((c.addr >> 8) & 0xFF, c.addr & 0xFF);
+                    for n in 0..count {
+                        let addr = next.addr.wrapping_add(n * step);
+                        next.write(
+                            addr,
+                            next.value.wrapping_add((n as u16).wrapping_mul(c.value)),
+                        );
+                    }
+                }
+                _ if c.is_boot() == boot => c.write(c.addr, c.value),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The cheats of a game, as loaded from its cheat file.
+pub struct CheatList {
+    path: PathBuf,
+    pub cheats: Vec<Cheat>,
+}
+
+impl CheatList {
+    /// Create an empty list, associated to the specified cheat file.
+    pub fn new(path: &Path) -> CheatList {
+        CheatList {
+            path: path.to_owned(),
+            cheats: Vec::new(),
+        }
+    }
+
+    /// Load the cheat file. A missing file is not an error (no cheats).
+    pub fn load(path: &Path) -> Result<CheatList> {
+        let mut list = CheatList::new(path);
+        if path.exists() {
+            let text = fs::read_to_string(path)
+                .chain_err(|| format!("cannot open cheat file {}", path.display()))?;
+            list.cheats = Self::parse(&text)
+                .chain_err(|| format!("invalid cheat file {}", path.display()))?;
+        }
+        Ok(list)
+    }
+
+    /// Parse the contents of a cheat file.
+    pub fn parse(text: &str) -> Result<Vec<Cheat>> {
+        let mut cheats: Vec<Cheat> = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let enabled = if line.starts_with("[x]") || line.starts_with("[X]") {
+                Some(true)
+            } else if line.starts_with("[ ]") {
+                Some(false)
+            } else {
+                None
+            };
+            if let Some(enabled) = enabled {
+                cheats.push(Cheat {
+                    name: line[3..].trim().to_owned(),
+                    codes: Vec::new(),
+                    enabled,
+                });
+                continue;
+            }
+            match cheats.last_mut() {
+                Some(cheat) => cheat
+                    .codes
+                    .push(CheatCode::parse(line).chain_err(|| format!("line {}", n + 1))?),
+                None => bail!("line {}: code outside of a cheat", n + 1),
+            }
+        }
+        for c in cheats.iter() {
+            c.validate()?;
+        }
+        Ok(cheats)
+    }
+
+    /// Write the cheat list back to its file (eg: to persist the enabled
+    /// state of each cheat).
+    pub fn save(&self) -> Result<()> {
+        let mut text = String::new();
+        for c in self.cheats.iter() {
+            text += &format!("[{}] {}\n", if c.enabled { "x" } else { " " }, c.name);
+            for code in c.codes.iter() {
+                text += &format!("{}\n", code);
+            }
+        }
+        write_atomic(&self.path, text.as_bytes())
+            .chain_err(|| format!("cannot write cheat file {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Apply the enabled cheats. Must be called at each VI interrupt.
+    pub fn apply(&self) {
+        for c in self.cheats.iter().filter(|c| c.enabled) {
+            c.apply(false);
+        }
+    }
+
+    /// Apply the boot codes of the enabled cheats. Must be called after each
+    /// reset, after the boot process has been setup.
+    pub fn apply_boot(&self, logger: &slog::Logger) {
+        for c in self.cheats.iter().filter(|c| c.enabled) {
+            if c.codes.iter().any(|code| code.is_boot()) {
+                info!(logger, "applying boot cheat"; "cheat" => c.name.clone());
+            }
+            c.apply(true);
+        }
+    }
+
+    #[cfg(feature = "frontend")]
+    pub fn render_debug<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>, logger: &slog::Logger) {
+        let ui = dr.ui();
+        let mut changed = false;
+        Window::new(im_str!("[N64] Cheats"))
+            .size([320.0, 300.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text(format!("File: {}", self.path.display()));
+                if ui.small_button(im_str!("Reload")) {
+                    match CheatList::load(&self.path) {
+                        Ok(list) => *self = list,
+                        Err(err) => {
+                            error!(logger, "cannot reload cheats"; "err" => err.to_string())
+                        }
+                    }
+                }
+                ui.separator();
+                if self.cheats.is_empty() {
+                    ui.text("No cheats defined for this game.");
+                }
+                for (idx, c) in self.cheats.iter_mut().enumerate() {
+                    changed |= ui.checkbox(&im_str!("{}##cheat{}", c.name, idx), &mut c.enabled);
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text(
+                            c.codes
+                                .iter()
+                                .map(|code| code.to_string())
+                                .collect::<Vec<_>>()
+                                .join("\n"),
+                        );
+                    }
+                }
+            });
+        if changed {
+            if let Err(err) = self.save() {
+                error!(logger, "cannot save cheats"; "err" => err.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ri::{RdramSize, Ri};
+    use super::*;
+    use std::env;
+
+    fn code(s: &str) -> CheatCode {
+        CheatCode::parse(s).unwrap()
+    }
+
+    fn cheat(codes: &[&str]) -> Cheat {
+        Cheat {
+            name: "test".to_owned(),
+            codes: codes.iter().map(|c| code(c)).collect(),
+            enabled: true,
+        }
+    }
+
+    // Setup a bus with just RDRAM, which is all cheats can access.
+    fn setup_bus() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        R4300::new(logger.new(o!())).register();
+        Ri::new(logger, RdramSize::Mb4).register();
+        R4300::get_mut()
+            .bus
+            .map_device(0x0000_0000, Ri::get(), 0)
+            .unwrap();
+    }
+
+    fn read8(addr: u32) -> u8 {
+        R4300::get().bus.read::<u8>(addr)
+    }
+
+    #[test]
+    fn parse_code() {
+        assert_eq!(
+            code("8033B21D 0064"),
+            CheatCode {
+                kind: 0x80,
+                addr: 0x33_B21D,
+                value: 0x64
+            }
+        );
+        assert_eq!(code("  d1033afa  ffff ").to_string(), "D1033AFA FFFF");
+        assert_eq!(code("50000A02 0001").addr, 0x00_0A02);
+
+        assert!(CheatCode::parse("8033B21D").is_err());
+        assert!(CheatCode::parse("8033B21D 064").is_err());
+        assert!(CheatCode::parse("8033B21D 0064 0000").is_err());
+        assert!(CheatCode::parse("8033B2ZZ 0064").is_err());
+        assert!(CheatCode::parse("1233B21D 0064").is_err());
+    }
+
+    #[test]
+    fn parse_list() {
+        let text = "# Super Mario 64 (U)\n\
+                    [x] Infinite lives\n\
+                    8033B21D 0064\n\
+                    \n\
+                    [ ] Have 120 stars\n\
+                    D033AFA1 0020\n\
+                    8033B218 0078\n";
+        let cheats = CheatList::parse(text).unwrap();
+        assert_eq!(cheats.len(), 2);
+        assert_eq!(cheats[0].name, "Infinite lives");
+        assert!(cheats[0].enabled);
+        assert_eq!(cheats[0].codes, vec![code("8033B21D 0064")]);
+        assert_eq!(cheats[1].name, "Have 120 stars");
+        assert!(!cheats[1].enabled);
+        assert_eq!(cheats[1].codes.len(), 2);
+
+        assert!(CheatList::parse("8033B21D 0064\n").is_err());
+        assert!(CheatList::parse("[x] a\n8033B21D 00\n").is_err());
+        assert!(CheatList::parse("[x] a\nD033AFA1 0020\n").is_err());
+        assert!(CheatList::parse("[x] a\n50000202 0001\n").is_err());
+        assert!(CheatList::parse("[x] a\n50000202 0001\nF0001000 0001\n").is_err());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = env::temp_dir().join(format!("r64emu-cheats-{}.cht", std::process::id()));
+        let mut list = CheatList::new(&path);
+        list.cheats = vec![cheat(&["D033AFA1 0020", "8033B218 0078"])];
+        list.cheats[0].enabled = false;
+        list.save().unwrap();
+
+        let loaded = CheatList::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.cheats.len(), 1);
+        assert_eq!(loaded.cheats[0].name, "test");
+        assert!(!loaded.cheats[0].enabled);
+        assert_eq!(loaded.cheats[0].codes, list.cheats[0].codes);
+    }
+
+    #[test]
+    fn apply() {
+        setup_bus();
+
+        // Plain writes, applied only outside of boot.
+        let c = cheat(&["80001000 0012", "81001003 ABCD"]);
+        c.apply(true);
+        assert_eq!(read8(0x1000), 0);
+        c.apply(false);
+        assert_eq!(read8(0x1000), 0x12);
+        assert_eq!((read8(0x1002), read8(0x1003)), (0xAB, 0xCD));
+
+        // Boot writes, applied only at boot.
+        let c = cheat(&["F0001010 0034"]);
+        c.apply(false);
+        assert_eq!(read8(0x1010), 0);
+        c.apply(true);
+        assert_eq!(read8(0x1010), 0x34);
+
+        // Conditionals skip the next code when false.
+        let c = cheat(&[
+            "D0001000 0012",
+            "80001020 0001",
+            "D2001000 0012",
+            "80001021 0001",
+        ]);
+        c.apply(false);
+        assert_eq!((read8(0x1020), read8(0x1021)), (1, 0));
+
+        // Repeater: 3 writes, every 2 bytes, incrementing the value. It is
+        // a regular code, not applied at boot.
+        let c = cheat(&["50000302 0001", "80001030 0010"]);
+        c.apply(true);
+        assert_eq!(read8(0x1030), 0);
+        c.apply(false);
+        let bytes: Vec<u8> = (0x1030..0x1036).map(read8).collect();
+        assert_eq!(bytes, vec![0x10, 0, 0x11, 0, 0x12, 0]);
+
+        // A false conditional skips the whole repeater, and the following
+        // code is applied.
+        let c = cheat(&[
+            "D0001000 0099",
+            "50000302 0001",
+            "80001040 0010",
+            "80001050 0055",
+        ]);
+        c.apply(false);
+        let bytes: Vec<u8> = (0x1040..0x1046).map(read8).collect();
+        assert_eq!(bytes, vec![0; 6]);
+        assert_eq!(read8(0x1050), 0x55);
+    }
+}
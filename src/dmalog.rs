@@ -0,0 +1,238 @@
+use super::r4300::R4300;
+#[cfg(feature = "frontend")]
+use emu::dbg::imgui::*;
+#[cfg(feature = "frontend")]
+use emu::dbg::DebuggerRenderer;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+// Number of DMA transfers kept in the log.
+const DMA_LOG_SIZE: usize = 4096;
+
+// Default span of the timeline: about one NTSC frame of CPU clock.
+#[cfg(feature = "frontend")]
+const DEFAULT_SPAN: i32 = 1_562_500;
+
+/// Device performing a DMA transfer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DmaDevice {
+    Pi,
+    Si,
+    Ai,
+    Sp,
+    Dp,
+}
+
+#[cfg(feature = "frontend")]
+const DEVICES: [DmaDevice; 5] = [
+    DmaDevice::Pi,
+    DmaDevice::Si,
+    DmaDevice::Ai,
+    DmaDevice::Sp,
+    DmaDevice::Dp,
+];
+
+#[cfg(feature = "frontend")]
+impl DmaDevice {
+    fn color(self) -> [f32; 4] {
+        match self {
+            DmaDevice::Pi => [1.0, 0.6, 0.2, 1.0],
+            DmaDevice::Si => [0.3, 0.9, 0.3, 1.0],
+            DmaDevice::Ai => [0.3, 0.7, 1.0, 1.0],
+            DmaDevice::Sp => [1.0, 0.4, 0.8, 1.0],
+            DmaDevice::Dp => [1.0, 1.0, 0.3, 1.0],
+        }
+    }
+}
+
+/// A DMA transfer, with the CPU clock at which it was started. Addresses
+/// are physical; for AI and DP, the destination is the device itself.
+#[derive(Copy, Clone, Debug)]
+pub struct DmaRecord {
+    pub clock: i64,
+    pub dev: DmaDevice,
+    pub src: u32,
+    pub dst: u32,
+    pub len: u32,
+}
+
+/// DmaLog records the DMA transfers of all devices into a ring buffer, to
+/// be inspected in the debugger.
+pub struct DmaLog {
+    records: VecDeque<DmaRecord>,
+    #[cfg(feature = "frontend")]
+    enabled: [bool; 5],
+    #[cfg(feature = "frontend")]
+    span: i32,
+}
+
+thread_local!(
+    static DMA_LOG: RefCell<DmaLog> = RefCell::new(DmaLog {
+        records: VecDeque::with_capacity(DMA_LOG_SIZE),
+        #[cfg(feature = "frontend")]
+        enabled: [true; 5],
+        #[cfg(feature = "frontend")]
+        span: DEFAULT_SPAN,
+    })
+);
+
+/// Record a DMA transfer that is being started.
+pub fn record(dev: DmaDevice, src: u32, dst: u32, len: u32) {
+    let clock = R4300::get().ctx().clock;
+    DMA_LOG.with(|log| {
+        let records = &mut log.borrow_mut().records;
+        if records.len() == DMA_LOG_SIZE {
+            records.pop_front();
+        }
+        records.push_back(DmaRecord {
+            clock,
+            dev,
+            src,
+            dst,
+            len,
+        });
+    });
+}
+
+#[cfg(feature = "frontend")]
+pub fn render_debug<'a, 'ui>(dr: &DebuggerRenderer<'a, 'ui>) {
+    DMA_LOG.with(|log| log.borrow_mut().render_debug(dr));
+}
+
+#[cfg(feature = "frontend")]
+impl DmaLog {
+    fn render_debug<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>) {
+        let ui = dr.ui();
+        let records = &mut self.records;
+        let enabled = &mut self.enabled;
+        let span = &mut self.span;
+
+        Window::new(im_str!("[N64] DMA activity"))
+            .size([600.0, 450.0], Condition::FirstUseEver)
+            .build(ui, || {
+                for (i, dev) in DEVICES.iter().enumerate() {
+                    if i != 0 {
+                        ui.same_line(0.0);
+                    }
+                    ui.checkbox(&im_str!("{:?}", dev), &mut enabled[i]);
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Clear")) {
+                    records.clear();
+                }
+                ui.set_next_item_width(120.0);
+                ui.input_int(im_str!("Timeline span (cycles)"), span)
+                    .build();
+                *span = (*span).max(1000);
+
+                let visible = |r: &DmaRecord| enabled[r.dev as usize];
+                let end = records.back().map(|r| r.clock).unwrap_or(0);
+                let start = end - *span as i64;
+
+                // Timeline: one row per device, one tick per transfer, with
+                // the most recent transfer on the right edge.
+                let row_height = ui.text_line_height_with_spacing();
+                let label_width = 40.0;
+                let pos = ui.cursor_screen_pos();
+                let width = ui.content_region_avail()[0] - label_width;
+                let dl = ui.get_window_draw_list();
+                for (i, dev) in DEVICES.iter().enumerate() {
+                    let y = pos[1] + i as f32 * row_height;
+                    dl.add_text([pos[0], y], [0.8, 0.8, 0.8, 1.0], &format!("{:?}", dev));
+                    dl.add_rect(
+                        [pos[0] + label_width, y + 1.0],
+                        [pos[0] + label_width + width, y + row_height - 1.0],
+                        [0.3, 0.3, 0.3, 1.0],
+                    )
+                    .build();
+                }
+                let mut hovered = None;
+                let mouse = ui.io().mouse_pos;
+                for r in records.iter().filter(|r| visible(r) && r.clock >= start) {
+                    let x = pos[0] + label_width + width * (r.clock - start) as f32 / *span as f32;
+                    let y = pos[1] + r.dev as usize as f32 * row_height;
+                    dl.add_line([x, y + 2.0], [x, y + row_height - 2.0], r.dev.color())
+                        .thickness(2.0)
+                        .build();
+                    if (mouse[0] - x).abs() <= 2.0 && mouse[1] >= y && mouse[1] < y + row_height {
+                        hovered = Some(*r);
+                    }
+                }
+                ui.dummy([label_width + width, row_height * DEVICES.len() as f32]);
+                if let Some(r) = hovered {
+                    ui.tooltip_text(format!(
+                        "{:?} @ {}: {:08X} -> {:08X} ({} bytes)",
+                        r.dev, r.clock, r.src, r.dst, r.len
+                    ));
+                }
+                ui.separator();
+
+                // List of transfers (newest first)
+                ChildWindow::new(im_str!("##dmalog#list"))
+                    .size([0.0, 0.0])
+                    .build(ui, || {
+                        ui.columns(5, im_str!("##dmalog#cols"), true);
+                        for title in ["Clock", "Device", "Source", "Destination", "Length"].iter() {
+                            ui.text(title);
+                            ui.next_column();
+                        }
+                        ui.separator();
+                        for r in records.iter().rev().filter(|r| visible(r)) {
+                            ui.text(format!("{}", r.clock));
+                            ui.next_column();
+                            ui.text_colored(r.dev.color(), format!("{:?}", r.dev));
+                            ui.next_column();
+                            ui.text(format!("{:08X}", r.src));
+                            ui.next_column();
+                            ui.text(format!("{:08X}", r.dst));
+                            ui.next_column();
+                            ui.text(format!("{}", r.len));
+                            ui.next_column();
+                        }
+                        ui.columns(1, im_str!(""), false);
+                    });
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<DmaRecord> {
+        DMA_LOG.with(|log| log.borrow().records.iter().copied().collect())
+    }
+
+    #[test]
+    fn record_dmas() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        R4300::new(logger).register();
+
+        R4300::get_mut().ctx_mut().clock = 1000;
+        record(DmaDevice::Pi, 0x1000_1000, 0x0010_0000, 0x200);
+        R4300::get_mut().ctx_mut().clock = 2000;
+        record(DmaDevice::Ai, 0x0020_0000, 0x0450_0000, 0x100);
+
+        let log = records();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].clock, 1000);
+        assert_eq!(log[0].dev, DmaDevice::Pi);
+        assert_eq!(
+            (log[0].src, log[0].dst, log[0].len),
+            (0x1000_1000, 0x0010_0000, 0x200)
+        );
+        assert_eq!(log[1].clock, 2000);
+        assert_eq!(log[1].dev, DmaDevice::Ai);
+
+        // Once full, the oldest transfers are discarded.
+        for i in 0..DMA_LOG_SIZE as u32 {
+            record(DmaDevice::Si, i, 0x1FC0_07C0, 64);
+        }
+        let log = records();
+        assert_eq!(log.len(), DMA_LOG_SIZE);
+        assert_eq!(log[0].dev, DmaDevice::Si);
+        assert_eq!(log[0].src, 0);
+        assert_eq!(log[DMA_LOG_SIZE - 1].src, DMA_LOG_SIZE as u32 - 1);
+    }
+}
@@ -2,14 +2,25 @@ extern crate bit_field;
 extern crate byteorder;
 extern crate emu;
 extern crate slog;
+use super::dmalog::{self, DmaDevice};
 use super::mi::{IrqMask, Mi};
 use super::r4300::R4300;
-use super::rdp::Rdp;
+use super::rdp::{self, Rdp, RenderBackend};
 use super::sp::RSPCPU;
+#[cfg(feature = "frontend")]
+use super::vi::{rdram_image, FbFormat};
 use emu::bus::be::{Device, MemIoR, Reg32, RegDeref, RegRef};
 use emu::dbg;
+#[cfg(feature = "frontend")]
+use emu::dbg::imgui::*;
+#[cfg(feature = "frontend")]
+use emu::dbg::{DebuggerRenderer, ImageView};
+#[cfg(feature = "frontend")]
+use emu::gfx::{Color, OwnedGfxBufferLE, Rgba8888};
 use emu::int::Numerics;
+use emu::state::{CurrentState, Field, Snapshotable};
 use emu::sync;
+use serde_derive::{Deserialize, Serialize};
 
 bitflags! {
     struct StatusFlags: u32 {
@@ -37,6 +48,16 @@ impl RegDeref for StatusFlags {
     }
 }
 
+// Status of the command fetcher, copied into the state only while
+// saving and restoring savestates.
+#[derive(Default, Copy, Clone, Serialize, Deserialize)]
+struct DpSnapshot {
+    fetched_start_addr: u32,
+    fetched_end_addr: u32,
+    cycles: i64,
+    running: bool,
+}
+
 #[derive(DeviceBE)]
 pub struct Dp {
     #[reg(bank = 0, offset = 0x0, rwmask = 0x00FFFFFF, wcb)]
@@ -48,7 +69,7 @@ pub struct Dp {
     #[reg(bank = 0, offset = 0x8, readonly)]
     cmd_current: Reg32,
 
-    #[reg(bank = 0, offset = 0xC, wcb)]
+    #[reg(bank = 0, offset = 0xC, wcb, rcb)]
     cmd_status: Reg32,
 
     logger: slog::Logger,
@@ -58,12 +79,62 @@ pub struct Dp {
     fetched_end_addr: u32,
     cycles: i64,
     running: bool,
+    snapshot: Field<DpSnapshot>,
 
+    // The internal RDP status (tiles, modes, TMEM) is not part of the
+    // state, so after a savestate is loaded, rendering might be glitchy
+    // until the game sets it up again.
     gfx: Box<Rdp>,
+
+    // Debugger: commands received in the current and in the last frame,
+    // and stepping of one command at a time.
+    dbg_cmds: Vec<u64>,
+    #[cfg(feature = "frontend")]
+    dbg_last_cmds: Vec<u64>,
+    dbg_step: bool,
+    #[cfg(feature = "frontend")]
+    dbg_selected: Option<usize>,
+    #[cfg(feature = "frontend")]
+    dbg_fbview: Option<ImageView>,
+    #[cfg(feature = "frontend")]
+    dbg_tmem_tile: usize,
+    #[cfg(feature = "frontend")]
+    dbg_tmemview: Option<(ImageView, ImageView)>,
+}
+
+// Maximum number of command words captured per frame for the debugger.
+const DBG_MAX_CMDS: usize = 256 * 1024;
+
+// Convert decoded texels into an image, for display.
+#[cfg(feature = "frontend")]
+fn texel_image(width: usize, height: usize, texels: &[[u8; 4]]) -> OwnedGfxBufferLE<Rgba8888> {
+    let mut img = OwnedGfxBufferLE::<Rgba8888>::new(width, height);
+    {
+        let mut buf = img.buf_mut();
+        for y in 0..height {
+            let mut line = buf.line(y);
+            for x in 0..width {
+                let t = texels[y * width + x];
+                line.set(x, Color::new_clamped(t[0], t[1], t[2], t[3]));
+            }
+        }
+    }
+    img
 }
 
 impl Dp {
     pub fn new(logger: slog::Logger) -> Box<Dp> {
+        // Version 1: the snapshot field was renamed from "dp::snapshot", to
+        // follow the naming convention of components.
+        {
+            let mut state = CurrentState();
+            state.register_component("Dp", 1);
+            state.add_migration("Dp", 0, |m| {
+                m.rename("dp::snapshot", "Dp::snapshot");
+                Ok(())
+            });
+        }
+
         let gfx_logger = logger.new(o!());
         Box::new(Dp {
             cmd_start: Reg32::default(),
@@ -76,7 +147,20 @@ impl Dp {
             fetched_mem: MemIoR::default(),
             fetched_start_addr: 0,
             fetched_end_addr: 0,
+            snapshot: Field::new("Dp::snapshot", DpSnapshot::default()),
             gfx: Box::new(Rdp::new(gfx_logger)),
+            dbg_cmds: Vec::new(),
+            #[cfg(feature = "frontend")]
+            dbg_last_cmds: Vec::new(),
+            dbg_step: false,
+            #[cfg(feature = "frontend")]
+            dbg_selected: None,
+            #[cfg(feature = "frontend")]
+            dbg_fbview: None,
+            #[cfg(feature = "frontend")]
+            dbg_tmem_tile: 0,
+            #[cfg(feature = "frontend")]
+            dbg_tmemview: None,
         })
     }
 
@@ -112,6 +196,24 @@ impl Dp {
         }
     }
 
+    fn cb_read_cmd_status(&self, old: u32) -> u32 {
+        // Polling the status is a way to know that the RDP has finished.
+        self.fence_rendering();
+        old
+    }
+
+    fn fetch_cmdbuf(&mut self) {
+        let start = self.fetched_start_addr;
+        if self.cmd_status_ref().contains(StatusFlags::XBUS_DMA) {
+            self.fetched_mem = RSPCPU::get().bus.fetch_read::<u64>(start);
+        } else {
+            self.fetched_mem = R4300::get().bus.fetch_read::<u64>(start);
+        }
+        if self.fetched_mem.iter().is_none() {
+            error!(self.logger, "cmd buffer pointing to non-linear memory"; o!("ptr" => start.hex()));
+        }
+    }
+
     fn check_start(&mut self) {
         let mut status = self.cmd_status_ref();
         if !status.contains(StatusFlags::END_VALID) {
@@ -125,19 +227,20 @@ impl Dp {
             let start = self.cmd_start.get();
             *self.cmd_current_ref() = start;
             self.fetched_start_addr = start;
-            if status.contains(StatusFlags::XBUS_DMA) {
-                self.fetched_mem = RSPCPU::get().bus.fetch_read::<u64>(start);
-            } else {
-                self.fetched_mem = R4300::get().bus.fetch_read::<u64>(start);
-            }
-            if self.fetched_mem.iter().is_none() {
-                error!(self.logger, "cmd buffer pointing to non-linear memory"; o!("ptr" => start.hex()));
-            }
+            self.fetch_cmdbuf();
             status.remove(StatusFlags::START_VALID);
         }
 
         self.fetched_end_addr = self.cmd_end.get();
         status.remove(StatusFlags::END_VALID);
+        if self.fetched_end_addr > *self.cmd_current_ref() {
+            dmalog::record(
+                DmaDevice::Dp,
+                *self.cmd_current_ref(),
+                0x0410_0000,
+                self.fetched_end_addr - *self.cmd_current_ref(),
+            );
+        }
         self.running = true;
         warn!(
             self.logger,
@@ -145,6 +248,246 @@ impl Dp {
             o!("start" => self.fetched_start_addr.hex(), "end" => self.fetched_end_addr.hex())
         );
     }
+
+    /// Execute RDP commands generated by the graphics HLE, bypassing the
+    /// command buffer registers. The DP interrupt is raised on Sync Full,
+    /// as it happens when the same commands are sent by a microcode.
+    pub(crate) fn hle_commands(&mut self, cmds: &[u64]) {
+        for &cmd in cmds {
+            self.capture_cmd(cmd);
+            self.gfx.op(cmd);
+            if (cmd >> 56) & 0x3F == 0x29 {
+                Mi::get_mut().set_irq_line(IrqMask::DP, true);
+            }
+        }
+        self.gfx.submit();
+    }
+
+    /// Set the number of threads used to render RDP primitives.
+    pub fn set_render_threads(&mut self, n: usize) {
+        self.gfx.set_threads(n);
+    }
+
+    /// Rasterize primitives on dedicated threads, while the emulation keeps
+    /// running; this is faster on multicore hosts. The emulation waits for
+    /// rendering to finish (fences) when the CPU checks whether the RDP is
+    /// done (through the DP interrupt or status), before scanning out a
+    /// frame and before saving a state.
+    pub fn set_threaded_rendering(&mut self, enabled: bool) {
+        self.gfx.set_background(enabled);
+    }
+
+    /// Select the renderer used to draw RDP primitives. It can be changed at
+    /// any time; the OpenGL renderer falls back to the software one if no
+    /// OpenGL context is available (eg: headless mode).
+    pub fn set_render_backend(&mut self, backend: RenderBackend) {
+        self.gfx.set_backend(backend);
+    }
+
+    /// Set the internal resolution of the OpenGL renderer, as a multiplier
+    /// of the native resolution.
+    pub fn set_render_scale(&mut self, scale: usize) {
+        self.gfx.set_scale(scale);
+    }
+
+    /// Return the OpenGL texture holding the color image at the specified
+    /// address and size, if it was drawn at a higher resolution, to be
+    /// displayed instead of RDRAM.
+    pub(crate) fn upscaled_image(&mut self, addr: u32, width: usize, height: usize) -> Option<u32> {
+        self.gfx.upscaled_image(addr, width, height)
+    }
+
+    /// Wait until all RDP primitives sent so far have been drawn into RDRAM.
+    pub fn flush_rendering(&mut self) {
+        self.gfx.flush();
+    }
+
+    /// Wait until the RDP primitives submitted for rendering have been drawn
+    /// into RDRAM. Primitives of an incomplete buffer are not drawn.
+    pub(crate) fn fence_rendering(&self) {
+        self.gfx.fence();
+    }
+
+    fn capture_cmd(&mut self, cmd: u64) {
+        if self.dbg_cmds.len() < DBG_MAX_CMDS {
+            self.dbg_cmds.push(cmd);
+        }
+    }
+
+    /// Called at the end of each frame, to rotate the commands captured
+    /// for the debugger.
+    pub fn end_frame(&mut self) {
+        let cmds = std::mem::replace(&mut self.dbg_cmds, Vec::new());
+        #[cfg(feature = "frontend")]
+        {
+            self.dbg_last_cmds = cmds;
+        }
+    }
+
+    #[cfg(feature = "frontend")]
+    pub fn render_debug<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>) {
+        let ui = dr.ui();
+        let fbview = self.dbg_fbview.get_or_insert_with(ImageView::new);
+        let (fb_addr, fb_width, fb_height, fb_bpp) = self.gfx.color_image();
+        let mut step = self.dbg_step;
+        let mut selected = self.dbg_selected;
+        let mut backend = self.gfx.backend();
+
+        // While the frame is in progress (eg: stepping), show the commands
+        // received so far; otherwise, the ones of the last frame.
+        let (cmds, current) = if self.dbg_cmds.is_empty() {
+            (&self.dbg_last_cmds, false)
+        } else {
+            (&self.dbg_cmds, true)
+        };
+
+        // Group words into commands
+        let mut list = Vec::new();
+        let mut idx = 0;
+        while idx < cmds.len() {
+            let len = rdp::cmd_len(cmds[idx]).min(cmds.len() - idx);
+            list.push(&cmds[idx..idx + len]);
+            idx += len;
+        }
+
+        Window::new(im_str!("[RDP] Commands"))
+            .size([600.0, 600.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text(format!(
+                    "{} commands ({})",
+                    list.len(),
+                    if current {
+                        "current frame"
+                    } else {
+                        "last frame"
+                    }
+                ));
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Step command")) {
+                    step = true;
+                    dr.resume();
+                }
+                ui.text("Renderer:");
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("Software"), &mut backend, RenderBackend::Software);
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("OpenGL"), &mut backend, RenderBackend::OpenGl);
+
+                ChildWindow::new(im_str!("##rdp#cmdlist"))
+                    .size([0.0, 250.0])
+                    .border(true)
+                    .build(ui, || {
+                        for (i, words) in list.iter().enumerate() {
+                            let text = im_str!("{:5} {}", i, rdp::disasm(words));
+                            if Selectable::new(&text)
+                                .selected(selected == Some(i))
+                                .build(ui)
+                            {
+                                selected = Some(i);
+                            }
+                        }
+                        if current {
+                            // Follow the last command while stepping
+                            ui.set_scroll_here_y();
+                        }
+                    });
+
+                if let Some(words) = selected.and_then(|i| list.get(i)) {
+                    ui.text_wrapped(&im_str!("{}", rdp::disasm(words)));
+                    for w in words.iter() {
+                        ui.text(format!("{:016X}", w));
+                    }
+                }
+                ui.separator();
+
+                // Framebuffer being drawn, refreshed at every frame (or step)
+                ui.text(format!(
+                    "Color image: {} {}x{} {}bpp",
+                    fb_addr.hex(),
+                    fb_width,
+                    fb_height,
+                    fb_bpp
+                ));
+                if fb_width != 0 {
+                    let format = match fb_bpp {
+                        32 => FbFormat::Rgba32,
+                        16 => FbFormat::Rgba16,
+                        _ => FbFormat::I8,
+                    };
+                    fbview.update(&rdram_image(fb_addr, fb_width, fb_height, format).buf());
+                    let w = ui.content_region_avail()[0];
+                    let h = w * fb_height as f32 / fb_width as f32;
+                    dr.render_image(fbview, [w, h]);
+                }
+            });
+
+        self.dbg_step = step;
+        self.dbg_selected = selected;
+        if backend != self.gfx.backend() {
+            self.gfx.set_backend(backend);
+        }
+
+        self.render_debug_tmem(dr);
+    }
+
+    #[cfg(feature = "frontend")]
+    fn render_debug_tmem<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>) {
+        let ui = dr.ui();
+        let (tileview, palview) = self
+            .dbg_tmemview
+            .get_or_insert_with(|| (ImageView::new(), ImageView::new()));
+        let gfx = &self.gfx;
+        let mut idx = self.dbg_tmem_tile;
+
+        Window::new(im_str!("[RDP] TMEM"))
+            .size([420.0, 600.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text("Tile:");
+                for i in 0..8 {
+                    ui.same_line(0.0);
+                    ui.radio_button(&im_str!("{}##rdp#tile", i), &mut idx, i);
+                }
+
+                let (desc, w, h, texels) = gfx.tmem_tile(idx);
+                ui.text_wrapped(&im_str!("{}", desc));
+                ui.text(format!("Size: {}x{}", w, h));
+                tileview.update(&texel_image(w, h, &texels).buf());
+                let avail = ui.content_region_avail()[0];
+                let zoom = (avail / w as f32).min(256.0 / h as f32).max(1.0).floor();
+                dr.render_image(tileview, [w as f32 * zoom, h as f32 * zoom]);
+                ui.separator();
+
+                ui.text("Palette (TLUT):");
+                palview.update(&texel_image(16, 16, &gfx.tmem_palette()).buf());
+                dr.render_image(palview, [256.0, 256.0]);
+            });
+
+        self.dbg_tmem_tile = idx;
+    }
+}
+
+impl Snapshotable for Dp {
+    fn before_save(&mut self) {
+        self.gfx.flush();
+        *self.snapshot = DpSnapshot {
+            fetched_start_addr: self.fetched_start_addr,
+            fetched_end_addr: self.fetched_end_addr,
+            cycles: self.cycles,
+            running: self.running,
+        };
+    }
+
+    fn after_load(&mut self) {
+        let snap = *self.snapshot;
+        self.fetched_start_addr = snap.fetched_start_addr;
+        self.fetched_end_addr = snap.fetched_end_addr;
+        self.cycles = snap.cycles;
+        self.running = snap.running;
+        self.fetched_mem = MemIoR::default();
+        if self.running {
+            self.fetch_cmdbuf();
+        }
+    }
 }
 
 impl sync::Subsystem for Dp {
@@ -152,7 +495,7 @@ impl sync::Subsystem for Dp {
         "RDP"
     }
 
-    fn run(&mut self, until: i64, _: &dbg::Tracer) -> dbg::Result<()> {
+    fn run(&mut self, until: i64, t: &dbg::Tracer) -> dbg::Result<()> {
         if !self.running {
             self.cycles = until;
             return Ok(());
@@ -166,9 +509,19 @@ impl sync::Subsystem for Dp {
                 .skip((*curr_addr - self.fetched_start_addr) as usize / 8)
                 .take((self.fetched_end_addr - *curr_addr) as usize / 8)
             {
+                if self.dbg_cmds.len() < DBG_MAX_CMDS {
+                    self.dbg_cmds.push(cmd);
+                }
                 self.gfx.op(cmd);
                 *curr_addr += 8;
                 self.cycles += 1;
+                if self.dbg_step && !self.gfx.partial_command() {
+                    // Draw the command before stopping, so that its effect
+                    // is visible in the debugger.
+                    self.dbg_step = false;
+                    self.gfx.flush();
+                    return t.break_here("RDP command stepped");
+                }
                 if self.cycles >= until {
                     return Ok(());
                 }
@@ -180,6 +533,7 @@ impl sync::Subsystem for Dp {
             self.check_start();
             if !self.running {
                 self.cycles = until;
+                self.gfx.submit();
                 Mi::get_mut().set_irq_line(IrqMask::DP, true);
                 return Ok(());
             }
@@ -197,4 +551,173 @@ impl sync::Subsystem for Dp {
     fn pc(&self) -> Option<u64> {
         None
     }
+
+    fn next_event(&self) -> Option<i64> {
+        // Commands take one cycle each; the interrupt is raised by the run
+        // following the last command of the buffer.
+        if !self.running {
+            return None;
+        }
+        let left = self.fetched_end_addr.saturating_sub(self.cmd_current.get()) / 8;
+        Some(self.cycles + left as i64 + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ri::{RdramSize, Ri};
+    use super::*;
+    use emu::state::{load_state, save_state};
+    use std::thread;
+
+    const MAGIC: &str = "dp-test";
+
+    fn snapshot(start: u32, end: u32, cycles: i64) -> DpSnapshot {
+        DpSnapshot {
+            fetched_start_addr: start,
+            fetched_end_addr: end,
+            cycles,
+            running: false,
+        }
+    }
+
+    fn dp() -> Box<Dp> {
+        Dp::new(slog::Logger::root(slog::Discard, o!()))
+    }
+
+    // Create a savestate as written before the snapshot field was renamed,
+    // when the Dp component was not versioned yet. A new thread is used to
+    // get an empty state.
+    fn old_savestate(snap: DpSnapshot) -> Vec<u8> {
+        thread::spawn(move || {
+            let _snapshot = Field::new("dp::snapshot", snap);
+            let mut buf = Vec::new();
+            save_state(&mut buf, MAGIC, 1, &mut []).unwrap();
+            buf
+        })
+        .join()
+        .unwrap()
+    }
+
+    #[test]
+    fn load_old_savestate() {
+        let mut dp = dp();
+        let buf = old_savestate(snapshot(0x100, 0x180, 1234));
+        load_state(&buf[..], MAGIC, 1, &mut [&mut *dp]).unwrap();
+        assert_eq!(dp.fetched_start_addr, 0x100);
+        assert_eq!(dp.fetched_end_addr, 0x180);
+        assert_eq!(dp.cycles, 1234);
+    }
+
+    #[test]
+    fn save_load_state() {
+        let mut dp = dp();
+        dp.fetched_start_addr = 0x200;
+        dp.fetched_end_addr = 0x240;
+        dp.cycles = 5678;
+        let mut buf = Vec::new();
+        save_state(&mut buf, MAGIC, 1, &mut [&mut *dp]).unwrap();
+
+        dp.fetched_start_addr = 0;
+        dp.fetched_end_addr = 0;
+        dp.cycles = 0;
+        load_state(&buf[..], MAGIC, 1, &mut [&mut *dp]).unwrap();
+        assert_eq!(dp.fetched_start_addr, 0x200);
+        assert_eq!(dp.fetched_end_addr, 0x240);
+        assert_eq!(dp.cycles, 5678);
+    }
+
+    const FB_ADDR: u32 = 0x10_0000;
+    const FB_WIDTH: u64 = 320;
+    const FB_HEIGHT: u64 = 240;
+
+    // A frame of full-screen fill rectangles and flat triangles, ending
+    // with Sync Full.
+    fn scene() -> Vec<u64> {
+        let mut cmds = vec![
+            0x3F << 56 | 2 << 51 | (FB_WIDTH - 1) << 32 | FB_ADDR as u64, // Set Color Image
+            0x2D << 56 | (FB_WIDTH << 2) << 12 | FB_HEIGHT << 2,          // Set Scissor
+            0x2F << 56 | 3 << 52,                                         // Set Other Modes: fill
+        ];
+        for i in 0..32u64 {
+            let color = (i * 0x0843) & 0xFFFE | 1;
+            cmds.push(0x37 << 56 | color << 16 | color); // Set Fill Color
+            cmds.push(0x36 << 56 | (319 - i) << 46 | (239 - i) << 34 | i << 14 | i << 2);
+        }
+
+        // 1-cycle mode, with the combiner outputting the primitive color.
+        cmds.push(0x2F << 56);
+        cmds.push(
+            0x3C << 56 | 8 << 37 | 16 << 32 | 8 << 24 | 3 << 6 | 7 << 21 | 7 << 18 | 7 << 3 | 3,
+        );
+        for i in 0..32u64 {
+            cmds.push(0x3A << 56 | 0x1020_30FF + i * 0x0705_0300); // Set Prim Color
+            let (yh, yl) = ((5 + i) << 2, (200 + i) << 2);
+            let x = (10 + i * 7) << 16;
+            cmds.push(0x08 << 56 | 1 << 55 | yl << 32 | yl << 16 | yh);
+            cmds.push(0);
+            cmds.push(x << 32);
+            cmds.push(x << 32 | 0x8000 + i * 0x800);
+        }
+        cmds.push(0x29 << 56); // Sync Full
+        cmds
+    }
+
+    // Render the scene on a fresh emulator in a new thread, then call
+    // `fence` and return the framebuffer as seen by the CPU.
+    fn render_scene(threads: usize, background: bool, fence: fn()) -> Vec<u32> {
+        thread::spawn(move || {
+            let logger = slog::Logger::root(slog::Discard, o!());
+            R4300::new(logger.new(o!())).register();
+            Mi::new(logger.new(o!())).register();
+            Ri::new(logger.new(o!()), RdramSize::Mb4).register();
+            Dp::new(logger).register();
+            let bus = &mut R4300::get_mut().bus;
+            bus.map_device(0x0000_0000, Ri::get(), 0).unwrap();
+            bus.map_device(0x0410_0000, Dp::get(), 0).unwrap();
+            bus.map_device(0x0430_0000, Mi::get(), 0).unwrap();
+
+            let dp = Dp::get_mut();
+            dp.set_render_threads(threads);
+            dp.set_threaded_rendering(background);
+            dp.hle_commands(&scene());
+            fence();
+
+            let bus = &R4300::get().bus;
+            (0..(FB_WIDTH * FB_HEIGHT / 2) as u32)
+                .map(|i| bus.read::<u32>(FB_ADDR + i * 4))
+                .collect()
+        })
+        .join()
+        .unwrap()
+    }
+
+    fn no_fence() {}
+
+    // Polling the DP status, as done by the RDP microcode and by games
+    // waiting for the RDP to be idle.
+    fn fence_dp_status() {
+        R4300::get().bus.read::<u32>(0x0410_000C);
+    }
+
+    // Reading the pending interrupts, as done by the interrupt handler.
+    fn fence_mi_irq() {
+        let irq = R4300::get().bus.read::<u32>(0x0430_0008);
+        assert_ne!(irq & IrqMask::DP.bits(), 0);
+    }
+
+    #[test]
+    fn threaded_rendering() {
+        let expected = render_scene(0, false, no_fence);
+        assert!(expected.iter().any(|&p| p != 0));
+
+        // Worker threads, waiting for them at submit.
+        assert!(render_scene(4, false, no_fence) == expected);
+
+        // Background rendering, waiting at the fences observable by the CPU.
+        for &threads in &[0, 1, 4] {
+            assert!(render_scene(threads, true, fence_dp_status) == expected);
+            assert!(render_scene(threads, true, fence_mi_irq) == expected);
+        }
+    }
 }
@@ -0,0 +1,116 @@
+//! Per-game settings.
+//!
+//! Some settings depend on the game being run: the save type and the CIC
+//! model (when they're not correctly detected), the CPU overclock (to hide
+//! slowdowns), the emulation of graphics microcodes (HLE or on the RSP),
+//! whether the Expansion Pak is installed, the memory timing model (for
+//! timing-sensitive games), and the emulation of the VI filters.
+//! Overrides for them are stored in the "games" section of the config file,
+//! keyed by the CRC found in the ROM header, so that they're applied
+//! automatically whenever the game is booted. Options given on the command
+//! line take precedence over them.
+use super::cartridge::CicModel;
+use super::cartsave::SaveType;
+use super::errors::*;
+
+use emu::config::ConfigSection;
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+
+#[cfg(feature = "frontend")]
+mod ui;
+
+/// Settings overridden for a game. Settings which are None keep their
+/// default value (usually detected from the ROM).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameSettings {
+    /// Internal name of the game, only to make the config file readable.
+    pub name: String,
+    /// Save type (eg: "eeprom4k", "sram"; see `SaveType::from_name`).
+    pub save_type: Option<String>,
+    /// CIC model (eg: "6105").
+    pub cic: Option<String>,
+    /// Clock multiplier of the main CPU.
+    pub cpu_clock_ratio: Option<f64>,
+    /// Emulation of graphics microcodes: "hle" or "lle".
+    pub gfx: Option<String>,
+    /// Install the Expansion Pak (8 MiB of RDRAM instead of 4 MiB).
+    pub expansion_pak: Option<bool>,
+    /// Timing model of memory accesses: "fast" or "accurate".
+    pub mem_timing: Option<String>,
+    /// Emulate the contents of the CPU caches.
+    pub cache_emulation: Option<bool>,
+    /// Emulate the VI filters (gamma, dither and divot).
+    pub vi_filters: Option<bool>,
+}
+
+impl GameSettings {
+    /// Return true if no setting is overridden.
+    pub fn is_empty(&self) -> bool {
+        self.save_type.is_none()
+            && self.cic.is_none()
+            && self.cpu_clock_ratio.is_none()
+            && self.gfx.is_none()
+            && self.expansion_pak.is_none()
+            && self.mem_timing.is_none()
+            && self.cache_emulation.is_none()
+            && self.vi_filters.is_none()
+    }
+
+    /// Check that all overridden settings have valid values.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(st) = self.save_type.as_ref() {
+            if SaveType::from_name(st).is_none() {
+                bail!("invalid save type: {}", st);
+            }
+        }
+        if let Some(cic) = self.cic.as_ref() {
+            CicModel::from_name(cic)?;
+        }
+        if let Some(ratio) = self.cpu_clock_ratio {
+            if !ratio.is_finite() || ratio <= 0.0 {
+                bail!("invalid CPU clock ratio: {}", ratio);
+            }
+        }
+        match self.gfx.as_ref().map(|g| g.as_str()) {
+            None | Some("hle") | Some("lle") => {}
+            Some(gfx) => bail!("invalid graphics emulation: {}", gfx),
+        }
+        match self.mem_timing.as_ref().map(|t| t.as_str()) {
+            None | Some("fast") | Some("accurate") => {}
+            Some(timing) => bail!("invalid memory timing: {}", timing),
+        }
+        Ok(())
+    }
+}
+
+/// The settings of all games, persisted in the config file.
+#[derive(Default, Serialize, Deserialize)]
+pub struct GameProfiles(BTreeMap<String, GameSettings>);
+
+impl ConfigSection for GameProfiles {
+    const NAME: &'static str = "games";
+}
+
+impl GameProfiles {
+    fn key(crc: (u32, u32)) -> String {
+        format!("{:08X}-{:08X}", crc.0, crc.1)
+    }
+
+    /// Return the settings of the game with the specified header CRC.
+    pub fn get(&self, crc: (u32, u32)) -> GameSettings {
+        self.0.get(&Self::key(crc)).cloned().unwrap_or_default()
+    }
+
+    /// Update the settings of the game with the specified header CRC. Games
+    /// without overrides are removed from the config.
+    pub fn set(&mut self, crc: (u32, u32), settings: GameSettings) {
+        if settings.is_empty() {
+            self.0.remove(&Self::key(crc));
+        } else {
+            self.0.insert(Self::key(crc), settings);
+        }
+    }
+}
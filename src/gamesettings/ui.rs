@@ -0,0 +1,102 @@
+use super::*;
+use emu::dbg::imgui::*;
+use emu::dbg::DebuggerRenderer;
+
+// Values that can be selected in the UI for each setting. The first one
+// ("auto") means that the setting is not overridden.
+const SAVE_TYPES: [&str; 6] = ["auto", "none", "eeprom4k", "eeprom16k", "sram", "flash"];
+const CIC_MODELS: [&str; 6] = ["auto", "6101", "6102", "6103", "6105", "6106"];
+const GFX_MODES: [&str; 3] = ["auto", "hle", "lle"];
+const EXPANSION_PAK: [&str; 3] = ["auto", "on", "off"];
+const MEM_TIMINGS: [&str; 3] = ["auto", "fast", "accurate"];
+const CACHE_EMULATION: [&str; 3] = ["auto", "on", "off"];
+const VI_FILTERS: [&str; 3] = ["auto", "on", "off"];
+
+impl GameSettings {
+    // Render the window to edit the settings of the current game. Changes
+    // are applied at the next boot.
+    pub(crate) fn render_debug<'a, 'ui>(
+        &mut self,
+        dr: &DebuggerRenderer<'a, 'ui>,
+        crc: (u32, u32),
+    ) {
+        let ui = dr.ui();
+        Window::new(im_str!("[N64] Game Settings"))
+            .size([320.0, 200.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.text(format!("{} ({:08X}-{:08X})", self.name, crc.0, crc.1));
+                ui.separator();
+                combo(ui, im_str!("Save type"), &SAVE_TYPES, &mut self.save_type);
+                combo(ui, im_str!("CIC"), &CIC_MODELS, &mut self.cic);
+                combo(ui, im_str!("Graphics"), &GFX_MODES, &mut self.gfx);
+                combo(
+                    ui,
+                    im_str!("Memory timing"),
+                    &MEM_TIMINGS,
+                    &mut self.mem_timing,
+                );
+
+                let mut pak = self
+                    .expansion_pak
+                    .map(|p| if p { "on" } else { "off" }.to_owned());
+                if combo(ui, im_str!("Expansion Pak"), &EXPANSION_PAK, &mut pak) {
+                    self.expansion_pak = pak.map(|p| p == "on");
+                }
+
+                let mut caches = self
+                    .cache_emulation
+                    .map(|c| if c { "on" } else { "off" }.to_owned());
+                if combo(
+                    ui,
+                    im_str!("Cache emulation"),
+                    &CACHE_EMULATION,
+                    &mut caches,
+                ) {
+                    self.cache_emulation = caches.map(|c| c == "on");
+                }
+
+                let mut filters = self
+                    .vi_filters
+                    .map(|f| if f { "on" } else { "off" }.to_owned());
+                if combo(ui, im_str!("VI filters"), &VI_FILTERS, &mut filters) {
+                    self.vi_filters = filters.map(|f| f == "on");
+                }
+
+                let mut overclock = self.cpu_clock_ratio.is_some();
+                if ui.checkbox(im_str!("Override CPU clock"), &mut overclock) {
+                    self.cpu_clock_ratio = if overclock { Some(1.0) } else { None };
+                }
+                if let Some(ratio) = self.cpu_clock_ratio.as_mut() {
+                    let mut r = *ratio as f32;
+                    if ui
+                        .slider_float(im_str!("CPU clock ratio"), &mut r, 0.25, 4.0)
+                        .build()
+                    {
+                        *ratio = r as f64;
+                    }
+                }
+                ui.separator();
+                ui.text_disabled("Saved on exit, applied at the next boot.");
+            });
+    }
+}
+
+// Show a combo to select the value of a setting, where the first value
+// means no override. Returns true if the setting was changed.
+fn combo(ui: &Ui, label: &ImStr, values: &[&str], setting: &mut Option<String>) -> bool {
+    let names: Vec<ImString> = values.iter().map(|v| ImString::new(*v)).collect();
+    let names: Vec<&ImStr> = names.iter().map(|n| n.as_ref()).collect();
+    let mut idx = setting
+        .as_ref()
+        .and_then(|s| values.iter().position(|v| v == s))
+        .unwrap_or(0);
+    if !ComboBox::new(label).build_simple_string(ui, &mut idx, &names) {
+        return false;
+    }
+    *setting = if idx == 0 {
+        None
+    } else {
+        Some(values[idx].to_owned())
+    };
+    true
+}
@@ -0,0 +1,304 @@
+//! Game Boy cartridge, as seen through the Transfer Pak.
+//!
+//! Only the cartridge is emulated (ROM, battery-backed RAM and the memory
+//! bank controller), not the Game Boy itself: the N64 accesses the cartridge
+//! bus directly, and performs bank switching by writing to the MBC registers.
+use crate::errors::*;
+use emu::backup::{BackupMem, FlushPolicy};
+
+use std::fs;
+use std::path::Path;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Mbc {
+    None,
+    Mbc1,
+    Mbc3,
+    Mbc5,
+}
+
+pub struct GbCart {
+    rom: Vec<u8>,
+    ram: BackupMem,
+    mbc: Mbc,
+    rom_bank: usize,
+    ram_bank: usize, // on MBC1, also the upper bits of the ROM bank
+    ram_enable: bool,
+    mode: u8, // MBC1 banking mode
+}
+
+impl GbCart {
+    /// Load a Game Boy ROM, and its battery-backed RAM (if present) from
+    /// `save_path`, which is kept in sync according to the flush policy.
+    pub fn new(
+        logger: slog::Logger,
+        rom_path: &Path,
+        save_path: &Path,
+        policy: FlushPolicy,
+    ) -> Result<GbCart> {
+        let rom = fs::read(rom_path)
+            .chain_err(|| format!("cannot open Game Boy ROM {}", rom_path.display()))?;
+        if rom.len() < 0x8000 {
+            bail!("invalid Game Boy ROM {}: too small", rom_path.display());
+        }
+
+        let (mbc, battery) = match rom[0x147] {
+            0x00 => (Mbc::None, false),
+            0x01 | 0x02 => (Mbc::Mbc1, false),
+            0x03 => (Mbc::Mbc1, true),
+            0x0F | 0x10 | 0x13 => (Mbc::Mbc3, true),
+            0x11 | 0x12 => (Mbc::Mbc3, false),
+            0x19 | 0x1A | 0x1C | 0x1D => (Mbc::Mbc5, false),
+            0x1B | 0x1E => (Mbc::Mbc5, true),
+            t => bail!("unsupported Game Boy cartridge type: {:02x}", t),
+        };
+        let ram_size = match rom[0x149] {
+            0x01 => 2 * 1024,
+            0x02 => 8 * 1024,
+            0x03 => 32 * 1024,
+            0x04 => 128 * 1024,
+            0x05 => 64 * 1024,
+            _ => 0,
+        };
+
+        // RAM without a battery is never persisted.
+        let save_path = Some(save_path).filter(|_| battery);
+        let ram = BackupMem::new(logger, save_path, ram_size, 0, policy)?;
+
+        Ok(GbCart {
+            rom,
+            ram,
+            mbc,
+            rom_bank: 1,
+            ram_bank: 0,
+            ram_enable: false,
+            mode: 0,
+        })
+    }
+
+    /// Title of the game, as found in the cartridge header.
+    pub fn title(&self) -> String {
+        self.rom[0x134..0x144]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as char)
+            .collect()
+    }
+
+    fn rom_byte(&self, bank: usize, addr: u16) -> u8 {
+        let off = bank * 0x4000 + (addr as usize & 0x3FFF);
+        self.rom[off % self.rom.len()]
+    }
+
+    // Offset within the RAM of an address in the 0xA000-0xBFFF window. In
+    // MBC1 mode 0, the RAM bank register selects the upper ROM bits instead.
+    fn ram_offset(&self, addr: u16) -> usize {
+        let bank = if self.mbc == Mbc::Mbc1 && self.mode == 0 {
+            0
+        } else {
+            self.ram_bank
+        };
+        bank * 0x2000 + (addr as usize - 0xA000)
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => {
+                let bank = if self.mbc == Mbc::Mbc1 && self.mode == 1 {
+                    self.rom_bank & !0x1F
+                } else {
+                    0
+                };
+                self.rom_byte(bank, addr)
+            }
+            0x4000..=0x7FFF => self.rom_byte(self.rom_bank, addr),
+            0xA000..=0xBFFF => {
+                let ram = self.ram.data();
+                if !self.ram_enable || ram.is_empty() {
+                    return 0xFF;
+                }
+                ram[self.ram_offset(addr) % ram.len()]
+            }
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match (self.mbc, addr) {
+            (Mbc::None, _) => {}
+            (_, 0x0000..=0x1FFF) => self.ram_enable = val & 0x0F == 0x0A,
+            (Mbc::Mbc1, 0x2000..=0x3FFF) => {
+                let low = (val as usize & 0x1F).max(1);
+                self.rom_bank = (self.rom_bank & !0x1F) | low;
+            }
+            (Mbc::Mbc1, 0x4000..=0x5FFF) => {
+                // The same register is used as RAM bank (in mode 1) and as
+                // upper bits of the ROM bank, depending on the banking mode.
+                self.ram_bank = val as usize & 3;
+                self.rom_bank = (self.rom_bank & 0x1F) | (self.ram_bank << 5);
+            }
+            (Mbc::Mbc1, 0x6000..=0x7FFF) => self.mode = val & 1,
+            (Mbc::Mbc3, 0x2000..=0x3FFF) => self.rom_bank = (val as usize & 0x7F).max(1),
+            // RTC registers (0x08-0x0C) are not emulated.
+            (Mbc::Mbc3, 0x4000..=0x5FFF) => self.ram_bank = val as usize & 3,
+            (Mbc::Mbc5, 0x2000..=0x2FFF) => {
+                self.rom_bank = (self.rom_bank & 0x100) | val as usize;
+            }
+            (Mbc::Mbc5, 0x3000..=0x3FFF) => {
+                self.rom_bank = (self.rom_bank & 0xFF) | ((val as usize & 1) << 8);
+            }
+            (Mbc::Mbc5, 0x4000..=0x5FFF) => self.ram_bank = val as usize & 0xF,
+            (_, 0xA000..=0xBFFF) => {
+                let len = self.ram.data().len();
+                if self.ram_enable && len != 0 {
+                    let off = self.ram_offset(addr) % len;
+                    self.ram.write(off, &[val]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Must be called at the end of each frame, to persist the
+    /// battery-backed RAM according to the flush policy.
+    pub fn end_frame(&mut self) -> std::io::Result<()> {
+        self.ram.end_frame()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+
+    fn tmppath(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("r64emu-gbcart-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    // Create a cartridge whose ROM banks start with their own bank number
+    // (16-bit, little endian), so that bank switching can be observed.
+    fn cart(name: &str, kind: u8, banks: usize, ram_size: u8) -> (GbCart, PathBuf) {
+        let mut rom = vec![0u8; banks * 0x4000];
+        for bank in 0..banks {
+            rom[bank * 0x4000] = bank as u8;
+            rom[bank * 0x4000 + 1] = (bank >> 8) as u8;
+        }
+        rom[0x147] = kind;
+        rom[0x149] = ram_size;
+        let rom_path = tmppath(&format!("{}.gb", name));
+        let save_path = tmppath(&format!("{}.sav", name));
+        fs::write(&rom_path, &rom).unwrap();
+
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let cart = GbCart::new(logger, &rom_path, &save_path, FlushPolicy::WriteThrough).unwrap();
+        let _ = fs::remove_file(&rom_path);
+        (cart, save_path)
+    }
+
+    fn bank(cart: &GbCart, addr: u16) -> usize {
+        cart.read(addr) as usize | (cart.read(addr + 1) as usize) << 8
+    }
+
+    #[test]
+    fn mbc1() {
+        let (mut c, save) = cart("mbc1", 0x03, 128, 0x03);
+        assert_eq!((bank(&c, 0x0000), bank(&c, 0x4000)), (0, 1));
+
+        // Bank 0 is mapped as bank 1, also with upper bits set.
+        c.write(0x2000, 0x00);
+        assert_eq!(bank(&c, 0x4000), 1);
+        c.write(0x2000, 0x1F);
+        assert_eq!(bank(&c, 0x4000), 0x1F);
+        c.write(0x4000, 0x02);
+        assert_eq!(bank(&c, 0x4000), 0x5F);
+        c.write(0x2000, 0x00);
+        assert_eq!(bank(&c, 0x4000), 0x41);
+
+        // In mode 1, upper bits also apply to 0x0000-0x3FFF.
+        assert_eq!(bank(&c, 0x0000), 0);
+        c.write(0x6000, 0x01);
+        assert_eq!((bank(&c, 0x0000), bank(&c, 0x4000)), (0x40, 0x41));
+
+        // RAM is disabled at startup, and banked only in mode 1.
+        assert_eq!(c.read(0xA000), 0xFF);
+        c.write(0xA000, 0x11);
+        c.write(0x0000, 0x0A);
+        assert_eq!(c.read(0xA000), 0x00);
+        c.write(0xA000, 0x22);
+        c.write(0x6000, 0x00);
+        assert_eq!(c.read(0xA000), 0x00);
+        c.write(0xA000, 0x33);
+        c.write(0x6000, 0x01);
+        assert_eq!(c.read(0xA000), 0x22);
+
+        c.write(0x0000, 0x00);
+        assert_eq!(c.read(0xA000), 0xFF);
+        c.write(0xA000, 0x44);
+
+        // The battery-backed RAM is persisted.
+        let data = fs::read(&save).unwrap();
+        assert_eq!(
+            (data.len(), data[0x0000], data[0x4000]),
+            (32 * 1024, 0x33, 0x22)
+        );
+        let _ = fs::remove_file(&save);
+    }
+
+    #[test]
+    fn mbc3() {
+        let (mut c, save) = cart("mbc3", 0x13, 128, 0x03);
+        c.write(0x2000, 0x00);
+        assert_eq!(bank(&c, 0x4000), 1);
+        c.write(0x2000, 0x7F);
+        assert_eq!((bank(&c, 0x0000), bank(&c, 0x4000)), (0, 0x7F));
+
+        c.write(0x0000, 0x0A);
+        for b in 0..4 {
+            c.write(0x4000, b);
+            c.write(0xBFFF, 0x10 + b);
+        }
+        for b in 0..4 {
+            c.write(0x4000, b);
+            assert_eq!(c.read(0xBFFF), 0x10 + b);
+        }
+        let _ = fs::remove_file(&save);
+    }
+
+    #[test]
+    fn mbc5() {
+        let (mut c, save) = cart("mbc5", 0x1B, 512, 0x04);
+        // Unlike other MBCs, bank 0 can be mapped at 0x4000.
+        c.write(0x2000, 0x00);
+        assert_eq!(bank(&c, 0x4000), 0);
+        c.write(0x2000, 0x34);
+        c.write(0x3000, 0x01);
+        assert_eq!(bank(&c, 0x4000), 0x134);
+        c.write(0x2000, 0xFF);
+        assert_eq!(bank(&c, 0x4000), 0x1FF);
+        c.write(0x3000, 0x00);
+        assert_eq!(bank(&c, 0x4000), 0xFF);
+
+        c.write(0x0000, 0x0A);
+        c.write(0x4000, 0x0F);
+        c.write(0xA000, 0x5A);
+        c.write(0x4000, 0x00);
+        assert_eq!(c.read(0xA000), 0x00);
+        c.write(0x4000, 0x0F);
+        assert_eq!(c.read(0xA000), 0x5A);
+        assert_eq!(fs::read(&save).unwrap()[0x1E000], 0x5A);
+        let _ = fs::remove_file(&save);
+    }
+
+    #[test]
+    fn no_battery() {
+        // MBC1+RAM: the RAM works, but is not persisted.
+        let (mut c, save) = cart("nobatt", 0x02, 4, 0x02);
+        c.write(0x0000, 0x0A);
+        c.write(0xA000, 0x77);
+        assert_eq!(c.read(0xA000), 0x77);
+        assert!(!save.exists());
+    }
+}
@@ -0,0 +1,280 @@
+//! Per-game database.
+//!
+//! Some games rely on behaviors that we don't emulate accurately (yet), or
+//! run much faster with some help from the emulator. Instead of checking for
+//! specific games within device code, all quirks are declared in the
+//! database below (keyed by the CRC pair found in the ROM header), and applied
+//! in a single place by `GameHacks::apply_*` functions, which are called
+//! by `N64` at the right moments. The database also records the properties
+//! of the cartridge that can't be detected from the ROM, like the save type.
+use super::cartridge::Cartridge;
+use super::cartsave::SaveType;
+use super::r4300::R4300;
+use super::sp::Sp;
+use emu::bus::be::Device;
+use emu::int::Numerics;
+use slog;
+
+/// Quirks that must be applied to a specific game.
+pub struct GameHacks {
+    /// Name of the game (for logging purposes).
+    pub name: &'static str,
+
+    /// CRC1/CRC2 as found in the ROM header (offset 0x10).
+    pub crc: (u32, u32),
+
+    /// Overrides the ratio between the CPU clock and the main clock, expressed
+    /// as a fraction (num, den). The default is (3, 2), that is 93.75 Mhz.
+    /// Lowering this makes the game run fewer instructions per frame,
+    /// similar to the "counter factor" found in other emulators.
+    pub cpu_clock_ratio: Option<(i64, i64)>,
+
+    /// Memory patches (bus address, value) written after the PIF/CIC boot
+    /// setup, before the game starts running.
+    pub boot_patches: &'static [(u32, u32)],
+
+    /// Addresses of idle loops that are not caught by the automatic
+    /// busy-wait detection of the CPU core.
+    pub idle_loops: &'static [u32],
+
+    /// Run the CPU as a plain interpreter, disabling the automatic busy-wait
+    /// detection (the equivalent of forcing the interpreter in emulators with
+    /// a recompiler), for games whose wait loops are wrongly detected as
+    /// idle and skipped.
+    pub force_interpreter: bool,
+
+    /// Overrides whether graphics tasks are executed through the microcode
+    /// HLE (true) or on the RSP (false), for games known to work correctly
+    /// (or not) with it. The default is running them on the RSP.
+    pub gfx_hle: Option<bool>,
+
+    /// Save chip of the cartridge, which can't be detected from the ROM.
+    /// Unknown games are assumed to use a 4 Kbit EEPROM (the most common
+    /// type); the save type can also be overridden in the game settings.
+    pub save_type: Option<SaveType>,
+}
+
+// Default hacks for unknown games: no quirks.
+const NO_HACKS: GameHacks = GameHacks {
+    name: "",
+    crc: (0, 0),
+    cpu_clock_ratio: None,
+    boot_patches: &[],
+    idle_loops: &[],
+    force_interpreter: false,
+    gfx_hle: None,
+    save_type: None,
+};
+
+// Database of known games, sorted by CRC. The current entries only record
+// the save type of the cartridge, which needs no explanation; quirks added
+// to an entry (clock ratio, patches, idle loops, ...) must come with a
+// comment explaining why the game needs them.
+static DATABASE: &'static [GameHacks] = &[
+    GameHacks {
+        name: "Conker's Bad Fur Day (U)",
+        crc: (0x30C7_AC50, 0x7704_072D),
+        save_type: Some(SaveType::Eeprom16K),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "Mario Kart 64 (U)",
+        crc: (0x3E50_55B6, 0x2E92_DA52),
+        save_type: Some(SaveType::Eeprom4K),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "Kirby 64 (U)",
+        crc: (0x4603_9FB4, 0x0337_822C),
+        save_type: Some(SaveType::Eeprom4K),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "The Legend of Zelda: Majora's Mask (U)",
+        crc: (0x5354_631C, 0x03A2_DEF0),
+        save_type: Some(SaveType::FlashRam),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "Diddy Kong Racing (U)",
+        crc: (0x53D4_40E7, 0x7519_B011),
+        save_type: Some(SaveType::Eeprom4K),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "Super Mario 64 (U)",
+        crc: (0x635A_2BFF, 0x8B02_2326),
+        save_type: Some(SaveType::Eeprom4K),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "Paper Mario (U)",
+        crc: (0x65EE_E53A, 0xED7D_733C),
+        save_type: Some(SaveType::FlashRam),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "Mario Golf (U)",
+        crc: (0x664B_A3D4, 0x678A_80B7),
+        save_type: Some(SaveType::Sram),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "Jet Force Gemini (U)",
+        crc: (0x8A60_09B6, 0x94AC_E150),
+        save_type: Some(SaveType::FlashRam),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "Super Smash Bros. (U)",
+        crc: (0x916B_8B5B, 0x780B_85A4),
+        save_type: Some(SaveType::Sram),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "Banjo-Kazooie (U)",
+        crc: (0xA4BF_9306, 0xBF0C_DFD1),
+        save_type: Some(SaveType::Eeprom4K),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "Star Fox 64 (U)",
+        crc: (0xA7D0_15F8, 0x2289_AA43),
+        save_type: Some(SaveType::Eeprom4K),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "Banjo-Tooie (U)",
+        crc: (0xC2E9_AA9A, 0x475D_70AA),
+        save_type: Some(SaveType::Eeprom16K),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "Pilotwings 64 (U)",
+        crc: (0xC851_961C, 0x78FC_AAFA),
+        save_type: Some(SaveType::Eeprom4K),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "Pokemon Snap (U)",
+        crc: (0xCA12_B547, 0x71FA_4EE4),
+        save_type: Some(SaveType::FlashRam),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "GoldenEye 007 (U)",
+        crc: (0xDCBC_50D1, 0x09FD_1AA3),
+        save_type: Some(SaveType::Eeprom4K),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "Donkey Kong 64 (U)",
+        crc: (0xEC58_EABF, 0xAD7C_7169),
+        save_type: Some(SaveType::Eeprom16K),
+        ..NO_HACKS
+    },
+    GameHacks {
+        name: "The Legend of Zelda: Ocarina of Time (U)",
+        crc: (0xEC70_11B7, 0x7616_D72B),
+        save_type: Some(SaveType::Sram),
+        ..NO_HACKS
+    },
+];
+
+impl GameHacks {
+    /// Lookup the quirks required by the cartridge currently inserted.
+    /// Always returns a valid entry, that does nothing for unknown games.
+    pub fn lookup(cart: &Cartridge) -> &'static GameHacks {
+        Self::lookup_crc(cart.header_crc())
+    }
+
+    fn lookup_crc(crc: (u32, u32)) -> &'static GameHacks {
+        DATABASE.iter().find(|h| h.crc == crc).unwrap_or(&NO_HACKS)
+    }
+
+    /// Return true if no quirk must be applied. The save type is not a
+    /// quirk, so it's not considered.
+    pub fn is_empty(&self) -> bool {
+        self.cpu_clock_ratio.is_none()
+            && self.boot_patches.is_empty()
+            && self.idle_loops.is_empty()
+            && !self.force_interpreter
+            && self.gfx_hle.is_none()
+    }
+
+    /// Compute the CPU clock given the main clock.
+    pub fn cpu_clock(&self, main_clock: i64) -> i64 {
+        let (num, den) = self.cpu_clock_ratio.unwrap_or((3, 2));
+        main_clock * num / den
+    }
+
+    /// Apply the hacks that affect the CPU core and the RSP. This must be
+    /// called once after the CPU has been created.
+    pub fn apply_cpu(&self, logger: &slog::Logger) {
+        if !self.is_empty() {
+            info!(logger, "applying game hacks"; "game" => self.name);
+        }
+        let cpu = R4300::get_mut();
+        cpu.set_idle_loops(self.idle_loops);
+        cpu.set_busy_wait_detection(!self.force_interpreter);
+        if let Some(hle) = self.gfx_hle {
+            Sp::get_mut().set_gfx_hle(hle);
+        }
+    }
+
+    /// Apply the memory patches. This must be called after each reset,
+    /// after the boot process has been setup.
+    pub fn apply_boot_patches(&self, logger: &slog::Logger) {
+        for (addr, val) in self.boot_patches {
+            info!(logger, "applying boot patch"; "addr" => addr.hex(), "val" => val.hex());
+            R4300::get_mut().bus.write::<u32>(*addr, *val);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn database_sorted() {
+        for w in DATABASE.windows(2) {
+            assert!(w[0].crc < w[1].crc, "{} is not sorted", w[1].name);
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let sm64 = GameHacks::lookup_crc((0x635A_2BFF, 0x8B02_2326));
+        assert_eq!(sm64.name, "Super Mario 64 (U)");
+        assert_eq!(sm64.save_type, Some(SaveType::Eeprom4K));
+        assert!(sm64.is_empty());
+
+        let oot = GameHacks::lookup_crc((0xEC70_11B7, 0x7616_D72B));
+        assert_eq!(oot.save_type, Some(SaveType::Sram));
+
+        // Both CRCs must match.
+        let unknown = GameHacks::lookup_crc((0x635A_2BFF, 0));
+        assert_eq!(unknown.name, "");
+        assert_eq!(unknown.save_type, None);
+        assert!(unknown.is_empty());
+    }
+
+    #[test]
+    fn quirks() {
+        let hacks = GameHacks {
+            name: "test",
+            crc: (1, 2),
+            force_interpreter: true,
+            ..NO_HACKS
+        };
+        assert!(!hacks.is_empty());
+        assert_eq!(hacks.cpu_clock(62_500_000), 93_750_000);
+
+        let hacks = GameHacks {
+            cpu_clock_ratio: Some((1, 1)),
+            ..hacks
+        };
+        assert_eq!(hacks.cpu_clock(62_500_000), 62_500_000);
+    }
+}
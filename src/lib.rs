@@ -28,17 +28,32 @@ pub mod errors {
 }
 
 mod rdp;
+mod regdb;
 
 pub mod ai;
 pub mod r4300;
 pub mod cartridge;
+pub mod cartsave;
+pub mod cheats;
+pub mod dmalog;
 pub mod dp;
+pub mod gamesettings;
+pub mod gbcart;
+pub mod hacks;
 pub mod mi;
+pub mod movie;
+pub mod netplay;
+pub mod pak;
 pub mod pi;
+pub mod ramsearch;
 pub mod ri;
+pub mod rombrowser;
+pub mod script;
 pub mod si;
 pub mod sp;
 pub mod vi;
 
 mod n64;
-pub use self::n64::N64;
+pub use self::n64::{BootMode, N64};
+pub use self::rdp::RenderBackend;
+pub use mips64::MemTiming;
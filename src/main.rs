@@ -1,19 +1,58 @@
 #[macro_use]
 extern crate error_chain;
 
-use emu::dbg;
+use emu::backup::FlushPolicy;
+use emu::config::Config;
+use emu::dbg::{self, DebuggerModel, DisasmView, InsnTraceView};
+use emu::gfx::{OwnedGfxBufferLE, Rgb888};
 use emu::hw;
 use emu::log;
+use emu::snd::{OwnedSndBuffer, S16_STEREO};
+use r64emu::cartridge::{self, Cartridge, CicModel, RomHeader};
+use r64emu::cartsave::SaveType;
 use r64emu::errors::*;
-use r64emu::N64;
-
-use std::path::Path;
+use r64emu::gamesettings::{GameProfiles, GameSettings};
+use r64emu::netplay::NetplayConfig;
+use r64emu::pak::PakKind;
+use r64emu::r4300::R4300;
+use r64emu::ri::RdramSize;
+use r64emu::rombrowser::RomBrowser;
+use r64emu::vi::TvType;
+use r64emu::{BootMode, MemTiming, RenderBackend, N64};
 
+use byteorder::{BigEndian, ByteOrder};
+use slog::{info, o};
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
-#[structopt(raw(setting = "structopt::clap::AppSettings::ColoredHelp"))]
-struct Cli {
+#[structopt(raw(global_setting = "structopt::clap::AppSettings::ColoredHelp"))]
+enum Cli {
+    /// Run a ROM (with the ROM browser if no ROM is specified). This is the
+    /// default when no arguments are given.
+    #[structopt(name = "run")]
+    Run(RunArgs),
+
+    /// Disassemble a region of a ROM
+    #[structopt(name = "disasm")]
+    Disasm(DisasmArgs),
+
+    /// Show the header of a ROM, and verify its CRC
+    #[structopt(name = "romcheck")]
+    RomCheck(RomCheckArgs),
+
+    /// Run a ROM without window for a number of frames, writing all the
+    /// instructions executed by the CPUs into a trace file
+    #[structopt(name = "trace")]
+    Trace(TraceArgs),
+
+    /// Run a ROM without window for a number of frames, and then export the
+    /// disassembly of a range of the main CPU address space into a listing
+    #[structopt(name = "disasm-export")]
+    DisasmExport(DisasmExportArgs),
+}
+
+#[derive(StructOpt)]
+struct RunArgs {
     /// Activate debugger at start
     #[structopt(short = "d", long = "debugger")]
     debugger: bool,
@@ -27,22 +66,799 @@ struct Cli {
     )]
     bios: std::path::PathBuf,
 
+    /// Boot without running PIF ROM and IPL3 (no BIOS file required). This
+    /// is the default if the BIOS file is not found.
+    #[structopt(long = "hle-boot")]
+    hle_boot: bool,
+
+    /// CIC model of the cartridge (eg: 6105). By default, it is detected
+    /// from the ROM boot code.
+    #[structopt(long = "cic")]
+    cic: Option<String>,
+
+    /// Handle FPU denormals and NaNs as in IEEE 754, instead of emulating
+    /// the R4300 FPU behavior
+    #[structopt(long = "fpu-ieee")]
+    fpu_ieee: bool,
+
+    /// Emulation of graphics microcodes: high-level (HLE, faster but less
+    /// accurate, for F3D/F3DEX/F3DEX2 only), on the RSP (LLE), or the
+    /// default for the game (auto)
+    #[structopt(
+        long = "gfx",
+        default_value = "auto",
+        raw(possible_values = r#"&["auto", "hle", "lle"]"#)
+    )]
+    gfx: String,
+
+    /// Timing model of the main CPU memory accesses: fast (every opcode
+    /// takes one cycle), accurate (emulate caches and memory latency, for
+    /// timing-sensitive games and test ROMs), or the default for the game
+    /// (auto)
+    #[structopt(
+        long = "mem-timing",
+        default_value = "auto",
+        raw(possible_values = r#"&["auto", "fast", "accurate"]"#)
+    )]
+    mem_timing: String,
+
+    /// Emulate the contents of the main CPU caches, for games relying on
+    /// stale cache lines (eg: missing invalidations around DMAs). It is
+    /// disabled by default, unless enabled in the game settings.
+    #[structopt(long = "cache-emulation", raw(possible_values = r#"&["on", "off"]"#))]
+    cache_emulation: Option<String>,
+
+    /// Number of threads used to render RDP primitives (0 or 1 to render on
+    /// the emulation thread)
+    #[structopt(long = "rdp-threads", default_value = "4")]
+    rdp_threads: usize,
+
+    /// Renderer used by the RDP: software rasterizer, or OpenGL (drawing on
+    /// the GPU; only available with the debugger, where it can also be
+    /// switched at runtime)
+    #[structopt(
+        long = "rdp-backend",
+        default_value = "soft",
+        raw(possible_values = r#"&["soft", "gl"]"#)
+    )]
+    rdp_backend: String,
+
+    /// Render RDP primitives in lockstep with the emulation, instead of in
+    /// background on dedicated threads
+    #[structopt(long = "sync-rdp")]
+    sync_rdp: bool,
+
+    /// Clock multiplier for the main CPU (eg: 2.0 to overclock it at twice
+    /// its speed, to reduce slowdowns in some games)
+    #[structopt(long = "cpu-clock-ratio")]
+    cpu_clock_ratio: Option<f64>,
+
+    /// Sync granularity of a subsystem, as NAME=CYCLES (eg: RSP=0): how
+    /// many VI clock cycles it can run out of step with the others. Lower
+    /// values are more accurate, higher values are faster. Can be repeated.
+    #[structopt(long = "sync-granularity", number_of_values = 1)]
+    sync_granularity: Vec<String>,
+
+    /// Install the Expansion Pak (8 MiB of RDRAM, required by some games
+    /// like Majora's Mask). It is installed by default; use "off" to test
+    /// a game with the base 4 MiB.
+    #[structopt(long = "expansion-pak", raw(possible_values = r#"&["on", "off"]"#))]
+    expansion_pak: Option<String>,
+
+    /// Path to a symbol file for the debugger (ELF, nm output or .map).
+    /// If not specified, a .sym file next to the ROM is used if present.
+    #[structopt(long = "symbols", parse(from_os_str))]
+    symbols: Option<std::path::PathBuf>,
+
+    /// Cartridge save type. By default, it is detected through a database of
+    /// known games. Saves are stored next to the ROM (.eep, .sra, .fla).
+    #[structopt(
+        long = "save-type",
+        default_value = "auto",
+        raw(possible_values = r#"&["auto", "none", "eeprom4k", "eeprom16k", "sram", "flash"]"#)
+    )]
+    save_type: String,
+
+    /// When saves and Controller Paks are written to disk: after each write
+    /// ("write-through"), at most once every N frames ("interval:N"), or
+    /// only on exit ("on-exit"). Files are always replaced atomically.
+    #[structopt(long = "save-flush", default_value = "interval:60")]
+    save_flush: String,
+
+    /// Accessory plugged into the first controller: a Controller Pak
+    /// (persisted next to the ROM as .mpk), a Rumble Pak (forwarded to the
+    /// gamepad, if it supports force feedback), a Transfer Pak (see --gb-rom),
+    /// or none
+    #[structopt(
+        long = "pak",
+        default_value = "mempak",
+        raw(possible_values = r#"&["none", "mempak", "rumble", "transfer"]"#)
+    )]
+    pak: String,
+
+    /// Game Boy ROM inserted into the Transfer Pak (requires --pak transfer)
+    #[structopt(long = "gb-rom", parse(from_os_str))]
+    gb_rom: Option<std::path::PathBuf>,
+
+    /// Game Boy battery save of the Transfer Pak cartridge. Defaults to the
+    /// Game Boy ROM path with .sav extension.
+    #[structopt(long = "gb-save", parse(from_os_str))]
+    gb_save: Option<std::path::PathBuf>,
+
+    /// Import a raw Controller Pak image (.mpk) into the game's Controller
+    /// Pak, replacing its contents
+    #[structopt(long = "mpk-import", parse(from_os_str))]
+    mpk_import: Option<std::path::PathBuf>,
+
+    /// Export the game's Controller Pak as a raw image (.mpk), and exit
+    #[structopt(long = "mpk-export", parse(from_os_str))]
+    mpk_export: Option<std::path::PathBuf>,
+
+    /// Deterministic mode: ignore the host input and on-disk saves, so that
+    /// runs with the same movie produce identical video and audio
+    #[structopt(long = "deterministic")]
+    deterministic: bool,
+
+    /// Seed of the pseudo-random noise generated by the emulated hardware
+    /// (it must be the same on both sides of a netplay session)
+    #[structopt(long = "seed", default_value = "0")]
+    seed: u32,
+
+    /// Record the controller inputs into a movie file (Mupen64 .m64 format)
+    #[structopt(long = "record", parse(from_os_str))]
+    record: Option<std::path::PathBuf>,
+
+    /// Start the recorded movie from this savestate instead of power-on.
+    /// The state is copied next to the movie, with .state extension.
+    #[structopt(long = "record-from-state", parse(from_os_str))]
+    record_from_state: Option<std::path::PathBuf>,
+
+    /// Play back a movie file (Mupen64 .m64 format), replacing the
+    /// controller inputs
+    #[structopt(long = "play", parse(from_os_str), conflicts_with = "record")]
+    play: Option<std::path::PathBuf>,
+
+    /// Play over the network with another instance running the same game
+    /// (address of the peer, as host:port). Implies --deterministic.
+    #[structopt(long = "netplay")]
+    netplay: Option<String>,
+
+    /// Local UDP port for netplay
+    #[structopt(long = "netplay-port", default_value = "6464")]
+    netplay_port: u16,
+
+    /// Player controlled by this instance in netplay (the peer must be the
+    /// other one)
+    #[structopt(
+        long = "netplay-player",
+        default_value = "1",
+        raw(possible_values = r#"&["1", "2"]"#)
+    )]
+    netplay_player: usize,
+
+    /// Netplay input delay, in frames: higher values hide more network
+    /// latency, at the cost of less responsive controls
+    #[structopt(long = "netplay-delay", default_value = "2")]
+    netplay_delay: usize,
+
+    /// Netplay with rollback: predict the inputs of the peer instead of
+    /// waiting for them, and emulate again the mispredicted frames. Both
+    /// instances must use it.
+    #[structopt(long = "netplay-rollback")]
+    netplay_rollback: bool,
+
+    /// Scaling of the output to the window: stretch to fill it, keep the 4:3
+    /// aspect ratio (letterboxing), or integer scaling. Video options are
+    /// saved in the config file, so they also apply to the next runs.
+    #[structopt(
+        long = "scale",
+        raw(possible_values = r#"&["stretch", "aspect", "integer"]"#)
+    )]
+    scale: Option<String>,
+
+    /// Use nearest-neighbor filtering instead of bilinear
+    #[structopt(long = "nearest")]
+    nearest: bool,
+
+    /// Strength of the CRT-style scanline effect (from 0.0 to 1.0)
+    #[structopt(long = "scanlines")]
+    scanlines: Option<f32>,
+
+    /// Load an external GLSL post-processing shader, reloaded automatically
+    /// whenever the file changes
+    #[structopt(long = "shader", parse(from_os_str))]
+    shader: Option<std::path::PathBuf>,
+
+    /// Directory where screenshots (F12) are saved
+    #[structopt(long = "screenshot-dir", parse(from_os_str), default_value = ".")]
+    screenshot_dir: std::path::PathBuf,
+
+    /// Record video and audio output: into a lossless video file through
+    /// ffmpeg (.mkv, .avi or .mov), or else into a directory as a PNG
+    /// sequence plus WAV (toggle with F10)
+    #[structopt(long = "record-video", parse(from_os_str))]
+    record_video: Option<std::path::PathBuf>,
+
+    /// Speed multiplier of turbo mode (toggled with T)
+    #[structopt(long = "turbo", default_value = "2.0")]
+    turbo: f32,
+
+    /// Speed of slow motion (toggled with L), as a fraction of the nominal
+    /// speed
+    #[structopt(long = "slow-motion", default_value = "0.25")]
+    slow_motion: f32,
+
+    /// Mute audio in slow motion, instead of stretching it
+    #[structopt(long = "mute-slow-motion")]
+    mute_slow_motion: bool,
+
+    /// Path to the input mapping config file (TOML). It is created with the
+    /// default mapping if it does not exist, and reloaded when modified.
+    /// Defaults to input.toml next to the config file.
+    #[structopt(long = "input-config", parse(from_os_str))]
+    input_config: Option<std::path::PathBuf>,
+
+    /// Run a Lua script, with access to memory, registers, input and
+    /// on-screen text (see the documentation of r64emu::script)
+    #[structopt(long = "script", parse(from_os_str))]
+    script: Option<std::path::PathBuf>,
+
+    /// Load GameShark cheats from this file, instead of the cheat file next
+    /// to the ROM (with .cht extension)
+    #[structopt(long = "cheats", parse(from_os_str))]
+    cheats: Option<std::path::PathBuf>,
+
+    /// Run without window and audio (for CI and automated testing), for the
+    /// number of frames specified with --frames
+    #[structopt(long = "headless")]
+    headless: bool,
+
+    /// Number of frames to run in headless mode (and in the trace command)
+    #[structopt(long = "frames", default_value = "60")]
+    frames: usize,
+
+    /// In headless mode, print the hash of each frame
+    #[structopt(long = "print-hashes")]
+    print_hashes: bool,
+
+    /// In headless mode, save each frame as PNG into the specified directory
+    #[structopt(long = "dump-png", parse(from_os_str))]
+    dump_png: Option<std::path::PathBuf>,
+
+    /// In headless mode, exit with an error if the hash of the last frame
+    /// differs from the specified one (in hex)
+    #[structopt(long = "expect-hash")]
+    expect_hash: Option<String>,
+
+    /// Serve a remote debugger (JSON-RPC over TCP, see emu::dbg::RemoteServer)
+    /// on the specified address (eg: 127.0.0.1:6465), with --debugger or
+    /// --headless. In headless mode, the emulation starts paused and runs
+    /// until a client sends "quit".
+    #[structopt(long = "remote-debugger")]
+    remote_debugger: Option<String>,
+
+    /// Config file, holding the video options, the layout of the debugger
+    /// windows, the ROM browser directory and the list of recently played
+    /// ROMs. Defaults to r64emu.toml in the platform config directory (eg:
+    /// ~/.config/r64emu on Linux).
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<std::path::PathBuf>,
+
+    /// Directory shown in the ROM browser
+    #[structopt(long = "rom-dir", parse(from_os_str))]
+    rom_dir: Option<std::path::PathBuf>,
+
+    /// Disable colors in console logs (they are always disabled if the
+    /// output is not a terminal)
+    #[structopt(long = "no-color")]
+    no_color: bool,
+
+    /// Timestamp console logs with the emulated time (frame and cycles)
+    /// instead of the host time, so that logs of different runs align
+    #[structopt(long = "log-emu-time")]
+    log_emu_time: bool,
+
+    /// Also write all logs to this file, as newline-delimited JSON
+    /// (one object per record), for post-processing
+    #[structopt(long = "log-json", parse(from_os_str))]
+    log_json: Option<std::path::PathBuf>,
+
+    /// Path to the ROM file. If not specified, a ROM browser is shown.
+    #[structopt(parse(from_os_str))]
+    rom: Option<std::path::PathBuf>,
+}
+
+#[derive(StructOpt)]
+struct DisasmArgs {
+    /// Offset in the ROM of the first instruction (in hex)
+    #[structopt(
+        long = "offset",
+        default_value = "1000",
+        parse(try_from_str = "parse_hex")
+    )]
+    offset: u64,
+
+    /// Number of instructions to disassemble
+    #[structopt(long = "count", default_value = "64")]
+    count: usize,
+
+    /// Address of the first instruction (in hex). By default, the region is
+    /// assumed to be loaded at the entry point, as done for the boot segment.
+    #[structopt(long = "pc", parse(try_from_str = "parse_hex"))]
+    pc: Option<u64>,
+
+    /// Path to the ROM file
+    #[structopt(parse(from_os_str))]
+    rom: std::path::PathBuf,
+}
+
+#[derive(StructOpt)]
+struct RomCheckArgs {
+    /// CIC model of the cartridge (eg: 6105). By default, it is detected
+    /// from the ROM boot code.
+    #[structopt(long = "cic")]
+    cic: Option<String>,
+
     /// Path to the ROM file
     #[structopt(parse(from_os_str))]
     rom: std::path::PathBuf,
 }
 
+#[derive(StructOpt)]
+struct TraceArgs {
+    /// Trace file to write (LZ4-compressed if its extension is .lz4)
+    #[structopt(
+        short = "o",
+        long = "output",
+        parse(from_os_str),
+        default_value = "trace.txt"
+    )]
+    output: std::path::PathBuf,
+
+    #[structopt(flatten)]
+    run: RunArgs,
+}
+
+#[derive(StructOpt)]
+struct DisasmExportArgs {
+    /// Address of the first instruction (in hex)
+    #[structopt(long = "start", parse(try_from_str = "parse_hex"))]
+    start: u64,
+
+    /// Address after the last instruction (in hex)
+    #[structopt(long = "end", parse(try_from_str = "parse_hex"))]
+    end: u64,
+
+    /// Listing file to write
+    #[structopt(
+        short = "o",
+        long = "output",
+        parse(from_os_str),
+        default_value = "disasm.txt"
+    )]
+    output: std::path::PathBuf,
+
+    #[structopt(flatten)]
+    run: RunArgs,
+}
+
+// Parse a number in hex, with or without the 0x prefix.
+fn parse_hex(s: &str) -> std::result::Result<u64, std::num::ParseIntError> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16)
+}
+
+impl RunArgs {
+    // Path of the ROM to run. It must have been selected already, either
+    // on the command line or through the ROM browser.
+    fn rom(&self) -> &std::path::Path {
+        self.rom.as_ref().expect("no ROM selected")
+    }
+
+    // Path of the symbol file: the one specified on the command line, or a
+    // .sym file next to the ROM (if present).
+    fn symbols_path(&self) -> Option<std::path::PathBuf> {
+        self.symbols.clone().or_else(|| {
+            let mut symfn = self.rom().to_owned();
+            symfn.set_extension("sym");
+            if symfn.exists() {
+                Some(symfn)
+            } else {
+                None
+            }
+        })
+    }
+}
+
 quick_main!(run);
 
-fn create_n64(romfn: &Path, biosfn: &Path, logger: slog::Logger) -> Result<N64> {
-    let mut n64 = N64::new(logger, romfn, biosfn).unwrap();
-    n64.setup_cic(true)?;
+// Create the console logger, also saving logs as JSON if requested.
+fn console_logger(args: &RunArgs) -> Result<slog::Logger> {
+    log::set_console_colors(!args.no_color);
+    log::set_console_emulated_time(args.log_emu_time);
+    match args.log_json.as_ref() {
+        Some(path) => Ok(log::new_console_json_logger(path)?),
+        None => Ok(log::new_console_logger()),
+    }
+}
+
+// Return the settings overridden for the game in the config file.
+fn game_settings(args: &RunArgs, config: &Config) -> Result<GameSettings> {
+    let crc = RomHeader::read(args.rom())?.crc;
+    Ok(config.get::<GameProfiles>()?.get(crc))
+}
+
+// Settings that must be applied before booting: the per-game settings, the
+// CIC model, and cheats (so that boot codes are applied).
+fn setup_before_boot(args: &RunArgs, game: &GameSettings, n64: &mut N64) -> Result<()> {
+    n64.set_game_settings(game.clone())?;
+    if let Some(cic) = args.cic.as_ref() {
+        n64.set_cic_model(CicModel::from_name(cic)?);
+    }
+    if let Some(path) = args.cheats.as_ref() {
+        n64.load_cheats(path)?;
+    }
+    Ok(())
+}
+
+fn create_n64(args: &RunArgs, game: &GameSettings, logger: slog::Logger) -> Result<N64> {
+    // Without a PIF ROM dump, fallback to HLE boot.
+    let hle_boot = args.hle_boot || !args.bios.exists();
+    if hle_boot && !args.hle_boot {
+        info!(logger, "PIF ROM not found, using HLE boot"; "bios" => args.bios.display().to_string());
+    }
+    let expansion_pak = match args.expansion_pak.as_ref().map(|p| p.as_str()) {
+        Some("off") => false,
+        Some(_) => true,
+        None => game.expansion_pak.unwrap_or(true),
+    };
+    let rdram = if expansion_pak {
+        RdramSize::Mb8
+    } else {
+        RdramSize::Mb4
+    };
+    let mut n64 = if hle_boot {
+        let mut n64 = N64::new(logger, args.rom(), None, rdram)?;
+        setup_before_boot(args, game, &mut n64)?;
+        n64.setup_boot(BootMode::Hle)?;
+        n64
+    } else {
+        let mut n64 = N64::new(logger, args.rom(), Some(&args.bios), rdram)?;
+        setup_before_boot(args, game, &mut n64)?;
+        n64.setup_boot(BootMode::Pif)?;
+        n64
+    };
+    n64.set_fpu_ieee_mode(args.fpu_ieee);
+    match args.gfx.as_str() {
+        "hle" => n64.set_gfx_hle(true),
+        "lle" => n64.set_gfx_hle(false),
+        _ => {}
+    }
+    match args.mem_timing.as_str() {
+        "fast" => n64.set_mem_timing(MemTiming::Fast),
+        "accurate" => n64.set_mem_timing(MemTiming::Accurate),
+        _ => {}
+    }
+    if let Some(caches) = args.cache_emulation.as_ref() {
+        n64.set_cache_emulation(caches == "on");
+    }
+    n64.set_rdp_threads(args.rdp_threads);
+    n64.set_threaded_rdp(!args.sync_rdp);
+    if args.rdp_backend == "gl" {
+        n64.set_rdp_backend(RenderBackend::OpenGl);
+    }
+    if args.deterministic || args.netplay.is_some() {
+        n64.set_deterministic(true)?;
+    }
+    n64.set_rng_seed(args.seed);
+    if let Some(ratio) = args.cpu_clock_ratio {
+        if !ratio.is_finite() || ratio <= 0.0 {
+            bail!("invalid CPU clock ratio: {}", ratio);
+        }
+        n64.set_cpu_clock_ratio(ratio);
+    }
+    for granularity in args.sync_granularity.iter() {
+        let mut parts = granularity.splitn(2, '=');
+        let name = parts.next().unwrap();
+        match parts.next().and_then(|c| c.parse::<i64>().ok()) {
+            Some(cycles) => n64.set_sync_granularity(name, cycles)?,
+            None => bail!("invalid sync granularity: {}", granularity),
+        }
+    }
+    let policy = args
+        .save_flush
+        .parse::<FlushPolicy>()
+        .map_err(|e| e.to_string())?;
+    n64.set_save_flush_policy(policy)?;
+    if let Some(save_type) = SaveType::from_name(&args.save_type) {
+        n64.set_save_type(save_type)?;
+    }
+    let pak = match args.pak.as_str() {
+        "none" => PakKind::None,
+        "rumble" => PakKind::Rumble,
+        "transfer" => {
+            let rom = match args.gb_rom.as_ref() {
+                Some(rom) => rom.clone(),
+                None => bail!("--pak transfer requires a Game Boy ROM (--gb-rom)"),
+            };
+            let save = args
+                .gb_save
+                .clone()
+                .unwrap_or_else(|| rom.with_extension("sav"));
+            PakKind::Transfer { rom, save }
+        }
+        _ => PakKind::Controller,
+    };
+    n64.set_pak(0, pak)?;
+    if let Some(path) = args.script.as_ref() {
+        n64.load_script(path)?;
+    }
+    if let Some(path) = args.mpk_import.as_ref() {
+        n64.import_controller_pak(0, path)?;
+    }
+    if let Some(path) = args.record.as_ref() {
+        if let Some(state) = args.record_from_state.as_ref() {
+            n64.load_state(state)?;
+        }
+        n64.start_recording(path, args.record_from_state.is_some())?;
+    } else if let Some(path) = args.play.as_ref() {
+        n64.start_playback(path)?;
+    }
+    if let Some(peer) = args.netplay.as_ref() {
+        n64.start_netplay(&NetplayConfig {
+            port: args.netplay_port,
+            peer: peer.clone(),
+            player: args.netplay_player - 1,
+            delay: args.netplay_delay,
+            rollback: args.netplay_rollback,
+        })?;
+    }
     Ok(n64)
 }
 
+// Run the emulator without initializing SDL, and check the hash of the last
+// frame (if requested). Errors make the process exit with a failure status.
+fn run_headless(args: &RunArgs) -> Result<()> {
+    let expected = match args.expect_hash.as_ref() {
+        Some(h) => match u64::from_str_radix(h.trim_start_matches("0x"), 16) {
+            Ok(h) => Some(h),
+            Err(_) => bail!("invalid hash: {}", h),
+        },
+        None => None,
+    };
+
+    let game = game_settings(args, &Config::load(&config_path(args))?)?;
+    let logger = console_logger(args)?;
+    hw::set_osd_logger(logger.new(o!()));
+    let mut n64 = create_n64(args, &game, logger.clone())?;
+    let fps = n64.tv_type().refresh_rate() as usize;
+    if let Some(addr) = args.remote_debugger.as_ref() {
+        let mut server = remote_server(addr, &logger)?;
+        let mut screen = OwnedGfxBufferLE::<Rgb888>::new(640, 480);
+        let mut sound =
+            OwnedSndBuffer::<S16_STEREO>::with_capacity(N64::AUDIO_OUTPUT_FREQUENCY as usize / fps);
+        server.run_headless(&mut n64, &mut screen.buf_mut(), &mut sound.buf_mut());
+        return Ok(());
+    }
+    let hashes = hw::run_headless(
+        &mut n64,
+        &hw::HeadlessConfig {
+            width: 640,
+            height: 480,
+            audio_frames: N64::AUDIO_OUTPUT_FREQUENCY as usize / fps,
+            frames: args.frames,
+            png_dir: args.dump_png.clone(),
+            print_hashes: args.print_hashes,
+        },
+    )?;
+
+    if let (Some(expected), Some(&last)) = (expected, hashes.last()) {
+        if last != expected {
+            bail!(
+                "hash mismatch on last frame: {:016x} (expected: {:016x})",
+                last,
+                expected
+            );
+        }
+    }
+    Ok(())
+}
+
+// Start listening for remote debugger clients.
+fn remote_server(addr: &str, logger: &slog::Logger) -> Result<dbg::RemoteServer> {
+    let server = dbg::RemoteServer::bind(addr)
+        .chain_err(|| format!("cannot start remote debugger on {}", addr))?;
+    info!(logger, "remote debugger listening"; "addr" => server.local_addr().to_string());
+    Ok(server)
+}
+
+// Disassemble instructions from the ROM with the main CPU decoder.
+fn run_disasm(args: &DisasmArgs) -> Result<()> {
+    let rom = cartridge::load_rom(&args.rom)?;
+    let start = args.offset as usize;
+    let end = start.saturating_add(args.count * 4).min(rom.len());
+    if start >= end {
+        bail!("offset out of ROM bounds: {:x}", args.offset);
+    }
+
+    let pc = match args.pc {
+        Some(pc) => pc,
+        None => {
+            let cart = Cartridge::from_data(rom.clone())?;
+            let entry = cart.header_entry_point();
+            let entry = match cart.detect_cic_model() {
+                Ok(cic) => cic.entry_point(entry),
+                Err(_) => entry,
+            };
+            (entry as i32 as i64 as u64).wrapping_add(args.offset.wrapping_sub(0x1000))
+        }
+    };
+
+    let cpu = R4300::new(slog::Logger::root(slog::Discard, slog::o!()));
+    for (i, off) in (start..end).step_by(4).enumerate() {
+        let opcode = BigEndian::read_u32(&rom[off..]);
+        let pc = pc.wrapping_add(i as u64 * 4);
+        let insn = cpu.disasm_insn(pc, opcode);
+        println!("{:08x}: {:08x}  {}", pc as u32, opcode, insn.disasm());
+    }
+    Ok(())
+}
+
+// Print the ROM header, and exit with an error if the CRC doesn't match.
+fn run_romcheck(args: &RomCheckArgs) -> Result<()> {
+    let cart = Cartridge::new(&args.rom)?;
+    let cic = match args.cic.as_ref() {
+        Some(cic) => CicModel::from_name(cic)?,
+        None => cart.detect_cic_model()?,
+    };
+    let (crc1, crc2) = cart.header_crc();
+    println!("Name:      {}", cart.header_name());
+    println!("Game code: {}", cart.game_code());
+    println!("Country:   {}", cart.country_code() as char);
+    println!("CIC:       {}", cic as u32);
+    println!("CRC:       {:08X} {:08X}", crc1, crc2);
+
+    let (chk1, chk2) = cart.compute_crc(cic)?;
+    if (chk1, chk2) != (crc1, crc2) {
+        bail!("CRC mismatch (computed: {:08X} {:08X})", chk1, chk2);
+    }
+    println!("CRC OK");
+    Ok(())
+}
+
+// Run the emulator without window, streaming executed instructions into
+// the trace file.
+fn run_trace(args: &TraceArgs) -> Result<()> {
+    let run = &args.run;
+    if run.rom.is_none() {
+        bail!("no ROM specified");
+    }
+    let game = game_settings(run, &Config::load(&config_path(run))?)?;
+    let mut n64 = create_n64(run, &game, console_logger(run)?)?;
+    let mut debugger = dbg::Debugger::new(&n64.all_cpus());
+    debugger.start_insn_trace(&args.output)?;
+
+    let mut screen = OwnedGfxBufferLE::<Rgb888>::new(640, 480);
+    let mut sound = OwnedSndBuffer::<S16_STEREO>::with_capacity(
+        N64::AUDIO_OUTPUT_FREQUENCY as usize / n64.tv_type().refresh_rate() as usize,
+    );
+    let mut frames = 0;
+    while frames < run.frames {
+        let tracer = debugger.new_tracer();
+        match n64.trace_frame(&mut screen.buf_mut(), &mut sound.buf_mut(), &tracer) {
+            Ok(()) => frames += 1,
+            Err(event) => match *event {
+                // Polling: the frame is resumed at the next iteration.
+                dbg::TraceEvent::Poll() => {}
+                event => {
+                    drop(tracer);
+                    let _ = debugger.stop_insn_trace();
+                    bail!("emulation stopped: {:?}", event);
+                }
+            },
+        }
+    }
+    if let Err(err) = debugger.stop_insn_trace() {
+        bail!(err);
+    }
+    Ok(())
+}
+
+// Run the emulator without window (so that the game code is loaded in
+// memory), then export the disassembly of the requested range.
+fn run_disasm_export(args: &DisasmExportArgs) -> Result<()> {
+    let run = &args.run;
+    if run.rom.is_none() {
+        bail!("no ROM specified");
+    }
+    if args.end <= args.start {
+        bail!("invalid range: {:x}-{:x}", args.start, args.end);
+    }
+    let game = game_settings(run, &Config::load(&config_path(run))?)?;
+    let mut n64 = create_n64(run, &game, console_logger(run)?)?;
+    if let Some(symfn) = run.symbols_path() {
+        n64.load_symbols(&symfn)?;
+    }
+    let fps = n64.tv_type().refresh_rate() as usize;
+    hw::run_headless(
+        &mut n64,
+        &hw::HeadlessConfig {
+            width: 640,
+            height: 480,
+            audio_frames: N64::AUDIO_OUTPUT_FREQUENCY as usize / fps,
+            frames: run.frames,
+            png_dir: None,
+            print_hashes: false,
+        },
+    )?;
+
+    let cpu = R4300::get();
+    let range = (cpu.pc_mask(args.start), cpu.pc_mask(args.end - 1) + 1);
+    let mut export = dbg::DisasmExport::create(&args.output, range)
+        .chain_err(|| format!("cannot create {}", args.output.display()))?;
+    let mut last = None;
+    while !export
+        .step(&**cpu)
+        .chain_err(|| format!("cannot write {}", args.output.display()))?
+    {
+        let perc = (export.progress() * 100.0) as u32;
+        if last != Some(perc) {
+            eprint!("\rExporting: {}%", perc);
+            last = Some(perc);
+        }
+    }
+    eprintln!("\rExported to {}", args.output.display());
+    Ok(())
+}
+
 fn run() -> Result<()> {
-    let args = Cli::from_args();
+    // Without arguments, the ROM browser is shown (as with "run").
+    let cli = if std::env::args_os().len() <= 1 {
+        Cli::Run(RunArgs::from_args())
+    } else {
+        Cli::from_args()
+    };
+    match cli {
+        Cli::Run(args) => run_emulator(args),
+        Cli::Disasm(args) => run_disasm(&args),
+        Cli::RomCheck(args) => run_romcheck(&args),
+        Cli::Trace(args) => run_trace(&args),
+        Cli::DisasmExport(args) => run_disasm_export(&args),
+    }
+}
+
+// Path of the config file: the one specified on the command line, or the
+// default one for the platform.
+fn config_path(args: &RunArgs) -> std::path::PathBuf {
+    args.config
+        .clone()
+        .or_else(|| Config::default_path("r64emu"))
+        .unwrap_or_else(|| "r64emu.toml".into())
+}
+
+fn run_emulator(mut args: RunArgs) -> Result<()> {
+    if args.rom.is_none() && (args.headless || args.mpk_export.is_some()) {
+        bail!("no ROM specified");
+    }
+    if let Some(path) = args.mpk_export.as_ref() {
+        let game = game_settings(&args, &Config::load(&config_path(&args))?)?;
+        let n64 = create_n64(&args, &game, console_logger(&args)?)?;
+        return n64.export_controller_pak(0, path);
+    }
+    if args.remote_debugger.is_some() && !args.debugger && !args.headless {
+        bail!("--remote-debugger requires --debugger or --headless");
+    }
+    // The OpenGL renderer draws with the context of the output window, which
+    // is only current on the emulation thread when running the debugger.
+    if args.rdp_backend == "gl" && !args.debugger {
+        bail!("--rdp-backend gl requires --debugger");
+    }
+    if args.headless {
+        return run_headless(&args);
+    }
 
+    // Frontend errors are only reported on the console, as the JSON log (if
+    // any) is reserved to the emulator.
+    log::set_console_colors(!args.no_color);
     let mut out = hw::Output::new(
         hw::VideoConfig {
             window_title: "R64EMU - Nintendo 64 Emulator".into(),
@@ -53,23 +869,104 @@ fn run() -> Result<()> {
         hw::AudioConfig {
             frequency: N64::AUDIO_OUTPUT_FREQUENCY as isize,
         },
+        log::new_console_logger(),
     )?;
     out.enable_video()?;
+
+    // Settings persisted in the config file can be overridden on the
+    // command line.
+    let mut config = Config::load(&config_path(&args))?;
+    out.load_config(&config)?;
+    let mut vopts = out.video_options();
+    match args.scale.as_ref().map(|s| s.as_str()) {
+        Some("stretch") => vopts.scale = hw::ScaleMode::Stretch,
+        Some("integer") => vopts.scale = hw::ScaleMode::Integer,
+        Some(_) => vopts.scale = hw::ScaleMode::Aspect,
+        None => {}
+    }
+    if args.nearest {
+        vopts.bilinear = false;
+    }
+    if let Some(scanlines) = args.scanlines {
+        vopts.scanlines = scanlines.max(0.0).min(1.0);
+    }
+    out.set_video_options(vopts);
+    if let Some(shader) = args.shader.as_ref() {
+        out.set_shader(shader)?;
+    }
+
+    let mut browser = RomBrowser::new(&config)?;
+    if let Some(dir) = args.rom_dir.as_ref() {
+        browser.set_dir(dir);
+    }
+    if args.rom.is_none() {
+        match out.run_launcher(&mut browser)? {
+            Some(rom) => args.rom = Some(rom),
+            None => {
+                browser.save_config(&mut config)?;
+                return Ok(config.save()?);
+            }
+        }
+    }
+    browser.add_recent(args.rom());
+    browser.save_config(&mut config)?;
+    config.save()?;
+
+    let rom_name = args
+        .rom()
+        .file_stem()
+        .map_or("screenshot".into(), |s| s.to_string_lossy());
+    out.set_screenshot_dir(&args.screenshot_dir, &rom_name);
+    // PAL games run at 50 fields per second.
+    let country = RomHeader::read(args.rom())?.country;
+    out.set_fps(TvType::from_country(country).refresh_rate());
+    if let Some(path) = args.record_video.as_ref() {
+        out.start_video_recording(path)?;
+    }
     out.enable_audio()?;
+    let input_config = args
+        .input_config
+        .clone()
+        .unwrap_or_else(|| config.path().with_file_name("input.toml"));
+    out.set_input_config(&input_config);
+    let speed = out.speed_control();
+    speed.set_turbo_ratio(args.turbo);
+    speed.set_slow_ratio(args.slow_motion);
+    if args.mute_slow_motion {
+        speed.set_slow_audio(hw::SlowAudio::Mute);
+    }
 
+    let game = game_settings(&args, &config)?;
     if args.debugger {
-        let (logger, logpool) = log::new_pool_logger();
-        let mut n64 = create_n64(&args.rom, &args.bios, logger).unwrap();
-        let mut dbgconfig = args.rom.clone();
+        let (logger, logpool) = match args.log_json.as_ref() {
+            Some(path) => log::new_pool_json_logger(path)?,
+            None => log::new_pool_logger(),
+        };
+        // Show on-screen messages in the log view of the debugger.
+        hw::set_osd_logger(logger.new(o!()));
+        if let Some(addr) = args.remote_debugger.as_ref() {
+            out.set_remote_debugger(remote_server(addr, &logger)?);
+        }
+        let mut n64 = create_n64(&args, &game, logger)?;
+        if let Some(symfn) = args.symbols_path() {
+            n64.load_symbols(&symfn)?;
+        }
+        let mut dbgconfig = args.rom().to_owned();
         dbgconfig.set_extension("dbg");
         out.run_and_debug(&mut n64, &dbgconfig, logpool);
+
+        // Persist the changes made in the Game Settings window.
+        let mut games: GameProfiles = config.get()?;
+        games.set(n64.rom_crc(), n64.game_settings().clone());
+        config.set(&games)?;
     } else {
         out.run_threaded(move || {
-            let logger = log::new_console_logger();
-            let n64 = create_n64(&args.rom, &args.bios, logger).unwrap();
+            let logger = console_logger(&args).map_err(|e| e.to_string())?;
+            let n64 = create_n64(&args, &game, logger).map_err(|e| e.to_string())?;
             Ok(Box::new(n64))
         });
     }
 
-    Ok(())
+    out.save_config(&mut config)?;
+    Ok(config.save()?)
 }
@@ -1,5 +1,10 @@
+use super::dp::Dp;
 use super::r4300::R4300;
 use emu::bus::be::{Device, Reg32};
+#[cfg(feature = "frontend")]
+use emu::dbg::imgui::*;
+#[cfg(feature = "frontend")]
+use emu::dbg::DebuggerRenderer;
 use emu::int::Numerics;
 use mips64::Cop0;
 
@@ -18,6 +23,38 @@ bitflags! {
     }
 }
 
+// Names of the MI interrupt lines, in bit order.
+#[cfg(feature = "frontend")]
+const IRQ_NAMES: [&str; 6] = ["SP", "SI", "AI", "VI", "PI", "DP"];
+
+// Names of the CPU interrupt lines (Cause.IP / Status.IM), in bit order.
+#[cfg(feature = "frontend")]
+const CPU_IRQ_NAMES: [&str; 8] = ["SW0", "SW1", "RCP", "Cart", "Reset", "IP5", "IP6", "Timer"];
+
+// Names of the exception codes (Cause.ExcCode).
+#[cfg(feature = "frontend")]
+fn exc_code_name(code: u32) -> &'static str {
+    match code {
+        0 => "Int",
+        1 => "Mod",
+        2 => "TLBL",
+        3 => "TLBS",
+        4 => "AdEL",
+        5 => "AdES",
+        6 => "IBE",
+        7 => "DBE",
+        8 => "Sys",
+        9 => "Bp",
+        10 => "RI",
+        11 => "CpU",
+        12 => "Ov",
+        13 => "Tr",
+        15 => "FPE",
+        23 => "WATCH",
+        _ => "?",
+    }
+}
+
 #[derive(DeviceBE)]
 pub struct Mi {
     // 0x04300000 to 0x04300003  MI_INIT_MODE_REG or MI_MODE_REG //MI init mode
@@ -34,7 +71,7 @@ pub struct Mi {
     #[reg(offset = 0x04, init = 0x02020102, readonly)]
     reg_version: Reg32,
 
-    #[reg(offset = 0x08, readonly)]
+    #[reg(offset = 0x08, readonly, rcb)]
     irq_ack: Reg32,
 
     #[reg(offset = 0x0C, wcb)]
@@ -106,6 +143,15 @@ impl Mi {
         self.update_cpu_irq();
     }
 
+    fn cb_read_irq_ack(&self, old: u32) -> u32 {
+        // The interrupt handler finds out that the RDP has finished drawing:
+        // wait for the primitives rendered in background.
+        if old & IrqMask::DP.bits() != 0 {
+            Dp::get().fence_rendering();
+        }
+        old
+    }
+
     fn cb_write_irq_mask(&mut self, old: u32, new: u32) {
         let mut mask = old;
         for i in 0..12 {
@@ -120,6 +166,109 @@ impl Mi {
         self.update_cpu_irq();
     }
 
+    #[cfg(feature = "frontend")]
+    pub fn render_debug<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>) {
+        let ui = dr.ui();
+        let cpu = R4300::get();
+        let cop0 = &cpu.cop0;
+        let (status, cause) = (cop0.status(), cop0.cause());
+
+        // Draw a row of flags, highlighting the ones that are set.
+        let flags = |names: &[&str], bits: u32| {
+            for (i, name) in names.iter().enumerate() {
+                if i != 0 {
+                    ui.same_line(0.0);
+                }
+                if bits.get_bit(i) {
+                    ui.text_colored([1.0, 1.0, 0.3, 1.0], name);
+                } else {
+                    ui.text_disabled(name);
+                }
+            }
+        };
+
+        Window::new(im_str!("[N64] Interrupts"))
+            .size([400.0, 500.0], Condition::FirstUseEver)
+            .build(ui, || {
+                let (ack, mask) = (self.irq_ack.get(), self.irq_mask.get());
+                ui.text(format!("MI mask:    {:02X}", mask));
+                dr.reg_tooltip(0x0430_000C, Some(mask as u64));
+                ui.same_line(140.0);
+                flags(&IRQ_NAMES, mask);
+                ui.text(format!("MI pending: {:02X}", ack));
+                dr.reg_tooltip(0x0430_0008, Some(ack as u64));
+                ui.same_line(140.0);
+                flags(&IRQ_NAMES, ack);
+                ui.text(format!("MI active:  {:02X}", ack & mask));
+                ui.same_line(140.0);
+                flags(&IRQ_NAMES, ack & mask);
+                ui.separator();
+
+                ui.text(format!("Status: {:08X}", status));
+                ui.text(format!(
+                    "  IE:{} EXL:{} ERL:{} KSU:{} UX:{} SX:{} KX:{}",
+                    status.get_bit(0) as u8,
+                    status.get_bit(1) as u8,
+                    status.get_bit(2) as u8,
+                    status.get_bits(3..5),
+                    status.get_bit(5) as u8,
+                    status.get_bit(6) as u8,
+                    status.get_bit(7) as u8,
+                ));
+                ui.text(format!(
+                    "  DS:{:03X} RE:{} FR:{} RP:{} CU:{:04b}",
+                    status.get_bits(16..25),
+                    status.get_bit(25) as u8,
+                    status.get_bit(26) as u8,
+                    status.get_bit(27) as u8,
+                    status.get_bits(28..32),
+                ));
+                ui.text("  IM:");
+                ui.same_line(0.0);
+                flags(&CPU_IRQ_NAMES, status.get_bits(8..16));
+                ui.separator();
+
+                ui.text(format!("Cause:  {:08X}", cause));
+                let code = cause.get_bits(2..7);
+                ui.text(format!(
+                    "  ExcCode:{} ({}) CE:{} BD:{}",
+                    code,
+                    exc_code_name(code),
+                    cause.get_bits(28..30),
+                    cause.get_bit(31) as u8,
+                ));
+                ui.text("  IP:");
+                ui.same_line(0.0);
+                flags(&CPU_IRQ_NAMES, cause.get_bits(8..16));
+                ui.separator();
+
+                ui.text(format!("EPC:      {:016X}", cop0.epc()));
+                ui.text(format!("ErrorEPC: {:016X}", cop0.error_epc()));
+                ui.separator();
+
+                ui.text("Last exceptions (newest first):");
+                ChildWindow::new(im_str!("##mi#exclog"))
+                    .size([0.0, 0.0])
+                    .build(ui, || {
+                        ui.columns(3, im_str!("##mi#exccols"), true);
+                        for title in ["Clock", "Exception", "EPC"].iter() {
+                            ui.text(title);
+                            ui.next_column();
+                        }
+                        ui.separator();
+                        for (clock, exc, epc) in cop0.exception_log().rev() {
+                            ui.text(format!("{}", clock));
+                            ui.next_column();
+                            ui.text(format!("{:?}", exc));
+                            ui.next_column();
+                            ui.text(format!("{:016X}", epc));
+                            ui.next_column();
+                        }
+                        ui.columns(1, im_str!(""), false);
+                    });
+            });
+    }
+
     fn update_cpu_irq(&self) {
         R4300::get_mut()
             .cop0
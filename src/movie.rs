@@ -0,0 +1,360 @@
+//! Movie recording and playback.
+//!
+//! A movie is the sequence of controller states read by the game, one sample
+//! per controller each time the game polls them. Movies are stored in the
+//! Mupen64 .m64 format (version 3), so that TAS runs can be exchanged with
+//! other emulators. Each input sample is stored as the 4 bytes of the joybus
+//! "read buttons" response, which is exactly the layout used by Mupen64.
+//!
+//! Movies can start either at power-on, or from a savestate. In the latter
+//! case, the savestate is stored next to the movie, with .state extension
+//! (the format is not compatible with Mupen64 .st files).
+use crate::errors::*;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const M64_SIGNATURE: &[u8; 4] = b"M64\x1A";
+const M64_VERSION: u32 = 3;
+const M64_HEADER_SIZE: usize = 0x400;
+
+const START_SNAPSHOT: u16 = 1;
+const START_POWERON: u16 = 2;
+
+// Offsets of the fields in the .m64 header
+const OFF_UID: usize = 0x008;
+const OFF_VI_FRAMES: usize = 0x00C;
+const OFF_RERECORDS: usize = 0x010;
+const OFF_VI_PER_SEC: usize = 0x014;
+const OFF_CONTROLLERS: usize = 0x015;
+const OFF_SAMPLES: usize = 0x018;
+const OFF_START_TYPE: usize = 0x01C;
+const OFF_CONTROLLER_FLAGS: usize = 0x020;
+const OFF_ROM_NAME: usize = 0x0C4;
+const OFF_ROM_CRC: usize = 0x0E4;
+const OFF_COUNTRY: usize = 0x0E8;
+const OFF_DESCRIPTION: usize = 0x300;
+
+/// Information about the game a movie was recorded on, used to fill the
+/// header and to check that a movie is played back on the right game.
+pub struct MovieGame {
+    pub name: String,
+    pub crc: u32,
+    pub country: u16,
+    pub vi_per_sec: u8,
+}
+
+enum Mode {
+    Recording(File),
+    Playback,
+}
+
+pub struct Movie {
+    header: Vec<u8>,
+    controllers: usize,
+    samples: Vec<u32>, // one per controller per poll, in joybus layout
+    pos: usize,
+    written: usize, // samples already written to disk
+    vi_frames: u32,
+    mode: Mode,
+}
+
+impl Movie {
+    /// Start recording a new movie into the specified file. The number of
+    /// controllers is fixed for the whole movie.
+    pub fn record(
+        path: &Path,
+        game: &MovieGame,
+        controllers: usize,
+        from_snapshot: bool,
+    ) -> Result<Movie> {
+        let mut header = vec![0u8; M64_HEADER_SIZE];
+        header[..4].copy_from_slice(M64_SIGNATURE);
+        LittleEndian::write_u32(&mut header[4..], M64_VERSION);
+        let uid = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as u32)
+            .unwrap_or(0);
+        LittleEndian::write_u32(&mut header[OFF_UID..], uid);
+        header[OFF_VI_PER_SEC] = game.vi_per_sec;
+        header[OFF_CONTROLLERS] = controllers as u8;
+        LittleEndian::write_u16(
+            &mut header[OFF_START_TYPE..],
+            if from_snapshot {
+                START_SNAPSHOT
+            } else {
+                START_POWERON
+            },
+        );
+        LittleEndian::write_u32(&mut header[OFF_CONTROLLER_FLAGS..], (1 << controllers) - 1);
+        write_str(&mut header[OFF_ROM_NAME..OFF_ROM_NAME + 32], &game.name);
+        LittleEndian::write_u32(&mut header[OFF_ROM_CRC..], game.crc);
+        LittleEndian::write_u16(&mut header[OFF_COUNTRY..], game.country);
+        write_str(
+            &mut header[OFF_DESCRIPTION..OFF_DESCRIPTION + 256],
+            "Recorded with r64emu",
+        );
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .chain_err(|| format!("cannot create movie {}", path.display()))?;
+        file.write_all(&header)?;
+
+        Ok(Movie {
+            header,
+            controllers,
+            samples: Vec::new(),
+            pos: 0,
+            written: 0,
+            vi_frames: 0,
+            mode: Mode::Recording(file),
+        })
+    }
+
+    /// Load a movie for playback, checking that it was recorded on the
+    /// specified game.
+    pub fn play(path: &Path, game: &MovieGame) -> Result<Movie> {
+        let mut data = Vec::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut data))
+            .chain_err(|| format!("cannot open movie {}", path.display()))?;
+        if data.len() < M64_HEADER_SIZE || &data[..4] != M64_SIGNATURE {
+            bail!("{} is not a .m64 movie", path.display());
+        }
+        let version = LittleEndian::read_u32(&data[4..]);
+        if version != M64_VERSION {
+            bail!("unsupported .m64 version: {}", version);
+        }
+        let crc = LittleEndian::read_u32(&data[OFF_ROM_CRC..]);
+        if crc != game.crc {
+            bail!(
+                "movie was recorded on a different game: {:?} (crc: {:08x})",
+                read_str(&data[OFF_ROM_NAME..OFF_ROM_NAME + 32]),
+                crc
+            );
+        }
+
+        let flags = LittleEndian::read_u32(&data[OFF_CONTROLLER_FLAGS..]);
+        let controllers = (flags & 0xF).count_ones() as usize;
+        let samples = data[M64_HEADER_SIZE..]
+            .chunks_exact(4)
+            .map(BigEndian::read_u32)
+            .collect();
+
+        Ok(Movie {
+            header: data[..M64_HEADER_SIZE].to_vec(),
+            controllers,
+            samples,
+            pos: 0,
+            written: 0,
+            vi_frames: 0,
+            mode: Mode::Playback,
+        })
+    }
+
+    /// Path of the savestate from which a movie starts.
+    pub fn state_path(path: &Path) -> PathBuf {
+        path.with_extension("state")
+    }
+
+    /// Number of controllers whose inputs are part of the movie.
+    pub fn controllers(&self) -> usize {
+        self.controllers
+    }
+
+    pub fn from_snapshot(&self) -> bool {
+        LittleEndian::read_u16(&self.header[OFF_START_TYPE..]) == START_SNAPSHOT
+    }
+
+    pub fn recording(&self) -> bool {
+        match self.mode {
+            Mode::Recording(_) => true,
+            Mode::Playback => false,
+        }
+    }
+
+    /// Return true if playback reached the end of the movie.
+    pub fn finished(&self) -> bool {
+        !self.recording() && self.pos >= self.samples.len()
+    }
+
+    /// Number of input samples recorded or played back so far, and total
+    /// number of samples (for playback).
+    pub fn progress(&self) -> (usize, usize) {
+        (self.pos, self.samples.len())
+    }
+
+    /// Process the poll of a controller by the game. `live` is the current
+    /// state of the controller: while recording, it is appended to the movie
+    /// and returned; during playback, it is replaced by the state found in
+    /// the movie. Returns None if the controller is not part of the movie.
+    pub fn poll(&mut self, ch: usize, live: u32) -> Option<u32> {
+        if ch >= self.controllers {
+            return None;
+        }
+        match self.mode {
+            Mode::Recording(_) => {
+                self.samples.push(live);
+                self.pos += 1;
+                Some(live)
+            }
+            Mode::Playback => {
+                let val = self.samples.get(self.pos).cloned().unwrap_or(0);
+                self.pos += 1;
+                Some(val)
+            }
+        }
+    }
+
+    /// Signal the end of a VI frame. While recording, this writes the
+    /// samples polled during the frame and updates the header, so that the
+    /// movie on disk is always valid.
+    pub fn end_frame(&mut self) -> Result<()> {
+        self.vi_frames += 1;
+        if let Mode::Recording(ref mut file) = self.mode {
+            let mut buf = vec![0u8; (self.samples.len() - self.written) * 4];
+            BigEndian::write_u32_into(&self.samples[self.written..], &mut buf);
+            file.seek(SeekFrom::End(0))?;
+            file.write_all(&buf)?;
+            self.written = self.samples.len();
+
+            let polls = self.samples.len() / self.controllers.max(1);
+            LittleEndian::write_u32(&mut self.header[OFF_VI_FRAMES..], self.vi_frames);
+            LittleEndian::write_u32(&mut self.header[OFF_SAMPLES..], polls as u32);
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&self.header[..OFF_SAMPLES + 4])?;
+        }
+        Ok(())
+    }
+
+    /// Number of times the movie was re-recorded, as stored in the header.
+    pub fn rerecords(&self) -> u32 {
+        LittleEndian::read_u32(&self.header[OFF_RERECORDS..])
+    }
+}
+
+fn write_str(dst: &mut [u8], s: &str) {
+    let len = s.len().min(dst.len());
+    dst[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
+
+fn read_str(src: &[u8]) -> String {
+    let end = src.iter().position(|&c| c == 0).unwrap_or(src.len());
+    String::from_utf8_lossy(&src[..end]).trim().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn tmppath(name: &str) -> PathBuf {
+        let path =
+            env::temp_dir().join(format!("r64emu-movie-{}-{}.m64", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn game(crc: u32) -> MovieGame {
+        MovieGame {
+            name: "SUPER MARIO 64".into(),
+            crc,
+            country: 0x45,
+            vi_per_sec: 60,
+        }
+    }
+
+    // Inputs of two controllers over a few frames, with a variable number
+    // of polls per frame (including none, eg: during loading).
+    fn inputs() -> Vec<Vec<(u32, u32)>> {
+        vec![
+            vec![(0x8000_0000, 0x0000_0000)],
+            vec![],
+            vec![(0x0010_7F81, 0x4000_0000), (0x0020_0000, 0x0000_1234)],
+            vec![(0xFFFF_FFFF, 0x0000_0001)],
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = tmppath("roundtrip");
+        let mut movie = Movie::record(&path, &game(0x635A_2BFF), 2, true).unwrap();
+        assert!(movie.recording());
+        for frame in inputs() {
+            for (p1, p2) in frame {
+                assert_eq!(movie.poll(0, p1), Some(p1));
+                assert_eq!(movie.poll(1, p2), Some(p2));
+                assert_eq!(movie.poll(2, 0x1234_5678), None);
+            }
+            movie.end_frame().unwrap();
+        }
+        assert_eq!(movie.progress(), (8, 8));
+        drop(movie);
+
+        // Check the header, and the samples in joybus layout.
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), M64_HEADER_SIZE + 8 * 4);
+        assert_eq!(&data[..8], b"M64\x1A\x03\x00\x00\x00");
+        assert_eq!(LittleEndian::read_u32(&data[OFF_VI_FRAMES..]), 4);
+        assert_eq!(LittleEndian::read_u32(&data[OFF_SAMPLES..]), 4);
+        assert_eq!(LittleEndian::read_u32(&data[OFF_RERECORDS..]), 0);
+        assert_eq!(data[OFF_VI_PER_SEC], 60);
+        assert_eq!(data[OFF_CONTROLLERS], 2);
+        assert_eq!(
+            LittleEndian::read_u16(&data[OFF_START_TYPE..]),
+            START_SNAPSHOT
+        );
+        assert_eq!(LittleEndian::read_u32(&data[OFF_CONTROLLER_FLAGS..]), 0b11);
+        assert_eq!(&data[OFF_ROM_NAME..OFF_ROM_NAME + 15], b"SUPER MARIO 64\0");
+        assert_eq!(LittleEndian::read_u32(&data[OFF_ROM_CRC..]), 0x635A_2BFF);
+        assert_eq!(LittleEndian::read_u16(&data[OFF_COUNTRY..]), 0x45);
+        assert_eq!(
+            &data[M64_HEADER_SIZE..M64_HEADER_SIZE + 4],
+            &[0x80, 0, 0, 0]
+        );
+
+        // Playback returns the recorded inputs, whatever the live state.
+        let mut movie = Movie::play(&path, &game(0x635A_2BFF)).unwrap();
+        assert!(!movie.recording());
+        assert!(movie.from_snapshot());
+        assert_eq!(movie.controllers(), 2);
+        for (p1, p2) in inputs().into_iter().flatten() {
+            assert!(!movie.finished());
+            assert_eq!(movie.poll(0, 0), Some(p1));
+            assert_eq!(movie.poll(1, 0), Some(p2));
+            assert_eq!(movie.poll(2, 0x1234_5678), None);
+        }
+        assert!(movie.finished());
+        assert_eq!(movie.progress(), (8, 8));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn play_errors() {
+        let path = tmppath("errors");
+        let mut movie = Movie::record(&path, &game(0x1111_1111), 1, false).unwrap();
+        movie.poll(0, 0x0000_0001);
+        movie.end_frame().unwrap();
+
+        // The movie on disk is valid while still recording.
+        let movie = Movie::play(&path, &game(0x1111_1111)).unwrap();
+        assert!(!movie.from_snapshot());
+        assert_eq!(movie.progress(), (0, 1));
+        assert!(Movie::play(&path, &game(0x2222_2222)).is_err());
+
+        let mut data = fs::read(&path).unwrap();
+        data[4] = 2;
+        fs::write(&path, &data).unwrap();
+        assert!(Movie::play(&path, &game(0x1111_1111)).is_err());
+        fs::write(&path, &data[..0x100]).unwrap();
+        assert!(Movie::play(&path, &game(0x1111_1111)).is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -1,31 +1,50 @@
+use emu::backup::FlushPolicy;
 use emu::bus::be::{Bus, Device};
 use emu::dbg;
-use emu::dbg::{DebuggerModel, DebuggerRenderer};
-use emu::gfx::{GfxBufferMutLE, Rgb888};
+use emu::dbg::DebuggerModel;
+#[cfg(feature = "frontend")]
+use emu::dbg::DebuggerRenderer;
+use emu::gfx::{GfxBufferMutLE, OwnedGfxBufferLE, Rgb888};
 use emu::hw;
 use emu::input::*;
-use emu::snd::{SampleFormat, SndBufferMut, S16_STEREO};
-use emu::state::{CurrentState, State};
+use emu::int::Numerics;
+use emu::snd::{OwnedSndBuffer, SampleFormat, SndBufferMut, S16_STEREO};
+use emu::state::{CurrentState, Snapshotable, State};
 use emu::sync;
 use emu::sync::Subsystem;
 use emu_derive::DeviceBE;
 
 use slog;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::ops::{Deref, DerefMut};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use super::ai::Ai;
 use super::cartridge::{Cartridge, CicModel};
+use super::cartsave::{CartSave, SaveType};
+use super::cheats::CheatList;
+use super::dmalog;
 use super::dp::Dp;
 use super::errors::*;
+use super::gamesettings::GameSettings;
+use super::gbcart::GbCart;
+use super::hacks::GameHacks;
 use super::mi::Mi;
-use super::mips64;
+use super::mips64::{self, MemTiming};
+use super::movie::{Movie, MovieGame};
+use super::netplay::{self, Netplay, NetplayConfig, MAX_ROLLBACK};
+use super::pak::{ControllerPak, Pak, PakKind, RumblePak, TransferPak};
 use super::pi::Pi;
 use super::r4300::R4300;
-use super::ri::Ri;
+use super::ramsearch::RamSearch;
+use super::rdp::RenderBackend;
+use super::regdb;
+use super::ri::{RdramSize, Ri};
+use super::script::Script;
 use super::si::Si;
 use super::sp::{Sp, RSPCPU};
-use super::vi::Vi;
+use super::vi::{TvType, Vi};
 
 // Used in debugger windows
 pub(crate) const MAINCPU_NAME: &'static str = "R4300";
@@ -35,6 +54,34 @@ pub struct N64 {
     logger: slog::Logger,
     sync: Box<sync::Sync<SyncEmu>>,
     initial_state: State,
+    hacks: &'static GameHacks,
+    cic: CicModel,
+    boot: BootMode,
+    savestate_path: PathBuf,
+    rom_path: PathBuf,
+    deterministic: bool,
+    flush_policy: FlushPolicy,
+    script: Option<Script>,
+    cheats: CheatList,
+    ramsearch: RamSearch,
+    game: GameSettings,
+    netplay: Option<Netplay>,
+}
+
+// Magic string and version of savestates. The version must be bumped only for
+// incompatible changes that cannot be handled through component migrations.
+const SAVESTATE_MAGIC: &'static str = "r64emu";
+const SAVESTATE_VERSION: u32 = 1;
+
+/// BootMode selects how the console is booted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BootMode {
+    /// Run the PIF ROM and the IPL3 bootcode found in the cartridge, like
+    /// the real hardware does. This requires a PIF ROM dump.
+    Pif,
+    /// Emulate the effects of PIF ROM and IPL3 at high-level, and jump
+    /// directly into the game entry point.
+    Hle,
 }
 
 // N64 timings
@@ -50,24 +97,54 @@ const _PIF_CLOCK: i64 = MAIN_CLOCK / 4;
 const _CARTRIDGE_CLOCK: i64 = _PIF_CLOCK / 8; // 1.953 MHZ
 pub(crate) const VCLK: i64 = X2 * 17 / 5; // 48.6812 MHZ
 
-struct SyncEmu;
+// Default sync granularity of the subsystems, in VI clock cycles (a
+// half-line is about 1550 cycles). The main CPU runs up to every half-line,
+// while the RCP can run in longer timeslices: its interrupts are exact sync
+// points anyway.
+const SYNC_GRANULARITY: [(&str, i64); 5] = [
+    (MAINCPU_NAME, 0),
+    (RSPCPU_NAME, 1500),
+    ("RDP", 3000),
+    ("Ai", 6000),
+    ("Pi", 3000),
+];
+
+struct SyncEmu {
+    cpu_clock: i64,
+    vclk: i64,           // VI clock of the TV type
+    video: (i64, usize), // VI line duration and half-lines per field
+}
+
 impl sync::SyncEmu for SyncEmu {
     fn config(&self) -> sync::Config {
+        // Each frame is a VI field, and each line is a VI half-line. The
+        // NTSC VI clock is used as main clock, so other TV types need their
+        // line duration converted.
+        let (line_cycles, halflines) = self.video;
+        let hdots = (line_cycles * VCLK / self.vclk / 4) as usize;
         sync::Config {
             main_clock: VCLK,
             dot_clock_divider: 2,
-            hdots: 773, // 773.5...
-            vdots: 525,
-            hsyncs: vec![0, 773 / 2], // sync two times per line
+            hdots,
+            vdots: halflines,
+            hsyncs: vec![0],
             vsyncs: vec![],
         }
     }
+    fn needs_sync(&self, evt: sync::Event) -> bool {
+        // Only the half-line raising the VI interrupt must be reached at the
+        // exact cycle; other half-lines just update V_CURRENT.
+        match evt {
+            sync::Event::HSync(_, y) => Vi::get().is_intr_halfline(y),
+            _ => true,
+        }
+    }
     fn subsystem(&self, idx: usize) -> Option<(&mut dyn sync::Subsystem, i64)> {
         match idx {
-            0 => Some((R4300::get_mut().deref_mut(), MAIN_CLOCK + MAIN_CLOCK / 2)), // FIXME: uses DIVMOD),
+            0 => Some((R4300::get_mut().deref_mut(), self.cpu_clock)), // FIXME: uses DIVMOD),
             1 => Some((RSPCPU::get_mut().deref_mut(), MAIN_CLOCK)),
             2 => Some((Dp::get_mut(), MAIN_CLOCK)),
-            3 => Some((Ai::get_mut(), VCLK)),
+            3 => Some((Ai::get_mut(), self.vclk)),
             4 => Some((Pi::get_mut(), MAIN_CLOCK)),
             _ => None,
         }
@@ -116,14 +193,48 @@ fn create_input_manager() -> InputManager {
 impl N64 {
     pub const AUDIO_OUTPUT_FREQUENCY: i64 = Ai::OUTPUT_FREQUENCY;
 
-    pub fn new(logger: slog::Logger, romfn: &Path, biosfn: &Path) -> Result<N64> {
-        let sync = sync::Sync::new(logger.new(o!()), SyncEmu);
+    /// Create a new N64. The PIF ROM dump (`biosfn`) is optional, but without it
+    /// the only supported boot mode is `BootMode::Hle`. `rdram` selects whether
+    /// the Expansion Pak is installed.
+    pub fn new(
+        logger: slog::Logger,
+        romfn: &Path,
+        biosfn: Option<&Path>,
+        rdram: RdramSize,
+    ) -> Result<N64> {
+        let cart = Cartridge::new(romfn).chain_err(|| "cannot open rom file")?;
+        N64::with_cartridge(logger, cart, Some(romfn), biosfn, rdram)
+    }
+
+    /// Create a new N64 from a ROM image already in memory (eg: in libretro).
+    /// There is no PIF ROM, so it must boot with `BootMode::Hle`; since there
+    /// is no ROM file either, the cartridge save and cheats are not loaded.
+    /// The Expansion Pak is installed.
+    pub fn from_rom_data(logger: slog::Logger, rom: Vec<u8>) -> Result<N64> {
+        let cart = Cartridge::from_data(rom).chain_err(|| "invalid rom file")?;
+        N64::with_cartridge(logger, cart, None, None, RdramSize::Mb8)
+    }
+
+    fn with_cartridge(
+        logger: slog::Logger,
+        cart: Box<Cartridge>,
+        romfn: Option<&Path>,
+        biosfn: Option<&Path>,
+        rdram: RdramSize,
+    ) -> Result<N64> {
+        let tv_type = TvType::from_country(cart.country_code());
+        let mut sync = sync::Sync::new(
+            logger.new(o!()),
+            SyncEmu {
+                cpu_clock: MAIN_CLOCK + MAIN_CLOCK / 2,
+                vclk: tv_type.vclk(),
+                video: tv_type.default_video_timing(),
+            },
+        );
 
         R4300::new(sync::Sync::new_logger(&sync)).register();
         Mi::new(sync::Sync::new_logger(&sync)).register();
-        Cartridge::new(romfn)
-            .chain_err(|| "cannot open rom file")?
-            .register();
+        cart.register();
 
         Pi::new(
             sync::Sync::new_logger(&sync),
@@ -135,18 +246,71 @@ impl N64 {
         Dp::new(sync::Sync::new_logger(&sync)).register();
         Sp::new(sync::Sync::new_logger(&sync))?.register();
         Si::new(sync::Sync::new_logger(&sync)).register();
-        Vi::new(sync::Sync::new_logger(&sync)).register();
+        Vi::new(sync::Sync::new_logger(&sync), tv_type).register();
         Ai::new(sync::Sync::new_logger(&sync)).register();
-        Ri::new(sync::Sync::new_logger(&sync)).register();
+        Ri::new(sync::Sync::new_logger(&sync), rdram).register();
+        CartSave::new(sync::Sync::new_logger(&sync)).register();
 
         // Now that all devices have been created, map the CPU buses.
         R4300::get_mut().map_bus()?;
         RSPCPU::get_mut().map_bus()?;
+        for (name, cycles) in SYNC_GRANULARITY.iter() {
+            sync.set_granularity(name, *cycles);
+        }
+
+        // Apply per-game quirks, if any.
+        let hacks = GameHacks::lookup(Cartridge::get());
+        sync.emu_mut().cpu_clock = hacks.cpu_clock(MAIN_CLOCK);
+        hacks.apply_cpu(&logger);
+
+        // Unknown boot codes are usually homebrew or hacked ROMs, which
+        // normally work with the most common CIC.
+        let cic = Cartridge::get().detect_cic_model().unwrap_or_else(|err| {
+            warn!(logger, "assuming CIC 6102"; "err" => err.to_string());
+            CicModel::Cic6102
+        });
+
+        let save_type = SaveType::detect(Cartridge::get());
+        let save_path = romfn.map(|f| f.with_extension(save_type.extension()));
+        CartSave::get_mut()
+            .setup(
+                save_type,
+                save_path.as_ref().map(|p| p.as_path()),
+                FlushPolicy::default(),
+            )
+            .chain_err(|| "cannot load cartridge save")?;
+
+        let cheats = match romfn {
+            Some(f) => CheatList::load(&f.with_extension("cht"))?,
+            None => CheatList::new(Path::new("")),
+        };
+        if !cheats.cheats.is_empty() {
+            info!(logger, "cheats loaded"; "num" => cheats.cheats.len());
+        }
 
         return Ok(N64 {
             logger,
             sync,
             initial_state: CurrentState().clone(),
+            hacks,
+            cic,
+            boot: if biosfn.is_some() {
+                BootMode::Pif
+            } else {
+                BootMode::Hle
+            },
+            savestate_path: romfn.map_or(PathBuf::new(), |f| f.with_extension("state")),
+            rom_path: romfn.map_or(PathBuf::new(), |f| f.to_owned()),
+            deterministic: false,
+            flush_policy: FlushPolicy::default(),
+            script: None,
+            cheats,
+            ramsearch: RamSearch::new(),
+            game: GameSettings {
+                name: Cartridge::get().header_name(),
+                ..GameSettings::default()
+            },
+            netplay: None,
         });
     }
 
@@ -165,15 +329,9 @@ impl N64 {
         // 000000FF | --  | CIC IPL2 seed value
         // -------- | S4  | TV Type (0=PAL, 1=NTSC, 2=MPAL)
 
-        // Setup the encryption seed, given the CIC model that we detect
-        // by checksumming the ROM header.
-        let mut seed: u32 = match Cartridge::get().detect_cic_model()? {
-            CicModel::Cic6101 => 0x3F, // starfox
-            CicModel::Cic6102 => 0x3F, // mario
-            CicModel::Cic6103 => 0x78, // banjo
-            CicModel::Cic6105 => 0x91, // zelda
-            CicModel::Cic6106 => 0x85, // f-zero x
-        } << 8;
+        // Setup the encryption seed, given the CIC model that we detected
+        // by checksumming the ROM boot code.
+        let mut seed: u32 = self.cic.seed() << 8;
 
         // Set the NMI/reset bit
         if !hard_reset {
@@ -185,21 +343,463 @@ impl N64 {
         // FIXME: fix RDRAM initialization emulation. IPL3 does initialize RDRAM (starting at 0x0400_0040),
         // and is supposed to end up writing the RAM size at 0x8000_0318, but it does not currently work.
         // This is relied upon by libdragon at least. So fix it by setting the RDRAM as already initialized
-        // and copying the RAM size.
+        // and copying the RAM size (which is where osMemSize is read from; the 6105 IPL3 stores it at
+        // 0x8000_03F0 instead).
+        let rdram_size = Ri::get().rdram.len() as u32;
         R4300::get_mut().bus.write::<u32>(0x0470_000C, 0x14);
-        R4300::get_mut().bus.write::<u32>(0x0000_0318, 4*1024*1024);
+        R4300::get_mut().bus.write::<u32>(0x0000_0318, rdram_size);
+        if self.cic == CicModel::Cic6105 {
+            R4300::get_mut().bus.write::<u32>(0x0000_03F0, rdram_size);
+        }
         Ok(())
     }
-}
 
-impl hw::OutputProducer for N64 {
-    type AudioSampleFormat = S16_STEREO;
+    /// Override the CIC model detected from the ROM boot code. It takes
+    /// effect at the next boot (see `setup_boot`).
+    pub fn set_cic_model(&mut self, cic: CicModel) {
+        self.cic = cic;
+    }
 
-    fn render_frame(
+    /// Return the TV type of the console, selected by the region of the game.
+    pub fn tv_type(&self) -> TvType {
+        Vi::get().tv_type()
+    }
+
+    /// Return the CRC pair stored in the ROM header, which identifies the game.
+    pub fn rom_crc(&self) -> (u32, u32) {
+        Cartridge::get().header_crc()
+    }
+
+    /// Apply the settings overridden for the game (see `GameSettings`). Like
+    /// the CIC model, they take effect at the next boot.
+    pub fn set_game_settings(&mut self, mut game: GameSettings) -> Result<()> {
+        game.validate()?;
+        if let Some(cic) = game.cic.as_ref() {
+            self.set_cic_model(CicModel::from_name(cic)?);
+        }
+        if let Some(st) = game
+            .save_type
+            .as_ref()
+            .and_then(|st| SaveType::from_name(st))
+        {
+            self.set_save_type(st)?;
+        }
+        if let Some(ratio) = game.cpu_clock_ratio {
+            self.set_cpu_clock_ratio(ratio);
+        }
+        match game.gfx.as_ref().map(|g| g.as_str()) {
+            Some("hle") => self.set_gfx_hle(true),
+            Some("lle") => self.set_gfx_hle(false),
+            _ => {}
+        }
+        match game.mem_timing.as_ref().map(|t| t.as_str()) {
+            Some("fast") => self.set_mem_timing(MemTiming::Fast),
+            Some("accurate") => self.set_mem_timing(MemTiming::Accurate),
+            _ => {}
+        }
+        if let Some(caches) = game.cache_emulation {
+            self.set_cache_emulation(caches);
+        }
+        if let Some(filters) = game.vi_filters {
+            self.set_vi_filters(filters);
+        }
+        if game.name.is_empty() {
+            game.name = self.game.name.clone();
+        }
+        self.game = game;
+        Ok(())
+    }
+
+    /// Return the settings overridden for the game, including the changes
+    /// made in the debugger, so that they can be persisted.
+    pub fn game_settings(&self) -> &GameSettings {
+        &self.game
+    }
+
+    /// Load a symbol file (ELF, nm output or .map) for the game, to be
+    /// displayed in the debugger.
+    pub fn load_symbols(&mut self, path: &Path) -> Result<()> {
+        let syms = dbg::SymbolTable::load(path)
+            .map_err(|e| format!("cannot load symbols from {}: {}", path.display(), e))?;
+        info!(self.logger, "loaded symbols";
+            "file" => path.display().to_string(), "count" => syms.len());
+        R4300::get_mut().set_symbols(&syms);
+        Ok(())
+    }
+
+    /// Configure the main CPU FPU to handle denormals and NaNs as specified
+    /// by IEEE 754, instead of emulating the R4300 FPU behavior (which
+    /// raises exceptions for them). Some games run faster this way, as
+    /// they might not need to handle FPU exceptions at all.
+    pub fn set_fpu_ieee_mode(&mut self, ieee: bool) {
+        R4300::get_mut().cop1.set_ieee_mode(ieee);
+    }
+
+    /// Select the timing model of the main CPU memory accesses: the fast one
+    /// runs every opcode in a single cycle, while the accurate one emulates
+    /// the caches and the latency of RDRAM, RCP and cartridge accesses.
+    pub fn set_mem_timing(&mut self, timing: MemTiming) {
+        R4300::get_mut().set_mem_timing(timing, R4300::mem_regions());
+    }
+
+    /// Emulate the contents of the main CPU instruction and data caches, so
+    /// that games relying on stale cache lines (eg: missing writebacks or
+    /// invalidations around DMAs) behave like on real hardware. This is
+    /// disabled by default, as most games do not need it and it is slower.
+    pub fn set_cache_emulation(&mut self, enabled: bool) {
+        R4300::get_mut().set_cache_emulation(enabled);
+    }
+
+    /// Emulate the filters applied by the VI when scanning out the
+    /// framebuffer (gamma correction and dither, dither and divot filters),
+    /// as enabled by the game. This is enabled by default.
+    pub fn set_vi_filters(&mut self, enabled: bool) {
+        Vi::get_mut().set_filters(enabled);
+    }
+
+    /// Execute graphics tasks with the microcode HLE (F3D, F3DEX and F3DEX2
+    /// display lists are converted directly into RDP commands), instead of
+    /// running the microcode on the RSP. This is much faster, but less
+    /// accurate; this overrides the per-game default.
+    pub fn set_gfx_hle(&mut self, enabled: bool) {
+        Sp::get_mut().set_gfx_hle(enabled);
+    }
+
+    /// Set the number of threads used by the RDP to render primitives. The
+    /// framebuffer is split in strips of scanlines across the threads; with
+    /// 0 or 1, rendering happens on the emulation thread.
+    pub fn set_rdp_threads(&mut self, n: usize) {
+        Dp::get_mut().set_render_threads(n);
+    }
+
+    /// Render RDP primitives in background, on dedicated threads, while the
+    /// emulation keeps running. The emulation still waits for rendering to
+    /// finish whenever the CPU can observe it, so games reading back the
+    /// framebuffer are not affected. This is disabled in deterministic mode.
+    pub fn set_threaded_rdp(&mut self, enabled: bool) {
+        Dp::get_mut().set_threaded_rendering(enabled && !self.deterministic);
+    }
+
+    /// Select the renderer used by the RDP: the software rasterizer, or the
+    /// OpenGL renderer drawing on the GPU. It can be switched at any time.
+    pub fn set_rdp_backend(&mut self, backend: RenderBackend) {
+        Dp::get_mut().set_render_backend(backend);
+    }
+
+    /// Change the clock of the main CPU, as a multiplier of its nominal
+    /// frequency. Overclocking the CPU (eg: 2.0) can hide slowdowns in games
+    /// which are CPU bound; the RCP and all DMAs keep running at their
+    /// nominal speed.
+    pub fn set_cpu_clock_ratio(&mut self, ratio: f64) {
+        if !self.sync.set_clock_ratio(MAINCPU_NAME, ratio) {
+            warn!(self.logger, "invalid CPU clock ratio, ignored"; "ratio" => ratio);
+        }
+    }
+
+    /// Change the sync granularity of a subsystem (eg: "RSP"): how many VI
+    /// clock cycles it can run out of step with the others, between the
+    /// events that need exact synchronization (VI, AI and RDP interrupts).
+    /// Lower values are more accurate, higher values are faster.
+    pub fn set_sync_granularity(&mut self, name: &str, cycles: i64) -> Result<()> {
+        if cycles < 0 {
+            bail!("invalid sync granularity: {}", cycles);
+        }
+        if !self.sync.set_granularity(name, cycles) {
+            bail!("unknown subsystem: {}", name);
+        }
+        Ok(())
+    }
+
+    /// Enable the deterministic mode: the emulation only depends on the ROM
+    /// and on the movie being played back (if any), so that two runs produce
+    /// bit-identical video and audio. The host input is ignored (unless a
+    /// movie is being recorded, as it becomes part of the movie), and the
+    /// cartridge save and Controller Paks start blank and are never written
+    /// to disk. This must be called before configuring saves and accessories.
+    ///
+    /// Emulation never depends on host time, except for accesses racing with
+    /// the RDP, so background rendering is disabled; frame pacing is
+    /// performed by the frontend.
+    pub fn set_deterministic(&mut self, det: bool) -> Result<()> {
+        self.deterministic = det;
+        Pi::get_mut().set_deterministic(det);
+        if det {
+            Dp::get_mut().set_threaded_rendering(false);
+        }
+        let kind = CartSave::get().kind();
+        self.set_save_type(kind)
+    }
+
+    /// Seed the pseudo-random noise generated by the emulated hardware (the
+    /// VI gamma dither). The noise only depends on the seed and on the frame
+    /// number, so runs with the same seed are reproducible.
+    pub fn set_rng_seed(&mut self, seed: u32) {
+        Vi::get_mut().set_rng_seed(seed);
+    }
+
+    /// Hash of the emulated memory (RDRAM), after all pending rendering is
+    /// complete. Runs in deterministic mode with the same inputs produce the
+    /// same sequence of hashes; netplay uses it to detect desyncs.
+    pub fn state_hash(&self) -> u64 {
+        Dp::get().fence_rendering();
+        netplay::rdram_hash(&Ri::get().rdram[..])
+    }
+
+    /// Override the save type of the cartridge, in case it's not correctly
+    /// detected. The save file is named after the ROM, with an extension
+    /// depending on the save type (.eep, .sra, .fla).
+    pub fn set_save_type(&mut self, save_type: SaveType) -> Result<()> {
+        let path = if self.deterministic {
+            None
+        } else {
+            Some(self.rom_path.with_extension(save_type.extension()))
+        };
+        CartSave::get_mut()
+            .setup(
+                save_type,
+                path.as_ref().map(|p| p.as_path()),
+                self.flush_policy,
+            )
+            .chain_err(|| "cannot load cartridge save")?;
+        Ok(())
+    }
+
+    /// Select when cartridge saves and accessories are written to disk (see
+    /// `FlushPolicy`); files are always replaced atomically. This must be
+    /// called before configuring accessories.
+    pub fn set_save_flush_policy(&mut self, policy: FlushPolicy) -> Result<()> {
+        self.flush_policy = policy;
+        let kind = CartSave::get().kind();
+        self.set_save_type(kind)
+    }
+
+    /// Plug an accessory into the specified controller (0-3), or remove it.
+    /// Controller Paks are persisted into a per-game raw image file (.mpk)
+    /// next to the ROM. The motor status of Rumble Paks is reported through
+    /// the input manager, so that the host can forward it to a physical
+    /// gamepad. The Game Boy cartridge of a Transfer Pak is loaded from the
+    /// specified ROM and save files.
+    pub fn set_pak(&mut self, ch: usize, kind: PakKind) -> Result<()> {
+        let pak: Option<Box<dyn Pak>> = match kind {
+            PakKind::None => None,
+            PakKind::Controller if self.deterministic => {
+                Some(Box::new(ControllerPak::blank(self.logger.clone())))
+            }
+            PakKind::Controller => Some(Box::new(self.open_controller_pak(ch)?)),
+            PakKind::Rumble => Some(Box::new(RumblePak::default())),
+            PakKind::Transfer { rom, save } => {
+                let cart = GbCart::new(self.logger.clone(), &rom, &save, self.flush_policy)?;
+                info!(self.logger, "Transfer Pak inserted"; "game" => cart.title());
+                Some(Box::new(TransferPak::new(cart)))
+            }
+        };
+        Pi::get_mut().set_pak(ch, pak);
+        Ok(())
+    }
+
+    fn open_controller_pak(&self, ch: usize) -> Result<ControllerPak> {
+        let path = self.mpk_path(ch);
+        ControllerPak::open(self.logger.clone(), &path, self.flush_policy)
+            .chain_err(|| format!("cannot open Controller Pak {}", path.display()))
+    }
+
+    fn mpk_path(&self, ch: usize) -> PathBuf {
+        if ch == 0 {
+            self.rom_path.with_extension("mpk")
+        } else {
+            self.rom_path.with_extension(format!("{}.mpk", ch + 1))
+        }
+    }
+
+    /// Import a raw Controller Pak image (eg: from another emulator) into
+    /// the per-game Controller Pak of the specified controller, and plug it.
+    pub fn import_controller_pak(&mut self, ch: usize, path: &Path) -> Result<()> {
+        let mut pak = self.open_controller_pak(ch)?;
+        pak.import(path)
+            .chain_err(|| format!("cannot import {}", path.display()))?;
+        Pi::get_mut().set_pak(ch, Some(Box::new(pak)));
+        Ok(())
+    }
+
+    /// Export the per-game Controller Pak of the specified controller as a
+    /// raw image, that can be used with other emulators.
+    pub fn export_controller_pak(&self, ch: usize, path: &Path) -> Result<()> {
+        let pak = self.open_controller_pak(ch)?;
+        pak.export(path)
+            .chain_err(|| format!("cannot export {}", path.display()))?;
+        Ok(())
+    }
+
+    fn movie_game(&self) -> MovieGame {
+        let cart = Cartridge::get();
+        let country = cart.country_code();
+        MovieGame {
+            name: cart.header_name(),
+            crc: cart.header_crc().0,
+            country: country as u16,
+            vi_per_sec: TvType::from_country(country).refresh_rate() as u8,
+        }
+    }
+
+    /// Start recording the controller inputs into a movie (.m64). If
+    /// `from_snapshot` is true, the movie starts from the current state,
+    /// which is saved next to the movie; otherwise, the movie must be
+    /// started at power-on.
+    pub fn start_recording(&mut self, path: &Path, from_snapshot: bool) -> Result<()> {
+        if from_snapshot {
+            self.save_state(&Movie::state_path(path))?;
+        }
+        // Only the first controller is currently connected.
+        let movie = Movie::record(path, &self.movie_game(), 1, from_snapshot)?;
+        info!(self.logger, "movie recording started"; "file" => path.display().to_string());
+        Pi::get_mut().set_movie(Some(movie));
+        Ok(())
+    }
+
+    /// Play back a movie (.m64), replacing the controller inputs. The
+    /// console is reset (or the movie savestate is loaded), so that the
+    /// emulation matches the one that was recorded.
+    pub fn start_playback(&mut self, path: &Path) -> Result<()> {
+        let movie = Movie::play(path, &self.movie_game())?;
+        if movie.controllers() > 1 {
+            bail!("movies with multiple controllers are not supported");
+        }
+        if movie.from_snapshot() {
+            self.load_state(&Movie::state_path(path))?;
+        } else {
+            DebuggerModel::reset(self, true)?;
+        }
+        info!(self.logger, "movie playback started"; "file" => path.display().to_string(), "samples" => movie.progress().1, "rerecords" => movie.rerecords());
+        Pi::get_mut().set_movie(Some(movie));
+        Ok(())
+    }
+
+    /// Stop recording or playing back the current movie.
+    pub fn stop_movie(&mut self) {
+        Pi::get_mut().set_movie(None);
+    }
+
+    /// Load a Lua script (see the `script` module), replacing the current
+    /// one (if any). The script is run immediately, and its frame callback
+    /// is invoked at the end of each frame.
+    pub fn load_script(&mut self, path: &Path) -> Result<()> {
+        self.stop_script();
+        self.script = Some(Script::load(self.logger.new(o!()), path)?);
+        info!(self.logger, "script loaded"; "file" => path.display().to_string());
+        Ok(())
+    }
+
+    /// Load a cheat file (see the `cheats` module), replacing the cheats
+    /// of the per-ROM cheat file. Changes to the enabled cheats are saved
+    /// back into this file.
+    pub fn load_cheats(&mut self, path: &Path) -> Result<()> {
+        self.cheats = CheatList::load(path)?;
+        info!(self.logger, "cheats loaded"; "file" => path.display().to_string(), "num" => self.cheats.cheats.len());
+        Ok(())
+    }
+
+    /// Stop the current script, and release the controllers it was driving.
+    pub fn stop_script(&mut self) {
+        self.script = None;
+        for ch in 0..4 {
+            Pi::get_mut().set_script_input(ch, None);
+        }
+    }
+
+    /// Start a netplay session with another instance running the same game
+    /// with the same settings (see the `netplay` module). This blocks until
+    /// the peer connects. Both instances must be in deterministic mode, and
+    /// start the session right after boot.
+    pub fn start_netplay(&mut self, cfg: &NetplayConfig) -> Result<()> {
+        if !self.deterministic {
+            bail!("netplay requires deterministic mode");
+        }
+        let np = Netplay::connect(cfg, self.rom_crc().0, self.logger.new(o!()))?;
+        hw::osd_message(format!("Netplay started (player {})", cfg.player + 1));
+        self.netplay = Some(np);
+        Ok(())
+    }
+
+    /// Stop the netplay session (if any), giving the controllers back to
+    /// the host input.
+    pub fn stop_netplay(&mut self) {
+        self.netplay = None;
+        Pi::get_mut().set_netplay_input(None);
+    }
+
+    // Emulate a frame of the netplay session: exchange inputs with the peer,
+    // and either wait for its input (delay mode), or emulate again the
+    // frames whose input was mispredicted (rollback mode).
+    fn netplay_frame(
         &mut self,
+        np: &mut Netplay,
         screen: &mut GfxBufferMutLE<Rgb888>,
-        sound: &mut SndBufferMut<Self::AudioSampleFormat>,
+        sound: &mut SndBufferMut<S16_STEREO>,
+    ) -> Result<()> {
+        np.push_local_input(Pi::get().host_input(0))?;
+        np.poll()?;
+        if !np.rollback() {
+            np.wait_remote(np.frame())?;
+            return self.netplay_step(np, screen, sound);
+        }
+
+        if let Some((frame, state)) = np.check_predictions() {
+            // The output of the frames emulated again was already shown.
+            let current = np.frame();
+            self.load_state_from(&state[..])?;
+            np.rewind(frame);
+            let mut scratch = OwnedGfxBufferLE::<Rgb888>::new(screen.width(), screen.height());
+            let mut scratch_sound = OwnedSndBuffer::<S16_STEREO>::with_capacity(sound.count());
+            while np.frame() < current {
+                self.netplay_step(np, &mut scratch.buf_mut(), &mut scratch_sound.buf_mut())?;
+            }
+        }
+        // Stall if running too far ahead of the peer.
+        if np.frame() >= MAX_ROLLBACK {
+            np.wait_remote(np.frame() - MAX_ROLLBACK)?;
+        }
+        self.netplay_step(np, screen, sound)
+    }
+
+    // Emulate the next frame of the netplay session. If the input of the
+    // peer was not received yet (only in rollback mode), it is predicted,
+    // and the state is saved to emulate the frame again if needed.
+    fn netplay_step(
+        &mut self,
+        np: &mut Netplay,
+        screen: &mut GfxBufferMutLE<Rgb888>,
+        sound: &mut SndBufferMut<S16_STEREO>,
+    ) -> Result<()> {
+        let frame = np.frame();
+        if np.needs_hash() {
+            np.set_hash(self.state_hash());
+        }
+        let remote = match np.remote_input(frame) {
+            Some(remote) => remote,
+            None => {
+                let predicted = np.predict_remote(frame);
+                let mut state = Vec::new();
+                self.save_state_into(&mut state)?;
+                np.save_prediction(frame, predicted, state);
+                predicted
+            }
+        };
+        let mut inputs = [remote; 2];
+        inputs[np.player()] = np.local_input(frame);
+        Pi::get_mut().set_netplay_input(Some(inputs));
+        self.run_frame(screen, sound);
+        np.advance();
+        Ok(())
+    }
+
+    // Emulate a frame.
+    fn run_frame(
+        &mut self,
+        screen: &mut GfxBufferMutLE<Rgb888>,
+        sound: &mut SndBufferMut<S16_STEREO>,
     ) {
+        self.update_video_timing();
+        let (script, cheats, logger) = (&mut self.script, &self.cheats, &self.logger);
         self.sync.run_frame(|evt| match evt {
             sync::Event::BeginFrame => {
                 Vi::get_mut().begin_frame(screen);
@@ -207,20 +807,271 @@ impl hw::OutputProducer for N64 {
                 Pi::get_mut().begin_frame();
             }
             sync::Event::HSync(x, y) if x == 0 => {
-                Vi::get_mut().set_line(y);
+                update_mem_contention();
+                if Vi::get_mut().set_halfline(y) {
+                    cheats.apply();
+                }
             }
             sync::Event::EndFrame => {
                 Vi::get_mut().end_frame(screen);
                 Ai::get_mut().end_frame(sound);
                 Pi::get_mut().end_frame();
+                Dp::get_mut().end_frame();
+                run_script(script, screen, logger);
             }
             _ => {}
         });
     }
 
+    /// Save the whole emulation state into the specified file.
+    pub fn save_state(&mut self, path: &Path) -> Result<()> {
+        // Check before creating the file, not to truncate a valid savestate.
+        if self.sync.in_frame() {
+            bail!("cannot save state in the middle of a frame");
+        }
+        let f = File::create(path).chain_err(|| "cannot create savestate file")?;
+        self.save_state_into(BufWriter::new(f))?;
+        info!(self.logger, "state saved"; "file" => path.display().to_string());
+        Ok(())
+    }
+
+    /// Load the whole emulation state from the specified file. If the load
+    /// fails, the current emulation state is not modified.
+    pub fn load_state(&mut self, path: &Path) -> Result<()> {
+        if self.netplay.is_some() {
+            bail!("cannot load state during netplay");
+        }
+        let f = File::open(path).chain_err(|| "cannot open savestate file")?;
+        self.load_state_from(BufReader::new(f))?;
+        info!(self.logger, "state loaded"; "file" => path.display().to_string());
+        Ok(())
+    }
+
+    /// Save the whole emulation state into a writer (eg: a memory buffer,
+    /// for frontends that manage savestates themselves).
+    pub fn save_state_into<W: Write>(&mut self, writer: W) -> Result<()> {
+        if self.sync.in_frame() {
+            bail!("cannot save state in the middle of a frame");
+        }
+        emu::state::save_state(
+            writer,
+            SAVESTATE_MAGIC,
+            SAVESTATE_VERSION,
+            &mut self.snapshot_components(),
+        )
+        .map_err(|e| format!("cannot save state: {}", e))?;
+        Ok(())
+    }
+
+    /// Load the whole emulation state from a reader. If the load fails, the
+    /// current emulation state is not modified.
+    pub fn load_state_from<R: Read>(&mut self, reader: R) -> Result<()> {
+        // Primitives still being drawn in background must not overwrite the
+        // loaded RDRAM.
+        Dp::get().fence_rendering();
+        emu::state::load_state(
+            reader,
+            SAVESTATE_MAGIC,
+            SAVESTATE_VERSION,
+            &mut self.snapshot_components(),
+        )
+        .map_err(|e| format!("cannot load state: {}", e))?;
+        Ok(())
+    }
+
+    // Save/load the quick savestate (bound to hotkeys), which is stored
+    // next to the ROM.
+    fn quick_save(&mut self) -> std::result::Result<(), String> {
+        let path = self.savestate_path.clone();
+        self.save_state(&path).map_err(|e| {
+            error!(self.logger, "error saving state"; "err" => e.to_string());
+            e.to_string()
+        })
+    }
+
+    fn quick_load(&mut self) -> std::result::Result<(), String> {
+        let path = self.savestate_path.clone();
+        self.load_state(&path).map_err(|e| {
+            error!(self.logger, "error loading state"; "err" => e.to_string());
+            e.to_string()
+        })
+    }
+
+    // Return all the components that keep part of their status outside
+    // of the emulator state.
+    fn snapshot_components(&mut self) -> [&mut dyn Snapshotable; 4] {
+        [
+            &mut *self.sync,
+            R4300::get_mut().deref_mut(),
+            RSPCPU::get_mut().deref_mut(),
+            Dp::get_mut(),
+        ]
+    }
+
+    /// Select the boot mode and prepare the console to boot the game.
+    pub fn setup_boot(&mut self, mode: BootMode) -> Result<()> {
+        if mode == BootMode::Pif && self.boot != BootMode::Pif {
+            bail!("PIF boot requires a PIF ROM dump");
+        }
+        self.boot = mode;
+        self.do_boot(true)
+    }
+
+    // Apply the video timing programmed in the VI (eg: after the game
+    // switched between interlaced and progressive modes) to the frames
+    // emulated from now on.
+    fn update_video_timing(&mut self) {
+        self.sync.emu_mut().video = Vi::get().video_timing();
+        self.sync.reconfigure();
+    }
+
+    fn do_boot(&mut self, hard_reset: bool) -> Result<()> {
+        self.setup_cic(hard_reset)?;
+        if self.boot == BootMode::Hle {
+            self.hle_boot(hard_reset)?;
+        }
+        self.hacks.apply_boot_patches(&self.logger);
+        self.cheats.apply_boot(&self.logger);
+        Ok(())
+    }
+
+    // Emulate the work done by PIF ROM and IPL3: copy the bootcode to DMEM
+    // and the first megabyte of the game to RDRAM, setup the hardware and
+    // CPU registers as IPL3 leaves them, and jump to the game entry point.
+    fn hle_boot(&mut self, hard_reset: bool) -> Result<()> {
+        let seed = self.cic.seed() as u64;
+
+        let cpu = R4300::get_mut();
+        let entry = self.cic.entry_point(cpu.bus.read::<u32>(0x1000_0008));
+
+        // Country code in the header selects the TV type (0=PAL, 1=NTSC, 2=MPAL)
+        let tv_type = Vi::get().tv_type() as u64;
+
+        info!(self.logger, "HLE boot"; "entry" => entry.hex(), "tv" => tv_type, "cic" => self.cic as u32);
+
+        for off in (0..0x1000).step_by(4) {
+            let v = cpu.bus.read::<u32>(0x1000_0000 + off);
+            cpu.bus.write::<u32>(0x0400_0000 + off, v);
+        }
+        for off in (0..0x10_0000).step_by(4) {
+            let v = cpu.bus.read::<u32>(0x1000_1000 + off);
+            cpu.bus.write::<u32>((entry & 0x1FFF_FFFF) + off, v);
+        }
+
+        // IPL2 leaves in IMEM the code that 6105 games use to poll PIF.
+        if self.cic == CicModel::Cic6105 {
+            let code = [
+                0x3C0D_BFC0u32,
+                0x8DA8_07FC,
+                0x25AD_07C0,
+                0x3108_0080,
+                0x5500_FFFC,
+                0x3C0D_BFC0,
+                0x8DA8_0024,
+                0x3C0B_B000,
+            ];
+            for (idx, insn) in code.iter().enumerate() {
+                cpu.bus.write::<u32>(0x0400_1000 + idx as u32 * 4, *insn);
+            }
+        }
+
+        // PIF ROM configures the cartridge bus with the timings found at
+        // the beginning of the ROM header.
+        Pi::get_mut().set_dom1_timing(cpu.bus.read::<u32>(0x1000_0000));
+
+        // RDRAM interface, as configured by IPL3 (RI_SELECT is set by
+        // setup_cic).
+        cpu.bus.write::<u32>(0x0470_0000, 0x0E); // RI_MODE
+        cpu.bus.write::<u32>(0x0470_0010, 0x0006_3634); // RI_REFRESH
+
+        // Status: CU0, CU1, FR (as left by IPL3)
+        cpu.set_cop0_reg(12, 0x3400_0000);
+
+        let ctx = cpu.ctx_mut();
+        ctx.regs[11] = 0xA400_0040u32.sx64(); // t3
+        ctx.regs[19] = 0; // s3: ROM type (0=GamePack)
+        ctx.regs[20] = tv_type; // s4: TV type
+        ctx.regs[21] = if hard_reset { 0 } else { 1 }; // s5: reset type
+        ctx.regs[22] = seed; // s6: IPL3 seed
+        ctx.regs[23] = 0; // s7: version
+        ctx.regs[29] = 0xA400_1FF0u32.sx64(); // sp
+        ctx.regs[31] = 0xA400_1550u32.sx64(); // ra
+        ctx.set_pc(entry.sx64());
+        Ok(())
+    }
+}
+
+// Extra latency of the main CPU memory reads while the RSP is running, as
+// its DMAs (and the RDP fetching commands) compete for RDRAM.
+const RSP_CONTENTION: i64 = 4;
+
+// Update the memory contention seen by the main CPU, which is sampled once
+// per half-line (it only matters with the accurate memory timing).
+fn update_mem_contention() {
+    let busy = !RSPCPU::get().ctx().is_halted();
+    R4300::get_mut().set_mem_contention(if busy { RSP_CONTENTION } else { 0 });
+}
+
+// Run the frame callback of the script (if any). Errors are logged and stop
+// the script.
+fn run_script(
+    script: &mut Option<Script>,
+    screen: &mut GfxBufferMutLE<Rgb888>,
+    logger: &slog::Logger,
+) {
+    let res = match script.as_mut() {
+        Some(s) => s.end_frame(screen),
+        None => return,
+    };
+    if let Err(err) = res {
+        error!(logger, "script stopped"; "err" => err.to_string());
+        *script = None;
+        for ch in 0..4 {
+            Pi::get_mut().set_script_input(ch, None);
+        }
+    }
+}
+
+impl hw::OutputProducer for N64 {
+    type AudioSampleFormat = S16_STEREO;
+
+    fn render_frame(
+        &mut self,
+        screen: &mut GfxBufferMutLE<Rgb888>,
+        sound: &mut SndBufferMut<Self::AudioSampleFormat>,
+    ) {
+        match self.netplay.take() {
+            Some(mut np) => match self.netplay_frame(&mut np, screen, sound) {
+                Ok(()) => self.netplay = Some(np),
+                Err(err) => {
+                    error!(self.logger, "netplay stopped"; "err" => err.to_string());
+                    hw::osd_message(format!("Netplay stopped: {}", err));
+                    self.stop_netplay();
+                }
+            },
+            None => self.run_frame(screen, sound),
+        }
+    }
+
     fn input_manager(&mut self) -> Option<&mut InputManager> {
         Some(&mut Pi::get_mut().input)
     }
+
+    fn set_upscale(&mut self, factor: usize) {
+        Dp::get_mut().set_render_scale(factor);
+    }
+
+    fn upscaled_frame(&mut self) -> Option<u32> {
+        Vi::get().upscaled_frame()
+    }
+
+    fn save_state(&mut self) -> std::result::Result<(), String> {
+        self.quick_save()
+    }
+
+    fn load_state(&mut self) -> std::result::Result<(), String> {
+        self.quick_load()
+    }
 }
 
 impl DebuggerModel for N64 {
@@ -230,6 +1081,8 @@ impl DebuggerModel for N64 {
         sound: &mut SndBufferMut<SF>,
         tracer: &dbg::Tracer,
     ) -> dbg::Result<()> {
+        self.update_video_timing();
+        let (script, cheats, logger) = (&mut self.script, &self.cheats, &self.logger);
         self.sync.trace_frame(
             |evt| match evt {
                 sync::Event::BeginFrame => {
@@ -241,9 +1094,14 @@ impl DebuggerModel for N64 {
                     Vi::get_mut().end_frame(screen);
                     Ai::get_mut().end_frame(sound);
                     Pi::get_mut().end_frame();
+                    Dp::get_mut().end_frame();
+                    run_script(script, screen, logger);
                 }
                 sync::Event::HSync(x, y) if x == 0 => {
-                    Vi::get_mut().set_line(y);
+                    update_mem_contention();
+                    if Vi::get_mut().set_halfline(y) {
+                        cheats.apply();
+                    }
                 }
                 _ => {}
             },
@@ -260,9 +1118,42 @@ impl DebuggerModel for N64 {
         }
     }
 
+    fn reg_database(&self) -> hw::RegDatabase {
+        regdb::reg_database()
+    }
+
+    fn memory_view(&mut self, cpu_name: &str) -> Option<&mut dyn dbg::MemoryView> {
+        match cpu_name {
+            MAINCPU_NAME => Some(R4300::get_mut().deref_mut()),
+            RSPCPU_NAME => Some(RSPCPU::get_mut().deref_mut()),
+            _ => None,
+        }
+    }
+
+    fn visit_regs(&mut self, cpu_name: &str, visit: &mut dyn FnMut(&str, dbg::RegisterSize)) {
+        match cpu_name {
+            MAINCPU_NAME => dbg::visit_all_regs(R4300::get_mut().deref_mut(), visit),
+            RSPCPU_NAME => dbg::visit_all_regs(RSPCPU::get_mut().deref_mut(), visit),
+            _ => {}
+        }
+    }
+
+    #[cfg(feature = "frontend")]
     fn render_debug<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>) {
         R4300::get_mut().render_debug(dr);
         RSPCPU::get_mut().render_debug(dr);
+        Sp::get_mut().render_debug(dr);
+        Ai::get_mut().render_debug(dr);
+        Mi::get_mut().render_debug(dr);
+        Dp::get_mut().render_debug(dr);
+        Vi::get_mut().render_debug(dr);
+        dmalog::render_debug(dr);
+        self.sync.render_debug(dr);
+        self.cheats.render_debug(dr, &self.logger);
+        self.game.render_debug(dr, Cartridge::get().header_crc());
+        self.ramsearch
+            .render_debug(dr, &mut self.cheats, &self.logger);
+        dr.render_input_calibration(&mut Pi::get_mut().input);
     }
 
     fn all_cpus(&self) -> Vec<String> {
@@ -277,17 +1168,72 @@ impl DebuggerModel for N64 {
         self.sync.frames()
     }
 
-    fn reset(&mut self, hard: bool) {
+    fn reset(&mut self, hard: bool) -> std::result::Result<(), String> {
         if hard {
             // Hard reset: restore initial emulator status
             self.initial_state.clone().make_current();
-            self.setup_cic(true).unwrap();
+            self.do_boot(true).map_err(|e| e.to_string())?;
             self.sync.reset();
         } else {
             // Soft reset: just trigger a reset on CPUs and hope for the best
             R4300::get_mut().reset();
             RSPCPU::get_mut().reset();
-            self.setup_cic(false).unwrap();
+            self.do_boot(false).map_err(|e| e.to_string())?;
         }
+        Ok(())
+    }
+
+    fn save_state(&mut self) -> std::result::Result<(), String> {
+        self.quick_save()
+    }
+
+    fn load_state(&mut self) -> std::result::Result<(), String> {
+        self.quick_load()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(input: &mut InputManager, name: &str, val: bool) {
+        input.process_event(InputEvent::Digital("joy1".into(), name.into(), val));
+    }
+
+    #[test]
+    fn controller_mapping() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let mut input = create_input_manager();
+        for name in JOY_NAMES.iter() {
+            assert!(input.device(name).unwrap().has_stick());
+        }
+        assert!(input.device("console").unwrap().input("reset").is_some());
+
+        // Buttons and stick in joybus layout: A B Z S U D L R, reset, 0,
+        // L R cU cD cL cR, then the X and Y axis as signed bytes.
+        press(&mut input, "A", true);
+        press(&mut input, "Z", true);
+        press(&mut input, "right", true);
+        press(&mut input, "L", true);
+        press(&mut input, "c-left", true);
+        input.process_event(InputEvent::Analog("joy1".into(), "X".into(), 0x4000));
+        input.process_event(InputEvent::Analog("joy1".into(), "Y".into(), -0x4000));
+        let pi = Pi::new(logger, None, input).unwrap();
+        assert_eq!(pi.host_input(0), 0xA122_40C0);
+        assert_eq!(pi.host_input(1), 0);
+    }
+
+    #[test]
+    fn controller_reset() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let mut input = create_input_manager();
+
+        // L+R+Start resets the controller, and hides Start.
+        press(&mut input, "L", true);
+        press(&mut input, "R", true);
+        press(&mut input, "S", true);
+        press(&mut input, "B", true);
+        let pi = Pi::new(logger, None, input).unwrap();
+        assert_eq!(pi.host_input(0), 0x40B0_0000);
     }
 }
@@ -0,0 +1,681 @@
+//! Netplay.
+//!
+//! Two instances of the emulator, running the same ROM with the same
+//! settings, exchange the state of their local controller every frame over
+//! UDP, so that both emulate the same two-player game. Each instance drives
+//! one controller (player 1 or 2) with its local input, and the other one
+//! with the input received from the peer. Since the emulation must be
+//! identical on both sides, netplay runs in deterministic mode (saves start
+//! blank, no background rendering), and savestates cannot be loaded.
+//!
+//! Two modes are available:
+//!
+//!   * delay (lockstep): local inputs are applied a few frames after they
+//!     are sampled (the input delay), to give them time to reach the peer.
+//!     A frame is only emulated once the inputs of both players are known,
+//!     so the emulation stalls whenever the network is slower than the
+//!     delay.
+//!   * rollback: when the input of the peer has not arrived yet, it is
+//!     predicted (by repeating the last one received) and the frame is
+//!     emulated anyway, after saving a snapshot of the state. When the
+//!     actual input arrives and differs from the prediction, the snapshot
+//!     is restored and the following frames are emulated again. This allows
+//!     playing with little or no input delay, at the cost of re-emulating
+//!     frames. The emulation can run at most `MAX_ROLLBACK` frames ahead of
+//!     the peer.
+//!
+//! To detect desyncs, once per second both instances exchange a hash of
+//! RDRAM at the start of the same frame; if they differ, the emulations
+//! diverged (eg: because of different settings), which is reported to the
+//! user.
+//!
+//! All packets start with a byte holding their type, followed by big-endian
+//! fields:
+//!
+//! | Type      | Fields                                                     |
+//! |-----------|------------------------------------------------------------|
+//! | 1 (hello) | magic "R64N", version, ROM CRC (u32), player, rollback     |
+//! | 2 (input) | ack (u32), first frame (u32), count (u8), inputs (u32 each)|
+//! | 3 (hash)  | frame (u32), hash of RDRAM (u64)                           |
+//! | 4 (quit)  | -                                                          |
+//!
+//! Input packets carry all the local inputs not yet acknowledged by the
+//! peer (`ack` is the first frame whose input was not received yet), so
+//! that lost packets are recovered by the next ones.
+use super::errors::*;
+
+use byteorder::{BigEndian, ByteOrder};
+use emu::hw::osd_message;
+use slog;
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 4] = b"R64N";
+const VERSION: u8 = 1;
+
+const MSG_HELLO: u8 = 1;
+const MSG_INPUT: u8 = 2;
+const MSG_HASH: u8 = 3;
+const MSG_QUIT: u8 = 4;
+
+/// Maximum number of frames that the emulation can run ahead of the peer in
+/// rollback mode (that is, with predicted inputs).
+pub const MAX_ROLLBACK: u32 = 8;
+
+// Maximum number of inputs sent in a single packet.
+const MAX_INPUTS_PER_PACKET: usize = 64;
+
+// Frames between two desync checks.
+const HASH_INTERVAL: u32 = 60;
+
+// How long to wait for the peer to start, and for its inputs during the
+// session, before giving up.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+
+// While waiting, packets are resent at this interval, to recover from
+// packet loss.
+const RESEND_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Configuration of a netplay session.
+#[derive(Clone, Debug)]
+pub struct NetplayConfig {
+    /// Local UDP port.
+    pub port: u16,
+    /// Address of the peer (host:port).
+    pub peer: String,
+    /// Local player (0 for player 1, 1 for player 2); the peer must use the
+    /// other one.
+    pub player: usize,
+    /// Input delay, in frames.
+    pub delay: usize,
+    /// Use rollback instead of waiting for the inputs of the peer. Both
+    /// instances must use the same mode.
+    pub rollback: bool,
+}
+
+// A frame emulated with a predicted input of the peer, which might have to
+// be emulated again.
+struct Prediction {
+    input: u32,
+    state: Vec<u8>, // savestate taken before the frame
+}
+
+/// A netplay session with a peer: it exchanges inputs and hashes, and keeps
+/// track of the frames emulated with predicted inputs. The emulation itself
+/// is driven by `N64`.
+pub struct Netplay {
+    logger: slog::Logger,
+    socket: UdpSocket,
+    peer: SocketAddr,
+    player: usize,
+    rollback: bool,
+    hello: Vec<u8>,
+
+    frame: u32,                 // next frame to emulate
+    local: BTreeMap<u32, u32>,  // local inputs, by frame
+    local_next: u32,            // next frame to assign a local input to
+    remote: BTreeMap<u32, u32>, // inputs received from the peer, by frame
+    remote_next: u32,           // first frame whose remote input is missing
+    peer_ack: u32,              // first local input not received by the peer
+    last_send: Instant,
+    last_recv: Instant,
+
+    predictions: BTreeMap<u32, Prediction>,
+    hashes: BTreeMap<u32, u64>,        // local hashes not yet final
+    remote_hashes: BTreeMap<u32, u64>, // hashes received from the peer
+    final_hashes: BTreeMap<u32, u64>,  // local hashes sent to the peer
+    desync: bool,                      // a desync was reported
+}
+
+impl Netplay {
+    /// Start a session: bind the local port, and wait for the peer (running
+    /// the ROM with the specified CRC) to connect.
+    pub fn connect(cfg: &NetplayConfig, rom_crc: u32, logger: slog::Logger) -> Result<Netplay> {
+        let peer = cfg
+            .peer
+            .to_socket_addrs()
+            .chain_err(|| format!("cannot resolve netplay peer {}", cfg.peer))?
+            .next()
+            .ok_or_else(|| format!("cannot resolve netplay peer {}", cfg.peer))?;
+        let socket = UdpSocket::bind(("0.0.0.0", cfg.port))
+            .chain_err(|| format!("cannot bind netplay port {}", cfg.port))?;
+        Self::with_socket(socket, peer, cfg, rom_crc, logger)
+    }
+
+    /// Start a session on an already bound socket, with the peer at the
+    /// specified address (the local port and peer of `cfg` are ignored).
+    pub fn with_socket(
+        socket: UdpSocket,
+        peer: SocketAddr,
+        cfg: &NetplayConfig,
+        rom_crc: u32,
+        logger: slog::Logger,
+    ) -> Result<Netplay> {
+        if cfg.player > 1 {
+            bail!("invalid netplay player: {}", cfg.player + 1);
+        }
+        socket.set_read_timeout(Some(Duration::from_millis(5)))?;
+
+        let mut np = Netplay {
+            logger,
+            socket,
+            peer,
+            player: cfg.player,
+            rollback: cfg.rollback,
+            hello: hello_packet(rom_crc, cfg.player, cfg.rollback),
+            frame: 0,
+            local: BTreeMap::new(),
+            local_next: 0,
+            remote: BTreeMap::new(),
+            remote_next: 0,
+            peer_ack: 0,
+            last_send: Instant::now(),
+            last_recv: Instant::now(),
+            predictions: BTreeMap::new(),
+            hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            final_hashes: BTreeMap::new(),
+            desync: false,
+        };
+
+        // The first frames (within the input delay) have no local input.
+        for _ in 0..cfg.delay {
+            np.local.insert(np.local_next, 0);
+            np.local_next += 1;
+        }
+
+        info!(np.logger, "netplay: waiting for peer"; "peer" => peer.to_string(), "player" => cfg.player + 1);
+        let hello = np.hello.clone();
+        let start = Instant::now();
+        let mut buf = [0u8; 1024];
+        loop {
+            if start.elapsed() > HANDSHAKE_TIMEOUT {
+                bail!("netplay: timeout waiting for {}", peer);
+            }
+            np.socket.send_to(&hello, peer)?;
+            let deadline = Instant::now() + RESEND_INTERVAL;
+            while Instant::now() < deadline {
+                let (len, src) = match np.socket.recv_from(&mut buf) {
+                    Ok(res) => res,
+                    Err(ref e) if is_timeout(e) => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                if src.ip() != peer.ip() || len == 0 || buf[0] != MSG_HELLO {
+                    continue;
+                }
+                np.check_hello(&buf[..len], &hello)?;
+                // Answer, in case the peer did not receive our hello yet.
+                np.peer = src;
+                np.socket.send_to(&hello, src)?;
+                info!(np.logger, "netplay: connected"; "peer" => src.to_string());
+                np.last_recv = Instant::now();
+                return Ok(np);
+            }
+        }
+    }
+
+    // Check that the hello of the peer is compatible with ours.
+    fn check_hello(&self, msg: &[u8], ours: &[u8]) -> Result<()> {
+        if msg.len() != ours.len() || msg[1..6] != ours[1..6] {
+            bail!("netplay: the peer is running an incompatible version");
+        }
+        if msg[6..10] != ours[6..10] {
+            bail!("netplay: the peer is running a different game");
+        }
+        if msg[10] == ours[10] {
+            bail!("netplay: both instances are player {}", self.player + 1);
+        }
+        if msg[11] != ours[11] {
+            bail!("netplay: both instances must use the same mode (rollback or delay)");
+        }
+        Ok(())
+    }
+
+    /// Local player (0 or 1).
+    pub fn player(&self) -> usize {
+        self.player
+    }
+
+    /// Return true in rollback mode.
+    pub fn rollback(&self) -> bool {
+        self.rollback
+    }
+
+    /// Next frame to emulate.
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// Return true if a desync with the peer was detected.
+    pub fn desync(&self) -> bool {
+        self.desync
+    }
+
+    /// Add the local input sampled for the current frame (it is applied
+    /// after the input delay), and send it to the peer.
+    pub fn push_local_input(&mut self, value: u32) -> Result<()> {
+        self.local.insert(self.local_next, value);
+        self.local_next += 1;
+        self.send_inputs()
+    }
+
+    /// Local input of a frame.
+    pub fn local_input(&self, frame: u32) -> u32 {
+        self.local.get(&frame).cloned().unwrap_or(0)
+    }
+
+    /// Input of the peer for a frame, if received.
+    pub fn remote_input(&self, frame: u32) -> Option<u32> {
+        self.remote.get(&frame).cloned()
+    }
+
+    /// Predict the input of the peer for a frame: the last one received
+    /// before it.
+    pub fn predict_remote(&self, frame: u32) -> u32 {
+        self.remote
+            .range(..frame)
+            .next_back()
+            .map_or(0, |(_, v)| *v)
+    }
+
+    /// Mark the current frame as emulated.
+    pub fn advance(&mut self) {
+        self.frame += 1;
+
+        // Inputs older than the rollback window are not needed anymore,
+        // once the peer received them.
+        let keep = self.frame.saturating_sub(MAX_ROLLBACK + 1);
+        let local_keep = keep.min(self.peer_ack);
+        self.local = self.local.split_off(&local_keep);
+        self.remote = self
+            .remote
+            .split_off(&keep.min(self.remote_next.saturating_sub(1)));
+        self.update_hashes();
+    }
+
+    /// Restart from a previous frame (after restoring a snapshot).
+    pub fn rewind(&mut self, frame: u32) {
+        self.frame = frame;
+    }
+
+    fn send_inputs(&mut self) -> Result<()> {
+        let first = self.peer_ack;
+        let values: Vec<u32> = self
+            .local
+            .range(first..)
+            .take(MAX_INPUTS_PER_PACKET)
+            .map(|(_, v)| *v)
+            .collect();
+        let mut msg = vec![0u8; 10 + values.len() * 4];
+        msg[0] = MSG_INPUT;
+        BigEndian::write_u32(&mut msg[1..], self.remote_next);
+        BigEndian::write_u32(&mut msg[5..], first);
+        msg[9] = values.len() as u8;
+        for (i, v) in values.iter().enumerate() {
+            BigEndian::write_u32(&mut msg[10 + i * 4..], *v);
+        }
+        self.send(&msg)
+    }
+
+    fn send(&mut self, msg: &[u8]) -> Result<()> {
+        self.last_send = Instant::now();
+        match self.socket.send_to(msg, self.peer) {
+            Ok(_) => Ok(()),
+            // The peer might not be listening for a moment (eg: ICMP port
+            // unreachable); this is detected by the timeout instead.
+            Err(ref e) if e.kind() == ErrorKind::ConnectionRefused => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Process the packets received from the peer, without blocking.
+    pub fn poll(&mut self) -> Result<()> {
+        self.socket.set_nonblocking(true)?;
+        let res = self.recv_all();
+        self.socket.set_nonblocking(false)?;
+        res
+    }
+
+    fn recv_all(&mut self) -> Result<()> {
+        let mut buf = [0u8; 1024];
+        loop {
+            let (len, src) = match self.socket.recv_from(&mut buf) {
+                Ok(res) => res,
+                Err(ref e) if is_timeout(e) => return Ok(()),
+                Err(ref e) if e.kind() == ErrorKind::ConnectionRefused => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            if src == self.peer {
+                self.process(&buf[..len])?;
+            }
+        }
+    }
+
+    /// Block until the input of the peer for the specified frame is
+    /// received. Fails if the peer does not answer for a while.
+    pub fn wait_remote(&mut self, frame: u32) -> Result<()> {
+        self.poll()?;
+        let mut buf = [0u8; 1024];
+        while self.remote_next <= frame {
+            if self.last_recv.elapsed() > PEER_TIMEOUT {
+                bail!("netplay: connection with the peer lost");
+            }
+            if self.last_send.elapsed() > RESEND_INTERVAL {
+                self.send_inputs()?;
+            }
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, src)) if src == self.peer => self.process(&buf[..len])?,
+                Ok(_) => {}
+                Err(ref e) if is_timeout(e) => {}
+                Err(ref e) if e.kind() == ErrorKind::ConnectionRefused => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, msg: &[u8]) -> Result<()> {
+        if msg.is_empty() {
+            return Ok(());
+        }
+        self.last_recv = Instant::now();
+        match msg[0] {
+            // The peer is still connecting: our hello was lost.
+            MSG_HELLO => {
+                let hello = self.hello.clone();
+                self.send(&hello)?;
+            }
+            MSG_INPUT if msg.len() >= 10 => {
+                let ack = BigEndian::read_u32(&msg[1..]);
+                let first = BigEndian::read_u32(&msg[5..]);
+                let count = (msg[9] as usize).min((msg.len() - 10) / 4);
+                self.peer_ack = self.peer_ack.max(ack);
+                for i in 0..count {
+                    let frame = first + i as u32;
+                    if frame >= self.remote_next {
+                        let v = BigEndian::read_u32(&msg[10 + i * 4..]);
+                        self.remote.insert(frame, v);
+                    }
+                }
+                while self.remote.contains_key(&self.remote_next) {
+                    self.remote_next += 1;
+                }
+                self.update_hashes();
+            }
+            MSG_HASH if msg.len() >= 13 => {
+                let frame = BigEndian::read_u32(&msg[1..]);
+                let hash = BigEndian::read_u64(&msg[5..]);
+                self.remote_hashes.insert(frame, hash);
+                self.check_hashes();
+            }
+            MSG_QUIT => bail!("netplay: the peer left the session"),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Record a frame emulated with a predicted input of the peer, with the
+    /// state before it.
+    pub fn save_prediction(&mut self, frame: u32, input: u32, state: Vec<u8>) {
+        self.predictions.insert(frame, Prediction { input, state });
+    }
+
+    /// Check the predicted inputs against the ones received. Predictions
+    /// which were right are dropped; if one was wrong, return the frame
+    /// and the state before it, from which the emulation must restart.
+    pub fn check_predictions(&mut self) -> Option<(u32, Vec<u8>)> {
+        loop {
+            let frame = match self.predictions.keys().next() {
+                Some(&frame) => frame,
+                None => return None,
+            };
+            let actual = self.remote_input(frame)?;
+            let pred = self.predictions.remove(&frame).unwrap();
+            if actual != pred.input {
+                // Frames after this one will be emulated again, with new
+                // predictions and hashes.
+                self.predictions.clear();
+                let _ = self.hashes.split_off(&(frame + 1));
+                return Some((frame, pred.state));
+            }
+        }
+    }
+
+    /// Return true if the hash of the state before the current frame must
+    /// be recorded (see `set_hash`).
+    pub fn needs_hash(&self) -> bool {
+        self.frame % HASH_INTERVAL == 0
+    }
+
+    /// Record the hash of the state before the current frame. It is sent to
+    /// the peer once all previous frames have been emulated with the actual
+    /// inputs.
+    pub fn set_hash(&mut self, hash: u64) {
+        self.hashes.insert(self.frame, hash);
+        self.update_hashes();
+    }
+
+    // Send the hashes which are final: the state before a frame is final
+    // once all previous frames were emulated with the actual inputs.
+    fn update_hashes(&mut self) {
+        let pending = match self.predictions.keys().next() {
+            Some(&first) => self.hashes.split_off(&(first + 1)),
+            None => BTreeMap::new(),
+        };
+        let done = std::mem::replace(&mut self.hashes, pending);
+        for (frame, hash) in done {
+            let mut msg = [0u8; 13];
+            msg[0] = MSG_HASH;
+            BigEndian::write_u32(&mut msg[1..], frame);
+            BigEndian::write_u64(&mut msg[5..], hash);
+            // A lost hash just skips a check.
+            let _ = self.send(&msg);
+            self.final_hashes.insert(frame, hash);
+        }
+        self.check_hashes();
+    }
+
+    fn check_hashes(&mut self) {
+        let frames: Vec<u32> = self
+            .remote_hashes
+            .keys()
+            .filter(|f| self.final_hashes.contains_key(f))
+            .cloned()
+            .collect();
+        for frame in frames {
+            let remote = self.remote_hashes.remove(&frame).unwrap();
+            let local = self.final_hashes.remove(&frame).unwrap();
+            if remote != local && !self.desync {
+                error!(self.logger, "netplay: desync detected"; "frame" => frame);
+                osd_message(format!("Netplay desync detected (frame {})", frame));
+                self.desync = true;
+            }
+        }
+        // Hashes the peer will never send (eg: lost packets) are dropped.
+        let old = self.frame.saturating_sub(HASH_INTERVAL * 4);
+        self.remote_hashes = self.remote_hashes.split_off(&old);
+        self.final_hashes = self.final_hashes.split_off(&old);
+    }
+}
+
+impl Drop for Netplay {
+    fn drop(&mut self) {
+        let _ = self.send(&[MSG_QUIT]);
+    }
+}
+
+fn hello_packet(rom_crc: u32, player: usize, rollback: bool) -> Vec<u8> {
+    let mut msg = vec![MSG_HELLO];
+    msg.extend_from_slice(MAGIC);
+    msg.push(VERSION);
+    let mut crc = [0u8; 4];
+    BigEndian::write_u32(&mut crc, rom_crc);
+    msg.extend_from_slice(&crc);
+    msg.push(player as u8);
+    msg.push(rollback as u8);
+    msg
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut
+}
+
+/// Hash of the contents of RDRAM (64-bit FNV-1a), used to detect desyncs.
+pub fn rdram_hash(rdram: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in rdram {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const ROM_CRC: u32 = 0x1234_5678;
+
+    fn config(player: usize, delay: usize, rollback: bool) -> NetplayConfig {
+        NetplayConfig {
+            port: 0,
+            peer: String::new(),
+            player,
+            delay,
+            rollback,
+        }
+    }
+
+    fn logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, o!())
+    }
+
+    // Connect two sessions over the loopback interface, with the specified
+    // ROM CRC for the second player.
+    fn connect(delay: usize, rollback: bool, crc2: u32) -> (Result<Netplay>, Result<Netplay>) {
+        let socket1 = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket2 = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr1 = socket1.local_addr().unwrap();
+        let addr2 = socket2.local_addr().unwrap();
+        let player2 = thread::spawn(move || {
+            Netplay::with_socket(socket2, addr1, &config(1, delay, rollback), crc2, logger())
+        });
+        let cfg = config(0, delay, rollback);
+        let np1 = Netplay::with_socket(socket1, addr2, &cfg, ROM_CRC, logger());
+        (np1, player2.join().unwrap())
+    }
+
+    fn session(delay: usize, rollback: bool) -> (Netplay, Netplay) {
+        let (np1, np2) = connect(delay, rollback, ROM_CRC);
+        (np1.unwrap(), np2.unwrap())
+    }
+
+    // Process the packets received by a session until the condition is met.
+    fn poll_until<F: Fn(&Netplay) -> bool>(np: &mut Netplay, cond: F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !cond(np) {
+            assert!(Instant::now() < deadline, "timeout waiting for the peer");
+            np.poll().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn handshake() {
+        let (np1, np2) = session(0, false);
+        assert_eq!((np1.player(), np2.player()), (0, 1));
+        assert!(!np1.rollback() && !np2.rollback());
+
+        // Both sides refuse a peer running a different game.
+        let (np1, np2) = connect(0, false, ROM_CRC + 1);
+        assert!(np1.is_err() && np2.is_err());
+    }
+
+    #[test]
+    fn input_delay() {
+        const DELAY: u32 = 2;
+        let (mut np1, mut np2) = session(DELAY as usize, false);
+        for frame in 0..20 {
+            np1.push_local_input(0x100 + frame).unwrap();
+            np2.push_local_input(0x200 + frame).unwrap();
+            np1.wait_remote(frame).unwrap();
+            np2.wait_remote(frame).unwrap();
+
+            // Inputs are applied after the delay; the first frames have none.
+            let (in1, in2) = match frame.checked_sub(DELAY) {
+                Some(f) => (0x100 + f, 0x200 + f),
+                None => (0, 0),
+            };
+            assert_eq!(np1.local_input(frame), in1);
+            assert_eq!(np2.local_input(frame), in2);
+            assert_eq!(np1.remote_input(frame), Some(in2));
+            assert_eq!(np2.remote_input(frame), Some(in1));
+            np1.advance();
+            np2.advance();
+        }
+        assert_eq!(np1.frame(), 20);
+    }
+
+    #[test]
+    fn rollback_on_late_input() {
+        let (mut np1, mut np2) = session(0, true);
+
+        // Player 1 runs two frames ahead, predicting that player 2 is idle.
+        for frame in 0..2 {
+            np1.push_local_input(1).unwrap();
+            np1.poll().unwrap();
+            assert_eq!(np1.remote_input(frame), None);
+            let pred = np1.predict_remote(frame);
+            assert_eq!(pred, 0);
+            np1.save_prediction(frame, pred, vec![frame as u8]);
+            np1.advance();
+        }
+        assert_eq!(np1.check_predictions(), None);
+
+        // The late inputs arrive: the first one matches the prediction, the
+        // second one does not, so the emulation restarts from frame 1.
+        np2.push_local_input(0).unwrap();
+        np2.push_local_input(0x8000_0000).unwrap();
+        poll_until(&mut np1, |np| np.remote_input(1).is_some());
+        assert_eq!(np1.check_predictions(), Some((1, vec![1])));
+        np1.rewind(1);
+        assert_eq!(np1.frame(), 1);
+        assert_eq!(np1.predict_remote(2), 0x8000_0000);
+
+        // All predictions were resolved.
+        assert_eq!(np1.check_predictions(), None);
+    }
+
+    #[test]
+    fn desync_detection() {
+        let (mut np1, mut np2) = session(0, false);
+
+        // Same state: no desync.
+        assert!(np1.needs_hash() && np2.needs_hash());
+        np1.set_hash(0x1111);
+        np2.set_hash(0x1111);
+        poll_until(&mut np1, |np| {
+            np.remote_hashes.is_empty() && np.final_hashes.is_empty()
+        });
+        poll_until(&mut np2, |np| {
+            np.remote_hashes.is_empty() && np.final_hashes.is_empty()
+        });
+        assert!(!np1.desync() && !np2.desync());
+
+        // At the next check, the states differ.
+        for _ in 0..HASH_INTERVAL {
+            np1.advance();
+            np2.advance();
+        }
+        assert!(np1.needs_hash() && np2.needs_hash());
+        np1.set_hash(0x1111);
+        np2.set_hash(0x2222);
+        poll_until(&mut np1, |np| np.desync());
+        poll_until(&mut np2, |np| np.desync());
+    }
+}
@@ -0,0 +1,422 @@
+use super::gbcart::GbCart;
+use emu::backup::{BackupMem, FlushPolicy};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Size of the Controller Pak memory.
+pub const CONTROLLER_PAK_SIZE: usize = 32 * 1024;
+
+/// PakKind selects the accessory plugged into a controller.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PakKind {
+    None,
+    Controller,
+    Rumble,
+    /// Transfer Pak, with a Game Boy cartridge (ROM and battery save).
+    Transfer {
+        rom: PathBuf,
+        save: PathBuf,
+    },
+}
+
+/// Pak is an accessory plugged into the expansion slot of a controller, and
+/// accessed through the joybus "read/write accessory" commands. Accesses are
+/// always performed in blocks of 32 bytes.
+pub trait Pak {
+    fn name(&self) -> &'static str;
+    fn read(&mut self, addr: u16, buf: &mut [u8]);
+    fn write(&mut self, addr: u16, buf: &[u8]);
+
+    /// Status of the rumble motor, for accessories that have one.
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    /// Called at the end of each frame, to persist the contents of the
+    /// accessory (if any) according to its flush policy.
+    fn end_frame(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// ControllerPak is the memory card accessory: 32 KiB of battery-backed
+/// SRAM, mapped at 0x0000-0x7FFF. Its contents are persisted into a raw
+/// image file (.mpk), the same format used by most emulators.
+pub struct ControllerPak {
+    mem: BackupMem,
+}
+
+impl ControllerPak {
+    /// Open the Controller Pak image at the specified path, which is kept in
+    /// sync according to the flush policy. If the file does not exist, a new
+    /// pak is created, which must be initialized by the game before being
+    /// used.
+    pub fn open(
+        logger: slog::Logger,
+        path: &Path,
+        policy: FlushPolicy,
+    ) -> io::Result<ControllerPak> {
+        let mem = if path.exists() {
+            BackupMem::with_data(logger, Some(path), Self::read_image(path)?, policy)
+        } else {
+            BackupMem::new(logger, Some(path), CONTROLLER_PAK_SIZE, 0, policy)?
+        };
+        Ok(ControllerPak { mem })
+    }
+
+    /// Create a blank pak, which is never persisted.
+    pub fn blank(logger: slog::Logger) -> ControllerPak {
+        let data = vec![0u8; CONTROLLER_PAK_SIZE];
+        ControllerPak {
+            mem: BackupMem::with_data(logger, None, data, FlushPolicy::OnExit),
+        }
+    }
+
+    fn read_image(path: &Path) -> io::Result<Vec<u8>> {
+        let data = fs::read(path)?;
+        if data.len() != CONTROLLER_PAK_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "invalid Controller Pak image {}: size is {} bytes, expected {}",
+                    path.display(),
+                    data.len(),
+                    CONTROLLER_PAK_SIZE
+                ),
+            ));
+        }
+        Ok(data)
+    }
+
+    /// Replace the contents of the pak with a raw image (eg: exported from
+    /// another emulator).
+    pub fn import(&mut self, path: &Path) -> io::Result<()> {
+        let data = Self::read_image(path)?;
+        self.mem.data_mut().copy_from_slice(&data);
+        self.mem.flush()
+    }
+
+    /// Write the contents of the pak as a raw image.
+    pub fn export(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.mem.data())
+    }
+}
+
+impl Pak for ControllerPak {
+    fn name(&self) -> &'static str {
+        "Controller Pak"
+    }
+
+    fn read(&mut self, addr: u16, buf: &mut [u8]) {
+        let addr = addr as usize;
+        let data = self.mem.data();
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = data.get(addr + i).cloned().unwrap_or(0);
+        }
+    }
+
+    fn write(&mut self, addr: u16, buf: &[u8]) {
+        let addr = addr as usize;
+        if addr < CONTROLLER_PAK_SIZE {
+            let len = buf.len().min(CONTROLLER_PAK_SIZE - addr);
+            self.mem.write(addr, &buf[..len]);
+        }
+    }
+
+    fn end_frame(&mut self) -> io::Result<()> {
+        self.mem.end_frame()
+    }
+}
+
+/// RumblePak is the force feedback accessory. It has no storage: the area
+/// 0x8000-0x8FFF is used to identify it, and writes to 0xC000-0xCFFF turn the
+/// motor on or off.
+#[derive(Default)]
+pub struct RumblePak {
+    motor: bool,
+}
+
+impl Pak for RumblePak {
+    fn name(&self) -> &'static str {
+        "Rumble Pak"
+    }
+
+    fn read(&mut self, addr: u16, buf: &mut [u8]) {
+        let val = if addr >= 0x8000 && addr < 0x9000 {
+            0x80
+        } else {
+            0x00
+        };
+        for b in buf.iter_mut() {
+            *b = val;
+        }
+    }
+
+    fn write(&mut self, addr: u16, buf: &[u8]) {
+        if addr >= 0xC000 && addr < 0xD000 {
+            self.motor = buf.last().map_or(false, |b| *b != 0);
+        }
+    }
+
+    fn rumble(&self) -> bool {
+        self.motor
+    }
+}
+
+/// TransferPak gives access to a Game Boy cartridge. The Game Boy address
+/// space is accessed through a 16 KiB window at 0xC000-0xFFFF, whose bank
+/// is selected by writing to 0xA000. The pak must be powered on by writing
+/// 0x84 to 0x8000, and the cartridge access is enabled through 0xB000.
+pub struct TransferPak {
+    cart: GbCart,
+    powered: bool,
+    bank: u16,
+    access: bool,
+    access_changed: bool,
+}
+
+impl TransferPak {
+    pub fn new(cart: GbCart) -> TransferPak {
+        TransferPak {
+            cart,
+            powered: false,
+            bank: 0,
+            access: false,
+            access_changed: false,
+        }
+    }
+
+    fn gb_addr(&self, addr: u16, i: usize) -> u16 {
+        ((addr & 0x3FFF) + self.bank * 0x4000).wrapping_add(i as u16)
+    }
+}
+
+impl Pak for TransferPak {
+    fn name(&self) -> &'static str {
+        "Transfer Pak"
+    }
+
+    fn read(&mut self, addr: u16, buf: &mut [u8]) {
+        let fill = match addr >> 12 {
+            0x8 => {
+                if self.powered {
+                    0x84
+                } else {
+                    0x00
+                }
+            }
+            0xB => {
+                if self.powered {
+                    // Bit 0: cartridge access enabled; bit 2: access mode
+                    // changed since last read; bit 3: reset/inserted;
+                    // bit 7: cartridge inserted.
+                    let mut status = if self.access { 0x89 } else { 0x80 };
+                    if self.access_changed {
+                        status |= 0x04;
+                        self.access_changed = false;
+                    }
+                    status
+                } else {
+                    0x00
+                }
+            }
+            0xC..=0xF => {
+                if self.powered && self.access {
+                    for (i, b) in buf.iter_mut().enumerate() {
+                        *b = self.cart.read(self.gb_addr(addr, i));
+                    }
+                    return;
+                }
+                0x00
+            }
+            _ => 0x00,
+        };
+        for b in buf.iter_mut() {
+            *b = fill;
+        }
+    }
+
+    fn write(&mut self, addr: u16, buf: &[u8]) {
+        let val = buf.last().cloned().unwrap_or(0);
+        match addr >> 12 {
+            0x8 => match val {
+                0x84 => self.powered = true,
+                0xFE => self.powered = false,
+                _ => {}
+            },
+            0xA => {
+                if self.powered {
+                    self.bank = val as u16 & 3;
+                }
+            }
+            0xB => {
+                if self.powered {
+                    self.access = val & 1 != 0;
+                    self.access_changed = true;
+                }
+            }
+            0xC..=0xF => {
+                if self.powered && self.access {
+                    for (i, b) in buf.iter().enumerate() {
+                        let gb_addr = self.gb_addr(addr, i);
+                        self.cart.write(gb_addr, *b);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn end_frame(&mut self) -> io::Result<()> {
+        self.cart.end_frame()
+    }
+}
+
+/// Extract the address of an accessory read/write command, which is sent
+/// with a 5-bit CRC in the lower bits (accesses are aligned to 32 bytes).
+/// The CRC is not checked.
+pub(crate) fn decode_addr(raw: u16) -> u16 {
+    raw & !0x1F
+}
+
+/// Compute the CRC that the controller appends to accessory data blocks
+/// (CRC-8, polynomial 0x85, including one final zero byte).
+pub(crate) fn data_crc(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0;
+    for i in 0..=data.len() {
+        let byte = data.get(i).cloned().unwrap_or(0);
+        for bit in (0..8).rev() {
+            let xor = if crc & 0x80 != 0 { 0x85 } else { 0 };
+            crc = (crc << 1) | ((byte >> bit) & 1);
+            crc ^= xor;
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, o!())
+    }
+
+    fn tmppath(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("r64emu-pak-{}-{}.mpk", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    // Reference implementations of __osContAddressCrc / __osContDataCrc
+    // from libultra, which games use to encode and check accessory accesses.
+    fn libultra_addr_crc(addr: u16) -> u16 {
+        let mut ret = 0u16;
+        for bit in (0..11).rev() {
+            ret <<= 1;
+            if (addr >> 5) & (1 << bit) != 0 {
+                ret ^= if ret & 0x20 != 0 { 0x14 } else { 1 };
+            } else if ret & 0x20 != 0 {
+                ret ^= 0x15;
+            }
+        }
+        for _ in 0..5 {
+            ret <<= 1;
+            if ret & 0x20 != 0 {
+                ret ^= 0x15;
+            }
+        }
+        addr | (ret & 0x1F)
+    }
+
+    fn libultra_data_crc(data: &[u8]) -> u8 {
+        let mut ret = 0u32;
+        for byte in data {
+            for bit in (0..8).rev() {
+                ret <<= 1;
+                if byte & (1 << bit) != 0 {
+                    ret ^= if ret & 0x100 != 0 { 0x84 } else { 1 };
+                } else if ret & 0x100 != 0 {
+                    ret ^= 0x85;
+                }
+            }
+        }
+        for _ in 0..8 {
+            ret <<= 1;
+            if ret & 0x100 != 0 {
+                ret ^= 0x85;
+            }
+        }
+        ret as u8
+    }
+
+    #[test]
+    fn address_crc() {
+        // Addresses used to probe and drive the Rumble Pak.
+        assert_eq!(libultra_addr_crc(0x8000), 0x8001);
+        assert_eq!(libultra_addr_crc(0xC000), 0xC01B);
+
+        for addr in (0..0x10000).step_by(32) {
+            let raw = libultra_addr_crc(addr as u16);
+            assert_eq!(decode_addr(raw), addr as u16);
+        }
+    }
+
+    #[test]
+    fn data_crc_matches_libultra() {
+        let ramp: Vec<u8> = (0..32).collect();
+        for data in &[[0u8; 32], [0xFF; 32], [0x80; 32], [0xFE; 32]] {
+            assert_eq!(data_crc(data), libultra_data_crc(data));
+        }
+        assert_eq!(data_crc(&ramp), libultra_data_crc(&ramp));
+        // Expected by osMotorInit after writing 0x80 to the Rumble Pak.
+        assert_eq!(data_crc(&[0x80; 32]), 0xB8);
+    }
+
+    #[test]
+    fn controller_pak_read_write() {
+        let mut pak = ControllerPak::blank(logger());
+        let data: Vec<u8> = (0..32).map(|i| i * 7 + 1).collect();
+        pak.write(decode_addr(libultra_addr_crc(0x0100)), &data);
+
+        let mut buf = [0u8; 32];
+        pak.read(decode_addr(libultra_addr_crc(0x0100)), &mut buf);
+        assert_eq!(&buf[..], &data[..]);
+        assert_eq!(data_crc(&buf), libultra_data_crc(&data));
+
+        // Neighbouring blocks are untouched.
+        pak.read(0x00E0, &mut buf);
+        assert_eq!(buf, [0u8; 32]);
+        pak.read(0x0120, &mut buf);
+        assert_eq!(buf, [0u8; 32]);
+
+        // The last block is writable; accesses past the end are ignored.
+        pak.write(0x7FE0, &[0x55; 32]);
+        pak.write(0x8000, &[0xAA; 32]);
+        pak.read(0x7FE0, &mut buf);
+        assert_eq!(buf, [0x55; 32]);
+        pak.read(0x8000, &mut buf);
+        assert_eq!(buf, [0u8; 32]);
+    }
+
+    #[test]
+    fn controller_pak_image() {
+        let path = tmppath("image");
+        let mut pak = ControllerPak::open(logger(), &path, FlushPolicy::WriteThrough).unwrap();
+        pak.write(0x0020, &[0x12; 32]);
+        assert_eq!(fs::read(&path).unwrap().len(), CONTROLLER_PAK_SIZE);
+
+        let mut pak = ControllerPak::open(logger(), &path, FlushPolicy::OnExit).unwrap();
+        let mut buf = [0u8; 32];
+        pak.read(0x0020, &mut buf);
+        assert_eq!(buf, [0x12; 32]);
+
+        // Images of the wrong size are rejected.
+        fs::write(&path, &[0u8; 1024]).unwrap();
+        assert!(ControllerPak::open(logger(), &path, FlushPolicy::OnExit).is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -1,6 +1,11 @@
+use super::cartridge::cic_6105_response;
+use super::cartsave::{CartSave, DOMAIN2_BASE, DOMAIN2_END};
+use super::dmalog::{self, DmaDevice};
 use super::mi::{IrqMask, Mi};
-use super::r4300::R4300;
+use super::movie::Movie;
 use super::n64::JOY_NAMES;
+use super::pak::{data_crc, decode_addr, Pak};
+use super::r4300::R4300;
 use super::si::Si;
 use crate::errors::*;
 use bitfield::Bit;
@@ -83,12 +88,28 @@ pub struct Pi {
     logger: slog::Logger,
     cycles: Field<i64>,
     pub(crate) input: InputManager,
+    paks: [Option<Box<dyn Pak>>; 4], // accessory plugged into each controller
+    movie: Option<Movie>,            // movie being recorded or played back
+    deterministic: bool,             // ignore host input, never write to disk
+    script_input: [Option<u32>; 4],  // input injected by scripts
+    netplay_input: Option<[u32; 2]>, // inputs of both players in netplay
 }
 
 impl Pi {
-    pub fn new(logger: slog::Logger, pifrom: &Path, input: InputManager) -> Result<Box<Pi>> {
+    pub fn new(
+        logger: slog::Logger,
+        pifrom: Option<&Path>,
+        input: InputManager,
+    ) -> Result<Box<Pi>> {
+        // Without a PIF ROM dump, the boot process must be emulated
+        // (see BootMode::Hle), so just leave the PIF ROM empty.
         let mut contents = vec![];
-        File::open(pifrom)?.read_to_end(&mut contents)?;
+        match pifrom {
+            Some(pifrom) => {
+                File::open(pifrom)?.read_to_end(&mut contents)?;
+            }
+            None => contents.resize(0x7C0, 0),
+        };
 
         Ok(Box::new(Pi {
             logger,
@@ -96,6 +117,11 @@ impl Pi {
             ram: Mem::default(),
             cycles: Field::new("Pi::cycles", 0),
             input: input,
+            paks: Default::default(),
+            movie: None,
+            deterministic: false,
+            script_input: [None; 4],
+            netplay_input: None,
             dma_ram_addr: Reg32::default(),
             dma_rom_addr: Reg32::default(),
             dma_rd_len: Reg32::default(),
@@ -125,15 +151,27 @@ impl Pi {
             "src(rom)" => raddr.hex(),
             "dst(ram)" => waddr.hex(),
             "len" => len+1));
+        dmalog::record(DmaDevice::Pi, raddr, waddr, len + 1);
 
         let bus = &mut R4300::get_mut().bus;
-        let mut i = 0;
-        while i < len + 1 {
-            let data = bus.read::<u32>(raddr);
-            bus.write::<u32>(waddr, data);
-            raddr = raddr + 4;
-            waddr = waddr + 4;
-            i += 4;
+        if raddr >= DOMAIN2_BASE && raddr < DOMAIN2_END {
+            // Save memory (SRAM / FlashRAM)
+            let mut buf = vec![0u8; (len as usize + 1 + 3) & !3];
+            CartSave::get_mut().dma_read(raddr, &mut buf);
+            for chunk in buf.chunks(4) {
+                bus.write::<u32>(waddr, BigEndian::read_u32(chunk));
+                raddr = raddr + 4;
+                waddr = waddr + 4;
+            }
+        } else {
+            let mut i = 0;
+            while i < len + 1 {
+                let data = bus.read::<u32>(raddr);
+                bus.write::<u32>(waddr, data);
+                raddr = raddr + 4;
+                waddr = waddr + 4;
+                i += 4;
+            }
         }
         self.dma_rom_addr.set(raddr);
         self.dma_ram_addr.set(waddr);
@@ -147,20 +185,29 @@ impl Pi {
             "src(ram)" => raddr.hex(),
             "dst(rom)" => waddr.hex(),
             "len" => val+1));
+        dmalog::record(DmaDevice::Pi, raddr, waddr, val + 1);
 
         let bus = &mut R4300::get_mut().bus;
+        let mut buf = Vec::with_capacity(val as usize + 4);
         let mut i = 0;
         while i < val + 1 {
             let v = bus.read::<u32>(raddr);
-            info!(self.logger, "DMA DATA"; "data" => v.hex());
-
+            buf.extend_from_slice(&v.to_be_bytes());
             raddr = raddr + 4;
-            waddr = waddr + 4;
             i += 4;
         }
-        Mi::get_mut().set_irq_line(IrqMask::PI, true);
 
-        unimplemented!();
+        if waddr >= DOMAIN2_BASE && waddr < DOMAIN2_END {
+            // Save memory (SRAM / FlashRAM)
+            CartSave::get_mut().dma_write(waddr, &buf);
+        } else {
+            warn!(self.logger, "DMA write to read-only cartridge area"; "addr" => waddr.hex());
+        }
+        waddr = waddr + buf.len() as u32;
+
+        self.dma_ram_addr.set(raddr);
+        self.dma_rom_addr.set(waddr);
+        Mi::get_mut().set_irq_line(IrqMask::PI, true);
     }
 
     pub fn begin_frame(&mut self) {
@@ -168,6 +215,99 @@ impl Pi {
     }
     pub fn end_frame(&mut self) {
         self.input.end_frame();
+        if !self.deterministic {
+            for pak in self.paks.iter_mut().flatten() {
+                if let Err(err) = pak.end_frame() {
+                    error!(self.logger, "cannot save accessory"; "pak" => pak.name(), "err" => err.to_string());
+                }
+            }
+        }
+        if let Err(err) = CartSave::get_mut().end_frame() {
+            error!(self.logger, "cannot write cartridge save"; "err" => err.to_string());
+        }
+        if let Some(movie) = self.movie.as_mut() {
+            if let Err(err) = movie.end_frame() {
+                error!(self.logger, "cannot write movie, recording stopped"; "err" => err.to_string());
+                self.movie = None;
+            } else if movie.finished() {
+                info!(self.logger, "movie playback finished"; "samples" => movie.progress().1);
+                self.movie = None;
+            }
+        }
+    }
+
+    /// Start recording or playing back a movie (or stop it). While a movie
+    /// is played back, the state of the controllers that are part of it is
+    /// read from the movie instead of the host input.
+    pub fn set_movie(&mut self, movie: Option<Movie>) {
+        self.movie = movie;
+    }
+
+    pub fn movie(&self) -> Option<&Movie> {
+        self.movie.as_ref()
+    }
+
+    /// In deterministic mode, the host input is ignored (controllers are
+    /// idle unless a movie is played back), and accessories are never
+    /// written to disk, so that the emulation only depends on the ROM and
+    /// the movie. While recording a movie, the host input is still read, as
+    /// it ends up in the movie.
+    pub fn set_deterministic(&mut self, det: bool) {
+        self.deterministic = det;
+    }
+
+    /// Configure the domain 1 (cartridge) timings, in the format of the
+    /// first word of the ROM header, as the PIF ROM does at boot.
+    pub fn set_dom1_timing(&mut self, cfg: u32) {
+        self.dom1_latency.set(cfg & 0xFF);
+        self.dom1_pulse_width.set((cfg >> 8) & 0xFF);
+        self.dom1_page_size.set((cfg >> 16) & 0xF);
+        self.dom1_release.set((cfg >> 20) & 0x3);
+    }
+
+    /// Override the state of a controller (in joybus layout) with input
+    /// injected by a script, until it is reset to None. Injected input
+    /// replaces the host input, and is recorded into movies.
+    pub fn set_script_input(&mut self, ch: usize, value: Option<u32>) {
+        self.script_input[ch] = value;
+    }
+
+    /// Set the state of the first two controllers (in joybus layout) during
+    /// a netplay session, where they are driven by the two players, or None
+    /// when the session ends. Netplay input takes precedence over any other
+    /// source.
+    pub fn set_netplay_input(&mut self, value: Option<[u32; 2]>) {
+        self.netplay_input = value;
+    }
+
+    // Read the state of a controller from the host input, in joybus layout.
+    pub(crate) fn host_input(&self, ch: usize) -> u32 {
+        let mut value: u32 = 0;
+        self.input
+            .device(JOY_NAMES[ch])
+            .unwrap()
+            .visit(|i| match i.value() {
+                InputValue::Digital(val) => {
+                    if val {
+                        value.set_bit(i.custom_id(), true);
+                    }
+                }
+                InputValue::Analog(val) => value |= ((val >> 8) as u8 as u32) << i.custom_id(),
+                _ => unreachable!(),
+            });
+
+        // L+R+Start => Reset (and Start is not reported).
+        if value.bit(21) && value.bit(20) && value.bit(28) {
+            value.set_bit(23, true);
+            value.set_bit(28, false);
+        }
+        value
+    }
+
+    /// Plug an accessory into the specified controller (or remove it).
+    pub fn set_pak(&mut self, ch: usize, pak: Option<Box<dyn Pak>>) {
+        self.paks[ch] = pak;
+        self.input.set_rumble(JOY_NAMES[ch], false);
     }
 
     fn joybus_cmd(
@@ -180,42 +320,83 @@ impl Pi {
             return Err("joybus: 0-len command");
         }
 
+        // Channel 4 is the cartridge EEPROM (if any).
+        if ch == 4 {
+            let mut buf = vec![0u8; out.len()];
+            if CartSave::get_mut().eeprom_cmd(&self.ram[cmd], &mut buf)? {
+                self.ram[out].copy_from_slice(&buf);
+            }
+            return Ok(());
+        }
+
         match self.ram[cmd.start] {
             0 => {
-                // Read controller status
-                if ch == 0 {
+                // Read controller status. Player 2 is connected only
+                // during netplay.
+                if ch == 0 || (ch == 1 && self.netplay_input.is_some()) {
                     self.ram[out.start + 0] = 0x05;
                     self.ram[out.start + 1] = 0x00;
-                    self.ram[out.start + 2] = 0x02;
+                    // Bit 0: accessory present; bit 1: accessory absent
+                    self.ram[out.start + 2] = if self.paks[ch].is_some() { 0x01 } else { 0x02 };
                 }
             }
             1 => {
                 // Read input data
                 if ch < 4 {
-                    let mut value: u32 = 0;
-                    self.input
-                        .device(JOY_NAMES[ch])
-                        .unwrap()
-                        .visit(|i| match i.value() {
-                            InputValue::Digital(val) => {
-                                if val {
-                                    value.set_bit(i.custom_id(), true);
-                                }
-                            }
-                            InputValue::Analog(val) => {
-                                value |= ((val >> 8) as u8 as u32) << i.custom_id()
-                            }
-                            _ => unreachable!(),
-                        });
-
-                    // S+Left+Right => Reset.
-                    if value.bit(21) && value.bit(20) && value.bit(18) {
-                        value.set_bit(23, true);
+                    let netplay = self.netplay_input.filter(|_| ch < 2);
+                    let recording = self.movie.as_ref().map_or(false, |m| m.recording());
+                    let mut value = match (netplay, self.script_input[ch]) {
+                        (Some(inputs), _) => inputs[ch],
+                        (None, Some(value)) => value,
+                        (None, None) if self.deterministic && !recording => 0,
+                        (None, None) => self.host_input(ch),
+                    };
+                    if let Some(movie) = self.movie.as_mut() {
+                        value = movie.poll(ch, value).unwrap_or(value);
                     }
 
                     BigEndian::write_u32(&mut self.ram[out.start..], value);
                 }
             }
+            2 => {
+                // Read from accessory: 2-byte address (with 5-bit CRC),
+                // 32 bytes of data + 1 byte of data CRC.
+                if cmd.len() < 3 || out.len() < 33 || ch >= 4 {
+                    return Err("joybus: invalid accessory read");
+                }
+                let addr = decode_addr(BigEndian::read_u16(&self.ram[cmd.start + 1..]));
+                let mut buf = [0u8; 32];
+                let crc = match self.paks[ch].as_mut() {
+                    Some(pak) => {
+                        pak.read(addr, &mut buf);
+                        data_crc(&buf)
+                    }
+                    // Without an accessory, the CRC is inverted.
+                    None => !data_crc(&buf),
+                };
+                self.ram[out.start..out.start + 32].copy_from_slice(&buf);
+                self.ram[out.start + 32] = crc;
+            }
+            3 => {
+                // Write to accessory: 2-byte address (with 5-bit CRC) and 32
+                // bytes of data; the answer is the data CRC.
+                if cmd.len() < 35 || out.len() < 1 || ch >= 4 {
+                    return Err("joybus: invalid accessory write");
+                }
+                let addr = decode_addr(BigEndian::read_u16(&self.ram[cmd.start + 1..]));
+                let mut buf = [0u8; 32];
+                buf.copy_from_slice(&self.ram[cmd.start + 3..cmd.start + 35]);
+                let crc = data_crc(&buf);
+                self.ram[out.start] = match self.paks[ch].as_mut() {
+                    Some(pak) => {
+                        pak.write(addr, &buf);
+                        let rumble = pak.rumble();
+                        self.input.set_rumble(JOY_NAMES[ch], rumble);
+                        crc
+                    }
+                    None => !crc,
+                };
+            }
             _ => {
                 return Err("invalid command");
             }
@@ -234,11 +415,18 @@ impl Pi {
                 // Special marker: end of joybus
                 return Ok(());
             }
+            if t == 0 {
+                // Skip channel
+                ch += 1;
+                continue;
+            }
             if t < 0x80 {
                 let r = *self.ram.get(idx).ok_or("joybus: premature end of RAM")?;
                 idx += 1;
 
-                if ch >= 1 {
+                // Only the first controller and the EEPROM are connected.
+                let eeprom = ch == 4 && CartSave::get().kind().is_eeprom();
+                if ch >= 1 && !eeprom {
                     self.ram[idx - 1] |= 0x80;
                 }
 
@@ -264,6 +452,22 @@ impl sync::Subsystem for Pi {
         *self.cycles = target_cycles;
 
         let status = self.ram[0x3F];
+        if status & 0x02 != 0 {
+            // CIC-NUS-6105 challenge: 15 bytes at 0x30, replaced by the
+            // response.
+            info!(self.logger, "CIC challenge");
+            let challenge: Vec<u8> = self.ram[0x30..0x3F]
+                .iter()
+                .flat_map(|b| vec![b >> 4, b & 0xF])
+                .collect();
+            let response = cic_6105_response(&challenge);
+            self.ram[0x2E] = 0;
+            self.ram[0x2F] = 0;
+            for i in 0..15 {
+                self.ram[0x30 + i] = (response[i * 2] << 4) | response[i * 2 + 1];
+            }
+            self.ram[0x3F] &= !0x02;
+        }
         if status & 0x20 != 0 {
             info!(self.logger, "unlock boot");
             self.ram[0x3F] |= 0x80;
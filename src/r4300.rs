@@ -1,13 +1,14 @@
-use std::ops::{Deref, DerefMut};
-use mips64;
 use emu::bus::be::{Bus, Device};
+use mips64;
+use std::ops::{Deref, DerefMut};
 
-use super::n64::MAINCPU_NAME;
 use super::ai::Ai;
 use super::cartridge::{Cartridge, CicModel};
+use super::cartsave::CartSave;
 use super::dp::Dp;
 use super::errors::*;
 use super::mi::Mi;
+use super::n64::MAINCPU_NAME;
 use super::pi::Pi;
 use super::ri::Ri;
 use super::si::Si;
@@ -72,9 +73,28 @@ impl R4300 {
         self.bus.map_device(0x0460_0000, Pi::get(), 0)?;
         self.bus.map_device(0x0470_0000, Ri::get(), 2)?;
         self.bus.map_device(0x0480_0000, Si::get(), 0)?;
+        self.bus.map_device(0x0800_0000, CartSave::get(), 0)?;
         self.bus.map_device(0x1000_0000, Cartridge::get(), 0)?;
         self.bus.map_device(0x1800_0000, Cartridge::get(), 1)?;
         self.bus.map_device(0x1FC0_0000, Pi::get(), 1)?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Latency of the memory regions mapped on the bus, used by the accurate
+    /// memory timing. Values are approximate, in CPU cycles.
+    pub fn mem_regions() -> Vec<mips64::MemRegion> {
+        let region = |start, end, latency, word, write| mips64::MemRegion {
+            start,
+            end,
+            latency,
+            word,
+            write,
+        };
+        vec![
+            region(0x0000_0000, 0x03F0_0000, 32, 2, 4),     // RDRAM
+            region(0x03F0_0000, 0x0490_0000, 24, 2, 8),     // RDRAM and RCP registers
+            region(0x0500_0000, 0x1FC0_0000, 140, 8, 8),    // PI bus (cartridge)
+            region(0x1FC0_0000, 0x1FC0_0800, 300, 20, 300), // PIF ROM and RAM
+        ]
+    }
+}
@@ -0,0 +1,409 @@
+//! RAM search (cheat finder).
+//!
+//! The search starts from a snapshot of RDRAM, and is refined by repeatedly
+//! filtering the candidate addresses, comparing the current contents of
+//! memory against either a constant value or the contents at the time of
+//! the previous filter (eg: "decreased by 1" after losing a life). Found
+//! addresses can be added to a watch list, or turned into GameShark cheats
+//! that freeze them to their current value.
+#[cfg(feature = "frontend")]
+use super::cheats::CheatList;
+use super::cheats::{Cheat, CheatCode};
+use super::ri::Ri;
+use emu::bus::be::Device;
+#[cfg(feature = "frontend")]
+use emu::dbg::imgui::*;
+#[cfg(feature = "frontend")]
+use emu::dbg::DebuggerRenderer;
+
+use byteorder::{BigEndian, ByteOrder};
+
+// Maximum number of results shown in the window.
+#[cfg(feature = "frontend")]
+const MAX_ROWS: usize = 200;
+
+/// Size of the values being searched.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SearchWidth {
+    W8,
+    W16,
+    W32,
+}
+
+impl SearchWidth {
+    fn bytes(self) -> usize {
+        match self {
+            SearchWidth::W8 => 1,
+            SearchWidth::W16 => 2,
+            SearchWidth::W32 => 4,
+        }
+    }
+
+    fn read(self, mem: &[u8], addr: u32) -> u32 {
+        let mem = &mem[addr as usize..];
+        match self {
+            SearchWidth::W8 => mem[0] as u32,
+            SearchWidth::W16 => BigEndian::read_u16(mem) as u32,
+            SearchWidth::W32 => BigEndian::read_u32(mem),
+        }
+    }
+
+    fn mask(self) -> u32 {
+        match self {
+            SearchWidth::W8 => 0xFF,
+            SearchWidth::W16 => 0xFFFF,
+            SearchWidth::W32 => 0xFFFF_FFFF,
+        }
+    }
+}
+
+/// Comparison used to filter the candidates.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SearchCmp {
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+    /// The value changed by the operand since the previous filter (with
+    /// wrapping arithmetic, so that decrements can be searched with
+    /// negative operands).
+    ChangedBy,
+}
+
+impl SearchCmp {
+    fn matches(self, width: SearchWidth, old: u32, cur: u32, operand: u32) -> bool {
+        match self {
+            SearchCmp::Equal => cur == operand,
+            SearchCmp::NotEqual => cur != operand,
+            SearchCmp::Greater => cur > operand,
+            SearchCmp::Less => cur < operand,
+            SearchCmp::ChangedBy => cur.wrapping_sub(old) & width.mask() == operand & width.mask(),
+        }
+    }
+}
+
+/// RamSearch holds the state of a search, and of its debugger window.
+pub struct RamSearch {
+    width: SearchWidth,
+    // Memory contents at the time of the previous filter.
+    snapshot: Vec<u8>,
+    candidates: Vec<u32>,
+    #[cfg(feature = "frontend")]
+    watches: Vec<(u32, SearchWidth)>,
+
+    // UI state
+    #[cfg(feature = "frontend")]
+    cmp: SearchCmp,
+    #[cfg(feature = "frontend")]
+    vs_previous: bool,
+    #[cfg(feature = "frontend")]
+    operand: ImString,
+    #[cfg(feature = "frontend")]
+    error: Option<String>,
+}
+
+impl RamSearch {
+    pub fn new() -> RamSearch {
+        RamSearch {
+            width: SearchWidth::W8,
+            snapshot: Vec::new(),
+            candidates: Vec::new(),
+            #[cfg(feature = "frontend")]
+            watches: Vec::new(),
+            #[cfg(feature = "frontend")]
+            cmp: SearchCmp::Equal,
+            #[cfg(feature = "frontend")]
+            vs_previous: true,
+            #[cfg(feature = "frontend")]
+            operand: ImString::with_capacity(16),
+            #[cfg(feature = "frontend")]
+            error: None,
+        }
+    }
+
+    /// Start a new search: every aligned address in RDRAM is a candidate.
+    pub fn start(&mut self, width: SearchWidth) {
+        self.width = width;
+        self.snapshot = Ri::get().rdram.to_vec();
+        let step = width.bytes();
+        self.candidates = (0..self.snapshot.len() - step + 1)
+            .step_by(step)
+            .map(|a| a as u32)
+            .collect();
+    }
+
+    /// Keep only the candidates whose current value matches the comparison.
+    /// If `operand` is None, values are compared against the previous
+    /// snapshot (for `ChangedBy`, this means "unchanged").
+    pub fn filter(&mut self, cmp: SearchCmp, operand: Option<u32>) {
+        let mem = Ri::get().rdram.to_vec();
+        let (width, old) = (self.width, &self.snapshot);
+        self.candidates.retain(|&addr| {
+            let prev = width.read(old, addr);
+            let cur = width.read(&mem, addr);
+            match operand {
+                Some(op) => cmp.matches(width, prev, cur, op),
+                None if cmp == SearchCmp::ChangedBy => cur == prev,
+                None => cmp.matches(width, prev, cur, prev),
+            }
+        });
+        self.snapshot = mem;
+    }
+
+    pub fn candidates(&self) -> &[u32] {
+        &self.candidates
+    }
+
+    /// Create a cheat that freezes the value at the address to its current
+    /// contents.
+    pub fn make_cheat(&self, addr: u32, width: SearchWidth) -> Cheat {
+        let mem = &Ri::get().rdram;
+        let val = width.read(mem, addr);
+        let codes = match width {
+            SearchWidth::W8 => vec![(0x80, addr, val as u16)],
+            SearchWidth::W16 => vec![(0x81, addr, val as u16)],
+            SearchWidth::W32 => vec![
+                (0x81, addr, (val >> 16) as u16),
+                (0x81, addr + 2, val as u16),
+            ],
+        };
+        Cheat {
+            name: format!("RAM search {:08X} = {:X}", addr, val),
+            codes: codes
+                .into_iter()
+                .map(|(kind, addr, value)| CheatCode { kind, addr, value })
+                .collect(),
+            enabled: true,
+        }
+    }
+
+    /// Parse an operand typed by the user (decimal, or hex with 0x prefix;
+    /// negative numbers are accepted for ChangedBy).
+    pub fn parse_operand(text: &str) -> Result<u32, String> {
+        let s = text.trim();
+        let (neg, s) = if s.starts_with('-') {
+            (true, &s[1..])
+        } else {
+            (false, s)
+        };
+        let val = if s.starts_with("0x") || s.starts_with("0X") {
+            u32::from_str_radix(&s[2..], 16)
+        } else {
+            s.parse::<u32>()
+        }
+        .map_err(|_| format!("invalid value: {:?}", text))?;
+        Ok(if neg { val.wrapping_neg() } else { val })
+    }
+
+    #[cfg(feature = "frontend")]
+    pub fn render_debug<'a, 'ui>(
+        &mut self,
+        dr: &DebuggerRenderer<'a, 'ui>,
+        cheats: &mut CheatList,
+        logger: &slog::Logger,
+    ) {
+        let ui = dr.ui();
+        let mut new_cheats = Vec::new();
+        Window::new(im_str!("[N64] RAM search"))
+            .size([380.0, 480.0], Condition::FirstUseEver)
+            .build(ui, || {
+                let mut width = self.width;
+                ui.radio_button(im_str!("8-bit"), &mut width, SearchWidth::W8);
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("16-bit"), &mut width, SearchWidth::W16);
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("32-bit"), &mut width, SearchWidth::W32);
+                if ui.button(im_str!("New search"), [0.0, 0.0]) || width != self.width {
+                    self.start(width);
+                    self.error = None;
+                }
+                ui.separator();
+
+                ui.radio_button(im_str!("=="), &mut self.cmp, SearchCmp::Equal);
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("!="), &mut self.cmp, SearchCmp::NotEqual);
+                ui.same_line(0.0);
+                ui.radio_button(im_str!(">"), &mut self.cmp, SearchCmp::Greater);
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("<"), &mut self.cmp, SearchCmp::Less);
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("Changed by"), &mut self.cmp, SearchCmp::ChangedBy);
+                ui.checkbox(im_str!("Compare to previous"), &mut self.vs_previous);
+                if !self.vs_previous {
+                    ui.same_line(0.0);
+                    ui.input_text(im_str!("##ramsearch#operand"), &mut self.operand)
+                        .build();
+                }
+                if ui.button(im_str!("Filter"), [0.0, 0.0]) {
+                    if self.snapshot.is_empty() {
+                        self.start(self.width);
+                    }
+                    let operand = if self.vs_previous {
+                        Ok(None)
+                    } else {
+                        Self::parse_operand(self.operand.to_str()).map(Some)
+                    };
+                    match operand {
+                        Ok(op) => {
+                            self.filter(self.cmp, op);
+                            self.error = None;
+                        }
+                        Err(err) => self.error = Some(err),
+                    }
+                }
+                if let Some(err) = self.error.as_ref() {
+                    ui.text_colored([1.0, 0.3, 0.3, 1.0], err);
+                }
+                ui.separator();
+
+                ui.text(format!("{} candidates", self.candidates.len()));
+                let mem: &[u8] = &Ri::get().rdram;
+                let width = self.width;
+                ChildWindow::new(im_str!("##ramsearch#results"))
+                    .size([0.0, 200.0])
+                    .always_vertical_scrollbar(true)
+                    .build(ui, || {
+                        for &addr in self.candidates.iter().take(MAX_ROWS) {
+                            ui.text(format!(
+                                "{:08X}: {:X} (was {:X})",
+                                addr,
+                                width.read(mem, addr),
+                                width.read(&self.snapshot, addr)
+                            ));
+                            ui.same_line(240.0);
+                            if ui.small_button(&im_str!("Watch##w{:x}", addr)) {
+                                self.watches.push((addr, width));
+                            }
+                            ui.same_line(0.0);
+                            if ui.small_button(&im_str!("Cheat##c{:x}", addr)) {
+                                new_cheats.push((addr, width));
+                            }
+                        }
+                    });
+                ui.separator();
+
+                ui.text("Watch list:");
+                let mut remove = None;
+                for (idx, &(addr, width)) in self.watches.iter().enumerate() {
+                    ui.text(format!("{:08X}: {:X}", addr, width.read(mem, addr)));
+                    ui.same_line(240.0);
+                    if ui.small_button(&im_str!("Cheat##wc{}", idx)) {
+                        new_cheats.push((addr, width));
+                    }
+                    ui.same_line(0.0);
+                    if ui.small_button(&im_str!("Remove##wr{}", idx)) {
+                        remove = Some(idx);
+                    }
+                }
+                if let Some(idx) = remove {
+                    self.watches.remove(idx);
+                }
+            });
+
+        if !new_cheats.is_empty() {
+            for (addr, width) in new_cheats {
+                cheats.cheats.push(self.make_cheat(addr, width));
+            }
+            if let Err(err) = cheats.save() {
+                error!(logger, "cannot save cheats"; "err" => err.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ri::RdramSize;
+    use super::*;
+
+    fn setup() -> RamSearch {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        Ri::new(logger, RdramSize::Mb4).register();
+        RamSearch::new()
+    }
+
+    fn poke(addr: usize, data: &[u8]) {
+        Ri::get_mut().rdram[addr..addr + data.len()].copy_from_slice(data);
+    }
+
+    #[test]
+    fn comparisons() {
+        use self::SearchCmp::*;
+        use self::SearchWidth::*;
+        assert!(Equal.matches(W8, 0, 5, 5));
+        assert!(!Equal.matches(W8, 5, 4, 5));
+        assert!(NotEqual.matches(W8, 5, 4, 5));
+        assert!(Greater.matches(W16, 0, 0x100, 0xFF));
+        assert!(!Greater.matches(W16, 0, 0xFF, 0xFF));
+        assert!(Less.matches(W32, 0, 0xFF, 0x100));
+
+        // Changes are computed with wrapping arithmetic, within the width.
+        assert!(ChangedBy.matches(W8, 3, 5, 2));
+        assert!(ChangedBy.matches(W8, 3, 2, (-1i32) as u32));
+        assert!(ChangedBy.matches(W8, 0x00, 0xFF, (-1i32) as u32));
+        assert!(ChangedBy.matches(W16, 0x0000, 0xFFFE, (-2i32) as u32));
+        assert!(!ChangedBy.matches(W16, 0x0000, 0x00FE, (-2i32) as u32));
+        assert!(ChangedBy.matches(W32, 10, 7, (-3i32) as u32));
+    }
+
+    #[test]
+    fn parse_operand() {
+        assert_eq!(RamSearch::parse_operand("42"), Ok(42));
+        assert_eq!(RamSearch::parse_operand(" 0x1F "), Ok(0x1F));
+        assert_eq!(RamSearch::parse_operand("0XfF"), Ok(0xFF));
+        assert_eq!(RamSearch::parse_operand("-1"), Ok(0xFFFF_FFFF));
+        assert_eq!(RamSearch::parse_operand("-0x10"), Ok(0xFFFF_FFF0));
+        assert!(RamSearch::parse_operand("").is_err());
+        assert!(RamSearch::parse_operand("abc").is_err());
+        assert!(RamSearch::parse_operand("0x").is_err());
+        assert!(RamSearch::parse_operand("4294967296").is_err());
+    }
+
+    #[test]
+    fn filter() {
+        let mut rs = setup();
+        poke(0x1000, &[3, 3, 3, 3]);
+        rs.start(SearchWidth::W8);
+        assert_eq!(rs.candidates().len(), RdramSize::Mb4.bytes());
+
+        // Lose a life at 0x1001 and 0x1003; then 0x1003 goes back up.
+        rs.filter(SearchCmp::Equal, Some(3));
+        assert_eq!(rs.candidates(), &[0x1000, 0x1001, 0x1002, 0x1003]);
+        poke(0x1001, &[2]);
+        poke(0x1003, &[2]);
+        rs.filter(SearchCmp::ChangedBy, Some((-1i32) as u32));
+        assert_eq!(rs.candidates(), &[0x1001, 0x1003]);
+        poke(0x1003, &[3]);
+        rs.filter(SearchCmp::ChangedBy, None);
+        assert_eq!(rs.candidates(), &[0x1001]);
+
+        // Comparison against the previous snapshot.
+        poke(0x1001, &[9]);
+        rs.filter(SearchCmp::Greater, None);
+        assert_eq!(rs.candidates(), &[0x1001]);
+        rs.filter(SearchCmp::NotEqual, None);
+        assert!(rs.candidates().is_empty());
+    }
+
+    #[test]
+    fn filter_wide() {
+        let mut rs = setup();
+        poke(0x2000, &[0x12, 0x34, 0x56, 0x78]);
+        rs.start(SearchWidth::W16);
+        rs.filter(SearchCmp::Equal, Some(0x5678));
+        assert_eq!(rs.candidates(), &[0x2002]);
+
+        rs.start(SearchWidth::W32);
+        assert_eq!(rs.candidates().len(), RdramSize::Mb4.bytes() / 4);
+        rs.filter(SearchCmp::Equal, Some(0x1234_5678));
+        assert_eq!(rs.candidates(), &[0x2000]);
+
+        let cheat = rs.make_cheat(0x2000, SearchWidth::W32);
+        let codes: Vec<_> = cheat
+            .codes
+            .iter()
+            .map(|c| (c.kind, c.addr, c.value))
+            .collect();
+        assert_eq!(codes, vec![(0x81, 0x2000, 0x1234), (0x81, 0x2002, 0x5678)]);
+    }
+}
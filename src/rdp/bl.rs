@@ -1,7 +1,7 @@
 // Blender
 
 // TODO:
-//   * alpha compare
+//   * coverage (antialiasing)
 
 extern crate bit_field;
 extern crate emu;
@@ -44,6 +44,7 @@ pub(crate) struct Blender {
     framebuffer: MultiColor,
     reg_blend: MultiColor,
     reg_fog: MultiColor,
+    force_blend: bool,
 
     zero: MultiColor, // 0x00
     ff: MultiColor,   // 0xFF
@@ -59,22 +60,54 @@ impl Blender {
         }
     }
 
+    #[inline(always)]
+    fn blend_cycle(&self, cyc: usize) -> MultiColor {
+        let (p, m, a, b) = self.cycles[cyc].fetch();
+        let a = a.replicate_alpha() >> 3;
+        let b = (b.replicate_alpha() >> 3) + MultiColor::splat(1);
+
+        (p * a + m * b) / (a + b)
+    }
+
+    // Last blender cycle: without force blend, the blending equation is
+    // only used on edges (partial coverage); coverage is not emulated, so
+    // all pixels are treated as fully covered and P is passed through.
+    #[inline(always)]
+    fn blend_last_cycle(&self, cyc: usize) -> MultiColor {
+        if self.force_blend {
+            self.blend_cycle(cyc)
+        } else {
+            unsafe { *self.cycles[cyc].p }
+        }
+    }
+
     #[inline(always)]
     pub(crate) fn blend_1cycle(
         &mut self,
         combined: MultiColor,
-        _shade: MultiColor,
+        shade: MultiColor,
         fb: MultiColor,
     ) -> MultiColor {
         self.combined = combined;
         self.inv_combined = combined.map_alpha(|a| 0xFF - a);
+        self.shade = shade;
         self.framebuffer = fb;
+        self.blend_last_cycle(0)
+    }
 
-        let (p, m, a, b) = self.cycles[0].fetch();
-        let a = a.replicate_alpha() >> 3;
-        let b = (b.replicate_alpha() >> 3) + MultiColor::splat(1);
-
-        (p * a + m * b) / (a + b)
+    #[inline(always)]
+    pub(crate) fn blend_2cycle(
+        &mut self,
+        combined: MultiColor,
+        shade: MultiColor,
+        fb: MultiColor,
+    ) -> MultiColor {
+        self.combined = combined;
+        self.inv_combined = combined.map_alpha(|a| 0xFF - a);
+        self.shade = shade;
+        self.framebuffer = fb;
+        self.partial_blended = self.blend_cycle(0);
+        self.blend_last_cycle(1)
     }
 
     pub(crate) unsafe fn setup_cycle_pm(&self, cyc: usize, p_or_m: u32) -> *const MultiColor {
@@ -126,6 +159,8 @@ impl Blender {
         let a = modes.get_bits(20..22) as u32;
         let b = modes.get_bits(16..18) as u32;
         self.cycles[1] = unsafe { self.setup_cycle(1, (p, m, a, b)) };
+
+        self.force_blend = modes.get_bit(14);
     }
 
     pub(crate) fn set_fog_color(&mut self, c: Color<Rgba8888>) {
@@ -1,7 +1,6 @@
 // Color combiner
 
 // TODO:
-//   * 2-cycle mode
 //   * chroma key
 //   * coverage alpha
 //   * alpha dithering
@@ -142,6 +141,13 @@ impl Combiner {
         return c;
     }
 
+    #[inline(always)]
+    pub(crate) fn combine_2cycle(&mut self, shade: MultiColor) -> MultiColor {
+        self.shade = shade;
+        self.combined = self.combine_cycle(0);
+        self.combine_cycle(1)
+    }
+
     unsafe fn setup_cycle_basic(&self, v: u32) -> *const MultiColor {
         match v {
             0 => &self.combined,
@@ -253,6 +259,9 @@ impl Combiner {
     pub(crate) fn set_env(&mut self, c: Color<Rgba8888>) {
         self.env = MultiColor::from_color(c);
     }
+    pub(crate) fn set_prim_lod_frac(&mut self, frac: u8) {
+        self.prim_lod_fraction = MultiColor::splat(frac as u16);
+    }
 
     fn repr_comb_ptr(&self, ptr: *const MultiColor) -> String {
         if ptr == &self.combined {
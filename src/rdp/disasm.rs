@@ -0,0 +1,304 @@
+// Disassembler of RDP commands, used by the debugger
+
+extern crate bit_field;
+use self::bit_field::BitField;
+
+// Name of a command, as in the RDP documentation.
+fn cmd_name(cmd: u64) -> &'static str {
+    match cmd.get_bits(56..62) {
+        0x00 => "No_Op",
+        0x08 => "Fill_Triangle",
+        0x09 => "Fill_ZBuffer_Triangle",
+        0x0A => "Texture_Triangle",
+        0x0B => "Texture_ZBuffer_Triangle",
+        0x0C => "Shade_Triangle",
+        0x0D => "Shade_ZBuffer_Triangle",
+        0x0E => "Shade_Texture_Triangle",
+        0x0F => "Shade_Texture_ZBuffer_Triangle",
+        0x24 => "Texture_Rectangle",
+        0x25 => "Texture_Rectangle_Flip",
+        0x26 => "Sync_Load",
+        0x27 => "Sync_Pipe",
+        0x28 => "Sync_Tile",
+        0x29 => "Sync_Full",
+        0x2A => "Set_Key_GB",
+        0x2B => "Set_Key_R",
+        0x2C => "Set_Convert",
+        0x2D => "Set_Scissor",
+        0x2E => "Set_Prim_Depth",
+        0x2F => "Set_Other_Modes",
+        0x30 => "Load_TLUT",
+        0x32 => "Set_Tile_Size",
+        0x33 => "Load_Block",
+        0x34 => "Load_Tile",
+        0x35 => "Set_Tile",
+        0x36 => "Fill_Rectangle",
+        0x37 => "Set_Fill_Color",
+        0x38 => "Set_Fog_Color",
+        0x39 => "Set_Blend_Color",
+        0x3A => "Set_Prim_Color",
+        0x3B => "Set_Env_Color",
+        0x3C => "Set_Combine",
+        0x3D => "Set_Texture_Image",
+        0x3E => "Set_Z_Image",
+        0x3F => "Set_Color_Image",
+        _ => "Unknown",
+    }
+}
+
+const CC_RGB_A: [&str; 8] = [
+    "COMBINED", "TEXEL0", "TEXEL1", "PRIM", "SHADE", "ENV", "1", "NOISE",
+];
+const CC_RGB_B: [&str; 8] = [
+    "COMBINED",
+    "TEXEL0",
+    "TEXEL1",
+    "PRIM",
+    "SHADE",
+    "ENV",
+    "KEY_CENTER",
+    "K4",
+];
+const CC_RGB_C: [&str; 16] = [
+    "COMBINED",
+    "TEXEL0",
+    "TEXEL1",
+    "PRIM",
+    "SHADE",
+    "ENV",
+    "KEY_SCALE",
+    "COMBINED_A",
+    "TEXEL0_A",
+    "TEXEL1_A",
+    "PRIM_A",
+    "SHADE_A",
+    "ENV_A",
+    "LOD_FRAC",
+    "PRIM_LOD_FRAC",
+    "K5",
+];
+const CC_RGB_D: [&str; 8] = [
+    "COMBINED", "TEXEL0", "TEXEL1", "PRIM", "SHADE", "ENV", "1", "0",
+];
+const CC_ALPHA_ABD: [&str; 8] = [
+    "COMBINED", "TEXEL0", "TEXEL1", "PRIM", "SHADE", "ENV", "1", "0",
+];
+const CC_ALPHA_C: [&str; 8] = [
+    "LOD_FRAC",
+    "TEXEL0",
+    "TEXEL1",
+    "PRIM",
+    "SHADE",
+    "ENV",
+    "PRIM_LOD_FRAC",
+    "0",
+];
+
+const FORMATS: [&str; 8] = ["RGBA", "YUV", "CI", "IA", "I", "?5", "?6", "?7"];
+
+fn cc_input(table: &[&'static str], idx: u64) -> &'static str {
+    table.get(idx as usize).cloned().unwrap_or("0")
+}
+
+fn fmt_combine(cmd: u64) -> String {
+    let rgb = |a, b, c, d| {
+        format!(
+            "({} - {}) * {} + {}",
+            cc_input(&CC_RGB_A, cmd.get_bits(a)),
+            cc_input(&CC_RGB_B, cmd.get_bits(b)),
+            cc_input(&CC_RGB_C, cmd.get_bits(c)),
+            cc_input(&CC_RGB_D, cmd.get_bits(d)),
+        )
+    };
+    let alpha = |a, b, c, d| {
+        format!(
+            "({} - {}) * {} + {}",
+            cc_input(&CC_ALPHA_ABD, cmd.get_bits(a)),
+            cc_input(&CC_ALPHA_ABD, cmd.get_bits(b)),
+            cc_input(&CC_ALPHA_C, cmd.get_bits(c)),
+            cc_input(&CC_ALPHA_ABD, cmd.get_bits(d)),
+        )
+    };
+    format!(
+        "rgb0={} alpha0={} rgb1={} alpha1={}",
+        rgb(52..56, 28..32, 47..52, 15..18),
+        alpha(44..47, 12..15, 41..44, 9..12),
+        rgb(37..41, 24..28, 32..37, 6..9),
+        alpha(21..24, 3..6, 18..21, 0..3),
+    )
+}
+
+fn fmt_other_modes(cmd: u64) -> String {
+    let cycle = ["1CYCLE", "2CYCLE", "COPY", "FILL"][cmd.get_bits(52..54) as usize];
+    let mut flags = Vec::new();
+    for &(bit, name) in &[
+        (51, "persp"),
+        (50, "detail"),
+        (49, "sharpen"),
+        (48, "lod"),
+        (47, "tlut"),
+        (46, "tlut_ia16"),
+        (45, "bilerp"),
+        (14, "force_blend"),
+        (13, "alpha_cvg_sel"),
+        (12, "cvg_x_alpha"),
+        (7, "color_on_cvg"),
+        (6, "image_read"),
+        (5, "z_update"),
+        (4, "z_compare"),
+        (3, "antialias"),
+        (2, "z_source_prim"),
+        (1, "dither_alpha"),
+        (0, "alpha_compare"),
+    ] {
+        if cmd.get_bit(bit) {
+            flags.push(name);
+        }
+    }
+    format!(
+        "{} z_mode={} blender={:04X} [{}]",
+        cycle,
+        cmd.get_bits(10..12),
+        cmd.get_bits(16..32),
+        flags.join(" ")
+    )
+}
+
+// Format a rectangle with 10.2 coordinates.
+fn fmt_rect(x0: u64, y0: u64, x1: u64, y1: u64) -> String {
+    let f = |v: u64| v as f32 / 4.0;
+    format!("({}, {}) - ({}, {})", f(x0), f(y0), f(x1), f(y1))
+}
+
+// Format an s15.16 fixed point value.
+fn fmt_fx(v: u64) -> String {
+    format!("{:.3}", v as u32 as i32 as f32 / 65536.0)
+}
+
+/// Disassemble a command (all its words) into readable text.
+pub fn disasm(words: &[u64]) -> String {
+    let cmd = words[0];
+    let name = cmd_name(cmd);
+    let args = match cmd.get_bits(56..62) {
+        0x08..=0x0F if words.len() >= 4 => {
+            let y = |v: u64| (((v as u32) << 18) as i32 >> 18) as f32 / 4.0;
+            format!(
+                "{} tile={} yh={} ym={} yl={} xh={} xm={} xl={} dxhdy={} dxmdy={} dxldy={}",
+                if cmd.get_bit(55) { "left" } else { "right" },
+                cmd.get_bits(48..51),
+                y(cmd.get_bits(0..14)),
+                y(cmd.get_bits(16..30)),
+                y(cmd.get_bits(32..46)),
+                fmt_fx(words[2] >> 32),
+                fmt_fx(words[3] >> 32),
+                fmt_fx(words[1] >> 32),
+                fmt_fx(words[2]),
+                fmt_fx(words[3]),
+                fmt_fx(words[1]),
+            )
+        }
+        0x24 | 0x25 if words.len() >= 2 => {
+            let st = |v: u64| v as u16 as i16 as f32;
+            format!(
+                "tile={} {} st=({}, {}) dsdx={} dtdy={}",
+                cmd.get_bits(24..27),
+                fmt_rect(
+                    cmd.get_bits(12..24),
+                    cmd.get_bits(0..12),
+                    cmd.get_bits(44..56),
+                    cmd.get_bits(32..44)
+                ),
+                st(words[1].get_bits(48..64)) / 32.0,
+                st(words[1].get_bits(32..48)) / 32.0,
+                st(words[1].get_bits(16..32)) / 1024.0,
+                st(words[1].get_bits(0..16)) / 1024.0,
+            )
+        }
+        0x2D => format!(
+            "{}{}",
+            fmt_rect(
+                cmd.get_bits(44..56),
+                cmd.get_bits(32..44),
+                cmd.get_bits(12..24),
+                cmd.get_bits(0..12)
+            ),
+            if cmd.get_bit(25) {
+                if cmd.get_bit(24) {
+                    " interlace=odd"
+                } else {
+                    " interlace=even"
+                }
+            } else {
+                ""
+            }
+        ),
+        0x2E => format!(
+            "z={:04X} dz={:04X}",
+            cmd.get_bits(16..32),
+            cmd.get_bits(0..16)
+        ),
+        0x2F => fmt_other_modes(cmd),
+        0x30 | 0x32 | 0x34 => format!(
+            "tile={} {}",
+            cmd.get_bits(24..27),
+            fmt_rect(
+                cmd.get_bits(44..56),
+                cmd.get_bits(32..44),
+                cmd.get_bits(12..24),
+                cmd.get_bits(0..12)
+            )
+        ),
+        0x33 => format!(
+            "tile={} sl={} tl={} sh={} dxt={:03X}",
+            cmd.get_bits(24..27),
+            cmd.get_bits(44..56),
+            cmd.get_bits(32..44),
+            cmd.get_bits(12..24),
+            cmd.get_bits(0..12)
+        ),
+        0x35 => format!(
+            "tile={} fmt={} bpp={} line={} tmem={:03X} pal={} \
+             t=[cm:{} mask:{} shift:{}] s=[cm:{} mask:{} shift:{}]",
+            cmd.get_bits(24..27),
+            FORMATS[cmd.get_bits(53..56) as usize],
+            4 << cmd.get_bits(51..53),
+            cmd.get_bits(41..50),
+            cmd.get_bits(32..41) * 8,
+            cmd.get_bits(20..24),
+            cmd.get_bits(18..20),
+            cmd.get_bits(14..18),
+            cmd.get_bits(10..14),
+            cmd.get_bits(8..10),
+            cmd.get_bits(4..8),
+            cmd.get_bits(0..4),
+        ),
+        0x36 => fmt_rect(
+            cmd.get_bits(12..24),
+            cmd.get_bits(0..12),
+            cmd.get_bits(44..56),
+            cmd.get_bits(32..44),
+        ),
+        0x37..=0x39 | 0x3B => format!("{:08X}", cmd as u32),
+        0x3A => format!(
+            "{:08X} min_level={} lod_frac={}",
+            cmd as u32,
+            cmd.get_bits(40..45),
+            cmd.get_bits(32..40)
+        ),
+        0x3C => fmt_combine(cmd),
+        0x3D | 0x3F => format!(
+            "fmt={} bpp={} width={} addr={:06X}",
+            FORMATS[cmd.get_bits(53..56) as usize],
+            4 << cmd.get_bits(51..53),
+            cmd.get_bits(32..42) + 1,
+            cmd.get_bits(0..26)
+        ),
+        0x3E => format!("addr={:06X}", cmd.get_bits(0..26)),
+        _ => String::new(),
+    };
+    if args.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{} {}", name, args)
+    }
+}
@@ -107,9 +107,16 @@ impl DpColorFormat {
 
 mod bl;
 mod cc;
+#[cfg(feature = "frontend")]
+mod disasm;
+#[cfg(feature = "frontend")]
+mod opengl;
 mod pipeline;
-mod raster;
 mod rdp;
+mod soft;
+mod tmem;
 
+#[cfg(feature = "frontend")]
+pub use self::disasm::disasm;
 pub use self::pipeline::PixelPipeline;
-pub use self::rdp::Rdp;
+pub use self::rdp::{cmd_len, Rdp, RenderBackend};
@@ -0,0 +1,1171 @@
+// OpenGL renderer
+//
+// Alternative to the software rasterizer (see soft.rs), which draws
+// primitives with the GPU of the host. Primitives are queued like in the
+// software renderer and drawn in batches; for each run of primitives drawn
+// into the same color image, the image (and the Z buffer, if used) is
+// uploaded from RDRAM into an offscreen render target, the primitives are
+// drawn, and the result is read back into RDRAM, so that the CPU, the VI and
+// texture loads keep seeing the framebuffer in memory.
+//
+// Images can be drawn at a higher internal resolution (2x, 4x). They are kept
+// on the GPU between batches, and only loaded again (losing the additional
+// resolution) if RDRAM does not contain what was last written back; RDRAM
+// always holds the image downscaled to native resolution. Images modified
+// outside of the renderer, typically by the CPU after reading them back for
+// framebuffer effects, fall back to native resolution. The VI displays the
+// upscaled image in place of the one in RDRAM, as long as they match.
+//
+// Triangles are converted into GL triangles covering their edges, with their
+// attributes evaluated at the vertices (they are planar, so the GPU
+// interpolates them exactly). The color combiner and the blender are
+// translated into fragment shaders, generated (and cached) for each mode;
+// tiles are decoded from TMEM into textures.
+//
+// TODO:
+//   * blender modes reading memory in the first cycle (memory reads as 0)
+//   * blender modes with the memory color in P (only M is blended by GL)
+//   * coverage alpha is not updated by blended primitives
+//   * dithering, LOD and mipmapping
+
+extern crate bit_field;
+extern crate emu;
+
+use self::bit_field::BitField;
+use super::rdp::RenderState;
+use super::soft::{Attr, Primitive, Rdram, Triangle};
+use super::tmem;
+use super::CycleMode;
+use emu::hw::gl;
+use emu::hw::gl::types::*;
+use emu::hw::{self, RenderTarget, ShaderProgram};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Maximum number of primitives queued before rendering them.
+const MAX_BATCH: usize = 4096;
+
+// Maximum number of color images kept on the GPU.
+const MAX_IMAGES: usize = 16;
+
+// Bits of the other modes which affect the generated fragment shaders: cycle
+// type, blender, force blend, alpha compare.
+const SHADER_MODES: u64 = 0x0030_0000_FFFF_4003;
+
+// Vertex attributes: position (x, y in pixels), depth (0.0-1.0), shade
+// (RGBA, 0-255), texture coordinates (S, T, W as s15.16 numbers).
+const ATTR_POS: u32 = 0;
+const ATTR_SHADE: u32 = 1;
+const ATTR_STW: u32 = 2;
+const VERTEX_SIZE: usize = 10;
+
+// All attributes are interpolated linearly in screen space, as the RDP does;
+// perspective correction is performed on texture coordinates by dividing by
+// W in the fragment shader.
+const VERTEX_SHADER: &str = "
+    #version 150
+    uniform vec2 u_size;
+    in vec3 a_pos;
+    in vec4 a_shade;
+    in vec3 a_stw;
+    noperspective out vec4 v_shade;
+    noperspective out vec3 v_stw;
+    noperspective out float v_z;
+    void main() {
+        gl_Position = vec4(a_pos.xy / u_size * 2.0 - 1.0, 0.0, 1.0);
+        v_shade = a_shade / 255.0;
+        v_stw = a_stw;
+        v_z = a_pos.z;
+    }
+";
+
+const FRAGMENT_HEADER: &str = "
+    #version 150
+    uniform sampler2D u_tex0;
+    uniform sampler2D u_tex1;
+    uniform vec4 u_tile0;  // xy: scale (shift), zw: origin (in texels)
+    uniform vec4 u_tile1;
+    uniform vec4 u_tex_size;  // xy: texture 0, zw: texture 1
+    uniform float u_tex_bias;
+    uniform bool u_persp;
+    uniform vec4 u_prim;
+    uniform vec4 u_env;
+    uniform vec4 u_blend;
+    uniform vec4 u_fog;
+    uniform vec4 u_fill0;
+    uniform vec4 u_fill1;
+    uniform float u_prim_lod_frac;
+    uniform float u_alpha_ref;  // 0-255 (0: disabled)
+    uniform float u_scale;  // internal resolution multiplier
+    noperspective in vec4 v_shade;
+    noperspective in vec3 v_stw;
+    noperspective in float v_z;
+    out vec4 o_color;
+
+    vec4 texel(sampler2D tex, vec4 tile, vec2 size, vec2 st) {
+        return texture(tex, (st * tile.xy / 32.0 - tile.zw + u_tex_bias) / size);
+    }
+";
+
+// Fragment shader used to load the Z buffer into the depth buffer.
+const DEPTH_VERTEX_SHADER: &str = "
+    #version 150
+    in vec3 a_pos;
+    out vec2 v_uv;
+    void main() {
+        gl_Position = vec4(a_pos.xy * 2.0 - 1.0, 0.0, 1.0);
+        v_uv = a_pos.xy;
+    }
+";
+
+const DEPTH_FRAGMENT_SHADER: &str = "
+    #version 150
+    uniform sampler2D u_depth;
+    in vec2 v_uv;
+    out vec4 o_color;
+    void main() {
+        gl_FragDepth = texture(u_depth, v_uv).r;
+        o_color = vec4(0.0);
+    }
+";
+
+fn fx(v: i32) -> f32 {
+    v as f32 / 65536.0
+}
+
+fn rgba(c: u32) -> [f32; 4] {
+    [
+        (c >> 24) as f32 / 255.0,
+        (c >> 16 & 0xFF) as f32 / 255.0,
+        (c >> 8 & 0xFF) as f32 / 255.0,
+        (c & 0xFF) as f32 / 255.0,
+    ]
+}
+
+fn rgba16(c: u16) -> [f32; 4] {
+    let ch = |v: u16| ((v & 0x1F) << 3 | (v & 0x1F) >> 2) as f32 / 255.0;
+    [ch(c >> 11), ch(c >> 6), ch(c >> 1), (c & 1) as f32]
+}
+
+// Combiner inputs for the color and alpha channels. In the first cycle,
+// "combined" is not defined (it reads as zero).
+fn cc_basic(sel: u64) -> &'static str {
+    match sel {
+        0 => "combined",
+        1 => "tex0",
+        2 => "tex1",
+        3 => "u_prim",
+        4 => "shade",
+        _ => "u_env",
+    }
+}
+
+fn cc_rgb(slot: usize, sel: u64) -> String {
+    let basic = |sel| format!("{}.rgb", cc_basic(sel));
+    match (slot, sel) {
+        (_, 0..=5) => basic(sel),
+        (0, 6) | (3, 6) => "vec3(1.0)".into(),
+        (2, 7) => "vec3(combined.a)".into(),
+        (2, 8..=12) => format!("vec3({}.a)", cc_basic(sel - 7)),
+        (2, 13) => "vec3(1.0)".into(), // LOD is not emulated
+        (2, 14) => "vec3(u_prim_lod_frac)".into(),
+        _ => "vec3(0.0)".into(),
+    }
+}
+
+fn cc_alpha(slot: usize, sel: u64) -> String {
+    match (slot, sel) {
+        (2, 0) => "1.0".into(),
+        (2, 6) => "u_prim_lod_frac".into(),
+        (2, 1..=5) | (_, 0..=5) => format!("{}.a", cc_basic(sel)),
+        (2, _) => "0.0".into(),
+        (_, 6) => "1.0".into(),
+        _ => "0.0".into(),
+    }
+}
+
+// Source of a combiner cycle: (a-b)*c+d, both for color and alpha.
+fn cc_cycle(mode: u64, cycle: usize) -> String {
+    let (rgb, alpha) = if cycle == 0 {
+        (
+            [52..56, 28..32, 47..52, 15..18],
+            [44..47, 12..15, 41..44, 9..12],
+        )
+    } else {
+        ([37..41, 24..28, 32..37, 6..9], [21..24, 3..6, 18..21, 0..3])
+    };
+    let r: Vec<_> = rgb
+        .iter()
+        .enumerate()
+        .map(|(i, b)| cc_rgb(i, mode.get_bits(b.clone())))
+        .collect();
+    let a: Vec<_> = alpha
+        .iter()
+        .enumerate()
+        .map(|(i, b)| cc_alpha(i, mode.get_bits(b.clone())))
+        .collect();
+    format!(
+        "clamp(vec4(({}-{})*{}+{}, ({}-{})*{}+{}), 0.0, 1.0)",
+        r[0], r[1], r[2], r[3], a[0], a[1], a[2], a[3]
+    )
+}
+
+/// How the last blender cycle is performed.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Blend {
+    // Computed by the shader, which outputs the final color
+    Shader,
+    // Memory is kept untouched
+    Keep,
+    // Fixed-function GL blending of the shader output (color and A factor)
+    // with memory, weighted with the specified factor.
+    Memory(GLenum),
+}
+
+// Blender inputs: P/M and A/B, as GLSL expressions.
+fn bl_pm(cycle: usize, sel: u32) -> &'static str {
+    match sel {
+        0 if cycle == 0 => "color.rgb",
+        0 => "blended",
+        1 => "vec3(0.0)", // memory (only blended by GL in the last cycle)
+        2 => "u_blend.rgb",
+        _ => "u_fog.rgb",
+    }
+}
+
+fn bl_a(sel: u32) -> &'static str {
+    match sel {
+        0 => "color.a",
+        1 => "u_fog.a",
+        2 => "shade.a",
+        _ => "0.0",
+    }
+}
+
+fn bl_b(sel: u32) -> &'static str {
+    match sel {
+        0 => "(1.0 - color.a)",
+        1 | 2 => "1.0",
+        _ => "0.0",
+    }
+}
+
+fn bl_cycle(modes: u64, cycle: usize) -> (u32, u32, u32, u32) {
+    let base = if cycle == 0 { 18 } else { 16 };
+    (
+        modes.get_bits(base + 12..base + 14) as u32,
+        modes.get_bits(base + 8..base + 10) as u32,
+        modes.get_bits(base + 4..base + 6) as u32,
+        modes.get_bits(base..base + 2) as u32,
+    )
+}
+
+fn bl_expr(cycle: usize, (p, m, a, b): (u32, u32, u32, u32)) -> String {
+    format!(
+        "blend({}, {}, {}, {})",
+        bl_pm(cycle, p),
+        bl_a(a),
+        bl_pm(cycle, m),
+        bl_b(b)
+    )
+}
+
+// Analyze the last blender cycle.
+fn last_blend(st: &RenderState) -> (Blend, String) {
+    let two = if let CycleMode::Two = st.cycle_mode() {
+        1
+    } else {
+        0
+    };
+    let (p, m, a, b) = bl_cycle(st.other_modes, two);
+    let force_blend = st.other_modes.get_bit(14);
+    if !force_blend || (p == 1 && m == 1) {
+        // Without force blend, P is passed through (coverage is not
+        // emulated, so all pixels are fully covered).
+        return if p == 1 {
+            (Blend::Keep, "vec4(0.0)".into())
+        } else {
+            (
+                Blend::Shader,
+                format!("vec4({}, 0xE0 / 255.0)", bl_pm(two, p)),
+            )
+        };
+    }
+    if m == 1 {
+        let factor = match b {
+            0 => gl::ONE_MINUS_SRC_ALPHA,
+            1 => gl::DST_ALPHA,
+            2 => gl::ONE,
+            _ => gl::ZERO,
+        };
+        return (
+            Blend::Memory(factor),
+            format!("vec4({}, {})", bl_pm(two, p), bl_a(a)),
+        );
+    }
+    (
+        Blend::Shader,
+        format!("vec4({}, 0xE0 / 255.0)", bl_expr(two, (p, m, a, b))),
+    )
+}
+
+// Generate the fragment shader for the specified state.
+fn fragment_source(st: &RenderState) -> String {
+    let mut src = String::from(FRAGMENT_HEADER);
+    src += "
+    vec3 blend(vec3 p, float a, vec3 m, float b) {
+        return a + b > 0.0 ? (p * a + m * b) / (a + b) : p;
+    }
+
+    void main() {
+        vec2 st = u_persp ? v_stw.xy * 32767.0 / max(v_stw.z, 1.0 / 65536.0) : v_stw.xy;
+        vec4 tex0 = texel(u_tex0, u_tile0, u_tex_size.xy, st);
+        vec4 tex1 = texel(u_tex1, u_tile1, u_tex_size.zw, st);
+        vec4 shade = clamp(v_shade, 0.0, 1.0);
+        vec4 combined = vec4(0.0);
+        gl_FragDepth = clamp(v_z, 0.0, 1.0);
+";
+    match st.cycle_mode() {
+        CycleMode::Fill => {
+            src += "
+        o_color = (int(gl_FragCoord.x / u_scale) & 1) == 0 ? u_fill0 : u_fill1;
+    }
+";
+        }
+        CycleMode::Copy => {
+            src += "
+        if (u_alpha_ref > 0.0 && tex0.a == 0.0) discard;
+        o_color = tex0;
+    }
+";
+        }
+        CycleMode::One | CycleMode::Two => {
+            if let CycleMode::Two = st.cycle_mode() {
+                src += &format!("        combined = {};\n", cc_cycle(st.combine_mode, 0));
+            }
+            src += &format!("        vec4 color = {};\n", cc_cycle(st.combine_mode, 1));
+            src += "        if (color.a * 255.0 + 0.5 < u_alpha_ref) discard;\n";
+            if let CycleMode::Two = st.cycle_mode() {
+                let first = bl_cycle(st.other_modes, 0);
+                src += &format!("        vec3 blended = {};\n", bl_expr(0, first));
+            }
+            src += &format!("        o_color = {};\n    }}\n", last_blend(st).1);
+        }
+    }
+    src
+}
+
+struct Shader {
+    program: ShaderProgram,
+    u_size: GLint,
+    u_tile: [GLint; 2],
+    u_tex_size: GLint,
+    u_tex_bias: GLint,
+    u_persp: GLint,
+    u_prim: GLint,
+    u_env: GLint,
+    u_blend: GLint,
+    u_fog: GLint,
+    u_fill: [GLint; 2],
+    u_prim_lod_frac: GLint,
+    u_alpha_ref: GLint,
+    u_scale: GLint,
+}
+
+impl Shader {
+    fn new(frag_source: &str) -> Result<Shader, String> {
+        let program = ShaderProgram::new(
+            VERTEX_SHADER,
+            frag_source,
+            &[
+                (ATTR_POS, "a_pos"),
+                (ATTR_SHADE, "a_shade"),
+                (ATTR_STW, "a_stw"),
+            ],
+        )?;
+        unsafe {
+            gl::UseProgram(program.id());
+            gl::Uniform1i(program.uniform("u_tex0"), 0);
+            gl::Uniform1i(program.uniform("u_tex1"), 1);
+        }
+        Ok(Shader {
+            u_size: program.uniform("u_size"),
+            u_tile: [program.uniform("u_tile0"), program.uniform("u_tile1")],
+            u_tex_size: program.uniform("u_tex_size"),
+            u_tex_bias: program.uniform("u_tex_bias"),
+            u_persp: program.uniform("u_persp"),
+            u_prim: program.uniform("u_prim"),
+            u_env: program.uniform("u_env"),
+            u_blend: program.uniform("u_blend"),
+            u_fog: program.uniform("u_fog"),
+            u_fill: [program.uniform("u_fill0"), program.uniform("u_fill1")],
+            u_prim_lod_frac: program.uniform("u_prim_lod_frac"),
+            u_alpha_ref: program.uniform("u_alpha_ref"),
+            u_scale: program.uniform("u_scale"),
+            program,
+        })
+    }
+}
+
+struct Texture {
+    id: GLuint,
+    size: (usize, usize),
+}
+
+impl Texture {
+    // Upload a tile decoded from TMEM.
+    fn from_tile(st: &RenderState, tile: usize) -> Texture {
+        let (size, repeat, texels) = tmem::decode_texture(st, tile);
+        // Mirrored tiles are decoded with their mirrored copy, so they can
+        // be repeated too.
+        let wrap = |axis: usize| {
+            if repeat[axis] {
+                gl::REPEAT as i32
+            } else {
+                gl::CLAMP_TO_EDGE as i32
+            }
+        };
+        let filter = match st.cycle_mode() {
+            CycleMode::Copy => gl::NEAREST,
+            _ if st.bilerp() => gl::LINEAR,
+            _ => gl::NEAREST,
+        } as i32;
+        unsafe {
+            let mut id = 0;
+            gl::GenTextures(1, &mut id);
+            gl::BindTexture(gl::TEXTURE_2D, id);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, wrap(0));
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, wrap(1));
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA8 as i32,
+                size.0 as i32,
+                size.1 as i32,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                texels.as_ptr() as _,
+            );
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+            Texture { id, size }
+        }
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+// Key of a run of primitives drawn with the same GL state: render state,
+// tile, and whether they're rectangles.
+type DrawKey = (usize, usize, bool);
+
+// Color image kept on the GPU between batches, at the internal resolution.
+struct Image {
+    target: RenderTarget,
+    scale: usize,
+    height: usize,
+    // Contents of the color image (and of the Z buffer, with its address) as
+    // last written back into RDRAM. As long as RDRAM still matches, there is
+    // no need to load the image again, which preserves its resolution.
+    color: Vec<u8>,
+    depth: Option<(u32, Vec<u8>)>,
+    // Set when the image was modified outside of the renderer (eg: by the
+    // CPU): it is then drawn at native resolution, as the effects applied to
+    // it were computed at that resolution.
+    native: bool,
+}
+
+fn read_bytes(rdram: &Rdram, addr: u32, len: usize) -> Vec<u8> {
+    (0..len as u32).map(|i| rdram.read_u8(addr + i)).collect()
+}
+
+fn write_bytes(rdram: &Rdram, addr: u32, data: &[u8]) {
+    for (i, &v) in data.iter().enumerate() {
+        rdram.write_u8(addr + i as u32, v);
+    }
+}
+
+// Convert a color image from its format in RDRAM into RGBA pixels.
+fn decode_image(data: &[u8], bpp: usize) -> Vec<u8> {
+    match bpp {
+        32 => data.to_vec(),
+        16 => data
+            .chunks(2)
+            .flat_map(|c| {
+                let c = rgba16((c[0] as u16) << 8 | c[1] as u16);
+                c.iter().map(|v| (v * 255.0) as u8).collect::<Vec<_>>()
+            })
+            .collect(),
+        _ => data.iter().flat_map(|&v| vec![v; 4]).collect(),
+    }
+}
+
+// Convert RGBA pixels into the format of a color image in RDRAM.
+fn encode_image(pixels: &[u8], bpp: usize) -> Vec<u8> {
+    match bpp {
+        32 => pixels.to_vec(),
+        16 => pixels
+            .chunks(4)
+            .flat_map(|c| {
+                let v = (c[0] as u16 >> 3) << 11
+                    | (c[1] as u16 >> 3) << 6
+                    | (c[2] as u16 >> 3) << 1
+                    | (c[3] >= 0x80) as u16;
+                vec![(v >> 8) as u8, v as u8]
+            })
+            .collect(),
+        _ => pixels.chunks(4).map(|c| c[0]).collect(),
+    }
+}
+
+// Enlarge RGBA pixels by an integer factor, replicating them.
+fn upscale(pixels: &[u8], width: usize, height: usize, scale: usize) -> Vec<u8> {
+    if scale == 1 {
+        return pixels.to_vec();
+    }
+    let mut out = Vec::with_capacity(pixels.len() * scale * scale);
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            let idx = ((y / scale) * width + x / scale) * 4;
+            out.extend_from_slice(&pixels[idx..idx + 4]);
+        }
+    }
+    out
+}
+
+// Shrink RGBA pixels by an integer factor, averaging blocks of pixels. The
+// size is the one of the result.
+fn downscale(pixels: &[u8], width: usize, height: usize, scale: usize) -> Vec<u8> {
+    if scale == 1 {
+        return pixels.to_vec();
+    }
+    let mut out = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0usize; 4];
+            for sy in 0..scale {
+                for sx in 0..scale {
+                    let idx = ((y * scale + sy) * width * scale + x * scale + sx) * 4;
+                    for c in 0..4 {
+                        sum[c] += pixels[idx + c] as usize;
+                    }
+                }
+            }
+            out.extend(sum.iter().map(|v| (v / (scale * scale)) as u8));
+        }
+    }
+    out
+}
+
+/// GlRenderer queues primitives and draws them with OpenGL. It must be used
+/// on the thread owning the OpenGL context of the output window.
+pub(crate) struct GlRenderer {
+    logger: slog::Logger,
+    queue: Vec<(Arc<RenderState>, Primitive)>,
+    shaders: HashMap<(u64, u64), Option<Shader>>,
+    depth_shader: ShaderProgram,
+    images: HashMap<(u32, usize, usize), Image>, // address, width, bpp
+    scale: usize,
+    textures: HashMap<(usize, usize), Texture>, // valid only within a batch
+    vao: GLuint,
+    vbo: GLuint,
+    vertices: Vec<f32>,
+}
+
+impl GlRenderer {
+    /// Create the renderer. Fails if the OpenGL context of the output window
+    /// is not available on this thread.
+    pub(crate) fn new(logger: slog::Logger) -> Result<GlRenderer, String> {
+        if !hw::gl_context_current() {
+            return Err("OpenGL is not available on the emulation thread".into());
+        }
+        let depth_shader = ShaderProgram::new(
+            DEPTH_VERTEX_SHADER,
+            DEPTH_FRAGMENT_SHADER,
+            &[(ATTR_POS, "a_pos")],
+        )?;
+        let (mut vao, mut vbo) = (0, 0);
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl::GenBuffers(1, &mut vbo);
+            gl::BindVertexArray(vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            let stride = (VERTEX_SIZE * std::mem::size_of::<f32>()) as i32;
+            let attr = |loc: u32, size: i32, off: usize| {
+                gl::VertexAttribPointer(
+                    loc,
+                    size,
+                    gl::FLOAT,
+                    gl::FALSE,
+                    stride,
+                    (off * std::mem::size_of::<f32>()) as *const _,
+                );
+                gl::EnableVertexAttribArray(loc);
+            };
+            attr(ATTR_POS, 3, 0);
+            attr(ATTR_SHADE, 4, 3);
+            attr(ATTR_STW, 3, 7);
+            gl::BindVertexArray(0);
+        }
+        Ok(GlRenderer {
+            logger,
+            queue: Vec::new(),
+            shaders: HashMap::new(),
+            depth_shader,
+            images: HashMap::new(),
+            scale: 1,
+            textures: HashMap::new(),
+            vao,
+            vbo,
+            vertices: Vec::new(),
+        })
+    }
+
+    /// Set the internal resolution, as a multiplier of the native one. Images
+    /// are loaded again from RDRAM at the new resolution.
+    pub(crate) fn set_scale(&mut self, scale: usize) {
+        self.submit();
+        self.scale = scale.max(1);
+        self.images.clear();
+    }
+
+    /// Return the texture containing the color image at the specified
+    /// address, if it is drawn at a higher resolution than the native one,
+    /// and RDRAM still contains what was drawn into it (otherwise, the image
+    /// was modified by the CPU, and must be displayed from RDRAM).
+    pub(crate) fn upscaled_image(&mut self, addr: u32, width: usize, height: usize) -> Option<u32> {
+        let rdram = Rdram::get();
+        for (key, img) in self.images.iter_mut() {
+            if key.0 != addr || key.1 != width || img.height != height {
+                continue;
+            }
+            if img.native || img.scale == 1 {
+                return None;
+            }
+            if read_bytes(&rdram, addr, img.color.len()) != img.color {
+                img.native = true;
+                return None;
+            }
+            return Some(img.target.color_texture());
+        }
+        None
+    }
+
+    pub(crate) fn push(&mut self, st: &Arc<RenderState>, prim: Primitive) {
+        self.queue.push((st.clone(), prim));
+        if self.queue.len() >= MAX_BATCH {
+            self.submit();
+        }
+    }
+
+    /// Draw all the queued primitives, and write them back into RDRAM.
+    pub(crate) fn submit(&mut self) {
+        if self.queue.is_empty() {
+            return;
+        }
+        let rdram = Rdram::get();
+        let batch = std::mem::replace(&mut self.queue, Vec::new());
+
+        // Split the batch into runs of primitives drawn into the same
+        // color image (and Z buffer).
+        let image = |st: &RenderState| (st.fb.dram_addr, st.fb.width, st.fb.bpp, st.z_addr);
+        let mut start = 0;
+        while start < batch.len() {
+            let key = image(&batch[start].0);
+            let end = batch[start..]
+                .iter()
+                .position(|(st, _)| image(st) != key)
+                .map_or(batch.len(), |n| start + n);
+            self.draw_image(&batch[start..end], &rdram);
+            start = end;
+        }
+
+        self.textures.clear();
+        unsafe {
+            // Restore the state expected by the rest of the frontend.
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::BindVertexArray(0);
+            gl::UseProgram(0);
+            gl::Disable(gl::BLEND);
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            gl::DepthMask(gl::TRUE);
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+    }
+
+    // Draw a run of primitives into their color image.
+    fn draw_image(&mut self, prims: &[(Arc<RenderState>, Primitive)], rdram: &Rdram) {
+        let fb = prims[0].0.fb;
+        let z_addr = prims[0].0.z_addr;
+        let width = fb.width;
+        let height = prims
+            .iter()
+            .map(|(st, _)| st.clip_rect().3)
+            .max()
+            .unwrap_or(0)
+            .max(1)
+            .min(1024) as usize;
+        let uses_z = prims.iter().any(|(st, _)| match st.cycle_mode() {
+            CycleMode::One | CycleMode::Two => st.z_compare() || st.z_update(),
+            _ => false,
+        });
+
+        // Load the color image from RDRAM, unless it still contains what was
+        // last drawn into it, in which case the image on the GPU is reused.
+        let key = (fb.dram_addr, width, fb.bpp);
+        let color = read_bytes(rdram, fb.dram_addr, width * height * fb.bpp / 8);
+        let (reload, native) = match self.images.get(&key) {
+            Some(img) if img.height == height && img.color == color => (false, img.native),
+            Some(img) if img.height == height => (true, true),
+            Some(img) => (true, img.native),
+            None => (true, false),
+        };
+        if reload {
+            let scale = if native { 1 } else { self.scale };
+            let target = match self.images.remove(&key) {
+                Some(img) if img.scale == scale && img.height == height => img.target,
+                _ => RenderTarget::new(width * scale, height * scale),
+            };
+            let pixels = decode_image(&color, fb.bpp);
+            target.write_color(&upscale(&pixels, width, height, scale));
+            if self.images.len() >= MAX_IMAGES {
+                self.images.clear();
+            }
+            let img = Image {
+                target,
+                scale,
+                height,
+                color,
+                depth: None,
+                native,
+            };
+            self.images.insert(key, img);
+        }
+        let img = &self.images[&key];
+        let scale = img.scale;
+        img.target.bind();
+        if uses_z {
+            let z = read_bytes(rdram, z_addr, width * height * 2);
+            let loaded = img
+                .depth
+                .as_ref()
+                .map_or(false, |(addr, data)| *addr == z_addr && *data == z);
+            if !loaded {
+                self.load_depth(&z, width, height);
+            }
+        }
+
+        // Draw primitives, merging consecutive ones drawn with the same state
+        // into a single draw call.
+        let mut current: Option<DrawKey> = None;
+        for (st, prim) in prims {
+            let id = &**st as *const RenderState as usize;
+            let key = match prim {
+                Primitive::Triangle(tri) => (id, tri.tile, false),
+                Primitive::TexRect { tile, .. } => (id, *tile, true),
+                Primitive::FillRect { .. } => (id, 0, true),
+            };
+            if current != Some(key) {
+                self.draw_vertices();
+                if !self.setup(st, key.1, key.2, (width, height), scale) {
+                    current = None;
+                    continue;
+                }
+                current = Some(key);
+            }
+            match prim {
+                Primitive::Triangle(tri) => self.triangle(st, tri),
+                Primitive::FillRect { x0, y0, x1, y1 } => self.rect(st, (*x0, *y0, *x1, *y1), None),
+                Primitive::TexRect {
+                    x0,
+                    y0,
+                    x1,
+                    y1,
+                    s,
+                    t,
+                    dsdx,
+                    dtdy,
+                    flip,
+                    ..
+                } => self.rect(
+                    st,
+                    (*x0, *y0, *x1, *y1),
+                    Some((*s, *t, *dsdx, *dtdy, *flip)),
+                ),
+            }
+        }
+        self.draw_vertices();
+
+        // Write the image back into RDRAM, at native resolution.
+        let img = self.images.get_mut(&key).unwrap();
+        let pixels = downscale(&img.target.read_color(), width, height, scale);
+        img.color = encode_image(&pixels, fb.bpp);
+        write_bytes(rdram, fb.dram_addr, &img.color);
+        if uses_z {
+            let depth = img.target.read_depth();
+            let mut z = Vec::with_capacity(width * height * 2);
+            for y in 0..height {
+                for x in 0..width {
+                    let d = depth[(y * width * scale + x) * scale];
+                    let v = (((d * 32768.0 + 0.5) as u32).min(0x7FFF) >> 1 << 2) as u16;
+                    z.push((v >> 8) as u8);
+                    z.push(v as u8);
+                }
+            }
+            write_bytes(rdram, z_addr, &z);
+            img.depth = Some((z_addr, z));
+        }
+    }
+
+    // Load the Z buffer into the depth buffer of the bound render target,
+    // by drawing a full-screen quad.
+    fn load_depth(&mut self, z: &[u8], width: usize, height: usize) {
+        let depth: Vec<f32> = z
+            .chunks(2)
+            .map(|v| {
+                let z = ((v[0] as u16) << 8 | v[1] as u16) >> 2;
+                (z as u32 * 2) as f32 / 32768.0
+            })
+            .collect();
+        unsafe {
+            let mut tex = 0;
+            gl::GenTextures(1, &mut tex);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, tex);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::R32F as i32,
+                width as i32,
+                height as i32,
+                0,
+                gl::RED,
+                gl::FLOAT,
+                depth.as_ptr() as _,
+            );
+
+            gl::UseProgram(self.depth_shader.id());
+            gl::Uniform1i(self.depth_shader.uniform("u_depth"), 0);
+            gl::Disable(gl::BLEND);
+            gl::Disable(gl::SCISSOR_TEST);
+            gl::Enable(gl::DEPTH_TEST);
+            gl::DepthFunc(gl::ALWAYS);
+            gl::DepthMask(gl::TRUE);
+            gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+            self.vertices.clear();
+            for &(x, y) in &[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)][..] {
+                self.vertices.extend_from_slice(&[x, y, 0.0]);
+                self.vertices.extend_from_slice(&[0.0; VERTEX_SIZE - 3]);
+            }
+            self.upload_vertices();
+            gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+            self.vertices.clear();
+
+            gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+            gl::DeleteTextures(1, &tex);
+        }
+    }
+
+    // Configure the GL state to draw primitives with the specified render
+    // state and tile, into an image of the specified (native) size and
+    // resolution multiplier. Returns false if the shader could not be
+    // compiled.
+    fn setup(
+        &mut self,
+        st: &RenderState,
+        tile: usize,
+        rect: bool,
+        size: (usize, usize),
+        scale: usize,
+    ) -> bool {
+        let key = (st.combine_mode, st.other_modes & SHADER_MODES);
+        let logger = &self.logger;
+        let shader = self.shaders.entry(key).or_insert_with(|| {
+            Shader::new(&fragment_source(st))
+                .map_err(|err| error!(logger, "cannot compile RDP shader"; "err" => err))
+                .ok()
+        });
+        let shader = match shader {
+            Some(shader) => shader,
+            None => return false,
+        };
+
+        let mode = st.cycle_mode();
+        let (blend, _) = last_blend(st);
+        let two_cycle = if let CycleMode::Two = mode { 1 } else { 0 };
+        let state_id = st as *const RenderState as usize;
+        unsafe {
+            gl::UseProgram(shader.program.id());
+            gl::Uniform2f(shader.u_size, size.0 as f32, size.1 as f32);
+            gl::Uniform1f(shader.u_scale, scale as f32);
+            gl::Uniform1i(shader.u_persp, (!rect && st.persp()) as i32);
+            let color = |loc: GLint, c: [f32; 4]| gl::Uniform4f(loc, c[0], c[1], c[2], c[3]);
+            color(shader.u_prim, rgba(st.prim_color));
+            color(shader.u_env, rgba(st.env_color));
+            color(shader.u_blend, rgba(st.blend_color));
+            color(shader.u_fog, rgba(st.fog_color));
+            match st.fb.bpp {
+                16 => {
+                    color(shader.u_fill[0], rgba16((st.fill_color >> 16) as u16));
+                    color(shader.u_fill[1], rgba16(st.fill_color as u16));
+                }
+                32 => {
+                    color(shader.u_fill[0], rgba(st.fill_color));
+                    color(shader.u_fill[1], rgba(st.fill_color));
+                }
+                _ => {
+                    let i = (st.fill_color >> 24) as f32 / 255.0;
+                    color(shader.u_fill[0], [i; 4]);
+                    color(shader.u_fill[1], [i; 4]);
+                }
+            }
+            gl::Uniform1f(shader.u_prim_lod_frac, st.prim_lod_frac as f32 / 255.0);
+            let alpha_ref = match (st.alpha_compare(), mode) {
+                (false, _) => 0.0,
+                (true, CycleMode::Copy) => 1.0,
+                (true, _) if st.dither_alpha() => 128.0,
+                (true, _) => (st.blend_color & 0xFF) as f32,
+            };
+            gl::Uniform1f(shader.u_alpha_ref, alpha_ref);
+
+            // Textures of the tile (and of the next one, in 2-cycle mode).
+            // Texels are copied without filtering in copy mode.
+            let bilerp = match mode {
+                CycleMode::Copy => false,
+                _ => st.bilerp(),
+            };
+            gl::Uniform1f(shader.u_tex_bias, if bilerp { 0.5 } else { 0.0 });
+            let mut sizes = [0.0; 4];
+            for i in 0..2 {
+                let idx = (tile + i * two_cycle) & 7;
+                let tex = self
+                    .textures
+                    .entry((state_id, idx))
+                    .or_insert_with(|| Texture::from_tile(st, idx));
+                gl::ActiveTexture(gl::TEXTURE0 + i as u32);
+                gl::BindTexture(gl::TEXTURE_2D, tex.id);
+                let td = &st.tiles[idx];
+                let scale = |sh: u32| {
+                    if sh < 11 {
+                        1.0 / (1 << sh) as f32
+                    } else {
+                        (1 << (16 - sh)) as f32
+                    }
+                };
+                gl::Uniform4f(
+                    shader.u_tile[i],
+                    scale(td.shift[0]),
+                    scale(td.shift[1]),
+                    td.sl as f32 / 4.0,
+                    td.tl as f32 / 4.0,
+                );
+                sizes[i * 2] = tex.size.0 as f32;
+                sizes[i * 2 + 1] = tex.size.1 as f32;
+            }
+            gl::Uniform4f(shader.u_tex_size, sizes[0], sizes[1], sizes[2], sizes[3]);
+            gl::ActiveTexture(gl::TEXTURE0);
+
+            // Depth test, only in 1-cycle and 2-cycle modes
+            let shaded = match mode {
+                CycleMode::One | CycleMode::Two => true,
+                _ => false,
+            };
+            if shaded && (st.z_compare() || st.z_update()) {
+                gl::Enable(gl::DEPTH_TEST);
+                gl::DepthFunc(match (st.z_compare(), st.z_mode()) {
+                    (false, _) => gl::ALWAYS,
+                    (true, 3) => gl::LEQUAL,
+                    (true, _) => gl::LESS,
+                });
+                gl::DepthMask(st.z_update() as u8);
+            } else {
+                gl::Disable(gl::DEPTH_TEST);
+            }
+
+            // Blending with memory is performed by GL; the coverage stored
+            // in alpha is left untouched.
+            match (shaded, blend) {
+                (true, Blend::Memory(factor)) => {
+                    gl::Enable(gl::BLEND);
+                    gl::BlendFunc(gl::SRC_ALPHA, factor);
+                    gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::FALSE);
+                }
+                (true, Blend::Keep) => {
+                    gl::Enable(gl::BLEND);
+                    gl::BlendFunc(gl::ZERO, gl::ONE);
+                    gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::FALSE);
+                }
+                _ => {
+                    gl::Disable(gl::BLEND);
+                    gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+                }
+            }
+
+            let (cx0, cy0, cx1, cy1) = st.clip_rect();
+            gl::Enable(gl::SCISSOR_TEST);
+            let sc = scale as i32;
+            gl::Scissor(
+                cx0 * sc,
+                cy0 * sc,
+                (cx1 - cx0).max(0) * sc,
+                (cy1 - cy0).max(0) * sc,
+            );
+        }
+        true
+    }
+
+    fn vertex(&mut self, (x, y): (f32, f32), z: f32, shade: [f32; 4], stw: [f32; 3]) {
+        self.vertices.extend_from_slice(&[x, y, z / 32768.0]);
+        self.vertices.extend_from_slice(&shade);
+        self.vertices.extend_from_slice(&stw);
+    }
+
+    fn triangle(&mut self, st: &RenderState, tri: &Triangle) {
+        // Edges and attributes are defined relative to the first scanline.
+        let ytop = (tri.yh >> 2) as f32;
+        let (yh, ym, yl) = (
+            tri.yh as f32 / 4.0,
+            tri.ym as f32 / 4.0,
+            tri.yl as f32 / 4.0,
+        );
+        let xmaj = |y: f32| fx(tri.xh) + fx(tri.dxhdy) * (y - ytop);
+        let xmid = |y: f32| fx(tri.xm) + fx(tri.dxmdy) * (y - ytop);
+        let xlow = |y: f32| fx(tri.xl) + fx(tri.dxldy) * (y - ym);
+        let at = |a: &Attr, (x, y): (f32, f32)| {
+            fx(a.v) + fx(a.de) * (y - ytop) + fx(a.dx) * (x - xmaj(y))
+        };
+
+        // The triangle is drawn as two trapezoids (above and below the
+        // middle vertex), split into two GL triangles each.
+        let quads = [
+            [
+                (xmaj(yh), yh),
+                (xmid(yh), yh),
+                (xmaj(ym), ym),
+                (xmid(ym), ym),
+            ],
+            [
+                (xmaj(ym), ym),
+                (xlow(ym), ym),
+                (xmaj(yl), yl),
+                (xlow(yl), yl),
+            ],
+        ];
+        for quad in quads.iter() {
+            for &idx in &[0, 1, 2, 1, 3, 2] {
+                let p = quad[idx];
+                let mut shade = [0.0; 4];
+                let mut stw = [0.0; 3];
+                if let Some(attrs) = &tri.shade {
+                    for i in 0..4 {
+                        shade[i] = at(&attrs[i], p);
+                    }
+                }
+                if let Some(attrs) = &tri.tex {
+                    for i in 0..3 {
+                        stw[i] = at(&attrs[i], p);
+                    }
+                }
+                let z = match &tri.z {
+                    _ if st.z_source_prim() => st.prim_z as f32,
+                    Some(attr) => at(attr, p),
+                    None => 0.0,
+                };
+                self.vertex(p, z, shade, stw);
+            }
+        }
+    }
+
+    fn rect(
+        &mut self,
+        st: &RenderState,
+        (x0, y0, x1, y1): (i32, i32, i32, i32),
+        tex: Option<(i32, i32, i32, i32, bool)>,
+    ) {
+        // In fill and copy modes, the bottom-right corner is inclusive.
+        let mode = st.cycle_mode();
+        let (xe, ye) = match mode {
+            CycleMode::Fill | CycleMode::Copy => ((x1 >> 2) + 1, (y1 >> 2) + 1),
+            _ => ((x1 + 3) >> 2, (y1 + 3) >> 2),
+        };
+        let (xs, ys) = (x0 >> 2, y0 >> 2);
+
+        // Texture coordinates at the corners, such that they have the
+        // expected value at the center of each pixel. In copy mode, dsdx is
+        // expressed in units of 4 pixels.
+        let step = if let CycleMode::Copy = mode {
+            128.0
+        } else {
+            32.0
+        };
+        let corners = [(xs, ys), (xe, ys), (xs, ye), (xe, ye)];
+        let mut verts = [((0.0, 0.0), [0.0; 3]); 4];
+        for (i, &(x, y)) in corners.iter().enumerate() {
+            let (dx, dy) = ((x - xs) as f32 - 0.5, (y - ys) as f32 - 0.5);
+            let stw = match tex {
+                Some((s, t, dsdx, dtdy, flip)) => {
+                    let (dx, dy) = if flip { (dy, dx) } else { (dx, dy) };
+                    [
+                        s as f32 + dsdx as f32 * dx / step,
+                        t as f32 + dtdy as f32 * dy / 32.0,
+                        1.0,
+                    ]
+                }
+                None => [0.0; 3],
+            };
+            verts[i] = ((x as f32, y as f32), stw);
+        }
+        for &idx in &[0, 1, 2, 1, 3, 2] {
+            let (p, stw) = verts[idx];
+            self.vertex(p, st.prim_z as f32, [0.0; 4], stw);
+        }
+    }
+
+    fn upload_vertices(&self) {
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (self.vertices.len() * std::mem::size_of::<f32>()) as isize,
+                self.vertices.as_ptr() as _,
+                gl::STREAM_DRAW,
+            );
+        }
+    }
+
+    // Draw the accumulated vertices with the current state.
+    fn draw_vertices(&mut self) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        self.upload_vertices();
+        unsafe {
+            gl::DrawArrays(gl::TRIANGLES, 0, (self.vertices.len() / VERTEX_SIZE) as i32);
+        }
+        self.vertices.clear();
+    }
+}
+
+impl Drop for GlRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.vbo);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
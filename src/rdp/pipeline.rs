@@ -17,12 +17,27 @@ impl PixelPipeline {
         }
     }
 
+    /// Run pixels through the color combiner and the blender, in either
+    /// 1-cycle or 2-cycle mode. Returns the combined color (whose alpha is
+    /// used for alpha compare) and the blended color.
     #[inline(always)]
-    pub fn calc_pixels(&mut self, shade: MultiColor, fb: MultiColor) -> MultiColor {
-        self.cc.set_tex0(shade);
-        let combined = self.cc.combine_1cycle(shade);
-        let blended = self.bl.blend_1cycle(combined, shade, fb);
-        return blended;
+    pub fn calc_pixels(
+        &mut self,
+        two_cycle: bool,
+        shade: MultiColor,
+        tex0: MultiColor,
+        tex1: MultiColor,
+        fb: MultiColor,
+    ) -> (MultiColor, MultiColor) {
+        self.cc.set_tex0(tex0);
+        self.cc.set_tex1(tex1);
+        if two_cycle {
+            let combined = self.cc.combine_2cycle(shade);
+            (combined, self.bl.blend_2cycle(combined, shade, fb))
+        } else {
+            let combined = self.cc.combine_1cycle(shade);
+            (combined, self.bl.blend_1cycle(combined, shade, fb))
+        }
     }
 
     pub fn set_combine_mode(&mut self, mode: u64) {
@@ -31,9 +46,15 @@ impl PixelPipeline {
     pub fn set_prim_color(&mut self, c: Color<Rgba8888>) {
         self.cc.set_prim(c);
     }
+    pub fn set_prim_lod_frac(&mut self, frac: u8) {
+        self.cc.set_prim_lod_frac(frac);
+    }
     pub fn set_env_color(&mut self, c: Color<Rgba8888>) {
         self.cc.set_env(c);
     }
+    pub fn set_fog_color(&mut self, c: Color<Rgba8888>) {
+        self.bl.set_fog_color(c);
+    }
     pub fn set_blend_color(&mut self, c: Color<Rgba8888>) {
         self.bl.set_blend_color(c);
     }
@@ -1,84 +1,375 @@
 extern crate bit_field;
-extern crate byteorder;
 extern crate emu;
 extern crate slog;
 use self::bit_field::BitField;
-use self::byteorder::{BigEndian, LittleEndian};
-use self::emu::bus::Device;
-use super::super::r4300::R4300;
+#[cfg(feature = "frontend")]
+use super::opengl::GlRenderer;
 use super::pipeline::PixelPipeline;
-use super::raster::{draw_rect, fill_rect, fill_rect_pp, DpRenderState};
+use super::soft::{Attr, Primitive, Rdram, Renderer, Triangle};
+use super::tmem;
 use super::{CycleMode, DpColorFormat};
-use emu::fp::formats::*;
-use emu::fp::Q;
-use emu::gfx::*;
 use emu::int::Numerics;
-use std::marker::PhantomData;
+use std::sync::Arc;
 
 #[derive(Copy, Clone, Default, Debug)]
-struct TileDescriptor {
-    color_format: DpColorFormat,
-    bpp: usize,
-    pitch: usize,
-    tmem_addr: u32,
-    palette: usize,
-    clamp: [bool; 2],
-    mirror: [bool; 2],
-    mask: [u32; 2],
-    shift: [u32; 2],
-
-    rect: Rect<U30F2>,
+pub(crate) struct TileDescriptor {
+    pub(crate) color_format: DpColorFormat,
+    pub(crate) bpp: usize,
+    pub(crate) pitch: usize,
+    pub(crate) tmem_addr: u32,
+    pub(crate) palette: usize,
+    pub(crate) clamp: [bool; 2],
+    pub(crate) mirror: [bool; 2],
+    pub(crate) mask: [u32; 2],
+    pub(crate) shift: [u32; 2],
+
+    // Tile size (10.2), set by Set Tile Size and by the load commands
+    pub(crate) sl: u32,
+    pub(crate) tl: u32,
+    pub(crate) sh: u32,
+    pub(crate) th: u32,
 }
 
 #[derive(Copy, Clone, Default, Debug)]
-struct ImageFormat {
-    color_format: DpColorFormat,
-    bpp: usize,
-    width: usize,
-    dram_addr: u32,
+pub(crate) struct ImageFormat {
+    pub(crate) color_format: DpColorFormat,
+    pub(crate) bpp: usize,
+    pub(crate) width: usize,
+    pub(crate) dram_addr: u32,
 }
 
-impl ImageFormat {
-    fn pitch(&self) -> usize {
-        self.width * self.bpp / 8
+/// RenderState is the subset of the RDP state used to draw primitives.
+/// Every queued primitive holds a reference to the state it was sent with;
+/// state changes create a new copy (on write) if the current one is still
+/// referenced by queued primitives.
+#[derive(Clone)]
+pub(crate) struct RenderState {
+    pub(crate) tmem: Arc<Vec<u8>>,
+    pub(crate) tiles: [TileDescriptor; 8],
+    pub(crate) fb: ImageFormat,
+    pub(crate) z_addr: u32,
+    pub(crate) clip: [i32; 4], // 10.2
+    pub(crate) other_modes: u64,
+    pub(crate) combine_mode: u64,
+    pub(crate) fill_color: u32,
+    pub(crate) blend_color: u32,
+    pub(crate) fog_color: u32,
+    pub(crate) prim_color: u32,
+    pub(crate) prim_lod_frac: u8,
+    pub(crate) prim_z: u32,
+    pub(crate) env_color: u32,
+}
+
+impl RenderState {
+    pub(crate) fn new() -> RenderState {
+        RenderState {
+            tmem: Arc::new(vec![0u8; 4096]),
+            tiles: [TileDescriptor::default(); 8],
+            fb: ImageFormat::default(),
+            z_addr: 0,
+            clip: [0; 4],
+            other_modes: 0,
+            combine_mode: 0,
+            fill_color: 0,
+            blend_color: 0,
+            fog_color: 0,
+            prim_color: 0,
+            prim_lod_frac: 0,
+            prim_z: 0,
+            env_color: 0,
+        }
+    }
+
+    /// Scissor rectangle in integer pixels (bottom-right corner excluded),
+    /// restricted to the framebuffer width.
+    pub(crate) fn clip_rect(&self) -> (i32, i32, i32, i32) {
+        (
+            self.clip[0] >> 2,
+            self.clip[1] >> 2,
+            (self.clip[2] >> 2).min(self.fb.width as i32),
+            self.clip[3] >> 2,
+        )
+    }
+
+    pub(crate) fn cycle_mode(&self) -> CycleMode {
+        match self.other_modes.get_bits(52..54) {
+            0 => CycleMode::One,
+            1 => CycleMode::Two,
+            2 => CycleMode::Copy,
+            3 => CycleMode::Fill,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn persp(&self) -> bool {
+        self.other_modes.get_bit(51)
+    }
+    pub(crate) fn tlut_en(&self) -> bool {
+        self.other_modes.get_bit(47)
+    }
+    pub(crate) fn tlut_ia16(&self) -> bool {
+        self.other_modes.get_bit(46)
+    }
+    pub(crate) fn bilerp(&self) -> bool {
+        self.other_modes.get_bit(45)
+    }
+    pub(crate) fn alpha_compare(&self) -> bool {
+        self.other_modes.get_bit(0)
+    }
+    pub(crate) fn dither_alpha(&self) -> bool {
+        self.other_modes.get_bit(1)
+    }
+    pub(crate) fn z_source_prim(&self) -> bool {
+        self.other_modes.get_bit(2)
+    }
+    pub(crate) fn z_compare(&self) -> bool {
+        self.other_modes.get_bit(4)
+    }
+    pub(crate) fn z_update(&self) -> bool {
+        self.other_modes.get_bit(5)
     }
+    pub(crate) fn z_mode(&self) -> u64 {
+        self.other_modes.get_bits(10..12)
+    }
+}
+
+/// Number of 64-bit words of the command starting with the specified word.
+pub fn cmd_len(cmd: u64) -> usize {
+    let op = cmd.get_bits(56..62);
+    match op {
+        0x08..=0x0F => {
+            // Edges, plus shade, texture and z coefficients
+            4 + if op & 4 != 0 { 8 } else { 0 }
+                + if op & 2 != 0 { 8 } else { 0 }
+                + if op & 1 != 0 { 2 } else { 0 }
+        }
+        0x24 | 0x25 => 2,
+        _ => 1,
+    }
+}
+
+// Sign-extend a y coordinate of an edge (s11.2, 14 bits)
+fn edge_y(v: u64) -> i32 {
+    ((v as u32) << 18) as i32 >> 18
+}
+
+// Decode the coefficients of `n` attributes packed in 8 words (shade
+// and texture coefficients share the same layout).
+fn attrs(w: &[u64], n: usize) -> Vec<Attr> {
+    (0..n)
+        .map(|i| {
+            let sh = 48 - 16 * i;
+            let fixed = |int: u64, frac: u64| {
+                (((int >> sh) & 0xFFFF) << 16 | ((frac >> sh) & 0xFFFF)) as u32 as i32
+            };
+            Attr {
+                v: fixed(w[0], w[2]),
+                dx: fixed(w[1], w[3]),
+                de: fixed(w[4], w[6]),
+                dy: fixed(w[5], w[7]),
+            }
+        })
+        .collect()
+}
+
+/// Renderer used to draw the primitives.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RenderBackend {
+    /// Software rasterizer, optionally running on worker threads.
+    Software,
+    /// OpenGL renderer, drawing on the GPU (requires the frontend).
+    OpenGl,
 }
 
 pub struct Rdp {
     logger: slog::Logger,
-    tmem: Box<[u8]>,
-    clip: Rect<I30F2>,
-    fb: ImageFormat,
+    state: Arc<RenderState>,
     tex: ImageFormat,
-    tiles: [TileDescriptor; 8],
-    fill_color: u32,
-    cycle_mode: CycleMode,
+    renderer: Renderer,
+    backend: RenderBackend,
+    scale: usize,
+    #[cfg(feature = "frontend")]
+    gl: Option<GlRenderer>,
 
+    // Only used to format the combiner and blender modes in logs
     pipeline: PixelPipeline,
 
-    cmdbuf: [u64; 16],
+    cmdbuf: [u64; 22],
     cmdlen: usize,
 }
 
 impl Rdp {
     pub fn new(logger: slog::Logger) -> Rdp {
-        let mut tmem = Vec::new();
-        tmem.resize(4096, 0);
         Rdp {
             logger: logger,
-            tmem: tmem.into_boxed_slice(),
-            clip: Rect::default(),
-            fb: ImageFormat::default(),
+            state: Arc::new(RenderState::new()),
             tex: ImageFormat::default(),
-            tiles: [TileDescriptor::default(); 8],
-            fill_color: 0,
-            cycle_mode: CycleMode::One,
+            renderer: Renderer::new(),
+            backend: RenderBackend::Software,
+            scale: 1,
+            #[cfg(feature = "frontend")]
+            gl: None,
             pipeline: PixelPipeline::new(),
-            cmdbuf: [0u64; 16],
+            cmdbuf: [0u64; 22],
             cmdlen: 0,
         }
     }
 
+    /// Set the number of threads used to render primitives (0 or 1 means
+    /// rendering on the emulation thread).
+    pub fn set_threads(&mut self, n: usize) {
+        self.renderer.set_threads(n);
+    }
+
+    /// Draw primitives on worker threads in background, while the emulation
+    /// keeps running. Callers must fence before memory drawn by the RDP is
+    /// observed.
+    pub fn set_background(&mut self, background: bool) {
+        self.renderer.set_background(background);
+    }
+
+    /// Start drawing all the queued primitives. In background mode, this
+    /// returns without waiting for them to be in RDRAM.
+    pub fn submit(&mut self) {
+        self.renderer.submit();
+        #[cfg(feature = "frontend")]
+        {
+            if let Some(gl) = &mut self.gl {
+                gl.submit();
+            }
+        }
+    }
+
+    /// Wait until all the submitted primitives have been drawn into RDRAM.
+    pub fn fence(&self) {
+        self.renderer.fence();
+    }
+
+    /// Wait until all the queued primitives have been drawn into RDRAM.
+    pub fn flush(&mut self) {
+        self.renderer.flush();
+        #[cfg(feature = "frontend")]
+        {
+            if let Some(gl) = &mut self.gl {
+                gl.submit();
+            }
+        }
+    }
+
+    /// Select the renderer used to draw primitives. Queued primitives are
+    /// drawn before switching. If OpenGL is not available, the software
+    /// renderer is used.
+    pub fn set_backend(&mut self, backend: RenderBackend) {
+        self.flush();
+        self.backend = backend;
+        #[cfg(feature = "frontend")]
+        {
+            if backend == RenderBackend::Software {
+                self.gl = None;
+            }
+        }
+        #[cfg(not(feature = "frontend"))]
+        {
+            if backend == RenderBackend::OpenGl {
+                error!(self.logger, "OpenGL renderer requires the frontend");
+                self.backend = RenderBackend::Software;
+            }
+        }
+    }
+
+    pub fn backend(&self) -> RenderBackend {
+        self.backend
+    }
+
+    /// Set the internal resolution of the OpenGL renderer, as a multiplier
+    /// of the native resolution. The software renderer always draws at
+    /// native resolution.
+    pub fn set_scale(&mut self, scale: usize) {
+        if scale == self.scale {
+            return;
+        }
+        self.scale = scale;
+        #[cfg(feature = "frontend")]
+        {
+            if let Some(gl) = &mut self.gl {
+                gl.set_scale(scale);
+            }
+        }
+    }
+
+    /// Return the texture holding the color image at the specified address
+    /// and size, if it was drawn by the OpenGL renderer at a higher
+    /// resolution than the native one.
+    pub fn upscaled_image(&mut self, addr: u32, width: usize, height: usize) -> Option<u32> {
+        #[cfg(feature = "frontend")]
+        {
+            self.gl
+                .as_mut()
+                .and_then(|gl| gl.upscaled_image(addr, width, height))
+        }
+        #[cfg(not(feature = "frontend"))]
+        {
+            let _ = (addr, width, height);
+            None
+        }
+    }
+
+    // Queue a primitive into the selected renderer. The OpenGL renderer is
+    // created the first time it's used, as it requires the GL context of the
+    // output window.
+    fn push(&mut self, prim: Primitive) {
+        #[cfg(feature = "frontend")]
+        {
+            if self.backend == RenderBackend::OpenGl && self.gl.is_none() {
+                match GlRenderer::new(self.logger.new(o!())) {
+                    Ok(mut gl) => {
+                        gl.set_scale(self.scale);
+                        self.gl = Some(gl);
+                    }
+                    Err(err) => {
+                        error!(self.logger, "cannot initialize OpenGL renderer"; "err" => err);
+                        self.backend = RenderBackend::Software;
+                    }
+                }
+            }
+            if let Some(gl) = &mut self.gl {
+                gl.push(&self.state, prim);
+                return;
+            }
+        }
+        self.renderer.push(&self.state, prim);
+    }
+
+    /// Return true if the last command received is incomplete (some of its
+    /// words are still missing).
+    pub fn partial_command(&self) -> bool {
+        self.cmdlen != 0
+    }
+
+    /// Current color image: RDRAM address, width, height (bottom of the
+    /// scissor rectangle) and bits per pixel.
+    #[cfg(feature = "frontend")]
+    pub fn color_image(&self) -> (u32, usize, usize, usize) {
+        let fb = &self.state.fb;
+        let height = (self.state.clip[3] >> 2).max(1) as usize;
+        (fb.dram_addr, fb.width, height, fb.bpp)
+    }
+
+    /// Decode the contents of TMEM through a tile descriptor, for the
+    /// debugger. Returns the descriptor, the tile size and its texels.
+    #[cfg(feature = "frontend")]
+    pub fn tmem_tile(&self, idx: usize) -> (String, usize, usize, Vec<[u8; 4]>) {
+        let desc = format!("{:?}", self.state.tiles[idx & 7]);
+        let (w, h, texels) = tmem::decode_tile(&self.state, idx);
+        (desc, w, h, texels)
+    }
+
+    /// Decode the palette stored in TMEM, for the debugger.
+    #[cfg(feature = "frontend")]
+    pub fn tmem_palette(&self) -> Vec<[u8; 4]> {
+        tmem::decode_palette(&self.state)
+    }
+
     fn parse_color_format(&self, bits: u64) -> DpColorFormat {
         DpColorFormat::from_bits(bits as usize)
             .or_else(|| {
@@ -88,13 +379,54 @@ impl Rdp {
             .unwrap()
     }
 
-    fn framebuffer<'s, 'r: 's>(&'s self) -> (&'r mut [u8], usize, usize, usize) {
-        let fb_mem = R4300::get_mut()
-            .bus
-            .fetch_write::<u8>(self.fb.dram_addr)
-            .mem()
-            .unwrap();
-        (fb_mem, 320, 240, self.fb.pitch())
+    // Modify the render state, copying it first if it's still in use
+    // by queued primitives.
+    fn state(&mut self) -> &mut RenderState {
+        Arc::make_mut(&mut self.state)
+    }
+
+    fn parse_triangle(&self, op: u64) -> Triangle {
+        let w = &self.cmdbuf;
+        let shade = op & 4 != 0;
+        let tex = op & 2 != 0;
+        let zbuf = op & 1 != 0;
+        let mut idx = 4;
+
+        let mut tri = Triangle {
+            lft: w[0].get_bit(55),
+            tile: w[0].get_bits(48..51) as usize,
+            yl: edge_y(w[0].get_bits(32..46)),
+            ym: edge_y(w[0].get_bits(16..30)),
+            yh: edge_y(w[0].get_bits(0..14)),
+            xl: (w[1] >> 32) as u32 as i32,
+            dxldy: w[1] as u32 as i32,
+            xh: (w[2] >> 32) as u32 as i32,
+            dxhdy: w[2] as u32 as i32,
+            xm: (w[3] >> 32) as u32 as i32,
+            dxmdy: w[3] as u32 as i32,
+            shade: None,
+            tex: None,
+            z: None,
+        };
+        if shade {
+            let a = attrs(&w[idx..idx + 8], 4);
+            tri.shade = Some([a[0], a[1], a[2], a[3]]);
+            idx += 8;
+        }
+        if tex {
+            let a = attrs(&w[idx..idx + 8], 3);
+            tri.tex = Some([a[0], a[1], a[2]]);
+            idx += 8;
+        }
+        if zbuf {
+            tri.z = Some(Attr {
+                v: (w[idx] >> 32) as u32 as i32,
+                dx: w[idx] as u32 as i32,
+                de: (w[idx + 1] >> 32) as u32 as i32,
+                dy: w[idx + 1] as u32 as i32,
+            });
+        }
+        tri
     }
 
     pub fn op(&mut self, cmd: u64) {
@@ -104,186 +436,118 @@ impl Rdp {
 
         let op = self.cmdbuf[0].get_bits(56..62);
         match op {
+            0x08..=0x0F => {
+                // Triangle (4 words for edges, plus 8 for shade, 8 for
+                // texture and 2 for z coefficients)
+                if self.cmdlen != cmd_len(self.cmdbuf[0]) {
+                    return;
+                }
+                let tri = self.parse_triangle(op);
+                info!(self.logger, "DP: Triangle"; "cmd" => (op as u8).hex(), "tri" => ?tri);
+                self.push(Primitive::Triangle(tri));
+                self.cmdlen = 0;
+            }
+            0x24 | 0x25 => {
+                // Texture rectangle (2 words), optionally flipped
+                if self.cmdlen != 2 {
+                    return;
+                }
+
+                let w = self.cmdbuf;
+                let prim = Primitive::TexRect {
+                    tile: w[0].get_bits(24..27) as usize,
+                    x1: w[0].get_bits(44..56) as i32,
+                    y1: w[0].get_bits(32..44) as i32,
+                    x0: w[0].get_bits(12..24) as i32,
+                    y0: w[0].get_bits(0..12) as i32,
+                    s: w[1].get_bits(48..64) as i16 as i32,
+                    t: w[1].get_bits(32..48) as i16 as i32,
+                    dsdx: w[1].get_bits(16..32) as i16 as i32,
+                    dtdy: w[1].get_bits(0..16) as i16 as i32,
+                    flip: op == 0x25,
+                };
+                info!(self.logger, "DP: Textured Rectangle"; "rect" => ?prim);
+                self.push(prim);
+                self.cmdlen = 0;
+            }
+            0x26 | 0x27 | 0x28 => {
+                // Sync Load / Sync Pipe / Sync Tile: primitives are drawn
+                // with a snapshot of the state, so there's nothing to wait for.
+                info!(self.logger, "DP: Sync"; "cmd" => (op as u8).hex());
+                self.cmdlen = 0;
+            }
+            0x29 => {
+                // Sync Full: the interrupt is raised by the caller. In
+                // background mode, primitives might still be drawing: the
+                // caller fences when the CPU looks at the interrupt.
+                info!(self.logger, "DP: Sync Full");
+                self.submit();
+                self.cmdlen = 0;
+            }
             0x2D => {
                 // Set Scissor
-                self.clip = Rect::from_bits(
+                self.state().clip = [
                     cmd.get_bits(44..56) as i32,
                     cmd.get_bits(32..44) as i32,
                     cmd.get_bits(12..24) as i32,
                     cmd.get_bits(0..12) as i32,
-                );
-                info!(self.logger, "DP: Set Scissor"; "clip" => ?self.clip);
-                self.cmdlen = 0;
-            }
-            0x3D | 0x3F => {
-                // Set Color/Texture Image
-                let format = ImageFormat {
-                    color_format: self.parse_color_format(cmd.get_bits(53..56)),
-                    bpp: 4 << cmd.get_bits(51..53),
-                    width: cmd.get_bits(32..42) as usize + 1,
-                    dram_addr: cmd.get_bits(0..26) as u32,
-                };
-
-                if op == 0x3F {
-                    self.fb = format;
-                    info!(self.logger, "DP: Set Color Image"; "format" => ?self.fb);
-                } else {
-                    self.tex = format;
-                    info!(self.logger, "DP: Set Texture Image"; "format" => ?self.tex);
-                }
+                ];
+                info!(self.logger, "DP: Set Scissor"; "clip" => ?self.state.clip);
                 self.cmdlen = 0;
             }
-            0x28 => {
-                // Sync Tile
-                info!(self.logger, "DP: Sync Tile");
+            0x2E => {
+                // Set Prim Depth (delta z is ignored)
+                self.state().prim_z = cmd.get_bits(16..31) as u32;
+                info!(self.logger, "DP: Set Prim Depth"; "z" => self.state.prim_z.hex());
                 self.cmdlen = 0;
             }
             0x2F => {
                 // Set Other Modes
-                self.cycle_mode = match cmd.get_bits(52..54) {
-                    0 => CycleMode::One,
-                    1 => CycleMode::Two,
-                    2 => CycleMode::Copy,
-                    3 => CycleMode::Fill,
-                    _ => unreachable!(),
-                };
+                self.state().other_modes = cmd;
                 self.pipeline.set_other_modes(cmd);
-                warn!(self.logger, "DP: Set Other Modes"; "blender" => self.pipeline.fmt_blender());
+                info!(self.logger, "DP: Set Other Modes"; "cycle" => ?self.state.cycle_mode(), "blender" => self.pipeline.fmt_blender());
                 self.cmdlen = 0;
             }
-            0x24 => {
-                // Texture rectangle (2 words)
-                if self.cmdlen != 2 {
+            0x30 | 0x32 | 0x33 | 0x34 => {
+                // Load Tlut / Set Tile Size / Load Block / Load Tile
+                let idx = cmd.get_bits(24..27) as usize;
+                let sl = cmd.get_bits(44..56) as u32;
+                let tl = cmd.get_bits(32..44) as u32;
+                let sh = cmd.get_bits(12..24) as u32;
+                let th = cmd.get_bits(0..12) as u32;
+
+                let tex = self.tex;
+                let tile = {
+                    let tile = &mut self.state().tiles[idx];
+                    tile.sl = sl;
+                    tile.tl = tl;
+                    tile.sh = sh;
+                    tile.th = th;
+                    *tile
+                };
+                info!(self.logger, "DP: Load/Set Tile Size"; "cmd" => (op as u8).hex(), "idx" => idx, "tile" => ?tile);
+                if op == 0x32 {
+                    self.cmdlen = 0;
                     return;
                 }
 
-                let tile = self.cmdbuf[0].get_bits(24..27) as usize;
-                let x1 = self.cmdbuf[0].get_bits(44..56) as u32;
-                let y1 = self.cmdbuf[0].get_bits(32..44) as u32;
-                let x0 = self.cmdbuf[0].get_bits(12..24) as u32;
-                let y0 = self.cmdbuf[0].get_bits(0..12) as u32;
-                let mut rect = Rect::<U30F2>::from_bits(x0, y0, x1, y1);
-
-                let s = Q::<I6F10>::from_bits(self.cmdbuf[1].get_bits(48..64) as i16);
-                let t = Q::<I6F10>::from_bits(self.cmdbuf[1].get_bits(32..48) as i16);
-                let dsdx = Q::<I6F10>::from_bits(self.cmdbuf[1].get_bits(16..32) as i16);
-                let dtdy = Q::<I6F10>::from_bits(self.cmdbuf[1].get_bits(0..16) as i16);
-
-                let ptex = Point::new(s, t);
-                let slope = Point::new(dsdx, dtdy);
-                info!(self.logger, "DP: Textured Rectangle"; "idx" => tile, "tile" => ?self.tiles[tile], "screen" => ?rect, "ptex" => ?ptex, "slope" => ?slope);
-
-                let tmem_addr = self.tiles[tile].tmem_addr as usize;
-                let tmem_pitch = self.tiles[tile].pitch;
-                let tex_rect = self.tiles[tile].rect;
-                let src = (
-                    &self.tmem[tmem_addr..],
-                    tex_rect.width().floor() as usize + 1,
-                    tex_rect.height().floor() as usize + 1,
-                    tmem_pitch,
-                );
-
-                let mut fb_writer = R4300::get_mut().bus.fetch_write::<u8>(self.fb.dram_addr);
-                let fb_mem = fb_writer.mem().unwrap();
-                let dst = (fb_mem, 320, 240, self.fb.pitch());
-
-                // FIXME: draw_rect_slopes() use inclusive rectangles... maybe we need clipping?
-                let w = rect.width() - 1;
-                let h = rect.height() - 1;
-                rect.set_width(w);
-                rect.set_height(h);
-
-                let state = DpRenderState {
-                    dst_cf: self.fb.color_format,
-                    dst_bpp: self.fb.bpp,
-                    src_cf: self.tiles[tile].color_format,
-                    src_bpp: self.tiles[tile].bpp,
-                    phantom: PhantomData,
-                };
-                state.draw_rect_slopes(dst, rect, src, ptex.cast(), slope.cast());
-
-                self.cmdlen = 0;
-            }
-            0x34 => {
-                // Load Tile
-                let tile = cmd.get_bits(24..27) as usize;
-                let s0 = cmd.get_bits(44..56) as u32;
-                let t0 = cmd.get_bits(32..44) as u32;
-                let s1 = cmd.get_bits(12..24) as u32;
-                let t1 = cmd.get_bits(0..12) as u32;
-                let mut rect = Rect::<U30F2>::from_bits(s0, t0, s1, t1);
-                info!(self.logger, "DP: Load Tile"; "idx" => tile, "rect" => ?rect);
-
-                // Load_Tile also updates the internal tile rect
-                self.tiles[tile].rect = rect;
-
-                let tmem_addr = self.tiles[tile].tmem_addr as usize;
-                let tmem_pitch = self.tiles[tile].pitch;
-                let tex_reader = R4300::get().bus.fetch_read::<u8>(self.tex.dram_addr);
-                let tex_mem = tex_reader.mem().unwrap();
-                let width = rect.width().floor() as usize + 1;
-                let height = rect.height().floor() as usize + 1;
-
-                let copy_width = width.min(self.tex.width); // FIXME: is this correct? See RDPI4Decode
-                rect.set_width(Q::from_int(copy_width as u32 - 1));
-
-                info!(self.logger, "DP: Load Tile: draw_rect"; "rect" => ?rect, "copy_width" => copy_width);
-                if self.tiles[tile].bpp == 16 && self.tex.bpp == 16 {
-                    let mut tmem = GfxBufferMutLE::<Rgba5551>::new(
-                        &mut self.tmem[tmem_addr..],
-                        copy_width,
-                        height,
-                        tmem_pitch,
-                    )
-                    .unwrap();
-
-                    let tex = GfxBufferLE::<Rgba5551>::new(
-                        &tex_mem,
-                        copy_width,
-                        height,
-                        self.tex.pitch(),
-                    )
-                    .unwrap();
-
-                    draw_rect(
-                        &mut tmem,
-                        Point::<U30F2>::from_int(0, 0),
-                        &tex,
-                        rect.cast::<U27F5>(),
-                    );
-                } else if self.tiles[tile].bpp == 8 && self.tex.bpp == 8 {
-                    let mut tmem = GfxBufferMutLE::<I8>::new(
-                        &mut self.tmem[tmem_addr..],
-                        copy_width,
-                        height,
-                        tmem_pitch,
-                    )
-                    .unwrap();
-
-                    let tex =
-                        GfxBufferLE::<I8>::new(&tex_mem, copy_width, height, self.tex.pitch())
-                            .unwrap();
-
-                    draw_rect(
-                        &mut tmem,
-                        Point::<U30F2>::from_int(0, 0),
-                        &tex,
-                        rect.cast::<U27F5>(),
-                    );
-                } else {
-                    panic!(
-                        "unknown src/dst bpp combination in load tile: dst={} src={}",
-                        self.tiles[tile].bpp, self.tex.bpp,
-                    );
+                // Texture loads read RDRAM, which might have been drawn
+                // by queued primitives.
+                self.flush();
+                let rdram = Rdram::get();
+                let tmem = Arc::make_mut(&mut self.state().tmem);
+                match op {
+                    0x30 => tmem::load_tlut(tmem, &rdram, &tex, &tile, (sl, tl, sh, th)),
+                    0x33 => tmem::load_block(tmem, &rdram, &tex, &tile, (sl, tl, sh, th)),
+                    _ => tmem::load_tile(tmem, &rdram, &tex, &tile, (sl, tl, sh, th)),
                 }
-
                 self.cmdlen = 0;
             }
             0x35 => {
                 // Set Tile
                 let idx = cmd.get_bits(24..27) as usize;
                 let color_format = self.parse_color_format(cmd.get_bits(53..56));
-                let tile = &mut self.tiles[idx];
+                let tile = &mut self.state().tiles[idx];
                 tile.color_format = color_format;
                 tile.bpp = 4 << cmd.get_bits(51..53);
                 tile.pitch = cmd.get_bits(41..50) as usize * 8;
@@ -297,78 +561,88 @@ impl Rdp {
                 tile.mask[1] = (1 << cmd.get_bits(14..18)) - 1;
                 tile.shift[0] = cmd.get_bits(0..4) as u32;
                 tile.shift[1] = cmd.get_bits(10..14) as u32;
+                let tile = *tile;
                 info!(self.logger, "DP: Set Tile"; "idx" => idx, "format" => ?tile);
                 self.cmdlen = 0;
             }
             0x36 => {
-                let x1 = cmd.get_bits(44..56) as u32;
-                let y1 = cmd.get_bits(32..44) as u32;
-                let x0 = cmd.get_bits(12..24) as u32;
-                let y0 = cmd.get_bits(0..12) as u32;
-                let mut rect = Rect::<U30F2>::from_bits(x0, y0, x1, y1);
-                info!(self.logger, "DP: Fill Rectangle"; "rect" => ?rect);
-
-                match self.cycle_mode {
-                    CycleMode::Fill => {
-                        // Fill rectangle works with 32-bit packed words. Thus, we treat everything
-                        // as RGBA8888, but we need to convert the rect coordinates to adjust them
-                        // to a fake 32-bit resolution.
-                        let bppconv = 32 / self.fb.bpp as u32;
-
-                        rect.c0.x /= bppconv;
-                        rect.c0.y /= bppconv;
-                        rect.c1.x = ((rect.c1.x + 1) / bppconv) - 1;
-                        rect.c1.y = ((rect.c1.y + 1) / bppconv) - 1;
-
-                        if rect.truncate().cast::<U30F2>() != rect {
-                            panic!("Coordinates in DP Fill Rectangle were not 32-bit aligned");
-                        }
-
-                        let fb = self.framebuffer();
-                        let mut dst = GfxBufferMut::<Rgba8888, BigEndian>::new(
-                            fb.0,
-                            fb.1 / bppconv as usize,
-                            fb.2,
-                            fb.3,
-                        )
-                        .unwrap();
-                        let color = Color::<Rgba8888>::from_bits(self.fill_color);
-                        fill_rect(&mut dst, rect, color);
-                    }
-                    CycleMode::One => {
-                        let fb = self.framebuffer();
-                        let mut dst =
-                            GfxBufferMut::<Rgba8888, LittleEndian>::new(fb.0, fb.1, fb.2, fb.3)
-                                .unwrap();
-
-                        if rect.truncate().cast::<U30F2>() != rect {
-                            panic!("Coordinates in DP Fill Rectangle were not 32-bit aligned");
-                        }
-
-                        let color = Color::<Abgr8888>::from_bits(self.fill_color); // FIXME: this is probably not correct
-                        fill_rect_pp(&mut dst, rect, color, &mut self.pipeline);
-                    }
-                    _ => unimplemented!(),
-                }
+                // Fill Rectangle
+                let prim = Primitive::FillRect {
+                    x1: cmd.get_bits(44..56) as i32,
+                    y1: cmd.get_bits(32..44) as i32,
+                    x0: cmd.get_bits(12..24) as i32,
+                    y0: cmd.get_bits(0..12) as i32,
+                };
+                info!(self.logger, "DP: Fill Rectangle"; "rect" => ?prim);
+                self.push(prim);
                 self.cmdlen = 0;
             }
             0x37 => {
                 let color = cmd.get_bits(0..32) as u32;
                 info!(self.logger, "DP: Set Fill Color"; "color" => color.hex());
-                self.fill_color = color;
+                self.state().fill_color = color;
+                self.cmdlen = 0;
+            }
+            0x38 => {
+                let color = cmd.get_bits(0..32) as u32;
+                info!(self.logger, "DP: Set Fog Color"; "color" => color.hex());
+                self.state().fog_color = color;
+                self.cmdlen = 0;
+            }
+            0x39 => {
+                let color = cmd.get_bits(0..32) as u32;
+                info!(self.logger, "DP: Set Blend Color"; "color" => color.hex());
+                self.state().blend_color = color;
+                self.cmdlen = 0;
+            }
+            0x3A => {
+                let color = cmd.get_bits(0..32) as u32;
+                let lod_frac = cmd.get_bits(32..40) as u8;
+                info!(self.logger, "DP: Set Prim Color"; "color" => color.hex(), "lod_frac" => lod_frac);
+                self.state().prim_color = color;
+                self.state().prim_lod_frac = lod_frac;
+                self.cmdlen = 0;
+            }
+            0x3B => {
+                let color = cmd.get_bits(0..32) as u32;
+                info!(self.logger, "DP: Set Env Color"; "color" => color.hex());
+                self.state().env_color = color;
                 self.cmdlen = 0;
             }
             0x3C => {
                 // Set Combine Mode
+                self.state().combine_mode = cmd;
                 self.pipeline.set_combine_mode(cmd);
                 info!(self.logger, "DP: Set Combine Mode"; "cmd" => cmd.hex(), "cc" => self.pipeline.fmt_combiner());
                 self.cmdlen = 0;
             }
-            0x39 => {
-                // Set Blend Color
-                let c = Color::<Abgr8888>::from_bits(cmd as u32);
-                self.pipeline.set_blend_color(c.cconv());
-                info!(self.logger, "DP: Set Blend Color"; "c" => ?c);
+            0x3D | 0x3F => {
+                // Set Color/Texture Image
+                let format = ImageFormat {
+                    color_format: self.parse_color_format(cmd.get_bits(53..56)),
+                    bpp: 4 << cmd.get_bits(51..53),
+                    width: cmd.get_bits(32..42) as usize + 1,
+                    dram_addr: cmd.get_bits(0..26) as u32,
+                };
+
+                if op == 0x3F {
+                    self.state().fb = format;
+                    info!(self.logger, "DP: Set Color Image"; "format" => ?format);
+                } else {
+                    self.tex = format;
+                    info!(self.logger, "DP: Set Texture Image"; "format" => ?format);
+                }
+                self.cmdlen = 0;
+            }
+            0x3E => {
+                // Set Z Image
+                self.state().z_addr = cmd.get_bits(0..26) as u32;
+                info!(self.logger, "DP: Set Z Image"; "addr" => self.state.z_addr.hex());
+                self.cmdlen = 0;
+            }
+            0x00 | 0x2A | 0x2B | 0x2C => {
+                // No Op / Set Key GB / Set Key R / Set Convert (chroma key
+                // and YUV conversion are not implemented)
                 self.cmdlen = 0;
             }
 
@@ -0,0 +1,806 @@
+// Software rasterizer
+//
+// Primitives (triangles and rectangles) are queued by the command processor,
+// each with a snapshot of the RDP state it must be drawn with, and rendered
+// in batches by a pool of worker threads. The framebuffer is split into
+// strips of scanlines, interleaved across workers: each worker draws all the
+// primitives of the batch, in order, but only within its own strips. So no
+// synchronization is needed between workers, and the result does not depend
+// on the number of threads. Batches can also be drawn in background, while
+// the emulation keeps running (see Renderer).
+
+// TODO:
+//   * coverage and antialiasing
+//   * dithering
+//   * compressed z-buffer format (a linear 14-bit depth is stored instead)
+//   * subpixel accuracy of edges (pixels are sampled at their center)
+
+extern crate emu;
+
+use super::super::r4300::R4300;
+use super::pipeline::PixelPipeline;
+use super::rdp::RenderState;
+use super::tmem;
+use super::{CycleMode, MultiColor};
+use emu::bus::be::Device;
+use emu::gfx::{Abgr8888, Color, ColorConverter};
+
+use std::cell::Cell;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+
+// Height of the strips of scanlines assigned to each worker thread.
+const STRIP_HEIGHT: i32 = 8;
+// Maximum number of primitives queued before rendering them.
+const MAX_BATCH: usize = 4096;
+
+/// Raw access to RDRAM for the renderer. Worker threads write to RDRAM
+/// concurrently, but always to different scanlines. In background mode, the
+/// emulation keeps running while workers draw: the RDP fences on all the
+/// accesses through which the CPU can observe that rendering has finished,
+/// so only accesses that would race on the real hardware see partial results.
+#[derive(Copy, Clone)]
+pub(crate) struct Rdram {
+    ptr: *mut u8,
+    len: usize,
+}
+
+unsafe impl Send for Rdram {}
+unsafe impl Sync for Rdram {}
+
+impl Rdram {
+    pub(crate) fn get() -> Rdram {
+        let mut io = R4300::get_mut().bus.fetch_write::<u8>(0);
+        let mem = io.mem().unwrap();
+        Rdram {
+            ptr: mem.as_mut_ptr(),
+            len: mem.len(),
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn read_u8(&self, addr: u32) -> u8 {
+        let addr = addr as usize & 0x00FF_FFFF;
+        if addr < self.len {
+            unsafe { *self.ptr.add(addr) }
+        } else {
+            0
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn write_u8(&self, addr: u32, val: u8) {
+        let addr = addr as usize & 0x00FF_FFFF;
+        if addr < self.len {
+            unsafe { *self.ptr.add(addr) = val }
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn read_u16(&self, addr: u32) -> u16 {
+        (self.read_u8(addr) as u16) << 8 | self.read_u8(addr + 1) as u16
+    }
+
+    #[inline(always)]
+    pub(crate) fn write_u16(&self, addr: u32, val: u16) {
+        self.write_u8(addr, (val >> 8) as u8);
+        self.write_u8(addr + 1, val as u8);
+    }
+
+    #[inline(always)]
+    pub(crate) fn read_u32(&self, addr: u32) -> u32 {
+        (self.read_u16(addr) as u32) << 16 | self.read_u16(addr + 2) as u32
+    }
+
+    #[inline(always)]
+    pub(crate) fn write_u32(&self, addr: u32, val: u32) {
+        self.write_u16(addr, (val >> 16) as u16);
+        self.write_u16(addr + 2, val as u16);
+    }
+}
+
+/// Coefficients of an attribute interpolated across a triangle (s15.16):
+/// value at the start of the major edge, and derivatives along x, along
+/// the major edge, and along y.
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct Attr {
+    pub(crate) v: i32,
+    pub(crate) dx: i32,
+    pub(crate) de: i32,
+    pub(crate) dy: i32,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Triangle {
+    pub(crate) lft: bool, // major edge on the left
+    pub(crate) tile: usize,
+    pub(crate) yh: i32, // s11.2
+    pub(crate) ym: i32,
+    pub(crate) yl: i32,
+    pub(crate) xh: i32, // s15.16
+    pub(crate) xm: i32,
+    pub(crate) xl: i32,
+    pub(crate) dxhdy: i32,
+    pub(crate) dxmdy: i32,
+    pub(crate) dxldy: i32,
+    pub(crate) shade: Option<[Attr; 4]>, // RGBA
+    pub(crate) tex: Option<[Attr; 3]>,   // S, T, W
+    pub(crate) z: Option<Attr>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Primitive {
+    Triangle(Triangle),
+    FillRect {
+        x0: i32, // 10.2
+        y0: i32,
+        x1: i32,
+        y1: i32,
+    },
+    TexRect {
+        tile: usize,
+        x0: i32, // 10.2
+        y0: i32,
+        x1: i32,
+        y1: i32,
+        s: i32, // s10.5
+        t: i32,
+        dsdx: i32, // s5.10
+        dtdy: i32,
+        flip: bool,
+    },
+}
+
+#[derive(Copy, Clone)]
+struct Strips {
+    index: i32,
+    count: i32,
+}
+
+impl Strips {
+    #[inline(always)]
+    fn contains(&self, y: i32) -> bool {
+        (y / STRIP_HEIGHT) % self.count == self.index
+    }
+}
+
+#[inline(always)]
+fn mul16(a: i32, b: i32) -> i32 {
+    ((a as i64 * b as i64) >> 16) as i32
+}
+
+#[inline(always)]
+fn to_mc(c: [u8; 4]) -> MultiColor {
+    let (r, g, b, a) = (c[0] as u16, c[1] as u16, c[2] as u16, c[3] as u16);
+    MultiColor::new(r, g, b, a, r, g, b, a)
+}
+
+#[inline(always)]
+fn from_mc(c: MultiColor) -> [u8; 4] {
+    [
+        c.extract(0) as u8,
+        c.extract(1) as u8,
+        c.extract(2) as u8,
+        c.extract(3) as u8,
+    ]
+}
+
+fn color(c: u32) -> Color<emu::gfx::Rgba8888> {
+    Color::<Abgr8888>::from_bits(c).cconv()
+}
+
+// Rasterizer draws primitives into RDRAM, restricted to a set of strips.
+struct Rasterizer {
+    pp: Box<PixelPipeline>,
+    state: Option<Arc<RenderState>>,
+    strips: Strips,
+}
+
+impl Rasterizer {
+    fn new(strips: Strips) -> Rasterizer {
+        Rasterizer {
+            pp: Box::new(PixelPipeline::new()),
+            state: None,
+            strips,
+        }
+    }
+
+    // Configure the pixel pipeline for the specified state, unless it's
+    // already the current one.
+    fn set_state(&mut self, st: &Arc<RenderState>) {
+        if let Some(cur) = &self.state {
+            if Arc::ptr_eq(cur, st) {
+                return;
+            }
+        }
+        self.pp.set_combine_mode(st.combine_mode);
+        self.pp.set_other_modes(st.other_modes);
+        self.pp.set_prim_color(color(st.prim_color));
+        self.pp.set_prim_lod_frac(st.prim_lod_frac);
+        self.pp.set_env_color(color(st.env_color));
+        self.pp.set_blend_color(color(st.blend_color));
+        self.pp.set_fog_color(color(st.fog_color));
+        self.state = Some(st.clone());
+    }
+
+    fn draw(&mut self, st: &Arc<RenderState>, prim: &Primitive, rdram: &Rdram) {
+        self.set_state(st);
+        match prim {
+            Primitive::Triangle(tri) => self.draw_triangle(st, tri, rdram),
+            Primitive::FillRect { x0, y0, x1, y1 } => {
+                self.draw_rect(st, rdram, (*x0, *y0, *x1, *y1), None)
+            }
+            Primitive::TexRect {
+                tile,
+                x0,
+                y0,
+                x1,
+                y1,
+                s,
+                t,
+                dsdx,
+                dtdy,
+                flip,
+            } => self.draw_rect(
+                st,
+                rdram,
+                (*x0, *y0, *x1, *y1),
+                Some((*tile, *s, *t, *dsdx, *dtdy, *flip)),
+            ),
+        }
+    }
+
+    fn draw_triangle(&mut self, st: &RenderState, tri: &Triangle, rdram: &Rdram) {
+        let (cx0, cy0, cx1, cy1) = st.clip_rect();
+        let ytop = tri.yh >> 2;
+        for y in ytop.max(cy0)..((tri.yl + 3) >> 2).min(cy1) {
+            if !self.strips.contains(y) {
+                continue;
+            }
+
+            // Sample at the center of the scanline.
+            let yc = (y << 16) + 0x8000;
+            if yc < tri.yh << 14 || yc >= tri.yl << 14 {
+                continue;
+            }
+            let dy = yc - (ytop << 16);
+            let xmaj = tri.xh + mul16(tri.dxhdy, dy);
+            let xmin = if yc < tri.ym << 14 {
+                tri.xm + mul16(tri.dxmdy, dy)
+            } else {
+                tri.xl + mul16(tri.dxldy, yc - (tri.ym << 14))
+            };
+            let (left, right) = if tri.lft { (xmaj, xmin) } else { (xmin, xmaj) };
+            let x0 = ((left + 0x7FFF) >> 16).max(cx0);
+            let x1 = ((right + 0x7FFF) >> 16).min(cx1);
+            if x0 >= x1 {
+                continue;
+            }
+
+            // Attributes at the center of the first pixel of the span.
+            let dx = (x0 << 16) + 0x8000 - xmaj;
+            let at = |a: &Attr| a.v + mul16(a.de, dy) + mul16(a.dx, dx);
+            let mut shade = [0i32; 4];
+            let mut stw = [0i32; 3];
+            let mut z = 0;
+            if let Some(attrs) = &tri.shade {
+                for i in 0..4 {
+                    shade[i] = at(&attrs[i]);
+                }
+            }
+            if let Some(attrs) = &tri.tex {
+                for i in 0..3 {
+                    stw[i] = at(&attrs[i]);
+                }
+            }
+            if let Some(attr) = &tri.z {
+                z = at(attr);
+            }
+
+            for x in x0..x1 {
+                let rgba = [
+                    (shade[0] >> 16).max(0).min(255) as u8,
+                    (shade[1] >> 16).max(0).min(255) as u8,
+                    (shade[2] >> 16).max(0).min(255) as u8,
+                    (shade[3] >> 16).max(0).min(255) as u8,
+                ];
+                let tex = tri.tex.map(|_| {
+                    if st.persp() {
+                        let w = stw[2].max(1) as i64;
+                        let s = (stw[0] as i64 * 0x7FFF / w) as i32;
+                        let t = (stw[1] as i64 * 0x7FFF / w) as i32;
+                        (tri.tile, s, t)
+                    } else {
+                        (tri.tile, stw[0] >> 16, stw[1] >> 16)
+                    }
+                });
+                let zpix = (z >> 16).max(0).min(0x7FFF) as u32;
+                self.shade_pixel(st, rdram, x, y, rgba, tex, zpix);
+
+                if let Some(attrs) = &tri.shade {
+                    for i in 0..4 {
+                        shade[i] = shade[i].wrapping_add(attrs[i].dx);
+                    }
+                }
+                if let Some(attrs) = &tri.tex {
+                    for i in 0..3 {
+                        stw[i] = stw[i].wrapping_add(attrs[i].dx);
+                    }
+                }
+                if let Some(attr) = &tri.z {
+                    z = z.wrapping_add(attr.dx);
+                }
+            }
+        }
+    }
+
+    fn draw_rect(
+        &mut self,
+        st: &RenderState,
+        rdram: &Rdram,
+        (x0, y0, x1, y1): (i32, i32, i32, i32),
+        tex: Option<(usize, i32, i32, i32, i32, bool)>,
+    ) {
+        let (cx0, cy0, cx1, cy1) = st.clip_rect();
+
+        // In fill and copy modes, the bottom-right corner is inclusive.
+        let mode = st.cycle_mode();
+        let (xe, ye) = match mode {
+            CycleMode::Fill | CycleMode::Copy => ((x1 >> 2) + 1, (y1 >> 2) + 1),
+            _ => ((x1 + 3) >> 2, (y1 + 3) >> 2),
+        };
+        let (xs, ys) = (x0 >> 2, y0 >> 2);
+
+        for y in ys.max(cy0)..ye.min(cy1) {
+            if !self.strips.contains(y) {
+                continue;
+            }
+            for x in xs.max(cx0)..xe.min(cx1) {
+                // Texture coordinates: in copy mode, dsdx is expressed in
+                // units of 4 pixels.
+                let texel = tex.map(|(tile, s, t, dsdx, dtdy, flip)| {
+                    let step = if let CycleMode::Copy = mode { 7 } else { 5 };
+                    let (dx, dy) = if flip {
+                        (y - ys, x - xs)
+                    } else {
+                        (x - xs, y - ys)
+                    };
+                    (tile, s + ((dsdx * dx) >> step), t + ((dtdy * dy) >> 5))
+                });
+
+                match mode {
+                    CycleMode::Fill => self.fill_pixel(st, rdram, x, y),
+                    CycleMode::Copy => {
+                        if let Some((tile, s, t)) = texel {
+                            self.copy_pixel(st, rdram, x, y, tile, s, t);
+                        }
+                    }
+                    _ => self.shade_pixel(st, rdram, x, y, [0; 4], texel, st.prim_z),
+                }
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn fb_addr(st: &RenderState, x: i32, y: i32) -> u32 {
+        let idx = y as u32 * st.fb.width as u32 + x as u32;
+        st.fb.dram_addr + idx * st.fb.bpp as u32 / 8
+    }
+
+    fn read_fb(st: &RenderState, rdram: &Rdram, addr: u32) -> [u8; 4] {
+        match st.fb.bpp {
+            32 => {
+                let v = rdram.read_u32(addr);
+                [
+                    (v >> 24) as u8,
+                    (v >> 16) as u8,
+                    (v >> 8) as u8,
+                    v as u8 & 0xE0,
+                ]
+            }
+            16 => {
+                let v = rdram.read_u16(addr);
+                let c = |v: u16| ((v & 0x1F) << 3 | (v & 0x1F) >> 2) as u8;
+                let cvg = if v & 1 != 0 { 0xE0 } else { 0 };
+                [c(v >> 11), c(v >> 6), c(v >> 1), cvg]
+            }
+            _ => {
+                let v = rdram.read_u8(addr);
+                [v, v, v, v]
+            }
+        }
+    }
+
+    fn write_fb(st: &RenderState, rdram: &Rdram, addr: u32, c: [u8; 4]) {
+        match st.fb.bpp {
+            32 => rdram.write_u32(
+                addr,
+                (c[0] as u32) << 24 | (c[1] as u32) << 16 | (c[2] as u32) << 8 | c[3] as u32,
+            ),
+            16 => rdram.write_u16(
+                addr,
+                (c[0] as u16 >> 3) << 11
+                    | (c[1] as u16 >> 3) << 6
+                    | (c[2] as u16 >> 3) << 1
+                    | (c[3] >= 0x80) as u16,
+            ),
+            _ => rdram.write_u8(addr, c[0]),
+        }
+    }
+
+    // Fill mode: write the fill color, which holds two 16-bit pixels (or
+    // four 8-bit pixels).
+    fn fill_pixel(&mut self, st: &RenderState, rdram: &Rdram, x: i32, y: i32) {
+        let addr = Self::fb_addr(st, x, y);
+        match st.fb.bpp {
+            32 => rdram.write_u32(addr, st.fill_color),
+            16 => {
+                let shift = if x & 1 == 0 { 16 } else { 0 };
+                rdram.write_u16(addr, (st.fill_color >> shift) as u16);
+            }
+            _ => rdram.write_u8(addr, (st.fill_color >> (24 - 8 * (x & 3))) as u8),
+        }
+    }
+
+    // Copy mode: texels are written directly to the framebuffer, skipping
+    // the pixel pipeline (except alpha compare).
+    fn copy_pixel(
+        &mut self,
+        st: &RenderState,
+        rdram: &Rdram,
+        x: i32,
+        y: i32,
+        tile: usize,
+        s: i32,
+        t: i32,
+    ) {
+        let texel = tmem::sample(st, tile, s, t, false);
+        if st.alpha_compare() && texel[3] == 0 {
+            return;
+        }
+        Self::write_fb(st, rdram, Self::fb_addr(st, x, y), texel);
+    }
+
+    // 1-cycle and 2-cycle modes: run the pixel through depth test, texture
+    // sampling, color combiner, alpha compare and blender.
+    fn shade_pixel(
+        &mut self,
+        st: &RenderState,
+        rdram: &Rdram,
+        x: i32,
+        y: i32,
+        shade: [u8; 4],
+        tex: Option<(usize, i32, i32)>,
+        z: u32,
+    ) {
+        let idx = y as u32 * st.fb.width as u32 + x as u32;
+        let zaddr = st.z_addr + idx * 2;
+        let z = if st.z_source_prim() { st.prim_z } else { z };
+        let znew = (z >> 1).min(0x3FFF) as u16;
+        if st.z_compare() {
+            let zold = rdram.read_u16(zaddr) >> 2;
+            let pass = if st.z_mode() == 3 {
+                znew <= zold
+            } else {
+                znew < zold
+            };
+            if !pass {
+                return;
+            }
+        }
+
+        let two_cycle = if let CycleMode::Two = st.cycle_mode() {
+            true
+        } else {
+            false
+        };
+        let (tex0, tex1) = match tex {
+            Some((tile, s, t)) => {
+                let tex0 = tmem::sample(st, tile, s, t, st.bilerp());
+                let tex1 = if two_cycle {
+                    tmem::sample(st, tile + 1, s, t, st.bilerp())
+                } else {
+                    tex0
+                };
+                (tex0, tex1)
+            }
+            None => ([0; 4], [0; 4]),
+        };
+
+        let addr = Self::fb_addr(st, x, y);
+        let fb = Self::read_fb(st, rdram, addr);
+        let (combined, blended) =
+            self.pp
+                .calc_pixels(two_cycle, to_mc(shade), to_mc(tex0), to_mc(tex1), to_mc(fb));
+
+        if st.alpha_compare() {
+            let threshold = if st.dither_alpha() {
+                0x80
+            } else {
+                st.blend_color & 0xFF
+            };
+            if (combined.extract(3) as u32) < threshold {
+                return;
+            }
+        }
+
+        // Coverage is not emulated: pixels are always fully covered.
+        let mut out = from_mc(blended);
+        out[3] = 0xE0;
+        Self::write_fb(st, rdram, addr, out);
+        if st.z_update() {
+            rdram.write_u16(zaddr, znew << 2);
+        }
+    }
+}
+
+struct Job {
+    batch: Arc<Vec<(Arc<RenderState>, Primitive)>>,
+    rdram: Rdram,
+}
+
+/// Renderer queues primitives and draws them in batches, splitting the work
+/// across a pool of worker threads.
+///
+/// In background mode, batches are handed off to the workers without waiting
+/// for them to be drawn, so that the emulation can proceed while they are
+/// rasterized; the caller must then fence (see `fence()`) before anything
+/// else looks at the memory drawn by the RDP. Without worker threads, a
+/// single worker is spawned to render in background.
+pub(crate) struct Renderer {
+    queue: Vec<(Arc<RenderState>, Primitive)>,
+    local: Rasterizer,
+    workers: Vec<mpsc::Sender<Job>>,
+    done: Option<mpsc::Receiver<()>>,
+    threads: usize,
+    background: bool,
+
+    // Number of completion notifications still expected from the workers
+    pending: Cell<usize>,
+}
+
+impl Renderer {
+    pub(crate) fn new() -> Renderer {
+        Renderer {
+            queue: Vec::new(),
+            local: Rasterizer::new(Strips { index: 0, count: 1 }),
+            workers: Vec::new(),
+            done: None,
+            threads: 0,
+            background: false,
+            pending: Cell::new(0),
+        }
+    }
+
+    /// Change the number of worker threads. With zero or one, primitives
+    /// are drawn on the calling thread (unless in background mode).
+    pub(crate) fn set_threads(&mut self, n: usize) {
+        self.flush();
+        self.threads = n;
+
+        // Dropping the job channels terminates the current workers.
+        self.workers.clear();
+        self.done = None;
+        let n = if n <= 1 { self.background as usize } else { n };
+        if n == 0 {
+            return;
+        }
+
+        let (done_tx, done_rx) = mpsc::channel();
+        for i in 0..n {
+            let (tx, rx) = mpsc::channel::<Job>();
+            let done_tx = done_tx.clone();
+            let strips = Strips {
+                index: i as i32,
+                count: n as i32,
+            };
+            thread::Builder::new()
+                .name(format!("rdp-worker-{}", i))
+                .spawn(move || {
+                    let mut rast = Rasterizer::new(strips);
+                    for job in rx.iter() {
+                        for (st, prim) in job.batch.iter() {
+                            rast.draw(st, prim, &job.rdram);
+                        }
+                        drop(job);
+                        if done_tx.send(()).is_err() {
+                            break;
+                        }
+                    }
+                })
+                .expect("cannot spawn RDP worker thread");
+            self.workers.push(tx);
+        }
+        self.done = Some(done_rx);
+    }
+
+    /// Enable or disable background rendering.
+    pub(crate) fn set_background(&mut self, background: bool) {
+        if self.background != background {
+            self.flush();
+            self.background = background;
+            self.set_threads(self.threads);
+        }
+    }
+
+    pub(crate) fn push(&mut self, st: &Arc<RenderState>, prim: Primitive) {
+        self.queue.push((st.clone(), prim));
+        if self.queue.len() >= MAX_BATCH {
+            self.submit();
+        }
+    }
+
+    /// Start drawing all the queued primitives. Unless in background mode,
+    /// they are in RDRAM when this function returns.
+    pub(crate) fn submit(&mut self) {
+        if self.queue.is_empty() {
+            return;
+        }
+        let rdram = Rdram::get();
+        let batch = std::mem::replace(&mut self.queue, Vec::new());
+        if self.workers.is_empty() {
+            for (st, prim) in batch.iter() {
+                self.local.draw(st, prim, &rdram);
+            }
+            return;
+        }
+
+        let batch = Arc::new(batch);
+        for w in &self.workers {
+            w.send(Job {
+                batch: batch.clone(),
+                rdram,
+            })
+            .expect("RDP worker thread died");
+        }
+        self.pending.set(self.pending.get() + self.workers.len());
+        if !self.background {
+            self.fence();
+        }
+    }
+
+    /// Wait until all the submitted primitives are in RDRAM.
+    pub(crate) fn fence(&self) {
+        if let Some(done) = &self.done {
+            while self.pending.get() > 0 {
+                done.recv().expect("RDP worker thread died");
+                self.pending.set(self.pending.get() - 1);
+            }
+        }
+    }
+
+    /// Draw all the queued primitives, and wait until they are in RDRAM.
+    pub(crate) fn flush(&mut self) {
+        self.submit();
+        self.fence();
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        // Workers must not write into RDRAM after it's gone.
+        if let Some(done) = &self.done {
+            for _ in 0..self.pending.get() {
+                if done.recv().is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ri::{RdramSize, Ri};
+    use super::super::rdp::ImageFormat;
+    use super::super::DpColorFormat;
+    use super::*;
+
+    const FB_ADDR: u32 = 0x10000;
+    const FB_WIDTH: usize = 16;
+
+    fn setup_rdram() -> Rdram {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        R4300::new(logger.new(o!())).register();
+        Ri::new(logger, RdramSize::Mb4).register();
+        R4300::get_mut()
+            .bus
+            .map_device(0x0000_0000, Ri::get(), 0)
+            .unwrap();
+        Rdram::get()
+    }
+
+    fn state(bpp: usize, other_modes: u64) -> RenderState {
+        let mut st = RenderState::new();
+        st.fb = ImageFormat {
+            color_format: DpColorFormat::Rgba,
+            bpp,
+            width: FB_WIDTH,
+            dram_addr: FB_ADDR,
+        };
+        st.clip = [0, 0, (FB_WIDTH as i32) << 2, (FB_WIDTH as i32) << 2];
+        st.other_modes = other_modes;
+        st
+    }
+
+    fn render(st: RenderState, prim: Primitive) {
+        let mut r = Renderer::new();
+        r.push(&Arc::new(st), prim);
+        r.flush();
+    }
+
+    #[test]
+    fn fill_rect() {
+        let rdram = setup_rdram();
+        let mut st = state(16, 3 << 52); // fill mode
+        st.fill_color = 0xF801_07C1;
+
+        // In fill mode, the bottom-right corner is included.
+        render(
+            st,
+            Primitive::FillRect {
+                x0: 2 << 2,
+                y0: 1 << 2,
+                x1: 5 << 2,
+                y1: 3 << 2,
+            },
+        );
+        for y in 0..FB_WIDTH as u32 {
+            for x in 0..FB_WIDTH as u32 {
+                let pix = rdram.read_u16(FB_ADDR + (y * FB_WIDTH as u32 + x) * 2);
+                let expected = match (x, y) {
+                    (2..=5, 1..=3) if x & 1 == 0 => 0xF801,
+                    (2..=5, 1..=3) => 0x07C1,
+                    _ => 0,
+                };
+                assert_eq!(pix, expected, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn shaded_triangle() {
+        // 1-cycle mode, with the combiner outputting the shade color and
+        // the blender passing it through.
+        let rdram = setup_rdram();
+        let mut st = state(32, 0);
+        st.combine_mode = 8 << 37 | 16 << 32 | 8 << 24 | 4 << 6 | 7 << 21 | 7 << 18 | 7 << 3 | 4;
+
+        // Vertices (4,2), (12,10), (4,10): the major edge is vertical, on
+        // the left, and the middle edge has slope 1.
+        let flat = |v: i32| Attr {
+            v: v << 16,
+            ..Default::default()
+        };
+        render(
+            st,
+            Primitive::Triangle(Triangle {
+                lft: true,
+                tile: 0,
+                yh: 2 << 2,
+                ym: 10 << 2,
+                yl: 10 << 2,
+                xh: 4 << 16,
+                xm: 4 << 16,
+                xl: 12 << 16,
+                dxhdy: 0,
+                dxmdy: 1 << 16,
+                dxldy: 0,
+                shade: Some([flat(0x11), flat(0x22), flat(0x33), flat(0xFF)]),
+                tex: None,
+                z: None,
+            }),
+        );
+
+        // Pixels are drawn if their center is within the triangle.
+        for y in 0..FB_WIDTH as u32 {
+            for x in 0..FB_WIDTH as u32 {
+                let pix = rdram.read_u32(FB_ADDR + (y * FB_WIDTH as u32 + x) * 4);
+                let inside = x >= 4 && x < y + 2 && y < 10;
+                let expected = if inside { 0x1122_33E0 } else { 0 };
+                assert_eq!(pix, expected, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+}
@@ -0,0 +1,494 @@
+// Texture memory: loading from RDRAM and texel sampling
+//
+// TMEM is 4 KiB, organized in 64-bit words. Texels of odd lines are stored
+// with the two 32-bit halves of each word swapped (both by Load Tile and by
+// Load Block, through its dxt counter), and the sampler swaps them back.
+// 32-bit RGBA texels are split: red/green in the low half of TMEM, and
+// blue/alpha at the same offset in the high half. Palettes (TLUT) are in the
+// high half, with each 16-bit entry replicated four times.
+
+// TODO:
+//   * mipmapping / LOD, detail and sharpen textures
+//   * YUV textures
+//   * 3-point bilinear filter (a standard 4-texel filter is used)
+
+use super::rdp::{ImageFormat, RenderState, TileDescriptor};
+use super::soft::Rdram;
+use super::DpColorFormat;
+
+const TMEM_SIZE: usize = 4096;
+const TLUT_BASE: usize = 0x800;
+
+// Byte address in TMEM, applying the swap of odd lines.
+#[inline(always)]
+fn tmem_addr(addr: usize, odd: bool) -> usize {
+    (addr ^ if odd { 4 } else { 0 }) & (TMEM_SIZE - 1)
+}
+
+// Copy a single texel of the texture image into TMEM. For 32-bit texels,
+// `off` is the offset within the low half.
+#[inline(always)]
+fn store_texel(tmem: &mut [u8], rdram: &Rdram, src: u32, bpp: usize, off: usize, odd: bool) {
+    match bpp {
+        32 => {
+            let dst = tmem_addr(off, odd) & (TLUT_BASE - 1);
+            tmem[dst] = rdram.read_u8(src);
+            tmem[dst + 1] = rdram.read_u8(src + 1);
+            tmem[dst + TLUT_BASE] = rdram.read_u8(src + 2);
+            tmem[dst + TLUT_BASE + 1] = rdram.read_u8(src + 3);
+        }
+        _ => {
+            for i in 0..bpp / 8 {
+                tmem[tmem_addr(off + i, odd)] = rdram.read_u8(src + i as u32);
+            }
+        }
+    }
+}
+
+/// Load Tile: copy a rectangle of the texture image into TMEM, as described
+/// by the tile. Coordinates are in 10.2 fixed point.
+pub(crate) fn load_tile(
+    tmem: &mut [u8],
+    rdram: &Rdram,
+    tex: &ImageFormat,
+    tile: &TileDescriptor,
+    (sl, tl, sh, th): (u32, u32, u32, u32),
+) {
+    // 4-bit textures cannot be loaded with Load Tile: they are usually
+    // loaded as 8-bit, so fallback to that.
+    let bpp = tex.bpp.max(8);
+    let bytes = bpp / 8;
+    let tmem_bytes = bytes.min(2); // 32-bit texels are split in two halves
+    for (line, t) in (tl >> 2..=th >> 2).enumerate() {
+        let src = tex.dram_addr + (t * tex.width as u32 + (sl >> 2)) * bytes as u32;
+        let base = tile.tmem_addr as usize + line * tile.pitch;
+        for (i, _) in (sl >> 2..=sh >> 2).enumerate() {
+            store_texel(
+                tmem,
+                rdram,
+                src + (i * bytes) as u32,
+                bpp,
+                base + i * tmem_bytes,
+                line & 1 != 0,
+            );
+        }
+    }
+}
+
+/// Load Block: copy a linear sequence of texels into TMEM. The line counter
+/// is incremented by dxt (1.11 fixed point) for each 64-bit word, and words
+/// of odd lines are swapped.
+pub(crate) fn load_block(
+    tmem: &mut [u8],
+    rdram: &Rdram,
+    tex: &ImageFormat,
+    tile: &TileDescriptor,
+    (sl, tl, sh, dxt): (u32, u32, u32, u32),
+) {
+    let bpp = tex.bpp;
+    let count = (sh.saturating_sub(sl) + 1) as usize;
+    let src = tex.dram_addr + ((tl * tex.width as u32 + sl) as usize * bpp / 8) as u32;
+    let base = tile.tmem_addr as usize;
+
+    if bpp == 32 {
+        for i in 0..count {
+            let word = i * 2 / 8;
+            let odd = (word as u32 * dxt) >> 11 & 1 != 0;
+            store_texel(tmem, rdram, src + i as u32 * 4, 32, base + i * 2, odd);
+        }
+    } else {
+        let bytes = (count * bpp + 7) / 8;
+        for i in 0..bytes {
+            let odd = ((i / 8) as u32 * dxt) >> 11 & 1 != 0;
+            tmem[tmem_addr(base + i, odd)] = rdram.read_u8(src + i as u32);
+        }
+    }
+}
+
+/// Load TLUT: copy palette entries (16-bit) into the high half of TMEM,
+/// replicating each of them four times.
+pub(crate) fn load_tlut(
+    tmem: &mut [u8],
+    rdram: &Rdram,
+    tex: &ImageFormat,
+    tile: &TileDescriptor,
+    (sl, tl, sh, _th): (u32, u32, u32, u32),
+) {
+    let src = tex.dram_addr + (tl >> 2) * tex.width as u32 * 2;
+    for (n, i) in (sl >> 2..=sh >> 2).enumerate() {
+        let hi = rdram.read_u8(src + i * 2);
+        let lo = rdram.read_u8(src + i * 2 + 1);
+        for k in 0..4 {
+            let dst = (tile.tmem_addr as usize + n * 8 + k * 2) & (TMEM_SIZE - 1);
+            tmem[dst] = hi;
+            tmem[dst + 1] = lo;
+        }
+    }
+}
+
+#[inline(always)]
+fn expand5(v: u16) -> u8 {
+    let v = (v & 0x1F) as u8;
+    v << 3 | v >> 2
+}
+
+#[inline(always)]
+fn decode_rgba16(v: u16) -> [u8; 4] {
+    [
+        expand5(v >> 11),
+        expand5(v >> 6),
+        expand5(v >> 1),
+        if v & 1 != 0 { 0xFF } else { 0 },
+    ]
+}
+
+#[inline(always)]
+fn decode_ia16(v: u16) -> [u8; 4] {
+    let i = (v >> 8) as u8;
+    [i, i, i, v as u8]
+}
+
+// Fetch and decode a single texel, given its integer coordinates within
+// the tile (already wrapped/clamped).
+fn fetch_texel(st: &RenderState, tile: &TileDescriptor, s: usize, t: usize) -> [u8; 4] {
+    let tmem = &st.tmem;
+    let base = tile.tmem_addr as usize + t * tile.pitch;
+    let odd = t & 1 != 0;
+    let rd16 = |addr: usize| {
+        let addr = addr & (TMEM_SIZE - 2);
+        (tmem[addr] as u16) << 8 | tmem[addr + 1] as u16
+    };
+
+    // With TLUT enabled, 4-bit and 8-bit texels are palette indices.
+    if st.tlut_en() && tile.bpp <= 8 {
+        let idx = if tile.bpp == 4 {
+            let b = tmem[tmem_addr(base + s / 2, odd) & (TLUT_BASE - 1)];
+            let nibble = if s & 1 == 0 { b >> 4 } else { b & 0xF };
+            tile.palette << 4 | nibble as usize
+        } else {
+            tmem[tmem_addr(base + s, odd) & (TLUT_BASE - 1)] as usize
+        };
+        let entry = rd16(TLUT_BASE + idx * 8);
+        return if st.tlut_ia16() {
+            decode_ia16(entry)
+        } else {
+            decode_rgba16(entry)
+        };
+    }
+
+    match (tile.color_format, tile.bpp) {
+        (DpColorFormat::Rgba, 32) => {
+            let addr = tmem_addr(base + s * 2, odd) & (TLUT_BASE - 1);
+            [
+                tmem[addr],
+                tmem[addr + 1],
+                tmem[addr + TLUT_BASE],
+                tmem[addr + TLUT_BASE + 1],
+            ]
+        }
+        (DpColorFormat::IntensityAlpha, 16) => decode_ia16(rd16(tmem_addr(base + s * 2, odd))),
+        (_, 16) => decode_rgba16(rd16(tmem_addr(base + s * 2, odd))),
+        (cf, 8) => {
+            let v = tmem[tmem_addr(base + s, odd)];
+            match cf {
+                DpColorFormat::IntensityAlpha => {
+                    let (i, a) = (v >> 4, v & 0xF);
+                    [i * 17, i * 17, i * 17, a * 17]
+                }
+                _ => [v, v, v, v],
+            }
+        }
+        (cf, _) => {
+            let b = tmem[tmem_addr(base + s / 2, odd)];
+            let v = if s & 1 == 0 { b >> 4 } else { b & 0xF };
+            match cf {
+                DpColorFormat::IntensityAlpha => {
+                    let i = v >> 1;
+                    let i = i << 5 | i << 2 | i >> 1;
+                    [i, i, i, if v & 1 != 0 { 0xFF } else { 0 }]
+                }
+                _ => [v * 17, v * 17, v * 17, v * 17],
+            }
+        }
+    }
+}
+
+// Apply clamp, mirror and mask to an integer texel coordinate.
+#[inline(always)]
+fn wrap(v: i32, clamp: bool, mirror: bool, mask: u32, max: i32) -> usize {
+    let mut v = v;
+    if clamp || mask == 0 {
+        v = v.max(0).min(max);
+    }
+    if mask != 0 {
+        if mirror && v & (mask + 1) as i32 != 0 {
+            v = !v;
+        }
+        v &= mask as i32;
+    }
+    v.max(0) as usize
+}
+
+#[inline(always)]
+fn shift(v: i32, sh: u32) -> i32 {
+    if sh < 11 {
+        v >> sh
+    } else {
+        v << (16 - sh)
+    }
+}
+
+/// Sample a texture at the specified coordinates (s10.5), using either point
+/// sampling or bilinear filtering.
+pub(crate) fn sample(st: &RenderState, tile: usize, s: i32, t: i32, bilerp: bool) -> [u8; 4] {
+    let tile = &st.tiles[tile & 7];
+    let s = shift(s, tile.shift[0]) - ((tile.sl as i32) << 3);
+    let t = shift(t, tile.shift[1]) - ((tile.tl as i32) << 3);
+    let smax = (tile.sh as i32 - tile.sl as i32) >> 2;
+    let tmax = (tile.th as i32 - tile.tl as i32) >> 2;
+    let ws = |s: i32| wrap(s, tile.clamp[0], tile.mirror[0], tile.mask[0], smax);
+    let wt = |t: i32| wrap(t, tile.clamp[1], tile.mirror[1], tile.mask[1], tmax);
+
+    let (si, ti) = (s >> 5, t >> 5);
+    if !bilerp {
+        return fetch_texel(st, tile, ws(si), wt(ti));
+    }
+
+    let (sf, tf) = ((s & 31) as u32, (t & 31) as u32);
+    let t00 = fetch_texel(st, tile, ws(si), wt(ti));
+    let t10 = fetch_texel(st, tile, ws(si + 1), wt(ti));
+    let t01 = fetch_texel(st, tile, ws(si), wt(ti + 1));
+    let t11 = fetch_texel(st, tile, ws(si + 1), wt(ti + 1));
+    let mut out = [0u8; 4];
+    for i in 0..4 {
+        let top = t00[i] as u32 * (32 - sf) + t10[i] as u32 * sf;
+        let bottom = t01[i] as u32 * (32 - sf) + t11[i] as u32 * sf;
+        out[i] = ((top * (32 - tf) + bottom * tf) >> 10) as u8;
+    }
+    out
+}
+
+/// Decode a whole tile (as defined by its size) into RGBA texels, for
+/// the debugger. If the tile size is not set, the size of the mask is used.
+#[cfg(feature = "frontend")]
+pub(crate) fn decode_tile(st: &RenderState, tile: usize) -> (usize, usize, Vec<[u8; 4]>) {
+    let tile = &st.tiles[tile & 7];
+    let size = |lo: u32, hi: u32, mask: u32| {
+        if hi > lo {
+            ((hi - lo) >> 2) as usize + 1
+        } else {
+            mask as usize + 1
+        }
+    };
+    let width = size(tile.sl, tile.sh, tile.mask[0]).min(1024);
+    let height = size(tile.tl, tile.th, tile.mask[1]).min(1024);
+    let mut texels = Vec::with_capacity(width * height);
+    for t in 0..height {
+        for s in 0..width {
+            texels.push(fetch_texel(st, tile, s, t));
+        }
+    }
+    (width, height, texels)
+}
+
+/// Decode a tile into RGBA texels, to be sampled by the GPU. Along each
+/// axis, the texture covers a whole wrapping period if the tile is masked
+/// (twice the mask if mirrored), so that it can be repeated; otherwise, it
+/// covers the tile size, and must be clamped. Returns the size of the
+/// texture, whether each axis repeats, and the texels.
+#[cfg(feature = "frontend")]
+pub(crate) fn decode_texture(
+    st: &RenderState,
+    tile: usize,
+) -> ((usize, usize), [bool; 2], Vec<[u8; 4]>) {
+    let tile = &st.tiles[tile & 7];
+    let smax = (tile.sh as i32 - tile.sl as i32) >> 2;
+    let tmax = (tile.th as i32 - tile.tl as i32) >> 2;
+    let axis = |i: usize, max: i32| {
+        if tile.mask[i] != 0 && !tile.clamp[i] {
+            let period = (tile.mask[i] as usize + 1) << tile.mirror[i] as usize;
+            (period.min(1024), true)
+        } else {
+            ((max.max(0) as usize + 1).min(1024), false)
+        }
+    };
+    let (width, repeat_s) = axis(0, smax);
+    let (height, repeat_t) = axis(1, tmax);
+
+    let mut texels = Vec::with_capacity(width * height);
+    for t in 0..height {
+        let t = wrap(t as i32, tile.clamp[1], tile.mirror[1], tile.mask[1], tmax);
+        for s in 0..width {
+            let s = wrap(s as i32, tile.clamp[0], tile.mirror[0], tile.mask[0], smax);
+            texels.push(fetch_texel(st, tile, s, t));
+        }
+    }
+    ((width, height), [repeat_s, repeat_t], texels)
+}
+
+/// Decode the 256 entries of the palette, according to the current TLUT
+/// type (RGBA16 or IA16).
+#[cfg(feature = "frontend")]
+pub(crate) fn decode_palette(st: &RenderState) -> Vec<[u8; 4]> {
+    (0..256)
+        .map(|idx| {
+            let addr = TLUT_BASE + idx * 8;
+            let entry = (st.tmem[addr] as u16) << 8 | st.tmem[addr + 1] as u16;
+            if st.tlut_ia16() {
+                decode_ia16(entry)
+            } else {
+                decode_rgba16(entry)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::r4300::R4300;
+    use super::super::super::ri::{RdramSize, Ri};
+    use super::*;
+    use std::sync::Arc;
+
+    const TEX_ADDR: u32 = 0x1000;
+
+    fn setup_rdram() -> Rdram {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        R4300::new(logger.new(o!())).register();
+        Ri::new(logger, RdramSize::Mb4).register();
+        R4300::get_mut()
+            .bus
+            .map_device(0x0000_0000, Ri::get(), 0)
+            .unwrap();
+        Rdram::get()
+    }
+
+    fn read16(tmem: &[u8], addr: usize) -> u16 {
+        (tmem[addr] as u16) << 8 | tmem[addr + 1] as u16
+    }
+
+    fn texture(bpp: usize, width: usize) -> ImageFormat {
+        ImageFormat {
+            color_format: DpColorFormat::Rgba,
+            bpp,
+            width,
+            dram_addr: TEX_ADDR,
+        }
+    }
+
+    fn sample_state(tmem: Vec<u8>, tile: TileDescriptor) -> RenderState {
+        let mut st = RenderState::new();
+        st.tmem = Arc::new(tmem);
+        st.tiles[0] = tile;
+        st
+    }
+
+    #[test]
+    fn load_tile() {
+        // 16-bit texture, 8 texels wide; each texel holds its coordinates.
+        let rdram = setup_rdram();
+        let texel = |s: u32, t: u32| (0x8000 | t << 8 | s) as u16;
+        for t in 0..4 {
+            for s in 0..8 {
+                rdram.write_u16(TEX_ADDR + (t * 8 + s) * 2, texel(s, t));
+            }
+        }
+
+        let mut tile = TileDescriptor {
+            bpp: 16,
+            pitch: 16,
+            tmem_addr: 0x10,
+            ..Default::default()
+        };
+        let mut tmem = vec![0u8; TMEM_SIZE];
+        super::load_tile(&mut tmem, &rdram, &texture(16, 8), &tile, (8, 4, 20, 8));
+
+        // Lines are `pitch` bytes apart, and odd lines are swapped.
+        for i in 0..4 {
+            assert_eq!(read16(&tmem, 0x10 + i * 2), texel(2 + i as u32, 1));
+            assert_eq!(read16(&tmem, (0x20 + i * 2) ^ 4), texel(2 + i as u32, 2));
+        }
+        assert!(tmem[..0x10].iter().all(|&b| b == 0));
+        assert!(tmem[0x18..0x20].iter().all(|&b| b == 0));
+        assert!(tmem[0x28..].iter().all(|&b| b == 0));
+
+        // The sampler undoes the swap, and subtracts the tile origin.
+        tile.sl = 8;
+        tile.tl = 4;
+        tile.sh = 20;
+        tile.th = 8;
+        let st = sample_state(tmem, tile);
+        for t in 1..=2 {
+            for s in 2..=5 {
+                assert_eq!(
+                    sample(&st, 0, (s << 5) as i32, (t << 5) as i32, false),
+                    decode_rgba16(texel(s, t))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn load_block() {
+        // Two lines of 8 texels (two 64-bit words each), so dxt is 0.5.
+        let rdram = setup_rdram();
+        let texel = |i: u32| (0x8001 + i * 0x0842) as u16;
+        for i in 0..16 {
+            rdram.write_u16(TEX_ADDR + i * 2, texel(i));
+        }
+
+        let mut tile = TileDescriptor {
+            bpp: 16,
+            pitch: 16,
+            tmem_addr: 0x100,
+            ..Default::default()
+        };
+        let mut tmem = vec![0u8; TMEM_SIZE];
+        super::load_block(&mut tmem, &rdram, &texture(16, 8), &tile, (0, 0, 15, 1024));
+        for i in 0..16 {
+            let addr = 0x100 + i * 2;
+            let addr = if i < 8 { addr } else { addr ^ 4 };
+            assert_eq!(read16(&tmem, addr), texel(i as u32));
+        }
+
+        tile.sh = 7 << 2;
+        tile.th = 1 << 2;
+        let st = sample_state(tmem, tile);
+        for i in 0..16 {
+            let (s, t) = ((i % 8) << 5, (i / 8) << 5);
+            assert_eq!(sample(&st, 0, s, t, false), decode_rgba16(texel(i as u32)));
+        }
+    }
+
+    #[test]
+    fn load_block_32bit() {
+        // 32-bit texels are split between the low and the high half.
+        let rdram = setup_rdram();
+        let texel = |i: u32| 0x0102_0304 + i * 0x1010_1010;
+        for i in 0..4 {
+            rdram.write_u32(TEX_ADDR + i * 4, texel(i));
+        }
+
+        let mut tile = TileDescriptor {
+            bpp: 32,
+            pitch: 16,
+            ..Default::default()
+        };
+        let mut tmem = vec![0u8; TMEM_SIZE];
+        super::load_block(&mut tmem, &rdram, &texture(32, 4), &tile, (0, 0, 3, 0));
+        for i in 0..4 {
+            assert_eq!(read16(&tmem, i * 2), (texel(i as u32) >> 16) as u16);
+            assert_eq!(read16(&tmem, TLUT_BASE + i * 2), texel(i as u32) as u16);
+        }
+
+        tile.sh = 3 << 2;
+        let st = sample_state(tmem, tile);
+        for i in 0..4 {
+            let v = texel(i as u32);
+            assert_eq!(
+                sample(&st, 0, i << 5, 0, false),
+                [(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]
+            );
+        }
+    }
+}
@@ -0,0 +1,429 @@
+// Documentation of the N64 memory-mapped hardware registers, used by the
+// debugger views (memory view, watchpoints, device windows) to show the
+// name of a register and decode its bitfields.
+use emu::hw::{RegDatabase, RegField, RegInfo};
+
+macro_rules! reg {
+    ($db:ident, $addr:expr, $name:expr, $desc:expr, [$(($fname:expr, $bits:expr, $fdesc:expr)),* $(,)*]) => {
+        $db.add(RegInfo {
+            addr: $addr,
+            name: $name,
+            desc: $desc,
+            fields: &[$(RegField { name: $fname, bits: $bits, desc: $fdesc }),*],
+        });
+    };
+}
+
+const IRQ_FIELDS: &[RegField] = &[
+    RegField {
+        name: "SP",
+        bits: 0..1,
+        desc: "",
+    },
+    RegField {
+        name: "SI",
+        bits: 1..2,
+        desc: "",
+    },
+    RegField {
+        name: "AI",
+        bits: 2..3,
+        desc: "",
+    },
+    RegField {
+        name: "VI",
+        bits: 3..4,
+        desc: "",
+    },
+    RegField {
+        name: "PI",
+        bits: 4..5,
+        desc: "",
+    },
+    RegField {
+        name: "DP",
+        bits: 5..6,
+        desc: "",
+    },
+];
+
+const DMA_LEN_FIELDS: &[RegField] = &[
+    RegField {
+        name: "LENGTH",
+        bits: 0..12,
+        desc: "bytes per row, minus 1",
+    },
+    RegField {
+        name: "COUNT",
+        bits: 12..20,
+        desc: "number of rows, minus 1",
+    },
+    RegField {
+        name: "SKIP",
+        bits: 20..32,
+        desc: "RDRAM stride between rows",
+    },
+];
+
+pub fn reg_database() -> RegDatabase {
+    let mut db = RegDatabase::new();
+
+    // RSP interface
+    reg!(
+        db,
+        0x0404_0000,
+        "SP_MEM_ADDR",
+        "RSP DMA address in DMEM/IMEM",
+        [("ADDR", 0..12, ""), ("IMEM", 12..13, "0=DMEM, 1=IMEM")]
+    );
+    reg!(
+        db,
+        0x0404_0004,
+        "SP_DRAM_ADDR",
+        "RSP DMA address in RDRAM",
+        [("ADDR", 0..24, "")]
+    );
+    db.add(RegInfo {
+        addr: 0x0404_0008,
+        name: "SP_RD_LEN",
+        desc: "RSP DMA from RDRAM (write starts the transfer)",
+        fields: DMA_LEN_FIELDS,
+    });
+    db.add(RegInfo {
+        addr: 0x0404_000C,
+        name: "SP_WR_LEN",
+        desc: "RSP DMA to RDRAM (write starts the transfer)",
+        fields: DMA_LEN_FIELDS,
+    });
+    reg!(
+        db,
+        0x0404_0010,
+        "SP_STATUS",
+        "RSP status (write uses set/clear bit pairs)",
+        [
+            ("HALT", 0..1, ""),
+            ("BROKE", 1..2, "BREAK opcode executed"),
+            ("DMA_BUSY", 2..3, ""),
+            ("DMA_FULL", 3..4, ""),
+            ("IO_FULL", 4..5, ""),
+            ("SSTEP", 5..6, "single-step mode"),
+            ("INTR_BREAK", 6..7, "raise interrupt on BREAK"),
+            ("SIG0", 7..8, ""),
+            ("SIG1", 8..9, ""),
+            ("SIG2", 9..10, ""),
+            ("SIG3", 10..11, ""),
+            ("SIG4", 11..12, ""),
+            ("SIG5", 12..13, ""),
+            ("SIG6", 13..14, ""),
+            ("SIG7", 14..15, ""),
+        ]
+    );
+    reg!(db, 0x0404_0014, "SP_DMA_FULL", "", [("FULL", 0..1, "")]);
+    reg!(db, 0x0404_0018, "SP_DMA_BUSY", "", [("BUSY", 0..1, "")]);
+    reg!(
+        db,
+        0x0404_001C,
+        "SP_SEMAPHORE",
+        "read sets it, write clears it",
+        [("SEMAPHORE", 0..1, "")]
+    );
+    reg!(
+        db,
+        0x0408_0000,
+        "SP_PC",
+        "RSP program counter",
+        [("PC", 0..12, "")]
+    );
+
+    // RDP command interface
+    reg!(
+        db,
+        0x0410_0000,
+        "DPC_START",
+        "start of the command buffer",
+        [("ADDR", 0..24, "")]
+    );
+    reg!(
+        db,
+        0x0410_0004,
+        "DPC_END",
+        "end of the command buffer (write starts processing)",
+        [("ADDR", 0..24, "")]
+    );
+    reg!(
+        db,
+        0x0410_0008,
+        "DPC_CURRENT",
+        "current command address",
+        [("ADDR", 0..24, "")]
+    );
+    reg!(
+        db,
+        0x0410_000C,
+        "DPC_STATUS",
+        "RDP status (write uses set/clear bit pairs)",
+        [
+            ("XBUS_DMEM_DMA", 0..1, "read commands from DMEM"),
+            ("FREEZE", 1..2, ""),
+            ("FLUSH", 2..3, ""),
+            ("START_GCLK", 3..4, ""),
+            ("TMEM_BUSY", 4..5, ""),
+            ("PIPE_BUSY", 5..6, ""),
+            ("CMD_BUSY", 6..7, ""),
+            ("CBUF_READY", 7..8, ""),
+            ("DMA_BUSY", 8..9, ""),
+            ("END_VALID", 9..10, ""),
+            ("START_VALID", 10..11, ""),
+        ]
+    );
+
+    // MIPS interface
+    reg!(
+        db,
+        0x0430_0000,
+        "MI_MODE",
+        "",
+        [
+            ("INIT_LENGTH", 0..7, ""),
+            ("INIT_MODE", 7..8, ""),
+            ("EBUS_TEST", 8..9, ""),
+            ("RDRAM_REG", 9..10, ""),
+        ]
+    );
+    reg!(
+        db,
+        0x0430_0004,
+        "MI_VERSION",
+        "",
+        [
+            ("IO", 0..8, ""),
+            ("RAC", 8..16, ""),
+            ("RDP", 16..24, ""),
+            ("RSP", 24..32, ""),
+        ]
+    );
+    db.add(RegInfo {
+        addr: 0x0430_0008,
+        name: "MI_INTR",
+        desc: "pending interrupts",
+        fields: IRQ_FIELDS,
+    });
+    db.add(RegInfo {
+        addr: 0x0430_000C,
+        name: "MI_INTR_MASK",
+        desc: "enabled interrupts (write uses set/clear bit pairs)",
+        fields: IRQ_FIELDS,
+    });
+
+    // Video interface
+    reg!(
+        db,
+        0x0440_0000,
+        "VI_CONTROL",
+        "",
+        [
+            ("TYPE", 0..2, "0=blank, 2=RGBA16, 3=RGBA32"),
+            ("GAMMA_DITHER", 2..3, ""),
+            ("GAMMA", 3..4, ""),
+            ("DIVOT", 4..5, ""),
+            ("SERRATE", 6..7, "interlaced"),
+            ("AA_MODE", 8..10, ""),
+            ("PIXEL_ADVANCE", 12..16, ""),
+            ("DITHER_FILTER", 16..17, ""),
+        ]
+    );
+    reg!(
+        db,
+        0x0440_0004,
+        "VI_ORIGIN",
+        "framebuffer address",
+        [("ADDR", 0..24, "")]
+    );
+    reg!(
+        db,
+        0x0440_0008,
+        "VI_WIDTH",
+        "framebuffer width in pixels",
+        [("WIDTH", 0..12, "")]
+    );
+    reg!(
+        db,
+        0x0440_000C,
+        "VI_V_INTR",
+        "half-line of the vertical interrupt",
+        [("LINE", 0..10, "")]
+    );
+    reg!(
+        db,
+        0x0440_0010,
+        "VI_V_CURRENT",
+        "current half-line (write acks interrupt)",
+        [("LINE", 0..10, "")]
+    );
+    reg!(
+        db,
+        0x0440_0014,
+        "VI_BURST",
+        "",
+        [
+            ("HSYNC_WIDTH", 0..8, ""),
+            ("BURST_WIDTH", 8..16, ""),
+            ("VSYNC_WIDTH", 16..20, ""),
+            ("BURST_START", 20..30, ""),
+        ]
+    );
+    reg!(
+        db,
+        0x0440_0018,
+        "VI_V_SYNC",
+        "half-lines per field",
+        [("V_SYNC", 0..10, "")]
+    );
+    reg!(
+        db,
+        0x0440_001C,
+        "VI_H_SYNC",
+        "",
+        [
+            ("H_SYNC", 0..12, "line duration (1/4 pixel)"),
+            ("LEAP", 16..21, ""),
+        ]
+    );
+    reg!(
+        db,
+        0x0440_0020,
+        "VI_LEAP",
+        "",
+        [("LEAP_B", 0..12, ""), ("LEAP_A", 16..28, "")]
+    );
+    reg!(
+        db,
+        0x0440_0024,
+        "VI_H_START",
+        "",
+        [("H_END", 0..10, ""), ("H_START", 16..26, "")]
+    );
+    reg!(
+        db,
+        0x0440_0028,
+        "VI_V_START",
+        "",
+        [("V_END", 0..10, ""), ("V_START", 16..26, "")]
+    );
+    reg!(
+        db,
+        0x0440_002C,
+        "VI_V_BURST",
+        "",
+        [("END", 0..10, ""), ("START", 16..26, "")]
+    );
+    reg!(
+        db,
+        0x0440_0030,
+        "VI_X_SCALE",
+        "",
+        [("SCALE", 0..12, "2.10 fixed point"), ("OFFSET", 16..28, "")]
+    );
+    reg!(
+        db,
+        0x0440_0034,
+        "VI_Y_SCALE",
+        "",
+        [("SCALE", 0..12, "2.10 fixed point"), ("OFFSET", 16..28, "")]
+    );
+
+    // Audio interface
+    reg!(db, 0x0450_0000, "AI_DRAM_ADDR", "", [("ADDR", 0..24, "")]);
+    reg!(
+        db,
+        0x0450_0004,
+        "AI_LEN",
+        "buffer length (write starts playback)",
+        [("LEN", 0..18, "")]
+    );
+    reg!(
+        db,
+        0x0450_0008,
+        "AI_CONTROL",
+        "",
+        [("DMA_ENABLE", 0..1, "")]
+    );
+    reg!(
+        db,
+        0x0450_000C,
+        "AI_STATUS",
+        "write acks interrupt",
+        [("BUSY", 30..31, ""), ("FULL", 31..32, "")]
+    );
+    reg!(db, 0x0450_0010, "AI_DACRATE", "", [("DACRATE", 0..14, "")]);
+    reg!(db, 0x0450_0014, "AI_BITRATE", "", [("BITRATE", 0..4, "")]);
+
+    // Peripheral interface
+    reg!(db, 0x0460_0000, "PI_DRAM_ADDR", "", [("ADDR", 0..24, "")]);
+    reg!(db, 0x0460_0004, "PI_CART_ADDR", "", [("ADDR", 0..32, "")]);
+    reg!(
+        db,
+        0x0460_0008,
+        "PI_RD_LEN",
+        "DMA from RDRAM to cartridge",
+        [("LEN", 0..24, "")]
+    );
+    reg!(
+        db,
+        0x0460_000C,
+        "PI_WR_LEN",
+        "DMA from cartridge to RDRAM",
+        [("LEN", 0..24, "")]
+    );
+    reg!(
+        db,
+        0x0460_0010,
+        "PI_STATUS",
+        "write: bit 0 resets, bit 1 acks interrupt",
+        [
+            ("DMA_BUSY", 0..1, ""),
+            ("IO_BUSY", 1..2, ""),
+            ("ERROR", 2..3, ""),
+            ("INTERRUPT", 3..4, ""),
+        ]
+    );
+    reg!(db, 0x0460_0014, "PI_BSD_DOM1_LAT", "", [("LAT", 0..8, "")]);
+    reg!(db, 0x0460_0018, "PI_BSD_DOM1_PWD", "", [("PWD", 0..8, "")]);
+    reg!(db, 0x0460_001C, "PI_BSD_DOM1_PGS", "", [("PGS", 0..4, "")]);
+    reg!(db, 0x0460_0020, "PI_BSD_DOM1_RLS", "", [("RLS", 0..2, "")]);
+    reg!(db, 0x0460_0024, "PI_BSD_DOM2_LAT", "", [("LAT", 0..8, "")]);
+    reg!(db, 0x0460_0028, "PI_BSD_DOM2_PWD", "", [("PWD", 0..8, "")]);
+    reg!(db, 0x0460_002C, "PI_BSD_DOM2_PGS", "", [("PGS", 0..4, "")]);
+    reg!(db, 0x0460_0030, "PI_BSD_DOM2_RLS", "", [("RLS", 0..2, "")]);
+
+    // Serial interface
+    reg!(db, 0x0480_0000, "SI_DRAM_ADDR", "", [("ADDR", 0..24, "")]);
+    reg!(
+        db,
+        0x0480_0004,
+        "SI_PIF_ADDR_RD64B",
+        "DMA from PIF RAM (write starts it)",
+        []
+    );
+    reg!(
+        db,
+        0x0480_0010,
+        "SI_PIF_ADDR_WR64B",
+        "DMA to PIF RAM (write starts it)",
+        []
+    );
+    reg!(
+        db,
+        0x0480_0018,
+        "SI_STATUS",
+        "write acks interrupt",
+        [
+            ("DMA_BUSY", 0..1, ""),
+            ("IO_BUSY", 1..2, ""),
+            ("DMA_ERROR", 3..4, ""),
+            ("INTERRUPT", 12..13, ""),
+        ]
+    );
+
+    db
+}
@@ -1,11 +1,39 @@
 extern crate emu;
 extern crate slog;
-use emu::bus::be::{Mem, Reg32};
+use emu::bus::be::{Mem, MemFlags, Reg32};
+
+/// Amount of RDRAM installed in the console.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RdramSize {
+    /// 4 MiB: the base console, with the Jumper Pak.
+    Mb4,
+    /// 8 MiB: with the Expansion Pak, required by some games (eg: Donkey
+    /// Kong 64, Majora's Mask) and used by others for higher resolutions.
+    Mb8,
+}
+
+impl RdramSize {
+    /// Size in bytes.
+    pub fn bytes(self) -> usize {
+        match self {
+            RdramSize::Mb4 => 4 * 1024 * 1024,
+            RdramSize::Mb8 => 8 * 1024 * 1024,
+        }
+    }
+}
 
 /// RDRAM
 #[derive(DeviceBE)]
 pub struct Ri {
-    #[mem(bank = 0, size = 4194304, offset = 0x0000_0000)]
+    // Without the Expansion Pak, the upper 4 MiB are not connected: writes
+    // are ignored and reads return zero, which is how games (osGetMemSize)
+    // detect the size of the installed memory.
+    #[mem(
+        bank = 0,
+        offset = 0x0000_0000,
+        vsize = 0x0080_0000,
+        fill = "Fixed(0x00)"
+    )]
     pub(crate) rdram: Mem,
 
     #[reg(bank = 1, offset = 0x00)]
@@ -83,9 +111,9 @@ pub struct Ri {
 }
 
 impl Ri {
-    pub fn new(logger: slog::Logger) -> Box<Ri> {
+    pub fn new(logger: slog::Logger, size: RdramSize) -> Box<Ri> {
         Box::new(Ri {
-            rdram: Mem::default(),
+            rdram: Mem::new("Ri::rdram", size.bytes(), MemFlags::new(true, true), None),
 
             reg_rdram_config: Reg32::default(),
             reg_rdram_device_id: Reg32::default(),
@@ -111,3 +139,43 @@ impl Ri {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::r4300::R4300;
+    use super::*;
+    use emu::bus::be::Device;
+
+    // Detect the installed memory like osGetMemSize: write a pattern at the
+    // start of each MiB, and stop at the first one that doesn't hold it.
+    fn detect_size(size: RdramSize) -> usize {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        R4300::new(logger.new(o!())).register();
+        Ri::new(logger, size).register();
+        let bus = &mut R4300::get_mut().bus;
+        bus.map_device(0x0000_0000, Ri::get(), 0).unwrap();
+
+        let mut mb = 0;
+        while mb < 8 {
+            let addr = mb * 0x10_0000 + 4;
+            bus.write::<u32>(addr, 0x1234_5678);
+            if bus.read::<u32>(addr) != 0x1234_5678 {
+                break;
+            }
+            mb += 1;
+        }
+        mb as usize * 0x10_0000
+    }
+
+    #[test]
+    fn detect_4mb() {
+        assert_eq!(detect_size(RdramSize::Mb4), RdramSize::Mb4.bytes());
+        // The upper half is not connected: it always reads as zero.
+        assert_eq!(R4300::get().bus.read::<u32>(0x0040_0004), 0);
+    }
+
+    #[test]
+    fn detect_8mb() {
+        assert_eq!(detect_size(RdramSize::Mb8), RdramSize::Mb8.bytes());
+    }
+}
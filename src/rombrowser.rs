@@ -0,0 +1,232 @@
+//! ROM browser and recent ROMs.
+//!
+//! The browser scans a directory for ROM files, and shows the information
+//! found in their headers in a sortable list. The directory and the list of
+//! recently played ROMs are persisted in the "browser" section of the config
+//! file.
+use super::cartridge::{is_rom_file, RomHeader};
+use super::errors::*;
+
+use emu::config::{Config, ConfigSection};
+#[cfg(feature = "frontend")]
+use emu::dbg::imgui::*;
+#[cfg(feature = "frontend")]
+use emu::hw::Launcher;
+use serde_derive::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Maximum number of entries in the recent ROMs list.
+const MAX_RECENT: usize = 10;
+
+#[derive(Default, Serialize, Deserialize)]
+struct BrowserConfig {
+    rom_dir: Option<PathBuf>,
+    #[serde(default)]
+    recent_roms: Vec<PathBuf>,
+}
+
+impl ConfigSection for BrowserConfig {
+    const NAME: &'static str = "browser";
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum SortKey {
+    Name,
+    Country,
+    Crc,
+    File,
+}
+
+struct RomEntry {
+    path: PathBuf,
+    header: RomHeader,
+}
+
+/// Return a readable name for the country code found in the ROM header.
+pub fn country_name(code: u8) -> &'static str {
+    match code {
+        b'7' => "Beta",
+        b'A' => "Asia",
+        b'B' => "Brazil",
+        b'C' => "China",
+        b'D' => "Germany",
+        b'E' => "USA",
+        b'F' => "France",
+        b'I' => "Italy",
+        b'J' => "Japan",
+        b'K' => "Korea",
+        b'P' | b'X' | b'Y' => "Europe",
+        b'S' => "Spain",
+        b'U' => "Australia",
+        _ => "Unknown",
+    }
+}
+
+pub struct RomBrowser {
+    config: BrowserConfig,
+    entries: Vec<RomEntry>,
+    sort: SortKey,
+    dir: PathBuf,
+    #[cfg(feature = "frontend")]
+    dir_input: ImString,
+    error: Option<String>,
+}
+
+impl RomBrowser {
+    /// Create the browser, restoring its state from the config file.
+    pub fn new(config: &Config) -> Result<RomBrowser> {
+        let config: BrowserConfig = config.get()?;
+        let dir = config.rom_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+
+        let mut browser = RomBrowser {
+            config,
+            entries: Vec::new(),
+            sort: SortKey::Name,
+            dir: PathBuf::new(),
+            #[cfg(feature = "frontend")]
+            dir_input: ImString::with_capacity(256),
+            error: None,
+        };
+        browser.set_dir(&dir);
+        Ok(browser)
+    }
+
+    /// Store the state of the browser into the config file (which is not
+    /// saved).
+    pub fn save_config(&self, config: &mut Config) -> Result<()> {
+        Ok(config.set(&self.config)?)
+    }
+
+    /// Change the directory being browsed, and rescan it.
+    pub fn set_dir(&mut self, dir: &Path) {
+        self.dir = dir.to_owned();
+        #[cfg(feature = "frontend")]
+        {
+            self.dir_input = ImString::new(dir.to_string_lossy());
+            self.dir_input.reserve(256);
+        }
+        self.scan();
+    }
+
+    /// Add a ROM at the top of the recent ROMs list.
+    pub fn add_recent(&mut self, rom: &Path) {
+        let rom = fs::canonicalize(rom).unwrap_or_else(|_| rom.to_owned());
+        self.config.recent_roms.retain(|r| *r != rom);
+        self.config.recent_roms.insert(0, rom);
+        self.config.recent_roms.truncate(MAX_RECENT);
+    }
+
+    // Scan the current directory for ROM files.
+    fn scan(&mut self) {
+        let dir = self.dir.clone();
+        self.entries.clear();
+        self.error = None;
+        let files = match fs::read_dir(&dir) {
+            Ok(files) => files,
+            Err(err) => {
+                self.error = Some(format!("cannot read {}: {}", dir.display(), err));
+                return;
+            }
+        };
+        for path in files.filter_map(|f| f.ok()).map(|f| f.path()) {
+            if !is_rom_file(&path) {
+                continue;
+            }
+            // Skip files that are not valid ROMs.
+            if let Ok(header) = RomHeader::read(&path) {
+                self.entries.push(RomEntry { path, header });
+            }
+        }
+        self.config.rom_dir = Some(dir);
+        self.sort_entries();
+    }
+
+    fn sort_entries(&mut self) {
+        match self.sort {
+            SortKey::Name => self
+                .entries
+                .sort_by(|a, b| a.header.name.cmp(&b.header.name)),
+            SortKey::Country => self.entries.sort_by_key(|e| country_name(e.header.country)),
+            SortKey::Crc => self.entries.sort_by_key(|e| e.header.crc),
+            SortKey::File => self.entries.sort_by(|a, b| a.path.cmp(&b.path)),
+        }
+    }
+}
+
+#[cfg(feature = "frontend")]
+impl Launcher for RomBrowser {
+    fn render(&mut self, ui: &Ui) -> Option<PathBuf> {
+        let mut selected = None;
+
+        ui.text("Directory:");
+        ui.same_line(0.0);
+        let enter = ui
+            .input_text(im_str!("##rombrowser#dir"), &mut self.dir_input)
+            .enter_returns_true(true)
+            .build();
+        ui.same_line(0.0);
+        if ui.button(im_str!("Scan"), [0.0, 0.0]) || enter {
+            self.dir = PathBuf::from(self.dir_input.to_str());
+            self.scan();
+        }
+        if let Some(err) = self.error.as_ref() {
+            ui.text_colored([1.0, 0.3, 0.3, 1.0], err);
+        }
+        ui.separator();
+
+        // Column headers can be clicked to sort the list.
+        ui.columns(4, im_str!("##rombrowser#roms"), true);
+        for (title, key) in [
+            (im_str!("Name"), SortKey::Name),
+            (im_str!("Country"), SortKey::Country),
+            (im_str!("CRC"), SortKey::Crc),
+            (im_str!("File"), SortKey::File),
+        ]
+        .iter()
+        {
+            if Selectable::new(title).selected(self.sort == *key).build(ui) {
+                self.sort = *key;
+                self.sort_entries();
+            }
+            ui.next_column();
+        }
+        ui.separator();
+        for (idx, e) in self.entries.iter().enumerate() {
+            Selectable::new(&im_str!("{}##rom{}", e.header.name, idx))
+                .span_all_columns(true)
+                .build(ui);
+            if ui.is_item_hovered() && ui.is_mouse_double_clicked(MouseButton::Left) {
+                selected = Some(e.path.clone());
+            }
+            ui.next_column();
+            ui.text(country_name(e.header.country));
+            ui.next_column();
+            ui.text(format!("{:08X}-{:08X}", e.header.crc.0, e.header.crc.1));
+            ui.next_column();
+            ui.text(e.path.file_name().unwrap_or_default().to_string_lossy());
+            ui.next_column();
+        }
+        ui.columns(1, im_str!(""), false);
+        if self.entries.is_empty() {
+            ui.text_disabled("No ROMs found in this directory.");
+        }
+
+        if !self.config.recent_roms.is_empty() {
+            ui.separator();
+            ui.text("Recent ROMs:");
+            for (idx, rom) in self.config.recent_roms.iter().enumerate() {
+                let name = rom.file_name().unwrap_or_default().to_string_lossy();
+                Selectable::new(&im_str!("{}##recent{}", name, idx)).build(ui);
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(rom.to_string_lossy());
+                    if ui.is_mouse_double_clicked(MouseButton::Left) {
+                        selected = Some(rom.clone());
+                    }
+                }
+            }
+        }
+        selected
+    }
+}
@@ -0,0 +1,280 @@
+//! Lua scripting.
+//!
+//! Scripts can inspect and modify the state of the emulated console while it
+//! runs, to implement cheats, bots or automated tests. A script is run once
+//! when loaded, and can then register a callback that is invoked at the end
+//! of every frame. All the functions are available in the `emu` table:
+//!
+//! ```lua
+//! emu.read8(addr), emu.read16(addr), emu.read32(addr)
+//! emu.write8(addr, val), emu.write16(addr, val), emu.write32(addr, val)
+//! emu.reg(name)                 -- CPU register, by name ("sp") or index
+//! emu.set_reg(name, val)
+//! emu.pc()
+//! emu.frame()                   -- number of frames since the script started
+//! emu.on_frame(function)        -- called at the end of every frame
+//! emu.set_input(ch, buttons)    -- override controller ch (1-4), or nil
+//! emu.draw_text(x, y, text [, color])
+//! emu.log(message)
+//! ```
+//!
+//! Addresses are physical addresses on the main CPU bus (eg: RDRAM starts at
+//! 0x00000000). `buttons` is a table with the pressed buttons set to true
+//! (A, B, Z, start, up, down, left, right, L, R, c_up, c_down, c_left,
+//! c_right), and the stick position in `x` and `y` (-128..127). Injected
+//! input is kept until changed, and replaces the host input. Text is drawn
+//! over the current frame only, so it must be drawn again at every frame;
+//! `color` is in 0xRRGGBB format (default: white).
+use super::errors::*;
+use super::pi::Pi;
+use super::r4300::R4300;
+
+use emu::bus::be::Device;
+use emu::gfx::{draw_text, Color, GfxBufferMutLE, Rgb888};
+use mips64::REG_NAMES;
+use rlua::{Function, Lua, Table, Value};
+
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// Name of the registry slot holding the frame callback.
+const ON_FRAME: &str = "r64emu_on_frame";
+
+// Buttons of the controller, with their bit in the joybus layout.
+const BUTTONS: [(&str, u32); 14] = [
+    ("A", 31),
+    ("B", 30),
+    ("Z", 29),
+    ("start", 28),
+    ("up", 27),
+    ("down", 26),
+    ("left", 25),
+    ("right", 24),
+    ("L", 21),
+    ("R", 20),
+    ("c_up", 19),
+    ("c_down", 18),
+    ("c_left", 17),
+    ("c_right", 16),
+];
+
+struct OverlayText {
+    x: isize,
+    y: isize,
+    text: String,
+    color: u32,
+}
+
+// State shared between the script functions.
+#[derive(Default)]
+struct ScriptState {
+    frame: i64,
+    overlay: Vec<OverlayText>,
+}
+
+pub struct Script {
+    logger: slog::Logger,
+    lua: Lua,
+    state: Arc<Mutex<ScriptState>>,
+}
+
+// Convert a register name or index into an index into the GPR array.
+fn reg_index(reg: Value) -> rlua::Result<usize> {
+    let idx = match reg {
+        Value::Integer(idx) => idx as usize,
+        Value::String(ref name) => {
+            let name = name.to_str()?;
+            REG_NAMES[..32]
+                .iter()
+                .position(|&r| r == name)
+                .unwrap_or(usize::max_value())
+        }
+        _ => usize::max_value(),
+    };
+    if idx >= 32 {
+        return Err(rlua::Error::RuntimeError(format!(
+            "invalid register: {:?}",
+            reg
+        )));
+    }
+    Ok(idx)
+}
+
+// Convert a table of buttons into the controller state, in joybus layout.
+fn joypad_state(buttons: Table) -> rlua::Result<u32> {
+    let mut value = 0u32;
+    for (name, bit) in BUTTONS.iter() {
+        if buttons.get::<_, Option<bool>>(*name)?.unwrap_or(false) {
+            value |= 1 << bit;
+        }
+    }
+    let x = buttons.get::<_, Option<i64>>("x")?.unwrap_or(0);
+    let y = buttons.get::<_, Option<i64>>("y")?.unwrap_or(0);
+    value |= (x.max(-128).min(127) as u8 as u32) << 8;
+    value |= y.max(-128).min(127) as u8 as u32;
+    Ok(value)
+}
+
+impl Script {
+    /// Load a script from a file and run it. Messages logged by the script
+    /// are sent to the specified logger.
+    pub fn load(logger: slog::Logger, path: &Path) -> Result<Script> {
+        let source = fs::read_to_string(path)
+            .chain_err(|| format!("cannot open script {}", path.display()))?;
+        let name = path.display().to_string();
+
+        let script = Script {
+            logger,
+            lua: Lua::new(),
+            state: Arc::new(Mutex::new(ScriptState::default())),
+        };
+        script
+            .lua
+            .context(|ctx| {
+                script.register(ctx)?;
+                ctx.load(&source).set_name(&name)?.exec()
+            })
+            .map_err(|e| format!("error in script {}: {}", name, e))?;
+        Ok(script)
+    }
+
+    fn register(&self, ctx: rlua::Context) -> rlua::Result<()> {
+        let emu = ctx.create_table()?;
+
+        emu.set(
+            "read8",
+            ctx.create_function(|_, addr: u32| Ok(R4300::get().bus.read::<u8>(addr)))?,
+        )?;
+        emu.set(
+            "read16",
+            ctx.create_function(|_, addr: u32| Ok(R4300::get().bus.read::<u16>(addr)))?,
+        )?;
+        emu.set(
+            "read32",
+            ctx.create_function(|_, addr: u32| Ok(R4300::get().bus.read::<u32>(addr)))?,
+        )?;
+        emu.set(
+            "write8",
+            ctx.create_function(|_, (addr, val): (u32, u8)| {
+                R4300::get_mut().bus.write::<u8>(addr, val);
+                Ok(())
+            })?,
+        )?;
+        emu.set(
+            "write16",
+            ctx.create_function(|_, (addr, val): (u32, u16)| {
+                R4300::get_mut().bus.write::<u16>(addr, val);
+                Ok(())
+            })?,
+        )?;
+        emu.set(
+            "write32",
+            ctx.create_function(|_, (addr, val): (u32, u32)| {
+                R4300::get_mut().bus.write::<u32>(addr, val);
+                Ok(())
+            })?,
+        )?;
+
+        emu.set(
+            "reg",
+            ctx.create_function(|_, reg: Value| {
+                let idx = reg_index(reg)?;
+                Ok(R4300::get().ctx().regs[idx] as i64)
+            })?,
+        )?;
+        emu.set(
+            "set_reg",
+            ctx.create_function(|_, (reg, val): (Value, i64)| {
+                let idx = reg_index(reg)?;
+                if idx != 0 {
+                    R4300::get_mut().ctx_mut().regs[idx] = val as u64;
+                }
+                Ok(())
+            })?,
+        )?;
+        emu.set(
+            "pc",
+            ctx.create_function(|_, ()| Ok(R4300::get().ctx().pc as u32))?,
+        )?;
+
+        let state = self.state.clone();
+        emu.set(
+            "frame",
+            ctx.create_function(move |_, ()| Ok(state.lock().unwrap().frame))?,
+        )?;
+        emu.set(
+            "on_frame",
+            ctx.create_function(|ctx, f: Option<Function>| {
+                ctx.set_named_registry_value(ON_FRAME, f)
+            })?,
+        )?;
+
+        emu.set(
+            "set_input",
+            ctx.create_function(|_, (ch, buttons): (usize, Option<Table>)| {
+                if ch < 1 || ch > 4 {
+                    return Err(rlua::Error::RuntimeError(format!(
+                        "invalid controller: {}",
+                        ch
+                    )));
+                }
+                let value = match buttons {
+                    Some(buttons) => Some(joypad_state(buttons)?),
+                    None => None,
+                };
+                Pi::get_mut().set_script_input(ch - 1, value);
+                Ok(())
+            })?,
+        )?;
+
+        let state = self.state.clone();
+        emu.set(
+            "draw_text",
+            ctx.create_function(
+                move |_, (x, y, text, color): (isize, isize, String, Option<u32>)| {
+                    state.lock().unwrap().overlay.push(OverlayText {
+                        x,
+                        y,
+                        text,
+                        color: color.unwrap_or(0xFF_FFFF),
+                    });
+                    Ok(())
+                },
+            )?,
+        )?;
+        let logger = self.logger.clone();
+        emu.set(
+            "log",
+            ctx.create_function(move |_, msg: String| {
+                info!(logger, "{}", msg);
+                Ok(())
+            })?,
+        )?;
+
+        ctx.globals().set("emu", emu)
+    }
+
+    /// Run the frame callback (if any), and draw the text requested by the
+    /// script over the frame. Must be called at the end of each frame.
+    pub fn end_frame(&mut self, screen: &mut GfxBufferMutLE<Rgb888>) -> Result<()> {
+        self.lua
+            .context(
+                |ctx| match ctx.named_registry_value::<_, Option<Function>>(ON_FRAME)? {
+                    Some(f) => f.call(()),
+                    None => Ok(()),
+                },
+            )
+            .map_err(|e| format!("error in script: {}", e))?;
+
+        let mut state = self.state.lock().unwrap();
+        for t in state.overlay.drain(..) {
+            let c = t.color;
+            let (r, g, b) = ((c >> 16) as u8, (c >> 8) as u8, c as u8);
+            let color = Color::new_clamped(r as i32, g as i32, b as i32, 0xFF);
+            draw_text(screen, t.x, t.y, &t.text, color);
+        }
+        state.frame += 1;
+        Ok(())
+    }
+}
@@ -1,8 +1,9 @@
 use slog;
 
+use super::dmalog::{self, DmaDevice};
 use super::mi::{IrqMask, Mi};
-use super::r4300::R4300;
 use super::pi::Pi;
+use super::r4300::R4300;
 
 use emu::bus::be::Reg32;
 use emu::bus::Device;
@@ -71,6 +72,7 @@ impl Si {
         let mut src = new;
         let mut dst = self.dma_address.get();
         info!(self.logger, "SI DMA read"; "pifram" => src.hex(), "rdram" => dst.hex());
+        dmalog::record(DmaDevice::Si, src, dst, 64);
 
         let bus = &mut R4300::get_mut().bus;
         for _ in 0..16 {
@@ -86,6 +88,7 @@ impl Si {
         let mut src = self.dma_address.get();
         let mut dst = new;
         info!(self.logger, "SI DMA write"; "rdram" => src.hex(), "pifram" => dst.hex());
+        dmalog::record(DmaDevice::Si, src, dst, 64);
 
         let bus = &mut R4300::get_mut().bus;
         for _ in 0..16 {
@@ -4,6 +4,7 @@ use crate::errors::*;
 use emu::bus::be::{Bus, Device};
 use emu::dbg;
 use emu::dbg::{DecodedInsn, Operand, Tracer};
+use emu::state::Snapshotable;
 use mips64;
 use mips64::REG_NAMES;
 
@@ -97,6 +98,8 @@ impl<'a> C0op<'a> {
     }
 }
 
+impl Snapshotable for SpCop0 {}
+
 impl mips64::Cop0 for SpCop0 {
     // RSP has no interrupts
     fn set_hwint_line(&mut self, _line: usize, _status: bool) {}
@@ -12,7 +12,7 @@ use emu::bus::be::{Bus, Device};
 use emu::dbg;
 use emu::int::Numerics;
 use emu::memint::MemInt;
-use emu::state::Field;
+use emu::state::{CurrentState, Field, Snapshotable};
 use mips64::{Cop, CpuContext};
 use serde_derive::{Deserialize, Serialize};
 use slog;
@@ -82,9 +82,20 @@ impl SpCop2 {
     pub const REG_ACCUM_HI: usize = 37;
 
     pub fn new(name: &str, logger: slog::Logger) -> Result<SpCop2> {
+        // Version 1: the context field was renamed from "sp::cop2", to
+        // follow the naming convention of components.
+        {
+            let mut state = CurrentState();
+            state.register_component("Sp", 1);
+            state.add_migration("Sp", 0, |m| {
+                m.rename("sp::cop2", "Sp::cop2");
+                Ok(())
+            });
+        }
+
         Ok(SpCop2 {
             name: name.to_owned(),
-            ctx: Field::new("sp::cop2", SpCop2Context::default()),
+            ctx: Field::new("Sp::cop2", SpCop2Context::default()),
             logger: logger,
         })
     }
@@ -744,6 +755,8 @@ fn sxv<T: MemInt>(dmem: &mut [u8], base: u32, offset: u32, reg: &VectorReg, elem
     T::endian_write_to::<BigEndian>(&mut dmem[ea..ea + T::SIZE], T::truncate_from(reg as u64));
 }
 
+impl Snapshotable for SpCop2 {}
+
 impl Cop for SpCop2 {
     fn reg(&self, _cpu: &CpuContext, idx: usize) -> u128 {
         match idx {
@@ -1078,13 +1091,14 @@ impl Cop for SpCop2 {
         decode(opcode, pc)
     }
 
+    #[cfg(feature = "frontend")]
     fn render_debug(&mut self, dr: &dbg::DebuggerRenderer) {
         dr.render_regview(self);
     }
 }
 
 impl dbg::RegisterView for SpCop2 {
-    const WINDOW_SIZE: [f32; 2] = [180.0, 400.0];
+    const WINDOW_SIZE: [f32; 2] = [180.0, 480.0];
     const COLUMNS: usize = 1;
 
     fn name(&self) -> &str {
@@ -1124,5 +1138,28 @@ impl dbg::RegisterView for SpCop2 {
                 v[j] = vle[7 - j];
             }
         }
+
+        // Flag registers, with one bit per lane (lane 0 on the left, like
+        // in vector registers).
+        let lanes = |bits: u16| {
+            (0..8)
+                .map(|i| ((bits >> i) & 1).to_string())
+                .collect::<String>()
+        };
+
+        let mut vco = ctx.vco();
+        let desc = format!("NE:{} C:{}", lanes(vco >> 8), lanes(vco));
+        visit("vco", Reg16(&mut vco), Some(&desc));
+        ctx.set_vco(vco);
+
+        let mut vcc = ctx.vcc();
+        let desc = format!("CLIP:{} CMP:{}", lanes(vcc >> 8), lanes(vcc));
+        visit("vcc", Reg16(&mut vcc), Some(&desc));
+        ctx.set_vcc(vcc);
+
+        let mut vce = ctx.vce();
+        let desc = format!("CMP:{}", lanes(vce as u16));
+        visit("vce", Reg8(&mut vce), Some(&desc));
+        ctx.set_vce(vce);
     }
 }
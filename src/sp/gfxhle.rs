@@ -0,0 +1,1108 @@
+use super::super::dp::Dp;
+use super::super::r4300::R4300;
+use super::voices::{OSTASK_ADDR, OSTASK_DATA_PTR, OSTASK_TYPE};
+use byteorder::{BigEndian, ByteOrder};
+use emu::int::Numerics;
+
+use std::cmp::Ordering;
+
+const OSTASK_UCODE_DATA: usize = OSTASK_ADDR + 0x18;
+const OSTASK_UCODE_DATA_SIZE: usize = OSTASK_ADDR + 0x1C;
+const M_GFXTASK: u32 = 1;
+
+// Maximum nesting of display lists (F3DEX2 has the deepest stack).
+const DL_STACK_SIZE: usize = 18;
+// Maximum depth of the modelview matrix stack.
+const MTX_STACK_SIZE: usize = 32;
+// Safety net against corrupted display lists that loop forever.
+const MAX_COMMANDS: usize = 1_000_000;
+
+// Geometry mode bits with the same value in all microcodes.
+const G_ZBUFFER: u32 = 0x0000_0001;
+const G_SHADE: u32 = 0x0000_0004;
+const G_LIGHTING: u32 = 0x0002_0000;
+
+// Indices of G_MOVEWORD
+const G_MW_NUMLIGHT: u32 = 0x02;
+const G_MW_SEGMENT: u32 = 0x06;
+
+// Othermode high bit selecting perspective correction of textures.
+const G_TEXTURE_PERSP: u32 = 1 << 19;
+
+type Matrix = [[f32; 4]; 4];
+
+// Convert to s15.16 fixed point, as used by RDP coefficients.
+fn fx(v: f32) -> u64 {
+    (v * 65536.0) as i32 as u32 as u64
+}
+
+// Push the coefficients of four attributes (shade RGBA, or texture STW):
+// value, and derivatives along x, along the major edge and along y.
+// Integer and fractional parts are stored in separate words.
+fn push_attrs(cmds: &mut Vec<u64>, attrs: &[[f32; 4]; 4]) {
+    let word = |n: usize, int: bool| {
+        (0..4).fold(0u64, |acc, i| {
+            let v = fx(attrs[i][n]);
+            let half = if int { v >> 16 } else { v } & 0xFFFF;
+            acc | half << (48 - 16 * i)
+        })
+    };
+    for &(n, int) in &[
+        (0, true),
+        (1, true),
+        (0, false),
+        (1, false),
+        (2, true),
+        (3, true),
+        (2, false),
+        (3, false),
+    ] {
+        cmds.push(word(n, int));
+    }
+}
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+fn mtx_mul(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.0f32; 4]; 4];
+    for i in 0..4 {
+        for j in 0..4 {
+            m[i][j] = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+// Graphics microcode families supported by the HLE. They share the
+// vertex pipeline, but differ in command numbering and encoding.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Ucode {
+    F3d,
+    F3dex,
+    F3dex2,
+}
+
+impl Ucode {
+    // Identify the microcode through the version string embedded in its
+    // data segment (eg: "RSP Gfx ucode F3DEX       fifo 2.06  Yoshitaka
+    // Yasumoto 1998"). Versions 1.x are F3DEX, 2.x are F3DEX2; line
+    // microcodes (L3DEX) and sprite microcodes (S2DEX) are not supported.
+    fn detect(data: &[u8]) -> Option<Ucode> {
+        fn find(data: &[u8], pat: &[u8]) -> Option<usize> {
+            data.windows(pat.len()).position(|w| w == pat)
+        }
+        if find(data, b"RSP SW Version: 2.0").is_some() {
+            return Some(Ucode::F3d);
+        }
+        let name = &data[find(data, b"RSP Gfx ucode ")? + 14..];
+        if !name.starts_with(b"F3D") {
+            return None;
+        }
+        let ver = name
+            .windows(2)
+            .take(40)
+            .find(|w| w[0].is_ascii_digit() && w[1] == b'.')?;
+        match ver[0] {
+            b'1' => Some(Ucode::F3dex),
+            b'2' => Some(Ucode::F3dex2),
+            _ => None,
+        }
+    }
+
+    fn max_vertices(self) -> usize {
+        match self {
+            Ucode::F3d => 16,
+            _ => 32,
+        }
+    }
+
+    fn cull_front(self) -> u32 {
+        match self {
+            Ucode::F3dex2 => 0x0000_0200,
+            _ => 0x0000_1000,
+        }
+    }
+
+    fn cull_back(self) -> u32 {
+        match self {
+            Ucode::F3dex2 => 0x0000_0400,
+            _ => 0x0000_2000,
+        }
+    }
+
+    fn shading_smooth(self) -> u32 {
+        match self {
+            Ucode::F3dex2 => 0x0020_0000,
+            _ => 0x0000_0200,
+        }
+    }
+}
+
+// Result of a display list command, as far as the display list walker
+// is concerned.
+enum Flow {
+    Next,
+    Call(u32),
+    Jump(u32),
+    End,
+}
+
+#[derive(Copy, Clone, Default)]
+struct Vertex {
+    clip: [f32; 4],   // Clip coordinates
+    screen: [f32; 3], // Screen coordinates (pixels), with z in 0..0x7FFF
+    color: [f32; 4],  // RGBA, 0..255
+    tex: [f32; 2],    // Texture coordinates (texels)
+}
+
+#[derive(Copy, Clone, Default)]
+struct Light {
+    color: [f32; 3],
+    dir: [f32; 3],
+}
+
+/// GfxHle executes graphics tasks (F3D, F3DEX and F3DEX2 display lists)
+/// at high level: display lists are walked directly in RDRAM, vertices are
+/// transformed and lit on the host CPU, and the resulting triangles are
+/// sent to the RDP, together with the RDP commands embedded in the lists.
+///
+/// This is much faster than running the microcode on the RSP, but it's
+/// not accurate: there is no near-plane clipping (triangles crossing it are
+/// dropped), and fog, texture generation and BRANCH_Z depth tests are not
+/// emulated (BRANCH_Z always branches).
+pub(crate) struct GfxHle {
+    enabled: bool,
+    logger: slog::Logger,
+
+    // Microcode detected for the last ucode data segment seen
+    ucode_cache: Option<(u32, Option<Ucode>)>,
+
+    // RSP state for the current task
+    segments: [u32; 16],
+    modelview: Vec<Matrix>,
+    projection: Matrix,
+    mvp: Matrix,
+    vscale: [f32; 3],
+    vtrans: [f32; 3],
+    vertices: [Vertex; 32],
+    lights: [Light; 8],
+    num_lights: usize,
+    geometry_mode: u32,
+    othermode_h: u32,
+    othermode_l: u32,
+    tex_scale: [f32; 2],
+    tex_tile: u64,
+    tex_level: u64,
+    tex_on: bool,
+    half1: u32,
+
+    // RDP commands generated by the current task
+    cmds: Vec<u64>,
+}
+
+impl GfxHle {
+    pub(crate) fn new(logger: slog::Logger) -> GfxHle {
+        GfxHle {
+            enabled: false,
+            logger,
+            ucode_cache: None,
+            segments: [0; 16],
+            modelview: Vec::new(),
+            projection: IDENTITY,
+            mvp: IDENTITY,
+            vscale: [0.0; 3],
+            vtrans: [0.0; 3],
+            vertices: [Vertex::default(); 32],
+            lights: [Light::default(); 8],
+            num_lights: 0,
+            geometry_mode: 0,
+            othermode_h: 0,
+            othermode_l: 0,
+            tex_scale: [0.0; 2],
+            tex_tile: 0,
+            tex_level: 0,
+            tex_on: false,
+            half1: 0,
+            cmds: Vec::new(),
+        }
+    }
+
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Inspect the task that is about to be started on the RSP. If it's a
+    /// graphics task using a supported microcode, execute it and return true:
+    /// the caller must then complete the task without running the RSP.
+    pub(crate) fn process_task(&mut self, dmem: &[u8]) -> bool {
+        if !self.enabled || BigEndian::read_u32(&dmem[OSTASK_TYPE..]) != M_GFXTASK {
+            return false;
+        }
+
+        let ucode_data = BigEndian::read_u32(&dmem[OSTASK_UCODE_DATA..]) & 0x00FF_FFFF;
+        let ucode_data_size = BigEndian::read_u32(&dmem[OSTASK_UCODE_DATA_SIZE..]);
+        let ucode = match self.ucode_cache {
+            Some((addr, ucode)) if addr == ucode_data => ucode,
+            _ => {
+                let bus = &R4300::get().bus;
+                let data: Vec<u8> = (0..ucode_data_size.min(0x1000))
+                    .map(|off| bus.read::<u8>(ucode_data + off))
+                    .collect();
+                let ucode = Ucode::detect(&data);
+                info!(self.logger, "graphics microcode detected"; "data" => ucode_data.hex(), "ucode" => ?ucode);
+                self.ucode_cache = Some((ucode_data, ucode));
+                ucode
+            }
+        };
+        let ucode = match ucode {
+            Some(ucode) => ucode,
+            None => return false,
+        };
+
+        self.reset();
+        let data_ptr = BigEndian::read_u32(&dmem[OSTASK_DATA_PTR..]) & 0x00FF_FFFF;
+        self.run_dlist(ucode, data_ptr);
+        Dp::get_mut().hle_commands(&self.cmds);
+        true
+    }
+
+    fn reset(&mut self) {
+        self.segments = [0; 16];
+        self.modelview.clear();
+        self.modelview.push(IDENTITY);
+        self.projection = IDENTITY;
+        self.mvp = IDENTITY;
+        self.vscale = [160.0, 120.0, 511.0];
+        self.vtrans = [160.0, 120.0, 511.0];
+        self.num_lights = 0;
+        self.geometry_mode = 0;
+        self.othermode_h = 0;
+        self.othermode_l = 0;
+        self.tex_on = false;
+        self.cmds.clear();
+    }
+
+    fn read32(addr: u32) -> u32 {
+        R4300::get().bus.read::<u32>(addr & 0x00FF_FFFC)
+    }
+
+    fn segaddr(&self, addr: u32) -> u32 {
+        (self.segments[(addr >> 24) as usize & 0xF] + (addr & 0x00FF_FFFF)) & 0x00FF_FFFF
+    }
+
+    fn run_dlist(&mut self, ucode: Ucode, start: u32) {
+        let mut stack = Vec::with_capacity(DL_STACK_SIZE);
+        let mut pc = start;
+        for _ in 0..MAX_COMMANDS {
+            let w0 = Self::read32(pc);
+            let w1 = Self::read32(pc + 4);
+            pc += 8;
+            let flow = match ucode {
+                Ucode::F3dex2 => self.gbi2_command(w0, w1, &mut pc),
+                _ => self.gbi1_command(ucode, w0, w1, &mut pc),
+            };
+            match flow {
+                Flow::Next => {}
+                Flow::Call(addr) => {
+                    if stack.len() == DL_STACK_SIZE {
+                        warn!(self.logger, "display list stack overflow"; "pc" => (pc - 8).hex());
+                        return;
+                    }
+                    stack.push(pc);
+                    pc = addr;
+                }
+                Flow::Jump(addr) => pc = addr,
+                Flow::End => match stack.pop() {
+                    Some(ret) => pc = ret,
+                    None => return,
+                },
+            }
+        }
+        warn!(self.logger, "display list too long, aborted"; "start" => start.hex());
+    }
+
+    // Commands of F3D and F3DEX (GBI1)
+    fn gbi1_command(&mut self, ucode: Ucode, w0: u32, w1: u32, pc: &mut u32) -> Flow {
+        match w0 >> 24 {
+            0x00 => {} // G_SPNOOP
+            0x01 => {
+                // G_MTX
+                let p = (w0 >> 16) & 0xFF;
+                self.matrix(self.segaddr(w1), p & 1 != 0, p & 2 != 0, p & 4 != 0);
+            }
+            0x03 => {
+                // G_MOVEMEM
+                let idx = (w0 >> 16) & 0xFF;
+                match idx {
+                    0x80 => self.viewport(self.segaddr(w1)),
+                    0x86..=0x94 => self.light((idx as usize - 0x86) / 2, self.segaddr(w1)),
+                    _ => {}
+                }
+            }
+            0x04 => {
+                // G_VTX
+                let (v0, n) = if ucode == Ucode::F3d {
+                    ((w0 >> 16) & 0xF, ((w0 >> 20) & 0xF) + 1)
+                } else {
+                    (((w0 >> 16) & 0xFF) / 2, (w0 >> 10) & 0x3F)
+                };
+                self.vertex(ucode, self.segaddr(w1), v0 as usize, n as usize);
+            }
+            0x06 => return self.dlist(w0 >> 16, w1), // G_DL
+            0xB0 if ucode == Ucode::F3dex => return Flow::Jump(self.segaddr(self.half1)), // G_BRANCH_Z
+            0xB1 if ucode == Ucode::F3dex => {
+                // G_TRI2
+                self.triangle(ucode, w0 >> 16, w0 >> 8, w0, 2);
+                self.triangle(ucode, w1 >> 16, w1 >> 8, w1, 2);
+            }
+            0xB4 => self.half1 = w1, // G_RDPHALF_1
+            0xB5 if ucode == Ucode::F3dex => {
+                // G_QUAD
+                self.triangle(ucode, w1 >> 24, w1 >> 16, w1 >> 8, 2);
+                self.triangle(ucode, w1 >> 24, w1 >> 8, w1, 2);
+            }
+            0xB6 => self.geometry_mode &= !w1, // G_CLEARGEOMETRYMODE
+            0xB7 => self.geometry_mode |= w1,  // G_SETGEOMETRYMODE
+            0xB8 => return Flow::End,          // G_ENDDL
+            0xB9 | 0xBA => {
+                // G_SETOTHERMODE_L / G_SETOTHERMODE_H
+                let shift = (w0 >> 8) & 0xFF;
+                let len = w0 & 0xFF;
+                self.othermode(w0 >> 24 == 0xBA, shift, len, w1);
+            }
+            0xBB => self.texture(w0, w1, w0 & 0xFF != 0), // G_TEXTURE
+            0xBC => self.moveword(ucode, w0 & 0xFF, (w0 >> 8) & 0xFFFF, w1), // G_MOVEWORD
+            0xBD => self.pop_matrix(1),                   // G_POPMTX
+            0xBE => {}                                    // G_CULLDL
+            0xBF => {
+                // G_TRI1
+                let div = if ucode == Ucode::F3d { 10 } else { 2 };
+                self.triangle(ucode, w1 >> 16, w1 >> 8, w1, div);
+            }
+            0xC0..=0xFF => self.rdp_command(w0, w1, pc),
+            op => warn!(self.logger, "unsupported display list command"; "op" => (op as u8).hex()),
+        }
+        Flow::Next
+    }
+
+    // Commands of F3DEX2 (GBI2)
+    fn gbi2_command(&mut self, w0: u32, w1: u32, pc: &mut u32) -> Flow {
+        let ucode = Ucode::F3dex2;
+        match w0 >> 24 {
+            0x00 => {} // G_NOOP
+            0x01 => {
+                // G_VTX
+                let n = (w0 >> 12) & 0xFF;
+                let vend = (w0 >> 1) & 0x7F;
+                if n <= vend {
+                    self.vertex(ucode, self.segaddr(w1), (vend - n) as usize, n as usize);
+                }
+            }
+            0x02 => {}                                              // G_MODIFYVTX
+            0x03 => {}                                              // G_CULLDL
+            0x04 => return Flow::Jump(self.segaddr(self.half1)),    // G_BRANCH_Z
+            0x05 => self.triangle(ucode, w0 >> 16, w0 >> 8, w0, 2), // G_TRI1
+            0x06 | 0x07 => {
+                // G_TRI2 / G_QUAD
+                self.triangle(ucode, w0 >> 16, w0 >> 8, w0, 2);
+                self.triangle(ucode, w1 >> 16, w1 >> 8, w1, 2);
+            }
+            0xD7 => self.texture(w0, w1, (w0 >> 1) & 0x7F != 0), // G_TEXTURE
+            0xD8 => self.pop_matrix((w1 / 64) as usize),         // G_POPMTX
+            0xD9 => {
+                // G_GEOMETRYMODE
+                self.geometry_mode = (self.geometry_mode & (w0 & 0x00FF_FFFF)) | w1;
+            }
+            0xDA => {
+                // G_MTX (the push flag is stored inverted)
+                let p = (w0 & 0xFF) ^ 1;
+                self.matrix(self.segaddr(w1), p & 4 != 0, p & 2 != 0, p & 1 != 0);
+            }
+            0xDB => self.moveword(ucode, (w0 >> 16) & 0xFF, w0 & 0xFFFF, w1), // G_MOVEWORD
+            0xDC => {
+                // G_MOVEMEM
+                let offset = ((w0 >> 8) & 0xFF) * 8;
+                match w0 & 0xFF {
+                    8 => self.viewport(self.segaddr(w1)),
+                    // The first two slots hold the lookat vectors
+                    10 if offset >= 48 => self.light(offset as usize / 24 - 2, self.segaddr(w1)),
+                    _ => {}
+                }
+            }
+            0xDE => return self.dlist(w0 >> 16, w1), // G_DL
+            0xDF => return Flow::End,                // G_ENDDL
+            0xE1 => self.half1 = w1,                 // G_RDPHALF_1
+            0xE2 | 0xE3 => {
+                // G_SETOTHERMODE_L / G_SETOTHERMODE_H
+                let len = (w0 & 0xFF) + 1;
+                let shift = 32u32.saturating_sub(((w0 >> 8) & 0xFF) + len);
+                self.othermode(w0 >> 24 == 0xE3, shift, len, w1);
+            }
+            0xC0..=0xFF => self.rdp_command(w0, w1, pc),
+            op => warn!(self.logger, "unsupported display list command"; "op" => (op as u8).hex()),
+        }
+        Flow::Next
+    }
+
+    fn dlist(&self, param: u32, addr: u32) -> Flow {
+        if param & 0xFF == 0 {
+            Flow::Call(self.segaddr(addr))
+        } else {
+            Flow::Jump(self.segaddr(addr))
+        }
+    }
+
+    // Forward a RDP command embedded in the display list. Texture
+    // rectangles are followed by two RDPHALF commands holding the second
+    // word of the RDP command.
+    fn rdp_command(&mut self, w0: u32, w1: u32, pc: &mut u32) {
+        self.cmds.push((w0 as u64) << 32 | w1 as u64);
+        if w0 >> 24 == 0xE4 || w0 >> 24 == 0xE5 {
+            let st = Self::read32(*pc + 4);
+            let dsdt = Self::read32(*pc + 12);
+            *pc += 16;
+            self.cmds.push((st as u64) << 32 | dsdt as u64);
+        }
+    }
+
+    fn othermode(&mut self, high: bool, shift: u32, len: u32, value: u32) {
+        let mask = (((1u64 << len) - 1) << shift) as u32;
+        if high {
+            self.othermode_h = (self.othermode_h & !mask) | (value & mask);
+        } else {
+            self.othermode_l = (self.othermode_l & !mask) | (value & mask);
+        }
+        self.cmds.push(
+            0xEF << 56 | ((self.othermode_h & 0x00FF_FFFF) as u64) << 32 | self.othermode_l as u64,
+        );
+    }
+
+    fn texture(&mut self, w0: u32, w1: u32, on: bool) {
+        self.tex_scale = [(w1 >> 16) as f32 / 65536.0, (w1 & 0xFFFF) as f32 / 65536.0];
+        self.tex_tile = ((w0 >> 8) & 7) as u64;
+        self.tex_level = ((w0 >> 11) & 7) as u64;
+        self.tex_on = on;
+    }
+
+    fn moveword(&mut self, ucode: Ucode, index: u32, offset: u32, value: u32) {
+        match index {
+            G_MW_SEGMENT => self.segments[(offset / 4) as usize & 0xF] = value & 0x00FF_FFFF,
+            G_MW_NUMLIGHT => {
+                let num = if ucode == Ucode::F3dex2 {
+                    value / 24
+                } else {
+                    (value.wrapping_sub(0x8000_0000) >> 5).wrapping_sub(1)
+                };
+                self.num_lights = num.min(7) as usize;
+            }
+            _ => {}
+        }
+    }
+
+    fn matrix(&mut self, addr: u32, projection: bool, load: bool, push: bool) {
+        // Matrices are stored as 16 s15.16 fixed-point numbers, with all the
+        // integer parts first, followed by all the fractional parts.
+        let mut m = [[0.0f32; 4]; 4];
+        for i in 0..4 {
+            for j in 0..4 {
+                let off = (i * 4 + j) as u32 * 2;
+                let int = Self::read32(addr + (off & !3));
+                let frac = Self::read32(addr + 32 + (off & !3));
+                let (int, frac) = if off & 2 == 0 {
+                    (int >> 16, frac >> 16)
+                } else {
+                    (int & 0xFFFF, frac & 0xFFFF)
+                };
+                m[i][j] = ((int << 16) | frac) as i32 as f32 / 65536.0;
+            }
+        }
+
+        if projection {
+            self.projection = if load {
+                m
+            } else {
+                mtx_mul(&m, &self.projection)
+            };
+        } else {
+            if push {
+                if self.modelview.len() < MTX_STACK_SIZE {
+                    let top = *self.modelview.last().unwrap();
+                    self.modelview.push(top);
+                } else {
+                    warn!(self.logger, "modelview matrix stack overflow");
+                }
+            }
+            let top = self.modelview.last_mut().unwrap();
+            *top = if load { m } else { mtx_mul(&m, top) };
+        }
+        self.mvp = mtx_mul(self.modelview.last().unwrap(), &self.projection);
+    }
+
+    fn pop_matrix(&mut self, num: usize) {
+        for _ in 0..num {
+            if self.modelview.len() > 1 {
+                self.modelview.pop();
+            }
+        }
+        self.mvp = mtx_mul(self.modelview.last().unwrap(), &self.projection);
+    }
+
+    fn viewport(&mut self, addr: u32) {
+        // Vp_t: scale and translation as s16, with x/y in 2-bit fixed point
+        let half = |off: u32| {
+            let w = Self::read32(addr + (off & !3));
+            (if off & 2 == 0 { w >> 16 } else { w }) as i16 as f32
+        };
+        self.vscale = [half(0) / 4.0, half(2) / 4.0, half(4)];
+        self.vtrans = [half(8) / 4.0, half(10) / 4.0, half(12)];
+    }
+
+    fn light(&mut self, idx: usize, addr: u32) {
+        if idx >= self.lights.len() {
+            return;
+        }
+        let col = Self::read32(addr);
+        let dir = Self::read32(addr + 8);
+        self.lights[idx] = Light {
+            color: [
+                (col >> 24) as f32,
+                ((col >> 16) & 0xFF) as f32,
+                ((col >> 8) & 0xFF) as f32,
+            ],
+            dir: [
+                (dir >> 24) as i8 as f32 / 127.0,
+                (dir >> 16) as i8 as f32 / 127.0,
+                (dir >> 8) as i8 as f32 / 127.0,
+            ],
+        };
+    }
+
+    fn vertex(&mut self, ucode: Ucode, addr: u32, v0: usize, n: usize) {
+        if v0 + n > ucode.max_vertices() {
+            warn!(self.logger, "vertex buffer overflow"; "v0" => v0, "n" => n);
+            return;
+        }
+        let mv = *self.modelview.last().unwrap();
+        for i in 0..n {
+            let base = addr + i as u32 * 16;
+            let xy = Self::read32(base);
+            let zf = Self::read32(base + 4);
+            let st = Self::read32(base + 8);
+            let rgba = Self::read32(base + 12);
+            let pos = [
+                (xy >> 16) as i16 as f32,
+                xy as i16 as f32,
+                (zf >> 16) as i16 as f32,
+            ];
+
+            let mut v = Vertex::default();
+            for j in 0..4 {
+                v.clip[j] = pos[0] * self.mvp[0][j]
+                    + pos[1] * self.mvp[1][j]
+                    + pos[2] * self.mvp[2][j]
+                    + self.mvp[3][j];
+            }
+            let w = v.clip[3];
+            if w > 0.0 {
+                v.screen = [
+                    self.vtrans[0] + v.clip[0] / w * self.vscale[0],
+                    self.vtrans[1] - v.clip[1] / w * self.vscale[1],
+                    ((self.vtrans[2] + v.clip[2] / w * self.vscale[2]) * 32.0)
+                        .max(0.0)
+                        .min(32767.0),
+                ];
+            }
+
+            let bytes = [
+                (rgba >> 24) as u8,
+                (rgba >> 16) as u8,
+                (rgba >> 8) as u8,
+                rgba as u8,
+            ];
+            v.color = if self.geometry_mode & G_LIGHTING != 0 {
+                // Vertex colors are normals: rotate them in eye space, and
+                // light them with the directional lights plus ambient.
+                let n = [
+                    bytes[0] as i8 as f32,
+                    bytes[1] as i8 as f32,
+                    bytes[2] as i8 as f32,
+                ];
+                let mut en = [0.0f32; 3];
+                for j in 0..3 {
+                    en[j] = n[0] * mv[0][j] + n[1] * mv[1][j] + n[2] * mv[2][j];
+                }
+                let len = en.iter().map(|c| c * c).sum::<f32>().sqrt().max(1e-6);
+                let mut c = self.lights[self.num_lights].color;
+                for l in &self.lights[..self.num_lights] {
+                    let dot = (0..3).map(|j| en[j] / len * l.dir[j]).sum::<f32>().max(0.0);
+                    for j in 0..3 {
+                        c[j] += l.color[j] * dot;
+                    }
+                }
+                [
+                    c[0].min(255.0),
+                    c[1].min(255.0),
+                    c[2].min(255.0),
+                    bytes[3] as f32,
+                ]
+            } else {
+                [
+                    bytes[0] as f32,
+                    bytes[1] as f32,
+                    bytes[2] as f32,
+                    bytes[3] as f32,
+                ]
+            };
+
+            // Texture coordinates are s10.5
+            v.tex = [
+                (st >> 16) as i16 as f32 / 32.0 * self.tex_scale[0],
+                st as i16 as f32 / 32.0 * self.tex_scale[1],
+            ];
+            self.vertices[v0 + i] = v;
+        }
+    }
+
+    fn triangle(&mut self, ucode: Ucode, a: u32, b: u32, c: u32, div: u32) {
+        let idx = [(a & 0xFF) / div, (b & 0xFF) / div, (c & 0xFF) / div];
+        if idx.iter().any(|&i| i as usize >= ucode.max_vertices()) {
+            return;
+        }
+        let mut vtx = [
+            self.vertices[idx[0] as usize],
+            self.vertices[idx[1] as usize],
+            self.vertices[idx[2] as usize],
+        ];
+        if vtx.iter().any(|v| v.clip[3] <= 0.0) {
+            return;
+        }
+
+        // Back-face culling; front faces are counter-clockwise on screen.
+        let area = (vtx[1].screen[0] - vtx[0].screen[0]) * (vtx[2].screen[1] - vtx[0].screen[1])
+            - (vtx[2].screen[0] - vtx[0].screen[0]) * (vtx[1].screen[1] - vtx[0].screen[1]);
+        if area == 0.0
+            || (area < 0.0 && self.geometry_mode & ucode.cull_front() != 0)
+            || (area > 0.0 && self.geometry_mode & ucode.cull_back() != 0)
+        {
+            return;
+        }
+
+        // Flat shading uses the color of the first vertex.
+        if self.geometry_mode & ucode.shading_smooth() == 0 {
+            let color = vtx[0].color;
+            for v in vtx.iter_mut() {
+                v.color = color;
+            }
+        }
+
+        vtx.sort_by(|a, b| {
+            a.screen[1]
+                .partial_cmp(&b.screen[1])
+                .unwrap_or(Ordering::Equal)
+        });
+        self.rdp_triangle(&vtx);
+    }
+
+    // Compute the RDP edge and attribute coefficients of a triangle, whose
+    // vertices are sorted by y.
+    fn rdp_triangle(&mut self, vtx: &[Vertex; 3]) {
+        let shade = self.geometry_mode & G_SHADE != 0;
+        let zbuf = self.geometry_mode & G_ZBUFFER != 0;
+        let tex = self.tex_on;
+
+        let (h, m, l) = (&vtx[0].screen, &vtx[1].screen, &vtx[2].screen);
+        let slope = |a: &[f32; 3], b: &[f32; 3]| {
+            if b[1] > a[1] {
+                (b[0] - a[0]) / (b[1] - a[1])
+            } else {
+                0.0
+            }
+        };
+        let dxhdy = slope(h, l);
+        let dxmdy = slope(h, m);
+        let dxldy = slope(m, l);
+
+        // Major and middle edges start at the first scanline touched by the
+        // triangle, while the low edge starts at the middle vertex.
+        let ytop = h[1].floor();
+        let xh = h[0] + dxhdy * (ytop - h[1]);
+        let xm = h[0] + dxmdy * (ytop - h[1]);
+        let xl = m[0];
+
+        let (dx1, dy1) = (m[0] - h[0], m[1] - h[1]);
+        let (dx2, dy2) = (l[0] - h[0], l[1] - h[1]);
+        let nz = dx1 * dy2 - dx2 * dy1;
+        let lft = nz > 0.0;
+
+        // Gradients of an attribute: value at the start of the major edge,
+        // and derivatives along x, along the major edge and along y.
+        let gradients = |ah: f32, am: f32, al: f32| {
+            let (da1, da2) = (am - ah, al - ah);
+            let dadx = (da1 * dy2 - da2 * dy1) / nz;
+            let dady = (dx1 * da2 - da1 * dx2) / nz;
+            let dade = dady + dadx * dxhdy;
+            [ah + dade * (ytop - h[1]), dadx, dade, dady]
+        };
+
+        let fy = |v: f32| ((v * 4.0) as i32 as u64) & 0x3FFF;
+
+        let op = 0x08 | (shade as u64) << 2 | (tex as u64) << 1 | zbuf as u64;
+        self.cmds.push(
+            op << 56
+                | (lft as u64) << 55
+                | self.tex_level << 51
+                | self.tex_tile << 48
+                | fy(l[1]) << 32
+                | fy(m[1]) << 16
+                | fy(h[1]),
+        );
+        self.cmds.push(fx(xl) << 32 | fx(dxldy) & 0xFFFF_FFFF);
+        self.cmds.push(fx(xh) << 32 | fx(dxhdy) & 0xFFFF_FFFF);
+        self.cmds.push(fx(xm) << 32 | fx(dxmdy) & 0xFFFF_FFFF);
+
+        if shade {
+            let mut attrs = [[0.0f32; 4]; 4];
+            for (i, a) in attrs.iter_mut().enumerate() {
+                *a = gradients(vtx[0].color[i], vtx[1].color[i], vtx[2].color[i]);
+            }
+            push_attrs(&mut self.cmds, &attrs);
+        }
+        if tex {
+            // With perspective correction, the RDP interpolates s/w, t/w
+            // and 1/w, normalized so that the nearest vertex has w=1.
+            let persp = self.othermode_h & G_TEXTURE_PERSP != 0;
+            let wmin = vtx.iter().map(|v| v.clip[3]).fold(std::f32::MAX, f32::min);
+            let mut stw = [[0.0f32; 3]; 3];
+            for (i, v) in vtx.iter().enumerate() {
+                let wn = if persp { wmin / v.clip[3] } else { 1.0 };
+                stw[i] = [v.tex[0] * 32.0 * wn, v.tex[1] * 32.0 * wn, wn * 32767.0];
+            }
+            let mut attrs = [[0.0f32; 4]; 4];
+            for (i, a) in attrs.iter_mut().take(3).enumerate() {
+                *a = gradients(stw[0][i], stw[1][i], stw[2][i]);
+            }
+            push_attrs(&mut self.cmds, &attrs);
+        }
+        if zbuf {
+            let z = gradients(vtx[0].screen[2], vtx[1].screen[2], vtx[2].screen[2]);
+            self.cmds.push(fx(z[0]) << 32 | fx(z[1]) & 0xFFFF_FFFF);
+            self.cmds.push(fx(z[2]) << 32 | fx(z[3]) & 0xFFFF_FFFF);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ri::{RdramSize, Ri};
+    use super::*;
+
+    const DL: u32 = 0x1000;
+    const DATA: u32 = 0x2000;
+
+    fn setup() -> GfxHle {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        R4300::new(logger.new(o!())).register();
+        Ri::new(logger.new(o!()), RdramSize::Mb4).register();
+        R4300::get_mut()
+            .bus
+            .map_device(0x0000_0000, Ri::get(), 0)
+            .unwrap();
+        let mut hle = GfxHle::new(logger);
+        hle.reset();
+        hle
+    }
+
+    fn write_words(addr: u32, words: &[u32]) {
+        let bus = &mut R4300::get_mut().bus;
+        for (i, &w) in words.iter().enumerate() {
+            bus.write::<u32>(addr + i as u32 * 4, w);
+        }
+    }
+
+    fn write_dlist(addr: u32, cmds: &[(u32, u32)]) {
+        let words: Vec<u32> = cmds.iter().flat_map(|&(w0, w1)| vec![w0, w1]).collect();
+        write_words(addr, &words);
+    }
+
+    // Store a matrix in the RSP format: integer parts, then fractional parts.
+    fn write_matrix(addr: u32, m: &Matrix) {
+        let mut words = [0u32; 16];
+        for k in 0..16 {
+            let v = (m[k / 4][k % 4] * 65536.0) as i32 as u32;
+            let shift = if k % 2 == 0 { 16 } else { 0 };
+            words[k / 2] |= (v >> 16) << shift;
+            words[8 + k / 2] |= (v & 0xFFFF) << shift;
+        }
+        write_words(addr, &words);
+    }
+
+    fn write_vertices(addr: u32, vtx: &[(i16, i16, i16, u32)]) {
+        for (i, &(x, y, z, rgba)) in vtx.iter().enumerate() {
+            write_words(
+                addr + i as u32 * 16,
+                &[
+                    (x as u16 as u32) << 16 | y as u16 as u32,
+                    (z as u16 as u32) << 16,
+                    0,
+                    rgba,
+                ],
+            );
+        }
+    }
+
+    const TRANSLATE: Matrix = [
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [1.0, 2.0, -3.5, 1.0],
+    ];
+
+    const SCALE: Matrix = [
+        [2.0, 0.0, 0.0, 0.0],
+        [0.0, 0.5, 0.0, 0.0],
+        [0.0, 0.0, 2.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ];
+
+    #[test]
+    fn detect_ucode() {
+        let detect = |s: &str| Ucode::detect(s.as_bytes());
+        assert_eq!(detect("RSP SW Version: 2.0D, 04-01-96"), Some(Ucode::F3d));
+        assert_eq!(
+            detect("..RSP Gfx ucode F3DEX       fifo 1.23 Yoshitaka Yasumoto 1997"),
+            Some(Ucode::F3dex)
+        );
+        assert_eq!(
+            detect("RSP Gfx ucode F3DZEX.NoN   fifo 2.08  Yoshitaka Yasumoto 1999"),
+            Some(Ucode::F3dex2)
+        );
+        assert_eq!(
+            detect("RSP Gfx ucode S2DEX  fifo 1.06  Yoshitaka Yasumoto 1998"),
+            None
+        );
+        assert_eq!(
+            detect("RSP Gfx ucode L3DEX       fifo 2.08  Yoshitaka Yasumoto 1999"),
+            None
+        );
+        assert_eq!(detect("no version string"), None);
+    }
+
+    #[test]
+    fn dlist_flow() {
+        let mut hle = setup();
+        write_dlist(
+            DL,
+            &[
+                (0xDB06_0004, DATA),            // G_MOVEWORD: segment 1
+                (0xDE00_0000, 0x0100_0000),     // G_DL: call segment 1
+                (0xE300_0C00, G_TEXTURE_PERSP), // G_SETOTHERMODE_H
+                (0xE400_0000, 0x0000_0000),     // G_TEXRECT
+                (0xE100_0000, 0x1111_2222),     // G_RDPHALF_1
+                (0xF100_0000, 0x3333_4444),     // G_RDPHALF_2
+                (0xDE01_0000, 0x0100_0010),     // G_DL: jump to segment 1 + 0x10
+                (0xE900_0000, 0),               // G_RDPFULLSYNC, skipped
+            ],
+        );
+        write_dlist(
+            DATA,
+            &[
+                (0xF900_0000, 0x1122_3344), // G_SETBLENDCOLOR
+                (0xDF00_0000, 0),           // G_ENDDL: return
+                (0xE700_0000, 0),           // G_RDPPIPESYNC
+                (0xDF00_0000, 0),           // G_ENDDL: end of task
+            ],
+        );
+        hle.run_dlist(Ucode::F3dex2, DL);
+        assert_eq!(hle.segments[1], DATA);
+        assert_eq!(
+            hle.cmds,
+            vec![
+                0xF900_0000_1122_3344,
+                0xEF08_0000_0000_0000,
+                0xE400_0000_0000_0000,
+                0x1111_2222_3333_4444,
+                0xE700_0000_0000_0000,
+            ]
+        );
+    }
+
+    #[test]
+    fn dlist_stack_overflow() {
+        // A display list calling itself forever.
+        let mut hle = setup();
+        write_dlist(DL, &[(0xF900_0000, 0), (0xDE00_0000, DL)]);
+        hle.run_dlist(Ucode::F3dex2, DL);
+        assert_eq!(hle.cmds.len(), DL_STACK_SIZE + 1);
+    }
+
+    #[test]
+    fn matrix_stack() {
+        let mut hle = setup();
+        write_matrix(DATA, &TRANSLATE);
+        write_matrix(DATA + 0x40, &SCALE);
+        write_dlist(
+            DL,
+            &[
+                (0xBC00_0406, DATA),        // G_MOVEWORD: segment 1
+                (0x0106_0040, 0x0100_0000), // G_MTX: modelview, load, push
+                (0x0104_0040, 0x0100_0040), // G_MTX: modelview, mul, push
+                (0x0103_0040, 0x0100_0040), // G_MTX: projection, load
+                (0xB800_0000, 0),           // G_ENDDL
+            ],
+        );
+        hle.run_dlist(Ucode::F3d, DL);
+        let st = mtx_mul(&SCALE, &TRANSLATE);
+        assert_eq!(hle.modelview, vec![IDENTITY, TRANSLATE, st]);
+        assert_eq!(hle.projection, SCALE);
+        assert_eq!(hle.mvp, mtx_mul(&st, &SCALE));
+
+        write_dlist(DL, &[(0xBD00_0000, 0), (0xB800_0000, 0)]); // G_POPMTX
+        hle.run_dlist(Ucode::F3d, DL);
+        assert_eq!(hle.modelview, vec![IDENTITY, TRANSLATE]);
+        assert_eq!(hle.mvp, mtx_mul(&TRANSLATE, &SCALE));
+
+        // The stack has a fixed depth, and the first matrix is never popped.
+        let mut cmds = vec![(0xDA38_0000, DATA + 0x40); 40]; // G_MTX: mul, push
+        cmds.push((0xDF00_0000, 0));
+        write_dlist(DL, &cmds);
+        hle.run_dlist(Ucode::F3dex2, DL);
+        assert_eq!(hle.modelview.len(), MTX_STACK_SIZE);
+
+        write_dlist(DL, &[(0xD838_0002, 100 * 64), (0xDF00_0000, 0)]); // G_POPMTX
+        hle.run_dlist(Ucode::F3dex2, DL);
+        assert_eq!(hle.modelview, vec![IDENTITY]);
+        assert_eq!(hle.mvp, SCALE);
+    }
+
+    #[test]
+    fn vertex_transform() {
+        let mut hle = setup();
+        let mut half = IDENTITY;
+        half[0][0] = 0.5;
+        half[1][1] = 0.5;
+        write_matrix(DATA, &half);
+        // Viewport: scale (100, 50, 256), translation (200, 100, 256)
+        write_words(
+            DATA + 0x40,
+            &[400 << 16 | 200, 256 << 16, 800 << 16 | 400, 256 << 16],
+        );
+        write_vertices(
+            DATA + 0x80,
+            &[(0, 0, 0, 0x1122_3344), (2, -2, 0, 0), (-2, 2, 1, 0)],
+        );
+        write_dlist(
+            DL,
+            &[
+                (0xDA38_0003, DATA),        // G_MTX: modelview, load
+                (0xDC00_0008, DATA + 0x40), // G_MOVEMEM: viewport
+                (0x0100_300A, DATA + 0x80), // G_VTX: 3 vertices at 2
+                (0xDF00_0000, 0),
+            ],
+        );
+        hle.run_dlist(Ucode::F3dex2, DL);
+
+        let v = &hle.vertices[2..5];
+        assert_eq!(v[0].screen, [200.0, 100.0, 256.0 * 32.0]);
+        assert_eq!(v[0].color, [17.0, 34.0, 51.0, 68.0]);
+        assert_eq!(v[1].clip, [1.0, -1.0, 0.0, 1.0]);
+        assert_eq!(v[1].screen, [300.0, 150.0, 256.0 * 32.0]);
+        assert_eq!(v[2].screen, [100.0, 50.0, 512.0 * 32.0]);
+
+        // Loads past the end of the vertex buffer are ignored.
+        write_dlist(DL, &[(0x0100_3042, DATA + 0x80), (0xDF00_0000, 0)]);
+        hle.run_dlist(Ucode::F3dex2, DL);
+        assert_eq!(hle.vertices[31].screen, [0.0; 3]);
+    }
+
+    #[test]
+    fn triangle_culling() {
+        let mut hle = setup();
+        // With the default viewport, the triangle (0,1,2) covers the
+        // whole screen and (0,2,1) is its back face.
+        write_vertices(DATA, &[(0, 1, 0, 0), (-1, -1, 0, 0), (1, -1, 0, 0)]);
+        write_dlist(
+            DL,
+            &[
+                (0xD9FF_FFFF, 0x0000_0400), // G_GEOMETRYMODE: cull back
+                (0x0100_3006, DATA),        // G_VTX
+                (0x0500_0204, 0),           // G_TRI1 (0,1,2)
+                (0x0500_0402, 0),           // G_TRI1 (0,2,1), culled
+                (0xDF00_0000, 0),
+            ],
+        );
+        hle.run_dlist(Ucode::F3dex2, DL);
+        assert_eq!(hle.cmds.len(), 4);
+        assert_eq!(hle.cmds[0], 0x0800_03C0_03C0_0000);
+        assert_eq!(hle.cmds[1], 0);
+        assert_eq!(hle.cmds[2] >> 48, 160);
+        assert_eq!(hle.cmds[3] >> 48, 160);
+
+        // Culling the front face instead, with shading and z-buffer.
+        hle.cmds.clear();
+        write_dlist(
+            DL,
+            &[
+                (0xD900_0000, 0x0020_0205), // G_GEOMETRYMODE: cull front
+                (0x0500_0204, 0),           // G_TRI1 (0,1,2), culled
+                (0x0600_0402, 0x0000_0000), // G_TRI2 (0,2,1) and (0,0,0)
+                (0xDF00_0000, 0),
+            ],
+        );
+        hle.run_dlist(Ucode::F3dex2, DL);
+        assert_eq!(hle.cmds.len(), 4 + 8 + 2);
+        assert_eq!(hle.cmds[0] >> 56, 0x0D);
+    }
+
+    #[test]
+    fn triangle_gbi1() {
+        let mut hle = setup();
+        write_vertices(DATA, &[(0, 1, 0, 0), (-1, -1, 0, 0), (1, -1, 0, 0)]);
+        write_dlist(
+            DL,
+            &[
+                (0xB700_0000, 0x0000_2000), // G_SETGEOMETRYMODE: cull back
+                (0x0420_0030, DATA),        // G_VTX: 3 vertices at 0
+                (0xBF00_0000, 0x0000_0A14), // G_TRI1 (0,1,2)
+                (0xBF00_0000, 0x0000_140A), // G_TRI1 (0,2,1), culled
+                (0xBF00_0000, 0x0000_0AC8), // G_TRI1 (0,1,20), out of range
+                (0xB800_0000, 0),
+            ],
+        );
+        hle.run_dlist(Ucode::F3d, DL);
+        assert_eq!(hle.cmds.len(), 4);
+        assert_eq!(hle.cmds[0], 0x0800_03C0_03C0_0000);
+    }
+
+    #[test]
+    fn clip_behind_camera() {
+        let mut hle = setup();
+        // Projection with w = z: vertices with z <= 0 are behind the camera.
+        let mut persp = IDENTITY;
+        persp[2][3] = 1.0;
+        persp[3][3] = 0.0;
+        write_matrix(DATA, &persp);
+        write_vertices(
+            DATA + 0x40,
+            &[(0, 1, 1, 0), (-1, -1, 1, 0), (1, -1, 1, 0), (1, -1, -1, 0)],
+        );
+        write_dlist(
+            DL,
+            &[
+                (0xDA38_0007, DATA),        // G_MTX: projection, load
+                (0x0100_4008, DATA + 0x40), // G_VTX: 4 vertices at 0
+                (0x0500_0204, 0),           // G_TRI1 (0,1,2)
+                (0x0500_0206, 0),           // G_TRI1 (0,1,3), dropped
+                (0xDF00_0000, 0),
+            ],
+        );
+        hle.run_dlist(Ucode::F3dex2, DL);
+        assert_eq!(hle.vertices[0].clip[3], 1.0);
+        assert_eq!(hle.vertices[3].clip[3], -1.0);
+        assert_eq!(hle.cmds.len(), 4);
+    }
+}
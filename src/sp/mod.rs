@@ -8,6 +8,8 @@ mod decode;
 mod accumulator;
 mod cop0;
 mod cop2;
+mod gfxhle;
 mod vclip;
 mod vmul;
+mod voices;
 mod vrcp;
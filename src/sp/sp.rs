@@ -1,9 +1,14 @@
+use super::super::dmalog::{self, DmaDevice};
 use super::super::mi::{IrqMask, Mi};
 use super::super::r4300::R4300;
 use super::cop0::SpCop0;
 use super::cop2::SpCop2;
+use super::gfxhle::GfxHle;
+use super::voices::AudioVoices;
 use crate::errors::*;
 use emu::bus::be::{Bus, Device, Mem, Reg32};
+#[cfg(feature = "frontend")]
+use emu::dbg::DebuggerRenderer;
 use emu::int::Numerics;
 use emu::memint::MemInt;
 use mips64;
@@ -151,6 +156,12 @@ pub struct Sp {
     #[reg(bank = 1, offset = 0x1C, init = 0x0, rwmask = 0x1, rcb)]
     reg_semaphore: Reg32,
 
+    // Debugging aid to inspect and mute audio voices (not part of the state)
+    voices: AudioVoices,
+
+    // High-level emulation of graphics microcodes (not part of the state)
+    gfx_hle: GfxHle,
+
     logger: slog::Logger,
 }
 
@@ -172,9 +183,18 @@ impl Sp {
             reg_rsp_pc: Reg32::default(),
             reg_dma_full: Reg32::default(),
             reg_semaphore: Reg32::default(),
+            voices: AudioVoices::default(),
+            gfx_hle: GfxHle::new(logger.new(o!())),
         }))
     }
 
+    /// Execute graphics tasks (F3D, F3DEX and F3DEX2 microcodes) at high
+    /// level instead of running them on the RSP. Tasks using other
+    /// microcodes are still executed on the RSP.
+    pub fn set_gfx_hle(&mut self, enabled: bool) {
+        self.gfx_hle.set_enabled(enabled);
+    }
+
     pub(crate) fn get_status(&self) -> StatusFlags {
         StatusFlags::from_bits(self.reg_status.get()).unwrap()
     }
@@ -298,6 +318,18 @@ impl Sp {
                 // execution continues from the point where it was halted
                 // before (verified on real hardware).
                 info!(self.logger, "RSP started");
+                self.voices.scan_task(&self.dmem);
+                if self.gfx_hle.process_task(&self.dmem) {
+                    // The display list was executed at high level: complete
+                    // the task as the microcode would (signal "task done"
+                    // and break), without running the RSP.
+                    let done = status | StatusFlags::HALT | StatusFlags::BROKE | StatusFlags::SIG2;
+                    self.reg_status.set(done.bits());
+                    if done.contains(StatusFlags::INTBREAK) {
+                        Mi::get_mut().set_irq_line(IrqMask::SP, true);
+                    }
+                    return None;
+                }
                 return Some(false);
             }
         }
@@ -369,6 +401,12 @@ impl Sp {
             "skip" => skip,
         ));
 
+        dmalog::record(
+            DmaDevice::Sp,
+            src,
+            dst + 0x0400_0000,
+            (width * count) as u32,
+        );
         self.dma_xfer(src, dst + 0x0400_0000, width, count, skip, 0);
     }
 
@@ -388,6 +426,12 @@ impl Sp {
             "skip" => skip,
         ));
 
+        dmalog::record(
+            DmaDevice::Sp,
+            self.reg_dma_rsp_addr.get() + 0x0400_0000,
+            self.reg_dma_rdram_addr.get(),
+            (width * count) as u32,
+        );
         self.dma_xfer(
             self.reg_dma_rsp_addr.get() + 0x0400_0000,
             self.reg_dma_rdram_addr.get(),
@@ -406,4 +450,9 @@ impl Sp {
     fn cb_read_reg_rsp_pc(&self, _old: u32) -> u32 {
         RSPCPU::get().ctx().get_pc() as u32 & 0xFFF
     }
+
+    #[cfg(feature = "frontend")]
+    pub fn render_debug<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>) {
+        self.voices.render_debug(dr);
+    }
 }
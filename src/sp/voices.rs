@@ -0,0 +1,279 @@
+use super::super::r4300::R4300;
+use byteorder::{BigEndian, ByteOrder};
+#[cfg(feature = "frontend")]
+use emu::dbg::imgui::*;
+#[cfg(feature = "frontend")]
+use emu::dbg::DebuggerRenderer;
+use emu::int::Numerics;
+
+use std::collections::BTreeMap;
+
+// OSTask structure, as loaded by libultra into the end of DMEM before
+// starting the RSP.
+pub(super) const OSTASK_ADDR: usize = 0xFC0;
+pub(super) const OSTASK_TYPE: usize = OSTASK_ADDR + 0x00;
+pub(super) const OSTASK_DATA_PTR: usize = OSTASK_ADDR + 0x30;
+pub(super) const OSTASK_DATA_SIZE: usize = OSTASK_ADDR + 0x34;
+const M_AUDTASK: u32 = 2;
+
+// Standard (ABI1) audio microcode commands that we need to follow
+// to reconstruct the voices being mixed.
+const A_ADPCM: u32 = 0x01;
+const A_CLEARBUFF: u32 = 0x02;
+const A_LOADBUFF: u32 = 0x04;
+const A_SEGMENT: u32 = 0x07;
+const A_SETBUFF: u32 = 0x08;
+
+// Flags of the audio commands (bits 16-23 of the first word). They are not
+// opcodes: the same value has a different meaning for each command.
+const A_AUX: u32 = 0x08; // SETBUFF: set the auxiliary (wet) buffers
+
+#[derive(Default, Clone)]
+struct Voice {
+    source: u32,    // RDRAM address of the last ADPCM block decoded
+    gain: u16,      // Gain of the last ADPCM command
+    hits: usize,    // Number of ADPCM commands seen in the last audio task
+    last_task: u64, // Last audio task in which this voice was mixed
+    muted: bool,
+    solo: bool,
+}
+
+/// AudioVoices follows the audio command lists sent to the RSP, to keep
+/// track of the single voices being mixed, and allows to mute/solo them.
+///
+/// Voices are identified by the RDRAM address of their ADPCM decoder state,
+/// which is allocated per voice by the audio library and thus is stable
+/// across frames. Muting is performed by patching the ADPCM command
+/// into a CLEARBUFF of the same output buffer, before the RSP executes it.
+#[derive(Default)]
+pub(crate) struct AudioVoices {
+    enabled: bool,
+    voices: BTreeMap<u32, Voice>,
+    tasks: u64,
+}
+
+impl AudioVoices {
+    /// Inspect the task that is about to be started on the RSP. If it's an
+    /// audio task, go through its command list.
+    pub(crate) fn scan_task(&mut self, dmem: &[u8]) {
+        if !self.enabled || BigEndian::read_u32(&dmem[OSTASK_TYPE..]) != M_AUDTASK {
+            return;
+        }
+
+        let data_ptr = BigEndian::read_u32(&dmem[OSTASK_DATA_PTR..]) & 0x00FF_FFFF;
+        let data_size = BigEndian::read_u32(&dmem[OSTASK_DATA_SIZE..]);
+        self.tasks += 1;
+        for v in self.voices.values_mut() {
+            v.hits = 0;
+        }
+
+        let any_solo = self.voices.values().any(|v| v.solo);
+        let bus = &mut R4300::get_mut().bus;
+        let mut segments = [0u32; 16];
+        let mut last_load = 0u32;
+        let mut dmemout = 0u32;
+        let mut count = 0u32;
+
+        let resolve = |segments: &[u32; 16], addr: u32| {
+            (segments[(addr >> 24) as usize & 0xF] + (addr & 0x00FF_FFFF)) & 0x00FF_FFFF
+        };
+
+        for off in (0..data_size & !7).step_by(8) {
+            let w0 = bus.read::<u32>(data_ptr + off);
+            let w1 = bus.read::<u32>(data_ptr + off + 4);
+            match w0 >> 24 {
+                A_SEGMENT => segments[(w1 >> 24) as usize & 0xF] = w1 & 0x00FF_FFFF,
+                A_SETBUFF if (w0 >> 16) & A_AUX == 0 => {
+                    dmemout = w1 >> 16;
+                    count = w1 & 0xFFFF;
+                }
+                A_LOADBUFF => last_load = resolve(&segments, w1),
+                A_ADPCM => {
+                    let state = resolve(&segments, w1);
+                    let tasks = self.tasks;
+                    let voice = self.voices.entry(state).or_insert_with(Voice::default);
+                    voice.source = last_load;
+                    voice.gain = w0 as u16;
+                    voice.hits += 1;
+                    voice.last_task = tasks;
+
+                    if voice.muted || (any_solo && !voice.solo) {
+                        bus.write::<u32>(data_ptr + off, (A_CLEARBUFF << 24) | dmemout);
+                        bus.write::<u32>(data_ptr + off + 4, count);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[cfg(feature = "frontend")]
+    pub(crate) fn render_debug<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>) {
+        let ui = dr.ui();
+        let tasks = self.tasks;
+        Window::new(im_str!("[RSP] Audio voices"))
+            .size([360.0, 300.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.checkbox(im_str!("Track audio tasks"), &mut self.enabled);
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Clear")) {
+                    self.voices.clear();
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Unmute all")) {
+                    for v in self.voices.values_mut() {
+                        v.muted = false;
+                        v.solo = false;
+                    }
+                }
+                ui.separator();
+
+                ui.columns(5, im_str!("##voices"), true);
+                for title in &["State", "Source", "Gain", "Mute", "Solo"] {
+                    ui.text(title);
+                    ui.next_column();
+                }
+                ui.separator();
+                for (state, v) in self.voices.iter_mut() {
+                    // Dim voices which were not mixed in the last audio task
+                    let color = if v.last_task == tasks {
+                        [1.0, 1.0, 1.0, 1.0]
+                    } else {
+                        [0.5, 0.5, 0.5, 1.0]
+                    };
+                    ui.text_colored(color, im_str!("{}", state.hex()));
+                    ui.next_column();
+                    ui.text_colored(color, im_str!("{}", v.source.hex()));
+                    ui.next_column();
+                    ui.text_colored(color, im_str!("{} (x{})", v.gain, v.hits));
+                    ui.next_column();
+                    ui.checkbox(&im_str!("###voices#mute#{}", state), &mut v.muted);
+                    ui.next_column();
+                    ui.checkbox(&im_str!("###voices#solo#{}", state), &mut v.solo);
+                    ui.next_column();
+                }
+                ui.columns(1, im_str!(""), false);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ri::{RdramSize, Ri};
+    use super::*;
+
+    const CMDS: u32 = 0x1000;
+    const A_INIT: u32 = 0x01;
+
+    fn setup() -> AudioVoices {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        R4300::new(logger.new(o!())).register();
+        Ri::new(logger, RdramSize::Mb4).register();
+        R4300::get_mut()
+            .bus
+            .map_device(0x0000_0000, Ri::get(), 0)
+            .unwrap();
+        AudioVoices {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    // Build the DMEM of a task whose command list is at CMDS.
+    fn task(ty: u32, cmds: &[(u32, u32)]) -> Vec<u8> {
+        let bus = &mut R4300::get_mut().bus;
+        for (i, &(w0, w1)) in cmds.iter().enumerate() {
+            bus.write::<u32>(CMDS + i as u32 * 8, w0);
+            bus.write::<u32>(CMDS + i as u32 * 8 + 4, w1);
+        }
+        let mut dmem = vec![0u8; 0x1000];
+        BigEndian::write_u32(&mut dmem[OSTASK_TYPE..], ty);
+        BigEndian::write_u32(&mut dmem[OSTASK_DATA_PTR..], 0x8000_0000 | CMDS);
+        BigEndian::write_u32(&mut dmem[OSTASK_DATA_SIZE..], cmds.len() as u32 * 8);
+        dmem
+    }
+
+    fn read_cmd(idx: u32) -> (u32, u32) {
+        let bus = &mut R4300::get_mut().bus;
+        (
+            bus.read::<u32>(CMDS + idx * 8),
+            bus.read::<u32>(CMDS + idx * 8 + 4),
+        )
+    }
+
+    // Two voices mixed into the same output buffer, with segmented addresses.
+    // The aux SETBUFF must not change the buffer used to silence voices.
+    fn mix_two_voices() -> Vec<u8> {
+        task(
+            M_AUDTASK,
+            &[
+                (A_SEGMENT << 24, 3 << 24 | 0x20_0000),
+                (A_SETBUFF << 24, 0x0440 << 16 | 0x0170),
+                (
+                    A_SETBUFF << 24 | A_AUX << 16 | 0x0100,
+                    0x0200 << 16 | 0x0300,
+                ),
+                (A_LOADBUFF << 24, 0x0300_1000),
+                (A_ADPCM << 24 | A_INIT << 16 | 0x7FFF, 0x0300_0100),
+                (A_LOADBUFF << 24, 0x0300_2000),
+                (A_ADPCM << 24 | 0x4000, 0x0300_0200),
+            ],
+        )
+    }
+
+    #[test]
+    fn decode_voices() {
+        let mut av = setup();
+        av.scan_task(&mix_two_voices());
+        assert_eq!(av.tasks, 1);
+        assert_eq!(av.voices.len(), 2);
+
+        let v = &av.voices[&0x20_0100];
+        assert_eq!(
+            (v.source, v.gain, v.hits, v.last_task),
+            (0x20_1000, 0x7FFF, 1, 1)
+        );
+        let v = &av.voices[&0x20_0200];
+        assert_eq!(
+            (v.source, v.gain, v.hits, v.last_task),
+            (0x20_2000, 0x4000, 1, 1)
+        );
+
+        // Nothing is patched unless a voice is muted.
+        assert_eq!(read_cmd(6), (A_ADPCM << 24 | 0x4000, 0x0300_0200));
+    }
+
+    #[test]
+    fn ignore_other_tasks() {
+        let mut av = setup();
+        let mut dmem = mix_two_voices();
+        BigEndian::write_u32(&mut dmem[OSTASK_TYPE..], 1);
+        av.scan_task(&dmem);
+        assert_eq!((av.tasks, av.voices.len()), (0, 0));
+
+        av.enabled = false;
+        av.scan_task(&mix_two_voices());
+        assert_eq!((av.tasks, av.voices.len()), (0, 0));
+    }
+
+    #[test]
+    fn mute_and_solo() {
+        let mut av = setup();
+        av.scan_task(&mix_two_voices());
+
+        av.voices.get_mut(&0x20_0200).unwrap().muted = true;
+        av.scan_task(&mix_two_voices());
+        assert_eq!(
+            read_cmd(4),
+            (A_ADPCM << 24 | A_INIT << 16 | 0x7FFF, 0x0300_0100)
+        );
+        assert_eq!(read_cmd(6), (A_CLEARBUFF << 24 | 0x0440, 0x0170));
+
+        av.voices.get_mut(&0x20_0200).unwrap().muted = false;
+        av.voices.get_mut(&0x20_0200).unwrap().solo = true;
+        av.scan_task(&mix_two_voices());
+        assert_eq!(read_cmd(4), (A_CLEARBUFF << 24 | 0x0440, 0x0170));
+        assert_eq!(read_cmd(6), (A_ADPCM << 24 | 0x4000, 0x0300_0200));
+        assert_eq!(av.voices[&0x20_0100].last_task, 3);
+    }
+}
@@ -1,13 +1,266 @@
 use emu::bus::be::{Device, Reg32};
+#[cfg(feature = "frontend")]
+use emu::dbg::imgui::*;
+#[cfg(feature = "frontend")]
+use emu::dbg::{imgui_input_hex, DebuggerRenderer, ImageView};
 use emu::gfx::*;
 use emu::int::Numerics;
 use emu_derive::DeviceBE;
+use lazy_static::lazy_static;
 
+use super::dp::Dp;
 use super::mi::{IrqMask, Mi};
 use super::r4300::R4300;
 
 use slog;
 
+/// Video standard of the console, which sets the frequency of the VI clock
+/// and the default video timing. Values match the TV type that the boot
+/// code passes to the game.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TvType {
+    Pal = 0,
+    Ntsc = 1,
+    Mpal = 2,
+}
+
+impl TvType {
+    /// Select the TV type from the country code in the ROM header.
+    pub fn from_country(code: u8) -> TvType {
+        match code {
+            b'D' | b'F' | b'I' | b'P' | b'S' | b'U' | b'X' | b'Y' => TvType::Pal,
+            b'B' => TvType::Mpal,
+            _ => TvType::Ntsc,
+        }
+    }
+
+    /// Nominal number of fields per second, at which the emulation must be
+    /// paced.
+    pub fn refresh_rate(self) -> isize {
+        match self {
+            TvType::Pal => 50,
+            TvType::Ntsc | TvType::Mpal => 60,
+        }
+    }
+
+    /// Frequency of the VI clock, in Hz.
+    pub fn vclk(self) -> i64 {
+        match self {
+            TvType::Pal => 49_656_530,
+            TvType::Ntsc => super::n64::VCLK,
+            TvType::Mpal => 48_628_322,
+        }
+    }
+
+    // H_SYNC and V_SYNC of the standard interlaced modes.
+    fn default_sync(self) -> (u32, u32) {
+        match self {
+            TvType::Pal => (0xC69, 0x270),
+            TvType::Ntsc => (0xC15, 0x20C),
+            TvType::Mpal => (0xC20, 0x20C),
+        }
+    }
+
+    /// Video timing used until the game programs the VI (see
+    /// `Vi::video_timing`).
+    pub fn default_video_timing(self) -> (i64, usize) {
+        let (hsync, vsync) = self.default_sync();
+        (hsync as i64 + 1, vsync as usize + 1)
+    }
+}
+
+// Shortest line (in VI clock cycles) and field (in half-lines) that are
+// considered a valid video timing; anything shorter means that the VI was
+// not programmed yet.
+const MIN_H_SYNC: u32 = 0x400;
+const MIN_V_SYNC: u32 = 0x100;
+
+// Bits of the status register enabling the filters applied during scanout.
+const STATUS_GAMMA_DITHER: u32 = 1 << 2;
+const STATUS_GAMMA: u32 = 1 << 3;
+const STATUS_DIVOT: u32 = 1 << 4;
+const STATUS_DITHER_FILTER: u32 = 1 << 16;
+
+// A pixel being scanned out: red, green, blue, and coverage (0-7, where 7
+// means fully covered).
+type Pixel = [u8; 4];
+
+const FULL_COVERAGE: u8 = 7;
+
+// Dither filter (16-bit framebuffers only): the RDP dithers colors before
+// truncating them to 5 bits, so the VI restores part of the lost precision
+// by nudging each component towards its 8 neighbors (by one 8-bit step for
+// each neighbor which is brighter or darker). Only applied to fully covered
+// pixels.
+fn dither_filter(pixels: &mut [Pixel], width: usize, height: usize) {
+    let src = pixels.to_vec();
+    for y in 0..height {
+        for x in 0..width {
+            let px = &mut pixels[y * width + x];
+            if px[3] != FULL_COVERAGE {
+                continue;
+            }
+            let mut delta = [0i32; 3];
+            for ny in y.max(1) - 1..(y + 2).min(height) {
+                for nx in x.max(1) - 1..(x + 2).min(width) {
+                    let n = &src[ny * width + nx];
+                    for (d, (nc, pc)) in delta.iter_mut().zip(n.iter().zip(px.iter())) {
+                        // Compare the original 5-bit values.
+                        *d += ((nc >> 3) as i32 - (pc >> 3) as i32).signum();
+                    }
+                }
+            }
+            for (pc, d) in px.iter_mut().zip(delta.iter()) {
+                *pc = (*pc as i32 + d).max(0).min(255) as u8;
+            }
+        }
+    }
+}
+
+fn median3(a: u8, b: u8, c: u8) -> u8 {
+    a.max(b).min(a.min(b).max(c))
+}
+
+// Divot filter: antialiased edges leave single-pixel notches ("divots")
+// where the coverage is partial, which are removed by replacing each
+// component with the median of the pixel and its horizontal neighbors.
+fn divot_filter(pixels: &mut [Pixel], width: usize, height: usize) {
+    for y in 0..height {
+        let line = &mut pixels[y * width..(y + 1) * width];
+        let src = line.to_vec();
+        for x in 1..width.max(1) - 1 {
+            let (l, p, r) = (src[x - 1], src[x], src[x + 1]);
+            if l[3] == FULL_COVERAGE && p[3] == FULL_COVERAGE && r[3] == FULL_COVERAGE {
+                continue;
+            }
+            line[x] = [
+                median3(l[0], p[0], r[0]),
+                median3(l[1], p[1], r[1]),
+                median3(l[2], p[2], r[2]),
+                p[3],
+            ];
+        }
+    }
+}
+
+lazy_static! {
+    // Gamma correction: square root of a component extended to 14 bits (with
+    // the gamma dither noise in the low 6 bits), times 2.
+    static ref GAMMA_TABLE: Vec<u8> = (0..1u32 << 14)
+        .map(|v| ((v as f64).sqrt() as u8) << 1)
+        .collect();
+}
+
+// Gamma correction (square root, to compensate the TV gamma), and gamma
+// dither, which adds random noise to the bits below the output precision
+// to hide banding. The noise is seeded by the caller, so that emulation
+// stays deterministic.
+fn gamma_filter(pixels: &mut [Pixel], gamma: bool, dither: bool, seed: u32) {
+    let mut rng = seed | 1;
+    let mut rand = || {
+        // xorshift32
+        rng ^= rng << 13;
+        rng ^= rng >> 17;
+        rng ^= rng << 5;
+        rng
+    };
+    for px in pixels.iter_mut() {
+        for pc in px[..3].iter_mut() {
+            let noise = match (gamma, dither) {
+                (true, true) => rand() & 0x3F,
+                (false, true) => rand() & 1,
+                _ => 0,
+            };
+            *pc = if gamma {
+                GAMMA_TABLE[((*pc as u32) << 6 | noise) as usize]
+            } else {
+                (*pc as u32 + noise).min(255) as u8
+            };
+        }
+    }
+}
+
+/// Pixel formats of framebuffers in RDRAM, as supported by the debugger
+/// framebuffer viewer.
+#[cfg(feature = "frontend")]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum FbFormat {
+    Rgba16,
+    Rgba32,
+    Ia8,
+    I8,
+}
+
+#[cfg(feature = "frontend")]
+impl FbFormat {
+    pub(crate) fn bpp(self) -> usize {
+        match self {
+            FbFormat::Rgba16 => 16,
+            FbFormat::Rgba32 => 32,
+            FbFormat::Ia8 | FbFormat::I8 => 8,
+        }
+    }
+}
+
+/// Read an image from RDRAM, converting it to RGBA8888 for display in the
+/// debugger. Pixels outside RDRAM are left black.
+#[cfg(feature = "frontend")]
+pub(crate) fn rdram_image(
+    addr: u32,
+    width: usize,
+    height: usize,
+    format: FbFormat,
+) -> OwnedGfxBufferLE<Rgba8888> {
+    let mut img = OwnedGfxBufferLE::<Rgba8888>::new(width, height);
+    let memio = R4300::get().bus.fetch_read::<u8>(addr);
+    let src = match memio.mem() {
+        Some(src) => src,
+        None => return img,
+    };
+    let bpp = format.bpp();
+    {
+        let mut buf = img.buf_mut();
+        for y in 0..height {
+            let mut line = buf.line(y);
+            for x in 0..width {
+                let off = (y * width + x) * bpp / 8;
+                if off + bpp / 8 > src.len() {
+                    break;
+                }
+                let c = match format {
+                    FbFormat::Rgba32 => {
+                        Color::new_clamped(src[off], src[off + 1], src[off + 2], 0xFF)
+                    }
+                    FbFormat::Rgba16 => {
+                        let v = (src[off] as u16) << 8 | src[off + 1] as u16;
+                        let c5 = |v: u16| ((v & 0x1F) << 3 | (v & 0x1F) >> 2) as u8;
+                        Color::new_clamped(c5(v >> 11), c5(v >> 6), c5(v >> 1), 0xFF)
+                    }
+                    FbFormat::Ia8 => {
+                        let i = (src[off] >> 4) * 17;
+                        let a = (src[off] & 0xF) * 17;
+                        Color::new_clamped(i, i, i, a)
+                    }
+                    FbFormat::I8 => Color::new_clamped(src[off], src[off], src[off], 0xFF),
+                };
+                line.set(x, c);
+            }
+        }
+    }
+    img
+}
+
+// Settings of the debugger framebuffer viewer
+#[cfg(feature = "frontend")]
+struct FbViewer {
+    addr: u32,
+    width: i32,
+    height: i32,
+    format: FbFormat,
+    follow_vi: bool, // track the framebuffer being scanned out
+    view: Option<ImageView>,
+}
+
 #[derive(DeviceBE)]
 pub struct Vi {
     // [1:0] type[1:0] (pixel size)
@@ -29,7 +282,8 @@ pub struct Vi {
     //     3: neither (replicate pixels, no interpolate)
     // [11] reserved - diagnostics only
     // [15:12] reserved
-    #[reg(offset = 0x00, rwmask = 0xFFFF)]
+    // [16] dither_filter_enable (normally on for 16-bit framebuffers)
+    #[reg(offset = 0x00, rwmask = 0x1FFFF)]
     status: Reg32,
 
     // [23:0] frame buffer origin in bytes
@@ -100,10 +354,17 @@ pub struct Vi {
 
     logger: slog::Logger,
     framecount: usize,
+    tv_type: TvType,
+    field: bool,           // odd field of an interlaced frame
+    upscaled: Option<u32>, // texture of the last frame, if drawn upscaled
+    filters: bool,         // apply the gamma, dither and divot filters
+    rng_seed: u32,         // seed of the gamma dither noise
+    #[cfg(feature = "frontend")]
+    fbviewer: FbViewer,
 }
 
 impl Vi {
-    pub fn new(logger: slog::Logger) -> Box<Vi> {
+    pub fn new(logger: slog::Logger, tv_type: TvType) -> Box<Vi> {
         Box::new(Vi {
             status: Reg32::default(),
             origin: Reg32::default(),
@@ -121,17 +382,66 @@ impl Vi {
             y_scale: Reg32::default(),
             logger,
             framecount: 0,
+            tv_type,
+            field: false,
+            upscaled: None,
+            filters: true,
+            rng_seed: 0,
+            #[cfg(feature = "frontend")]
+            fbviewer: FbViewer {
+                addr: 0,
+                width: 320,
+                height: 240,
+                format: FbFormat::Rgba16,
+                follow_vi: true,
+                view: None,
+            },
         })
     }
 
-    pub fn set_line(&mut self, y: usize) {
-        // FIXME: handle interleaved mode (LSB is fixed within the same field)
-        // FIXME: NTSC has 525 lines, what happens to this 9-bit register when line > 512?
-        self.current_line.set(y as u32);
+    pub fn tv_type(&self) -> TvType {
+        self.tv_type
+    }
+
+    fn interlaced(&self) -> bool {
+        self.status.get() & (1 << 6) != 0
+    }
+
+    /// Return the video timing programmed in the VI: the duration of a line
+    /// in VI clock cycles, and the number of half-lines in a field. Until
+    /// the VI is programmed, the default timing of the TV type is used.
+    /// The PAL leap pattern is ignored, so PAL lines have their average
+    /// duration.
+    pub fn video_timing(&self) -> (i64, usize) {
+        let hsync = self.horizontal_sync.get() & 0xFFF;
+        let vsync = self.vertical_sync.get() & 0x3FF;
+        if hsync < MIN_H_SYNC || vsync < MIN_V_SYNC {
+            return self.tv_type.default_video_timing();
+        }
+        (hsync as i64 + 1, vsync as usize + 1)
+    }
 
-        if y as u32 == self.vertical_interrupt.get() {
+    /// Set the half-line of the current field being displayed. Returns true
+    /// if the VI interrupt was raised.
+    pub fn set_halfline(&mut self, y: usize) -> bool {
+        // V_CURRENT is sampled once per line, so it always holds an even
+        // half-line, with the lsb giving the field in interlaced modes.
+        let line = y as u32 & 0x3FE;
+        let field = (self.interlaced() && self.field) as u32;
+        self.current_line.set(line | field);
+
+        if self.is_intr_halfline(y) {
             Mi::get_mut().set_irq_line(IrqMask::VI, true);
+            return true;
         }
+        false
+    }
+
+    /// Return true if the VI interrupt is raised at the specified half-line:
+    /// this happens once per field when the line reaches V_INTR, whatever
+    /// the field.
+    pub fn is_intr_halfline(&self, y: usize) -> bool {
+        y & 1 == 0 && y as u32 & 0x3FE == self.vertical_interrupt.get() & 0x3FE
     }
 
     fn cb_write_current_line(&mut self, _old: u32, _new: u32) {
@@ -149,7 +459,16 @@ impl Vi {
     pub fn end_frame(&mut self, screen: &mut GfxBufferMutLE<Rgb888>) {
         self.framecount += 1;
 
+        // Each emulated frame is a field: interlaced modes alternate between
+        // even and odd fields.
+        self.field = self.interlaced() && !self.field;
+
+        // Make sure the framebuffer contains everything the RDP was asked
+        // to draw so far.
+        Dp::get_mut().flush_rendering();
+
         let bpp = self.status.get() & 3;
+        self.upscaled = None;
 
         // display disable -> clear screen
         if bpp == 0 || bpp == 1 {
@@ -164,60 +483,263 @@ impl Vi {
         }
 
         info!(self.logger, "draw frame"; o!("origin" => self.origin.get().hex()));
+        let width = self.width.get() as usize;
+        self.upscaled = Dp::get_mut().upscaled_image(self.origin.get(), width, width * 3 / 4);
+        let (w, h) = match width {
+            640 => (640, 480),
+            320 => (320, 240),
+            _ => {
+                error!(self.logger, "unsupported screen width"; o!("width" => width));
+                return;
+            }
+        };
+        let mut pixels = self.scanout(w, h, bpp);
+
+        // Pixels are replicated to fill the screen buffer (640x480).
+        let zoom = 640 / w;
+        for y in 0..480 {
+            let mut dst = screen.line(y);
+            let src = &pixels[(y / zoom) * w..(y / zoom + 1) * w];
+            for x in 0..640 {
+                let px = src[x / zoom];
+                dst.set(x, Color::new_clamped(px[0], px[1], px[2], 0xFF));
+            }
+        }
+    }
+
+    // Fetch the framebuffer being scanned out (16-bit or 32-bit pixels), and
+    // apply the filters enabled in the status register.
+    fn scanout(&self, width: usize, height: usize, bpp: u32) -> Vec<Pixel> {
         let memio = R4300::get().bus.fetch_read::<u8>(self.origin.get());
         let src = memio.mem().unwrap();
+        let size = if bpp == 3 { 4 } else { 2 };
+        let mut pixels = vec![[0, 0, 0, FULL_COVERAGE]; width * height];
+        for (px, src) in pixels.iter_mut().zip(src.chunks_exact(size)) {
+            *px = if size == 4 {
+                // The top 3 bits of alpha hold the coverage.
+                [src[0], src[1], src[2], src[3] >> 5]
+            } else {
+                // Only the top bit of the coverage is stored in the pixel
+                // (the other two are in the hidden RDRAM bits, which are
+                // not emulated, and are assumed to be set).
+                let v = (src[0] as u16) << 8 | src[1] as u16;
+                let c5 = |v: u16| ((v & 0x1F) << 3 | (v & 0x1F) >> 2) as u8;
+                [c5(v >> 11), c5(v >> 6), c5(v >> 1), (v & 1) as u8 * 4 + 3]
+            };
+        }
+        if !self.filters {
+            return pixels;
+        }
 
-        match self.width.get() {
-            640 => {
-                let src = GfxBufferLE::<Rgb888>::new(src, 640, 480, 640 * 4).unwrap();
-                for y in 0..480 {
-                    let mut dst = screen.line(y);
-                    let src = src.line(y);
-                    for x in 0..640 {
-                        dst.set(x, src.get(x));
-                    }
-                }
-            }
+        let status = self.status.get();
+        if size == 2 && status & STATUS_DITHER_FILTER != 0 {
+            dither_filter(&mut pixels, width, height);
+        }
+        if status & STATUS_DIVOT != 0 {
+            divot_filter(&mut pixels, width, height);
+        }
+        let gamma = status & STATUS_GAMMA != 0;
+        let dither = status & STATUS_GAMMA_DITHER != 0;
+        if gamma || dither {
+            let seed = (self.framecount as u32).wrapping_mul(0x9E37_79B9) ^ self.rng_seed;
+            gamma_filter(&mut pixels, gamma, dither, seed);
+        }
+        pixels
+    }
 
-            320 => {
-                match bpp {
-                    // 32-bit
-                    3 => {
-                        let src = GfxBufferLE::<Rgb888>::new(src, 320, 240, 320 * 4).unwrap();
-                        for y in 0..240 {
-                            let (mut dst1, mut dst2) = screen.lines(y * 2, y * 2 + 1);
-                            let src = src.line(y);
-                            for x in 0..320 {
-                                let px = src.get(x);
-                                dst1.set(x * 2, px);
-                                dst1.set(x * 2 + 1, px);
-                                dst2.set(x * 2, px);
-                                dst2.set(x * 2 + 1, px);
-                            }
-                        }
-                    }
-                    // 16-bit
-                    2 => {
-                        let src = GfxBufferBE::<Xbgr1555>::new(src, 320, 240, 320 * 2).unwrap();
-                        for y in 0..240 {
-                            let (mut dst1, mut dst2) = screen.lines(y * 2, y * 2 + 1);
-                            let src = src.line(y);
-                            for x in 0..320 {
-                                let px = src.get(x).cconv();
-                                dst1.set(x * 2, px);
-                                dst1.set(x * 2 + 1, px);
-                                dst2.set(x * 2, px);
-                                dst2.set(x * 2 + 1, px);
-                            }
-                        }
+    /// Enable or disable the emulation of the filters applied by the VI to
+    /// the framebuffer during scanout (gamma correction, gamma dither, dither
+    /// and divot filters), when enabled by the game. They are enabled by
+    /// default; some games look noisy or washed out without them, while
+    /// others look better without. They're not applied to frames displayed
+    /// from upscaled OpenGL textures.
+    pub fn set_filters(&mut self, enabled: bool) {
+        self.filters = enabled;
+    }
+
+    /// Seed the noise added by the gamma dither filter. For a given seed, the
+    /// noise only depends on the frame number.
+    pub fn set_rng_seed(&mut self, seed: u32) {
+        self.rng_seed = seed;
+    }
+
+    /// OpenGL texture holding the last frame scanned out, if it was drawn by
+    /// the RDP at a higher resolution (and still matches RDRAM).
+    pub fn upscaled_frame(&self) -> Option<u32> {
+        self.upscaled
+    }
+
+    #[cfg(feature = "frontend")]
+    pub fn render_debug<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>) {
+        let ui = dr.ui();
+        let fbv = &mut self.fbviewer;
+        if fbv.follow_vi {
+            fbv.addr = self.origin.get();
+            fbv.width = self.width.get() as i32;
+            fbv.height = fbv.width * 3 / 4;
+            fbv.format = match self.status.get() & 3 {
+                3 => FbFormat::Rgba32,
+                _ => FbFormat::Rgba16,
+            };
+        }
+        let mut view = fbv.view.take().unwrap_or_else(ImageView::new);
+
+        Window::new(im_str!("[VI] Framebuffer viewer"))
+            .size([400.0, 420.0], Condition::FirstUseEver)
+            .build(ui, || {
+                ui.checkbox(im_str!("Follow VI scanout"), &mut fbv.follow_vi);
+                if imgui_input_hex(ui, im_str!("Address"), &mut fbv.addr, true) {
+                    fbv.follow_vi = false;
+                }
+                ui.set_next_item_width(100.0);
+                if ui.input_int(im_str!("Width"), &mut fbv.width).build() {
+                    fbv.follow_vi = false;
+                }
+                ui.set_next_item_width(100.0);
+                if ui.input_int(im_str!("Height"), &mut fbv.height).build() {
+                    fbv.follow_vi = false;
+                }
+                let format = fbv.format;
+                for (i, &(name, f)) in [
+                    ("RGBA16", FbFormat::Rgba16),
+                    ("RGBA32", FbFormat::Rgba32),
+                    ("IA8", FbFormat::Ia8),
+                    ("I8", FbFormat::I8),
+                ]
+                .iter()
+                .enumerate()
+                {
+                    if i != 0 {
+                        ui.same_line(0.0);
                     }
-                    _ => unimplemented!(),
+                    ui.radio_button(&im_str!("{}", name), &mut fbv.format, f);
+                }
+                if fbv.format != format {
+                    fbv.follow_vi = false;
                 }
-            }
 
-            _ => {
-                error!(self.logger, "unsupported screen width"; o!("width" => self.width.get()));
+                fbv.width = fbv.width.max(1).min(2048);
+                fbv.height = fbv.height.max(1).min(2048);
+                let (w, h) = (fbv.width as usize, fbv.height as usize);
+                ui.text(format!(
+                    "{}..{}",
+                    (fbv.addr & 0xFF_FFFF).hex(),
+                    ((fbv.addr & 0xFF_FFFF) + (w * h * fbv.format.bpp() / 8) as u32).hex()
+                ));
+                view.update(&rdram_image(fbv.addr & 0xFF_FFFF, w, h, fbv.format).buf());
+                let avail = ui.content_region_avail();
+                let scale = (avail[0] / w as f32).min(avail[1] / h as f32);
+                dr.render_image(&view, [w as f32 * scale, h as f32 * scale]);
+            });
+        fbv.view = Some(view);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(v: u8, cvg: u8) -> Pixel {
+        [v, v, v, cvg]
+    }
+
+    #[test]
+    fn gamma() {
+        let mut pixels = vec![gray(0, 7), gray(16, 7), gray(64, 3), gray(255, 7)];
+        gamma_filter(&mut pixels, true, false, 0);
+        assert_eq!(
+            pixels,
+            vec![gray(0, 7), gray(64, 7), gray(128, 3), gray(254, 7)]
+        );
+
+        // The table matches the square root of the 14-bit value.
+        for v in 0..1u32 << 14 {
+            let root = GAMMA_TABLE[v as usize] as u32 / 2;
+            assert!(root * root <= v && (root + 1) * (root + 1) > v);
+        }
+    }
+
+    #[test]
+    fn gamma_dither() {
+        let src: Vec<Pixel> = (0..=255).map(|v| gray(v, 7)).collect();
+
+        // Dither alone adds 0 or 1 (saturating).
+        let mut pixels = src.clone();
+        gamma_filter(&mut pixels, false, true, 1234);
+        for (px, s) in pixels.iter().zip(src.iter()) {
+            for i in 0..3 {
+                assert!(px[i] == s[i] || px[i] == s[i] + 1);
             }
+            assert_eq!(px[3], 7);
         }
+        assert_eq!(pixels[255], gray(255, 7));
+        assert!(pixels.iter().zip(src.iter()).any(|(p, s)| p != s));
+
+        // With gamma, the noise fills the 6 bits below the component.
+        let mut pixels = src.clone();
+        gamma_filter(&mut pixels, true, true, 1234);
+        for (px, s) in pixels.iter().zip(src.iter()) {
+            let lo = GAMMA_TABLE[(s[0] as usize) << 6];
+            let hi = GAMMA_TABLE[(s[0] as usize) << 6 | 0x3F];
+            assert!(px[..3].iter().all(|&c| c >= lo && c <= hi));
+        }
+
+        // The noise only depends on the seed.
+        let mut again = src.clone();
+        gamma_filter(&mut again, true, true, 1234);
+        assert_eq!(again, pixels);
+        let mut again = src;
+        gamma_filter(&mut again, true, true, 5678);
+        assert_ne!(again, pixels);
+    }
+
+    #[test]
+    fn dither() {
+        // The center pixel is darker than all its neighbors (by one 5-bit
+        // step); the top one is not fully covered.
+        let mut pixels = vec![gray(0x88, 7); 9];
+        pixels[4] = gray(0x80, 7);
+        pixels[1] = gray(0x88, 3);
+        dither_filter(&mut pixels, 3, 3);
+
+        assert_eq!(pixels[4], gray(0x88, 7));
+        assert_eq!(pixels[1], gray(0x88, 3));
+        for &i in &[0, 2, 3, 5, 6, 7, 8] {
+            assert_eq!(pixels[i], gray(0x87, 7), "pixel {}", i);
+        }
+
+        // Differences within the same 5-bit value are ignored.
+        let mut pixels = vec![gray(0x80, 7), gray(0x87, 7)];
+        dither_filter(&mut pixels, 2, 1);
+        assert_eq!(pixels, vec![gray(0x80, 7), gray(0x87, 7)]);
+    }
+
+    #[test]
+    fn divot() {
+        // Only pixels next to a partially covered one are filtered, with
+        // the median of the original values.
+        let mut pixels = vec![
+            gray(10, 7),
+            gray(200, 3),
+            gray(20, 7),
+            gray(30, 7),
+            gray(40, 7),
+        ];
+        divot_filter(&mut pixels, 5, 1);
+        assert_eq!(
+            pixels,
+            vec![
+                gray(10, 7),
+                gray(20, 3),
+                gray(30, 7),
+                gray(30, 7),
+                gray(40, 7),
+            ]
+        );
+
+        // Lines are filtered independently.
+        let mut pixels = vec![gray(10, 3), gray(200, 3), gray(20, 3)];
+        divot_filter(&mut pixels, 1, 3);
+        assert_eq!(pixels, vec![gray(10, 3), gray(200, 3), gray(20, 3)]);
     }
 }
@@ -0,0 +1,182 @@
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate r64emu;
+
+use emu::gfx::{OwnedGfxBufferLE, Rgb888};
+use emu::hw::OutputProducer;
+use emu::snd::{OwnedSndBuffer, S16_STEREO};
+use r64emu::pi::Pi;
+use r64emu::r4300::R4300;
+use r64emu::ri::RdramSize;
+use r64emu::{BootMode, N64};
+use slog::Discard;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+static ROM_PATH: &'static str = "roms/tests";
+
+const NUM_FRAMES: usize = 30;
+const RNG_SEED: u32 = 0x1234_5678;
+
+// Address of PIF RAM on the CPU bus.
+const PIF_RAM: u32 = 0x1FC0_07C0;
+
+#[derive(Clone)]
+enum Movie {
+    None,
+    Record(PathBuf),
+    Play(PathBuf),
+}
+
+// Output of a run for each frame: the framebuffer, the audio, the state of
+// the first controller as read through the joybus, and the state hash.
+struct Frame {
+    pixels: Vec<u8>,
+    samples: Vec<i16>,
+    input: u32,
+    hash: u64,
+}
+
+type RunOutput = Vec<Frame>;
+
+// Input of the first controller scripted at each frame (except during
+// playback, where it comes from the movie). It changes every frame, so
+// that a movie with missing or misplaced samples doesn't play back the same.
+fn scripted_input(frame: usize) -> u32 {
+    (frame as u32 + 1).wrapping_mul(0x9E37_79B9)
+}
+
+// Ask the PIF to read the first controller at the next joybus run, as games
+// do: the test ROMs don't necessarily poll the controllers themselves.
+fn request_input_poll() {
+    let bus = &mut R4300::get_mut().bus;
+    bus.write::<u32>(PIF_RAM, 0xFF01_0401); // channel 0: read buttons
+    bus.write::<u32>(PIF_RAM + 4, 0);
+    bus.write::<u32>(PIF_RAM + 8, 0xFE00_0000); // end of commands
+    bus.write::<u32>(PIF_RAM + 0x3C, 0x0000_0001); // start joybus
+}
+
+// Controller state returned by the last joybus run.
+fn polled_input() -> u32 {
+    R4300::get().bus.fetch_read_nolog::<u32>(PIF_RAM + 4).read()
+}
+
+// Run the emulator on a ROM in deterministic mode, collecting all the
+// produced frames. Each run happens in its own thread, as the emulator
+// state is thread-local.
+fn run(romfn: &str, movie: Movie) -> RunOutput {
+    let romfn = romfn.to_owned();
+    thread::spawn(move || {
+        let logger = slog::Logger::root(Discard, o!());
+        let mut n64 = N64::new(logger, Path::new(&romfn), None, RdramSize::Mb4).unwrap();
+        n64.setup_boot(BootMode::Hle).unwrap();
+        n64.set_deterministic(true).unwrap();
+        n64.set_rng_seed(RNG_SEED);
+        let scripted = match movie {
+            Movie::None => true,
+            Movie::Record(ref path) => {
+                n64.start_recording(path, false).unwrap();
+                true
+            }
+            Movie::Play(ref path) => {
+                n64.start_playback(path).unwrap();
+                false
+            }
+        };
+
+        let mut output = Vec::new();
+        for i in 0..NUM_FRAMES {
+            if scripted {
+                Pi::get_mut().set_script_input(0, Some(scripted_input(i)));
+            }
+            let mut screen = OwnedGfxBufferLE::<Rgb888>::new(640, 480);
+            let mut sound = OwnedSndBuffer::<S16_STEREO>::with_capacity(1024);
+            n64.render_frame(&mut screen.buf_mut(), &mut sound.buf_mut());
+
+            let pixels = screen.buf_mut().raw().0.to_vec();
+            let sound = sound.buf();
+            let samples = (0..sound.count())
+                .flat_map(|s| vec![sound.get_sample(s, 0), sound.get_sample(s, 1)])
+                .collect();
+            output.push(Frame {
+                pixels,
+                samples,
+                input: polled_input(),
+                hash: n64.state_hash(),
+            });
+            request_input_poll();
+        }
+        output
+    })
+    .join()
+    .unwrap()
+}
+
+fn assert_same(run1: &RunOutput, run2: &RunOutput) {
+    assert_eq!(run1.len(), run2.len());
+    for (i, (f1, f2)) in run1.iter().zip(run2.iter()).enumerate() {
+        assert!(f1.pixels == f2.pixels, "framebuffer differs at frame {}", i);
+        assert!(f1.samples == f2.samples, "audio differs at frame {}", i);
+        assert_eq!(f1.input, f2.input, "input differs at frame {}", i);
+        assert_eq!(f1.hash, f2.hash, "state hash differs at frame {}", i);
+    }
+}
+
+// The input polled during each frame (but the first, which has no pending
+// poll) must be the one scripted for that frame.
+fn assert_scripted(run: &RunOutput) {
+    for (i, f) in run.iter().enumerate().skip(1) {
+        assert_eq!(f.input, scripted_input(i), "wrong input at frame {}", i);
+    }
+}
+
+fn test_determinism(romfn: &str) {
+    let run1 = run(romfn, Movie::None);
+    let run2 = run(romfn, Movie::None);
+    assert_scripted(&run1);
+    assert_same(&run1, &run2);
+}
+
+// Record a movie with scripted input and play it back without it: the
+// playback must read the same input from the movie, and be identical to the
+// recording.
+fn test_movie(romfn: &str, name: &str) {
+    let path = env::temp_dir().join(format!("r64emu-{}.m64", name));
+    let run1 = run(romfn, Movie::Record(path.clone()));
+    let run2 = run(romfn, Movie::Play(path.clone()));
+    let _ = std::fs::remove_file(&path);
+    assert_scripted(&run1);
+    assert_same(&run1, &run2);
+}
+
+macro_rules! determinism {
+    ($test_name:ident, $romfn:expr) => {
+        #[test]
+        fn $test_name() {
+            test_determinism(&format!("{}/{}", ROM_PATH, $romfn));
+        }
+    };
+}
+
+macro_rules! movie {
+    ($test_name:ident, $romfn:expr) => {
+        #[test]
+        fn $test_name() {
+            test_movie(&format!("{}/{}", ROM_PATH, $romfn), stringify!($test_name));
+        }
+    };
+}
+
+determinism!(det_cpu_div, "CPUTest/CPU/DIV/CPUDIV.N64");
+determinism!(det_fpu_sqrt, "CPUTest/CP1/SQRT/CP1SQRT.N64");
+determinism!(det_rsp_gradient, "RSP/Gradient/RSPGradient.N64");
+determinism!(
+    det_rsp_fastdct,
+    "RSP/DCT/FastDCTBlockDecode/RSPFastDCTBlockDecode.N64"
+);
+
+movie!(movie_cpu_div, "CPUTest/CPU/DIV/CPUDIV.N64");
+movie!(movie_rsp_gradient, "RSP/Gradient/RSPGradient.N64");
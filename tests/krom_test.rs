@@ -14,6 +14,7 @@ use failure::Error;
 use image::png::PNGEncoder;
 use image::{ColorType, Pixel, RgbaImage};
 use r64emu::r4300::R4300;
+use r64emu::ri::RdramSize;
 use r64emu::N64;
 use slog::Discard;
 use std::env;
@@ -38,7 +39,12 @@ fn test_krom(romfn: &str, flags: u32) -> Result<(), Error> {
     };
 
     // Create N64 object and emulate 5 frames
-    let mut n64 = N64::new(logger, Path::new(romfn), Path::new("bios/pifdata.bin")).unwrap();
+    let mut n64 = N64::new(
+        logger,
+        Path::new(romfn),
+        Some(Path::new("bios/pifdata.bin")),
+        RdramSize::Mb4,
+    ).unwrap();
     n64.setup_cic(true).unwrap();
     let mut screen1 = OwnedGfxBufferLE::<Rgb888>::new(640, 480);
     let mut sound1 = OwnedSndBuffer::<S16_STEREO>::with_capacity(512);
@@ -0,0 +1,167 @@
+#[macro_use]
+extern crate slog;
+#[macro_use]
+extern crate serde_derive;
+
+extern crate emu;
+extern crate r64emu;
+extern crate toml;
+
+use emu::bus::be::Device;
+use emu::hw;
+use r64emu::r4300::R4300;
+use r64emu::ri::RdramSize;
+use r64emu::{BootMode, N64};
+use slog::Discard;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::thread;
+
+static ROM_PATH: &'static str = "roms/tests";
+static MANIFEST: &'static str = "tests/romsuite.toml";
+
+#[derive(Deserialize)]
+struct RdramCheck {
+    addr: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct TestRom {
+    name: String,
+    path: String,
+    frames: usize,
+    hash: Option<String>,
+    #[serde(default)]
+    rdram: Vec<RdramCheck>,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    rom: Vec<TestRom>,
+}
+
+// Results of a run: the hash of the last frame, and the RDRAM words
+// requested by the checks (address, value).
+struct RomResult {
+    hash: u64,
+    rdram: Vec<(u32, u32)>,
+}
+
+fn parse_hex(s: &str) -> u64 {
+    u64::from_str_radix(&s.trim_start_matches("0x").replace('_', ""), 16)
+        .unwrap_or_else(|_| panic!("invalid hex number in manifest: {:?}", s))
+}
+
+// Boot a ROM and run it headlessly in deterministic mode. Each run happens
+// in its own thread, as the emulator state is thread-local.
+fn run(rom: &TestRom) -> Result<RomResult, String> {
+    let romfn = Path::new(ROM_PATH).join(&rom.path);
+    let frames = rom.frames;
+    let addrs: Vec<u32> = rom
+        .rdram
+        .iter()
+        .map(|c| parse_hex(&c.addr) as u32)
+        .collect();
+    thread::spawn(move || {
+        let logger = slog::Logger::root(Discard, o!());
+        let mut n64 = N64::new(logger, &romfn, None, RdramSize::Mb4).map_err(|e| e.to_string())?;
+        n64.setup_boot(BootMode::Hle).map_err(|e| e.to_string())?;
+        n64.set_deterministic(true).map_err(|e| e.to_string())?;
+
+        let fps = n64.tv_type().refresh_rate() as usize;
+        let hashes = hw::run_headless(
+            &mut n64,
+            &hw::HeadlessConfig {
+                width: 640,
+                height: 480,
+                audio_frames: N64::AUDIO_OUTPUT_FREQUENCY as usize / fps,
+                frames,
+                png_dir: None,
+                print_hashes: false,
+            },
+        )?;
+
+        let bus = &R4300::get().bus;
+        let rdram = addrs
+            .iter()
+            .map(|&addr| (addr, bus.fetch_read_nolog::<u32>(addr).read()))
+            .collect();
+        Ok(RomResult {
+            hash: hashes.last().cloned().unwrap_or(0),
+            rdram,
+        })
+    })
+    .join()
+    .unwrap()
+}
+
+// Print the results of a run in the manifest format, to fill in golden
+// values.
+fn print_result(rom: &TestRom, res: &RomResult) {
+    println!("[[rom]]");
+    println!("name = {:?}", rom.name);
+    println!("path = {:?}", rom.path);
+    println!("frames = {}", rom.frames);
+    println!("hash = \"{:016x}\"", res.hash);
+    if !res.rdram.is_empty() {
+        println!("rdram = [");
+        for (addr, val) in res.rdram.iter() {
+            println!("    {{ addr = \"{:x}\", value = \"{:08x}\" }},", addr, val);
+        }
+        println!("]");
+    }
+    println!();
+}
+
+// The suite needs the test ROMs (see README), so it only runs on request:
+// cargo test --release --test romsuite_test -- --ignored
+#[test]
+#[ignore]
+fn romsuite() {
+    let manifest = fs::read_to_string(MANIFEST).unwrap();
+    let manifest: Manifest = toml::from_str(&manifest).unwrap();
+    let print = env::var_os("R64EMU_ROMSUITE_PRINT").is_some();
+
+    let mut failures = Vec::new();
+    for rom in manifest.rom.iter() {
+        if !Path::new(ROM_PATH).join(&rom.path).exists() {
+            failures.push(format!("{}: ROM not found: {}", rom.name, rom.path));
+            continue;
+        }
+        let res = match run(rom) {
+            Ok(res) => res,
+            Err(err) => {
+                failures.push(format!("{}: {}", rom.name, err));
+                continue;
+            }
+        };
+        if print {
+            print_result(rom, &res);
+        }
+
+        match rom.hash.as_ref() {
+            Some(hash) if res.hash != parse_hex(hash) => failures.push(format!(
+                "{}: frame hash {:016x} (expected: {})",
+                rom.name, res.hash, hash
+            )),
+            Some(_) => {}
+            None if !print => failures.push(format!("{}: no golden hash", rom.name)),
+            None => {}
+        }
+        for (check, &(addr, val)) in rom.rdram.iter().zip(res.rdram.iter()) {
+            if val != parse_hex(&check.value) as u32 {
+                failures.push(format!(
+                    "{}: RDRAM[{:x}] = {:08x} (expected: {})",
+                    rom.name, addr, val, check.value
+                ));
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "ROM suite failures:\n{}",
+        failures.join("\n")
+    );
+}